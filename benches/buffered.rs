@@ -0,0 +1,79 @@
+/*!
+times decoding from a file: through `QoiPixels` straight from the `File`,
+through `QoiPixels` from a `BufReader`, and through `BufPixels` from the
+same `BufReader`, which decodes out of its buffer.
+
+run with `cargo bench --bench buffered`.
+*/
+
+use std::io::{BufReader, Seek};
+use std::time::{Duration, Instant};
+
+use dwelf::{Format, Rgba, qoi};
+
+/// the same mix of runs and short ops as the collect bench.
+fn image(width: u32, height: u32) -> Vec<Rgba> {
+	let mut out = Vec::with_capacity((width * height) as usize);
+	for y in 0..height {
+		for x in 0..width {
+			if (x / 64 + y / 64) % 2 == 0 {
+				out.push((40, 80, 120, 255));
+			} else {
+				out.push((x as u8, y as u8, (x ^ y) as u8, 255));
+			}
+		}
+	}
+	out
+}
+
+fn time(name: &str, rounds: u32, mut f: impl FnMut() -> usize) -> Duration {
+	// warm up
+	std::hint::black_box(f());
+
+	let start = Instant::now();
+	for _ in 0..rounds {
+		std::hint::black_box(f());
+	}
+	let elapsed = start.elapsed() / rounds;
+	println!("{name:>10}: {elapsed:?}");
+	elapsed
+}
+
+fn main() {
+	let (width, height) = (1024, 1024);
+	let header = qoi::QoiHeader {
+		width,
+		height,
+		channels: qoi::QoiHeaderChannels::RGBA,
+		colorspace: qoi::QoiHeaderColorspace::SRGB,
+	};
+	let path = std::env::temp_dir().join(format!("dwelf-bench-{}.qoi", std::process::id()));
+	let mut out = std::fs::File::create(&path).unwrap();
+	qoi::Qoi.encode(image(width, height).into_iter(), header, &mut out).unwrap();
+	drop(out);
+
+	let mut file = std::fs::File::open(&path).unwrap();
+
+	time("unbuffered", 2, || {
+		file.rewind().unwrap();
+		let (_, iter) = qoi::Qoi.decode_checked(&mut file).unwrap();
+		iter.fold(0, |n, px| n + px.is_ok() as usize)
+	});
+
+	let reader = time("read", 20, || {
+		file.rewind().unwrap();
+		let mut data = BufReader::new(&file);
+		let (_, iter) = qoi::Qoi.decode_checked(&mut data).unwrap();
+		iter.fold(0, |n, px| n + px.is_ok() as usize)
+	});
+
+	let buffered = time("buffered", 20, || {
+		file.rewind().unwrap();
+		let mut data = BufReader::new(&file);
+		let (_, iter) = qoi::decode_buffered(&mut data).unwrap();
+		iter.fold(0, |n, px| n + px.is_ok() as usize)
+	});
+
+	println!("buffered is {:.2}x as fast as read", reader.as_secs_f64() / buffered.as_secs_f64());
+	std::fs::remove_file(&path).unwrap();
+}
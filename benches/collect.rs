@@ -0,0 +1,79 @@
+/*!
+times collecting decoded pixels, going through `next()` one pixel at a
+time versus the iterator's own `fold`.
+
+run with `cargo bench --bench collect`.
+*/
+
+use std::time::{Duration, Instant};
+
+use dwelf::{Format, Rgba, qoi};
+
+/// smooth gradients broken up by flat stretches, so there's a mix of runs
+/// and short ops like a real image.
+fn image(width: u32, height: u32) -> Vec<Rgba> {
+	let mut out = Vec::with_capacity((width * height) as usize);
+	for y in 0..height {
+		for x in 0..width {
+			if (x / 64 + y / 64) % 2 == 0 {
+				out.push((40, 80, 120, 255));
+			} else {
+				out.push((x as u8, y as u8, (x ^ y) as u8, 255));
+			}
+		}
+	}
+	out
+}
+
+fn time(name: &str, rounds: u32, mut f: impl FnMut() -> usize) -> Duration {
+	// warm up
+	std::hint::black_box(f());
+
+	let start = Instant::now();
+	for _ in 0..rounds {
+		std::hint::black_box(f());
+	}
+	let elapsed = start.elapsed() / rounds;
+	println!("{name:>8}: {elapsed:?}");
+	elapsed
+}
+
+fn main() {
+	let (width, height) = (1024, 1024);
+	let header = qoi::QoiHeader {
+		width,
+		height,
+		channels: qoi::QoiHeaderChannels::RGBA,
+		colorspace: qoi::QoiHeaderColorspace::SRGB,
+	};
+	let mut file = vec![];
+	qoi::Qoi.encode(image(width, height).into_iter(), header, &mut file).unwrap();
+
+	let rounds = 20;
+
+	// `Vec`'s `collect` pulls items with `next`, so it's measured as is, and
+	// against filling the `Vec` through `for_each`, which goes through `fold`
+	time("collect", rounds, || {
+		let mut data = &file[..];
+		let (_, iter) = qoi::Qoi.decode(&mut data).unwrap();
+		iter.collect::<Vec<_>>().len()
+	});
+
+	let next = time("next", rounds, || {
+		let mut data = &file[..];
+		let (_, mut iter) = qoi::Qoi.decode(&mut data).unwrap();
+		let mut out = Vec::with_capacity((width * height) as usize);
+		core::iter::from_fn(|| iter.next()).for_each(|px| out.push(px));
+		out.len()
+	});
+
+	let fold = time("fold", rounds, || {
+		let mut data = &file[..];
+		let (_, iter) = qoi::Qoi.decode(&mut data).unwrap();
+		let mut out = Vec::with_capacity((width * height) as usize);
+		iter.for_each(|px| out.push(px));
+		out.len()
+	});
+
+	println!("fold is {:.2}x as fast as next", next.as_secs_f64() / fold.as_secs_f64());
+}
@@ -0,0 +1,41 @@
+//! cfg aliases for the sets of formats that share helpers in `lib.rs`, so
+//! their feature lists are written down once, here.
+
+/// every format's feature, so a build with none of them can be refused.
+const FORMATS: &[&str] = &[
+	"qoi", "png", "bmp", "pnm", "tga", "gif", "tiff", "jpeg", "hdr", "exr", "pcx", "ico", "dds", "ktx2", "xbm", "xpm",
+	"ras", "sgi", "qoy", "qoir", "ilbm", "psd", "fits", "raw",
+];
+
+/// formats that encode, and so need `push_exact`.
+const ENCODERS: &[&str] = &[
+	"qoi", "png", "bmp", "pnm", "tga", "gif", "tiff", "jpeg", "hdr", "pcx", "ktx2", "xbm", "xpm", "sgi", "qoy", "raw",
+];
+
+/// formats that decode with `read_exact`. that's all of the decoders but
+/// qoi's, which reads an op at a time through `QoiDecoder`.
+const READERS: &[&str] = &[
+	"png", "bmp", "pnm", "tga", "gif", "tiff", "hdr", "exr", "pcx", "dds", "ktx2", "xbm", "xpm", "ras", "sgi", "qoy",
+	"qoir", "ilbm", "psd", "fits", "raw",
+];
+
+/// formats that skip rows by seeking past them, with `seek::seek`.
+const SEEKERS: &[&str] = &["bmp", "dds", "raw"];
+
+fn alias(name: &str, features: &[&str]) {
+	println!("cargo::rustc-check-cfg=cfg({name})");
+	let enabled = features.iter().any(|feature| {
+		std::env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_some()
+	});
+	if enabled {
+		println!("cargo::rustc-cfg={name}");
+	}
+}
+
+fn main() {
+	println!("cargo::rerun-if-changed=build.rs");
+	alias("any_format", FORMATS);
+	alias("any_encoder", ENCODERS);
+	alias("any_reader", READERS);
+	alias("any_seeker", SEEKERS);
+}
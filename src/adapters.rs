@@ -0,0 +1,1038 @@
+/*!
+iterator adapters that rearrange an image's pixels as they go by, for
+putting between a decoder and an encoder.
+
+each takes the pixels of a `width` by `height` image in row-major order,
+and hands them out rearranged, in row-major order again, with the new
+image's size from `Dimensions`, so adapters can be chained. pixels can be
+any type, so they work on `Rgba16` and gray as well as `Rgba`.
+
+nothing is buffered that doesn't have to be: cropping keeps nothing,
+flipping horizontally and resizing keep a row, and flipping vertically or
+rotating keeps the whole image, since its last row comes out first.
+buffers grow as pixels arrive, rather than to the size the image is said
+to be.
+
+converting colors goes a pixel at a time, so those adapters don't need
+the image's size, and keep nothing. dithering needs to know where each
+pixel is, but keeps nothing either.
+
+what comes out is always the start of what would have, so pixels running
+out early, or memory running out, ends an adapter at the first pixel it
+doesn't have, and an encoder after it sees too few. an image that's turned
+needs its last row first, so nothing comes out if it's cut short.
+*/
+
+use crate::{Dimensions, Error, Pixel, Rgba, RgbaF32, pixel};
+
+mod blue_noise;
+
+/// adds `px` to `buf`, or returns false if there's no memory for it.
+fn push<P>(buf: &mut Vec<P>, px: P) -> bool {
+	if buf.len() == buf.capacity() && buf.try_reserve(1).is_err() {
+		return false;
+	}
+	buf.push(px);
+	true
+}
+
+/// reads past `n` pixels, or returns false if they run out first.
+fn skip(pixels: &mut impl Iterator, mut n: u64) -> bool {
+	while n > 0 {
+		let step = usize::try_from(n).unwrap_or(usize::MAX);
+		if pixels.nth(step - 1).is_none() {
+			return false;
+		}
+		n -= step as u64;
+	}
+	true
+}
+
+/// the pixels of an image mirrored left to right.
+#[derive(Debug, Clone)]
+pub struct FlipHorizontal<I, P> {
+	pixels: I,
+	width: u32,
+	height: u32,
+	/// rows not yet read.
+	rows: u32,
+	/// what's left of the row being handed out, from its right end.
+	row: Vec<P>,
+}
+
+/// mirrors `pixels`, of a `width` by `height` image, left to right. a row is
+/// kept at a time.
+pub fn flip_horizontal<I: Iterator<Item = P>, P: Copy>(pixels: I, width: u32, height: u32) -> FlipHorizontal<I, P> {
+	FlipHorizontal {
+		pixels,
+		width,
+		height,
+		rows: height,
+		row: vec![],
+	}
+}
+
+impl<I: Iterator<Item = P>, P: Copy> Iterator for FlipHorizontal<I, P> {
+	type Item = P;
+
+	fn next(&mut self) -> Option<P> {
+		if self.row.is_empty() {
+			if self.rows == 0 || self.width == 0 {
+				return None;
+			}
+			self.rows -= 1;
+			for px in (&mut self.pixels).take(self.width as usize) {
+				if !push(&mut self.row, px) {
+					break;
+				}
+			}
+			if self.row.len() < self.width as usize {
+				self.rows = 0;
+				self.row.clear();
+				return None;
+			}
+		}
+		// the row was read left to right, so it comes out of the end reversed
+		self.row.pop()
+	}
+}
+
+impl<I, P> Dimensions for FlipHorizontal<I, P> {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// a whole image, read before the first pixel comes out.
+#[derive(Debug, Clone)]
+struct Whole<I, P> {
+	/// the pixels, until they've all been read.
+	pixels: Option<I>,
+	width: u32,
+	height: u32,
+	image: Vec<P>,
+}
+
+impl<I: Iterator<Item = P>, P: Copy> Whole<I, P> {
+	fn new(pixels: I, width: u32, height: u32) -> Self {
+		Self {
+			pixels: Some(pixels),
+			width,
+			height,
+			image: vec![],
+		}
+	}
+
+	/// the image, read if it hasn't been yet. one that was cut short is empty,
+	/// since the first pixel it's turned into could be one that's missing.
+	fn image(&mut self) -> &[P] {
+		if let Some(pixels) = self.pixels.take() {
+			let total = (self.width as u64 * self.height as u64).try_into().unwrap_or(usize::MAX);
+			for px in pixels.take(total) {
+				if !push(&mut self.image, px) {
+					break;
+				}
+			}
+			if self.image.len() < total {
+				self.image = vec![];
+			}
+		}
+		&self.image
+	}
+
+	/// the size hint of what's left once `i` pixels have come out.
+	fn size_hint(&self, i: usize) -> (usize, Option<usize>) {
+		match self.pixels {
+			Some(ref pixels) => {
+				let total = (self.width as u64 * self.height as u64).try_into().unwrap_or(usize::MAX);
+				(0, Some(pixels.size_hint().1.map_or(total, |n| n.min(total))))
+			}
+			None => {
+				let left = self.image.len() - i;
+				(left, Some(left))
+			}
+		}
+	}
+}
+
+/// the pixels of an image turned upside down.
+#[derive(Debug, Clone)]
+pub struct FlipVertical<I, P> {
+	whole: Whole<I, P>,
+	/// pixels handed out so far.
+	i: usize,
+}
+
+/// turns `pixels`, of a `width` by `height` image, upside down. the whole
+/// image is kept, and read before the first pixel comes out.
+pub fn flip_vertical<I: Iterator<Item = P>, P: Copy>(pixels: I, width: u32, height: u32) -> FlipVertical<I, P> {
+	FlipVertical {
+		whole: Whole::new(pixels, width, height),
+		i: 0,
+	}
+}
+
+impl<I: Iterator<Item = P>, P: Copy> Iterator for FlipVertical<I, P> {
+	type Item = P;
+
+	fn next(&mut self) -> Option<P> {
+		let (width, height) = (self.whole.width as usize, self.whole.height as usize);
+		let image = self.whole.image();
+		if self.i == image.len() {
+			return None;
+		}
+		let (x, y) = (self.i % width, self.i / width);
+		self.i += 1;
+		Some(image[(height - 1 - y) * width + x])
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.whole.size_hint(self.i)
+	}
+}
+
+impl<I, P> Dimensions for FlipVertical<I, P> {
+	fn width(&self) -> u32 {
+		self.whole.width
+	}
+
+	fn height(&self) -> u32 {
+		self.whole.height
+	}
+}
+
+/// how far `Rotate` turns an image, clockwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Turn {
+	Quarter,
+	Half,
+	ThreeQuarters,
+}
+
+/// the pixels of an image turned clockwise. a quarter turn either way swaps
+/// the width and height.
+#[derive(Debug, Clone)]
+pub struct Rotate<I, P> {
+	whole: Whole<I, P>,
+	turn: Turn,
+	/// pixels handed out so far.
+	i: usize,
+}
+
+fn rotate<I: Iterator<Item = P>, P: Copy>(pixels: I, width: u32, height: u32, turn: Turn) -> Rotate<I, P> {
+	Rotate {
+		whole: Whole::new(pixels, width, height),
+		turn,
+		i: 0,
+	}
+}
+
+/// turns `pixels`, of a `width` by `height` image, a quarter turn clockwise,
+/// into a `height` by `width` one. the whole image is kept, and read before
+/// the first pixel comes out.
+pub fn rotate_90<I: Iterator<Item = P>, P: Copy>(pixels: I, width: u32, height: u32) -> Rotate<I, P> {
+	rotate(pixels, width, height, Turn::Quarter)
+}
+
+/// turns `pixels`, of a `width` by `height` image, upside down and back to
+/// front. the whole image is kept, and read before the first pixel comes out.
+pub fn rotate_180<I: Iterator<Item = P>, P: Copy>(pixels: I, width: u32, height: u32) -> Rotate<I, P> {
+	rotate(pixels, width, height, Turn::Half)
+}
+
+/// turns `pixels`, of a `width` by `height` image, a quarter turn
+/// anticlockwise, into a `height` by `width` one. the whole image is kept, and
+/// read before the first pixel comes out.
+pub fn rotate_270<I: Iterator<Item = P>, P: Copy>(pixels: I, width: u32, height: u32) -> Rotate<I, P> {
+	rotate(pixels, width, height, Turn::ThreeQuarters)
+}
+
+impl<I: Iterator<Item = P>, P: Copy> Iterator for Rotate<I, P> {
+	type Item = P;
+
+	fn next(&mut self) -> Option<P> {
+		let (width, height) = (self.whole.width as usize, self.whole.height as usize);
+		let image = self.whole.image();
+		let i = self.i;
+		if i == image.len() {
+			return None;
+		}
+		self.i += 1;
+		// where the `i`th pixel of the turned image was in the original
+		let at = match self.turn {
+			// columns, bottom to top, from the left
+			Turn::Quarter => (height - 1 - i % height) * width + i / height,
+			Turn::Half => image.len() - 1 - i,
+			// columns, top to bottom, from the right
+			Turn::ThreeQuarters => i % height * width + (width - 1 - i / height),
+		};
+		Some(image[at])
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.whole.size_hint(self.i)
+	}
+}
+
+impl<I, P> Dimensions for Rotate<I, P> {
+	fn width(&self) -> u32 {
+		match self.turn {
+			Turn::Half => self.whole.width,
+			Turn::Quarter | Turn::ThreeQuarters => self.whole.height,
+		}
+	}
+
+	fn height(&self) -> u32 {
+		match self.turn {
+			Turn::Half => self.whole.height,
+			Turn::Quarter | Turn::ThreeQuarters => self.whole.width,
+		}
+	}
+}
+
+/// the pixels of a rectangle of an image.
+#[derive(Debug, Clone)]
+pub struct Crop<I> {
+	pixels: I,
+	width: u32,
+	height: u32,
+	/// pixels to skip before the next one.
+	gap: u64,
+	/// pixels of each row that are skipped, after the first.
+	stride: u64,
+	/// where in its row the next pixel is.
+	x: u32,
+	/// pixels of the rectangle still to come.
+	left: u64,
+}
+
+/// the `crop_width` by `crop_height` rectangle of `pixels`, of a `width` by
+/// `height` image, with its top left at `(x, y)`. nothing is kept, and
+/// nothing after the rectangle is read. one that doesn't fit in the image is
+/// `OutOfBounds`.
+pub fn crop<I: Iterator>(pixels: I, width: u32, height: u32, x: u32, y: u32, crop_width: u32, crop_height: u32) -> Result<Crop<I>, Error> {
+	if x as u64 + crop_width as u64 > width as u64 || y as u64 + crop_height as u64 > height as u64 {
+		return Err(Error::OutOfBounds { x, y, width: crop_width, height: crop_height });
+	}
+	Ok(Crop {
+		pixels,
+		width: crop_width,
+		height: crop_height,
+		gap: y as u64 * width as u64 + x as u64,
+		stride: (width - crop_width) as u64,
+		x: 0,
+		left: crop_width as u64 * crop_height as u64,
+	})
+}
+
+impl<I: Iterator> Iterator for Crop<I> {
+	type Item = I::Item;
+
+	fn next(&mut self) -> Option<I::Item> {
+		if self.left == 0 {
+			return None;
+		}
+		if !skip(&mut self.pixels, self.gap) {
+			self.left = 0;
+			return None;
+		}
+		self.gap = 0;
+		let Some(px) = self.pixels.next() else {
+			self.left = 0;
+			return None;
+		};
+		self.left -= 1;
+		self.x += 1;
+		if self.x == self.width {
+			self.x = 0;
+			self.gap = self.stride;
+		}
+		Some(px)
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(0, self.left.try_into().ok())
+	}
+}
+
+impl<I> Dimensions for Crop<I> {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// the pixels of an image scaled to another size, each taken from the pixel
+/// nearest it.
+#[derive(Debug, Clone)]
+pub struct ResizeNearest<I, P> {
+	pixels: I,
+	/// the size of the image the pixels are from.
+	source_width: u32,
+	source_height: u32,
+	width: u32,
+	height: u32,
+	/// the row pixels are being taken from, and which one it is.
+	row: Vec<P>,
+	row_y: Option<u32>,
+	/// where the next pixel is.
+	x: u32,
+	y: u32,
+	done: bool,
+}
+
+/// scales `pixels`, of a `width` by `height` image, to `new_width` by
+/// `new_height`, taking each pixel from the one nearest its center. a row is
+/// kept at a time, rows nothing is taken from are read past, and nothing
+/// after the last one that something is taken from is read. scaling up
+/// by a whole number repeats every pixel that many times each way.
+pub fn resize_nearest<I: Iterator<Item = P>, P: Copy>(pixels: I, width: u32, height: u32, new_width: u32, new_height: u32) -> ResizeNearest<I, P> {
+	ResizeNearest {
+		pixels,
+		source_width: width,
+		source_height: height,
+		width: new_width,
+		height: new_height,
+		row: vec![],
+		row_y: None,
+		x: 0,
+		y: 0,
+		// there's nothing to take pixels from
+		done: width == 0 || height == 0,
+	}
+}
+
+/// the pixel of `size` nearest the center of pixel `i` of `new_size`.
+fn nearest(i: u32, size: u32, new_size: u32) -> u32 {
+	((2 * i as u128 + 1) * size as u128 / (2 * new_size as u128)) as u32
+}
+
+impl<I: Iterator<Item = P>, P: Copy> ResizeNearest<I, P> {
+	/// reads up to row `y`, keeping it, or returns false if the pixels run out
+	/// first.
+	fn read_row(&mut self, y: u32) -> bool {
+		let past = y - self.row_y.map_or(0, |row_y| row_y + 1);
+		if !skip(&mut self.pixels, past as u64 * self.source_width as u64) {
+			return false;
+		}
+		self.row.clear();
+		for px in (&mut self.pixels).take(self.source_width as usize) {
+			if !push(&mut self.row, px) {
+				return false;
+			}
+		}
+		self.row_y = Some(y);
+		self.row.len() == self.source_width as usize
+	}
+}
+
+impl<I: Iterator<Item = P>, P: Copy> Iterator for ResizeNearest<I, P> {
+	type Item = P;
+
+	fn next(&mut self) -> Option<P> {
+		if self.done || self.y == self.height || self.width == 0 {
+			return None;
+		}
+		if self.x == 0 {
+			let y = nearest(self.y, self.source_height, self.height);
+			if self.row_y != Some(y) && !self.read_row(y) {
+				self.done = true;
+				return None;
+			}
+		}
+		let px = self.row[nearest(self.x, self.source_width, self.width) as usize];
+		self.x += 1;
+		if self.x == self.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(px)
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		if self.done {
+			return (0, Some(0));
+		}
+		let left = (self.height - self.y) as u64 * self.width as u64 - self.x as u64;
+		(0, left.try_into().ok())
+	}
+}
+
+impl<I, P> Dimensions for ResizeNearest<I, P> {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// the pixels of an srgb image, in linear light.
+#[derive(Debug, Clone)]
+pub struct SrgbToLinear<I> {
+	pixels: I,
+	/// the linear sample for each srgb one.
+	table: [f32; 256],
+}
+
+/// takes `pixels` out of srgb into linear light, as floats from 0 to 1, to
+/// blend or resize in. a table of the 256 samples is made up front, so each
+/// pixel is three lookups. alpha is linear already, and only scaled.
+pub fn srgb_to_linear<I: Iterator<Item = Rgba>>(pixels: I) -> SrgbToLinear<I> {
+	let mut table = [0.0; 256];
+	for (x, linear) in table.iter_mut().enumerate() {
+		*linear = pixel::srgb_inverse(x as f32 / 255.0);
+	}
+	SrgbToLinear { pixels, table }
+}
+
+impl<I: Iterator<Item = Rgba>> Iterator for SrgbToLinear<I> {
+	type Item = RgbaF32;
+
+	fn next(&mut self) -> Option<RgbaF32> {
+		let (r, g, b, a) = self.pixels.next()?;
+		let table = &self.table;
+		Some((table[r as usize], table[g as usize], table[b as usize], a as f32 / 255.0))
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.pixels.size_hint()
+	}
+}
+
+/// the pixels of an image in linear light, in srgb.
+#[derive(Debug, Clone)]
+pub struct LinearToSrgb<I> {
+	pixels: I,
+}
+
+/// takes `pixels` out of linear light into srgb, undoing `srgb_to_linear`,
+/// so 8-bit pixels that go through both come out as they went in. floats
+/// can't index a table, so each sample is worked out. out of range samples,
+/// and nan, are clamped.
+pub fn linear_to_srgb<I: Iterator<Item = RgbaF32>>(pixels: I) -> LinearToSrgb<I> {
+	LinearToSrgb { pixels }
+}
+
+impl<I: Iterator<Item = RgbaF32>> Iterator for LinearToSrgb<I> {
+	type Item = Rgba;
+
+	fn next(&mut self) -> Option<Rgba> {
+		let (r, g, b, a) = self.pixels.next()?;
+		Some(Rgba::from_f32((pixel::srgb(r), pixel::srgb(g), pixel::srgb(b), a)))
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.pixels.size_hint()
+	}
+}
+
+/// the pixels of an image with their color multiplied by their alpha.
+#[derive(Debug, Clone)]
+pub struct Premultiply<I> {
+	pixels: I,
+}
+
+/// multiplies the color of each of `pixels` by its alpha, as engines tend
+/// to want it, rounding to the nearest.
+pub fn premultiply<I: Iterator<Item = Rgba>>(pixels: I) -> Premultiply<I> {
+	Premultiply { pixels }
+}
+
+impl<I: Iterator<Item = Rgba>> Iterator for Premultiply<I> {
+	type Item = Rgba;
+
+	fn next(&mut self) -> Option<Rgba> {
+		let (r, g, b, a) = self.pixels.next()?;
+		// `x * a / 255`, rounded, without dividing
+		let mul = |x: u8| {
+			let t = x as u32 * a as u32 + 128;
+			((t + (t >> 8)) >> 8) as u8
+		};
+		Some((mul(r), mul(g), mul(b), a))
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.pixels.size_hint()
+	}
+}
+
+/// the pixels of a premultiplied image with their color divided by their
+/// alpha again.
+#[derive(Debug, Clone)]
+pub struct Unpremultiply<I> {
+	pixels: I,
+}
+
+/// divides the color of each of `pixels` by its alpha, as formats store it,
+/// rounding to the nearest. pixels with no alpha have no color left to get
+/// back, and come out as transparent black, and color brighter than alpha
+/// allows is clamped. premultiplying loses precision at low alpha, so this
+/// only gets back what's left of it.
+pub fn unpremultiply<I: Iterator<Item = Rgba>>(pixels: I) -> Unpremultiply<I> {
+	Unpremultiply { pixels }
+}
+
+impl<I: Iterator<Item = Rgba>> Iterator for Unpremultiply<I> {
+	type Item = Rgba;
+
+	fn next(&mut self) -> Option<Rgba> {
+		let (r, g, b, a) = self.pixels.next()?;
+		if a == 0 {
+			return Some((0, 0, 0, 0));
+		}
+		let div = |x: u8| ((x as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+		Some((div(r), div(g), div(b), a))
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.pixels.size_hint()
+	}
+}
+
+/// how much each of red, green and blue count toward a pixel's gray. they
+/// should add up to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Luma {
+	pub r: f32,
+	pub g: f32,
+	pub b: f32,
+}
+
+impl Luma {
+	/// rec. 709's weights, for srgb and hd video.
+	pub const REC_709: Self = Self { r: 0.2126, g: 0.7152, b: 0.0722 };
+	/// rec. 601's weights, in the 256ths that `Pixel` and the encoders
+	/// convert to gray with.
+	pub const REC_601: Self = Self { r: 77.0 / 256.0, g: 150.0 / 256.0, b: 29.0 / 256.0 };
+}
+
+impl Default for Luma {
+	fn default() -> Self {
+		Self::REC_709
+	}
+}
+
+/// the pixels of an image in gray.
+#[derive(Debug, Clone)]
+pub struct Grayscale<I, P> {
+	pixels: I,
+	luma: Luma,
+	gray: core::marker::PhantomData<fn() -> P>,
+}
+
+/// turns `pixels` gray, weighing their samples as they're stored, with
+/// `luma`. the gray comes out as any pixel type: `Gray8` for just the gray,
+/// or `Rgba` for it in each of red, green and blue, with alpha kept.
+pub fn to_grayscale<I: Iterator<Item = Q>, Q: Pixel, P: Pixel>(pixels: I, luma: Luma) -> Grayscale<I, P> {
+	Grayscale {
+		pixels,
+		luma,
+		gray: core::marker::PhantomData,
+	}
+}
+
+impl<I: Iterator<Item = Q>, Q: Pixel, P: Pixel> Iterator for Grayscale<I, P> {
+	type Item = P;
+
+	fn next(&mut self) -> Option<P> {
+		let (r, g, b, a) = self.pixels.next()?.to_f32();
+		let Luma { r: wr, g: wg, b: wb } = self.luma;
+		let x = r * wr + g * wg + b * wb;
+		Some(P::from_f32((x, x, x, a)))
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.pixels.size_hint()
+	}
+}
+
+/// `x`, out of 255, moved to the nearest of `levels` evenly spaced values
+/// below `x` plus `offset`, which is from 0 to 1, so that over many pixels
+/// with offsets evenly spread the average comes out at `x`.
+fn dither(x: u8, offset: f32, levels: u8) -> u8 {
+	let steps = (levels - 1) as f32;
+	let step = (x as f32 / 255.0 * steps + offset).floor().min(steps);
+	(step / steps * 255.0 + 0.5) as u8
+}
+
+/// the sizes of bayer matrix `dither_ordered` can use. bigger ones have more
+/// thresholds, so they make more shades, in a coarser pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bayer {
+	X2,
+	X4,
+	X8,
+}
+
+/// the pixels of an image dithered with a matrix of thresholds tiled over
+/// it, from `dither_ordered` or `dither_blue_noise`.
+#[derive(Debug, Clone)]
+pub struct OrderedDither<I> {
+	pixels: I,
+	width: u32,
+	height: u32,
+	/// the matrix, a row at a time, each threshold from 0 to 1.
+	matrix: Vec<f32>,
+	size: u32,
+	levels: u8,
+	/// where the next pixel is.
+	x: u32,
+	y: u32,
+}
+
+/// dithers with the `size` by `size` matrix whose cells hold `ranks`, each
+/// rank from 0 up once.
+fn tiled<I>(pixels: I, width: u32, height: u32, ranks: impl Iterator<Item = u32>, size: u32, levels: u8) -> Result<OrderedDither<I>, Error> {
+	if levels < 2 {
+		return Err(Error::InvalidInput { reason: "dithering needs at least 2 levels" });
+	}
+	let cells = (size * size) as f32;
+	Ok(OrderedDither {
+		pixels,
+		width,
+		height,
+		matrix: ranks.map(|rank| (rank as f32 + 0.5) / cells).collect(),
+		size,
+		levels,
+		x: 0,
+		y: 0,
+	})
+}
+
+/// dithers `pixels`, of a `width` by `height` image, down to `levels` values
+/// a sample, such as 2 for 1-bit output, with the thresholds of a bayer
+/// `matrix` tiled over it. each pixel's threshold depends only on where it
+/// is, so nothing is kept. alpha is left alone. fewer than 2 levels is
+/// `InvalidInput`.
+pub fn dither_ordered<I: Iterator<Item = Rgba>>(pixels: I, width: u32, height: u32, matrix: Bayer, levels: u8) -> Result<OrderedDither<I>, Error> {
+	let size = match matrix {
+		Bayer::X2 => 2,
+		Bayer::X4 => 4,
+		Bayer::X8 => 8,
+	};
+	// each size is the one half of it four times over, scaled up and offset
+	// by the size 2 one
+	let mut ranks = vec![0u32];
+	let mut half = 1;
+	while half < size {
+		let full = half * 2;
+		ranks = (0..full * full).map(|i| {
+			let (x, y) = (i % full, i / full);
+			let offset = [0, 2, 3, 1][(y / half * 2 + x / half) as usize];
+			ranks[(y % half * half + x % half) as usize] * 4 + offset
+		}).collect();
+		half = full;
+	}
+	tiled(pixels, width, height, ranks.into_iter(), size, levels)
+}
+
+/// like `dither_ordered`, with a 32x32 blue noise mask instead of a bayer
+/// matrix. it has no pattern to see, so it looks better on photos, at the
+/// cost of a lookup in a bigger table.
+pub fn dither_blue_noise<I: Iterator<Item = Rgba>>(pixels: I, width: u32, height: u32, levels: u8) -> Result<OrderedDither<I>, Error> {
+	let ranks = blue_noise::MASK.iter().map(|&rank| rank as u32);
+	tiled(pixels, width, height, ranks, blue_noise::SIZE as u32, levels)
+}
+
+impl<I: Iterator<Item = Rgba>> Iterator for OrderedDither<I> {
+	type Item = Rgba;
+
+	fn next(&mut self) -> Option<Rgba> {
+		if self.y == self.height || self.width == 0 {
+			return None;
+		}
+		let (r, g, b, a) = self.pixels.next()?;
+		let offset = self.matrix[(self.y % self.size * self.size + self.x % self.size) as usize];
+		self.x += 1;
+		if self.x == self.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		let levels = self.levels;
+		Some((dither(r, offset, levels), dither(g, offset, levels), dither(b, offset, levels), a))
+	}
+}
+
+impl<I> Dimensions for OrderedDither<I> {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{Dimensions, adapters};
+
+	#[test]
+	fn flip() {
+		let pixels = || 0..12u8;
+		let flipped = adapters::flip_horizontal(pixels(), 4, 3);
+		assert_eq!((flipped.width(), flipped.height()), (4, 3));
+		assert_eq!(flipped.collect::<Vec<_>>(), [3, 2, 1, 0, 7, 6, 5, 4, 11, 10, 9, 8]);
+		let flipped = adapters::flip_vertical(pixels(), 4, 3);
+		assert_eq!(flipped.size_hint(), (0, Some(12)));
+		assert_eq!(flipped.collect::<Vec<_>>(), [8, 9, 10, 11, 4, 5, 6, 7, 0, 1, 2, 3]);
+
+		// both ways is half a turn, and twice is where it started
+		let turned = adapters::flip_vertical(adapters::flip_horizontal(pixels(), 4, 3), 4, 3);
+		assert_eq!(turned.collect::<Vec<_>>(), pixels().rev().collect::<Vec<_>>());
+		let twice = adapters::flip_vertical(adapters::flip_vertical(pixels(), 4, 3), 4, 3);
+		assert_eq!(twice.collect::<Vec<_>>(), pixels().collect::<Vec<_>>());
+
+		// a row cut short is dropped, and nothing past the image is read
+		assert_eq!(adapters::flip_horizontal(0..10u8, 4, 3).collect::<Vec<_>>(), [3, 2, 1, 0, 7, 6, 5, 4]);
+		assert_eq!(adapters::flip_vertical(0..10u8, 4, 3).count(), 0);
+		assert_eq!(adapters::flip_horizontal(0..20u8, 4, 3).count(), 12);
+		let mut flipped = adapters::flip_vertical(0..20u8, 4, 3);
+		assert_eq!(flipped.next(), Some(8));
+		assert_eq!(flipped.size_hint(), (11, Some(11)));
+
+		// nothing wide has no pixels to hand out
+		assert_eq!(adapters::flip_horizontal(pixels(), 0, 3).count(), 0);
+		assert_eq!(adapters::flip_vertical(pixels(), 0, 3).count(), 0);
+	}
+
+	#[test]
+	fn rotate() {
+		// 0 1 2
+		// 3 4 5
+		let pixels = || 0..6u8;
+		let turned = adapters::rotate_90(pixels(), 3, 2);
+		assert_eq!((turned.width(), turned.height()), (2, 3));
+		assert_eq!(turned.collect::<Vec<_>>(), [3, 0, 4, 1, 5, 2]);
+		let turned = adapters::rotate_180(pixels(), 3, 2);
+		assert_eq!((turned.width(), turned.height()), (3, 2));
+		assert_eq!(turned.collect::<Vec<_>>(), [5, 4, 3, 2, 1, 0]);
+		let turned = adapters::rotate_270(pixels(), 3, 2);
+		assert_eq!((turned.width(), turned.height()), (2, 3));
+		assert_eq!(turned.collect::<Vec<_>>(), [2, 5, 1, 4, 0, 3]);
+
+		// turns add up
+		let twice = adapters::rotate_90(adapters::rotate_90(pixels(), 3, 2), 2, 3);
+		assert_eq!(twice.collect::<Vec<_>>(), adapters::rotate_180(pixels(), 3, 2).collect::<Vec<_>>());
+		let back = adapters::rotate_270(adapters::rotate_90(pixels(), 3, 2), 2, 3);
+		assert_eq!(back.collect::<Vec<_>>(), pixels().collect::<Vec<_>>());
+		let flipped = adapters::flip_vertical(adapters::flip_horizontal(pixels(), 3, 2), 3, 2);
+		assert_eq!(flipped.collect::<Vec<_>>(), adapters::rotate_180(pixels(), 3, 2).collect::<Vec<_>>());
+
+		let mut turned = adapters::rotate_90(pixels(), 3, 2);
+		assert_eq!(turned.size_hint(), (0, Some(6)));
+		turned.next();
+		assert_eq!(turned.size_hint(), (5, Some(5)));
+		assert_eq!(adapters::rotate_90(0..5u8, 3, 2).count(), 0);
+		assert_eq!(adapters::rotate_270(pixels(), 0, 2).count(), 0);
+	}
+
+	#[test]
+	fn crop() {
+		use crate::Error;
+
+		// 0  1  2  3
+		// 4  5  6  7
+		// 8  9  10 11
+		let pixels = || 0..12u8;
+		let take = |x, y, width, height| adapters::crop(pixels(), 4, 3, x, y, width, height).expect("in bounds?").collect::<Vec<_>>();
+		assert_eq!(take(1, 1, 2, 2), [5, 6, 9, 10]);
+		assert_eq!(take(0, 2, 4, 1), [8, 9, 10, 11]);
+		assert_eq!(take(3, 0, 1, 3), [3, 7, 11]);
+		assert_eq!(take(0, 0, 4, 3), pixels().collect::<Vec<_>>());
+		assert!(take(2, 1, 0, 2).is_empty());
+		let cropped = adapters::crop(pixels(), 4, 3, 1, 0, 3, 2).expect("in bounds?");
+		assert_eq!((cropped.width(), cropped.height()), (3, 2));
+
+		// nothing after the rectangle is read, and one cut short ends early
+		let mut pixels = pixels();
+		assert_eq!(adapters::crop(&mut pixels, 4, 3, 1, 0, 2, 2).expect("in bounds?").count(), 4);
+		assert_eq!(pixels.next(), Some(7));
+		assert_eq!(adapters::crop(0..7u8, 4, 3, 1, 1, 2, 2).expect("in bounds?").collect::<Vec<_>>(), [5, 6]);
+
+		let result = adapters::crop(0..12u8, 4, 3, 0, 2, 1, 2);
+		assert!(matches!(result, Err(Error::OutOfBounds { x: 0, y: 2, width: 1, height: 2 })), "{result:?}");
+	}
+
+	#[test]
+	fn resize_nearest() {
+		// 0 1
+		// 2 3
+		let pixels = || 0..4u8;
+		let resized = adapters::resize_nearest(pixels(), 2, 2, 4, 6);
+		assert_eq!((resized.width(), resized.height()), (4, 6));
+		assert_eq!(resized.size_hint(), (0, Some(24)));
+		assert_eq!(resized.collect::<Vec<_>>(), [
+			0, 0, 1, 1,
+			0, 0, 1, 1,
+			0, 0, 1, 1,
+			2, 2, 3, 3,
+			2, 2, 3, 3,
+			2, 2, 3, 3,
+		]);
+
+		// down, from the middle of each block, reading past the rows between
+		let mut big = 0..16u8;
+		assert_eq!(adapters::resize_nearest(&mut big, 4, 4, 2, 2).collect::<Vec<_>>(), [5, 7, 13, 15]);
+		assert_eq!(big.next(), None);
+		assert_eq!(adapters::resize_nearest(0..12u8, 3, 4, 3, 1).collect::<Vec<_>>(), [6, 7, 8]);
+		assert_eq!(adapters::resize_nearest(0..4u8, 2, 2, 2, 2).collect::<Vec<_>>(), [0, 1, 2, 3]);
+
+		// one cut short ends at the first row it doesn't have
+		assert_eq!(adapters::resize_nearest(0..3u8, 2, 2, 4, 4).collect::<Vec<_>>(), [0, 0, 1, 1, 0, 0, 1, 1]);
+		assert_eq!(adapters::resize_nearest(pixels(), 0, 2, 4, 4).count(), 0);
+		assert_eq!(adapters::resize_nearest(pixels(), 2, 2, 0, 4).count(), 0);
+	}
+
+	#[test]
+	fn srgb() {
+		use crate::{Rgba, RgbaF32};
+
+		// every sample makes it there and back
+		let pixels = (0..=255).map(|x| (x, 255 - x, x / 2, x)).collect::<Vec<Rgba>>();
+		let linear = adapters::srgb_to_linear(pixels.iter().copied()).collect::<Vec<_>>();
+		assert_eq!(adapters::linear_to_srgb(linear.iter().copied()).collect::<Vec<_>>(), pixels);
+
+		// srgb's middle is much darker than half in linear light, and alpha is
+		// left alone
+		let (r, _, _, a) = linear[188];
+		assert!((r - 0.5).abs() < 0.005, "{r}");
+		assert_eq!(a, 188.0 / 255.0);
+		assert_eq!(linear[0].0, 0.0);
+		assert_eq!(linear[255].0, 1.0);
+
+		let px: RgbaF32 = (2.0, -1.0, f32::NAN, 0.5);
+		assert_eq!(adapters::linear_to_srgb([px].into_iter()).next(), Some((255, 0, 0, 128)));
+	}
+
+	#[test]
+	fn premultiply() {
+		let once = |px| adapters::premultiply([px].into_iter()).next().expect("pixel?");
+		let back = |px| adapters::unpremultiply([px].into_iter()).next().expect("pixel?");
+		for a in 0..=255u8 {
+			for x in 0..=255u8 {
+				let expected = (x as f64 * a as f64 / 255.0).round() as u8;
+				let px = once((x, x, x, a));
+				assert_eq!(px, (expected, expected, expected, a), "{x} {a}");
+
+				// what's lost is less than a step of the premultiplied color
+				let (y, ..) = back(px);
+				assert!(a == 0 || (y as f64 - x as f64).abs() <= 127.5 / a as f64 + 0.5, "{x} {a} {y}");
+			}
+		}
+		assert_eq!(back(once((10, 200, 30, 255))), (10, 200, 30, 255));
+		assert_eq!(back((200, 100, 3, 100)), (255, 255, 8, 100));
+		assert_eq!(back((7, 8, 9, 0)), (0, 0, 0, 0));
+	}
+
+	#[test]
+	fn grayscale() {
+		use crate::{Gray8, Pixel, Rgba, adapters::Luma};
+
+		let pixels = (0..=255).map(|x| (x, 255 - x, x / 3, x)).collect::<Vec<Rgba>>();
+		let gray: Vec<Gray8> = adapters::to_grayscale(pixels.iter().copied(), Luma::REC_601).collect();
+		// floats can come out either side of a gray that's exactly half way
+		for (&(r, g, b, a), gray) in pixels.iter().zip(gray) {
+			let expected = (r, g, b, a).convert::<Gray8>();
+			let tie = (77 * r as u32 + 150 * g as u32 + 29 * b as u32) % 256 == 128;
+			assert!(gray == expected || tie && gray.abs_diff(expected) == 1, "{r}, {g}, {b}: {gray} {expected}");
+		}
+
+		let gray = |px: Rgba, luma| -> Rgba { adapters::to_grayscale([px].into_iter(), luma).next().expect("pixel?") };
+		assert_eq!(gray((0, 255, 0, 9), Luma::default()), (182, 182, 182, 9));
+		assert_eq!(gray((0, 255, 0, 9), Luma::REC_601), (149, 149, 149, 9));
+		assert_eq!(gray((255, 255, 255, 255), Luma::REC_709), (255, 255, 255, 255));
+		assert_eq!(gray((10, 20, 200, 255), Luma { r: 0.0, g: 0.0, b: 1.0 }), (200, 200, 200, 255));
+	}
+
+	#[test]
+	fn dither_ordered() {
+		use crate::{Error, adapters::Bayer};
+
+		let dithered = |px, matrix, levels| {
+			adapters::dither_ordered(core::iter::repeat_n(px, 64), 8, 8, matrix, levels).expect("levels?").collect::<Vec<_>>()
+		};
+		// the matrices have every threshold once
+		for (matrix, size) in [(Bayer::X2, 2), (Bayer::X4, 4), (Bayer::X8, 8)] {
+			let grays = (0..=size * size).map(|n| {
+				let x = (n * 255 / (size * size)) as u8;
+				let tile = dithered((x, x, x, 255), matrix, 2);
+				tile.iter().filter(|px| px.0 == 255).count() / (64 / (size * size))
+			});
+			assert_eq!(grays.collect::<Vec<_>>(), (0..=size * size).collect::<Vec<_>>(), "{matrix:?}");
+		}
+
+		// half gray makes a checkerboard, and alpha is left alone
+		let tile = dithered((128, 128, 128, 7), Bayer::X2, 2);
+		assert_eq!(tile[..4], [(0, 0, 0, 7), (255, 255, 255, 7), (0, 0, 0, 7), (255, 255, 255, 7)]);
+		assert_eq!(tile[8..10], [(255, 255, 255, 7), (0, 0, 0, 7)]);
+
+		// black, white and exact levels stay as they are
+		for x in [0, 85, 170, 255] {
+			assert!(dithered((x, x, x, 255), Bayer::X8, 4).iter().all(|&px| px == (x, x, x, 255)));
+		}
+		// every sample comes out as one of the levels
+		let pixels = (0..=255).map(|x| (x, x, x, x)).collect::<Vec<_>>();
+		let levels = [0, 51, 102, 153, 204, 255];
+		let dithered = adapters::dither_ordered(pixels.iter().copied(), 16, 16, Bayer::X4, 6).expect("levels?");
+		assert!(dithered.zip(&pixels).all(|(px, &(_, _, _, a))| levels.contains(&px.0) && px.3 == a));
+
+		let result = adapters::dither_ordered(pixels.into_iter(), 16, 16, Bayer::X4, 1);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn dither_blue_noise() {
+		use crate::Error;
+
+		// every threshold is in the mask once, so each of the 1024 shades of a
+		// 1-bit tile has as many white pixels as it should
+		let whites = |x: u8| {
+			let tile = adapters::dither_blue_noise(core::iter::repeat_n((x, x, x, 255), 1024), 32, 32, 2).expect("levels?");
+			tile.filter(|px| px.0 == 255).count()
+		};
+		for x in 0..=255u8 {
+			let expected = (x as f64 / 255.0 * 1024.0).round() as usize;
+			assert!(whites(x).abs_diff(expected) <= 1, "{x}");
+		}
+
+		// it tiles, and alpha is left alone
+		let pixels = core::iter::repeat_n((100, 150, 200, 9), 64 * 40);
+		let dithered = adapters::dither_blue_noise(pixels, 64, 40, 3).expect("levels?").collect::<Vec<_>>();
+		assert_eq!(dithered[..32], dithered[32..64]);
+		assert_eq!(dithered[..64], dithered[32 * 64..33 * 64]);
+		assert!(dithered.iter().all(|&(r, g, b, a)| [r, g, b].iter().all(|x| [0, 128, 255].contains(x)) && a == 9));
+
+		let result = adapters::dither_blue_noise(core::iter::empty(), 1, 1, 0);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+	}
+
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn between_formats() {
+		use crate::{Format, qoi};
+
+		let image = include_bytes!("../test/small.qoi");
+		let mut data = &image[..];
+		let (header, pixels) = qoi::Qoi.decode(&mut data).expect("error?");
+		let (width, height) = (header.width, header.height);
+		let mut out = vec![];
+		qoi::Qoi.encode(adapters::flip_vertical(pixels, width, height), header, &mut out).expect("error?");
+
+		let expected = qoi::decode_to_vec(image).expect("error?").1;
+		let (_, flipped) = qoi::decode_to_vec(&out).expect("error?");
+		for y in 0..height as usize {
+			let w = width as usize;
+			assert_eq!(flipped[y * w..y * w + w], expected[(height as usize - 1 - y) * w..(height as usize - y) * w]);
+		}
+	}
+}
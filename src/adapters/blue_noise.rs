@@ -0,0 +1,47 @@
+/*!
+a tileable 32x32 blue noise mask, for `dither_blue_noise`.
+
+made by void and cluster, with a gaussian of sigma 1.5 that wraps around
+the edges, so the mask tiles without seams. each cell holds its rank, from
+0 to 1023, and every rank is there once, so whatever the threshold, the
+cells under it are spread evenly, with no clumps or patterns.
+*/
+
+/// the rank of each cell, a row at a time.
+pub(super) const MASK: [u16; SIZE * SIZE] = [
+	1000, 21, 883, 416, 992, 785, 587, 483, 136, 882, 419, 1008, 488, 61, 961, 622, 355, 151, 600, 370, 800, 490, 1005, 300, 7, 661, 242, 124, 626, 272, 804, 524,
+	709, 223, 493, 104, 541, 210, 299, 962, 695, 280, 621, 789, 220, 739, 306, 108, 845, 694, 991, 196, 900, 67, 170, 566, 877, 365, 977, 503, 913, 692, 69, 328,
+	148, 629, 841, 348, 722, 876, 639, 0, 392, 826, 72, 527, 373, 586, 922, 431, 513, 6, 291, 477, 707, 327, 655, 765, 465, 180, 735, 38, 345, 197, 470, 875,
+	404, 772, 279, 954, 59, 412, 159, 769, 554, 206, 979, 152, 873, 32, 666, 249, 747, 887, 562, 112, 859, 423, 937, 263, 91, 831, 558, 420, 844, 751, 597, 946,
+	536, 86, 571, 179, 671, 506, 1017, 319, 899, 476, 680, 285, 754, 462, 127, 1006, 176, 335, 645, 779, 229, 598, 22, 539, 989, 317, 677, 226, 1021, 125, 286, 8,
+	359, 1004, 440, 897, 810, 243, 609, 68, 723, 114, 384, 610, 934, 342, 824, 576, 410, 939, 62, 396, 1016, 149, 799, 367, 646, 140, 890, 64, 577, 457, 668, 830,
+	728, 132, 637, 314, 24, 385, 850, 452, 230, 802, 958, 53, 181, 520, 264, 726, 95, 797, 529, 273, 720, 473, 903, 204, 445, 770, 505, 278, 791, 350, 960, 228,
+	581, 872, 215, 764, 530, 711, 150, 978, 623, 326, 556, 435, 854, 662, 16, 911, 471, 209, 670, 878, 43, 307, 563, 698, 37, 959, 388, 704, 917, 42, 162, 495,
+	301, 55, 466, 987, 109, 907, 277, 514, 11, 891, 143, 721, 240, 983, 386, 619, 298, 997, 118, 447, 605, 974, 100, 839, 267, 601, 110, 190, 528, 648, 857, 767,
+	415, 926, 675, 352, 592, 424, 663, 777, 374, 689, 287, 815, 492, 106, 774, 165, 540, 834, 363, 780, 163, 741, 408, 507, 921, 341, 820, 1001, 427, 246, 369, 102,
+	602, 803, 164, 244, 837, 46, 199, 931, 122, 467, 998, 56, 589, 332, 892, 429, 718, 30, 647, 257, 893, 343, 218, 659, 156, 731, 484, 13, 615, 879, 719, 993,
+	260, 4, 538, 724, 479, 970, 316, 572, 856, 222, 633, 398, 944, 211, 665, 75, 975, 216, 928, 460, 543, 3, 793, 1011, 60, 570, 237, 788, 320, 97, 178, 504,
+	684, 965, 329, 881, 85, 758, 641, 417, 36, 516, 790, 169, 737, 544, 863, 288, 500, 377, 591, 83, 701, 953, 596, 453, 308, 886, 400, 945, 673, 561, 908, 353,
+	836, 428, 133, 613, 399, 261, 137, 1023, 706, 276, 904, 323, 14, 446, 128, 631, 817, 736, 147, 862, 297, 187, 368, 121, 832, 630, 173, 79, 459, 213, 746, 66,
+	232, 553, 932, 208, 796, 901, 550, 340, 816, 74, 567, 672, 1007, 807, 360, 933, 57, 256, 1019, 432, 784, 498, 923, 757, 233, 478, 986, 708, 847, 380, 1018, 480,
+	782, 642, 58, 697, 512, 20, 660, 188, 475, 949, 403, 138, 486, 252, 583, 198, 464, 565, 334, 653, 111, 618, 52, 549, 683, 34, 339, 552, 284, 17, 593, 145,
+	916, 375, 269, 1003, 318, 438, 957, 743, 113, 617, 227, 888, 744, 49, 963, 775, 682, 905, 15, 843, 221, 982, 295, 870, 390, 929, 778, 135, 912, 651, 828, 304,
+	92, 738, 472, 846, 153, 805, 245, 383, 868, 311, 776, 532, 349, 649, 436, 115, 387, 177, 748, 487, 354, 725, 448, 101, 192, 607, 254, 502, 729, 200, 433, 531,
+	988, 182, 579, 40, 644, 518, 65, 573, 681, 5, 972, 94, 191, 825, 293, 861, 551, 282, 994, 585, 146, 914, 534, 688, 801, 437, 1002, 82, 389, 955, 48, 679,
+	255, 823, 407, 938, 347, 880, 752, 1012, 202, 496, 401, 699, 590, 999, 509, 29, 935, 696, 80, 421, 821, 35, 250, 964, 321, 19, 643, 853, 292, 580, 867, 358,
+	501, 625, 105, 733, 174, 253, 413, 119, 325, 885, 792, 271, 455, 142, 714, 231, 624, 346, 849, 217, 632, 771, 395, 595, 157, 829, 517, 184, 756, 469, 139, 763,
+	27, 918, 312, 535, 981, 687, 481, 822, 560, 636, 154, 948, 50, 896, 391, 811, 450, 161, 526, 973, 309, 497, 71, 866, 461, 727, 357, 971, 98, 654, 235, 966,
+	443, 214, 813, 402, 10, 584, 89, 942, 239, 33, 366, 542, 742, 322, 606, 76, 1015, 745, 1, 676, 134, 924, 710, 203, 1022, 90, 251, 604, 409, 894, 333, 700,
+	608, 750, 130, 652, 851, 302, 759, 376, 713, 995, 664, 838, 474, 238, 865, 186, 557, 283, 381, 860, 442, 569, 281, 378, 657, 564, 906, 766, 39, 818, 545, 84,
+	869, 372, 990, 247, 449, 925, 194, 511, 129, 441, 274, 175, 63, 984, 669, 489, 781, 947, 616, 224, 787, 87, 951, 812, 12, 439, 144, 294, 499, 189, 1010, 270,
+	167, 568, 45, 525, 705, 70, 620, 798, 898, 582, 773, 920, 628, 406, 131, 338, 44, 434, 120, 910, 351, 690, 168, 508, 310, 858, 717, 952, 635, 364, 730, 456,
+	941, 762, 330, 874, 155, 968, 361, 258, 18, 337, 93, 523, 313, 795, 943, 703, 848, 207, 734, 547, 25, 458, 614, 761, 985, 212, 522, 397, 107, 806, 2, 658,
+	88, 418, 640, 268, 783, 425, 559, 864, 674, 1014, 430, 732, 219, 9, 546, 265, 603, 482, 1009, 290, 827, 969, 248, 394, 117, 678, 41, 842, 266, 976, 515, 303,
+	794, 195, 1020, 494, 23, 686, 201, 123, 491, 809, 183, 950, 612, 852, 451, 160, 915, 81, 382, 638, 185, 712, 54, 578, 884, 344, 599, 702, 444, 141, 588, 909,
+	485, 693, 116, 574, 902, 305, 980, 611, 393, 289, 103, 510, 371, 96, 996, 760, 324, 691, 871, 126, 519, 426, 919, 786, 262, 468, 1013, 193, 889, 753, 356, 234,
+	77, 930, 379, 241, 819, 463, 716, 78, 936, 755, 650, 895, 715, 275, 656, 414, 31, 555, 259, 768, 956, 331, 99, 533, 158, 740, 73, 315, 537, 28, 667, 855,
+	594, 296, 749, 634, 166, 51, 362, 840, 236, 548, 26, 336, 171, 835, 521, 205, 808, 940, 454, 47, 627, 225, 685, 833, 411, 927, 575, 814, 405, 967, 172, 422,
+];
+
+/// the mask's width and height.
+pub(super) const SIZE: usize = 32;
@@ -0,0 +1,67 @@
+/*!
+animations, whatever format they're in.
+
+`AnimatedFormat` decodes an animation into `Frames`, which hands out one
+frame at a time, each with its own pixels. frames come out as they're
+stored, covering only their own area of the canvas. putting them together
+is left to the caller, going by each frame's place, disposal and blending,
+which are the same for every format.
+
+a frame's pixels borrow the frames, so `Frames` isn't an iterator, and
+asking for the next frame skips whatever was left of the last.
+*/
+
+use crate::{Error, Format, Rgba};
+
+/// what happens to a frame's area before the next frame is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Dispose {
+	/// it's left as it is.
+	None,
+	/// it's cleared to transparent black.
+	Background,
+	/// it goes back to what it was before the frame.
+	Previous,
+}
+
+/// how a frame is drawn over the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Blend {
+	/// replaces what's under it, alpha included.
+	Source,
+	/// alpha composited over what's under it.
+	Over,
+}
+
+/// a frame's place in the animation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Frame {
+	pub width: u32,
+	pub height: u32,
+	/// where the frame's top left corner goes on the canvas.
+	pub x: u32,
+	pub y: u32,
+	/// how long the frame is shown.
+	pub delay: std::time::Duration,
+	pub dispose: Dispose,
+	pub blend: Blend,
+}
+
+/// the frames of an animation, read one at a time.
+pub trait Frames {
+	/// the next frame, with its pixels, `width` by `height` of them, the
+	/// last of which is the error if they can't all be decoded. `None` after
+	/// the last frame.
+	#[allow(clippy::type_complexity)]
+	fn next_frame(&mut self) -> Result<Option<(Frame, impl Iterator<Item = Result<Rgba, Error>> + '_)>, Error>;
+}
+
+/// a `Format` that can hold more than one frame.
+pub trait AnimatedFormat: Format {
+	/// reads the header, which covers the whole canvas, and returns it with
+	/// the frames. a still image is one frame covering all of it.
+	fn decode_frames(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Frames), Error>;
+}
@@ -0,0 +1,909 @@
+/*!
+bmp images.
+
+uncompressed 1, 4, 8, 24 and 32-bit images are decoded, with any of the
+usual headers, including `BI_BITFIELDS` masks of any width. 32-bit images
+without an alpha mask come out opaque, since the spec leaves that byte
+unused. `BI_RLE8` and `BI_RLE4` images are decoded too, whole, before the
+first pixel, and any pixels they skip over are index 0. indexed images
+come out as colors from their color table, which is also in the header.
+
+rows are usually stored bottom-up, the last row first. since pixels are
+handed out top to bottom, the image data is read and kept whole before the
+first pixel. top-down images, with a negative height, are read a row at a
+time instead. the encoder is the same, so it only streams top-down images.
+a reader that can seek gets around that: once it's been sought to a row,
+each row is sought to and read on its own, whichever way up they are.
+rle images can't be sought, since where a row is depends on every one
+before it, and can't be top-down either.
+
+the encoder doesn't write indexed images. 24-bit images are written with
+a `BITMAPINFOHEADER`, and 32-bit ones with a `BITMAPV4HEADER` and an
+alpha mask, which is what most tools read alpha from.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+/// `BITMAPFILEHEADER`, which every version of the format starts with.
+const FILE_HEADER_SIZE: u64 = 14;
+/// `BITMAPCOREHEADER`, `BITMAPINFOHEADER`, its v2 to v5.
+const HEADER_SIZES: [u32; 6] = [12, 40, 52, 56, 108, 124];
+
+/// channel masks of 32-bit images without a mask of their own.
+const MASKS: [u32; 4] = [0x00ff0000, 0x0000ff00, 0x000000ff, 0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum BmpColorType {
+	/// 24-bit, or 32-bit without alpha.
+	RGB,
+	/// 32-bit, with an alpha mask.
+	RGBA,
+	/// 1, 4 or 8 bits, possibly run-length encoded, indexing the color table.
+	Indexed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BmpHeader {
+	pub width: u32,
+	pub height: u32,
+	pub color_type: BmpColorType,
+	/// whether rows are stored top to bottom, rather than bmp's usual bottom
+	/// to top.
+	pub top_down: bool,
+	/// the color table of indexed images, and empty otherwise.
+	pub palette: Vec<crate::Rgba>,
+}
+
+impl crate::Dimensions for BmpHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Bmp;
+
+impl Default for Bmp {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// bytes in a row of `width` pixels of `bits` each, padded to 4 bytes.
+fn stride(width: u32, bits: u64) -> u64 {
+	(width as u64 * bits).div_ceil(32) * 4
+}
+
+/// an 8-bit channel from a pixel of a 32-bit image, given its mask, or
+/// `fallback` if there's no mask.
+fn channel(px: u32, mask: u32, fallback: u8) -> u8 {
+	if mask == 0 {
+		return fallback;
+	}
+	let max = (mask >> mask.trailing_zeros()) as u64;
+	let x = ((px & mask) >> mask.trailing_zeros()) as u64;
+	(x * 255 / max) as u8
+}
+
+/// pixels of a bmp image, read from the reader as they're asked for, or all
+/// at once before the first for bottom-up images.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct BmpPixels<'a, R> {
+	header: BmpHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// where the pixel data starts.
+	start: u64,
+	/// seeks between offsets, once a row has been sought to, so rows are
+	/// read one by one.
+	#[allow(clippy::type_complexity)]
+	seek: Option<fn(&mut R, u64, u64) -> Result<(), Error>>,
+	/// bytes per pixel in `row`, which has a byte for each pixel of an
+	/// indexed image.
+	bytes: usize,
+	bits: u16,
+	/// whether the image is `BI_RLE8` or `BI_RLE4`.
+	rle: bool,
+	/// red, green, blue and alpha masks, for 32-bit images.
+	masks: [u32; 4],
+	stride: usize,
+	/// the rows that haven't been handed out, for bottom-up images, once
+	/// they've been read.
+	rows_data: Vec<u8>,
+	/// the rows of an rle image that have pixels in them, once it's been
+	/// read, with how far up they are. the top one is last.
+	rle_rows: Vec<(u32, Vec<u8>)>,
+	row: Vec<u8>,
+	/// next pixel of `row` to hand out.
+	x: u32,
+	/// rows that haven't been handed out.
+	rows: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> BmpPixels<'a, R> {
+	/// reads the headers, up to the pixel data.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; 14 + 124];
+		crate::read_exact(data, &mut buf[..18], 0)?;
+		if buf[..2] != *b"BM" {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let u16_at = |buf: &[u8], at: usize| u16::from_le_bytes([buf[at], buf[at + 1]]);
+		let u32_at = |buf: &[u8], at: usize| u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+
+		let pixel_offset = u32_at(&buf, 10) as u64;
+		let size = u32_at(&buf, 14);
+		if !HEADER_SIZES.contains(&size) {
+			return Err(field(14, "header size"));
+		}
+		let end = (FILE_HEADER_SIZE + size as u64) as usize;
+		crate::read_exact(data, &mut buf[18..end], 18)?;
+
+		let (width, height, planes, bits, compression) = match size {
+			// the os/2 header, with 16-bit dimensions and no compression
+			12 => (u16_at(&buf, 18) as i32, u16_at(&buf, 20) as i16 as i32, u16_at(&buf, 22), u16_at(&buf, 24), 0),
+			_ => (u32_at(&buf, 18) as i32, u32_at(&buf, 22) as i32, u16_at(&buf, 26), u16_at(&buf, 28), u32_at(&buf, 30)),
+		};
+		if width <= 0 {
+			return Err(field(18, "width"));
+		}
+		// the most negative height has no positive counterpart
+		if height == 0 || height == i32::MIN {
+			return Err(field(22, "height"));
+		}
+		if planes != 1 {
+			return Err(field(26, "planes"));
+		}
+		let bytes = match bits {
+			1 | 4 | 8 => 1,
+			24 => 3,
+			32 => 4,
+			_ => return Err(field(28, "bit depth")),
+		};
+
+		// masks come after a plain info header, and inside the later ones
+		let mut offset = end as u64;
+		let masks = match (compression, bits) {
+			// BI_RGB, and BI_RLE8 and BI_RLE4, which only go with their depth
+			(0, _) | (1, 8) | (2, 4) => MASKS,
+			// BI_BITFIELDS, and BI_ALPHABITFIELDS
+			(3 | 6, 32) => {
+				let count = if compression == 6 || size >= 56 { 4 } else { 3 };
+				if size == 40 {
+					crate::read_exact(data, &mut buf[end..end + count * 4], offset)?;
+					offset += count as u64 * 4;
+				}
+				let mut masks = [0; 4];
+				for (i, mask) in masks.iter_mut().enumerate().take(count) {
+					*mask = u32_at(&buf, 54 + i * 4);
+				}
+				masks
+			}
+			_ => return Err(field(30, "compression")),
+		};
+		for mask in masks {
+			let shifted = mask.checked_shr(mask.trailing_zeros()).unwrap_or(0);
+			if shifted & shifted.wrapping_add(1) != 0 {
+				return Err(field(54, "masks"));
+			}
+		}
+
+		// rle rows have to be read bottom-up, so top-down isn't allowed
+		let rle = matches!(compression, 1 | 2);
+		if rle && height < 0 {
+			return Err(field(22, "height"));
+		}
+
+		// the color table comes right after the headers, in 3-byte entries
+		// for the os/2 header and 4-byte ones otherwise
+		let mut palette = vec![];
+		if bits <= 8 {
+			let (entry, colors) = match size {
+				12 => (3, 0),
+				_ => (4, u32_at(&buf, 46)),
+			};
+			if colors > 1 << bits {
+				return Err(field(46, "colors used"));
+			}
+			let colors = match colors {
+				0 => 1 << bits,
+				_ => colors,
+			};
+			let mut table = [0; 256 * 4];
+			let table = &mut table[..(colors * entry) as usize];
+			crate::read_exact(data, table, offset)?;
+			offset += table.len() as u64;
+			palette = table.chunks_exact(entry as usize).map(|c| (c[2], c[1], c[0], 255)).collect();
+		}
+
+		// whatever's between the headers and the pixels, like a color table
+		// of an image that isn't indexed, is skipped
+		if pixel_offset < offset {
+			return Err(field(10, "pixel data offset"));
+		}
+		let mut gap = pixel_offset - offset;
+		while gap > 0 {
+			let n = gap.min(buf.len() as u64) as usize;
+			crate::read_exact(data, &mut buf[..n], offset)?;
+			offset += n as u64;
+			gap -= n as u64;
+		}
+
+		let header = BmpHeader {
+			width: width as u32,
+			height: height.unsigned_abs(),
+			color_type: match (bits, masks[3]) {
+				(..=8, _) => BmpColorType::Indexed,
+				(_, 0) => BmpColorType::RGB,
+				_ => BmpColorType::RGBA,
+			},
+			top_down: height < 0,
+			palette,
+		};
+		let stride = stride(header.width, bits as u64);
+		let mut row = vec![];
+		// indexed rows are unpacked to a byte a pixel
+		let row_size = stride.max(header.width as u64);
+		row.try_reserve_exact(row_size.try_into().map_err(|_| oom())?).map_err(|_| oom())?;
+
+		Ok(Self {
+			x: header.width,
+			rows: header.height,
+			header,
+			data,
+			offset,
+			start: offset,
+			seek: None,
+			bytes: bytes as usize,
+			bits,
+			rle,
+			masks,
+			// reserved above, so this fits
+			stride: stride as usize,
+			rows_data: vec![],
+			rle_rows: vec![],
+			row,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &BmpHeader {
+		&self.header
+	}
+
+	/// appends `len` bytes to `rows_data`, a piece at a time, so memory is
+	/// only taken up once the data shows up.
+	fn read(&mut self, mut len: usize) -> Result<(), Error> {
+		while len > 0 {
+			let n = len.min(1 << 16);
+			let start = self.rows_data.len();
+			self.rows_data.try_reserve(n).map_err(|_| oom())?;
+			self.rows_data.resize(start + n, 0);
+			crate::read_exact(self.data, &mut self.rows_data[start..], self.offset)?;
+			self.offset = self.offset.saturating_add(n as u64);
+			len -= n;
+		}
+		Ok(())
+	}
+
+	/// reads all of an rle image into `rle_rows`. pixels that are skipped
+	/// over are left as index 0, and ones outside the image are dropped.
+	fn rle(&mut self) -> Result<(), Error> {
+		let (width, height) = (self.header.width as usize, self.header.height);
+		let four = self.bits == 4;
+		let mut rows: Vec<(u32, Vec<u8>)> = vec![];
+		let (mut x, mut y) = (0usize, 0u32);
+		let mut buf = [0; 256];
+		loop {
+			crate::read_exact(self.data, &mut buf[..2], self.offset)?;
+			self.offset = self.offset.saturating_add(2);
+
+			// a run of the same byte, or a 0 and an escape code
+			let (count, value) = match (buf[0], buf[1]) {
+				// end of line
+				(0, 0) => {
+					x = 0;
+					y = y.saturating_add(1);
+					continue;
+				}
+				// end of bitmap
+				(0, 1) => break,
+				// delta
+				(0, 2) => {
+					crate::read_exact(self.data, &mut buf[..2], self.offset)?;
+					self.offset = self.offset.saturating_add(2);
+					x = x.saturating_add(buf[0] as usize);
+					y = y.saturating_add(buf[1] as u32);
+					continue;
+				}
+				// a run of indices as they are, padded to 2 bytes
+				(0, n) => {
+					let len = if four { (n as usize).div_ceil(2) } else { n as usize };
+					let len = len + len % 2;
+					crate::read_exact(self.data, &mut buf[..len], self.offset)?;
+					self.offset = self.offset.saturating_add(len as u64);
+					(n as usize, None)
+				}
+				(n, value) => (n as usize, Some(value)),
+			};
+
+			if y >= height || x >= width {
+				x = x.saturating_add(count);
+				continue;
+			}
+			if rows.last().is_none_or(|(at, _)| *at != y) {
+				rows.try_reserve(1).map_err(|_| oom())?;
+				rows.push((y, vec![]));
+			}
+			let Some((_, row)) = rows.last_mut()
+			else {
+				continue;
+			};
+			// x only goes forward on a line, so the row doesn't go past it
+			let end = x.saturating_add(count).min(width);
+			row.try_reserve(end - row.len()).map_err(|_| oom())?;
+			row.resize(x, 0);
+			for i in 0..end - x {
+				let byte = value.unwrap_or(buf[if four { i / 2 } else { i }]);
+				// the high nibble comes first
+				row.push(match (four, i % 2) {
+					(false, _) => byte,
+					(true, 0) => byte >> 4,
+					(true, _) => byte & 0xf,
+				});
+			}
+			x = x.saturating_add(count);
+		}
+		self.rle_rows = rows;
+		Ok(())
+	}
+
+	fn next_row(&mut self) -> Result<(), Error> {
+		if self.rle {
+			if self.rows == self.header.height {
+				self.rle()?;
+			}
+			self.rows -= 1;
+			self.x = 0;
+			self.row.clear();
+			if let Some((_, row)) = self.rle_rows.pop_if(|(at, _)| *at == self.rows) {
+				self.row = row;
+			}
+			return Ok(());
+		}
+
+		if let Some(seek) = self.seek {
+			// the row from the top, and where it is
+			let y = self.header.height - self.rows;
+			let up = if self.header.top_down { y } else { self.header.height - 1 - y };
+			let at = self.start.saturating_add(up as u64 * self.stride as u64);
+			seek(self.data, self.offset, at)?;
+			self.offset = at;
+			self.rows_data.clear();
+			self.read(self.stride)?;
+		}
+		else if self.header.top_down {
+			self.rows_data.clear();
+			self.read(self.stride)?;
+		}
+		else if self.rows == self.header.height {
+			for _ in 0..self.header.height {
+				self.read(self.stride)?;
+			}
+		}
+
+		// the last row left is the top one
+		let start = self.rows_data.len().saturating_sub(self.stride);
+		self.row.clear();
+		self.row.extend_from_slice(&self.rows_data[start..]);
+		self.rows_data.truncate(start);
+
+		// indices are unpacked in place from the end, since a pixel never
+		// starts past its own index in the packed row
+		if self.bits < 8 {
+			let bits = self.bits as usize;
+			let width = self.header.width as usize;
+			self.row.resize(self.row.len().max(width), 0);
+			for x in (0..width).rev() {
+				let shift = 8 - bits - x * bits % 8;
+				self.row[x] = (self.row[x * bits / 8] >> shift) & ((1 << bits) - 1);
+			}
+		}
+
+		self.rows -= 1;
+		self.x = 0;
+		Ok(())
+	}
+
+	/// skips any pixels that are left, and returns the number of bytes the
+	/// image took up. nothing after the pixel data is read.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if let Some(seek) = self.seek {
+			let end = self.start.saturating_add(self.header.height as u64 * self.stride as u64);
+			seek(self.data, self.offset, end)?;
+			return Ok(end);
+		}
+		// bottom-up images have been read whole once they've been started.
+		// where an rle image ends can only be found by decoding it
+		if self.rle {
+			if self.rows == self.header.height {
+				self.rle()?;
+			}
+		}
+		else if self.header.top_down || self.rows == self.header.height {
+			let mut buf = [0; 512];
+			let mut left = (self.rows as u64).saturating_mul(self.stride as u64);
+			while left > 0 {
+				let n = left.min(buf.len() as u64) as usize;
+				crate::read_exact(self.data, &mut buf[..n], self.offset)?;
+				self.offset = self.offset.saturating_add(n as u64);
+				left -= n as u64;
+			}
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<R: std::io::Read + std::io::Seek> BmpPixels<'_, R> {
+	/// goes to the start of row `y`, from the top, forward or back. the rows
+	/// from there on are each sought to and read on their own. `y` can be
+	/// the height, past which is `OutOfBounds`.
+	pub fn seek_row(&mut self, y: u32) -> Result<(), Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		if self.rle {
+			return Err(Error::InvalidInput { reason: "rle images can't be sought" });
+		}
+		if y > self.header.height {
+			return Err(Error::OutOfBounds { x: 0, y, width: self.header.width, height: 1 });
+		}
+		self.seek = Some(crate::seek::seek::<R>);
+		self.rows_data.clear();
+		self.rows = self.header.height - y;
+		self.x = self.header.width;
+		Ok(())
+	}
+}
+
+impl<R: std::io::Read> Iterator for BmpPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+
+		if self.x == self.header.width {
+			if self.rows == 0 {
+				return None;
+			}
+			if let Err(e) = self.next_row() {
+				self.failed = true;
+				return Some(Err(e));
+			}
+		}
+
+		let i = self.x as usize * self.bytes;
+		// rle rows stop after their last pixel, and the rest are index 0
+		let px = self.row.get(i..i + self.bytes).unwrap_or(&[0]);
+		self.x += 1;
+
+		Some(Ok(match *px {
+			[i] => match self.header.palette.get(i as usize) {
+				Some(&px) => px,
+				None => {
+					self.failed = true;
+					let reason = "color index is past the end of the color table";
+					return Some(Err(Error::Malformed { offset: self.offset, reason }));
+				}
+			},
+			[b, g, r] => (r, g, b, 255),
+			[a, b, c, d] => {
+				let px = u32::from_le_bytes([a, b, c, d]);
+				let [r, g, b, a] = self.masks;
+				(channel(px, r, 0), channel(px, g, 0), channel(px, b, 0), channel(px, a, 255))
+			}
+			_ => return None,
+		}))
+	}
+}
+
+/// `BmpPixels`, ending at the first error, for `Format::decode`.
+struct Pixels<'a, R>(BmpPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// push-based encoder state, which `Bmp::encode` drives.
+#[derive(Debug)]
+pub(crate) struct BmpEncoder<W> {
+	out: W,
+	color_type: BmpColorType,
+	top_down: bool,
+	/// bytes of pixels in a row, padding not included.
+	row_size: usize,
+	stride: usize,
+	row: Vec<u8>,
+	/// the rows so far, for bottom-up images.
+	rows: Vec<u8>,
+}
+
+impl<W: std::io::Write> BmpEncoder<W> {
+	/// writes the headers.
+	pub(crate) fn new(header: &BmpHeader, mut out: W) -> Result<Self, Error> {
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		if header.width == 0 || header.width > i32::MAX as u32 {
+			return Err(field(18, "width"));
+		}
+		if header.height == 0 || header.height > i32::MAX as u32 {
+			return Err(field(22, "height"));
+		}
+
+		// BI_RGB, or BI_BITFIELDS with an alpha mask
+		let (bytes, size, compression): (u64, u32, u32) = match header.color_type {
+			BmpColorType::RGB => (3, 40, 0),
+			BmpColorType::RGBA => (4, 108, 3),
+			BmpColorType::Indexed => return Err(field(28, "bit depth")),
+		};
+		let stride = stride(header.width, bytes * 8);
+		let image_size = stride * header.height as u64;
+		let pixel_offset = FILE_HEADER_SIZE as u32 + size;
+		let (Ok(image_size), Ok(file_size)) = (u32::try_from(image_size), u32::try_from(image_size + pixel_offset as u64))
+		else {
+			return Err(field(2, "file size"));
+		};
+
+		let mut buf = vec![];
+		buf.extend_from_slice(b"BM");
+		buf.extend_from_slice(&file_size.to_le_bytes());
+		buf.extend_from_slice(&[0; 4]);
+		buf.extend_from_slice(&pixel_offset.to_le_bytes());
+
+		let height = match header.top_down {
+			true => -(header.height as i32),
+			false => header.height as i32,
+		};
+		buf.extend_from_slice(&size.to_le_bytes());
+		buf.extend_from_slice(&(header.width as i32).to_le_bytes());
+		buf.extend_from_slice(&height.to_le_bytes());
+		buf.extend_from_slice(&1u16.to_le_bytes());
+		buf.extend_from_slice(&(bytes as u16 * 8).to_le_bytes());
+		buf.extend_from_slice(&compression.to_le_bytes());
+		buf.extend_from_slice(&image_size.to_le_bytes());
+		// 72 dpi
+		buf.extend_from_slice(&2835u32.to_le_bytes());
+		buf.extend_from_slice(&2835u32.to_le_bytes());
+		// no color table
+		buf.extend_from_slice(&[0; 8]);
+
+		if header.color_type == BmpColorType::RGBA {
+			for mask in [0x00ff0000u32, 0x0000ff00, 0x000000ff, 0xff000000] {
+				buf.extend_from_slice(&mask.to_le_bytes());
+			}
+			buf.extend_from_slice(b"BGRs");
+			// endpoints and gamma, which srgb doesn't use
+			buf.extend_from_slice(&[0; 48]);
+		}
+		crate::write_all(&mut out, &buf)?;
+
+		let mut row = vec![];
+		row.try_reserve_exact(stride as usize).map_err(|_| oom())?;
+
+		Ok(Self {
+			out,
+			color_type: header.color_type,
+			top_down: header.top_down,
+			// `file_size` checked that these fit
+			row_size: (header.width as u64 * bytes) as usize,
+			stride: stride as usize,
+			row,
+			rows: vec![],
+		})
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		match self.color_type {
+			BmpColorType::RGB => self.row.extend_from_slice(&[px.2, px.1, px.0]),
+			_ => self.row.extend_from_slice(&[px.2, px.1, px.0, px.3]),
+		}
+		if self.row.len() == self.row_size {
+			self.end_row()?;
+		}
+		Ok(())
+	}
+
+	fn end_row(&mut self) -> Result<(), Error> {
+		self.row.resize(self.stride, 0);
+		if self.top_down {
+			crate::write_all(&mut self.out, &self.row)?;
+		}
+		else {
+			self.rows.try_reserve(self.stride).map_err(|_| oom())?;
+			self.rows.extend_from_slice(&self.row);
+		}
+		self.row.clear();
+		Ok(())
+	}
+
+	/// writes a row that didn't get all its pixels, and for bottom-up
+	/// images, every row, last first.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		if !self.row.is_empty() {
+			self.end_row()?;
+		}
+		for row in self.rows.rchunks_exact(self.stride) {
+			crate::write_all(&mut self.out, row)?;
+		}
+		self.rows.clear();
+		Ok(())
+	}
+}
+
+impl crate::Format for Bmp {
+	type Header = BmpHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = BmpPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = BmpPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = BmpEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+/// rle images are `InvalidInput`.
+impl crate::SeekDecode for Bmp {
+	fn decode_from_row<R: std::io::Read + std::io::Seek>(self, data: &mut R, y: u32) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let mut pixels = BmpPixels::new(data)?;
+		pixels.seek_row(y)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, bmp};
+
+	const IMAGE_GRADIENT: &[u8] = include_bytes!("../test/gradient.bmp");
+
+	fn gradient(x: u32, y: u32) -> crate::Rgba {
+		((x * 7) as u8, (y * 30) as u8, (x * y) as u8, 255)
+	}
+
+	#[test]
+	fn decode() {
+		// bottom-up, with a byte of padding on every row. the top-down one has
+		// a color table before the pixels, to be skipped
+		for (image, top_down) in [(IMAGE_GRADIENT, false), (include_bytes!("../test/gradient_top_down.bmp"), true)] {
+			let mut data = image;
+			let mut pixels = bmp::BmpPixels::new(&mut data).expect("error?");
+			let header = pixels.header();
+			assert_eq!((header.width, header.height, header.color_type), (33, 7, bmp::BmpColorType::RGB));
+			assert_eq!(header.top_down, top_down);
+
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 33, i as u32 / 33);
+				assert_eq!(px.expect("error?"), gradient(x, y), "{x}, {y}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+
+		// the os/2 header
+		let mut data = &include_bytes!("../test/core.bmp")[..];
+		let (_, iter) = bmp::Bmp.decode(&mut data).expect("error?");
+		assert!(iter.eq((0..33 * 7).map(|i| gradient(i % 33, i / 33))));
+	}
+
+	#[test]
+	fn seek() {
+		use crate::SeekDecode;
+
+		for image in [IMAGE_GRADIENT, include_bytes!("../test/gradient_top_down.bmp"), include_bytes!("../test/indexed_4.bmp")] {
+			let mut data = image;
+			let (header, iter) = bmp::Bmp.decode(&mut data).expect("error?");
+			let all = iter.collect::<Vec<_>>();
+			let width = header.width as usize;
+
+			let mut data = std::io::Cursor::new(image);
+			let (_, iter) = bmp::Bmp.decode_from_row(&mut data, 3).expect("error?");
+			assert!(iter.map(|px| px.expect("error?")).eq(all[width * 3..].iter().copied()));
+
+			// back, partway through a row, and to the end
+			let mut data = std::io::Cursor::new(image);
+			let mut pixels = bmp::BmpPixels::new(&mut data).expect("error?");
+			(&mut pixels).take(width * 2 + 5).for_each(drop);
+			pixels.seek_row(1).expect("error?");
+			assert!((&mut pixels).take(width * 2).map(|px| px.expect("error?")).eq(all[width..width * 3].iter().copied()));
+			pixels.seek_row(header.height).expect("error?");
+			assert!(pixels.next().is_none());
+			let result = pixels.seek_row(header.height + 1);
+			assert!(matches!(result, Err(Error::OutOfBounds { .. })), "{result:?}");
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+
+		let mut data = std::io::Cursor::new(include_bytes!("../test/rle8.bmp"));
+		let result = bmp::Bmp.decode_from_row(&mut data, 0).map(|_| ());
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn decode_masks() {
+		// a v4 header with an alpha mask
+		let mut data = &include_bytes!("../test/small.bmp")[..];
+		let (header, iter) = bmp::Bmp.decode(&mut data).expect("error?");
+		assert_eq!(header.color_type, bmp::BmpColorType::RGBA);
+		let expected = (0..16).map(|i| ((i * 16) as u8, (i * 3) as u8, (255 - i) as u8, (i * 17) as u8));
+		assert!(iter.eq(expected));
+
+		// BI_RGB, with junk where alpha would be
+		let mut data = &include_bytes!("../test/rgbx.bmp")[..];
+		let (header, iter) = bmp::Bmp.decode(&mut data).expect("error?");
+		assert_eq!(header.color_type, bmp::BmpColorType::RGB);
+		assert!(iter.eq((0..33 * 7).map(|i| gradient(i % 33, i / 33))));
+
+		// 10 bits a channel, in masks after an info header
+		let mut data = &include_bytes!("../test/bitfields.bmp")[..];
+		let (_, iter) = bmp::Bmp.decode(&mut data).expect("error?");
+		let expected = (0..16).map(|i| {
+			let scale = |x: u32| (x * 255 / 1023) as u8;
+			(scale(i * 64), scale(1023 - i * 50), scale(i * i), 255)
+		});
+		assert!(iter.eq(expected));
+	}
+
+	#[test]
+	fn decode_indexed() {
+		fn color(i: u32) -> crate::Rgba {
+			(i as u8, (255 - i) as u8, (i * 3) as u8, 255)
+		}
+
+		// the os/2 one has a color table of 3-byte entries
+		for (image, bits) in [
+			(&include_bytes!("../test/indexed_1.bmp")[..], 1),
+			(include_bytes!("../test/indexed_4.bmp"), 4),
+			(include_bytes!("../test/indexed_8.bmp"), 8),
+			(include_bytes!("../test/indexed_core.bmp"), 8),
+		] {
+			let mut data = image;
+			let mut pixels = bmp::BmpPixels::new(&mut data).expect("error?");
+			let header = pixels.header();
+			assert_eq!((header.width, header.height, header.color_type), (13, 5, bmp::BmpColorType::Indexed));
+			assert_eq!(header.palette.len(), 1 << bits);
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				assert_eq!(px.expect("error?"), color((x * 7 + y * 3) % (1 << bits)), "{bits}: {x}, {y}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+
+		// runs, absolute runs, a delta, and runs past the right edge
+		for (image, four) in [(&include_bytes!("../test/rle8.bmp")[..], false), (include_bytes!("../test/rle4.bmp"), true)] {
+			let rle = |x: u32, y: u32| match x {
+				0..8 if four && x % 2 == 1 => 15 - y,
+				0..8 => y,
+				8..14 if y == 2 => 0,
+				8..14 => (x + y) % 16,
+				_ if y.is_multiple_of(2) => 0,
+				_ => 15,
+			};
+			let mut data = image;
+			let mut pixels = bmp::BmpPixels::new(&mut data).expect("error?");
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 20, i as u32 / 20);
+				assert_eq!(px.expect("error?"), color(rle(x, y)), "{four}: {x}, {y}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			// where it ends is found without handing out any pixels
+			let mut data = image;
+			let pixels = bmp::BmpPixels::new(&mut data).expect("error?");
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+	}
+
+	#[test]
+	fn encode() {
+		for color_type in [bmp::BmpColorType::RGB, bmp::BmpColorType::RGBA] {
+			for top_down in [false, true] {
+				let (width, height) = (13, 5);
+				let header = bmp::BmpHeader { width, height, color_type, top_down, palette: vec![] };
+				let pixels = (0..width * height).map(|i| ((i * 3) as u8, (i / width * 40) as u8, i as u8 ^ 0x55, (i * 7) as u8));
+
+				let mut out = vec![];
+				bmp::Bmp.encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+
+				let mut data = &out[..];
+				let mut decoded = bmp::BmpPixels::new(&mut data).expect("error?");
+				assert_eq!(*decoded.header(), header);
+				for (i, (a, b)) in (&mut decoded).zip(pixels).enumerate() {
+					let b = match color_type {
+						bmp::BmpColorType::RGB => (b.0, b.1, b.2, 255),
+						_ => b,
+					};
+					assert_eq!(a.expect("error?"), b, "{i}");
+				}
+				assert_eq!(decoded.finish().expect("error?"), out.len() as u64);
+			}
+		}
+
+		// the header says how big the whole file is
+		let header = bmp::BmpHeader { width: 33, height: 7, color_type: bmp::BmpColorType::RGB, top_down: false, palette: vec![] };
+		let mut out = vec![];
+		bmp::Bmp.encode((0..33 * 7).map(|i| gradient(i % 33, i / 33)), header.clone(), &mut out).expect("error?");
+		assert_eq!(u32::from_le_bytes([out[2], out[3], out[4], out[5]]) as usize, out.len());
+		assert_eq!(out, IMAGE_GRADIENT);
+
+		// indexed images aren't written
+		let header = bmp::BmpHeader { color_type: bmp::BmpColorType::Indexed, palette: vec![(0, 0, 0, 255)], ..header };
+		let result = bmp::BmpEncoder::new(&header, vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 28, .. })));
+
+		// too big for the format
+		let header = bmp::BmpHeader { width: 1 << 16, height: 1 << 16, color_type: bmp::BmpColorType::RGBA, top_down: true, palette: vec![] };
+		let result = bmp::BmpEncoder::new(&header, vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 2, .. })));
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = bmp::BmpPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".bmp")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("bad_header_size", Error::InvalidHeader { offset: 14, field: "header size" });
+		case!("zero_width", Error::InvalidHeader { offset: 18, field: "width" });
+		case!("bad_bit_depth", Error::InvalidHeader { offset: 28, field: "bit depth" });
+		case!("bad_compression", Error::InvalidHeader { offset: 30, field: "compression" });
+		case!("bad_masks", Error::InvalidHeader { offset: 54, field: "masks" });
+		case!("bad_offset", Error::InvalidHeader { offset: 10, field: "pixel data offset" });
+		case!("truncated", Error::UnexpectedEof { offset: 500 });
+		case!("bad_colors_used", Error::InvalidHeader { offset: 46, field: "colors used" });
+		case!("bad_color_index", Error::Malformed { reason: "color index is past the end of the color table", .. });
+		case!("rle_top_down", Error::InvalidHeader { offset: 22, field: "height" });
+		case!("rle_bad_depth", Error::InvalidHeader { offset: 30, field: "compression" });
+		case!("rle_truncated", Error::UnexpectedEof { offset: 190 });
+
+		assert!(decode(IMAGE_GRADIENT).is_ok());
+	}
+}
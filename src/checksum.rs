@@ -0,0 +1,235 @@
+/*!
+crc32 checksums of encoded streams.
+
+this is the common ieee crc32 (the one zlib, png and gzip use), computed
+while encoding so the output doesn't need to be read back.
+*/
+
+use crate::{Error, Format, Rgba};
+
+const TABLE: [u32; 256] = {
+	let mut table = [0; 256];
+	let mut i = 0;
+	while i < 256 {
+		let mut c = i as u32;
+		let mut k = 0;
+		while k < 8 {
+			c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+			k += 1;
+		}
+		table[i] = c;
+		i += 1;
+	}
+	table
+};
+
+/// running crc32.
+#[derive(Debug, Clone)]
+pub struct Crc32 {
+	value: u32,
+}
+
+impl Default for Crc32 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Crc32 {
+	pub fn new() -> Self {
+		Self { value: 0xffffffff }
+	}
+
+	pub fn update(&mut self, data: &[u8]) {
+		for &x in data {
+			self.value = TABLE[((self.value ^ x as u32) & 0xff) as usize] ^ (self.value >> 8);
+		}
+	}
+
+	pub fn finish(&self) -> u32 {
+		self.value ^ 0xffffffff
+	}
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+	let mut crc = Crc32::new();
+	crc.update(data);
+	crc.finish()
+}
+
+/// writer that checksums and counts everything written through it.
+///
+/// encoders don't report write errors yet, so the first one is also kept
+/// here to be checked afterwards.
+#[derive(Debug)]
+pub struct ChecksumWriter<W> {
+	inner: W,
+	crc: Crc32,
+	written: u64,
+	error: Option<std::io::Error>,
+}
+
+impl<W: std::io::Write> ChecksumWriter<W> {
+	pub fn new(inner: W) -> Self {
+		Self {
+			inner,
+			crc: Crc32::new(),
+			written: 0,
+			error: None,
+		}
+	}
+
+	/// checksum of the bytes written so far.
+	pub fn checksum(&self) -> u32 {
+		self.crc.finish()
+	}
+
+	/// number of bytes written so far.
+	pub fn written(&self) -> u64 {
+		self.written
+	}
+
+	/// takes the first write error, if there was one.
+	pub fn take_error(&mut self) -> Option<std::io::Error> {
+		self.error.take()
+	}
+
+	pub fn into_inner(self) -> W {
+		self.inner
+	}
+}
+
+impl<W: std::io::Write> std::io::Write for ChecksumWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		match self.inner.write(buf) {
+			Ok(n) => {
+				let n = n.min(buf.len());
+				self.crc.update(&buf[..n]);
+				self.written += n as u64;
+				Ok(n)
+			}
+			Err(e) => {
+				let kind = e.kind();
+				self.error.get_or_insert(e);
+				Err(kind.into())
+			}
+		}
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+/// encodes `data` and returns the number of bytes written along with their crc32.
+/// the checksum covers every byte of the output, header and end marker included.
+pub fn encode_with_checksum<F: Format>(
+	format: F,
+	data: impl Iterator<Item = Rgba>,
+	header: F::Header,
+	out: &mut impl std::io::Write,
+) -> Result<(u64, u32), Error> {
+	let mut writer = ChecksumWriter::new(out);
+	let result = format.encode(data, header, &mut writer);
+	// the writer's own error, since the encoder only saw its kind
+	if let Some(e) = writer.take_error() {
+		return Err(e.into());
+	}
+	result?;
+	Ok((writer.written(), writer.checksum()))
+}
+
+/// reads `data` to the end and checks its crc32 against `expected`.
+pub fn verify_checksum(data: &mut impl std::io::Read, expected: u32) -> Result<(), Error> {
+	let mut crc = Crc32::new();
+	let mut buf = [0; 4096];
+	loop {
+		match data.read(&mut buf) {
+			Ok(0) => break,
+			Ok(n) => crc.update(&buf[..n.min(buf.len())]),
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e.into()),
+		}
+	}
+
+	let actual = crc.finish();
+	if actual != expected {
+		return Err(Error::ChecksumMismatch { expected, actual });
+	}
+	Ok(())
+}
+
+
+#[cfg(all(test, feature = "qoi"))]
+mod test {
+	use crate::{Error, checksum, qoi};
+
+	const IMAGE_SMALL: &[u8; 44] = include_bytes!("../test/small.qoi");
+
+	#[test]
+	fn crc32() {
+		// the standard check value, and zlib.crc32 of the fixture
+		assert_eq!(checksum::crc32(b"123456789"), 0xcbf43926);
+		assert_eq!(checksum::crc32(IMAGE_SMALL), 0x0083b7f1);
+
+		let mut crc = checksum::Crc32::new();
+		crc.update(&IMAGE_SMALL[..10]);
+		crc.update(&IMAGE_SMALL[10..]);
+		assert_eq!(crc.finish(), 0x0083b7f1);
+	}
+
+	#[test]
+	fn encode() {
+		let data = [(0, 0, 0, 255), (10, 20, 30, 255), (10, 20, 30, 255), (1, 2, 3, 4)];
+		let header = qoi::QoiHeader {
+			width: 2,
+			height: 2,
+			channels: qoi::QoiHeaderChannels::RGBA,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+
+		let mut out = vec![];
+		let (written, crc) = checksum::encode_with_checksum(qoi::Qoi, data.iter().cloned(), header, &mut out).expect("error?");
+
+		assert_eq!(written, out.len() as u64);
+		assert_eq!(crc, checksum::crc32(&out));
+
+		checksum::verify_checksum(&mut &out[..], crc).expect("error?");
+
+		out[20] ^= 0b100;
+		let result = checksum::verify_checksum(&mut &out[..], crc);
+		assert!(matches!(result, Err(Error::ChecksumMismatch { expected, .. }) if expected == crc), "{result:?}");
+	}
+
+	#[test]
+	fn overstated_counts() {
+		// io that claims to have moved more than the buffer it was given,
+		// until it runs out of lies
+		struct Liar(usize);
+		impl std::io::Read for Liar {
+			fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+				if self.0 == 0 {
+					return Ok(0);
+				}
+				self.0 -= 1;
+				Ok(buf.len() + 1)
+			}
+		}
+		impl std::io::Write for Liar {
+			fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+				Ok(buf.len() + 1)
+			}
+			fn flush(&mut self) -> std::io::Result<()> {
+				Ok(())
+			}
+		}
+
+		let mut writer = checksum::ChecksumWriter::new(Liar(0));
+		_ = std::io::Write::write(&mut writer, IMAGE_SMALL);
+		assert_eq!(writer.written(), IMAGE_SMALL.len() as u64);
+		assert_eq!(writer.checksum(), 0x0083b7f1);
+
+		let mut reader = Liar(4);
+		assert!(checksum::verify_checksum(&mut reader, 0).is_err());
+	}
+}
@@ -0,0 +1,173 @@
+/*!
+tokens of c source, for the formats that are c source: xbm, and xpm.
+
+whitespace and comments are skipped between tokens, which are read a byte at
+a time so nothing past the end of the image is.
+*/
+
+// xbm doesn't read strings
+#![cfg_attr(not(feature = "xpm"), allow(dead_code))]
+
+use crate::Error;
+
+/// the longest identifier that's read.
+pub(crate) const WORD_MAX: usize = 256;
+
+pub(crate) fn is_word(b: u8) -> bool {
+	b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// whether `name` can be a c identifier.
+pub(crate) fn is_identifier(name: &str) -> bool {
+	name.bytes().all(is_word) && !name.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// tokens of c source, read a byte at a time.
+#[derive(Debug)]
+pub(crate) struct Source<'a, R> {
+	data: &'a mut R,
+	/// where the next byte `byte` returns is.
+	pub(crate) offset: u64,
+	/// a byte that was read, then put back.
+	pending: Option<u8>,
+}
+
+impl<'a, R: std::io::Read> Source<'a, R> {
+	pub(crate) fn new(data: &'a mut R) -> Self {
+		Self { data, offset: 0, pending: None }
+	}
+
+	pub(crate) fn byte(&mut self) -> Result<u8, Error> {
+		let b = match self.pending.take() {
+			Some(b) => b,
+			None => {
+				let mut buf = [0];
+				crate::read_exact(self.data, &mut buf, self.offset)?;
+				buf[0]
+			}
+		};
+		self.offset = self.offset.saturating_add(1);
+		Ok(b)
+	}
+
+	pub(crate) fn unread(&mut self, b: u8) {
+		self.pending = Some(b);
+		self.offset -= 1;
+	}
+
+	/// skips whitespace and comments, and returns the byte after them
+	/// without taking it.
+	pub(crate) fn peek(&mut self) -> Result<u8, Error> {
+		loop {
+			let b = self.byte()?;
+			if b.is_ascii_whitespace() {
+				continue;
+			}
+			if b != b'/' {
+				self.unread(b);
+				return Ok(b);
+			}
+			let start = self.offset - 1;
+			match self.byte()? {
+				b'*' => {
+					let mut last = 0;
+					loop {
+						let b = self.byte()?;
+						if last == b'*' && b == b'/' {
+							break;
+						}
+						last = b;
+					}
+				}
+				b'/' => while self.byte()? != b'\n' {},
+				_ => return Err(Error::Malformed { offset: start, reason: "there's a / outside a comment" }),
+			}
+		}
+	}
+
+	/// takes `c`, after whatever comes before it.
+	pub(crate) fn expect(&mut self, c: u8, field: &'static str) -> Result<(), Error> {
+		if self.peek()? != c {
+			return Err(Error::InvalidHeader { offset: self.offset, field });
+		}
+		self.byte()?;
+		Ok(())
+	}
+
+	/// reads a string, after whatever comes before it, and returns where it
+	/// starts and what's between the quotes. there are no escapes, as xpm
+	/// has it.
+	pub(crate) fn string(&mut self, max: usize, field: &'static str) -> Result<(u64, Vec<u8>), Error> {
+		self.expect(b'"', field)?;
+		let start = self.offset - 1;
+		let mut string = vec![];
+		loop {
+			match self.byte()? {
+				b'"' => return Ok((start, string)),
+				_ if string.len() == max => return Err(Error::InvalidHeader { offset: start, field }),
+				b => string.push(b),
+			}
+		}
+	}
+
+	/// reads an identifier, or a keyword, and returns where it starts.
+	pub(crate) fn word(&mut self, field: &'static str) -> Result<(u64, String), Error> {
+		self.peek()?;
+		let start = self.offset;
+		let mut word = String::new();
+		loop {
+			let b = self.byte()?;
+			if !is_word(b) {
+				self.unread(b);
+				break;
+			}
+			if word.len() == WORD_MAX {
+				return Err(Error::InvalidHeader { offset: start, field });
+			}
+			word.push(b as char);
+		}
+		if word.is_empty() {
+			return Err(Error::InvalidHeader { offset: start, field });
+		}
+		Ok((start, word))
+	}
+
+	/// reads a hex or decimal number, returning where it starts, or `None`
+	/// if there isn't one there. big ones saturate.
+	pub(crate) fn number(&mut self) -> Result<(u64, Option<u64>), Error> {
+		let first = self.peek()?;
+		let start = self.offset;
+		if !first.is_ascii_digit() {
+			return Ok((start, None));
+		}
+		self.byte()?;
+		let mut radix = 10;
+		let mut n = (first - b'0') as u64;
+		let mut digits = 1;
+		if first == b'0' {
+			let b = self.byte()?;
+			match b {
+				b'x' | b'X' => {
+					radix = 16;
+					digits = 0;
+				}
+				_ => self.unread(b),
+			}
+		}
+		let end = loop {
+			let b = self.byte()?;
+			let Some(digit) = (b as char).to_digit(radix)
+			else {
+				self.unread(b);
+				break b;
+			};
+			n = n.saturating_mul(radix as u64).saturating_add(digit as u64);
+			digits += 1;
+		};
+		// `0x` without digits, or digits running into a word
+		if digits == 0 || is_word(end) {
+			return Ok((start, None));
+		}
+		Ok((start, Some(n)))
+	}
+}
@@ -0,0 +1,601 @@
+/*!
+directdraw surfaces, for uncompressed 32-bit textures and bc1 and bc3
+compressed ones. there's no encoder.
+
+the file starts with the magic and a 124-byte header, with the size of the
+largest mip level, how many levels there are, and a pixel format of channel
+masks or a four-character code. the code `DX10` means another 20-byte header
+follows, with a dxgi format and how many surfaces there are. the surfaces
+come one after another, each with its levels from the largest down, every
+level half the size of the last, rounded down, but never smaller than 1.
+
+only the first surface is decoded, at whichever level is asked for. levels
+before it are skipped, and rows are read as they're asked for, or for
+compressed textures, rows of 4 by 4 blocks, with the pixels of blocks that
+go past the edge dropped. cube maps and arrays are decoded too, as their
+first face or element, but volume textures aren't. rows all take up the
+same number of bytes, so a reader that can seek can jump to any of them.
+*/
+
+mod bc;
+
+use crate::Error;
+use crate::error::oom;
+
+const MAGIC: [u8; 4] = *b"DDS ";
+/// the magic and `DDS_HEADER`.
+const HEADER_SIZE: u64 = 4 + 124;
+/// `DDS_HEADER_DXT10`.
+const DX10_SIZE: u64 = 20;
+
+/// pixel format flags.
+const ALPHA_PIXELS: u32 = 0x1;
+const FOUR_CC: u32 = 0x4;
+const RGB: u32 = 0x40;
+
+/// `caps2` flags.
+const CUBEMAP: u32 = 0x200;
+const VOLUME: u32 = 0x200000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum DdsFormat {
+	/// 8 bits a channel, in the order of the name.
+	R8G8B8A8,
+	/// `R8G8B8A8`, with the last byte unused.
+	R8G8B8X8,
+	B8G8R8A8,
+	B8G8R8X8,
+	/// 8-byte blocks, with alpha of either 0 or 255. `DXT1`.
+	Bc1,
+	/// 16-byte blocks, with 8 bits of alpha. `DXT5`.
+	Bc3,
+}
+
+impl DdsFormat {
+	/// rows of pixels that are stored together.
+	fn rows(self) -> u32 {
+		match self {
+			Self::Bc1 | Self::Bc3 => 4,
+			_ => 1,
+		}
+	}
+
+	/// bytes a row of `width` pixels, or of blocks, takes up.
+	fn row_len(self, width: u32) -> u64 {
+		match self {
+			Self::Bc1 => width.div_ceil(4) as u64 * 8,
+			Self::Bc3 => width.div_ceil(4) as u64 * 16,
+			_ => width as u64 * 4,
+		}
+	}
+
+	/// the format of a dxgi format, if it's one that's decoded. srgb and
+	/// typeless formats store the same bytes.
+	fn dxgi(format: u32) -> Option<Self> {
+		match format {
+			27..=29 => Some(Self::R8G8B8A8),
+			70..=72 => Some(Self::Bc1),
+			76..=78 => Some(Self::Bc3),
+			87 | 90 | 91 => Some(Self::B8G8R8A8),
+			88 | 92 | 93 => Some(Self::B8G8R8X8),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DdsHeader {
+	/// of the largest mip level.
+	pub width: u32,
+	pub height: u32,
+	pub format: DdsFormat,
+	/// at least 1.
+	pub mip_levels: u32,
+	/// surfaces, each with its own mip levels. six for each cube map.
+	pub array_size: u32,
+	pub cube: bool,
+}
+
+impl DdsHeader {
+	/// the size of mip level `level`.
+	pub fn level_size(&self, level: u32) -> (u32, u32) {
+		let half = |x: u32| x.checked_shr(level).unwrap_or(0).max(1);
+		(half(self.width), half(self.height))
+	}
+
+	/// bytes mip level `level` takes up.
+	fn level_len(&self, level: u32) -> u64 {
+		let (width, height) = self.level_size(level);
+		(height.div_ceil(self.format.rows()) as u64).saturating_mul(self.format.row_len(width))
+	}
+}
+
+impl crate::Dimensions for DdsHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// pixels of a mip level of a dds texture, read from the reader a row at a
+/// time as they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct DdsPixels<'a, R> {
+	header: DdsHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// where the level being decoded starts.
+	start: u64,
+	/// the size of the level being decoded.
+	width: u32,
+	height: u32,
+	row: Vec<u8>,
+	/// the pixels of a row of blocks, for compressed textures.
+	block_rows: Vec<crate::Rgba>,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> DdsPixels<'a, R> {
+	/// reads the headers, up to the largest mip level.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		Self::level(data, 0)
+	}
+
+	/// reads the headers, and skips to mip level `level`, 0 being the
+	/// largest.
+	pub fn level(data: &'a mut R, level: u32) -> Result<Self, Error> {
+		let mut buf = [0; (HEADER_SIZE + DX10_SIZE) as usize];
+		crate::read_exact(data, &mut buf[..HEADER_SIZE as usize], 0)?;
+		if buf[..4] != MAGIC {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let u32_at = |buf: &[u8], at: usize| u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		if u32_at(&buf, 4) != 124 {
+			return Err(field(4, "header size"));
+		}
+		let height = u32_at(&buf, 12);
+		if height == 0 {
+			return Err(field(12, "height"));
+		}
+		let width = u32_at(&buf, 16);
+		if width == 0 {
+			return Err(field(16, "width"));
+		}
+		let mip_levels = u32_at(&buf, 28).max(1);
+		let caps2 = u32_at(&buf, 112);
+		if caps2 & VOLUME != 0 && u32_at(&buf, 24) > 1 {
+			return Err(field(24, "depth"));
+		}
+
+		let flags = u32_at(&buf, 80);
+		let mut offset = HEADER_SIZE;
+		let (format, array_size, cube) = match flags & FOUR_CC != 0 {
+			true if buf[84..88] == *b"DX10" => {
+				crate::read_exact(data, &mut buf[HEADER_SIZE as usize..], offset)?;
+				offset += DX10_SIZE;
+				let Some(format) = DdsFormat::dxgi(u32_at(&buf, 128))
+				else {
+					return Err(field(128, "dxgi format"));
+				};
+				// 1d and 2d textures
+				if !matches!(u32_at(&buf, 132), 2 | 3) {
+					return Err(field(132, "resource dimension"));
+				}
+				let cube = u32_at(&buf, 136) & 0x4 != 0;
+				let array_size = u32_at(&buf, 140);
+				if array_size == 0 {
+					return Err(field(140, "array size"));
+				}
+				(format, array_size.saturating_mul(if cube { 6 } else { 1 }), cube)
+			}
+			true => match &buf[84..88] {
+				b"DXT1" => (DdsFormat::Bc1, 1, false),
+				b"DXT5" => (DdsFormat::Bc3, 1, false),
+				_ => return Err(field(84, "four cc")),
+			},
+			false => {
+				let masks = [92, 96, 100, 104].map(|at| u32_at(&buf, at));
+				let alpha = flags & ALPHA_PIXELS != 0 && masks[3] != 0;
+				let format = match (flags & RGB != 0, u32_at(&buf, 88), masks, alpha) {
+					(true, 32, [0xff, 0xff00, 0xff0000, _], true) => DdsFormat::R8G8B8A8,
+					(true, 32, [0xff, 0xff00, 0xff0000, _], false) => DdsFormat::R8G8B8X8,
+					(true, 32, [0xff0000, 0xff00, 0xff, _], true) => DdsFormat::B8G8R8A8,
+					(true, 32, [0xff0000, 0xff00, 0xff, _], false) => DdsFormat::B8G8R8X8,
+					_ => return Err(field(80, "pixel format")),
+				};
+				let cube = caps2 & CUBEMAP != 0;
+				(format, if cube { 6 } else { 1 }, cube)
+			}
+		};
+		let header = DdsHeader { width, height, format, mip_levels, array_size, cube };
+		if level >= mip_levels {
+			return Err(Error::InvalidInput { reason: "there's no such mip level" });
+		}
+
+		// the levels before, a piece at a time. past the first 32 they're all
+		// 1 by 1
+		let small = level.saturating_sub(32) as u64 * header.level_len(32);
+		let mut left = (0..level.min(32)).fold(small, |n, level| n.saturating_add(header.level_len(level)));
+		while left > 0 {
+			let n = left.min(buf.len() as u64) as usize;
+			crate::read_exact(data, &mut buf[..n], offset)?;
+			offset = offset.saturating_add(n as u64);
+			left -= n as u64;
+		}
+
+		let (width, height) = header.level_size(level);
+		Ok(Self {
+			header,
+			data,
+			offset,
+			start: offset,
+			width,
+			height,
+			row: vec![],
+			block_rows: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &DdsHeader {
+		&self.header
+	}
+
+	/// the size of the mip level being decoded.
+	pub fn size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+
+	/// reads the next row into `row`, a piece at a time, so memory is only
+	/// taken up once the data shows up. a row of blocks is decoded into
+	/// `block_rows`.
+	fn read_row(&mut self) -> Result<(), Error> {
+		let format = self.header.format;
+		let mut left = format.row_len(self.width);
+		self.row.clear();
+		while left > 0 {
+			let n = left.min(1 << 16) as usize;
+			let start = self.row.len();
+			self.row.try_reserve(n).map_err(|_| oom())?;
+			self.row.resize(start + n, 0);
+			crate::read_exact(self.data, &mut self.row[start..], self.offset)?;
+			self.offset = self.offset.saturating_add(n as u64);
+			left -= n as u64;
+		}
+
+		let size = match format {
+			DdsFormat::Bc1 => 8,
+			DdsFormat::Bc3 => 16,
+			_ => return Ok(()),
+		};
+		let width = self.width as usize;
+		let len = width * 4;
+		self.block_rows.clear();
+		self.block_rows.try_reserve_exact(len).map_err(|_| oom())?;
+		self.block_rows.resize(len, (0, 0, 0, 0));
+		for (i, block) in self.row.chunks_exact(size).enumerate() {
+			let pixels = match format {
+				DdsFormat::Bc1 => bc::bc1(block),
+				_ => bc::bc3(block),
+			};
+			for (j, px) in pixels.into_iter().enumerate() {
+				let x = i * 4 + j % 4;
+				if x < width {
+					self.block_rows[j / 4 * width + x] = px;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// skips any pixels that are left, and returns where the level ends.
+	/// the levels and surfaces after it aren't read.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		// only what's left of the current row, or row of blocks
+		let format = self.header.format;
+		let read = match self.x {
+			0 => self.y.div_ceil(format.rows()),
+			_ => self.y / format.rows() + 1,
+		};
+		let rows = self.height.div_ceil(format.rows()) - read.min(self.height.div_ceil(format.rows()));
+		let mut left = (rows as u64).saturating_mul(format.row_len(self.width));
+		let mut buf = [0; 512];
+		while left > 0 {
+			let n = left.min(buf.len() as u64) as usize;
+			crate::read_exact(self.data, &mut buf[..n], self.offset)?;
+			self.offset = self.offset.saturating_add(n as u64);
+			left -= n as u64;
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<R: std::io::Read + std::io::Seek> DdsPixels<'_, R> {
+	/// goes to the start of row `y` of the level, forward or back, reading
+	/// only the row of blocks it's in, if it's partway through one. `y` can
+	/// be the level's height, past which is `OutOfBounds`.
+	pub fn seek_row(&mut self, y: u32) -> Result<(), Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		if y > self.height {
+			return Err(Error::OutOfBounds { x: 0, y, width: self.width, height: 1 });
+		}
+		let format = self.header.format;
+		// the end is past the last row of blocks, even one that's cut off
+		let blocks = if y == self.height { y.div_ceil(format.rows()) } else { y / format.rows() };
+		let at = self.start.saturating_add((blocks as u64).saturating_mul(format.row_len(self.width)));
+		crate::seek::seek(self.data, self.offset, at).inspect_err(|_| self.failed = true)?;
+		self.offset = at;
+		self.x = 0;
+		self.y = y;
+		if y < self.height && !y.is_multiple_of(format.rows()) {
+			self.read_row().inspect_err(|_| self.failed = true)?;
+		}
+		Ok(())
+	}
+}
+
+impl<R: std::io::Read> Iterator for DdsPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.height {
+			return None;
+		}
+		if self.x == 0
+			&& self.y.is_multiple_of(self.header.format.rows())
+			&& let Err(e) = self.read_row()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let x = self.x as usize;
+		let i = x * 4;
+		let px = match self.header.format {
+			DdsFormat::Bc1 | DdsFormat::Bc3 => self.block_rows[(self.y % 4) as usize * self.width as usize + x],
+			format => {
+				let [a, b, c, d] = [self.row[i], self.row[i + 1], self.row[i + 2], self.row[i + 3]];
+				match format {
+					DdsFormat::R8G8B8A8 => (a, b, c, d),
+					DdsFormat::R8G8B8X8 => (a, b, c, 255),
+					DdsFormat::B8G8R8A8 => (c, b, a, d),
+					_ => (c, b, a, 255),
+				}
+			}
+		};
+
+		self.x += 1;
+		if self.x == self.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, dds};
+
+	const IMAGE_RGBA: &[u8] = include_bytes!("../test/rgba.dds");
+	const IMAGE_DX10: &[u8] = include_bytes!("../test/dx10.dds");
+
+	fn color(x: u32, y: u32, level: u32) -> crate::Rgba {
+		((x * 19 + level * 50) as u8, (y * 36) as u8, ((x + y) * 10) as u8, (x * y * 3 + 7) as u8)
+	}
+
+	#[test]
+	fn decode() {
+		// each level of a texture with a legacy header, and one with a dx10
+		// header, two surfaces of which only the first is read
+		for (image, format, mip_levels, array_size, ends) in [
+			(IMAGE_RGBA, dds::DdsFormat::R8G8B8A8, 3, 1, &[492, 564, 576][..]),
+			(IMAGE_DX10, dds::DdsFormat::B8G8R8A8, 4, 2, &[512, 584, 596, 600]),
+		] {
+			for (level, &end) in ends.iter().enumerate() {
+				let level = level as u32;
+				let mut data = image;
+				let mut pixels = dds::DdsPixels::level(&mut data, level).expect("error?");
+				let header = pixels.header();
+				assert_eq!((header.width, header.height, header.format), (13, 7, format));
+				assert_eq!((header.mip_levels, header.array_size, header.cube), (mip_levels, array_size, false));
+				let (width, height) = pixels.size();
+				assert_eq!((width, height), header.level_size(level));
+				let mut n = 0;
+				for (i, px) in (&mut pixels).enumerate() {
+					let (x, y) = (i as u32 % width, i as u32 / width);
+					assert_eq!(px.expect("error?"), color(x, y, level), "{level}: {x}, {y}");
+					n += 1;
+				}
+				assert_eq!(n, width * height);
+				assert_eq!(pixels.finish().expect("error?"), end);
+
+				let mut data = image;
+				let mut pixels = dds::DdsPixels::level(&mut data, level).expect("error?");
+				_ = pixels.next();
+				assert_eq!(pixels.finish().expect("error?"), end);
+			}
+			let mut data = image;
+			let result = dds::DdsPixels::level(&mut data, mip_levels);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+		}
+
+		// the unused byte is ignored
+		let mut data = &include_bytes!("../test/bgrx.dds")[..];
+		let pixels = dds::DdsPixels::new(&mut data).expect("error?");
+		assert_eq!(pixels.header().format, dds::DdsFormat::B8G8R8X8);
+		assert!(pixels.map(|px| px.expect("error?")).eq((0..13 * 7).map(|i| {
+			let px = color(i % 13, i / 13, 0);
+			(px.0, px.1, px.2, 255)
+		})));
+
+		// the first face of a cube map
+		let mut data = &include_bytes!("../test/cube.dds")[..];
+		let pixels = dds::DdsPixels::new(&mut data).expect("error?");
+		let header = pixels.header().clone();
+		assert_eq!((header.width, header.height, header.array_size, header.cube), (4, 4, 6, true));
+		assert!(pixels.map(|px| px.expect("error?")).eq((0..16).map(|i| color(i % 4, i / 4, 0))));
+
+		// levels stop at 1 by 1
+		let header = dds::DdsHeader { width: 1 << 10, height: 4, ..header };
+		assert_eq!(header.level_size(2), (1 << 8, 1));
+		assert_eq!(header.level_size(11), (1, 1));
+		assert_eq!(header.level_size(40), (1, 1));
+	}
+
+	#[test]
+	fn decode_bc() {
+		// a solid color a block, from where it is, with the top left pixel
+		// of each bc1 block transparent, and each bc3 block's alpha from
+		// where it is too
+		fn solid(x: u32, y: u32, level: u32) -> crate::Rgba {
+			let (r, g, b) = (x / 4 * 3, y / 4 * 9, level * 7);
+			((r << 3 | r >> 2) as u8, (g << 2 | g >> 4) as u8, (b << 3 | b >> 2) as u8, 255)
+		}
+		let bc1 = include_bytes!("../test/bc1.dds");
+		for (level, end) in [(0, 192), (1, 208)] {
+			let mut data = &bc1[..];
+			let mut pixels = dds::DdsPixels::level(&mut data, level).expect("error?");
+			assert_eq!(pixels.header().format, dds::DdsFormat::Bc1);
+			let (width, height) = pixels.size();
+			let mut n = 0;
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % width, i as u32 / width);
+				let expected = match (x % 4, y % 4) {
+					(0, 0) => (0, 0, 0, 0),
+					_ => solid(x, y, level),
+				};
+				assert_eq!(px.expect("error?"), expected, "{level}: {x}, {y}");
+				n += 1;
+			}
+			assert_eq!(n, width * height);
+			assert_eq!(pixels.finish().expect("error?"), end);
+
+			// where it ends from partway through a row of blocks
+			let mut data = &bc1[..];
+			let mut pixels = dds::DdsPixels::level(&mut data, level).expect("error?");
+			_ = pixels.nth(width as usize + 1);
+			assert_eq!(pixels.finish().expect("error?"), end);
+		}
+
+		let mut data = &include_bytes!("../test/bc3.dds")[..];
+		let mut pixels = dds::DdsPixels::new(&mut data).expect("error?");
+		assert_eq!((pixels.header().width, pixels.header().height, pixels.header().format), (9, 5, dds::DdsFormat::Bc3));
+		for (i, px) in (&mut pixels).enumerate() {
+			let (x, y) = (i as u32 % 9, i as u32 / 9);
+			let alpha = (x / 4 * 40 + y / 4 * 20) as u8;
+			let expected = solid(x, y, 0);
+			assert_eq!(px.expect("error?"), (expected.0, expected.1, expected.2, alpha), "{x}, {y}");
+		}
+		assert_eq!(pixels.finish().expect("error?"), 244);
+	}
+
+	#[test]
+	fn seek() {
+		// every row, and back to each from the last, uncompressed at a level
+		// past the first and in blocks
+		for (image, level, end) in [(IMAGE_DX10, 1, 584), (&include_bytes!("../test/bc3.dds")[..], 0, 244)] {
+			let mut data = image;
+			let pixels = dds::DdsPixels::level(&mut data, level).expect("error?");
+			let (width, height) = pixels.size();
+			let all = pixels.map(|px| px.expect("error?")).collect::<Vec<_>>();
+			for y in 0..=height {
+				let mut data = std::io::Cursor::new(image);
+				let mut pixels = dds::DdsPixels::level(&mut data, level).expect("error?");
+				pixels.seek_row(height).expect("error?");
+				pixels.seek_row(y).expect("error?");
+				let rest = (&mut pixels).map(|px| px.expect("error?")).collect::<Vec<_>>();
+				assert_eq!(rest, all[(y * width) as usize..], "{level}: {y}");
+				pixels.seek_row(y).expect("error?");
+				assert_eq!(pixels.finish().expect("error?"), end, "{level}: {y}");
+			}
+
+			let mut data = std::io::Cursor::new(image);
+			let mut pixels = dds::DdsPixels::level(&mut data, level).expect("error?");
+			let result = pixels.seek_row(height + 1);
+			assert!(matches!(result, Err(Error::OutOfBounds { x: 0, width: w, height: 1, .. }) if w == width), "{result:?}");
+		}
+	}
+
+	#[test]
+	fn blocks() {
+		// red and blue, with two colors between them, and pixel i using
+		// index i % 4
+		let mut block = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0xe4, 0xe4, 0xe4];
+		let palette = [(255, 0, 0, 255), (0, 0, 255, 255), (170, 0, 85, 255), (85, 0, 170, 255)];
+		assert_eq!(super::bc::bc1(&block), core::array::from_fn(|i| palette[i % 4]));
+		// in the other order, one color between them and transparent black
+		block[..4].copy_from_slice(&[0x1f, 0x00, 0x00, 0xf8]);
+		let palette = [(0, 0, 255, 255), (255, 0, 0, 255), (127, 0, 127, 255), (0, 0, 0, 0)];
+		assert_eq!(super::bc::bc1(&block), core::array::from_fn(|i| palette[i % 4]));
+
+		// eight alphas, or six and 0 and 255, with pixel i using index i % 8.
+		// the colors always have four, whichever order they're in
+		let indices: u64 = (0..16).map(|i| (i % 8) << (i * 3)).sum();
+		let mut block = [0; 16];
+		block[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+		block[8..].copy_from_slice(&[0x1f, 0x00, 0x00, 0xf8, 0xe4, 0xe4, 0xe4, 0xe4]);
+		let colors = [(0, 0, 255), (255, 0, 0), (85, 0, 170), (170, 0, 85)];
+		for (a0, a1, alphas) in [(255, 0, [255, 0, 218, 182, 145, 109, 72, 36]), (0, 255, [0, 255, 51, 102, 153, 204, 0, 255])] {
+			block[..2].copy_from_slice(&[a0, a1]);
+			let expected: [crate::Rgba; 16] = core::array::from_fn(|i| {
+				let (r, g, b) = colors[i % 4];
+				(r, g, b, alphas[i % 8])
+			});
+			assert_eq!(super::bc::bc3(&block), expected, "{a0}, {a1}");
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = dds::DdsPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".dds")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("bad_size", Error::InvalidHeader { offset: 4, .. });
+		case!("zero_height", Error::InvalidHeader { offset: 12, .. });
+		case!("zero_width", Error::InvalidHeader { offset: 16, .. });
+		case!("volume", Error::InvalidHeader { offset: 24, .. });
+		case!("bad_pixel_format", Error::InvalidHeader { offset: 80, .. });
+		case!("bad_four_cc", Error::InvalidHeader { offset: 84, .. });
+		case!("bad_dxgi", Error::InvalidHeader { offset: 128, .. });
+		case!("bad_dimension", Error::InvalidHeader { offset: 132, .. });
+		case!("zero_array", Error::InvalidHeader { offset: 140, .. });
+		case!("truncated", Error::UnexpectedEof { offset: 300 });
+		case!("truncated_bc", Error::UnexpectedEof { offset: 168 });
+
+		assert!(decode(IMAGE_RGBA).is_ok());
+		assert!(decode(IMAGE_DX10).is_ok());
+	}
+}
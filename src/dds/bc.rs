@@ -0,0 +1,68 @@
+/*!
+bc1 and bc3 blocks, each 4 by 4 pixels, stored row by row.
+
+a bc1 block is two 5:6:5 colors and a 2-bit index for each pixel, from the
+low bits, into a palette of the two and two more between them. if the first
+color isn't greater than the second, there's only one between them, and the
+last index is transparent black. bc3 blocks start with 8 bytes of alpha, two
+alphas and a 3-bit index for each pixel into six or eight of them, then a
+bc1 block that always has four colors.
+*/
+
+/// a 5:6:5 color, scaled up to 8 bits.
+fn color(x: u16) -> [u8; 3] {
+	let (r, g, b) = ((x >> 11) as u8, (x >> 5 & 0x3f) as u8, (x & 0x1f) as u8);
+	[r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// `(a * x + b * y) / (x + y)`, for each channel.
+fn mix(a: [u8; 3], b: [u8; 3], x: u16, y: u16) -> [u8; 3] {
+	[0, 1, 2].map(|i| ((a[i] as u16 * x + b[i] as u16 * y) / (x + y)) as u8)
+}
+
+/// the colors of the 8 bytes of a bc1 block, which can be transparent unless
+/// the block is part of a bc3 block.
+fn colors(block: &[u8], four: bool) -> [crate::Rgba; 16] {
+	let c0 = u16::from_le_bytes([block[0], block[1]]);
+	let c1 = u16::from_le_bytes([block[2], block[3]]);
+	let (a, b) = (color(c0), color(c1));
+	let opaque = |[r, g, b]: [u8; 3]| (r, g, b, 255);
+	let (c, d) = match four || c0 > c1 {
+		true => (opaque(mix(a, b, 2, 1)), opaque(mix(a, b, 1, 2))),
+		false => (opaque(mix(a, b, 1, 1)), (0, 0, 0, 0)),
+	};
+	let palette = [opaque(a), opaque(b), c, d];
+
+	let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+	core::array::from_fn(|i| palette[(indices >> (i * 2) & 3) as usize])
+}
+
+/// the pixels of an 8-byte block.
+pub(super) fn bc1(block: &[u8]) -> [crate::Rgba; 16] {
+	colors(block, false)
+}
+
+/// the pixels of a 16-byte block.
+pub(super) fn bc3(block: &[u8]) -> [crate::Rgba; 16] {
+	let (a0, a1) = (block[0] as u16, block[1] as u16);
+	let alpha = |i: u64| -> u8 {
+		let (x, y) = match (a0 > a1, i) {
+			(_, 0) => return a0 as u8,
+			(_, 1) => return a1 as u8,
+			(true, i) => (8 - i as u16, i as u16 - 1),
+			(false, 6) => return 0,
+			(false, 7) => return 255,
+			(false, i) => (6 - i as u16, i as u16 - 1),
+		};
+		((a0 * x + a1 * y) / (x + y)) as u8
+	};
+	let mut bits = [0; 8];
+	bits[..6].copy_from_slice(&block[2..8]);
+	let indices = u64::from_le_bytes(bits);
+
+	let mut colors = colors(&block[8..], true);
+	for (i, px) in colors.iter_mut().enumerate() {
+		px.3 = alpha(indices >> (i * 3) & 7);
+	}
+	colors
+}
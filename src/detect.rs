@@ -0,0 +1,430 @@
+/*!
+telling which of the crate's formats a stream is in, from its first bytes.
+
+only formats that start with a magic can be told apart, so tga and raw are
+never detected, and jpeg only encodes. formats from other crates are found
+through `registry`, once none of the crate's own match, so registering one
+of their magics doesn't take it over here.
+
+pcx and xbm have short, weak magics, so other data starting the same way
+comes out as them, and fails to decode.
+*/
+
+use crate::{Error, Image, registry};
+
+/// the most bytes any of the crate's formats needs to be told apart.
+pub const LEN: usize = 12;
+
+/// a format that's compiled in or registered, along with how to decode it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Detected {
+	#[cfg(feature = "qoi")]
+	Qoi,
+	#[cfg(feature = "png")]
+	Png,
+	#[cfg(feature = "bmp")]
+	Bmp,
+	#[cfg(feature = "pnm")]
+	Pnm,
+	#[cfg(feature = "pnm")]
+	Pfm,
+	#[cfg(feature = "gif")]
+	Gif,
+	#[cfg(feature = "tiff")]
+	Tiff,
+	#[cfg(feature = "hdr")]
+	Hdr,
+	#[cfg(feature = "exr")]
+	Exr,
+	#[cfg(feature = "pcx")]
+	Pcx,
+	#[cfg(feature = "ico")]
+	Ico,
+	#[cfg(feature = "dds")]
+	Dds,
+	#[cfg(feature = "ktx2")]
+	Ktx2,
+	#[cfg(feature = "xbm")]
+	Xbm,
+	#[cfg(feature = "xpm")]
+	Xpm,
+	#[cfg(feature = "ras")]
+	Ras,
+	#[cfg(feature = "sgi")]
+	Sgi,
+	#[cfg(feature = "qoy")]
+	Qoy,
+	#[cfg(feature = "qoir")]
+	Qoir,
+	#[cfg(feature = "ilbm")]
+	Ilbm,
+	#[cfg(feature = "psd")]
+	Psd,
+	#[cfg(feature = "fits")]
+	Fits,
+	/// a format from `registry`.
+	Registered(registry::Id),
+}
+
+impl Detected {
+	/// every format that's compiled in, which leaves out registered ones.
+	pub const ALL: &[Self] = &[
+		#[cfg(feature = "qoi")]
+		Self::Qoi,
+		#[cfg(feature = "png")]
+		Self::Png,
+		#[cfg(feature = "bmp")]
+		Self::Bmp,
+		#[cfg(feature = "pnm")]
+		Self::Pnm,
+		#[cfg(feature = "pnm")]
+		Self::Pfm,
+		#[cfg(feature = "gif")]
+		Self::Gif,
+		#[cfg(feature = "tiff")]
+		Self::Tiff,
+		#[cfg(feature = "hdr")]
+		Self::Hdr,
+		#[cfg(feature = "exr")]
+		Self::Exr,
+		#[cfg(feature = "pcx")]
+		Self::Pcx,
+		#[cfg(feature = "ico")]
+		Self::Ico,
+		#[cfg(feature = "dds")]
+		Self::Dds,
+		#[cfg(feature = "ktx2")]
+		Self::Ktx2,
+		#[cfg(feature = "xbm")]
+		Self::Xbm,
+		#[cfg(feature = "xpm")]
+		Self::Xpm,
+		#[cfg(feature = "ras")]
+		Self::Ras,
+		#[cfg(feature = "sgi")]
+		Self::Sgi,
+		#[cfg(feature = "qoy")]
+		Self::Qoy,
+		#[cfg(feature = "qoir")]
+		Self::Qoir,
+		#[cfg(feature = "ilbm")]
+		Self::Ilbm,
+		#[cfg(feature = "psd")]
+		Self::Psd,
+		#[cfg(feature = "fits")]
+		Self::Fits,
+	];
+
+	/// the format whose magic `start` begins with. `LEN` bytes are enough
+	/// for any of the crate's, though registered magics can be longer.
+	pub fn from_start(start: &[u8]) -> Option<Self> {
+		let found = Self::ALL.iter().copied().find(|format| format.matches(start));
+		found.or_else(|| registry::position(start).map(Self::Registered))
+	}
+
+	// with no formats compiled in, there's nothing to look at
+	#[allow(unused_variables)]
+	fn matches(self, start: &[u8]) -> bool {
+		match self {
+			#[cfg(feature = "qoi")]
+			Self::Qoi => start.starts_with(b"qoif"),
+			#[cfg(feature = "png")]
+			Self::Png => start.starts_with(b"\x89PNG\r\n\x1a\n"),
+			#[cfg(feature = "bmp")]
+			Self::Bmp => start.starts_with(b"BM"),
+			#[cfg(feature = "pnm")]
+			Self::Pnm => matches!(start, [b'P', b'1'..=b'7', ..]),
+			#[cfg(feature = "pnm")]
+			Self::Pfm => matches!(start, [b'P', b'F' | b'f', ..]),
+			#[cfg(feature = "gif")]
+			Self::Gif => start.starts_with(b"GIF87a") || start.starts_with(b"GIF89a"),
+			#[cfg(feature = "tiff")]
+			Self::Tiff => start.starts_with(b"II*\0") || start.starts_with(b"MM\0*"),
+			#[cfg(feature = "hdr")]
+			Self::Hdr => start.starts_with(b"#?"),
+			#[cfg(feature = "exr")]
+			Self::Exr => start.starts_with(&[0x76, 0x2f, 0x31, 0x01]),
+			// the version and whether it's run-length encoded
+			#[cfg(feature = "pcx")]
+			Self::Pcx => matches!(start, [0x0a, 0 | 2..=5, 0 | 1, ..]),
+			// an icon or a cursor
+			#[cfg(feature = "ico")]
+			Self::Ico => matches!(start, [0, 0, 1 | 2, 0, ..]),
+			#[cfg(feature = "dds")]
+			Self::Dds => start.starts_with(b"DDS "),
+			#[cfg(feature = "ktx2")]
+			Self::Ktx2 => start.starts_with(b"\xabKTX 20\xbb\r\n\x1a\n"),
+			#[cfg(feature = "xbm")]
+			Self::Xbm => start.starts_with(b"#define"),
+			#[cfg(feature = "xpm")]
+			Self::Xpm => start.starts_with(b"/* XPM */"),
+			#[cfg(feature = "ras")]
+			Self::Ras => start.starts_with(&[0x59, 0xa6, 0x6a, 0x95]),
+			#[cfg(feature = "sgi")]
+			Self::Sgi => start.starts_with(&[0x01, 0xda]),
+			#[cfg(feature = "qoy")]
+			Self::Qoy => start.starts_with(b"qoyf"),
+			#[cfg(feature = "qoir")]
+			Self::Qoir => start.starts_with(b"QOIR"),
+			// the length comes between the two
+			#[cfg(feature = "ilbm")]
+			Self::Ilbm => start.starts_with(b"FORM") && start.get(8..12) == Some(b"ILBM"),
+			#[cfg(feature = "psd")]
+			Self::Psd => start.starts_with(b"8BPS"),
+			#[cfg(feature = "fits")]
+			Self::Fits => start.starts_with(b"SIMPLE  = "),
+			Self::Registered(id) => registry::position(start) == Some(id),
+		}
+	}
+
+	/// the name of the module the format lives in, or `"pfm"`, or the name a
+	/// registered one was registered as.
+	pub fn name(self) -> &'static str {
+		match self {
+			#[cfg(feature = "qoi")]
+			Self::Qoi => "qoi",
+			#[cfg(feature = "png")]
+			Self::Png => "png",
+			#[cfg(feature = "bmp")]
+			Self::Bmp => "bmp",
+			#[cfg(feature = "pnm")]
+			Self::Pnm => "pnm",
+			#[cfg(feature = "pnm")]
+			Self::Pfm => "pfm",
+			#[cfg(feature = "gif")]
+			Self::Gif => "gif",
+			#[cfg(feature = "tiff")]
+			Self::Tiff => "tiff",
+			#[cfg(feature = "hdr")]
+			Self::Hdr => "hdr",
+			#[cfg(feature = "exr")]
+			Self::Exr => "exr",
+			#[cfg(feature = "pcx")]
+			Self::Pcx => "pcx",
+			#[cfg(feature = "ico")]
+			Self::Ico => "ico",
+			#[cfg(feature = "dds")]
+			Self::Dds => "dds",
+			#[cfg(feature = "ktx2")]
+			Self::Ktx2 => "ktx2",
+			#[cfg(feature = "xbm")]
+			Self::Xbm => "xbm",
+			#[cfg(feature = "xpm")]
+			Self::Xpm => "xpm",
+			#[cfg(feature = "ras")]
+			Self::Ras => "ras",
+			#[cfg(feature = "sgi")]
+			Self::Sgi => "sgi",
+			#[cfg(feature = "qoy")]
+			Self::Qoy => "qoy",
+			#[cfg(feature = "qoir")]
+			Self::Qoir => "qoir",
+			#[cfg(feature = "ilbm")]
+			Self::Ilbm => "ilbm",
+			#[cfg(feature = "psd")]
+			Self::Psd => "psd",
+			#[cfg(feature = "fits")]
+			Self::Fits => "fits",
+			Self::Registered(id) => registry::get(id).map_or("", |(name, _)| name),
+		}
+	}
+
+	/// decodes a whole image, with each format's defaults. floating point
+	/// samples are mapped with the srgb curve, icons give their largest
+	/// image, and animations their first frame.
+	#[allow(unused_variables)]
+	pub fn decode(self, data: &mut impl std::io::Read) -> Result<Image, Error> {
+		#[allow(unused_imports)]
+		use crate::{Dimensions, Format};
+
+		/// collects a `Format`'s pixels.
+		#[allow(unused_macros)]
+		macro_rules! checked {
+			($format:expr) => {{
+				let (header, pixels) = $format.decode_checked(data)?;
+				Image::collect_checked(header.width(), header.height(), pixels)
+			}};
+		}
+		/// collects the pixels of a decoder that only has an iterator.
+		#[allow(unused_macros)]
+		macro_rules! pixels {
+			($pixels:path) => {{
+				let pixels = <$pixels>::new(data)?;
+				let (width, height) = (pixels.header().width(), pixels.header().height());
+				Image::collect_checked(width, height, pixels)
+			}};
+		}
+
+		match self {
+			#[cfg(feature = "qoi")]
+			Self::Qoi => checked!(crate::qoi::Qoi),
+			#[cfg(feature = "png")]
+			Self::Png => checked!(crate::png::Png),
+			#[cfg(feature = "bmp")]
+			Self::Bmp => checked!(crate::bmp::Bmp),
+			#[cfg(feature = "pnm")]
+			Self::Pnm => checked!(crate::pnm::Pnm),
+			#[cfg(feature = "pnm")]
+			Self::Pfm => checked!(crate::pnm::Pfm::default()),
+			#[cfg(feature = "gif")]
+			Self::Gif => checked!(crate::gif::Gif),
+			#[cfg(feature = "tiff")]
+			Self::Tiff => checked!(crate::tiff::Tiff),
+			#[cfg(feature = "hdr")]
+			Self::Hdr => checked!(crate::hdr::Hdr::default()),
+			#[cfg(feature = "exr")]
+			Self::Exr => checked!(crate::exr::Exr::default()),
+			#[cfg(feature = "pcx")]
+			Self::Pcx => checked!(crate::pcx::Pcx),
+			#[cfg(feature = "ico")]
+			Self::Ico => {
+				let images = crate::ico::IcoImages::new(data)?;
+				let largest = images.header().entries.iter()
+					.enumerate()
+					.max_by_key(|(_, entry)| entry.width as u64 * entry.height as u64)
+					.map_or(0, |(i, _)| i);
+				Ok(images.decode(largest)?.image)
+			}
+			#[cfg(feature = "dds")]
+			Self::Dds => pixels!(crate::dds::DdsPixels<_>),
+			#[cfg(feature = "ktx2")]
+			Self::Ktx2 => checked!(crate::ktx2::Ktx2),
+			#[cfg(feature = "xbm")]
+			Self::Xbm => checked!(crate::xbm::Xbm),
+			#[cfg(feature = "xpm")]
+			Self::Xpm => checked!(crate::xpm::Xpm),
+			#[cfg(feature = "ras")]
+			Self::Ras => pixels!(crate::ras::RasPixels<_>),
+			#[cfg(feature = "sgi")]
+			Self::Sgi => checked!(crate::sgi::Sgi),
+			#[cfg(feature = "qoy")]
+			Self::Qoy => checked!(crate::qoy::Qoy),
+			#[cfg(feature = "qoir")]
+			Self::Qoir => checked!(crate::qoir::Qoir),
+			#[cfg(feature = "ilbm")]
+			Self::Ilbm => pixels!(crate::ilbm::IlbmPixels<_>),
+			#[cfg(feature = "psd")]
+			Self::Psd => pixels!(crate::psd::PsdPixels<_>),
+			#[cfg(feature = "fits")]
+			Self::Fits => {
+				let (header, pixels) = crate::fits::Fits::default().decode(data)?;
+				Image::collect(header.width(), header.height(), pixels)
+			}
+			Self::Registered(id) => {
+				let (_, format) = registry::get(id).ok_or(Error::InvalidInput { reason: "no such registered format" })?;
+				registry::collect(&*format, data)
+			}
+		}
+	}
+}
+
+/// bytes read while detecting, put back in front of the rest.
+pub type Sniffed<R> = std::io::Chain<std::io::Cursor<Vec<u8>>, R>;
+
+/// up to `len` bytes from the start of `data`, fewer if it ends first.
+pub(crate) fn start(data: &mut impl std::io::Read, len: usize) -> Result<Vec<u8>, Error> {
+	let mut start = vec![0; len];
+	let mut n = 0;
+	while n < len {
+		match data.read(&mut start[n..]) {
+			Ok(0) => break,
+			Ok(x) => n += x.min(len - n),
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e.into()),
+		}
+	}
+	start.truncate(n);
+	Ok(start)
+}
+
+/// reads enough of `data` to tell which format it's in, if any. `data` is
+/// handed back with what was read put back in front, so the format can
+/// decode it from the start.
+pub fn detect<R: std::io::Read>(mut data: R) -> Result<(Option<Detected>, Sniffed<R>), Error> {
+	let start = start(&mut data, LEN.max(registry::longest()))?;
+	let format = Detected::from_start(&start);
+	Ok((format, std::io::Read::chain(std::io::Cursor::new(start), data)))
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::detect::{self, Detected};
+
+	#[test]
+	fn files() {
+		#[allow(unused_mut)]
+		let mut files: Vec<(&[u8], Detected)> = vec![];
+		macro_rules! file {
+			($feature:literal, $name:literal, $format:ident) => {
+				#[cfg(feature = $feature)]
+				files.push((include_bytes!(concat!("../test/", $name)), Detected::$format));
+			};
+		}
+		file!("qoi", "small.qoi", Qoi);
+		file!("png", "small.png", Png);
+		file!("bmp", "small.bmp", Bmp);
+		file!("pnm", "small.pam", Pnm);
+		file!("pnm", "mask_plain.pbm", Pnm);
+		file!("pnm", "ramp.pfm", Pfm);
+		file!("pnm", "ramp_gray.pfm", Pfm);
+		file!("gif", "still87.gif", Gif);
+		file!("gif", "animated.gif", Gif);
+		file!("tiff", "gradient.tiff", Tiff);
+		file!("tiff", "gradient_be.tiff", Tiff);
+		file!("hdr", "gradient.hdr", Hdr);
+		file!("exr", "gradient.exr", Exr);
+		file!("pcx", "rgb.pcx", Pcx);
+		file!("ico", "icon.ico", Ico);
+		file!("ico", "cursor.cur", Ico);
+		file!("dds", "rgba.dds", Dds);
+		file!("ktx2", "cube.ktx2", Ktx2);
+		file!("xbm", "cross.xbm", Xbm);
+		file!("xpm", "icon.xpm", Xpm);
+		file!("ras", "gray.ras", Ras);
+		file!("sgi", "rgb.sgi", Sgi);
+		file!("qoy", "full.qoy", Qoy);
+		file!("qoir", "gradient.qoir", Qoir);
+		file!("ilbm", "rgb.iff", Ilbm);
+		file!("psd", "rgb.psd", Psd);
+		file!("fits", "u8.fits", Fits);
+
+		for (file, expected) in files {
+			let (format, mut data) = detect::detect(file).expect("error?");
+			assert_eq!(format, Some(expected));
+			let image = expected.decode(&mut data).unwrap_or_else(|e| panic!("{}: {e:?}", expected.name()));
+			assert!(image.width() > 0 && image.height() > 0, "{}", expected.name());
+		}
+	}
+
+	#[test]
+	fn unknown() {
+		let (format, mut data) = detect::detect(&b"nothing"[..]).expect("error?");
+		assert_eq!(format, None);
+		let mut rest = vec![];
+		std::io::Read::read_to_end(&mut data, &mut rest).expect("error?");
+		assert_eq!(rest, b"nothing");
+		assert_eq!(detect::detect(&b""[..]).expect("error?").0, None);
+	}
+
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn cut_short() {
+		use crate::Error;
+
+		let file = include_bytes!("../test/small.qoi");
+		let (format, mut data) = detect::detect(&file[..20]).expect("error?");
+		assert_eq!(format, Some(Detected::Qoi));
+		let result = Detected::Qoi.decode(&mut data);
+		assert!(matches!(result, Err(Error::UnexpectedEof { offset: 20 })), "{result:?}");
+
+		// the start comes in pieces, and is put back whole
+		let (format, mut data) = detect::detect(std::io::Read::chain(&file[..1], &file[1..])).expect("error?");
+		assert_eq!(format, Some(Detected::Qoi));
+		let image = Detected::Qoi.decode(&mut data).expect("error?");
+		let mut reader = &file[..];
+		let (_, pixels) = crate::Format::decode(crate::qoi::Qoi, &mut reader).expect("error?");
+		assert_eq!(image.pixels(), pixels.collect::<Vec<_>>());
+	}
+}
@@ -0,0 +1,227 @@
+/*!
+formats behind `dyn`, for picking one at runtime.
+
+`Format` reads from generic readers and hands back `impl Iterator`, so it
+can't be made into a trait object. `DynFormat` can: it reads from a
+`dyn Read` and hands back a boxed iterator, and its headers are all one
+type, `AnyHeader`. every `Format` in the crate is a `DynFormat`, and so is
+a format from another crate once its header converts to and from
+`AnyHeader`, or once it's wrapped in `registry::Registered`.
+*/
+
+use std::sync::Arc;
+
+use crate::{Dimensions, Error, Format, Rgba};
+
+/// a `Format` that can be kept behind `dyn`.
+pub trait DynFormat: Send + Sync {
+	/// like `Format::decode_checked`. the reader is behind two references so
+	/// the pixels can borrow the outer one.
+	#[allow(clippy::type_complexity)]
+	fn decode<'a>(&self, data: &'a mut &mut dyn std::io::Read) -> Result<(AnyHeader, Box<dyn Iterator<Item = Result<Rgba, Error>> + 'a>), Error>;
+
+	/// like `Format::decode_header`.
+	fn decode_header(&self, data: &mut dyn std::io::Read) -> Result<AnyHeader, Error>;
+
+	/// like `Format::encode`. a header for another format is `InvalidInput`.
+	fn encode(&self, data: &mut dyn Iterator<Item = Rgba>, header: AnyHeader, out: &mut dyn std::io::Write) -> Result<(), Error>;
+}
+
+impl<F> DynFormat for F
+where
+	F: Format + Clone + Send + Sync + 'static,
+	F::Header: Into<AnyHeader> + TryFrom<AnyHeader>,
+	Error: From<<F::Header as TryFrom<AnyHeader>>::Error>,
+{
+	fn decode<'a>(&self, data: &'a mut &mut dyn std::io::Read) -> Result<(AnyHeader, Box<dyn Iterator<Item = Result<Rgba, Error>> + 'a>), Error> {
+		let (header, pixels) = self.clone().decode_checked(data)?;
+		Ok((header.into(), Box::new(pixels)))
+	}
+
+	fn decode_header(&self, mut data: &mut dyn std::io::Read) -> Result<AnyHeader, Error> {
+		self.clone().decode_header(&mut data).map(Into::into)
+	}
+
+	fn encode(&self, data: &mut dyn Iterator<Item = Rgba>, header: AnyHeader, mut out: &mut dyn std::io::Write) -> Result<(), Error> {
+		Format::encode(self.clone(), data, header.try_into()?, &mut out)
+	}
+}
+
+/// file extensions, without the dot, that aren't the name of their format's
+/// module, and that name. `by_name` matches them whatever their case.
+pub const EXTENSIONS: &[(&str, &str)] = &[
+	("jpg", "jpeg"),
+	("jpe", "jpeg"),
+	("jfif", "jpeg"),
+	("tif", "tiff"),
+	("ppm", "pnm"),
+	("pgm", "pnm"),
+	("pbm", "pnm"),
+	("pam", "pnm"),
+	("dib", "bmp"),
+	("icb", "tga"),
+	("vda", "tga"),
+	("vst", "tga"),
+	("pic", "hdr"),
+	("rgb", "sgi"),
+	("rgba", "sgi"),
+	("bw", "sgi"),
+	("int", "sgi"),
+	("inta", "sgi"),
+];
+
+macro_rules! formats {
+	($($feature:literal $name:literal $variant:ident: $format:expr, $header:path;)*) => {
+		/// the header of any of the crate's formats.
+		#[derive(Debug, Clone, PartialEq)]
+		#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+		#[non_exhaustive]
+		pub enum AnyHeader {
+			$(
+				#[cfg(feature = $feature)]
+				$variant($header),
+			)*
+			/// the header of a format from another crate, of which only the
+			/// dimensions are known.
+			Other { width: u32, height: u32 },
+		}
+
+		$(
+			#[cfg(feature = $feature)]
+			impl From<$header> for AnyHeader {
+				fn from(header: $header) -> Self {
+					Self::$variant(header)
+				}
+			}
+
+			#[cfg(feature = $feature)]
+			impl TryFrom<AnyHeader> for $header {
+				type Error = Error;
+
+				#[allow(unreachable_patterns)]
+				fn try_from(header: AnyHeader) -> Result<Self, Error> {
+					match header {
+						AnyHeader::$variant(header) => Ok(header),
+						_ => Err(Error::InvalidInput { reason: "the header is for another format" }),
+					}
+				}
+			}
+		)*
+
+		impl Dimensions for AnyHeader {
+			fn width(&self) -> u32 {
+				match *self {
+					$(
+						#[cfg(feature = $feature)]
+						Self::$variant(ref header) => header.width(),
+					)*
+					Self::Other { width, .. } => width,
+				}
+			}
+
+			fn height(&self) -> u32 {
+				match *self {
+					$(
+						#[cfg(feature = $feature)]
+						Self::$variant(ref header) => header.height(),
+					)*
+					Self::Other { height, .. } => height,
+				}
+			}
+		}
+
+		/// the format named `name`, which is the name of its module, `"pfm"`,
+		/// or a file extension in `EXTENSIONS`, with its defaults. other names
+		/// are looked up in `registry`.
+		pub fn by_name(name: &str) -> Option<Arc<dyn DynFormat>> {
+			let module = EXTENSIONS.iter().find(|(extension, _)| extension.eq_ignore_ascii_case(name));
+			match module.map_or(name, |&(_, module)| module) {
+				$(
+					#[cfg(feature = $feature)]
+					$name => Some(Arc::new($format)),
+				)*
+				_ => crate::registry::by_name(name),
+			}
+		}
+	};
+}
+
+formats! {
+	"qoi" "qoi" Qoi: crate::qoi::Qoi, crate::qoi::QoiHeader;
+	"png" "png" Png: crate::png::Png, crate::png::PngHeader;
+	"bmp" "bmp" Bmp: crate::bmp::Bmp, crate::bmp::BmpHeader;
+	"pnm" "pnm" Pnm: crate::pnm::Pnm, crate::pnm::PnmHeader;
+	"pnm" "pfm" Pfm: crate::pnm::Pfm::default(), crate::pnm::PfmHeader;
+	"tga" "tga" Tga: crate::tga::Tga, crate::tga::TgaHeader;
+	"gif" "gif" Gif: crate::gif::Gif, crate::gif::GifHeader;
+	"tiff" "tiff" Tiff: crate::tiff::Tiff, crate::tiff::TiffHeader;
+	"jpeg" "jpeg" Jpeg: crate::jpeg::Jpeg::default(), crate::jpeg::JpegHeader;
+	"hdr" "hdr" Hdr: crate::hdr::Hdr::default(), crate::hdr::HdrHeader;
+	"pcx" "pcx" Pcx: crate::pcx::Pcx, crate::pcx::PcxHeader;
+	"ktx2" "ktx2" Ktx2: crate::ktx2::Ktx2, crate::ktx2::Ktx2Header;
+	"xbm" "xbm" Xbm: crate::xbm::Xbm, crate::xbm::XbmHeader;
+	"xpm" "xpm" Xpm: crate::xpm::Xpm, crate::xpm::XpmHeader;
+	"sgi" "sgi" Sgi: crate::sgi::Sgi, crate::sgi::SgiHeader;
+	"qoy" "qoy" Qoy: crate::qoy::Qoy, crate::qoy::QoyHeader;
+	"raw" "raw" Raw: crate::raw::Raw::default(), crate::raw::RawHeader;
+}
+
+
+#[cfg(test)]
+mod test {
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn qoi() {
+		use crate::{Dimensions, Error, dynamic, qoi};
+
+		let format = dynamic::by_name("qoi").expect("compiled in?");
+		let pixels = (0..12).map(|i| (i * 20, 255 - i, i, 255)).collect::<Vec<_>>();
+		let header = qoi::QoiHeader { width: 4, height: 3, channels: qoi::QoiHeaderChannels::RGBA, colorspace: qoi::QoiHeaderColorspace::SRGB };
+		let mut out = vec![];
+		format.encode(&mut pixels.iter().copied(), header.clone().into(), &mut out).expect("error?");
+
+		let mut data: &mut dyn std::io::Read = &mut &out[..];
+		let (decoded, iter) = format.decode(&mut data).expect("error?");
+		assert_eq!((decoded.width(), decoded.height()), (4, 3));
+		assert_eq!(decoded, dynamic::AnyHeader::Qoi(header));
+		assert_eq!(iter.collect::<Result<Vec<_>, _>>().expect("error?"), pixels);
+		let mut data = &out[..];
+		assert_eq!(format.decode_header(&mut data).expect("error?"), decoded);
+		assert_eq!(data.len(), out.len() - 14);
+
+		// cut short, which ends in the error
+		let mut data: &mut dyn std::io::Read = &mut &out[..20];
+		let (_, iter) = format.decode(&mut data).expect("error?");
+		assert!(matches!(iter.last(), Some(Err(Error::UnexpectedEof { .. }))));
+		assert!(dynamic::by_name("nothing").is_none());
+	}
+
+	#[cfg(feature = "pnm")]
+	#[test]
+	fn extensions() {
+		use crate::{dynamic, pnm};
+
+		for name in ["pnm", "ppm", "PGM", "Pbm"] {
+			let mut data = &include_bytes!("../test/gradient.pgm")[..];
+			let header = dynamic::by_name(name).expect("compiled in?").decode_header(&mut data).expect("error?");
+			assert!(matches!(header, dynamic::AnyHeader::Pnm(pnm::PnmHeader { kind: pnm::PnmKind::Graymap, .. })), "{name}: {header:?}");
+		}
+		assert!(dynamic::by_name("PNM").is_none());
+		#[cfg(feature = "jpeg")]
+		assert!(dynamic::by_name("JPG").is_some());
+	}
+
+	#[cfg(all(feature = "qoi", feature = "bmp"))]
+	#[test]
+	fn wrong_header() {
+		use crate::{Error, bmp, dynamic};
+
+		let mut data: &mut dyn std::io::Read = &mut &include_bytes!("../test/small.bmp")[..];
+		let (header, iter) = dynamic::by_name("bmp").expect("compiled in?").decode(&mut data).expect("error?");
+		let pixels = iter.collect::<Result<Vec<_>, _>>().expect("error?");
+		assert!(matches!(header, dynamic::AnyHeader::Bmp(bmp::BmpHeader { .. })));
+
+		let result = dynamic::by_name("qoi").expect("compiled in?").encode(&mut pixels.into_iter(), header, &mut vec![]);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+	}
+}
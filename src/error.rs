@@ -0,0 +1,131 @@
+/*!
+errors shared by all formats.
+
+offsets are byte positions in the stream handed to the decoder.
+*/
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+	/// the stream doesn't start with the format's magic bytes.
+	InvalidMagic { offset: u64 },
+	/// a header field holds a value the format doesn't allow.
+	InvalidHeader { offset: u64, field: &'static str },
+	/// the stream ended in the middle of the image.
+	UnexpectedEof { offset: u64 },
+	/// the image data can't be decoded.
+	Malformed { offset: u64, reason: &'static str },
+	/// a run covers more pixels than the image has left.
+	RunOverrun { offset: u64 },
+	/// the end marker is missing or wrong.
+	MissingEndMarker { offset: u64 },
+	/// there are bytes after the end of the image.
+	TrailingData { offset: u64 },
+	/// an encoder was handed a number of pixels that doesn't fit the image.
+	PixelCountMismatch { expected: u64, got: u64 },
+	/// the data doesn't match its checksum.
+	ChecksumMismatch { expected: u32, actual: u32 },
+	/// a rectangle, or a single pixel, doesn't fit inside the image.
+	OutOfBounds { x: u32, y: u32, width: u32, height: u32 },
+	/// a caller's buffer is the wrong size.
+	BufferSize { expected: u64, got: u64 },
+	/// a call doesn't make sense given what came before it.
+	InvalidInput { reason: &'static str },
+	/// a progress callback asked for it to stop.
+	Cancelled,
+	/// the image is larger than a `Limits` allows.
+	TooLarge { what: &'static str, value: u64, limit: u64 },
+	/// an image in one colorspace was to be written as another, without
+	/// converting it.
+	#[cfg(feature = "std")]
+	ColorspaceMismatch { from: crate::Colorspace, to: crate::Colorspace },
+	/// the format can't do what was asked of it, such as encoding when it
+	/// only decodes.
+	Unsupported { what: &'static str },
+	/// the underlying reader or writer failed.
+	#[cfg(feature = "std")]
+	Io(std::io::Error),
+}
+
+impl Error {
+	/// where in the stream the error was found, if it came from the data itself.
+	pub fn offset(&self) -> Option<u64> {
+		match self {
+			Self::InvalidMagic { offset }
+			| Self::InvalidHeader { offset, .. }
+			| Self::UnexpectedEof { offset }
+			| Self::Malformed { offset, .. }
+			| Self::RunOverrun { offset }
+			| Self::MissingEndMarker { offset }
+			| Self::TrailingData { offset } => Some(*offset),
+			Self::PixelCountMismatch { .. }
+			| Self::ChecksumMismatch { .. }
+			| Self::OutOfBounds { .. }
+			| Self::BufferSize { .. }
+			| Self::InvalidInput { .. }
+			| Self::Cancelled
+			| Self::TooLarge { .. }
+			| Self::Unsupported { .. } => None,
+			#[cfg(feature = "std")]
+			Self::ColorspaceMismatch { .. } | Self::Io(_) => None,
+		}
+	}
+}
+
+impl core::fmt::Display for Error {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::InvalidMagic { offset } => write!(f, "invalid magic at byte {offset}"),
+			Self::InvalidHeader { offset, field } => write!(f, "invalid header field '{field}' at byte {offset}"),
+			Self::UnexpectedEof { offset } => write!(f, "unexpected end of data at byte {offset}"),
+			Self::Malformed { offset, reason } => write!(f, "malformed data at byte {offset}: {reason}"),
+			Self::RunOverrun { offset } => write!(f, "run at byte {offset} overruns the image"),
+			Self::MissingEndMarker { offset } => write!(f, "missing end marker at byte {offset}"),
+			Self::TrailingData { offset } => write!(f, "trailing data at byte {offset}"),
+			Self::PixelCountMismatch { expected, got } => write!(f, "expected {expected} pixels, got {got}"),
+			Self::ChecksumMismatch { expected, actual } => write!(f, "checksum mismatch: expected {expected:08x}, got {actual:08x}"),
+			Self::OutOfBounds { x, y, width: 1, height: 1 } => write!(f, "pixel ({x}, {y}) is out of bounds"),
+			Self::OutOfBounds { x, y, width, height } => write!(f, "{width}x{height} region at ({x}, {y}) is out of bounds"),
+			Self::BufferSize { expected, got } => write!(f, "buffer is {got} bytes, expected {expected}"),
+			Self::InvalidInput { reason } => write!(f, "invalid input: {reason}"),
+			Self::Cancelled => write!(f, "cancelled"),
+			Self::TooLarge { what, value, limit } => write!(f, "{what} of {value} is over the limit of {limit}"),
+			#[cfg(feature = "std")]
+			Self::ColorspaceMismatch { from, to } => write!(f, "{from:?} image written as {to:?} without converting it"),
+			Self::Unsupported { what } => write!(f, "{what} isn't supported"),
+			#[cfg(feature = "std")]
+			Self::Io(e) => write!(f, "io error: {e}"),
+		}
+	}
+}
+
+impl core::error::Error for Error {
+	fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+		match self {
+			#[cfg(feature = "std")]
+			Self::Io(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+/// what a failed `try_reserve` becomes.
+#[cfg(feature = "std")]
+pub(crate) fn oom() -> Error {
+	Error::Io(std::io::ErrorKind::OutOfMemory.into())
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+	fn from(value: std::io::Error) -> Self {
+		Self::Io(value)
+	}
+}
+
+
+/// for conversions that can't fail, such as `AnyHeader` into itself.
+impl From<core::convert::Infallible> for Error {
+	fn from(value: core::convert::Infallible) -> Self {
+		match value {}
+	}
+}
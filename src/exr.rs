@@ -0,0 +1,707 @@
+/*!
+openexr decoding, for single-part scanline images. there's no encoder, so
+`Exr::encode` is `Unsupported`.
+
+the file starts with the magic and a version, then the header, a list of
+attributes each with a name, type, size and value, ending in an empty
+name. `channels`, `compression` and `dataWindow` are read, and the rest
+skipped. then comes a table of where each block of rows is, and the blocks,
+each starting with its first row's y and its size.
+
+rows are stored a channel at a time, in the order the channels are listed,
+which is by name. samples are little-endian 32-bit unsigned integers,
+16-bit halves or 32-bit floats. channels have to be sampled at every pixel.
+uncompressed, `ZIPS` and `ZIP` blocks are read, which hold one row and 16
+rows. zip blocks are zlib streams of the block's bytes split into the even
+and the odd ones, with each byte stored as the difference from the last,
+and blocks that wouldn't have been any smaller are stored as they are.
+
+blocks are read in the order the table has them, which is usually top-down,
+and the ones that come before they're needed are kept until they are.
+
+`ExrPixels` hands out red, green, blue and alpha as floats, taken from the
+channels named `R`, `G`, `B` and `A`. an image without any of the first
+three, but with `Y`, comes out gray. missing channels are 0, but alpha,
+which is 1, and the rest are skipped. `Exr::decode` maps red, green and
+blue to 8 bits with its `ToneCurve`, and alpha linearly, and
+`Exr::decode_float` hands them out as they are.
+*/
+
+use std::collections::HashMap;
+
+use crate::Error;
+use crate::error::oom;
+use crate::zlib::{self, Inflate};
+
+pub use crate::tone::ToneCurve;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+/// version flags.
+const TILED: u32 = 1 << 9;
+const LONG_NAMES: u32 = 1 << 10;
+const DEEP: u32 = 1 << 11;
+const MULTIPART: u32 = 1 << 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ExrCompression {
+	None,
+	/// zip, a row at a time.
+	Zips,
+	/// zip, 16 rows at a time.
+	Zip,
+}
+
+impl ExrCompression {
+	fn rows(self) -> u32 {
+		match self {
+			Self::None | Self::Zips => 1,
+			Self::Zip => 16,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ExrSampleType {
+	Uint,
+	Half,
+	Float,
+}
+
+impl ExrSampleType {
+	fn size(self) -> usize {
+		match self {
+			Self::Half => 2,
+			Self::Uint | Self::Float => 4,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExrChannel {
+	pub name: String,
+	pub sample_type: ExrSampleType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExrHeader {
+	pub width: u32,
+	pub height: u32,
+	/// where the top left pixel is, which can be anywhere.
+	pub x: i32,
+	pub y: i32,
+	pub compression: ExrCompression,
+	/// in the order they're stored.
+	pub channels: Vec<ExrChannel>,
+}
+
+impl crate::Dimensions for ExrHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Exr {
+	pub curve: ToneCurve,
+}
+
+impl Default for Exr {
+	fn default() -> Self {
+		Self { curve: ToneCurve::Srgb }
+	}
+}
+
+/// a half as a float.
+fn half(x: u16) -> f32 {
+	let sign = if x & 0x8000 != 0 { -1.0 } else { 1.0 };
+	let exponent = (x >> 10 & 0x1f) as i32;
+	let mantissa = (x & 0x3ff) as f32;
+	sign * match exponent {
+		0 => mantissa * 2f32.powi(-24),
+		31 if mantissa == 0.0 => f32::INFINITY,
+		31 => f32::NAN,
+		_ => (mantissa + 1024.0) * 2f32.powi(exponent - 25),
+	}
+}
+
+/// compressed bytes in memory, for `Inflate`.
+struct Slice<'a> {
+	data: &'a [u8],
+	offset: u64,
+}
+
+impl zlib::Input for Slice<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		let n = buf.len().min(self.data.len());
+		buf[..n].copy_from_slice(&self.data[..n]);
+		self.data = &self.data[n..];
+		self.offset = self.offset.saturating_add(n as u64);
+		Ok(n)
+	}
+
+	fn offset(&self) -> u64 {
+		self.offset
+	}
+}
+
+/// pixels of an exr image, as red, green, blue and alpha. blocks of rows
+/// are read as they're needed.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct ExrPixels<'a, R> {
+	header: ExrHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// where each channel starts in a row, and the channels red, green,
+	/// blue and alpha come from.
+	starts: Vec<usize>,
+	stride: usize,
+	sources: [Option<usize>; 4],
+	/// each block's offset, and which block it is, in the order they're
+	/// read. `next` is the next one to read.
+	blocks: Vec<(u64, u32)>,
+	next: usize,
+	/// blocks read before they were needed, as they're stored.
+	ahead: HashMap<u32, (u64, Vec<u8>)>,
+	/// the block being handed out, decompressed.
+	block: Vec<u8>,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> ExrPixels<'a, R> {
+	/// reads the header and the table of blocks, up to the first block.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut magic = [0; 8];
+		crate::read_exact(data, &mut magic, 0)?;
+		if magic[..4] != MAGIC {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let version = u32::from_le_bytes([magic[4], magic[5], magic[6], magic[7]]);
+		if version & 0xff != 2 {
+			return Err(Error::InvalidHeader { offset: 4, field: "version" });
+		}
+		if version & (TILED | DEEP | MULTIPART) != 0 {
+			return Err(Error::InvalidHeader { offset: 5, field: "flags" });
+		}
+		let name_max = if version & LONG_NAMES != 0 { 255 } else { 31 };
+
+		let mut offset = 8;
+		let mut channels = None;
+		let mut compression = None;
+		let mut window = None;
+		loop {
+			let start = offset;
+			let name = Self::name(data, &mut offset, name_max, "attribute name")?;
+			if name.is_empty() {
+				break;
+			}
+			let kind = Self::name(data, &mut offset, name_max, "attribute type")?;
+			let mut size = [0; 4];
+			crate::read_exact(data, &mut size, offset)?;
+			offset += 4;
+			let Ok(size) = u64::try_from(i32::from_le_bytes(size))
+			else {
+				return Err(Error::InvalidHeader { offset: offset - 4, field: "attribute size" });
+			};
+
+			let field = match (&name[..], &kind[..]) {
+				(b"channels", b"chlist") => "channels",
+				(b"compression", b"compression") => "compression",
+				(b"dataWindow", b"box2i") => "data window",
+				_ => {
+					skip(data, &mut offset, size)?;
+					continue;
+				}
+			};
+			let value = read(data, &mut offset, size)?;
+			let invalid = Error::InvalidHeader { offset: start, field };
+			match field {
+				"channels" => channels = Some(Self::channels(&value).ok_or(invalid)?),
+				"compression" => {
+					compression = Some(match value[..] {
+						[0] => ExrCompression::None,
+						[2] => ExrCompression::Zips,
+						[3] => ExrCompression::Zip,
+						_ => return Err(invalid),
+					});
+				}
+				_ => {
+					let Ok(value) = <[u8; 16]>::try_from(&value[..])
+					else {
+						return Err(invalid);
+					};
+					let at = |i: usize| i32::from_le_bytes([value[i], value[i + 1], value[i + 2], value[i + 3]]) as i64;
+					let (x, y) = (at(0), at(4));
+					let (width, height) = (at(8) - x + 1, at(12) - y + 1);
+					let (Ok(width), Ok(height)) = (u32::try_from(width), u32::try_from(height))
+					else {
+						return Err(invalid);
+					};
+					if width == 0 || height == 0 {
+						return Err(invalid);
+					}
+					window = Some((x as i32, y as i32, width, height));
+				}
+			}
+		}
+
+		let missing = |field| Error::InvalidHeader { offset: offset - 1, field };
+		let channels: Vec<ExrChannel> = channels.ok_or(missing("channels"))?;
+		let compression = compression.ok_or(missing("compression"))?;
+		let (x, y, width, height) = window.ok_or(missing("data window"))?;
+
+		let mut starts = vec![];
+		let mut stride = 0usize;
+		let mut sources = [None; 4];
+		let mut gray = None;
+		for (i, channel) in channels.iter().enumerate() {
+			starts.push(stride);
+			let size = (width as usize).checked_mul(channel.sample_type.size());
+			stride = size.and_then(|x| x.checked_add(stride)).ok_or_else(oom)?;
+			match &channel.name[..] {
+				"R" => sources[0] = Some(i),
+				"G" => sources[1] = Some(i),
+				"B" => sources[2] = Some(i),
+				"A" => sources[3] = Some(i),
+				"Y" => gray = Some(i),
+				_ => {}
+			}
+		}
+		if sources[..3] == [None; 3] && gray.is_some() {
+			sources[..3].fill(gray);
+		}
+
+		let count = height.div_ceil(compression.rows());
+		let mut blocks = vec![];
+		for block in 0..count {
+			let mut at = [0; 8];
+			crate::read_exact(data, &mut at, offset)?;
+			offset += 8;
+			blocks.try_reserve(1).map_err(|_| oom())?;
+			blocks.push((u64::from_le_bytes(at), block));
+		}
+		blocks.sort_unstable();
+
+		Ok(Self {
+			header: ExrHeader { width, height, x, y, compression, channels },
+			data,
+			offset,
+			starts,
+			stride,
+			sources,
+			blocks,
+			next: 0,
+			ahead: HashMap::new(),
+			block: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &ExrHeader {
+		&self.header
+	}
+
+	/// reads a name, and the null after it.
+	fn name(data: &mut R, offset: &mut u64, max: usize, field: &'static str) -> Result<Vec<u8>, Error> {
+		let start = *offset;
+		let mut name = vec![];
+		loop {
+			let mut b = [0];
+			crate::read_exact(data, &mut b, *offset)?;
+			*offset += 1;
+			if b[0] == 0 {
+				return Ok(name);
+			}
+			if name.len() == max {
+				return Err(Error::InvalidHeader { offset: start, field });
+			}
+			name.push(b[0]);
+		}
+	}
+
+	/// the channels a `chlist` lists.
+	fn channels(mut value: &[u8]) -> Option<Vec<ExrChannel>> {
+		let mut channels = vec![];
+		loop {
+			let end = value.iter().position(|&b| b == 0)?;
+			if end == 0 {
+				return Some(channels);
+			}
+			let name = String::from_utf8_lossy(&value[..end]).into_owned();
+			let rest = value.get(end + 1..end + 17)?;
+			let sample_type = match rest[..4] {
+				[0, 0, 0, 0] => ExrSampleType::Uint,
+				[1, 0, 0, 0] => ExrSampleType::Half,
+				[2, 0, 0, 0] => ExrSampleType::Float,
+				_ => return None,
+			};
+			// sampled at every pixel, across and down
+			if rest[8..] != [1, 0, 0, 0, 1, 0, 0, 0] {
+				return None;
+			}
+			channels.try_reserve(1).ok()?;
+			channels.push(ExrChannel { name, sample_type });
+			value = &value[end + 17..];
+		}
+	}
+
+	/// reads the next block in the file, checking it's where the table
+	/// says, and returns which it is, where its data starts, and the data.
+	fn read_block(&mut self, keep: bool) -> Result<(u32, u64, Vec<u8>), Error> {
+		let (at, block) = self.blocks[self.next];
+		self.next += 1;
+		if at < self.offset {
+			return Err(Error::Malformed { offset: at, reason: "block overlaps the one before it" });
+		}
+		let gap = at - self.offset;
+		skip(self.data, &mut self.offset, gap)?;
+
+		let mut head = [0; 8];
+		crate::read_exact(self.data, &mut head, self.offset)?;
+		let y = i32::from_le_bytes([head[0], head[1], head[2], head[3]]) as i64;
+		if y != self.header.y as i64 + block as i64 * self.header.compression.rows() as i64 {
+			return Err(Error::Malformed { offset: self.offset, reason: "block isn't the one the table says" });
+		}
+		let Ok(size) = u64::try_from(i32::from_le_bytes([head[4], head[5], head[6], head[7]]))
+		else {
+			return Err(Error::Malformed { offset: self.offset + 4, reason: "block's size is negative" });
+		};
+		self.offset += 8;
+		let start = self.offset;
+		let data = match keep {
+			true => read(self.data, &mut self.offset, size)?,
+			false => {
+				skip(self.data, &mut self.offset, size)?;
+				vec![]
+			}
+		};
+		Ok((block, start, data))
+	}
+
+	/// decompresses the block that's needed next into `block`, reading up
+	/// to it.
+	fn load(&mut self) -> Result<(), Error> {
+		let rows = self.header.compression.rows();
+		let want = self.y / rows;
+		let (start, stored) = loop {
+			if let Some(block) = self.ahead.remove(&want) {
+				break block;
+			}
+			if self.next == self.blocks.len() {
+				return Err(Error::Malformed { offset: self.offset, reason: "block is missing" });
+			}
+			let (block, start, stored) = self.read_block(true)?;
+			if block == want {
+				break (start, stored);
+			}
+			self.ahead.try_reserve(1).map_err(|_| oom())?;
+			self.ahead.insert(block, (start, stored));
+		};
+
+		let rows = rows.min(self.header.height - want * rows);
+		let len = (self.stride as u64).checked_mul(rows as u64).and_then(|x| usize::try_from(x).ok()).ok_or_else(oom)?;
+		self.block.clear();
+		if self.header.compression == ExrCompression::None || stored.len() == len {
+			self.block = stored;
+		}
+		else {
+			let mut inflate = Inflate::new(Slice { data: &stored, offset: start });
+			while self.block.len() < len {
+				let n = (len - self.block.len()).min(1 << 16);
+				let at = self.block.len();
+				self.block.try_reserve(n).map_err(|_| oom())?;
+				self.block.resize(at + n, 0);
+				let read = inflate.read(&mut self.block[at..])?;
+				self.block.truncate(at + read);
+				if read == 0 {
+					break;
+				}
+			}
+
+			// the differences, then the two halves back together
+			for i in 1..self.block.len() {
+				self.block[i] = self.block[i].wrapping_add(self.block[i - 1]).wrapping_sub(128);
+			}
+			let half = self.block.len().div_ceil(2);
+			let mut joined = vec![];
+			joined.try_reserve_exact(self.block.len()).map_err(|_| oom())?;
+			for i in 0..self.block.len() {
+				joined.push(self.block[if i % 2 == 0 { i / 2 } else { half + i / 2 }]);
+			}
+			self.block = joined;
+		}
+		if self.block.len() != len {
+			return Err(Error::Malformed { offset: start, reason: "block isn't the size of its rows" });
+		}
+		Ok(())
+	}
+
+	/// reads past any blocks that are left, and returns the number of bytes
+	/// the image took up.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		while self.next < self.blocks.len() {
+			self.read_block(false)?;
+		}
+		Ok(self.offset)
+	}
+}
+
+/// reads `len` bytes, a piece at a time, so memory is only taken up once
+/// the data shows up.
+fn read(data: &mut impl std::io::Read, offset: &mut u64, mut len: u64) -> Result<Vec<u8>, Error> {
+	let mut buf = vec![];
+	while len > 0 {
+		let n = len.min(1 << 16) as usize;
+		let start = buf.len();
+		buf.try_reserve(n).map_err(|_| oom())?;
+		buf.resize(start + n, 0);
+		crate::read_exact(data, &mut buf[start..], *offset)?;
+		*offset = offset.saturating_add(n as u64);
+		len -= n as u64;
+	}
+	Ok(buf)
+}
+
+fn skip(data: &mut impl std::io::Read, offset: &mut u64, mut len: u64) -> Result<(), Error> {
+	let mut buf = [0; 512];
+	while len > 0 {
+		let n = len.min(buf.len() as u64) as usize;
+		crate::read_exact(data, &mut buf[..n], *offset)?;
+		*offset = offset.saturating_add(n as u64);
+		len -= n as u64;
+	}
+	Ok(())
+}
+
+impl<R: std::io::Read> Iterator for ExrPixels<'_, R> {
+	type Item = Result<[f32; 4], Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		let rows = self.header.compression.rows();
+		if self.x == 0 && self.y.is_multiple_of(rows)
+			&& let Err(e) = self.load()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let row = (self.y % rows) as usize * self.stride;
+		let sample = |channel: Option<usize>, default: f32| {
+			let Some(i) = channel
+			else {
+				return default;
+			};
+			let sample_type = self.header.channels[i].sample_type;
+			let at = row + self.starts[i] + self.x as usize * sample_type.size();
+			let b = &self.block[at..at + sample_type.size()];
+			match sample_type {
+				ExrSampleType::Half => half(u16::from_le_bytes([b[0], b[1]])),
+				ExrSampleType::Uint => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+				ExrSampleType::Float => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+			}
+		};
+		let [r, g, b, a] = self.sources;
+		let px = [sample(r, 0.0), sample(g, 0.0), sample(b, 0.0), sample(a, 1.0)];
+
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+/// a pixel brought down to 8 bits, color through `curve`.
+fn tone([r, g, b, a]: [f32; 4], curve: ToneCurve) -> crate::Rgba {
+	let [r, g, b] = [r, g, b].map(|x| curve.apply(x));
+	(r, g, b, ToneCurve::Linear.apply(a))
+}
+
+impl crate::Format for Exr {
+	type Header = ExrHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = ExrPixels::new(data)?;
+		let header = pixels.header().clone();
+		Ok((header, pixels.map_while(Result::ok).map(move |px| tone(px, self.curve))))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = ExrPixels::new(data)?;
+		let header = pixels.header().clone();
+		Ok((header, pixels.map(move |px| px.map(|px| tone(px, self.curve)))))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		_ = (data, header, out);
+		Err(Error::Unsupported { what: "encoding exr" })
+	}
+
+	/// samples as they are, without the curve.
+	fn decode_float(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::RgbaF32>), Error> {
+		let pixels = ExrPixels::new(data)?;
+		let header = pixels.header().clone();
+		Ok((header, pixels.map_while(Result::ok).map(|[r, g, b, a]| (r, g, b, a))))
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, exr};
+
+	fn sample(x: u32, y: u32, c: u32) -> f32 {
+		(x as f32 + y as f32 * 16.0 + c as f32 * 0.25) / 8.0
+	}
+
+	fn read(image: &[u8]) -> (exr::ExrHeader, Vec<[f32; 4]>) {
+		let mut data = image;
+		let mut pixels = exr::ExrPixels::new(&mut data).expect("error?");
+		let header = pixels.header().clone();
+		let decoded = (&mut pixels).map(|px| px.expect("error?")).collect();
+		assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		(header, decoded)
+	}
+
+	#[test]
+	fn half() {
+		assert_eq!(super::half(0x3c00), 1.0);
+		assert_eq!(super::half(0xc000), -2.0);
+		assert_eq!(super::half(0x7bff), 65504.0);
+		assert_eq!(super::half(0x0001), 2f32.powi(-24));
+		assert_eq!(super::half(0x8000), 0.0);
+		assert_eq!(super::half(0x7c00), f32::INFINITY);
+		assert!(super::half(0x7e00).is_nan());
+	}
+
+	#[test]
+	fn decode() {
+		// uncompressed, zip, and zip a row at a time with the blocks stored
+		// bottom-up
+		for (image, compression) in [
+			(&include_bytes!("../test/gradient.exr")[..], exr::ExrCompression::None),
+			(include_bytes!("../test/gradient_zip.exr"), exr::ExrCompression::Zip),
+			(include_bytes!("../test/gradient_zips.exr"), exr::ExrCompression::Zips),
+		] {
+			let (header, pixels) = read(image);
+			assert_eq!((header.width, header.height, header.x, header.y), (20, 20, 3, -2));
+			assert_eq!(header.compression, compression);
+			let names = header.channels.iter().map(|c| &c.name[..]).collect::<Vec<_>>();
+			assert_eq!(names, ["A", "B", "G", "R"]);
+			assert!(header.channels.iter().all(|c| c.sample_type == exr::ExrSampleType::Half));
+			assert_eq!(pixels.len(), 400);
+			for (i, px) in pixels.into_iter().enumerate() {
+				let (x, y) = (i as u32 % 20, i as u32 / 20);
+				assert_eq!(px, [0, 1, 2, 3].map(|c| sample(x, y, c)), "{compression:?} {x}, {y}");
+			}
+		}
+
+		// a float luminance channel, and an integer one that's skipped
+		let (header, pixels) = read(include_bytes!("../test/gray.exr"));
+		assert_eq!(header.channels[0], exr::ExrChannel { name: "Y".into(), sample_type: exr::ExrSampleType::Float });
+		assert_eq!(header.channels[1].sample_type, exr::ExrSampleType::Uint);
+		for (i, px) in pixels.into_iter().enumerate() {
+			let (x, y) = (i as u32 % 20, i as u32 / 20);
+			assert_eq!(px, [sample(x, y, 0), sample(x, y, 0), sample(x, y, 0), 1.0], "{x}, {y}");
+		}
+
+		// blocks that didn't compress, and no alpha
+		let mut seed = 12345u32;
+		let mut noise = vec![[0.0; 3]; 16 * 4];
+		for y in 0..4 {
+			for c in [2, 1, 0] {
+				for x in 0..16 {
+					seed = (seed.wrapping_mul(1103515245).wrapping_add(12345)) & 0x7fffffff;
+					noise[y * 16 + x][c] = (seed >> 16 & 2047) as f32 / 64.0;
+				}
+			}
+		}
+		let (_, pixels) = read(include_bytes!("../test/noise.exr"));
+		assert_eq!([0, 1, 2].map(|x| pixels[x][0]), [17.875, 29.796875, 28.90625]);
+		assert!(pixels.into_iter().eq(noise.into_iter().map(|[r, g, b]| [r, g, b, 1.0])));
+
+		// finishing right away skips the blocks
+		let image = include_bytes!("../test/gradient_zips.exr");
+		let mut data = &image[..];
+		let pixels = exr::ExrPixels::new(&mut data).expect("error?");
+		assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+	}
+
+	#[test]
+	fn format() {
+		use crate::{Format, RgbaF32};
+
+		// floats past 1 through decode_as, and the curve through decode
+		let image = include_bytes!("../test/noise.exr");
+		let (_, expected) = read(image);
+		let mut data = &image[..];
+		let (_, iter) = exr::Exr::default().decode_as::<RgbaF32>(&mut data).expect("error?");
+		assert!(iter.eq(expected.iter().map(|&[r, g, b, a]| (r, g, b, a))));
+		let mut data = &image[..];
+		let (header, iter) = exr::Exr { curve: exr::ToneCurve::Linear }.decode(&mut data).expect("error?");
+		assert!(iter.eq(expected.iter().map(|px| {
+			let [r, g, b, a] = px.map(|x| exr::ToneCurve::Linear.apply(x));
+			(r, g, b, a)
+		})));
+
+		let result = exr::Exr::default().encode_from([(0.0, 0.0, 0.0, 1.0f32)].into_iter(), header, &mut vec![]);
+		assert!(matches!(result, Err(Error::Unsupported { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = exr::ExrPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name)));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic.exr", Error::InvalidMagic { offset: 0 });
+		case!("bad_version.exr", Error::InvalidHeader { offset: 4, field: "version" });
+		case!("tiled.exr", Error::InvalidHeader { offset: 5, field: "flags" });
+		case!("piz.exr", Error::InvalidHeader { offset: 101, field: "compression" });
+		case!("subsampled.exr", Error::InvalidHeader { offset: 8, field: "channels" });
+		case!("no_channels.exr", Error::InvalidHeader { offset: 330, field: "channels" });
+		case!("long_name.exr", Error::InvalidHeader { offset: 8, field: "attribute name" });
+		case!("bad_window.exr", Error::InvalidHeader { field: "data window", .. });
+		case!("overlapping.exr", Error::Malformed { offset: 491, .. });
+		case!("wrong_y.exr", Error::Malformed { .. });
+		case!("bad_zip.exr", Error::Malformed { .. });
+		case!("short_block.exr", Error::Malformed { offset: 499, .. });
+		case!("truncated.exr", Error::UnexpectedEof { offset: 1000 });
+
+		assert!(decode(include_bytes!("../test/gradient.exr")).is_ok());
+	}
+}
@@ -0,0 +1,821 @@
+/*!
+row-based reading and writing, shaped like the `png` crate's api: make a
+`Reader`, look at its `info()`, then pull rows into a buffer. `Writer` is
+the same in reverse.
+
+rows are packed bytes, `channels` per pixel, in r, g, b, a order.
+
+`Reader` tells the format from what its `BufRead` has buffered, so it reads
+any that's compiled in or registered, and decodes a row at a time, as rows
+are asked for. qoi goes through `QoiDecoder`, and keeps its channels and
+colorspace. the rest go through `Format::decode_rows`, and come out as
+rgba, in srgb, except for icons and registered formats, which are decoded
+whole when the reader is made.
+
+`Writer` looks the format up with `dynamic::by_name`. qoi is encoded as
+rows are written. the rest are kept until `finish`, and encoded through
+`DynFormat`, with a header that has the image's channels and each
+format's defaults for everything else. formats without an encoder, or
+whose header an `ImageInfo` can't fill in, are `Unsupported`.
+*/
+
+use std::sync::Arc;
+
+use crate::detect::{self, Detected};
+use crate::error::oom;
+use crate::rows::Rows;
+use crate::{AnyHeader, DynFormat, Error, Rgba, dynamic};
+#[cfg(feature = "qoi")]
+use crate::qoi::{QoiDecoder, QoiEncoder, QoiHeader, QoiHeaderChannels, QoiHeaderColorspace};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colorspace {
+	SRGB,
+	Linear,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageInfo {
+	pub width: u32,
+	pub height: u32,
+	/// 3 for rgb, 4 for rgba.
+	pub channels: u8,
+	pub colorspace: Colorspace,
+	/// name of the format, such as `"qoi"`.
+	pub format: &'static str,
+}
+
+impl ImageInfo {
+	/// bytes in one row.
+	pub fn row_size(&self) -> u64 {
+		self.width as u64 * self.channels as u64
+	}
+
+	/// bytes in the whole image.
+	pub fn image_size(&self) -> u64 {
+		self.row_size() * self.height as u64
+	}
+
+	#[cfg(feature = "qoi")]
+	fn qoi(header: &QoiHeader) -> Self {
+		Self {
+			width: header.width,
+			height: header.height,
+			channels: match header.channels {
+				QoiHeaderChannels::RGB => 3,
+				QoiHeaderChannels::RGBA => 4,
+			},
+			colorspace: match header.colorspace {
+				QoiHeaderColorspace::SRGB => Colorspace::SRGB,
+				QoiHeaderColorspace::Linear => Colorspace::Linear,
+			},
+			format: "qoi",
+		}
+	}
+
+	/// the header `format` encodes this image with.
+	#[allow(unused_variables)]
+	fn header(&self, format: &str) -> Result<AnyHeader, Error> {
+		let (width, height) = (self.width, self.height);
+		let alpha = match self.channels {
+			3 => false,
+			4 => true,
+			_ => return Err(Error::InvalidInput { reason: "channels isn't 3 or 4" }),
+		};
+		Ok(match format {
+			#[cfg(feature = "png")]
+			"png" => {
+				use crate::png::{PngColorType, PngHeader};
+				let color_type = if alpha { PngColorType::RGBA } else { PngColorType::RGB };
+				PngHeader { width, height, color_type, bit_depth: 8, interlaced: false, palette: vec![] }.into()
+			}
+			#[cfg(feature = "bmp")]
+			"bmp" => {
+				use crate::bmp::{BmpColorType, BmpHeader};
+				let color_type = if alpha { BmpColorType::RGBA } else { BmpColorType::RGB };
+				BmpHeader { width, height, color_type, top_down: false, palette: vec![] }.into()
+			}
+			#[cfg(feature = "pnm")]
+			"pnm" => {
+				use crate::pnm::{PamTupleType, PnmHeader, PnmKind};
+				let kind = if alpha { PnmKind::Pam(PamTupleType::RGBAlpha) } else { PnmKind::Pixmap };
+				PnmHeader { width, height, kind, plain: false, max_value: 255 }.into()
+			}
+			#[cfg(feature = "tga")]
+			"tga" => {
+				use crate::tga::{TgaColorType, TgaHeader};
+				let color_type = if alpha { TgaColorType::RGBA } else { TgaColorType::RGB };
+				TgaHeader { width, height, color_type, top_down: false, right_to_left: false, rle: false, palette: vec![] }.into()
+			}
+			#[cfg(feature = "gif")]
+			"gif" => crate::gif::GifHeader { width, height, palette: vec![], background: 0 }.into(),
+			#[cfg(feature = "tiff")]
+			"tiff" => {
+				use crate::tiff::{TiffColorType, TiffCompression, TiffHeader};
+				let color_type = if alpha { TiffColorType::RGBA } else { TiffColorType::RGB };
+				TiffHeader { width, height, color_type, compression: TiffCompression::None, big_endian: false }.into()
+			}
+			#[cfg(feature = "jpeg")]
+			"jpeg" => {
+				use crate::jpeg::{JpegColorType, JpegHeader, JpegSubsampling};
+				JpegHeader { width, height, color_type: JpegColorType::YCbCr, subsampling: JpegSubsampling::Chroma420 }.into()
+			}
+			#[cfg(feature = "hdr")]
+			"hdr" => crate::hdr::HdrHeader { width, height, exposure: 1.0 }.into(),
+			#[cfg(feature = "pcx")]
+			"pcx" => crate::pcx::PcxHeader { width, height, color_type: crate::pcx::PcxColorType::RGB, rle: true }.into(),
+			#[cfg(feature = "xbm")]
+			"xbm" => crate::xbm::XbmHeader { width, height, name: "image".into(), hotspot: None, x10: false }.into(),
+			#[cfg(feature = "xpm")]
+			"xpm" => crate::xpm::XpmHeader { width, height, name: "image".into(), hotspot: None }.into(),
+			#[cfg(feature = "sgi")]
+			"sgi" => {
+				use crate::sgi::{SgiColorType, SgiHeader};
+				let color_type = if alpha { SgiColorType::RGBA } else { SgiColorType::RGB };
+				SgiHeader { width, height, color_type, bit_depth: 8, rle: true, name: String::new() }.into()
+			}
+			#[cfg(feature = "qoy")]
+			"qoy" => {
+				use crate::qoy::{QoyChannels, QoyHeader};
+				let channels = if alpha { QoyChannels::RGBA } else { QoyChannels::RGB };
+				QoyHeader { width, height, channels, subsampled: false }.into()
+			}
+			#[cfg(feature = "raw")]
+			"raw" => {
+				use crate::raw::{RawHeader, RawOrder};
+				let order = if alpha { RawOrder::RGBA } else { RawOrder::RGB };
+				RawHeader { width, height, order, ..RawHeader::default() }.into()
+			}
+			#[cfg(feature = "ktx2")]
+			"ktx2" => return Err(Error::Unsupported { what: "writing ktx2 through `Writer`" }),
+			#[cfg(feature = "pnm")]
+			"pfm" => return Err(Error::Unsupported { what: "writing pfm through `Writer`" }),
+			// registered formats make their own headers out of this
+			_ => AnyHeader::Other { width, height },
+		})
+	}
+}
+
+/// pixels of any format.
+type Pixels<'a> = Box<dyn Iterator<Item = Result<Rgba, Error>> + 'a>;
+
+/// where the rows come from.
+enum Source<'a> {
+	#[cfg(feature = "qoi")]
+	Qoi(Box<QoiDecoder>, &'a mut dyn std::io::Read),
+	Rows(Rows<Pixels<'a>>),
+}
+
+/// the width, height and rows of an image in `format`.
+#[allow(unused_imports, unused_macros)]
+fn rows<'a, R: std::io::Read>(format: Detected, data: &'a mut R) -> Result<(u32, u32, Rows<Pixels<'a>>), Error> {
+	use crate::{Dimensions, Format};
+
+	macro_rules! checked {
+		($format:expr) => {{
+			let (header, pixels) = $format.decode_checked(data)?;
+			(header.width(), header.height(), Box::new(pixels))
+		}};
+	}
+	/// a decoder that only has an iterator.
+	macro_rules! pixels {
+		($pixels:path) => {{
+			let pixels = <$pixels>::new(data)?;
+			(pixels.header().width(), pixels.header().height(), Box::new(pixels))
+		}};
+	}
+
+	let (width, height, pixels): (_, _, Pixels<'a>) = match format {
+		#[cfg(feature = "qoi")]
+		Detected::Qoi => checked!(crate::qoi::Qoi),
+		#[cfg(feature = "png")]
+		Detected::Png => checked!(crate::png::Png),
+		#[cfg(feature = "bmp")]
+		Detected::Bmp => checked!(crate::bmp::Bmp),
+		#[cfg(feature = "pnm")]
+		Detected::Pnm => checked!(crate::pnm::Pnm),
+		#[cfg(feature = "pnm")]
+		Detected::Pfm => checked!(crate::pnm::Pfm::default()),
+		#[cfg(feature = "gif")]
+		Detected::Gif => checked!(crate::gif::Gif),
+		#[cfg(feature = "tiff")]
+		Detected::Tiff => checked!(crate::tiff::Tiff),
+		#[cfg(feature = "hdr")]
+		Detected::Hdr => checked!(crate::hdr::Hdr::default()),
+		#[cfg(feature = "exr")]
+		Detected::Exr => checked!(crate::exr::Exr::default()),
+		#[cfg(feature = "pcx")]
+		Detected::Pcx => checked!(crate::pcx::Pcx),
+		#[cfg(feature = "dds")]
+		Detected::Dds => pixels!(crate::dds::DdsPixels<_>),
+		#[cfg(feature = "ktx2")]
+		Detected::Ktx2 => checked!(crate::ktx2::Ktx2),
+		#[cfg(feature = "xbm")]
+		Detected::Xbm => checked!(crate::xbm::Xbm),
+		#[cfg(feature = "xpm")]
+		Detected::Xpm => checked!(crate::xpm::Xpm),
+		#[cfg(feature = "ras")]
+		Detected::Ras => pixels!(crate::ras::RasPixels<_>),
+		#[cfg(feature = "sgi")]
+		Detected::Sgi => checked!(crate::sgi::Sgi),
+		#[cfg(feature = "qoy")]
+		Detected::Qoy => checked!(crate::qoy::Qoy),
+		#[cfg(feature = "qoir")]
+		Detected::Qoir => checked!(crate::qoir::Qoir),
+		#[cfg(feature = "ilbm")]
+		Detected::Ilbm => pixels!(crate::ilbm::IlbmPixels<_>),
+		#[cfg(feature = "psd")]
+		Detected::Psd => pixels!(crate::psd::PsdPixels<_>),
+		#[cfg(feature = "fits")]
+		Detected::Fits => {
+			// scaled by the range of every value, so they're all read up front
+			let (header, pixels) = crate::fits::Fits::default().decode(data)?;
+			(header.width(), header.height(), Box::new(pixels.map(Ok)))
+		}
+		// an icon's largest image and a registered format are only decoded whole
+		format => {
+			let image = format.decode(data)?;
+			(image.width(), image.height(), Box::new(image.into_pixels().into_iter().map(Ok)))
+		}
+	};
+	Ok((width, height, Rows::new(pixels, width, height)))
+}
+
+/// reads an image a row at a time, whatever format it's in.
+pub struct Reader<'a> {
+	source: Source<'a>,
+	info: ImageInfo,
+	rows: u32,
+}
+
+impl core::fmt::Debug for Reader<'_> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.debug_struct("Reader").field("info", &self.info).field("rows", &self.rows).finish_non_exhaustive()
+	}
+}
+
+impl<'a> Reader<'a> {
+	/// tells the format from what `data` has buffered, which is enough for a
+	/// `BufReader` that hasn't been read from, and reads the header. data in
+	/// no format it knows is `InvalidMagic`, and no data at all
+	/// `UnexpectedEof`.
+	pub fn new<R: std::io::BufRead>(data: &'a mut R) -> Result<Self, Error> {
+		let start = data.fill_buf()?;
+		if start.is_empty() {
+			return Err(Error::UnexpectedEof { offset: 0 });
+		}
+		let start = &start[..start.len().min(detect::LEN.max(crate::registry::longest()))];
+		let format = Detected::from_start(start).ok_or(Error::InvalidMagic { offset: 0 })?;
+
+		#[cfg(feature = "qoi")]
+		if format == Detected::Qoi {
+			let mut decoder = QoiDecoder::new();
+			let header = crate::qoi::ready(decoder.poll_header(data))?;
+			return Ok(Self {
+				source: Source::Qoi(Box::new(decoder), data),
+				info: ImageInfo::qoi(&header),
+				rows: 0,
+			});
+		}
+
+		let (width, height, rows) = rows(format, data)?;
+		let info = ImageInfo {
+			width,
+			height,
+			channels: 4,
+			colorspace: Colorspace::SRGB,
+			format: format.name(),
+		};
+		Ok(Self {
+			source: Source::Rows(rows),
+			info,
+			rows: 0,
+		})
+	}
+
+	pub fn info(&self) -> &ImageInfo {
+		&self.info
+	}
+
+	/// size of the buffer `read_image` needs.
+	pub fn output_buffer_size(&self) -> u64 {
+		self.info.image_size()
+	}
+
+	/// decodes the next row into the start of `buf`. returns `false` once
+	/// every row has been read, after checking that a qoi image ends
+	/// properly.
+	pub fn next_row(&mut self, buf: &mut [u8]) -> Result<bool, Error> {
+		if self.rows == self.info.height {
+			#[cfg(feature = "qoi")]
+			if let Source::Qoi(decoder, data) = &mut self.source {
+				crate::qoi::ready(decoder.poll_end(data))?;
+			}
+			return Ok(false);
+		}
+
+		let size = self.info.row_size();
+		if (buf.len() as u64) < size {
+			return Err(Error::BufferSize { expected: size, got: buf.len() as u64 });
+		}
+
+		let channels = self.info.channels as usize;
+		let row = &mut buf[..size as usize];
+		match &mut self.source {
+			#[cfg(feature = "qoi")]
+			Source::Qoi(decoder, data) => {
+				for dst in row.chunks_exact_mut(channels) {
+					let Some(px) = crate::qoi::ready(decoder.poll_pixel(data))? else {
+						return Err(Error::UnexpectedEof { offset: decoder.offset() });
+					};
+					dst.copy_from_slice(&[px.0, px.1, px.2, px.3][..channels]);
+				}
+			}
+			Source::Rows(rows) => {
+				let pixels = rows.next_row()?.unwrap_or_default();
+				for (dst, px) in row.chunks_exact_mut(channels).zip(pixels) {
+					dst.copy_from_slice(&[px.0, px.1, px.2, px.3]);
+				}
+			}
+		}
+
+		self.rows += 1;
+		Ok(true)
+	}
+
+	/// decodes every row that's left into `buf`.
+	pub fn read_image(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+		let size = self.info.row_size() * (self.info.height - self.rows) as u64;
+		if (buf.len() as u64) < size {
+			return Err(Error::BufferSize { expected: size, got: buf.len() as u64 });
+		}
+
+		let mut rows = buf[..size as usize].chunks_exact_mut((self.info.row_size() as usize).max(1));
+		while self.next_row(rows.next().unwrap_or_default())? {}
+		Ok(())
+	}
+}
+
+enum State<W> {
+	Start(W),
+	#[cfg(feature = "qoi")]
+	Qoi(Box<QoiEncoder<W>>, ImageInfo),
+	/// a format that's encoded once every row is in.
+	Kept {
+		format: Arc<dyn DynFormat>,
+		header: AnyHeader,
+		info: ImageInfo,
+		pixels: Vec<Rgba>,
+		out: W,
+	},
+	/// an error was hit partway through.
+	Failed,
+}
+
+/// writes an image a row at a time, in the format named by its `ImageInfo`.
+///
+/// `finish` has to be called once every row is written.
+pub struct Writer<W> {
+	state: State<W>,
+}
+
+impl<W> core::fmt::Debug for Writer<W> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		let state = match &self.state {
+			State::Start(_) => "start",
+			#[cfg(feature = "qoi")]
+			State::Qoi(..) => "qoi",
+			State::Kept { .. } => "kept",
+			State::Failed => "failed",
+		};
+		f.debug_struct("Writer").field("state", &state).finish()
+	}
+}
+
+impl<W: std::io::Write> Writer<W> {
+	pub fn new(out: W) -> Self {
+		Self {
+			state: State::Start(out),
+		}
+	}
+
+	pub fn write_header(&mut self, info: &ImageInfo) -> Result<(), Error> {
+		if !matches!(self.state, State::Start(_)) {
+			return Err(Error::InvalidInput { reason: "header already written" });
+		}
+		if !matches!(info.channels, 3 | 4) {
+			return Err(Error::InvalidHeader { offset: 12, field: "channels" });
+		}
+		if info.width == 0 {
+			return Err(Error::InvalidHeader { offset: 4, field: "width" });
+		}
+		if info.height == 0 {
+			return Err(Error::InvalidHeader { offset: 8, field: "height" });
+		}
+
+		#[cfg(feature = "qoi")]
+		if info.format == "qoi" {
+			let header = QoiHeader {
+				width: info.width,
+				height: info.height,
+				channels: if info.channels == 3 { QoiHeaderChannels::RGB } else { QoiHeaderChannels::RGBA },
+				colorspace: match info.colorspace {
+					Colorspace::SRGB => QoiHeaderColorspace::SRGB,
+					Colorspace::Linear => QoiHeaderColorspace::Linear,
+				},
+			};
+			let State::Start(out) = core::mem::replace(&mut self.state, State::Failed) else {
+				return Err(Error::InvalidInput { reason: "header already written" });
+			};
+			self.state = State::Qoi(Box::new(QoiEncoder::new(&header, out)?), info.clone());
+			return Ok(());
+		}
+
+		let format = dynamic::by_name(info.format).ok_or(Error::Unsupported { what: "writing this format" })?;
+		let header = info.header(info.format)?;
+		let State::Start(out) = core::mem::replace(&mut self.state, State::Failed) else {
+			return Err(Error::InvalidInput { reason: "header already written" });
+		};
+		self.state = State::Kept {
+			format,
+			header,
+			info: info.clone(),
+			pixels: vec![],
+			out,
+		};
+		Ok(())
+	}
+
+	fn info(&self) -> Result<&ImageInfo, Error> {
+		match &self.state {
+			State::Start(_) => Err(Error::InvalidInput { reason: "no header written" }),
+			#[cfg(feature = "qoi")]
+			State::Qoi(_, info) => Ok(info),
+			State::Kept { info, .. } => Ok(info),
+			State::Failed => Err(Error::InvalidInput { reason: "an earlier write failed" }),
+		}
+	}
+
+	/// pixels that haven't been written yet.
+	fn remaining(&self) -> u64 {
+		match &self.state {
+			#[cfg(feature = "qoi")]
+			State::Qoi(encoder, _) => encoder.remaining(),
+			State::Kept { info, pixels, .. } => info.width as u64 * info.height as u64 - pixels.len() as u64,
+			_ => 0,
+		}
+	}
+
+	/// encodes one row, which must be exactly `info.row_size()` bytes.
+	pub fn write_row(&mut self, row: &[u8]) -> Result<(), Error> {
+		let info = self.info()?;
+
+		let size = info.row_size();
+		if row.len() as u64 != size {
+			return Err(Error::BufferSize { expected: size, got: row.len() as u64 });
+		}
+
+		let total = info.width as u64 * info.height as u64;
+		if self.remaining() == 0 {
+			return Err(Error::PixelCountMismatch { expected: total, got: total + info.width as u64 });
+		}
+
+		let channels = info.channels as usize;
+		let pixels = row.chunks_exact(channels).map(|px| (px[0], px[1], px[2], if channels == 4 { px[3] } else { 255 }));
+		let result = match &mut self.state {
+			#[cfg(feature = "qoi")]
+			State::Qoi(encoder, _) => { pixels }.try_for_each(|px| encoder.push(px)),
+			State::Kept { pixels: kept, .. } => match kept.try_reserve(row.len() / channels) {
+				Ok(()) => {
+					kept.extend(pixels);
+					Ok(())
+				}
+				Err(_) => Err(oom()),
+			},
+			_ => Ok(()),
+		};
+		if result.is_err() {
+			self.state = State::Failed;
+		}
+		result
+	}
+
+	/// encodes every row that's left, packed one after another.
+	pub fn write_image(&mut self, data: &[u8]) -> Result<(), Error> {
+		let info = self.info()?;
+
+		let size = self.remaining() * info.channels as u64;
+		if data.len() as u64 != size {
+			return Err(Error::BufferSize { expected: size, got: data.len() as u64 });
+		}
+
+		let row_size = (info.row_size() as usize).max(1);
+		for row in data.chunks_exact(row_size) {
+			self.write_row(row)?;
+		}
+		Ok(())
+	}
+
+	/// ends the image and hands back the writer.
+	pub fn finish(self) -> Result<W, Error> {
+		match self.state {
+			State::Start(_) => Err(Error::InvalidInput { reason: "no header written" }),
+			#[cfg(feature = "qoi")]
+			State::Qoi(encoder, _) => encoder.finish(),
+			State::Kept { format, header, info, pixels, mut out } => {
+				let total = info.width as u64 * info.height as u64;
+				if pixels.len() as u64 != total {
+					return Err(Error::PixelCountMismatch { expected: total, got: pixels.len() as u64 });
+				}
+				format.encode(&mut pixels.into_iter(), header, &mut out)?;
+				Ok(out)
+			}
+			State::Failed => Err(Error::InvalidInput { reason: "an earlier write failed" }),
+		}
+	}
+}
+
+#[cfg(all(test, feature = "qoi"))]
+mod test {
+	use crate::{Colorspace, Error, Format, ImageInfo, Reader, Writer, qoi};
+
+	const IMAGE_SMALL: &[u8; 44] = include_bytes!("../test/small.qoi");
+
+	fn pixels(data: &[u8], channels: usize) -> Vec<crate::Rgba> {
+		data.chunks_exact(channels).map(|x| (x[0], x[1], x[2], if channels == 4 { x[3] } else { 255 })).collect()
+	}
+
+	fn write(info: &ImageInfo, data: &[u8]) -> Vec<u8> {
+		let mut writer = Writer::new(vec![]);
+		writer.write_header(info).expect("error?");
+		writer.write_image(data).expect("error?");
+		writer.finish().expect("error?")
+	}
+
+	#[test]
+	fn decode() {
+		let mut data = &IMAGE_SMALL[..];
+		let mut reader = Reader::new(&mut data).expect("error?");
+
+		assert_eq!(reader.info(), &ImageInfo {
+			width: 4,
+			height: 4,
+			channels: 4,
+			colorspace: Colorspace::SRGB,
+			format: "qoi",
+		});
+		assert_eq!(reader.output_buffer_size(), 64);
+
+		let mut buf = vec![0; 64];
+		reader.read_image(&mut buf).expect("error?");
+		let data = pixels(&buf, 4);
+
+		assert_eq!(data[0], (0, 0, 0, 255));
+		assert_eq!(data[5], (0, 255, 0, 255));
+		assert_eq!(data[7], (0, 0, 255, 255));
+		assert_eq!(data[13], (255, 0, 0, 255));
+
+		// row by row gives the same, and the buffer can be bigger than a row
+		let mut data = &IMAGE_SMALL[..];
+		let mut reader = Reader::new(&mut data).expect("error?");
+		let mut row = [0; 20];
+		let mut rows = vec![];
+		while reader.next_row(&mut row).expect("error?") {
+			rows.extend_from_slice(&row[..16]);
+		}
+		assert_eq!(rows, buf);
+		assert!(!reader.next_row(&mut row).expect("error?"));
+
+		let mut data = &IMAGE_SMALL[..];
+		let mut reader = Reader::new(&mut data).expect("error?");
+		let result = reader.next_row(&mut [0; 15]);
+		assert!(matches!(result, Err(Error::BufferSize { expected: 16, got: 15 })), "{result:?}");
+	}
+
+	#[test]
+	fn other_formats() {
+		#[allow(unused_mut)]
+		let mut files: Vec<(&[u8], &str, Vec<crate::Rgba>)> = vec![];
+		macro_rules! file {
+			($feature:literal, $name:literal, $format:expr) => {
+				#[cfg(feature = $feature)]
+				{
+					let file = include_bytes!(concat!("../test/", $name));
+					let mut data = &file[..];
+					let (_, pixels) = $format.decode(&mut data).expect("error?");
+					files.push((file, $feature, pixels.collect()));
+				}
+			};
+		}
+		file!("png", "small.png", crate::png::Png);
+		file!("bmp", "small.bmp", crate::bmp::Bmp);
+		file!("tiff", "gradient.tiff", crate::tiff::Tiff);
+
+		for (file, format, expected) in files {
+			let mut data = file;
+			let mut reader = Reader::new(&mut data).expect("error?");
+			let info = reader.info();
+			assert_eq!((info.channels, info.colorspace, info.format), (4, Colorspace::SRGB, format));
+			assert_eq!(info.width as usize * info.height as usize, expected.len());
+			let mut buf = vec![0; reader.output_buffer_size() as usize];
+			reader.read_image(&mut buf).expect("error?");
+			assert_eq!(pixels(&buf, 4), expected, "{format}");
+			assert!(!reader.next_row(&mut buf).expect("error?"));
+		}
+
+		let result = Reader::new(&mut &b"nothing"[..]).map(|_| ());
+		assert!(matches!(result, Err(Error::InvalidMagic { offset: 0 })), "{result:?}");
+		let result = Reader::new(&mut &b""[..]).map(|_| ());
+		assert!(matches!(result, Err(Error::UnexpectedEof { offset: 0 })), "{result:?}");
+	}
+
+	#[test]
+	fn streams() {
+		let files: [&[u8]; _] = [
+			#[cfg(feature = "png")]
+			include_bytes!("../test/gradient.png"),
+			#[cfg(feature = "pnm")]
+			include_bytes!("../test/gradient.ppm"),
+			#[cfg(feature = "bmp")]
+			include_bytes!("../test/gradient_top_down.bmp"),
+		];
+
+		// cut short, the header still reads, and the pixels only run out later
+		for file in files {
+			let mut data = &file[..file.len() / 2 + 20];
+			let mut reader = Reader::new(&mut data).expect("error?");
+			let mut buf = vec![0; reader.output_buffer_size() as usize];
+			assert!(reader.read_image(&mut buf).is_err());
+		}
+	}
+
+	#[test]
+	fn encode_other_formats() {
+		let data = (0..4 * 3 * 4).map(|i| (i * 5) as u8 | 1).collect::<Vec<_>>();
+		let info = ImageInfo {
+			width: 4,
+			height: 3,
+			channels: 4,
+			colorspace: Colorspace::SRGB,
+			format: "qoi",
+		};
+
+		let formats = [
+			#[cfg(feature = "png")]
+			"png",
+			#[cfg(feature = "bmp")]
+			"bmp",
+			#[cfg(feature = "tiff")]
+			"tiff",
+		];
+		for format in formats {
+			let out = write(&ImageInfo { format, ..info.clone() }, &data);
+			let mut file = &out[..];
+			let mut reader = Reader::new(&mut file).expect("error?");
+			assert_eq!(reader.info().format, format);
+			let mut buf = vec![0; 48];
+			reader.read_image(&mut buf).expect("error?");
+			assert_eq!(buf, data, "{format}");
+		}
+
+		// exr only decodes, and dds isn't in `dynamic` at all
+		for format in ["exr", "dds"] {
+			let result = Writer::new(vec![]).write_header(&ImageInfo { format, ..info.clone() });
+			assert!(matches!(result, Err(Error::Unsupported { .. })), "{result:?}");
+		}
+	}
+
+	#[test]
+	fn encode() {
+		let data = [
+			255, 255, 255,
+			255, 255, 255,
+			0, 255, 255,
+			255, 0, 255,
+			255, 255, 0,
+			255, 255, 255,
+		];
+		let info = ImageInfo {
+			width: 3,
+			height: 2,
+			channels: 3,
+			colorspace: Colorspace::Linear,
+			format: "qoi",
+		};
+
+		let out = write(&info, &data);
+
+		let mut file = &out[..];
+		let mut reader = Reader::new(&mut file).expect("error?");
+		assert_eq!(reader.info(), &info);
+		let mut buf = vec![0; 18];
+		reader.read_image(&mut buf).expect("error?");
+		assert_eq!(buf, data);
+	}
+
+	#[test]
+	fn encode_ops() {
+		let data = [
+			10, 10, 10, 255,
+			10, 10, 10, 128,
+			11, 9, 10, 128,
+			20, 30, 15, 128,
+			255, 0, 1, 128,
+			1, 255, 0, 128,
+			10, 10, 10, 255,
+			10, 10, 10, 255,
+			10, 10, 10, 255,
+		];
+		let info = ImageInfo {
+			width: 3,
+			height: 3,
+			channels: 4,
+			colorspace: Colorspace::SRGB,
+			format: "qoi",
+		};
+
+		let out = write(&info, &data);
+
+		// the same bytes as going through `Format`
+		let header = qoi::QoiHeader {
+			width: 3,
+			height: 3,
+			channels: qoi::QoiHeaderChannels::RGBA,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+		let mut expected = vec![];
+		qoi::Qoi.encode(pixels(&data, 4).into_iter(), header, &mut expected).expect("error?");
+		assert_eq!(out, expected);
+
+		// and a row at a time is the same as all at once
+		let mut writer = Writer::new(vec![]);
+		writer.write_header(&info).expect("error?");
+		for row in data.chunks(12) {
+			writer.write_row(row).expect("error?");
+		}
+		assert_eq!(writer.finish().expect("error?"), expected);
+	}
+
+	#[test]
+	fn writer_misuse() {
+		let info = ImageInfo {
+			width: 2,
+			height: 1,
+			channels: 3,
+			colorspace: Colorspace::SRGB,
+			format: "qoi",
+		};
+
+		let mut writer = Writer::new(vec![]);
+		assert!(matches!(writer.write_row(&[0; 6]), Err(Error::InvalidInput { .. })));
+		let result = writer.write_header(&ImageInfo { format: "nothing", ..info.clone() });
+		assert!(matches!(result, Err(Error::Unsupported { .. })), "{result:?}");
+		let result = writer.write_header(&ImageInfo { width: 0, ..info.clone() });
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 4, field: "width" })), "{result:?}");
+		let result = writer.write_header(&ImageInfo { channels: 2, ..info.clone() });
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 12, field: "channels" })), "{result:?}");
+
+		writer.write_header(&info).expect("error?");
+		assert!(matches!(writer.write_header(&info), Err(Error::InvalidInput { .. })));
+		let result = writer.write_row(&[0; 5]);
+		assert!(matches!(result, Err(Error::BufferSize { expected: 6, got: 5 })), "{result:?}");
+		writer.write_row(&[0; 6]).expect("error?");
+		let result = writer.write_row(&[0; 6]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 2, got: 4 })), "{result:?}");
+
+		let mut writer = Writer::new(vec![]);
+		writer.write_header(&ImageInfo { height: 2, ..info }).expect("error?");
+		writer.write_row(&[0; 6]).expect("error?");
+		let result = writer.finish();
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 4, got: 2 })), "{result:?}");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(data: &[u8]) -> Result<(), Error> {
+			let mut data = data;
+			let mut reader = Reader::new(&mut data)?;
+			let mut buf = vec![0; reader.output_buffer_size() as usize];
+			reader.read_image(&mut buf)
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".qoi")));
+				assert!(matches!(result, $pat), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Err(Error::InvalidMagic { offset: 0 }));
+		case!("zero_width", Err(Error::InvalidHeader { offset: 4, field: "width" }));
+		case!("zero_height", Err(Error::InvalidHeader { offset: 8, field: "height" }));
+		case!("bad_channels", Err(Error::InvalidHeader { offset: 12, field: "channels" }));
+		case!("bad_colorspace", Err(Error::InvalidHeader { offset: 13, field: "colorspace" }));
+		case!("truncated_header", Err(Error::UnexpectedEof { offset: 9 }));
+		case!("truncated_rgba", Err(Error::UnexpectedEof { offset: 17 }));
+		case!("run_overrun", Err(Error::RunOverrun { offset: 14 }));
+		case!("missing_end_marker", Err(Error::MissingEndMarker { offset: 18 }));
+		// a reader only reads one image, so what comes after it is left alone
+		case!("trailing_garbage", Ok(()));
+	}
+}
@@ -0,0 +1,574 @@
+/*!
+fits images, the primary one of a file, as long as it's 2d. there's no
+encoder.
+
+the header is 80-byte cards of text, each a keyword, and `= ` and a value
+for most, in blocks of 2880 bytes, up to `END`. it starts with `SIMPLE`,
+`BITPIX`, `NAXIS` and `NAXIS1` up to however many axes there are, in that
+order. axes past the second have to be 1. `BZERO`, `BSCALE`, `BLANK`,
+`DATAMIN` and `DATAMAX` are read too, and the rest skipped.
+
+samples are big-endian 8-bit unsigned, 16, 32 or 64-bit signed integers, or
+32 or 64-bit floats, with `NAXIS1` in a row. the first row is the bottom
+one, so they're handed out flipped, top-down. a sample's value is
+`BZERO + BSCALE * sample`, and integer samples equal to `BLANK` are nan.
+the samples are padded to a block too. extensions after them aren't read.
+
+`FitsPixels` hands out the values, and since they're flipped, reads and
+keeps all the samples before the first. `Fits::decode` maps them to gray,
+from a black point to a white point, with its `FitsRange` and `FitsScale`.
+nan is transparent.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+const BLOCK: u64 = 2880;
+const CARD: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum FitsSampleType {
+	U8,
+	I16,
+	I32,
+	I64,
+	F32,
+	F64,
+}
+
+impl FitsSampleType {
+	fn bytes(self) -> usize {
+		match self {
+			Self::U8 => 1,
+			Self::I16 => 2,
+			Self::I32 | Self::F32 => 4,
+			Self::I64 | Self::F64 => 8,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FitsHeader {
+	pub width: u32,
+	pub height: u32,
+	pub sample_type: FitsSampleType,
+	/// values are `bzero + bscale * sample`.
+	pub bzero: f64,
+	pub bscale: f64,
+	/// the integer sample that means there's no value.
+	pub blank: Option<i64>,
+	/// the smallest and largest values, as the header has them.
+	pub data_min: Option<f64>,
+	pub data_max: Option<f64>,
+}
+
+impl crate::Dimensions for FitsHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// where the black and white points are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum FitsRange {
+	/// the smallest and largest values.
+	Data,
+	/// `DATAMIN` and `DATAMAX`, or the smallest and largest values where
+	/// either is missing.
+	Header,
+	/// this fraction of the values is cut off each end, like 0.005, which
+	/// keeps a few bright stars from making everything else black.
+	Clipped(f64),
+	/// these black and white points.
+	Fixed(f64, f64),
+}
+
+/// how values between the black and white points are mapped to 8 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum FitsScale {
+	Linear,
+	Sqrt,
+	/// `log10(1000 x + 1)`, scaled to 1.
+	Log,
+	/// `asinh(10 x)`, scaled to 1.
+	Asinh,
+}
+
+impl FitsScale {
+	/// a value mapped to 8 bits, from `black` to `white`. anything out of
+	/// range is clamped, and nan is 0.
+	pub fn apply(self, x: f64, black: f64, white: f64) -> u8 {
+		let x = match white > black {
+			true => ((x - black) / (white - black)).clamp(0.0, 1.0),
+			false if x >= white => 1.0,
+			false => 0.0,
+		};
+		let x = match self {
+			Self::Linear => x,
+			Self::Sqrt => x.sqrt(),
+			Self::Log => (1000.0 * x + 1.0).log10() / 1001f64.log10(),
+			Self::Asinh => (10.0 * x).asinh() / 10f64.asinh(),
+		};
+		// nan fails every comparison, so it comes out as 0
+		if x > 0.0 { (x.min(1.0) * 255.0 + 0.5) as u8 } else { 0 }
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fits {
+	pub range: FitsRange,
+	pub scale: FitsScale,
+}
+
+impl Default for Fits {
+	fn default() -> Self {
+		Self { range: FitsRange::Data, scale: FitsScale::Linear }
+	}
+}
+
+/// a card's value, trimmed and without its comment, or none if it doesn't
+/// have one, or it's a string.
+fn value(card: &[u8]) -> Option<&str> {
+	if card[8..10] != *b"= " {
+		return None;
+	}
+	let text = std::str::from_utf8(&card[10..]).ok()?;
+	if text.trim_start().starts_with('\'') {
+		return None;
+	}
+	text.split('/').next().map(str::trim)
+}
+
+fn int(card: &[u8]) -> Option<i64> {
+	value(card)?.parse().ok()
+}
+
+/// fortran writes exponents with a `D` too.
+fn float(card: &[u8]) -> Option<f64> {
+	let x: f64 = value(card)?.replace(['D', 'd'], "E").parse().ok()?;
+	x.is_finite().then_some(x)
+}
+
+/// values of a fits image, from the top left. all of the samples are read
+/// before the first is handed out.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct FitsPixels<'a, R> {
+	header: FitsHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// the samples, from right after the header, once they've been read.
+	samples: Vec<u8>,
+	loaded: bool,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> FitsPixels<'a, R> {
+	/// reads the header, up to the samples.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut block = [0; BLOCK as usize];
+		let mut offset = 0;
+		let mut axes = 0;
+		let (mut width, mut height, mut sample_type) = (0, 0, FitsSampleType::U8);
+		let mut header = FitsHeader {
+			width: 0,
+			height: 0,
+			sample_type,
+			bzero: 0.0,
+			bscale: 1.0,
+			blank: None,
+			data_min: None,
+			data_max: None,
+		};
+		// cards so far, since the first few have to come in order
+		let mut i = 0;
+		'header: loop {
+			crate::read_exact(data, &mut block, offset)?;
+			for card in block.chunks_exact(CARD) {
+				let at = offset;
+				offset += CARD as u64;
+				let field = |field| Error::InvalidHeader { offset: at, field };
+				let keyword = std::str::from_utf8(&card[..8]).unwrap_or("").trim_end();
+				match i {
+					0 => {
+						if keyword != "SIMPLE" || card[8..10] != *b"= " {
+							return Err(Error::InvalidMagic { offset: 0 });
+						}
+						if value(card) != Some("T") {
+							return Err(field("SIMPLE"));
+						}
+					}
+					1 => {
+						sample_type = match int(card).filter(|_| keyword == "BITPIX") {
+							Some(8) => FitsSampleType::U8,
+							Some(16) => FitsSampleType::I16,
+							Some(32) => FitsSampleType::I32,
+							Some(64) => FitsSampleType::I64,
+							Some(-32) => FitsSampleType::F32,
+							Some(-64) => FitsSampleType::F64,
+							_ => return Err(field("BITPIX")),
+						};
+					}
+					2 => {
+						axes = match int(card).filter(|_| keyword == "NAXIS") {
+							Some(n @ 2..=999) => n,
+							_ => return Err(field("NAXIS")),
+						};
+					}
+					n if n < 3 + axes => {
+						let axis = n - 2;
+						let name = match axis {
+							1 => "NAXIS1",
+							2 => "NAXIS2",
+							_ => "NAXISn",
+						};
+						let len = int(card).filter(|_| keyword.strip_prefix("NAXIS") == Some(&axis.to_string()));
+						match (axis, len) {
+							(1, Some(len @ 1..=0xffff_ffff)) => width = len as u32,
+							(2, Some(len @ 1..=0xffff_ffff)) => height = len as u32,
+							(3.., Some(1)) => {}
+							_ => return Err(field(name)),
+						}
+					}
+					_ => match keyword {
+						"END" => break 'header,
+						"BZERO" => header.bzero = float(card).ok_or(field("BZERO"))?,
+						"BSCALE" => header.bscale = float(card).ok_or(field("BSCALE"))?,
+						"BLANK" => header.blank = Some(int(card).ok_or(field("BLANK"))?),
+						"DATAMIN" => header.data_min = Some(float(card).ok_or(field("DATAMIN"))?),
+						"DATAMAX" => header.data_max = Some(float(card).ok_or(field("DATAMAX"))?),
+						_ => {}
+					},
+				}
+				i += 1;
+			}
+		}
+		// the rest of the block is padding
+		let offset = offset.div_ceil(BLOCK) * BLOCK;
+
+		Ok(Self {
+			header: FitsHeader { width, height, sample_type, ..header },
+			data,
+			offset,
+			samples: vec![],
+			loaded: false,
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &FitsHeader {
+		&self.header
+	}
+
+	/// the bytes the samples take up, not counting the padding.
+	fn len(&self) -> u64 {
+		(self.header.width as u64 * self.header.height as u64).saturating_mul(self.header.sample_type.bytes() as u64)
+	}
+
+	/// reads all of the samples, a piece at a time, so memory is only taken
+	/// up once they show up.
+	fn load(&mut self) -> Result<(), Error> {
+		let len = self.len();
+		let mut done = 0;
+		while done < len {
+			let n = (len - done).min(1 << 16) as usize;
+			let at = self.samples.len();
+			self.samples.try_reserve(n).map_err(|_| oom())?;
+			self.samples.resize(at + n, 0);
+			crate::read_exact(self.data, &mut self.samples[at..], self.offset + done)?;
+			done += n as u64;
+		}
+		self.offset += len;
+		self.loaded = true;
+		Ok(())
+	}
+
+	/// reads whatever's left of the samples and their padding, and returns
+	/// the number of bytes the image took up. nothing after it is read.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		if !self.loaded {
+			self.load()?;
+		}
+		let mut padding = [0; BLOCK as usize];
+		let n = (self.offset.div_ceil(BLOCK) * BLOCK - self.offset) as usize;
+		crate::read_exact(self.data, &mut padding[..n], self.offset)?;
+		Ok(self.offset + n as u64)
+	}
+}
+
+impl<R: std::io::Read> Iterator for FitsPixels<'_, R> {
+	type Item = Result<f64, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		if !self.loaded
+			&& let Err(e) = self.load()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		// the bottom row is first
+		let (width, height) = (self.header.width as usize, self.header.height as usize);
+		let bytes = self.header.sample_type.bytes();
+		let i = ((height - 1 - self.y as usize) * width + self.x as usize) * bytes;
+		let mut b = [0; 8];
+		b[8 - bytes..].copy_from_slice(&self.samples[i..i + bytes]);
+		let sample = match self.header.sample_type {
+			FitsSampleType::U8 | FitsSampleType::I16 | FitsSampleType::I32 | FitsSampleType::I64 => {
+				// sign extended by shifting back down
+				let shift = 64 - 8 * bytes as u32;
+				let sample = match self.header.sample_type {
+					FitsSampleType::U8 => u64::from_be_bytes(b) as i64,
+					_ => (u64::from_be_bytes(b) << shift) as i64 >> shift,
+				};
+				match self.header.blank == Some(sample) {
+					true => f64::NAN,
+					false => sample as f64,
+				}
+			}
+			FitsSampleType::F32 => f32::from_bits(u64::from_be_bytes(b) as u32) as f64,
+			FitsSampleType::F64 => f64::from_bits(u64::from_be_bytes(b)),
+		};
+
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(self.header.bzero + self.header.bscale * sample))
+	}
+}
+
+impl Fits {
+	/// decodes the values, like `Format::decode`, and maps them to gray,
+	/// with nan transparent. pixels are handed out until the first error.
+	pub fn decode(self, data: &mut impl std::io::Read) -> Result<(FitsHeader, impl Iterator<Item = crate::Rgba>), Error> {
+		let mut pixels = FitsPixels::new(data)?;
+		let header = pixels.header().clone();
+		let mut values = vec![];
+		if let Some(Ok(first)) = pixels.next() {
+			// the samples have all been read by now, so this takes up no more
+			// than 8 times what they did
+			values.try_reserve_exact(header.width as usize * header.height as usize).map_err(|_| oom())?;
+			values.push(first);
+			values.extend((&mut pixels).map_while(Result::ok));
+		}
+
+		let (black, white) = self.points(&header, &values)?;
+		let scale = self.scale;
+		let pixels = values.into_iter().map(move |x| match x.is_nan() {
+			true => (0, 0, 0, 0),
+			false => {
+				let v = scale.apply(x, black, white);
+				(v, v, v, 255)
+			}
+		});
+		Ok((header, pixels))
+	}
+
+	/// the black and white points for `values`.
+	fn points(self, header: &FitsHeader, values: &[f64]) -> Result<(f64, f64), Error> {
+		let known = || values.iter().copied().filter(|x| x.is_finite());
+		let min_max = || match known().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), x| (min.min(x), max.max(x))) {
+			(min, max) if min <= max => (min, max),
+			_ => (0.0, 0.0),
+		};
+		Ok(match self.range {
+			FitsRange::Data => min_max(),
+			FitsRange::Header => match (header.data_min, header.data_max) {
+				(Some(min), Some(max)) => (min, max),
+				_ => min_max(),
+			},
+			FitsRange::Clipped(fraction) => {
+				let mut sorted = vec![];
+				sorted.try_reserve_exact(values.len()).map_err(|_| oom())?;
+				sorted.extend(known());
+				if sorted.is_empty() {
+					return Ok((0.0, 0.0));
+				}
+				sorted.sort_unstable_by(f64::total_cmp);
+				let cut = (sorted.len() as f64 * fraction.clamp(0.0, 0.5)) as usize;
+				(sorted[cut.min(sorted.len() - 1)], sorted[sorted.len() - 1 - cut.min(sorted.len() - 1)])
+			}
+			FitsRange::Fixed(black, white) => (black, white),
+		})
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, fits};
+
+	const IMAGE_U8: &[u8] = include_bytes!("../test/u8.fits");
+	const IMAGE_I16: &[u8] = include_bytes!("../test/i16.fits");
+	const IMAGE_F32: &[u8] = include_bytes!("../test/f32.fits");
+
+	fn read(image: &[u8]) -> (fits::FitsHeader, Vec<f64>) {
+		let mut data = image;
+		let mut pixels = fits::FitsPixels::new(&mut data).expect("error?");
+		let header = pixels.header().clone();
+		let values = (&mut pixels).collect::<Result<Vec<_>, _>>().expect("error?");
+		assert_eq!(values.len(), 13 * 5);
+		// not counting the extension after the first image
+		let len = if image == IMAGE_U8 { image.len() - 2880 } else { image.len() };
+		assert_eq!(pixels.finish().expect("error?"), len as u64);
+		(header, values)
+	}
+
+	#[test]
+	fn decode() {
+		for (image, sample_type, expected) in [
+			(IMAGE_U8, fits::FitsSampleType::U8, (|x, y| ((x * 7 + y * 20) & 255) as f64) as fn(i64, i64) -> f64),
+			(include_bytes!("../test/i32.fits"), fits::FitsSampleType::I32, |x, y| (x * 7 - y * 20 + 10) as f64),
+			(include_bytes!("../test/i64.fits"), fits::FitsSampleType::I64, |x, y| (x * 7 - y * 20 + 10) as f64),
+			(include_bytes!("../test/f64.fits"), fits::FitsSampleType::F64, |x, y| (x * 7 - y * 20 + 10) as f64),
+		] {
+			let (header, values) = read(image);
+			assert_eq!((header.width, header.height, header.sample_type), (13, 5, sample_type));
+			assert_eq!((header.bzero, header.bscale, header.blank), (0.0, 1.0, None));
+			// the bottom row is stored first
+			for (i, &v) in values.iter().enumerate() {
+				let (x, y) = (i as i64 % 13, i as i64 / 13);
+				assert_eq!(v, expected(x, y), "{sample_type:?}: {x}, {y}");
+			}
+		}
+	}
+
+	#[test]
+	fn decode_scaled() {
+		// a third axis of 1, a string with a / in it, and unsigned samples
+		// as signed ones with an offset
+		let (header, values) = read(IMAGE_I16);
+		assert_eq!((header.sample_type, header.bzero, header.blank), (fits::FitsSampleType::I16, 32768.0, Some(-32768)));
+		assert_eq!((header.data_min, header.data_max), (Some(100.0), Some(60000.0)));
+		for (i, &v) in values.iter().enumerate() {
+			let (x, y) = (i % 13, i / 13);
+			match (x, y) {
+				(3, 1) => assert!(v.is_nan()),
+				_ => assert_eq!(v, (x * 5000 + y * 300 + 100) as f64, "{x}, {y}"),
+			}
+		}
+
+		// with a fortran exponent
+		let (header, values) = read(IMAGE_F32);
+		assert_eq!((header.sample_type, header.bscale), (fits::FitsSampleType::F32, 5.0));
+		assert!(values[0].is_nan());
+		assert_eq!(values[64], 5e6);
+		assert_eq!(values[1..64], (1..64).map(|i| (i % 13 + i / 13 * 13) as f64 * 5.0).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn scale() {
+		for scale in [fits::FitsScale::Linear, fits::FitsScale::Sqrt, fits::FitsScale::Log, fits::FitsScale::Asinh] {
+			assert_eq!(scale.apply(-1.0, 0.0, 1.0), 0);
+			assert_eq!(scale.apply(0.0, 0.0, 1.0), 0);
+			assert_eq!(scale.apply(10.0, 0.0, 10.0), 255);
+			assert_eq!(scale.apply(f64::NAN, 0.0, 1.0), 0);
+			// brighter than linear, which is what they're for
+			assert!(scale.apply(0.1, 0.0, 1.0) >= 26, "{scale:?}");
+		}
+		assert_eq!(fits::FitsScale::Linear.apply(3.0, 2.0, 4.0), 128);
+		assert_eq!(fits::FitsScale::Sqrt.apply(0.25, 0.0, 1.0), 128);
+		// a black point at or past the white one is a threshold
+		assert_eq!(fits::FitsScale::Linear.apply(1.0, 1.0, 1.0), 255);
+		assert_eq!(fits::FitsScale::Linear.apply(0.5, 1.0, 1.0), 0);
+	}
+
+	#[test]
+	fn decode_gray() {
+		let gray = |range| {
+			let mut data = IMAGE_I16;
+			let (_, pixels) = fits::Fits { range, scale: fits::FitsScale::Linear }.decode(&mut data).expect("error?");
+			pixels.collect::<Vec<_>>()
+		};
+		let v = |x: usize, y: usize, black: f64, white: f64| {
+			let v = ((x * 5000 + y * 300 + 100) as f64 - black) / (white - black);
+			(v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+		};
+
+		for (range, black, white) in [
+			(fits::FitsRange::Data, 100.0, 61300.0),
+			(fits::FitsRange::Header, 100.0, 60000.0),
+			(fits::FitsRange::Fixed(10000.0, 20000.0), 10000.0, 20000.0),
+			// a 65th of 64 values is one off each end
+			(fits::FitsRange::Clipped(1.0 / 64.0), 400.0, 61000.0),
+		] {
+			let pixels = gray(range);
+			assert_eq!(pixels.len(), 13 * 5);
+			for (i, &px) in pixels.iter().enumerate() {
+				let (x, y) = (i % 13, i / 13);
+				match (x, y) {
+					(3, 1) => assert_eq!(px, (0, 0, 0, 0)),
+					_ => {
+						let v = v(x, y, black, white);
+						assert_eq!(px, (v, v, v, 255), "{range:?}: {x}, {y}");
+					}
+				}
+			}
+		}
+
+		// the brightest value doesn't make the rest black when it's clipped
+		let mut data = IMAGE_F32;
+		let fits = fits::Fits { range: fits::FitsRange::Clipped(0.02), scale: fits::FitsScale::Linear };
+		let (_, pixels) = fits.decode(&mut data).expect("error?");
+		let pixels = pixels.collect::<Vec<_>>();
+		assert_eq!((pixels[0], pixels[64]), ((0, 0, 0, 0), (255, 255, 255, 255)));
+		assert!(pixels[40].0 > 100);
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = fits::FitsPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".fits")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("not_simple", Error::InvalidHeader { offset: 0, .. });
+		case!("bad_bitpix", Error::InvalidHeader { offset: 80, .. });
+		case!("one_axis", Error::InvalidHeader { offset: 160, .. });
+		case!("zero_width", Error::InvalidHeader { offset: 240, .. });
+		case!("wrong_axis", Error::InvalidHeader { offset: 240, .. });
+		case!("zero_height", Error::InvalidHeader { offset: 320, .. });
+		case!("big_axis", Error::InvalidHeader { offset: 400, .. });
+		case!("bad_bzero", Error::InvalidHeader { offset: 560, .. });
+		case!("no_end", Error::UnexpectedEof { offset: 2880 });
+		case!("truncated", Error::UnexpectedEof { offset: 2910 });
+		case!("truncated_padding", Error::UnexpectedEof { offset: 2950 });
+
+		assert!(decode(IMAGE_U8).is_ok());
+		assert!(decode(IMAGE_I16).is_ok());
+	}
+}
@@ -0,0 +1,1190 @@
+/*!
+gif images and animations.
+
+`GifFrames` decodes frames one at a time, each whole, since the rows of an
+interlaced one come out of order. frames come out as they're stored, each
+covering only its own area, with its transparent pixels as transparent
+black. or they're composited onto the canvas with the last frame's disposal
+applied first, the whole canvas coming out every frame, as a player would
+show it. that takes a canvas the size of the logical screen, kept between
+frames.
+
+the canvas starts out transparent, and background disposal clears back to
+transparent too, like browsers do, rather than to the background color.
+comments and the netscape looping extension are read as they go by.
+
+images are encoded with a color table made for them by median cut, unless
+the header has one, in which case each pixel gets the nearest color in it.
+pixels less than half opaque are transparent. `encode_frames` does the same
+for each frame of an animation.
+*/
+
+mod lzw;
+mod quantize;
+
+use crate::Error;
+use crate::error::oom;
+
+/// the block that starts an extension, an image, and the end of the file.
+const EXTENSION: u8 = 0x21;
+const IMAGE: u8 = 0x2c;
+const TRAILER: u8 = 0x3b;
+
+/// extension labels.
+const GRAPHIC_CONTROL: u8 = 0xf9;
+const COMMENT: u8 = 0xfe;
+const APPLICATION: u8 = 0xff;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GifHeader {
+	/// the logical screen, which every frame goes inside.
+	pub width: u32,
+	pub height: u32,
+	/// the global color table, for frames without their own, and empty if
+	/// there isn't one.
+	pub palette: Vec<crate::Rgba>,
+	/// the global color table's index of the background color.
+	pub background: u8,
+}
+
+impl crate::Dimensions for GifHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Gif;
+
+impl Default for Gif {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// options for `Format::encode_with_options`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GifOptions {
+	/// the most colors the color table made for the image has, from 2 to
+	/// 256, a transparent one included. 256 by default. a header with a
+	/// color table of its own keeps it.
+	pub palette_size: u16,
+}
+
+impl Default for GifOptions {
+	fn default() -> Self {
+		Self { palette_size: 256 }
+	}
+}
+
+/// what happens to a frame's area before the next frame is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum GifDispose {
+	/// it's left as it is, which is also what unspecified and unknown
+	/// disposal methods do.
+	None,
+	/// it's cleared to transparent black.
+	Background,
+	/// it goes back to what it was before the frame.
+	Previous,
+}
+
+/// a frame's place in the animation, from its image descriptor and the
+/// graphic control extension before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GifFrameControl {
+	pub width: u32,
+	pub height: u32,
+	/// where the frame's top left corner goes on the canvas.
+	pub x: u32,
+	pub y: u32,
+	/// how long the frame is shown, in hundredths of a second.
+	pub delay: u16,
+	pub dispose: GifDispose,
+	/// the color index that's left transparent.
+	pub transparent: Option<u8>,
+	pub interlaced: bool,
+}
+
+impl GifFrameControl {
+	pub fn delay(&self) -> std::time::Duration {
+		std::time::Duration::from_millis(self.delay as u64 * 10)
+	}
+}
+
+/// one frame of an animation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GifFrame {
+	pub control: GifFrameControl,
+	/// the frame's own `control.width` by `control.height` pixels, or the
+	/// whole canvas once it's been drawn on, for composited frames.
+	pub image: crate::Image,
+}
+
+/// the frames of a gif, read from the reader one at a time.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct GifFrames<'a, R> {
+	header: GifHeader,
+	data: &'a mut R,
+	offset: u64,
+	metadata: crate::Metadata,
+	plays: Option<u16>,
+	/// the graphic control extension for the next image, as the delay,
+	/// dispose and transparent index.
+	control: Option<(u16, GifDispose, Option<u8>)>,
+	/// whether frames are composited, and the canvas they're composited on.
+	composite: bool,
+	canvas: Option<crate::Image>,
+	/// the last frame composited, and what its area was before it, for
+	/// frames that go back to that.
+	last: Option<(GifFrameControl, Option<crate::Image>)>,
+	done: bool,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> GifFrames<'a, R> {
+	/// reads the header and the global color table, up to the first block.
+	/// frames come out as they're stored.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; 13];
+		crate::read_exact(data, &mut buf, 0)?;
+		if &buf[..6] != b"GIF87a" && &buf[..6] != b"GIF89a" {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let width = u16::from_le_bytes([buf[6], buf[7]]);
+		let height = u16::from_le_bytes([buf[8], buf[9]]);
+		if width == 0 {
+			return Err(field(6, "width"));
+		}
+		if height == 0 {
+			return Err(field(8, "height"));
+		}
+
+		let mut offset = buf.len() as u64;
+		let palette = match buf[10] & 0x80 {
+			0 => vec![],
+			_ => table(data, &mut offset, buf[10])?,
+		};
+		Ok(Self {
+			header: GifHeader {
+				width: width as u32,
+				height: height as u32,
+				palette,
+				background: buf[11],
+			},
+			data,
+			offset,
+			metadata: crate::Metadata::new(),
+			plays: None,
+			control: None,
+			composite: false,
+			canvas: None,
+			last: None,
+			done: false,
+			failed: false,
+		})
+	}
+
+	/// like `new`, but frames come out composited onto the canvas.
+	pub fn composited(data: &'a mut R) -> Result<Self, Error> {
+		Ok(Self { composite: true, ..Self::new(data)? })
+	}
+
+	pub fn header(&self) -> &GifHeader {
+		&self.header
+	}
+
+	/// comments read so far, as `COMMENT`, joined by newlines.
+	pub fn metadata(&self) -> &crate::Metadata {
+		&self.metadata
+	}
+
+	/// times to play the animation, or 0 to loop forever, from the netscape
+	/// extension if it's been read. without one, it's played once.
+	pub fn plays(&self) -> Option<u16> {
+		self.plays
+	}
+
+	fn byte(&mut self) -> Result<u8, Error> {
+		let mut buf = [0; 1];
+		crate::read_exact(self.data, &mut buf, self.offset)?;
+		self.offset = self.offset.saturating_add(1);
+		Ok(buf[0])
+	}
+
+	/// reads a data sub-block into `buf`, and returns its length, which is 0
+	/// for the terminator.
+	fn sub_block(&mut self, buf: &mut [u8; 255]) -> Result<usize, Error> {
+		let len = self.byte()? as usize;
+		crate::read_exact(self.data, &mut buf[..len], self.offset)?;
+		self.offset = self.offset.saturating_add(len as u64);
+		Ok(len)
+	}
+
+	/// skips sub-blocks up to and including the terminator.
+	fn skip_blocks(&mut self) -> Result<(), Error> {
+		let mut buf = [0; 255];
+		while self.sub_block(&mut buf)? > 0 {}
+		Ok(())
+	}
+
+	fn extension(&mut self) -> Result<(), Error> {
+		let offset = self.offset;
+		let label = self.byte()?;
+		let mut buf = [0; 255];
+		match label {
+			GRAPHIC_CONTROL => {
+				let len = self.sub_block(&mut buf)?;
+				let &[packed, d0, d1, transparent] = &buf[..len]
+				else {
+					return Err(Error::Malformed { offset, reason: "graphic control extension isn't 4 bytes" });
+				};
+				let dispose = match (packed >> 2) & 7 {
+					2 => GifDispose::Background,
+					3 => GifDispose::Previous,
+					_ => GifDispose::None,
+				};
+				let transparent = (packed & 1 != 0).then_some(transparent);
+				self.control = Some((u16::from_le_bytes([d0, d1]), dispose, transparent));
+				self.skip_blocks()
+			}
+			COMMENT => {
+				let mut text = match self.metadata.remove(crate::metadata::COMMENT) {
+					Some(x) => x.as_bytes().to_vec(),
+					None => vec![],
+				};
+				if !text.is_empty() {
+					text.push(b'\n');
+				}
+				loop {
+					let len = self.sub_block(&mut buf)?;
+					if len == 0 {
+						break;
+					}
+					text.try_reserve(len).map_err(|_| oom())?;
+					text.extend_from_slice(&buf[..len]);
+				}
+				let text = String::from_utf8_lossy(&text).into_owned();
+				self.metadata.insert(crate::metadata::COMMENT, text);
+				Ok(())
+			}
+			// only netscape's looping is read
+			APPLICATION => {
+				let len = self.sub_block(&mut buf)?;
+				if len == 0 {
+					return Ok(());
+				}
+				let netscape = buf[..len] == *b"NETSCAPE2.0";
+				loop {
+					let len = self.sub_block(&mut buf)?;
+					match buf[..len] {
+						[] => return Ok(()),
+						[1, p0, p1] if netscape => self.plays = Some(u16::from_le_bytes([p0, p1])),
+						_ => {}
+					}
+				}
+			}
+			_ => self.skip_blocks(),
+		}
+	}
+
+	/// reads a frame, from after its image separator.
+	fn image(&mut self) -> Result<GifFrame, Error> {
+		let offset = self.offset;
+		let malformed = |reason| Error::Malformed { offset, reason };
+		let mut buf = [0; 255];
+		crate::read_exact(self.data, &mut buf[..9], self.offset)?;
+		self.offset += 9;
+		let u16_at = |at: usize| u16::from_le_bytes([buf[at], buf[at + 1]]) as u32;
+		let (delay, dispose, transparent) = self.control.take().unwrap_or((0, GifDispose::None, None));
+		let control = GifFrameControl {
+			x: u16_at(0),
+			y: u16_at(2),
+			width: u16_at(4),
+			height: u16_at(6),
+			delay,
+			dispose,
+			transparent,
+			interlaced: buf[8] & 0x40 != 0,
+		};
+		if control.width == 0 || control.height == 0 {
+			return Err(malformed("frame is empty"));
+		}
+		if control.x + control.width > self.header.width || control.y + control.height > self.header.height {
+			return Err(malformed("frame goes past the edge of the image"));
+		}
+		let local = match buf[8] & 0x80 {
+			0 => None,
+			_ => Some(table(self.data, &mut self.offset, buf[8])?),
+		};
+
+		let min_size = self.byte()?;
+		if !(1..=11).contains(&min_size) {
+			return Err(Error::Malformed { offset: self.offset - 1, reason: "lzw code size is out of range" });
+		}
+		let len = (control.width as u64 * control.height as u64).try_into().map_err(|_| oom())?;
+		let mut lzw = lzw::Lzw::new(min_size);
+		let mut indices = vec![];
+		loop {
+			let at = self.offset.saturating_add(1);
+			let n = self.sub_block(&mut buf)?;
+			if n == 0 {
+				break;
+			}
+			// the codes after the last pixel, and the end code, are skipped
+			if lzw.decode(&buf[..n], at, &mut indices, len)? {
+				self.skip_blocks()?;
+				break;
+			}
+		}
+		if indices.len() < len {
+			return Err(Error::Malformed { offset: self.offset, reason: "image data ends early" });
+		}
+
+		if control.interlaced {
+			let (width, height) = (control.width as usize, control.height as usize);
+			let mut stored = vec![];
+			stored.try_reserve_exact(len).map_err(|_| oom())?;
+			stored.extend_from_slice(&indices);
+			for (row, y) in stored.chunks_exact(width).zip(interlaced(height)) {
+				indices[y * width..(y + 1) * width].copy_from_slice(row);
+			}
+		}
+
+		let palette = local.as_ref().unwrap_or(&self.header.palette);
+		if palette.is_empty() {
+			return Err(malformed("frame has no color table"));
+		}
+		let mut pixels = vec![];
+		pixels.try_reserve_exact(len).map_err(|_| oom())?;
+		for &i in &indices {
+			pixels.push(match palette.get(i as usize) {
+				_ if Some(i) == control.transparent => (0, 0, 0, 0),
+				Some(&px) => px,
+				None => return Err(Error::Malformed { offset: self.offset, reason: "color index is past the end of the color table" }),
+			});
+		}
+		let image = crate::Image::from_pixels(control.width, control.height, pixels)?;
+		match self.composite {
+			true => self.draw(control, &indices, image),
+			false => Ok(GifFrame { control, image }),
+		}
+	}
+
+	/// undoes the last frame as its disposal says, draws `image` over the
+	/// canvas, and returns the canvas as a frame.
+	fn draw(&mut self, control: GifFrameControl, indices: &[u8], image: crate::Image) -> Result<GifFrame, Error> {
+		let (width, height) = (self.header.width, self.header.height);
+		let mut canvas = match self.canvas.take() {
+			Some(canvas) => canvas,
+			None => crate::Image::new(width, height, (0, 0, 0, 0))?,
+		};
+		if let Some((last, before)) = self.last.take() {
+			let pixels = canvas.pixels_mut();
+			match (last.dispose, before) {
+				(GifDispose::Background, _) => area(last, width).for_each(|row| pixels[row].fill((0, 0, 0, 0))),
+				(GifDispose::Previous, Some(before)) => {
+					for (row, old) in area(last, width).zip(before.rows()) {
+						pixels[row].copy_from_slice(old);
+					}
+				}
+				_ => {}
+			}
+		}
+
+		let before = match control.dispose {
+			GifDispose::Previous => Some(canvas.sub_image(control.x, control.y, control.width, control.height)?),
+			_ => None,
+		};
+		let pixels = canvas.pixels_mut();
+		let frame = image.rows().zip(indices.chunks_exact(control.width as usize));
+		for (row, (new, indices)) in area(control, width).zip(frame) {
+			for ((px, &new), &i) in pixels[row].iter_mut().zip(new).zip(indices) {
+				if Some(i) != control.transparent {
+					*px = new;
+				}
+			}
+		}
+		self.last = Some((control, before));
+		let frame = GifFrame { control, image: canvas.clone() };
+		self.canvas = Some(canvas);
+		Ok(frame)
+	}
+
+	/// reads blocks up to the next image, and decodes it.
+	fn next_frame(&mut self) -> Result<Option<GifFrame>, Error> {
+		loop {
+			let offset = self.offset;
+			match self.byte()? {
+				EXTENSION => self.extension()?,
+				IMAGE => return self.image().map(Some),
+				TRAILER => {
+					self.done = true;
+					return Ok(None);
+				}
+				_ => return Err(Error::Malformed { offset, reason: "unknown block" }),
+			}
+		}
+	}
+
+	/// skips the frames that are left, without compositing them, and returns
+	/// the number of bytes the image took up. nothing is read past the
+	/// trailer.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		self.composite = false;
+		for frame in &mut self {
+			frame?;
+		}
+		match self.done {
+			true => Ok(self.offset),
+			false => Err(Error::InvalidInput { reason: "an earlier read failed" }),
+		}
+	}
+}
+
+impl<R: std::io::Read> Iterator for GifFrames<'_, R> {
+	type Item = Result<GifFrame, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.done {
+			return None;
+		}
+		match self.next_frame() {
+			Ok(frame) => frame.map(Ok),
+			Err(e) => {
+				self.failed = true;
+				Some(Err(e))
+			}
+		}
+	}
+}
+
+/// the order interlaced rows are stored in: every 8th from 0, then every
+/// 8th from 4, every 4th from 2, and every 2nd from 1.
+fn interlaced(height: usize) -> impl Iterator<Item = usize> {
+	(0..height).step_by(8).chain((4..height).step_by(8)).chain((2..height).step_by(4)).chain((1..height).step_by(2))
+}
+
+/// the first frame on a transparent canvas, for `Format::decode`, without
+/// making the canvas. nothing comes out if it couldn't be read.
+struct Pixels {
+	width: u32,
+	at: u64,
+	total: u64,
+	frame: Option<GifFrame>,
+}
+
+impl Iterator for Pixels {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.at >= self.total {
+			return None;
+		}
+		let (x, y) = ((self.at % self.width as u64) as u32, (self.at / self.width as u64) as u32);
+		self.at += 1;
+		let frame = self.frame.as_ref()?;
+		// left of or above the frame wraps around to past its edge
+		let (fx, fy) = (x.wrapping_sub(frame.control.x), y.wrapping_sub(frame.control.y));
+		Some(frame.image.get_pixel(fx, fy).unwrap_or((0, 0, 0, 0)))
+	}
+}
+
+/// the canvas pixels a frame covers, a row at a time, for a canvas `width`
+/// pixels wide.
+fn area(control: GifFrameControl, width: u32) -> impl Iterator<Item = std::ops::Range<usize>> {
+	let (x, y) = (control.x as usize, control.y as usize);
+	(0..control.height as usize).map(move |row| {
+		let start = (y + row) * width as usize + x;
+		start..start + control.width as usize
+	})
+}
+
+/// reads a color table of the size in `packed`'s low bits.
+fn table(data: &mut impl std::io::Read, offset: &mut u64, packed: u8) -> Result<Vec<crate::Rgba>, Error> {
+	let mut buf = [0; 256 * 3];
+	let buf = &mut buf[..3 << ((packed & 7) + 1)];
+	crate::read_exact(data, buf, *offset)?;
+	*offset += buf.len() as u64;
+	Ok(buf.chunks_exact(3).map(|c| (c[0], c[1], c[2], 255)).collect())
+}
+
+/// the low bits of a color table's packed field, for a table of `len`
+/// colors. the table holds `2 << bits`.
+fn table_bits(len: usize) -> u8 {
+	(0..7).find(|&bits| 2 << bits >= len).unwrap_or(7)
+}
+
+/// writes `palette`, padded with black to the size its packed field says.
+fn write_table(out: &mut impl std::io::Write, palette: &[crate::Rgba]) -> Result<(), Error> {
+	let mut buf = [0; 256 * 3];
+	for (c, px) in buf.chunks_exact_mut(3).zip(palette) {
+		c.copy_from_slice(&[px.0, px.1, px.2]);
+	}
+	crate::write_all(out, &buf[..3 << (table_bits(palette.len()) + 1)])
+}
+
+/// writes `data` as sub-blocks, and the terminator.
+fn write_blocks(out: &mut impl std::io::Write, data: &[u8]) -> Result<(), Error> {
+	for chunk in data.chunks(255) {
+		crate::write_all(out, &[chunk.len() as u8])?;
+		crate::write_all(out, chunk)?;
+	}
+	crate::write_all(out, &[0])
+}
+
+/// writes the header, and the global color table if there is one.
+fn write_header(header: &GifHeader, out: &mut impl std::io::Write) -> Result<(), Error> {
+	let field = |offset, field| Error::InvalidHeader { offset, field };
+	if header.width == 0 || header.width > u16::MAX as u32 {
+		return Err(field(6, "width"));
+	}
+	if header.height == 0 || header.height > u16::MAX as u32 {
+		return Err(field(8, "height"));
+	}
+	if header.palette.len() > 256 {
+		return Err(field(10, "color table size"));
+	}
+
+	let mut buf = [0; 13];
+	buf[..6].copy_from_slice(b"GIF89a");
+	buf[6..8].copy_from_slice(&(header.width as u16).to_le_bytes());
+	buf[8..10].copy_from_slice(&(header.height as u16).to_le_bytes());
+	if !header.palette.is_empty() {
+		// 8 bits of color resolution
+		buf[10] = 0x80 | 7 << 4 | table_bits(header.palette.len());
+	}
+	buf[11] = header.background;
+	crate::write_all(out, &buf)?;
+	if !header.palette.is_empty() {
+		write_table(out, &header.palette)?;
+	}
+	Ok(())
+}
+
+/// writes a frame of `pixels`, with a color table of its own of at most
+/// `size` colors unless `global` has colors, and a graphic control extension
+/// unless it'd say nothing.
+fn write_frame(out: &mut impl std::io::Write, control: &GifFrameControl, pixels: &[crate::Rgba], global: &[crate::Rgba], size: usize) -> Result<(), Error> {
+	let local = match global.is_empty() {
+		true => quantize::median_cut(pixels, size)?,
+		false => vec![],
+	};
+	let palette = if local.is_empty() { global } else { &local };
+	let (mut indices, transparent) = quantize::map(pixels, palette)?;
+
+	if control.delay > 0 || control.dispose != GifDispose::None || transparent.is_some() {
+		let dispose = match control.dispose {
+			GifDispose::None => 1,
+			GifDispose::Background => 2,
+			GifDispose::Previous => 3,
+		};
+		let [d0, d1] = control.delay.to_le_bytes();
+		let packed = dispose << 2 | transparent.is_some() as u8;
+		crate::write_all(out, &[EXTENSION, GRAPHIC_CONTROL, 4, packed, d0, d1, transparent.unwrap_or(0), 0])?;
+	}
+
+	let mut buf = [0; 10];
+	buf[0] = IMAGE;
+	for (at, x) in [control.x, control.y, control.width, control.height].into_iter().enumerate() {
+		buf[1 + at * 2..3 + at * 2].copy_from_slice(&(x as u16).to_le_bytes());
+	}
+	if !local.is_empty() {
+		buf[9] = 0x80 | table_bits(local.len());
+	}
+	if control.interlaced {
+		buf[9] |= 0x40;
+		let width = control.width as usize;
+		let mut stored = vec![];
+		stored.try_reserve_exact(indices.len()).map_err(|_| oom())?;
+		for y in interlaced(control.height as usize) {
+			stored.extend_from_slice(&indices[y * width..(y + 1) * width]);
+		}
+		indices = stored;
+	}
+	crate::write_all(out, &buf)?;
+	if !local.is_empty() {
+		write_table(out, &local)?;
+	}
+
+	let min_size = (table_bits(palette.len()) + 1).max(2);
+	let mut data = vec![];
+	lzw::encode(&indices, min_size, &mut data)?;
+	crate::write_all(out, &[min_size])?;
+	write_blocks(out, &data)
+}
+
+fn write_comment(out: &mut impl std::io::Write, text: &str) -> Result<(), Error> {
+	crate::write_all(out, &[EXTENSION, COMMENT])?;
+	write_blocks(out, text.as_bytes())
+}
+
+/// encodes an animation of `frames` on a canvas the size of `header`'s. each
+/// frame's image is the size of its own area, which has to be inside the
+/// canvas. frames get color tables of their own unless `header` has one.
+/// which pixels are transparent comes from their alpha, so the controls'
+/// `transparent` is ignored.
+///
+/// `plays` is written in a netscape extension, with 0 looping forever.
+/// without one, players play the animation once.
+pub fn encode_frames(frames: impl IntoIterator<Item = GifFrame>, header: &GifHeader, plays: Option<u16>, out: &mut impl std::io::Write) -> Result<(), Error> {
+	write_header(header, out)?;
+	if let Some(plays) = plays {
+		let [p0, p1] = plays.to_le_bytes();
+		crate::write_all(out, &[EXTENSION, APPLICATION, 11])?;
+		crate::write_all(out, b"NETSCAPE2.0")?;
+		crate::write_all(out, &[3, 1, p0, p1, 0])?;
+	}
+	for frame in frames {
+		let control = &frame.control;
+		if (frame.image.width(), frame.image.height()) != (control.width, control.height) {
+			return Err(Error::InvalidInput { reason: "frame's image isn't the size of its area" });
+		}
+		if control.width == 0 || control.height == 0 {
+			return Err(Error::InvalidInput { reason: "frame is empty" });
+		}
+		if control.x as u64 + control.width as u64 > header.width as u64 || control.y as u64 + control.height as u64 > header.height as u64 {
+			return Err(Error::InvalidInput { reason: "frame goes past the edge of the image" });
+		}
+		write_frame(out, control, frame.image.pixels(), &header.palette, 256)?;
+	}
+	crate::write_all(out, &[TRAILER])
+}
+
+/// push-based encoder state, which `Gif::encode` drives. the pixels are kept
+/// until the end, since the color table is made from all of them.
+#[derive(Debug)]
+pub(crate) struct GifEncoder<W> {
+	out: W,
+	width: u32,
+	palette: Vec<crate::Rgba>,
+	/// the most colors a color table made for the pixels can have.
+	size: usize,
+	pixels: Vec<crate::Rgba>,
+}
+
+impl<W: std::io::Write> GifEncoder<W> {
+	/// writes the header and any global color table.
+	pub(crate) fn new(header: &GifHeader, mut out: W) -> Result<Self, Error> {
+		write_header(header, &mut out)?;
+		Ok(Self {
+			out,
+			width: header.width,
+			palette: header.palette.clone(),
+			size: 256,
+			pixels: vec![],
+		})
+	}
+
+	/// writes the comment, if there is one.
+	pub(crate) fn text(&mut self, metadata: &crate::Metadata) -> Result<(), Error> {
+		match metadata.get_text(crate::metadata::COMMENT) {
+			Some(text) => write_comment(&mut self.out, text),
+			None => Ok(()),
+		}
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		self.pixels.try_reserve(1).map_err(|_| oom())?;
+		self.pixels.push(px);
+		Ok(())
+	}
+
+	/// writes the image and the trailer. a row that didn't get all its pixels
+	/// is transparent after them, and rows that didn't get any are left off
+	/// the bottom of the image.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		let width = self.width as usize;
+		let rows = self.pixels.len().div_ceil(width);
+		if rows > 0 {
+			self.pixels.resize(rows * width, (0, 0, 0, 0));
+			let control = GifFrameControl {
+				width: self.width,
+				height: rows as u32,
+				x: 0,
+				y: 0,
+				delay: 0,
+				dispose: GifDispose::None,
+				transparent: None,
+				interlaced: false,
+			};
+			write_frame(&mut self.out, &control, &self.pixels, &self.palette, self.size)?;
+		}
+		self.pixels.clear();
+		crate::write_all(&mut self.out, &[TRAILER])
+	}
+}
+
+impl crate::Format for Gif {
+	type Header = GifHeader;
+	type Options = GifOptions;
+
+	/// the first frame, composited onto the canvas.
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let (header, _, pixels) = self.decode_with_metadata(data)?;
+		Ok((header, pixels))
+	}
+
+	/// the logical screen and global color table, without the first frame.
+	fn decode_header(self, data: &mut impl std::io::Read) -> Result<Self::Header, Error> {
+		Ok(GifFrames::new(data)?.header().clone())
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		self.encode_with_metadata(data, header, &crate::Metadata::new(), out)
+	}
+
+	/// the comments before the first frame are read.
+	fn decode_with_metadata(self, data: &mut impl std::io::Read) -> Result<(Self::Header, crate::Metadata, impl Iterator<Item = crate::Rgba>), Error> {
+		let mut frames = GifFrames::new(data)?;
+		let frame = frames.next().transpose()?;
+		let header = frames.header().clone();
+		let pixels = Pixels {
+			width: header.width,
+			at: 0,
+			total: if frame.is_some() { header.width as u64 * header.height as u64 } else { 0 },
+			frame,
+		};
+		Ok((header, frames.metadata().clone(), pixels))
+	}
+
+	/// the comment goes in a comment extension, before the image.
+	fn encode_with_metadata(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, metadata: &crate::Metadata, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = GifEncoder::new(&header, out)?;
+		encoder.text(metadata)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+
+	fn encode_with_options(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, options: &GifOptions, out: &mut impl std::io::Write) -> Result<(), Error> {
+		if !(2..=256).contains(&options.palette_size) {
+			return Err(Error::InvalidInput { reason: "palette size isn't from 2 to 256" });
+		}
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = GifEncoder::new(&header, out)?;
+		encoder.size = options.palette_size as usize;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+impl<R: std::io::Read> crate::animation::Frames for GifFrames<'_, R> {
+	/// frames are decoded whole, so their pixels are already there. the
+	/// transparent ones are transparent black, drawn over the canvas.
+	fn next_frame(&mut self) -> Result<Option<(crate::animation::Frame, impl Iterator<Item = Result<crate::Rgba, Error>> + '_)>, Error> {
+		use crate::animation::{Blend, Dispose, Frame};
+
+		let Some(frame) = self.next().transpose()? else {
+			return Ok(None);
+		};
+		let control = frame.control;
+		let info = Frame {
+			width: control.width,
+			height: control.height,
+			x: control.x,
+			y: control.y,
+			delay: control.delay(),
+			dispose: match control.dispose {
+				GifDispose::None => Dispose::None,
+				GifDispose::Background => Dispose::Background,
+				GifDispose::Previous => Dispose::Previous,
+			},
+			blend: Blend::Over,
+		};
+		Ok(Some((info, frame.image.into_pixels().into_iter().map(Ok))))
+	}
+}
+
+impl crate::AnimatedFormat for Gif {
+	fn decode_frames(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl crate::animation::Frames), Error> {
+		let frames = GifFrames::new(data)?;
+		Ok((frames.header().clone(), frames))
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format};
+	use crate::gif::{self, GifDispose};
+
+	const IMAGE_STILL: &[u8] = include_bytes!("../test/still.gif");
+	const IMAGE_STILL_87: &[u8] = include_bytes!("../test/still87.gif");
+	const IMAGE_INTERLACED: &[u8] = include_bytes!("../test/interlaced.gif");
+	const IMAGE_NOISE: &[u8] = include_bytes!("../test/noise.gif");
+	const IMAGE_NOISE_DEFERRED: &[u8] = include_bytes!("../test/noise_deferred.gif");
+	const IMAGE_ANIMATED: &[u8] = include_bytes!("../test/animated.gif");
+
+	/// the colors the fixtures' tables were made with.
+	fn color(i: u32) -> crate::Rgba {
+		((i * 16) as u8, 255u32.wrapping_sub(i * 8) as u8, (i * 3) as u8, 255)
+	}
+
+	fn frames(mut data: &[u8]) -> Vec<gif::GifFrame> {
+		let frames = gif::GifFrames::new(&mut data).expect("error?");
+		frames.map(|x| x.expect("error?")).collect()
+	}
+
+	#[test]
+	fn still() {
+		for image in [IMAGE_STILL, IMAGE_STILL_87] {
+			let mut data = image;
+			let mut frames = gif::GifFrames::new(&mut data).expect("error?");
+			assert_eq!((frames.header().width, frames.header().height), (13, 5));
+			assert_eq!(frames.header().palette.len(), 16);
+
+			let frame = frames.next().expect("frame?").expect("error?");
+			assert_eq!((frame.control.width, frame.control.height, frame.control.x, frame.control.y), (13, 5, 0, 0));
+			assert_eq!((frame.control.delay, frame.control.dispose, frame.control.transparent), (0, GifDispose::None, None));
+			for (i, &px) in frame.image.pixels().iter().enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				assert_eq!(px, color((x * 7 + y * 3) % 16), "{x}, {y}");
+			}
+			assert!(frames.next().is_none());
+			assert_eq!(frames.plays(), None);
+			assert_eq!(frames.finish().expect("error?"), image.len() as u64);
+		}
+
+		// comments in separate extensions are joined, around an extension
+		// that's skipped
+		let mut data = IMAGE_STILL;
+		let mut frames = gif::GifFrames::new(&mut data).expect("error?");
+		frames.next().expect("frame?").expect("error?");
+		assert_eq!(frames.metadata().get_text(crate::metadata::COMMENT), Some("a comment\nand another"));
+
+		// the screen and the global color table, and nothing after
+		let mut data = IMAGE_STILL;
+		let header = crate::Format::decode_header(gif::Gif, &mut data).expect("error?");
+		assert_eq!((header.width, header.height, header.palette.len()), (13, 5, 16));
+		assert_eq!(data.len(), IMAGE_STILL.len() - 13 - 16 * 3);
+	}
+
+	#[test]
+	fn interlaced() {
+		let frames = frames(IMAGE_INTERLACED);
+		assert_eq!(frames.len(), 1);
+		assert!(frames[0].control.interlaced);
+		for (i, &px) in frames[0].image.pixels().iter().enumerate() {
+			let (x, y) = (i as u32 % 11, i as u32 / 11);
+			assert_eq!(px, color((x + y * 3) % 8 * 2), "{x}, {y}");
+		}
+	}
+
+	#[test]
+	fn full_table() {
+		// the table fills up, and is either cleared or left as it is
+		for image in [IMAGE_NOISE, IMAGE_NOISE_DEFERRED] {
+			let frames = frames(image);
+			for (i, &px) in frames[0].image.pixels().iter().enumerate() {
+				let (x, y) = (i as u32 % 100, i as u32 / 100);
+				assert_eq!(px, color((x * x * 7 + y * 13 + x * y * 5 + (x ^ y)) % 256), "{x}, {y}");
+			}
+		}
+	}
+
+	/// the fixture's frames, as width, height, x, y, and color indices.
+	const AREAS: [(u32, u32, u32, u32); 4] = [(8, 6, 0, 0), (4, 3, 2, 1), (5, 2, 3, 4), (2, 2, 0, 0)];
+
+	/// the pixels of the fixture's frames, before compositing.
+	fn raw(n: usize, x: u32, y: u32) -> crate::Rgba {
+		let i = match n {
+			0 => (x + y) % 8,
+			1 => (x + y) % 3,
+			2 => (x * y) % 4,
+			_ => [1, 2, 3, 1][(y * 2 + x) as usize],
+		};
+		match (n, i) {
+			(1, 0) => (0, 0, 0, 0),
+			_ => ((n as u32 * 60 + i * 10) as u8, (i * 30) as u8, (255 - n as u32 * 40) as u8, 255),
+		}
+	}
+
+	#[test]
+	fn animated() {
+		let mut data = IMAGE_ANIMATED;
+		let mut frames = gif::GifFrames::new(&mut data).expect("error?");
+		assert_eq!((frames.header().width, frames.header().height), (8, 6));
+
+		let expected = [
+			(10, GifDispose::None, None, false),
+			(20, GifDispose::Background, Some(0), false),
+			(30, GifDispose::Previous, None, true),
+			(0, GifDispose::None, None, false),
+		];
+		for (n, (delay, dispose, transparent, interlaced)) in expected.into_iter().enumerate() {
+			let frame = frames.next().expect("frame?").expect("error?");
+			let (width, height, x, y) = AREAS[n];
+			let control = frame.control;
+			assert_eq!((control.width, control.height, control.x, control.y), (width, height, x, y));
+			assert_eq!((control.dispose, control.transparent, control.interlaced), (dispose, transparent, interlaced));
+			assert_eq!(control.delay(), std::time::Duration::from_millis(delay as u64 * 10));
+			for (i, &px) in frame.image.pixels().iter().enumerate() {
+				assert_eq!(px, raw(n, i as u32 % width, i as u32 / width), "{n}: {i}");
+			}
+		}
+		assert!(frames.next().is_none());
+		assert_eq!(frames.plays(), Some(0));
+		assert_eq!(frames.metadata().get_text(crate::metadata::COMMENT), Some("between frames"));
+		assert_eq!(frames.finish().expect("error?"), IMAGE_ANIMATED.len() as u64);
+	}
+
+	#[test]
+	fn animated_format() {
+		use crate::animation::{Blend, Dispose, Frames};
+		use crate::AnimatedFormat;
+
+		let mut data = IMAGE_ANIMATED;
+		let (header, mut frames) = gif::Gif.decode_frames(&mut data).expect("error?");
+		assert_eq!((header.width, header.height), (8, 6));
+		let native = self::frames(IMAGE_ANIMATED);
+		for (n, expected) in native.iter().enumerate() {
+			let (frame, pixels) = frames.next_frame().expect("error?").expect("frame?");
+			let (width, height, x, y) = AREAS[n];
+			assert_eq!((frame.width, frame.height, frame.x, frame.y), (width, height, x, y));
+			assert_eq!((frame.delay, frame.blend), (expected.control.delay(), Blend::Over));
+			assert_eq!(frame.dispose, [Dispose::None, Dispose::Background, Dispose::Previous, Dispose::None][n]);
+			assert_eq!(pixels.collect::<Result<Vec<_>, _>>().expect("error?"), expected.image.pixels());
+		}
+		assert!(frames.next_frame().expect("error?").is_none());
+
+		let mut data = &IMAGE_ANIMATED[..IMAGE_ANIMATED.len() - 20];
+		let (_, mut frames) = gif::Gif.decode_frames(&mut data).expect("error?");
+		let result = loop {
+			match frames.next_frame() {
+				Ok(Some(_)) => {}
+				result => break result.map(|x| x.is_some()),
+			}
+		};
+		assert!(matches!(result, Err(Error::UnexpectedEof { .. } | Error::Malformed { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn composited() {
+		// the second frame is partly transparent and cleared after, and the
+		// third is put back to what was under it
+		let canvas = |n: usize, x: u32, y: u32| {
+			let on = |f: usize| {
+				let (width, height, fx, fy) = AREAS[f];
+				(fx..fx + width).contains(&x) && (fy..fy + height).contains(&y)
+			};
+			let own = |f: usize| raw(f, x - AREAS[f].2, y - AREAS[f].3);
+			match n {
+				_ if on(n) && own(n).3 > 0 => own(n),
+				1 => raw(0, x, y),
+				_ if on(1) => (0, 0, 0, 0),
+				_ => raw(0, x, y),
+			}
+		};
+
+		let mut data = IMAGE_ANIMATED;
+		let mut frames = gif::GifFrames::composited(&mut data).expect("error?");
+		for (n, &(_, _, x, y)) in AREAS.iter().enumerate() {
+			let frame = frames.next().expect("frame?").expect("error?");
+			assert_eq!((frame.control.x, frame.control.y), (x, y));
+			assert_eq!((frame.image.width(), frame.image.height()), (8, 6));
+			for (i, &px) in frame.image.pixels().iter().enumerate() {
+				assert_eq!(px, canvas(n, i as u32 % 8, i as u32 / 8), "{n}: {i}");
+			}
+		}
+		assert!(frames.next().is_none());
+
+		// what's left is skipped without compositing
+		let mut data = IMAGE_ANIMATED;
+		let mut frames = gif::GifFrames::composited(&mut data).expect("error?");
+		frames.next().expect("frame?").expect("error?");
+		assert_eq!(frames.finish().expect("error?"), IMAGE_ANIMATED.len() as u64);
+	}
+
+	#[test]
+	fn encode() {
+		// few enough colors for the table to hold them all, some of them
+		// transparent, and a row that isn't finished
+		let (width, height) = (13, 5);
+		let px = |i: u32| match i % 7 {
+			0 => (0, 0, 0, (i * 3) as u8 & 0x7f),
+			_ => ((i * 3) as u8, (i * 40) as u8, i as u8 ^ 0x55, 255),
+		};
+		let header = gif::GifHeader { width, height, palette: vec![], background: 0 };
+		let mut text = crate::Metadata::new();
+		text.insert(crate::metadata::COMMENT, "made in a test");
+		let mut out = vec![];
+		gif::Gif.encode_with_metadata((0..width * height).map(px), header.clone(), &text, &mut out).expect("error?");
+
+		let mut data = &out[..];
+		let (decoded, metadata, pixels) = gif::Gif.decode_with_metadata(&mut data).expect("error?");
+		assert_eq!((decoded.width, decoded.height, decoded.palette.len()), (width, height, 0));
+		assert_eq!(metadata.get_text(crate::metadata::COMMENT), Some("made in a test"));
+		for (i, a) in pixels.enumerate() {
+			let b = match px(i as u32) {
+				(.., 255) => px(i as u32),
+				_ => (0, 0, 0, 0),
+			};
+			assert_eq!(a, b, "{i}");
+		}
+
+		// too few pixels
+		let result = gif::Gif.encode((0..width * 2 + 3).map(px), header.clone(), &mut vec![]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 65, got: 29 })), "{result:?}");
+
+		// a table that fills, more than once
+		let mut data = IMAGE_NOISE;
+		let (header, pixels) = gif::Gif.decode(&mut data).expect("error?");
+		let pixels = pixels.collect::<Vec<_>>();
+		let mut out = vec![];
+		gif::Gif.encode(pixels.iter().cloned(), header.clone(), &mut out).expect("error?");
+		let mut data = &out[..];
+		let (decoded, again) = gif::Gif.decode(&mut data).expect("error?");
+		assert_eq!(decoded, header);
+		assert!(again.eq(pixels));
+
+		// with a table in the header, pixels get the nearest color in it
+		let palette = vec![(0, 0, 0, 255), (255, 255, 255, 255), (255, 0, 0, 255), (9, 9, 9, 0)];
+		let header = gif::GifHeader { width: 4, height: 1, palette: palette.clone(), background: 1 };
+		let mut out = vec![];
+		gif::Gif.encode([(10, 10, 10, 255), (250, 200, 200, 255), (200, 40, 0, 255), (255, 255, 255, 0)].into_iter(), header.clone(), &mut out).expect("error?");
+		let mut data = &out[..];
+		let (decoded, pixels) = gif::Gif.decode(&mut data).expect("error?");
+		assert_eq!(decoded, gif::GifHeader { palette: vec![palette[0], palette[1], palette[2], (9, 9, 9, 255)], ..header.clone() });
+		assert!(pixels.eq([palette[0], palette[1], palette[2], (0, 0, 0, 0)]));
+
+		// too big for the format
+		let header = gif::GifHeader { width: 1 << 16, ..header };
+		let result = gif::GifEncoder::new(&header, vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 6, .. })), "{result:?}");
+		let header = gif::GifHeader { width: 4, palette: vec![(0, 0, 0, 255); 257], ..header };
+		let result = gif::GifEncoder::new(&header, vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 10, .. })), "{result:?}");
+	}
+
+	#[test]
+	fn quantize() {
+		// more colors than fit, which come out close
+		let (width, height) = (64, 64);
+		let px = |i: u32| ((i % 64 * 4) as u8, (i / 64 * 4) as u8, ((i % 64 + i / 64) * 2) as u8, 255);
+		let header = gif::GifHeader { width, height, palette: vec![], background: 0 };
+		let mut out = vec![];
+		gif::Gif.encode((0..width * height).map(px), header, &mut out).expect("error?");
+
+		let mut data = &out[..];
+		let mut frames = gif::GifFrames::new(&mut data).expect("error?");
+		let frame = frames.next().expect("frame?").expect("error?");
+		let colors = frame.image.pixels().iter().collect::<std::collections::HashSet<_>>();
+		assert_eq!(colors.len(), 256);
+		let mut total = 0;
+		for (i, &a) in frame.image.pixels().iter().enumerate() {
+			let b = px(i as u32);
+			let d = |a: u8, b: u8| (a as i32 - b as i32).abs();
+			let error = d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2);
+			assert!(error < 48, "{i}: {a:?}, {b:?}");
+			total += error;
+		}
+		assert!(total / ((width * height) as i32) < 16, "{total}");
+
+		// as many colors as fit are kept as they are
+		let mut pixels = vec![(0, 0, 0, 255); 300];
+		pixels.extend((0..=255).map(|i| (i, 0, 0, 255)));
+		let mut palette = super::quantize::median_cut(&pixels, 256).expect("error?");
+		palette.sort();
+		assert!(palette.into_iter().eq((0..=255).map(|i| (i, 0, 0, 255))));
+
+		// all transparent
+		let palette = super::quantize::median_cut(&[(1, 2, 3, 0); 4], 256).expect("error?");
+		assert_eq!(palette, [(0, 0, 0, 0)]);
+
+		// a smaller table, as an option, which a transparent color counts
+		// towards
+		let mut data = IMAGE_NOISE;
+		let (header, pixels) = gif::Gif.decode(&mut data).expect("error?");
+		let header = gif::GifHeader { palette: vec![], ..header };
+		let mut pixels = pixels.collect::<Vec<_>>();
+		pixels[0] = (0, 0, 0, 0);
+		for size in [2, 16, 256] {
+			let mut out = vec![];
+			let options = gif::GifOptions { palette_size: size };
+			gif::Gif.encode_with_options(pixels.iter().copied(), header.clone(), &options, &mut out).expect("error?");
+			let mut data = &out[..];
+			let (_, decoded) = gif::Gif.decode(&mut data).expect("error?");
+			let colors = decoded.collect::<std::collections::HashSet<_>>();
+			assert!(colors.len() <= size as usize && colors.len() > 1 && colors.contains(&(0, 0, 0, 0)), "{size}: {}", colors.len());
+		}
+		assert_eq!(gif::GifOptions::default().palette_size, 256);
+		for size in [0, 1, 257] {
+			let options = gif::GifOptions { palette_size: size };
+			let result = gif::Gif.encode_with_options(pixels.iter().copied(), header.clone(), &options, &mut vec![]);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+		}
+	}
+
+	#[test]
+	fn encode_frames() {
+		let raw = frames(IMAGE_ANIMATED);
+		let header = gif::GifHeader { width: 8, height: 6, palette: vec![], background: 0 };
+		let mut out = vec![];
+		gif::encode_frames(raw.iter().cloned(), &header, Some(3), &mut out).expect("error?");
+
+		let mut data = &out[..];
+		let mut frames = gif::GifFrames::new(&mut data).expect("error?");
+		for (a, b) in (&mut frames).zip(&raw) {
+			let a = a.expect("error?");
+			assert_eq!(a.image, b.image);
+			assert_eq!(gif::GifFrameControl { transparent: None, ..a.control }, gif::GifFrameControl { transparent: None, ..b.control });
+		}
+		assert_eq!(frames.plays(), Some(3));
+		assert_eq!(frames.finish().expect("error?"), out.len() as u64);
+
+		// frames have to fit their area, and the canvas
+		let frame = raw[1].clone();
+		let outside = gif::GifFrame { control: gif::GifFrameControl { x: 5, ..frame.control }, ..frame.clone() };
+		let result = gif::encode_frames([outside], &header, None, &mut vec![]);
+		assert!(matches!(result, Err(Error::InvalidInput { reason: "frame goes past the edge of the image" })), "{result:?}");
+		let wrong = gif::GifFrame { control: gif::GifFrameControl { width: 3, ..frame.control }, ..frame };
+		let result = gif::encode_frames([wrong], &header, None, &mut vec![]);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut frames = gif::GifFrames::composited(&mut data)?;
+			for frame in &mut frames {
+				frame?;
+			}
+			frames.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".gif")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("zero_width", Error::InvalidHeader { offset: 6, .. });
+		case!("frame_outside", Error::Malformed { offset: 62, reason: "frame goes past the edge of the image" });
+		case!("empty_frame", Error::Malformed { reason: "frame is empty", .. });
+		case!("bad_code", Error::Malformed { reason: "lzw code isn't in the table", .. });
+		case!("bad_code_size", Error::Malformed { offset: 71, reason: "lzw code size is out of range" });
+		case!("ends_early", Error::Malformed { reason: "image data ends early", .. });
+		case!("no_color_table", Error::Malformed { reason: "frame has no color table", .. });
+		case!("bad_color_index", Error::Malformed { reason: "color index is past the end of the color table", .. });
+		case!("unknown_block", Error::Malformed { offset: 61, reason: "unknown block" });
+		case!("bad_graphic_control", Error::Malformed { reason: "graphic control extension isn't 4 bytes", .. });
+		case!("truncated", Error::UnexpectedEof { offset: 100 });
+		case!("no_trailer", Error::UnexpectedEof { offset: 154 });
+
+		assert!(decode(IMAGE_STILL).is_ok());
+	}
+}
@@ -0,0 +1,233 @@
+/*!
+lzw, as gif uses it. codes are 3 to 12 bits, packed from the low bits,
+and start a bit wider than the image's minimum code size, growing as the
+table fills. the table stops growing at 4096 entries, until the next clear
+code.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+/// the most entries in the table, and so the widest code.
+const TABLE_SIZE: usize = 4096;
+const CODE_MAX: u8 = 12;
+
+#[derive(Debug)]
+pub(super) struct Lzw {
+	min_size: u8,
+	/// bits in the next code.
+	size: u8,
+	/// the entry each entry extends, the byte it adds, and the first byte of
+	/// the whole thing.
+	prefix: [u16; TABLE_SIZE],
+	suffix: [u8; TABLE_SIZE],
+	first: [u8; TABLE_SIZE],
+	/// the next entry to be added.
+	next: u16,
+	/// the last code, which the next one's entry extends.
+	prev: Option<u16>,
+	/// bits that haven't made a whole code yet.
+	bits: u32,
+	count: u8,
+	/// whether the end code has been reached.
+	ended: bool,
+	/// an entry's bytes, last first.
+	stack: Vec<u8>,
+}
+
+impl Lzw {
+	/// `min_size` is 1 to 11.
+	pub(super) fn new(min_size: u8) -> Self {
+		let mut lzw = Self {
+			min_size,
+			size: min_size + 1,
+			prefix: [0; TABLE_SIZE],
+			suffix: [0; TABLE_SIZE],
+			first: [0; TABLE_SIZE],
+			next: 0,
+			prev: None,
+			bits: 0,
+			count: 0,
+			ended: false,
+			stack: Vec::with_capacity(TABLE_SIZE),
+		};
+		for i in 0..1 << min_size {
+			lzw.suffix[i] = i as u8;
+			lzw.first[i] = i as u8;
+		}
+		lzw.clear();
+		lzw
+	}
+
+	fn clear(&mut self) {
+		self.size = self.min_size + 1;
+		self.next = (1 << self.min_size) + 2;
+		self.prev = None;
+	}
+
+	/// decodes the codes in `input`, which starts at `offset` in the stream,
+	/// appending to `out` until it holds `limit` bytes. returns whether
+	/// that's as far as it goes, either from the end code or from `out`
+	/// being full.
+	pub(super) fn decode(&mut self, input: &[u8], offset: u64, out: &mut Vec<u8>, limit: usize) -> Result<bool, Error> {
+		for (i, &byte) in input.iter().enumerate() {
+			self.bits |= (byte as u32) << self.count;
+			self.count += 8;
+			while self.count >= self.size {
+				let code = (self.bits & ((1 << self.size) - 1)) as u16;
+				self.bits >>= self.size;
+				self.count -= self.size;
+				let offset = offset.saturating_add(i as u64);
+				self.code(code, offset, out, limit)?;
+				if self.ended || out.len() >= limit {
+					return Ok(true);
+				}
+			}
+		}
+		Ok(false)
+	}
+
+	fn code(&mut self, code: u16, offset: u64, out: &mut Vec<u8>, limit: usize) -> Result<(), Error> {
+		let clear = 1 << self.min_size;
+		if code == clear {
+			self.clear();
+			return Ok(());
+		}
+		if code == clear + 1 {
+			self.ended = true;
+			return Ok(());
+		}
+
+		let malformed = Error::Malformed { offset, reason: "lzw code isn't in the table" };
+		match self.prev {
+			None if code >= clear => return Err(malformed),
+			None => {}
+			Some(prev) => {
+				// the entry being added is the last code's, and the first byte
+				// of this one's, which for a code that's only now being added
+				// is the first byte of the last code's
+				let byte = match code {
+					_ if code < self.next => self.first[code as usize],
+					_ if code == self.next => self.first[prev as usize],
+					_ => return Err(malformed),
+				};
+				let next = self.next as usize;
+				if next < TABLE_SIZE {
+					self.prefix[next] = prev;
+					self.suffix[next] = byte;
+					self.first[next] = self.first[prev as usize];
+					self.next += 1;
+					if self.next == 1 << self.size && self.size < CODE_MAX {
+						self.size += 1;
+					}
+				}
+			}
+		}
+		self.prev = Some(code);
+
+		// every entry extends an older one, so this ends at a root
+		self.stack.clear();
+		let mut at = code;
+		loop {
+			self.stack.push(self.suffix[at as usize]);
+			if at < clear {
+				break;
+			}
+			at = self.prefix[at as usize];
+		}
+		let len = self.stack.len().min(limit.saturating_sub(out.len()));
+		out.try_reserve(len).map_err(|_| oom())?;
+		out.extend(self.stack.iter().rev().take(len));
+		Ok(())
+	}
+}
+
+/// codes, packed into bytes from the low bits, with the width the decoder
+/// will read them at.
+struct Packer<'a> {
+	out: &'a mut Vec<u8>,
+	bits: u32,
+	count: u8,
+	min_size: u8,
+	size: u8,
+	/// the decoder's next entry, and whether the next code is the first
+	/// since a clear code, which doesn't add one.
+	next: u16,
+	first: bool,
+}
+
+impl Packer<'_> {
+	fn code(&mut self, code: u16) -> Result<(), Error> {
+		self.bits |= (code as u32) << self.count;
+		self.count += self.size;
+		while self.count >= 8 {
+			self.out.try_reserve(1).map_err(|_| oom())?;
+			self.out.push(self.bits as u8);
+			self.bits >>= 8;
+			self.count -= 8;
+		}
+
+		let clear = 1 << self.min_size;
+		if code == clear {
+			self.size = self.min_size + 1;
+			self.next = clear + 2;
+			self.first = true;
+		}
+		else if self.first {
+			self.first = false;
+		}
+		else if (self.next as usize) < TABLE_SIZE {
+			self.next += 1;
+			if self.next == 1 << self.size && self.size < CODE_MAX {
+				self.size += 1;
+			}
+		}
+		Ok(())
+	}
+
+	fn end(self) -> Result<(), Error> {
+		if self.count > 0 {
+			self.out.try_reserve(1).map_err(|_| oom())?;
+			self.out.push(self.bits as u8);
+		}
+		Ok(())
+	}
+}
+
+/// encodes `indices`, each less than `1 << min_size`, appending the codes
+/// to `out`. the table is cleared whenever it fills.
+pub(super) fn encode(indices: &[u8], min_size: u8, out: &mut Vec<u8>) -> Result<(), Error> {
+	let clear = 1 << min_size;
+	let mut packer = Packer { out, bits: 0, count: 0, min_size, size: min_size + 1, next: clear + 2, first: true };
+	// each entry as the entry it extends and the byte it adds
+	let mut table = std::collections::HashMap::<(u16, u8), u16>::new();
+	let mut next = clear + 2;
+	packer.code(clear)?;
+
+	let mut indices = indices.iter();
+	let Some(&first) = indices.next()
+	else {
+		packer.code(clear + 1)?;
+		return packer.end();
+	};
+	let mut prefix = first as u16;
+	for &byte in indices {
+		if let Some(&code) = table.get(&(prefix, byte)) {
+			prefix = code;
+			continue;
+		}
+		packer.code(prefix)?;
+		table.try_reserve(1).map_err(|_| oom())?;
+		table.insert((prefix, byte), next);
+		next += 1;
+		if next as usize == TABLE_SIZE {
+			packer.code(clear)?;
+			table.clear();
+			next = clear + 2;
+		}
+		prefix = byte as u16;
+	}
+	packer.code(prefix)?;
+	packer.code(clear + 1)?;
+	packer.end()
+}
@@ -0,0 +1,68 @@
+/*!
+color tables for encoding. frames without one get one made by median cut,
+and pixels go to the nearest color in the table.
+
+gif's transparency is all or nothing, so pixels less than half opaque are
+transparent, and the rest opaque.
+*/
+
+use std::collections::HashMap;
+
+use crate::Error;
+use crate::error::oom;
+
+fn opaque(px: crate::Rgba) -> bool {
+	px.3 >= 128
+}
+
+/// a table of at most `size` colors for `pixels`, which is from 2 to 256,
+/// made by median cut. when some are transparent, one of those is
+/// transparent black, last.
+pub(super) fn median_cut(pixels: &[crate::Rgba], size: usize) -> Result<Vec<crate::Rgba>, Error> {
+	let transparent = pixels.iter().any(|&px| !opaque(px));
+	let max = size - transparent as usize;
+	let opaques = pixels.iter().filter(|&&px| opaque(px)).map(|px| (px.0, px.1, px.2, 255));
+	let mut palette = crate::quantize::median_cut(opaques, max)?;
+	if transparent {
+		palette.push((0, 0, 0, 0));
+	}
+	Ok(palette)
+}
+
+/// `pixels` as indices into `palette`, which has at most 256 colors, and
+/// the index that's transparent, if any. each pixel gets the nearest
+/// opaque color, or the first transparent one if it's transparent too.
+pub(super) fn map(pixels: &[crate::Rgba], palette: &[crate::Rgba]) -> Result<(Vec<u8>, Option<u8>), Error> {
+	let transparent = palette.iter().position(|&px| !opaque(px)).map(|i| i as u8);
+	let nearest = |px: crate::Rgba| {
+		let distance = |c: crate::Rgba| {
+			let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+			d(px.0, c.0) + d(px.1, c.1) + d(px.2, c.2)
+		};
+		let opaque = palette.iter().enumerate().filter(|(_, c)| opaque(**c));
+		opaque.min_by_key(|(_, c)| distance(**c)).map_or(0, |(i, _)| i as u8)
+	};
+
+	let mut cache = HashMap::new();
+	let mut indices = vec![];
+	indices.try_reserve_exact(pixels.len()).map_err(|_| oom())?;
+	for &px in pixels {
+		let i = match transparent {
+			Some(i) if !opaque(px) => i,
+			_ => {
+				let px = (px.0, px.1, px.2, 255);
+				match cache.get(&px) {
+					Some(&i) => i,
+					None => {
+						let i = nearest(px);
+						cache.try_reserve(1).map_err(|_| oom())?;
+						cache.insert(px, i);
+						i
+					}
+				}
+			}
+		};
+		indices.push(i);
+	}
+	Ok((indices, transparent))
+}
@@ -0,0 +1,681 @@
+/*!
+radiance hdr images, also known as rgbe.
+
+the header is lines of text: the magic, `#?` and usually `RADIANCE`, then
+variables like `FORMAT=32-bit_rle_rgbe`, ending in an empty line, and then
+the resolution on a line of its own. only `-Y height +X width` is read,
+which is rows top-down and left to right, and what everything writes. the
+`EXPOSURE` variables are multiplied together, and the rest are skipped.
+`xyze` images aren't read.
+
+pixels are four bytes, a mantissa each for red, green and blue and an
+exponent they share. rows 8 to 32767 pixels wide are usually run-length
+encoded a channel at a time, and start with 2, 2 and the width to say so.
+the rest are flat, where a pixel of 1, 1, 1 repeats the one before it, the
+exponent times, and a second such pixel right after adds its count shifted
+left by 8, and so on.
+
+`HdrPixels` hands out pixels as those four bytes, which [`radiance`] turns
+into floats. the exposure is only read and written: radiance is the
+samples divided by it, and most tools ignore it. `Hdr::decode` maps
+samples to 8 bits with its `ToneCurve`, and `Hdr::encode` maps them back.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+pub use crate::tone::ToneCurve;
+
+/// the longest header line that's read.
+const LINE_MAX: usize = 1 << 12;
+/// the widths that can be run-length encoded.
+const RLE_WIDTHS: std::ops::RangeInclusive<u32> = 8..=0x7fff;
+/// the shortest run that's encoded as one.
+const RUN_MIN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HdrHeader {
+	pub width: u32,
+	pub height: u32,
+	/// what the samples were multiplied by, 1 if there are no `EXPOSURE`
+	/// variables.
+	pub exposure: f32,
+}
+
+impl crate::Dimensions for HdrHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// red, green and blue for `rgbe`, the way radiance has them, from the
+/// middle of the range each mantissa stands for.
+pub fn radiance(rgbe: [u8; 4]) -> [f32; 3] {
+	if rgbe[3] == 0 {
+		return [0.0; 3];
+	}
+	let scale = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+	[rgbe[0], rgbe[1], rgbe[2]].map(|x| (x as f32 + 0.5) * scale)
+}
+
+/// the rgbe pixel `radiance` maps close to `rgb`. negative samples and nan
+/// are taken as 0, and anything too bright for a pixel as the brightest.
+pub fn rgbe(rgb: [f32; 3]) -> [u8; 4] {
+	let rgb = rgb.map(|x| if x > 0.0 { x } else { 0.0 });
+	let max = rgb[0].max(rgb[1]).max(rgb[2]);
+	if max < 1e-32 {
+		return [0; 4];
+	}
+	if max.is_infinite() {
+		return [255; 4];
+	}
+	// `max` is `mantissa * 2^exponent`, with the mantissa from 0.5 to 1
+	let exponent = ((max.to_bits() >> 23) & 0xff) as i32 - 126;
+	if exponent > 127 {
+		return [255; 4];
+	}
+	let scale = 2f32.powi(8 - exponent);
+	let [r, g, b] = rgb.map(|x| (x * scale).min(255.0) as u8);
+	[r, g, b, (exponent + 128) as u8]
+}
+
+#[derive(Debug, Clone)]
+pub struct Hdr {
+	pub curve: ToneCurve,
+}
+
+impl Default for Hdr {
+	fn default() -> Self {
+		Self { curve: ToneCurve::Srgb }
+	}
+}
+
+/// pixels of a hdr image, as rgbe. rows are read as they're needed.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct HdrPixels<'a, R> {
+	header: HdrHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// the row's channels one after another, when it's run-length encoded.
+	row: Vec<u8>,
+	rle: bool,
+	/// the first pixel of a flat row, read while looking for the start of
+	/// a run-length encoded one.
+	first: Option<[u8; 4]>,
+	/// the last pixel of a flat row, how many more times it's repeated, and
+	/// how far the next repeat count is shifted.
+	last: [u8; 4],
+	repeat: u64,
+	shift: u32,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+/// reads a line, without its newline, moving `offset` past it.
+fn line(data: &mut impl std::io::Read, offset: &mut u64) -> Result<Vec<u8>, Error> {
+	let start = *offset;
+	let mut line = vec![];
+	loop {
+		let mut b = [0];
+		crate::read_exact(data, &mut b, *offset)?;
+		*offset = offset.saturating_add(1);
+		if b[0] == b'\n' {
+			return Ok(line);
+		}
+		if line.len() == LINE_MAX {
+			return Err(Error::InvalidHeader { offset: start, field: "header line" });
+		}
+		line.push(b[0]);
+	}
+}
+
+impl<'a, R: std::io::Read> HdrPixels<'a, R> {
+	/// reads the header, up to the first pixel.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut offset = 0;
+		let magic = line(data, &mut offset).map_err(|e| match e {
+			Error::InvalidHeader { .. } => Error::InvalidMagic { offset: 0 },
+			e => e,
+		})?;
+		if !magic.starts_with(b"#?") {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+
+		let mut exposure = 1.0f32;
+		loop {
+			let start = offset;
+			let line = line(data, &mut offset)?;
+			if line.is_empty() {
+				break;
+			}
+			let text = String::from_utf8_lossy(&line);
+			if let Some(format) = text.strip_prefix("FORMAT=")
+				&& format.trim() != "32-bit_rle_rgbe"
+			{
+				return Err(Error::InvalidHeader { offset: start, field: "format" });
+			}
+			if let Some(value) = text.strip_prefix("EXPOSURE=") {
+				let value = value.trim().parse::<f32>().ok().filter(|x| x.is_finite() && *x > 0.0);
+				let Some(value) = value.map(|x| x * exposure).filter(|x| x.is_finite() && *x > 0.0)
+				else {
+					return Err(Error::InvalidHeader { offset: start, field: "exposure" });
+				};
+				exposure = value;
+			}
+		}
+
+		let start = offset;
+		let line = line(data, &mut offset)?;
+		let text = String::from_utf8_lossy(&line);
+		let words = text.split_ascii_whitespace().collect::<Vec<_>>();
+		let number = |word: &str| word.parse::<u32>().ok().filter(|&x| x != 0);
+		let resolution = match words[..] {
+			["-Y", height, "+X", width] => (number(height), number(width)),
+			_ => (None, None),
+		};
+		let (Some(height), Some(width)) = resolution
+		else {
+			return Err(Error::InvalidHeader { offset: start, field: "resolution" });
+		};
+
+		Ok(Self {
+			header: HdrHeader { width, height, exposure },
+			data,
+			offset,
+			row: vec![],
+			rle: false,
+			first: None,
+			last: [0; 4],
+			repeat: 0,
+			shift: 0,
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &HdrHeader {
+		&self.header
+	}
+
+	fn byte(&mut self) -> Result<u8, Error> {
+		let mut b = [0];
+		crate::read_exact(self.data, &mut b, self.offset)?;
+		self.offset = self.offset.saturating_add(1);
+		Ok(b[0])
+	}
+
+	fn pixel(&mut self) -> Result<[u8; 4], Error> {
+		let mut px = [0; 4];
+		crate::read_exact(self.data, &mut px, self.offset)?;
+		self.offset = self.offset.saturating_add(4);
+		Ok(px)
+	}
+
+	/// reads the start of a row, and the whole row if it's run-length
+	/// encoded.
+	fn start_row(&mut self) -> Result<(), Error> {
+		self.rle = false;
+		self.shift = 0;
+		if !RLE_WIDTHS.contains(&self.header.width) {
+			return Ok(());
+		}
+		let start = self.offset;
+		let px = self.pixel()?;
+		if px[..2] != [2, 2] || px[2] & 0x80 != 0 {
+			self.first = Some(px);
+			return Ok(());
+		}
+		let width = self.header.width as usize;
+		if u16::from_be_bytes([px[2], px[3]]) as usize != width {
+			return Err(Error::Malformed { offset: start, reason: "row's width isn't the image's" });
+		}
+
+		self.rle = true;
+		self.row.clear();
+		self.row.try_reserve_exact(width * 4).map_err(|_| oom())?;
+		for channel in 0..4 {
+			let end = (channel + 1) * width;
+			while self.row.len() < end {
+				let start = self.offset;
+				let count = self.byte()?;
+				let (n, run) = match count {
+					0 => return Err(Error::Malformed { offset: start, reason: "run is empty" }),
+					129.. => (count as usize - 128, true),
+					_ => (count as usize, false),
+				};
+				if self.row.len() + n > end {
+					return Err(Error::Malformed { offset: start, reason: "run goes past the end of the row" });
+				}
+				if run {
+					let x = self.byte()?;
+					self.row.resize(self.row.len() + n, x);
+				}
+				else {
+					let at = self.row.len();
+					self.row.resize(at + n, 0);
+					crate::read_exact(self.data, &mut self.row[at..], self.offset)?;
+					self.offset = self.offset.saturating_add(n as u64);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// the next pixel of a flat row.
+	fn flat(&mut self) -> Result<[u8; 4], Error> {
+		if self.repeat > 0 {
+			self.repeat -= 1;
+			return Ok(self.last);
+		}
+		loop {
+			let start = self.offset;
+			let px = match self.first.take() {
+				Some(px) => px,
+				None => self.pixel()?,
+			};
+			if px[..3] != [1, 1, 1] {
+				self.shift = 0;
+				self.last = px;
+				return Ok(px);
+			}
+
+			if self.x == 0 {
+				return Err(Error::Malformed { offset: start, reason: "run has nothing to repeat" });
+			}
+			let count = (px[3] as u64) << self.shift;
+			self.shift = (self.shift + 8).min(32);
+			if count > (self.header.width - self.x) as u64 {
+				return Err(Error::Malformed { offset: start, reason: "run goes past the end of the row" });
+			}
+			if count > 0 {
+				self.repeat = count - 1;
+				return Ok(self.last);
+			}
+		}
+	}
+
+	/// reads any pixels that are left, and returns the number of bytes the
+	/// image took up.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		for px in &mut self {
+			px?;
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<R: std::io::Read> Iterator for HdrPixels<'_, R> {
+	type Item = Result<[u8; 4], Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		if self.x == 0
+			&& let Err(e) = self.start_row()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let px = if self.rle {
+			let (x, width) = (self.x as usize, self.header.width as usize);
+			[0, 1, 2, 3].map(|c| self.row[c * width + x])
+		}
+		else {
+			match self.flat() {
+				Ok(px) => px,
+				Err(e) => {
+					self.failed = true;
+					return Some(Err(e));
+				}
+			}
+		};
+
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+/// `HdrPixels` mapped to 8 bits, ending at the first error, for
+/// `Format::decode`.
+struct Pixels<'a, R> {
+	pixels: HdrPixels<'a, R>,
+	curve: ToneCurve,
+}
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		Some(tone(self.pixels.next()?.ok()?, self.curve))
+	}
+}
+
+/// a pixel brought down to 8 bits through `curve`.
+fn tone(rgbe: [u8; 4], curve: ToneCurve) -> crate::Rgba {
+	let [r, g, b] = radiance(rgbe).map(|x| curve.apply(x));
+	(r, g, b, 255)
+}
+
+/// a channel of a row, run-length encoded.
+fn pack(channel: &[u8], out: &mut Vec<u8>) {
+	let mut i = 0;
+	while i < channel.len() {
+		// the next run long enough to be one, or the end
+		let mut start = i;
+		let mut run = 0;
+		while start < channel.len() {
+			run = channel[start..].iter().take(127).take_while(|&&x| x == channel[start]).count();
+			if run >= RUN_MIN {
+				break;
+			}
+			start += run;
+		}
+		for literal in channel[i..start].chunks(128) {
+			out.push(literal.len() as u8);
+			out.extend_from_slice(literal);
+		}
+		if start < channel.len() {
+			out.extend_from_slice(&[128 + run as u8, channel[start]]);
+		}
+		i = start + run;
+	}
+}
+
+/// encodes rgbe pixels. rows 8 to 32767 pixels wide are run-length encoded,
+/// and the rest written flat, where a pixel of 1, 1, 1 would be taken for a
+/// run, so those are written the way [`rgbe`] would have made them.
+///
+/// each row is kept until it's whole. `data` running out is a
+/// `PixelCountMismatch`, by which time the rows it filled are written.
+pub fn encode_rgbe(data: impl Iterator<Item = [u8; 4]>, header: &HdrHeader, out: &mut impl std::io::Write) -> Result<(), Error> {
+	let field = |offset, field| Error::InvalidHeader { offset, field };
+	let mut text = String::from("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n");
+	if header.exposure != 1.0 {
+		if !header.exposure.is_finite() || header.exposure <= 0.0 {
+			return Err(field(text.len() as u64, "exposure"));
+		}
+		text += &format!("EXPOSURE={}\n", header.exposure);
+	}
+	text += "\n";
+	if header.width == 0 || header.height == 0 {
+		return Err(field(text.len() as u64, "resolution"));
+	}
+	text += &format!("-Y {} +X {}\n", header.height, header.width);
+	crate::write_all(out, text.as_bytes())?;
+
+	let width = header.width as usize;
+	let rle = RLE_WIDTHS.contains(&header.width);
+	let total = header.width as u64 * header.height as u64;
+	let mut row = vec![];
+	let mut packed = vec![];
+	crate::push_exact(data, total, |px| {
+		row.try_reserve(1).map_err(|_| oom())?;
+		row.push(px);
+		if row.len() < width {
+			return Ok(());
+		}
+
+		packed.clear();
+		if rle {
+			packed.extend_from_slice(&[2, 2]);
+			packed.extend_from_slice(&(width as u16).to_be_bytes());
+			for c in 0..4 {
+				let channel = row.iter().map(|px| px[c]).collect::<Vec<_>>();
+				pack(&channel, &mut packed);
+			}
+		}
+		else {
+			packed.try_reserve(width * 4).map_err(|_| oom())?;
+			for &px in &row {
+				let px = if px[..3] == [1, 1, 1] { rgbe(radiance(px)) } else { px };
+				packed.extend_from_slice(&px);
+			}
+		}
+		crate::write_all(out, &packed)?;
+		row.clear();
+		Ok(())
+	})
+}
+
+/// encodes red, green and blue, through [`rgbe`].
+pub fn encode_f32(data: impl Iterator<Item = [f32; 3]>, header: &HdrHeader, out: &mut impl std::io::Write) -> Result<(), Error> {
+	encode_rgbe(data.map(rgbe), header, out)
+}
+
+impl crate::Format for Hdr {
+	type Header = HdrHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = HdrPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels { pixels, curve: self.curve }))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = HdrPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels.map(move |px| px.map(|px| tone(px, self.curve)))))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let data = data.map(|px| [px.0, px.1, px.2].map(|x| self.curve.invert(x)));
+		encode_f32(data, &header, out)
+	}
+
+	/// radiance, as [`radiance`] gives it, without the curve. alpha is 1.
+	fn decode_float(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::RgbaF32>), Error> {
+		let pixels = HdrPixels::new(data)?;
+		let header = pixels.header().clone();
+		Ok((header, pixels.map_while(Result::ok).map(|px| {
+			let [r, g, b] = radiance(px);
+			(r, g, b, 1.0)
+		})))
+	}
+
+	/// radiance, through [`rgbe`], without the curve. alpha is dropped.
+	fn encode_float(self, data: impl Iterator<Item = crate::RgbaF32>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		encode_f32(data.map(|px| [px.0, px.1, px.2]), &header, out)
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, hdr};
+
+	fn radiance(x: u32, y: u32) -> [f32; 3] {
+		if y == 0 {
+			return [1.0, 0.5, 0.25];
+		}
+		[x as f32 * 0.25, y as f32 * 0.5 + 0.125, 2f32.powi(x as i32 - 8)]
+	}
+
+	fn read(image: &[u8]) -> (hdr::HdrHeader, Vec<[u8; 4]>) {
+		let mut data = image;
+		let mut pixels = hdr::HdrPixels::new(&mut data).expect("error?");
+		let header = pixels.header().clone();
+		let decoded = (&mut pixels).map(|px| px.expect("error?")).collect();
+		assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		(header, decoded)
+	}
+
+	#[test]
+	fn rgbe() {
+		assert_eq!(hdr::rgbe([1.0, 0.5, 0.25]), [128, 64, 32, 129]);
+		assert_eq!(hdr::radiance([128, 64, 32, 129]), [128.5 / 128.0, 64.5 / 128.0, 32.5 / 128.0]);
+		assert_eq!(hdr::rgbe([0.0; 3]), [0; 4]);
+		assert_eq!(hdr::radiance([0, 0, 0, 0]), [0.0; 3]);
+		assert_eq!(hdr::rgbe([-1.0, f32::NAN, 0.0]), [0; 4]);
+		assert_eq!(hdr::rgbe([f32::INFINITY, 0.0, 0.0]), [255; 4]);
+		assert_eq!(hdr::rgbe([f32::MAX, 0.0, 0.0]), [255; 4]);
+
+		// close enough, up to the precision of the biggest channel
+		for x in [1e-20, 0.003, 0.7, 1.0, 3.5, 1e4, 1e30] {
+			let px = hdr::radiance(hdr::rgbe([x, x / 3.0, 0.0]));
+			assert!((px[0] - x).abs() <= x / 128.0, "{x}: {px:?}");
+			assert!((px[1] - x / 3.0).abs() <= x / 128.0, "{x}: {px:?}");
+		}
+	}
+
+	#[test]
+	fn decode() {
+		// run-length encoded, with comments and two exposures
+		let (header, pixels) = read(include_bytes!("../test/gradient.hdr"));
+		assert_eq!(header, hdr::HdrHeader { width: 20, height: 6, exposure: 1.5 });
+		for (i, &px) in pixels.iter().enumerate() {
+			let (x, y) = (i as u32 % 20, i as u32 / 20);
+			assert_eq!(px, hdr::rgbe(radiance(x, y)), "{x}, {y}");
+		}
+		assert_eq!(pixels.len(), 120);
+
+		// flat, with runs. a second run right after one is shifted left
+		let a = hdr::rgbe([0.5, 0.25, 1.0]);
+		let b = hdr::rgbe([2.0, 3.0, 0.125]);
+		let (header, pixels) = read(include_bytes!("../test/flat.hdr"));
+		assert_eq!(header, hdr::HdrHeader { width: 5, height: 3, exposure: 1.0 });
+		let row = (0..5).map(|x| hdr::rgbe(radiance(x, 1)));
+		assert!(pixels.iter().cloned().eq(row.chain([a; 5]).chain([b, b, b, a, a])));
+		let (header, pixels) = read(include_bytes!("../test/flat_wide.hdr"));
+		assert_eq!(header.width, 40000);
+		assert_eq!(pixels, [a; 40000]);
+
+		let mut data = &include_bytes!("../test/gradient.hdr")[..];
+		let (_, iter) = hdr::Hdr { curve: hdr::ToneCurve::Linear }.decode(&mut data).expect("error?");
+		let expected = (0..120).map(|i| {
+			let px = hdr::radiance(hdr::rgbe(radiance(i % 20, i / 20)));
+			let [r, g, b] = px.map(|x| hdr::ToneCurve::Linear.apply(x));
+			(r, g, b, 255)
+		});
+		assert!(iter.eq(expected));
+	}
+
+	#[test]
+	fn float() {
+		// radiance past 1 comes through decode_as and encode_from as it is
+		let mut data = &include_bytes!("../test/gradient.hdr")[..];
+		let (header, iter) = hdr::Hdr::default().decode_as::<crate::RgbaF32>(&mut data).expect("error?");
+		let pixels = iter.collect::<Vec<_>>();
+		for (i, &px) in pixels.iter().enumerate() {
+			let [r, g, b] = hdr::radiance(hdr::rgbe(radiance(i as u32 % 20, i as u32 / 20)));
+			assert_eq!(px, (r, g, b, 1.0), "{i}");
+		}
+		assert!(pixels.iter().any(|px| px.0 > 1.0));
+
+		let mut out = vec![];
+		hdr::Hdr::default().encode_from(pixels.iter().copied(), header, &mut out).expect("error?");
+		let mut data = &out[..];
+		let (_, iter) = hdr::Hdr::default().decode_as::<crate::RgbaF32>(&mut data).expect("error?");
+		assert!(iter.eq(pixels));
+	}
+
+	#[test]
+	fn encode() {
+		// both kinds of row, and rows from the fixture's
+		for width in [5, 20, 40000] {
+			let header = hdr::HdrHeader { width, height: 3, exposure: 1.0 };
+			let pixels = (0..width * 3).map(|i| hdr::rgbe(radiance(i % width % 32, i / width)));
+			let mut out = vec![];
+			hdr::encode_rgbe(pixels.clone(), &header, &mut out).expect("error?");
+			let (decoded, iter) = read(&out);
+			assert_eq!(decoded, header);
+			assert!(iter.into_iter().eq(pixels), "{width}");
+		}
+
+		let header = hdr::HdrHeader { width: 20, height: 6, exposure: 1.5 };
+		let mut out = vec![];
+		hdr::encode_f32((0..120).map(|i| radiance(i % 20, i / 20)), &header, &mut out).expect("error?");
+		let (decoded, pixels) = read(&out);
+		assert_eq!(decoded, header);
+		assert!(pixels.into_iter().eq((0..120).map(|i| hdr::rgbe(radiance(i % 20, i / 20)))));
+		// the top row is one run a channel
+		let start = out.windows(11).position(|x| x == b"-Y 6 +X 20\n").expect("no resolution?") + 11;
+		assert_eq!(out[start..start + 12], [2, 2, 0, 20, 148, 128, 148, 64, 148, 32, 148, 129]);
+
+		// a flat pixel that would be taken for a run
+		let header = hdr::HdrHeader { width: 2, height: 1, exposure: 1.0 };
+		let mut out = vec![];
+		hdr::encode_rgbe([[9, 9, 9, 140], [1, 1, 1, 140]].into_iter(), &header, &mut out).expect("error?");
+		let (_, pixels) = read(&out);
+		assert_eq!(pixels, [[9, 9, 9, 140], [192, 192, 192, 133]]);
+
+		// too few pixels, after the rows they filled
+		let header = hdr::HdrHeader { width: 20, height: 6, exposure: 1.0 };
+		let mut out = vec![];
+		let result = hdr::encode_f32((0..45).map(|i| radiance(i % 20, i / 20)), &header, &mut out);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 120, got: 45 })), "{result:?}");
+		let mut data = &out[..];
+		let pixels = hdr::HdrPixels::new(&mut data).expect("error?");
+		assert_eq!(pixels.take_while(|px| px.is_ok()).count(), 40);
+
+		// through 8 bits. the channels share an exponent, so gray keeps the
+		// most
+		let pixels = (0..15).map(|i| ((i * 17) as u8, (i * 17) as u8, (i * 17) as u8, 255));
+		let mut out = vec![];
+		hdr::Hdr::default().encode(pixels.clone(), hdr::HdrHeader { width: 5, height: 3, exposure: 1.0 }, &mut out).expect("error?");
+		let mut data = &out[..];
+		let (_, iter) = hdr::Hdr::default().decode(&mut data).expect("error?");
+		for (a, b) in iter.zip(pixels) {
+			let d = |a: u8, b: u8| a.abs_diff(b);
+			assert!(d(a.0, b.0).max(d(a.1, b.1)).max(d(a.2, b.2)) <= 1, "{a:?} {b:?}");
+		}
+
+		let header = hdr::HdrHeader { width: 5, height: 3, exposure: -1.0 };
+		let result = hdr::encode_rgbe([].into_iter(), &header, &mut vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 34, field: "exposure" })), "{result:?}");
+		let header = hdr::HdrHeader { width: 0, height: 3, exposure: 1.0 };
+		let result = hdr::encode_rgbe([].into_iter(), &header, &mut vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 35, field: "resolution" })), "{result:?}");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = hdr::HdrPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name)));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic.hdr", Error::InvalidMagic { offset: 0 });
+		case!("xyze.hdr", Error::InvalidHeader { offset: 11, field: "format" });
+		case!("bad_exposure.hdr", Error::InvalidHeader { offset: 11, field: "exposure" });
+		case!("flipped.hdr", Error::InvalidHeader { offset: 12, field: "resolution" });
+		case!("zero_width.hdr", Error::InvalidHeader { offset: 12, field: "resolution" });
+		case!("long_line.hdr", Error::InvalidHeader { offset: 11, field: "header line" });
+		case!("row_width.hdr", Error::Malformed { offset: 23, .. });
+		case!("long_run.hdr", Error::Malformed { offset: 27, .. });
+		case!("empty_run.hdr", Error::Malformed { offset: 27, .. });
+		case!("nothing_to_repeat.hdr", Error::Malformed { offset: 22, .. });
+		case!("flat_long_run.hdr", Error::Malformed { offset: 26, .. });
+		case!("truncated.hdr", Error::UnexpectedEof { offset: 150 });
+
+		assert!(decode(include_bytes!("../test/gradient.hdr")).is_ok());
+	}
+}
@@ -0,0 +1,569 @@
+/*!
+ico and cur files.
+
+an icon, or a cursor, is a directory of images, usually the same picture at
+a few sizes and depths, with each image stored as a bmp without its file
+header or as a whole png. `IcoImages` reads the directory and every image's
+bytes, and decodes whichever image is asked for. since images can be stored
+in any order, or even share bytes, there's no decoding them as they go by.
+
+bmp images are twice as tall as they say, the pixels on top of a 1-bit mask
+of which pixels are transparent. 32-bit ones have alpha of their own, which
+is used instead of the mask. the other depths `bmp` decodes come out opaque
+where the mask is clear. the directory's sizes only go up to 256, and
+aren't always right, so images come out at the size they say they are.
+
+`encode` packs images into one icon or cursor, each as a 32-bit bmp or a
+png. there's no `Format` impl, since there's more than one image.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+const DIRECTORY_SIZE: u64 = 6;
+const ENTRY_SIZE: u64 = 16;
+/// `BITMAPFILEHEADER`, which bmp images in an icon go without.
+const FILE_HEADER_SIZE: usize = 14;
+/// `BITMAPINFOHEADER`, the smallest header an icon's bmp can have.
+const INFO_HEADER_SIZE: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum IcoKind {
+	/// an ico file.
+	Icon,
+	/// a cur file, whose images each have a hotspot.
+	Cursor,
+}
+
+/// how an image is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum IcoFormat {
+	Bmp,
+	Png,
+}
+
+/// an image in the directory, as the directory has it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IcoEntry {
+	/// 1 to 256.
+	pub width: u32,
+	pub height: u32,
+	/// bits per pixel, for icons, which is often left 0.
+	pub bits: u16,
+	/// where a cursor points, from the top left. 0, 0 for icons.
+	pub hotspot: (u16, u16),
+	pub format: IcoFormat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IcoHeader {
+	pub kind: IcoKind,
+	pub entries: Vec<IcoEntry>,
+}
+
+/// one image of an icon or cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcoImage {
+	/// where a cursor points, from the top left, which icons ignore.
+	pub hotspot: (u16, u16),
+	pub format: IcoFormat,
+	pub image: crate::Image,
+}
+
+/// `e`, with its offset moved by `f`.
+fn moved(e: Error, f: impl Fn(u64) -> u64) -> Error {
+	match e {
+		Error::InvalidMagic { offset } => Error::InvalidMagic { offset: f(offset) },
+		Error::InvalidHeader { offset, field } => Error::InvalidHeader { offset: f(offset), field },
+		Error::UnexpectedEof { offset } => Error::UnexpectedEof { offset: f(offset) },
+		Error::Malformed { offset, reason } => Error::Malformed { offset: f(offset), reason },
+		Error::RunOverrun { offset } => Error::RunOverrun { offset: f(offset) },
+		Error::MissingEndMarker { offset } => Error::MissingEndMarker { offset: f(offset) },
+		Error::TrailingData { offset } => Error::TrailingData { offset: f(offset) },
+		e => e,
+	}
+}
+
+/// the images of an icon or cursor, read whole.
+#[derive(Debug)]
+pub struct IcoImages {
+	header: IcoHeader,
+	/// the bytes after the directory, up to the end of the last image.
+	data: Vec<u8>,
+	/// where each image starts in the stream, and how long it is.
+	ranges: Vec<(u64, usize)>,
+}
+
+impl IcoImages {
+	/// reads the directory and the images.
+	pub fn new(data: &mut impl std::io::Read) -> Result<Self, Error> {
+		let mut buf = [0; DIRECTORY_SIZE as usize];
+		crate::read_exact(data, &mut buf, 0)?;
+		if buf[..2] != [0, 0] {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let kind = match u16::from_le_bytes([buf[2], buf[3]]) {
+			1 => IcoKind::Icon,
+			2 => IcoKind::Cursor,
+			_ => return Err(field(2, "type")),
+		};
+		let count = u16::from_le_bytes([buf[4], buf[5]]);
+		if count == 0 {
+			return Err(field(4, "image count"));
+		}
+
+		let mut directory = vec![];
+		let len = count as usize * ENTRY_SIZE as usize;
+		directory.try_reserve_exact(len).map_err(|_| oom())?;
+		directory.resize(len, 0);
+		crate::read_exact(data, &mut directory, DIRECTORY_SIZE)?;
+		let base = DIRECTORY_SIZE + len as u64;
+
+		let mut entries = vec![];
+		let mut ranges = vec![];
+		entries.try_reserve_exact(count as usize).map_err(|_| oom())?;
+		ranges.try_reserve_exact(count as usize).map_err(|_| oom())?;
+		let mut end = base;
+		for (i, entry) in directory.chunks_exact(ENTRY_SIZE as usize).enumerate() {
+			let at = DIRECTORY_SIZE + i as u64 * ENTRY_SIZE;
+			let u16_at = |i: usize| u16::from_le_bytes([entry[i], entry[i + 1]]);
+			let u32_at = |i: usize| u32::from_le_bytes([entry[i], entry[i + 1], entry[i + 2], entry[i + 3]]);
+			let size = u32_at(8);
+			let offset = u32_at(12) as u64;
+			if size == 0 {
+				return Err(field(at + 8, "image size"));
+			}
+			if offset < base {
+				return Err(field(at + 12, "image offset"));
+			}
+			end = end.max(offset + size as u64);
+			ranges.push((offset, size as usize));
+
+			let (bits, hotspot) = match kind {
+				IcoKind::Icon => (u16_at(6), (0, 0)),
+				IcoKind::Cursor => (0, (u16_at(4), u16_at(6))),
+			};
+			// 0 is 256
+			let side = |x: u8| if x == 0 { 256 } else { x as u32 };
+			entries.push(IcoEntry {
+				width: side(entry[0]),
+				height: side(entry[1]),
+				bits,
+				hotspot,
+				// not known until the image is read
+				format: IcoFormat::Bmp,
+			});
+		}
+
+		// a piece at a time, so memory is only taken up once the data shows
+		// up
+		let mut bytes = vec![];
+		let mut left = end - base;
+		while left > 0 {
+			let n = left.min(1 << 16) as usize;
+			let start = bytes.len();
+			bytes.try_reserve(n).map_err(|_| oom())?;
+			bytes.resize(start + n, 0);
+			crate::read_exact(data, &mut bytes[start..], base + start as u64)?;
+			left -= n as u64;
+		}
+
+		for (entry, &(offset, size)) in entries.iter_mut().zip(&ranges) {
+			let start = (offset - base) as usize;
+			if bytes[start..start + size].starts_with(&crate::png::SIGNATURE) {
+				entry.format = IcoFormat::Png;
+			}
+		}
+		Ok(Self {
+			header: IcoHeader { kind, entries },
+			data: bytes,
+			ranges,
+		})
+	}
+
+	pub fn header(&self) -> &IcoHeader {
+		&self.header
+	}
+
+	/// decodes the image at `index` in the directory.
+	pub fn decode(&self, index: usize) -> Result<IcoImage, Error> {
+		let (Some(entry), Some(&(offset, size))) = (self.header.entries.get(index), self.ranges.get(index))
+		else {
+			return Err(Error::InvalidInput { reason: "there's no image at that index" });
+		};
+		let base = DIRECTORY_SIZE + self.header.entries.len() as u64 * ENTRY_SIZE;
+		let start = (offset - base) as usize;
+		let data = &self.data[start..start + size];
+		let image = match entry.format {
+			IcoFormat::Png => png(data).map_err(|e| moved(e, |x| x.saturating_add(offset)))?,
+			IcoFormat::Bmp => bmp(data, offset)?,
+		};
+		Ok(IcoImage { hotspot: entry.hotspot, format: entry.format, image })
+	}
+}
+
+/// every pixel of `pixels`, or its first error.
+fn collect(width: u32, height: u32, pixels: impl Iterator<Item = Result<crate::Rgba, Error>>) -> Result<crate::Image, Error> {
+	// grown as pixels come, rather than all at once, since the size is only
+	// what the image says
+	let mut all = vec![];
+	for px in pixels {
+		all.try_reserve(1).map_err(|_| oom())?;
+		all.push(px?);
+	}
+	crate::Image::from_pixels(width, height, all)
+}
+
+fn png(mut data: &[u8]) -> Result<crate::Image, Error> {
+	let pixels = crate::png::PngPixels::new(&mut data)?;
+	let (width, height) = (pixels.header().width, pixels.header().height);
+	collect(width, height, pixels)
+}
+
+/// a bmp without its file header, twice as tall as it would be, starting at
+/// `offset` in the stream.
+fn bmp(data: &[u8], offset: u64) -> Result<crate::Image, Error> {
+	let field = |at: usize, field| Error::InvalidHeader { offset: offset + at as u64, field };
+	if data.len() < INFO_HEADER_SIZE {
+		return Err(Error::UnexpectedEof { offset: offset + data.len() as u64 });
+	}
+	let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+	let u32_at = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+	let size = u32_at(0) as usize;
+	if size < INFO_HEADER_SIZE || size > data.len() {
+		return Err(field(0, "header size"));
+	}
+	let height = u32_at(8) as i32 / 2;
+	if height <= 0 {
+		return Err(field(8, "height"));
+	}
+	let bits = u16_at(14);
+	// BI_RGB, or BI_BITFIELDS
+	let compression = u32_at(16);
+	if !matches!(compression, 0 | 3) {
+		return Err(field(16, "compression"));
+	}
+	let colors = match (bits, u32_at(32)) {
+		(9.., _) => 0,
+		(_, 0) => 1 << bits,
+		(_, colors) => colors.min(256),
+	};
+
+	// a file header in front, and the height of the pixels without the mask.
+	// alpha in 32-bit images is only read from a mask, so BI_RGB ones get
+	// BI_ALPHABITFIELDS and masks, after a plain info header and in place of
+	// the unused ones in later headers
+	let alpha = bits == 32 && compression == 0 && (size == INFO_HEADER_SIZE || size >= 56);
+	let inserted = if alpha && size == INFO_HEADER_SIZE { 16 } else { 0 };
+	// BI_BITFIELDS masks after a plain info header
+	let masks = if compression == 3 && size == INFO_HEADER_SIZE { 12 } else { 0 };
+	let pixel_offset = FILE_HEADER_SIZE + size + inserted + masks + colors as usize * 4;
+	let mut file = vec![];
+	file.try_reserve_exact(FILE_HEADER_SIZE + inserted + data.len()).map_err(|_| oom())?;
+	file.extend_from_slice(b"BM");
+	file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+	file.extend_from_slice(&[0; 4]);
+	file.extend_from_slice(&(pixel_offset as u32).to_le_bytes());
+	file.extend_from_slice(&data[..size]);
+	file[FILE_HEADER_SIZE + 8..FILE_HEADER_SIZE + 12].copy_from_slice(&height.to_le_bytes());
+	if alpha {
+		file[FILE_HEADER_SIZE + 16..FILE_HEADER_SIZE + 20].copy_from_slice(&6u32.to_le_bytes());
+		let mut masks = [0; 16];
+		for (i, mask) in [0x00ff0000u32, 0x0000ff00, 0x000000ff, 0xff000000].into_iter().enumerate() {
+			masks[i * 4..i * 4 + 4].copy_from_slice(&mask.to_le_bytes());
+		}
+		match inserted {
+			0 => file[FILE_HEADER_SIZE + 40..FILE_HEADER_SIZE + 56].copy_from_slice(&masks),
+			_ => file.extend_from_slice(&masks),
+		}
+	}
+	file.extend_from_slice(&data[size..]);
+
+	// offsets in `file` back to ones in the stream
+	let to_stream = |x: u64| {
+		let x = x.saturating_sub(FILE_HEADER_SIZE as u64);
+		let x = match x.checked_sub(size as u64) {
+			Some(past) if inserted > 0 => size as u64 + past.saturating_sub(inserted as u64),
+			_ => x,
+		};
+		offset.saturating_add(x)
+	};
+	let mut reader = &file[..];
+	let pixels = crate::bmp::BmpPixels::new(&mut reader).map_err(|e| moved(e, to_stream))?;
+	let (width, height) = (pixels.header().width, pixels.header().height);
+	let mut image = collect(width, height, pixels).map_err(|e| moved(e, to_stream))?;
+	if bits == 32 {
+		return Ok(image);
+	}
+
+	// the mask comes right after the pixels, bottom-up like them, a bit a
+	// pixel with rows padded to 4 bytes
+	let stride = |bits: u64| (width as u64 * bits).div_ceil(32) * 4;
+	let start = (size + colors as usize * 4) as u64 + stride(bits as u64) * height as u64;
+	let mask_stride = stride(1);
+	let end = start.saturating_add(mask_stride * height as u64);
+	if end > data.len() as u64 {
+		return Err(Error::Malformed { offset: offset + data.len() as u64, reason: "mask is missing" });
+	}
+	for y in 0..height {
+		let row = start as usize + (height - 1 - y) as usize * mask_stride as usize;
+		for x in 0..width {
+			if data[row + x as usize / 8] >> (7 - x % 8) & 1 == 1
+				&& let Some(px) = image.get_pixel(x, y)
+			{
+				image.set_pixel(x, y, (px.0, px.1, px.2, 0))?;
+			}
+		}
+	}
+	Ok(image)
+}
+
+/// a 32-bit bmp of `image` without its file header, twice as tall, with the
+/// mask after the pixels.
+fn write_bmp(image: &crate::Image, out: &mut Vec<u8>) -> Result<(), Error> {
+	let (width, height) = (image.width(), image.height());
+	let mask_stride = (width as usize).div_ceil(32) * 4;
+	let size = width as usize * height as usize * 4 + mask_stride * height as usize;
+	out.try_reserve_exact(INFO_HEADER_SIZE + size).map_err(|_| oom())?;
+
+	out.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+	out.extend_from_slice(&width.to_le_bytes());
+	out.extend_from_slice(&(height * 2).to_le_bytes());
+	out.extend_from_slice(&1u16.to_le_bytes());
+	out.extend_from_slice(&32u16.to_le_bytes());
+	// BI_RGB
+	out.extend_from_slice(&0u32.to_le_bytes());
+	out.extend_from_slice(&(size as u32).to_le_bytes());
+	// no resolution, and no color table
+	out.extend_from_slice(&[0; 16]);
+
+	let rows = || image.pixels().chunks_exact(width as usize).rev();
+	for row in rows() {
+		for px in row {
+			out.extend_from_slice(&[px.2, px.1, px.0, px.3]);
+		}
+	}
+	// for readers that ignore alpha
+	for row in rows() {
+		let start = out.len();
+		out.resize(start + mask_stride, 0);
+		for (x, px) in row.iter().enumerate() {
+			if px.3 == 0 {
+				out[start + x / 8] |= 0x80 >> (x % 8);
+			}
+		}
+	}
+	Ok(())
+}
+
+fn write_png(image: &crate::Image, out: &mut Vec<u8>) -> Result<(), Error> {
+	let header = crate::png::PngHeader {
+		width: image.width(),
+		height: image.height(),
+		color_type: crate::png::PngColorType::RGBA,
+		bit_depth: 8,
+		interlaced: false,
+		palette: vec![],
+	};
+	let mut encoder = crate::png::PngEncoder::new(&header, &crate::Metadata::new(), out)?;
+	for &px in image.pixels() {
+		encoder.push(px)?;
+	}
+	encoder.end()
+}
+
+/// encodes `images` into one icon or cursor, in the order they're given.
+/// each has to be 1 to 256 pixels on a side.
+pub fn encode(images: impl IntoIterator<Item = IcoImage>, kind: IcoKind, out: &mut impl std::io::Write) -> Result<(), Error> {
+	let mut entries = vec![];
+	for image in images {
+		if entries.len() == u16::MAX as usize {
+			return Err(Error::InvalidInput { reason: "more than 65535 images" });
+		}
+		let (width, height) = (image.image.width(), image.image.height());
+		if !(1..=256).contains(&width) || !(1..=256).contains(&height) {
+			return Err(Error::InvalidInput { reason: "image isn't 1 to 256 pixels on a side" });
+		}
+		let mut data = vec![];
+		match image.format {
+			IcoFormat::Bmp => write_bmp(&image.image, &mut data)?,
+			IcoFormat::Png => write_png(&image.image, &mut data)?,
+		}
+		entries.try_reserve(1).map_err(|_| oom())?;
+		entries.push((image, data));
+	}
+	if entries.is_empty() {
+		return Err(Error::InvalidInput { reason: "there are no images" });
+	}
+
+	let mut buf = vec![0, 0];
+	let kind_id: u16 = match kind {
+		IcoKind::Icon => 1,
+		IcoKind::Cursor => 2,
+	};
+	buf.extend_from_slice(&kind_id.to_le_bytes());
+	buf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+	let mut offset = DIRECTORY_SIZE + entries.len() as u64 * ENTRY_SIZE;
+	for (image, data) in &entries {
+		let (Ok(size), Ok(at)) = (u32::try_from(data.len()), u32::try_from(offset))
+		else {
+			return Err(Error::InvalidInput { reason: "images don't fit in 4 gb" });
+		};
+		// 256 is 0
+		buf.push(image.image.width() as u8);
+		buf.push(image.image.height() as u8);
+		// no color table, and a reserved byte
+		buf.extend_from_slice(&[0, 0]);
+		let (a, b) = match kind {
+			// 1 plane of 32 bits
+			IcoKind::Icon => (1, 32),
+			IcoKind::Cursor => image.hotspot,
+		};
+		buf.extend_from_slice(&a.to_le_bytes());
+		buf.extend_from_slice(&b.to_le_bytes());
+		buf.extend_from_slice(&size.to_le_bytes());
+		buf.extend_from_slice(&at.to_le_bytes());
+		offset += data.len() as u64;
+	}
+	crate::write_all(out, &buf)?;
+	for (_, data) in &entries {
+		crate::write_all(out, data)?;
+	}
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, ico};
+
+	const IMAGE_ICON: &[u8] = include_bytes!("../test/icon.ico");
+
+	fn color(x: u32, y: u32) -> crate::Rgba {
+		((x * 16) as u8, (y * 16) as u8, 128, (x * y * 4) as u8)
+	}
+
+	#[test]
+	fn decode() {
+		// a png first, then the bmps, out of the directory's order
+		let images = ico::IcoImages::new(&mut &IMAGE_ICON[..]).expect("error?");
+		let header = images.header();
+		assert_eq!(header.kind, ico::IcoKind::Icon);
+		let entries = header.entries.iter().map(|x| (x.width, x.height, x.bits, x.format)).collect::<Vec<_>>();
+		assert_eq!(entries, [(16, 16, 32, ico::IcoFormat::Bmp), (7, 5, 4, ico::IcoFormat::Bmp), (20, 20, 32, ico::IcoFormat::Png)]);
+
+		// alpha of its own
+		let image = images.decode(0).expect("error?").image;
+		assert_eq!((image.width(), image.height()), (16, 16));
+		for (i, &px) in image.pixels().iter().enumerate() {
+			let (x, y) = (i as u32 % 16, i as u32 / 16);
+			assert_eq!(px, color(x, y), "{x}, {y}");
+		}
+
+		// a color table, and the mask along the diagonal
+		let image = images.decode(1).expect("error?").image;
+		assert_eq!((image.width(), image.height()), (7, 5));
+		for (i, &px) in image.pixels().iter().enumerate() {
+			let (x, y) = (i as u32 % 7, i as u32 / 7);
+			let c = (x + 2 * y) % 16;
+			let expected = ((c * 17) as u8, (255 - c * 17) as u8, (c * 5) as u8, if x == y { 0 } else { 255 });
+			assert_eq!(px, expected, "{x}, {y}");
+		}
+
+		let image = images.decode(2).expect("error?").image;
+		assert_eq!((image.width(), image.height()), (20, 20));
+		assert!(image.pixels().iter().enumerate().all(|(i, &px)| {
+			let (x, y) = (i as u32 % 20, i as u32 / 20);
+			px == ((x * 12) as u8, (y * 12) as u8, ((x + y) * 6) as u8, 200)
+		}));
+
+		assert!(matches!(images.decode(3), Err(Error::InvalidInput { .. })));
+	}
+
+	#[test]
+	fn decode_cursor() {
+		// 1 bit, with the left column masked off
+		let images = ico::IcoImages::new(&mut &include_bytes!("../test/cursor.cur")[..]).expect("error?");
+		assert_eq!(images.header().kind, ico::IcoKind::Cursor);
+		assert_eq!(images.header().entries[0].hotspot, (4, 3));
+		let cursor = images.decode(0).expect("error?");
+		assert_eq!(cursor.hotspot, (4, 3));
+		for (i, &px) in cursor.image.pixels().iter().enumerate() {
+			let (x, y) = (i as u32 % 9, i as u32 / 9);
+			let c = if (x + y) % 2 == 1 { 255 } else { 0 };
+			assert_eq!(px, (c, c, c, if x == 0 { 0 } else { 255 }), "{x}, {y}");
+		}
+	}
+
+	#[test]
+	fn encode() {
+		let image = |width, height| crate::Image::collect(width, height, (0..width * height).map(|i| color(i % width, i / width))).expect("error?");
+		for kind in [ico::IcoKind::Icon, ico::IcoKind::Cursor] {
+			// 256 is written as 0
+			let images = [
+				ico::IcoImage { hotspot: (1, 2), format: ico::IcoFormat::Bmp, image: image(16, 16) },
+				ico::IcoImage { hotspot: (3, 4), format: ico::IcoFormat::Png, image: image(13, 7) },
+				ico::IcoImage { hotspot: (0, 0), format: ico::IcoFormat::Bmp, image: image(256, 3) },
+			];
+			let mut out = vec![];
+			ico::encode(images.clone(), kind, &mut out).expect("error?");
+
+			let decoded = ico::IcoImages::new(&mut &out[..]).expect("error?");
+			assert_eq!(decoded.header().kind, kind);
+			for (i, image) in images.iter().enumerate() {
+				let entry = &decoded.header().entries[i];
+				assert_eq!((entry.width, entry.height, entry.format), (image.image.width(), image.image.height(), image.format));
+				let mut expected = image.clone();
+				if kind == ico::IcoKind::Icon {
+					expected.hotspot = (0, 0);
+				}
+				assert_eq!(decoded.decode(i).expect("error?"), expected, "{kind:?}: {i}");
+			}
+		}
+
+		let one = |width, height| [ico::IcoImage { hotspot: (0, 0), format: ico::IcoFormat::Png, image: image(width, height) }];
+		for (width, height) in [(257, 1), (1, 257), (0, 0)] {
+			let result = ico::encode(one(width, height), ico::IcoKind::Icon, &mut vec![]);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+		}
+		let result = ico::encode([], ico::IcoKind::Icon, &mut vec![]);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let images = ico::IcoImages::new(&mut data)?;
+			for i in 0..images.header().entries.len() {
+				images.decode(i)?;
+			}
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".ico")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("bad_type", Error::InvalidHeader { offset: 2, .. });
+		case!("no_images", Error::InvalidHeader { offset: 4, .. });
+		case!("bad_offset", Error::InvalidHeader { offset: 18, .. });
+		case!("zero_size", Error::InvalidHeader { offset: 30, .. });
+		case!("truncated", Error::UnexpectedEof { offset: 300 });
+		case!("no_mask", Error::Malformed { offset: 2340, .. });
+		case!("bad_compression", Error::InvalidHeader { offset: 2232, .. });
+		// found by `bmp`, at the bit depth in the image's header
+		case!("bad_depth", Error::InvalidHeader { offset: 2230, .. });
+		case!("bad_png", Error::ChecksumMismatch { .. });
+
+		assert!(decode(IMAGE_ICON).is_ok());
+	}
+}
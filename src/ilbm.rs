@@ -0,0 +1,549 @@
+/*!
+amiga iff ilbm images, which are only decoded.
+
+the file is an iff `FORM` of type `ILBM`, a list of chunks, each a 4-byte id
+and a big-endian size, and padded to an even size. `BMHD` has the size, how
+many bitplanes there are, the masking and the compression, `CMAP` the
+colors, `CAMG` the amiga display mode, and `BODY` the pixels. other
+chunks are skipped.
+
+each row has a row of each bitplane in turn, the lowest bit first, and then
+one of the mask, if there is one, each padded to 2 bytes. 1 to 8 planes are
+indices into the colors, and 24 or 32 are 8 bits each of red, green, blue
+and alpha, the lowest first. ham images, with 6 or 8 planes, have the top 2
+bits of each pixel pick between an index into the first 16 or 64 colors, or
+changing the blue, red or green of the last pixel to the other bits. each
+row starts from the first color. extra halfbrite images, with 6 planes,
+have 32 more colors, each of the first at half brightness. images without a
+color map are gray.
+
+`ByteRun1` compression has a signed byte before each run. 0 to 127 is that
+many bytes and one more as they are, -1 to -127 is the next byte repeated
+one more time than its negation, and -128 is nothing. runs can go on from
+one row to the next.
+
+rows are read as they're asked for.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+/// the `CAMG` mode bits.
+const HAM: u32 = 0x800;
+const EXTRA_HALFBRITE: u32 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum IlbmMode {
+	/// indices into the color map.
+	Indexed,
+	/// hold and modify.
+	Ham,
+	/// indices into the color map and its half-bright copy.
+	ExtraHalfbrite,
+	/// 8 bits of each channel.
+	RGB,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum IlbmMasking {
+	None,
+	/// a plane after the others, set where pixels are opaque.
+	Mask,
+	/// this index is transparent, in indexed and extra halfbrite images.
+	Transparent(u16),
+	/// only for drawing programs, so it's the same as none.
+	Lasso,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IlbmHeader {
+	pub width: u32,
+	pub height: u32,
+	/// bitplanes, not counting the mask.
+	pub planes: u8,
+	pub mode: IlbmMode,
+	pub masking: IlbmMasking,
+	/// whether the pixels are `ByteRun1` compressed.
+	pub rle: bool,
+	/// the color map, with the half-bright colors of extra halfbrite images.
+	/// it's empty for images without one.
+	pub palette: Vec<crate::Rgba>,
+}
+
+impl crate::Dimensions for IlbmHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// reads past `n` bytes, which start at `offset`.
+fn skip(data: &mut impl std::io::Read, mut n: u64, mut offset: u64) -> Result<(), Error> {
+	let mut buf = [0; 512];
+	while n > 0 {
+		let len = n.min(buf.len() as u64) as usize;
+		crate::read_exact(data, &mut buf[..len], offset)?;
+		offset += len as u64;
+		n -= len as u64;
+	}
+	Ok(())
+}
+
+/// pixels of an ilbm image, read from the reader a row at a time as
+/// they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct IlbmPixels<'a, R> {
+	header: IlbmHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// where the form ends.
+	end: u64,
+	/// the bytes a row of one plane takes up.
+	stride: usize,
+	/// what's left of a run that went on past the last row read, and the
+	/// byte it repeats, or none if its bytes are as they are.
+	run: (u16, Option<u8>),
+	row: Vec<u8>,
+	/// the last pixel of a ham image.
+	ham: crate::Rgba,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> IlbmPixels<'a, R> {
+	/// reads the chunks before `BODY`, up to the first row.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; 12];
+		crate::read_exact(data, &mut buf, 0)?;
+		if buf[..4] != *b"FORM" {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		if buf[8..] != *b"ILBM" {
+			return Err(Error::InvalidHeader { offset: 8, field: "form type" });
+		}
+
+		let end = 8 + u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]) as u64;
+		let mut offset = 12;
+		let mut bmhd = None;
+		let mut map = vec![];
+		let mut camg = None;
+		loop {
+			let mut chunk = [0; 8];
+			crate::read_exact(data, &mut chunk, offset)?;
+			let size = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+			offset += 8;
+			// what's left of the chunk after what's been read of it
+			let mut rest = size + size % 2;
+			match &chunk[..4] {
+				b"BODY" => break,
+				b"BMHD" => {
+					if size < 20 {
+						return Err(Error::InvalidHeader { offset: offset - 4, field: "BMHD size" });
+					}
+					let mut b = [0; 20];
+					crate::read_exact(data, &mut b, offset)?;
+					bmhd = Some((offset, b));
+					rest -= 20;
+				}
+				b"CMAP" => {
+					let n = size.min(3 * 256) as usize / 3 * 3;
+					map.clear();
+					map.resize(n, 0);
+					crate::read_exact(data, &mut map, offset)?;
+					rest -= n as u64;
+				}
+				b"CAMG" if size >= 4 => {
+					let mut b = [0; 4];
+					crate::read_exact(data, &mut b, offset)?;
+					camg = Some((offset, u32::from_be_bytes(b)));
+					rest -= 4;
+				}
+				_ => {}
+			}
+			offset += size + size % 2 - rest;
+			skip(data, rest, offset)?;
+			offset += rest;
+		}
+
+		let Some((at, b)) = bmhd
+		else {
+			return Err(Error::Malformed { offset: offset - 8, reason: "BODY comes before BMHD" });
+		};
+		let field = |i, field| Error::InvalidHeader { offset: at + i, field };
+		let u16_at = |i: usize| u16::from_be_bytes([b[i], b[i + 1]]);
+		let (width, height, planes) = (u16_at(0) as u32, u16_at(2) as u32, b[8]);
+		if width == 0 {
+			return Err(field(0, "width"));
+		}
+		if height == 0 {
+			return Err(field(2, "height"));
+		}
+		let masking = match b[9] {
+			0 => IlbmMasking::None,
+			1 => IlbmMasking::Mask,
+			2 => IlbmMasking::Transparent(u16_at(12)),
+			3 => IlbmMasking::Lasso,
+			_ => return Err(field(9, "masking")),
+		};
+		let rle = match b[10] {
+			0 => false,
+			1 => true,
+			_ => return Err(field(10, "compression")),
+		};
+		let (camg_at, camg) = camg.unwrap_or((0, 0));
+		let mode = match planes {
+			24 | 32 => IlbmMode::RGB,
+			6 | 8 if camg & HAM != 0 => IlbmMode::Ham,
+			6 if camg & EXTRA_HALFBRITE != 0 => IlbmMode::ExtraHalfbrite,
+			_ if camg & HAM != 0 => return Err(Error::InvalidHeader { offset: camg_at, field: "mode" }),
+			1..=8 => IlbmMode::Indexed,
+			_ => return Err(field(8, "planes")),
+		};
+
+		let mut palette: Vec<_> = map.chunks_exact(3).map(|c| (c[0], c[1], c[2], 255)).collect();
+		if mode == IlbmMode::ExtraHalfbrite && !palette.is_empty() {
+			palette.resize(32, (0, 0, 0, 255));
+			for i in 0..32 {
+				let (r, g, b, _) = palette[i];
+				palette.push((r >> 1, g >> 1, b >> 1, 255));
+			}
+		}
+
+		Ok(Self {
+			ham: palette.first().copied().unwrap_or((0, 0, 0, 255)),
+			header: IlbmHeader { width, height, planes, mode, masking, rle, palette },
+			data,
+			offset,
+			end,
+			stride: width.div_ceil(16) as usize * 2,
+			run: (0, None),
+			row: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &IlbmHeader {
+		&self.header
+	}
+
+	fn byte(&mut self) -> Result<u8, Error> {
+		let mut b = [0];
+		crate::read_exact(self.data, &mut b, self.offset)?;
+		self.offset = self.offset.saturating_add(1);
+		Ok(b[0])
+	}
+
+	/// `n` bytes as they are onto the end of `row`.
+	fn read(&mut self, n: usize) -> Result<(), Error> {
+		let at = self.row.len();
+		self.row.try_reserve(n).map_err(|_| oom())?;
+		self.row.resize(at + n, 0);
+		crate::read_exact(self.data, &mut self.row[at..], self.offset)?;
+		self.offset = self.offset.saturating_add(n as u64);
+		Ok(())
+	}
+
+	/// reads the next row of every plane and the mask.
+	fn read_row(&mut self) -> Result<(), Error> {
+		self.row.clear();
+		let planes = self.header.planes as usize + (self.header.masking == IlbmMasking::Mask) as usize;
+		let mut len = self.stride * planes;
+		while len > 0 {
+			if !self.header.rle {
+				self.read(len)?;
+				break;
+			}
+			if self.run.0 == 0 {
+				self.run = match self.byte()? as i8 {
+					-128 => continue,
+					n @ 0.. => (n as u16 + 1, None),
+					n => ((1 - n as i16) as u16, Some(self.byte()?)),
+				};
+			}
+			let n = (self.run.0 as usize).min(len);
+			match self.run.1 {
+				Some(b) => {
+					self.row.try_reserve(n).map_err(|_| oom())?;
+					self.row.resize(self.row.len() + n, b);
+				}
+				None => self.read(n)?,
+			}
+			self.run.0 -= n as u16;
+			len -= n;
+		}
+		Ok(())
+	}
+
+	/// the color at index `i`, or gray without a color map.
+	fn color(&self, i: u32) -> Result<crate::Rgba, Error> {
+		if self.header.palette.is_empty() {
+			let v = (i * 255 / ((1 << self.header.planes.min(8)) - 1)) as u8;
+			return Ok((v, v, v, 255));
+		}
+		match self.header.palette.get(i as usize) {
+			Some(&px) => Ok(px),
+			None => Err(Error::Malformed { offset: self.offset, reason: "index is past the color map" }),
+		}
+	}
+
+	/// skips any rows that are left and the chunks after them, and returns
+	/// the number of bytes the image took up, which is the size of the form,
+	/// unless the pixels went on past it.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		let start = if self.x > 0 { self.y + 1 } else { self.y };
+		for _ in start..self.header.height {
+			self.read_row()?;
+		}
+		let rest = self.end.saturating_sub(self.offset);
+		skip(self.data, rest, self.offset)?;
+		Ok(self.offset + rest)
+	}
+}
+
+impl<R: std::io::Read> Iterator for IlbmPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		if self.x == 0 {
+			if let Err(e) = self.read_row() {
+				self.failed = true;
+				return Some(Err(e));
+			}
+			self.ham = self.color(0).unwrap_or((0, 0, 0, 255));
+		}
+
+		let (x, stride, planes) = (self.x as usize, self.stride, self.header.planes as u32);
+		let bit = |p: usize| (self.row[p * stride + x / 8] >> (7 - x % 8) & 1) as u32;
+		let value = (0..planes).fold(0, |v, p| v | bit(p as usize) << p);
+		let px = match self.header.mode {
+			IlbmMode::RGB => {
+				let a = if planes == 32 { (value >> 24) as u8 } else { 255 };
+				Ok((value as u8, (value >> 8) as u8, (value >> 16) as u8, a))
+			}
+			IlbmMode::Indexed | IlbmMode::ExtraHalfbrite => self.color(value),
+			IlbmMode::Ham => {
+				let bits = planes - 2;
+				let v = value & ((1 << bits) - 1);
+				// 4 bits are repeated, and 6 keep the last pixel's lowest 2
+				let modify = |c: u8| match bits {
+					4 => (v * 17) as u8,
+					_ => (v << 2) as u8 | c & 3,
+				};
+				let (r, g, b, a) = self.ham;
+				let px = match value >> bits {
+					0 => self.color(v),
+					1 => Ok((r, g, modify(b), a)),
+					2 => Ok((modify(r), g, b, a)),
+					_ => Ok((r, modify(g), b, a)),
+				};
+				if let Ok(px) = px {
+					self.ham = px;
+				}
+				px
+			}
+		};
+		let indexed = matches!(self.header.mode, IlbmMode::Indexed | IlbmMode::ExtraHalfbrite);
+		let px = px.map(|px| match self.header.masking {
+			IlbmMasking::Mask if bit(planes as usize) == 0 => (px.0, px.1, px.2, 0),
+			IlbmMasking::Transparent(i) if indexed && i as u32 == value => (px.0, px.1, px.2, 0),
+			_ => px,
+		});
+		if px.is_err() {
+			self.failed = true;
+		}
+
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(px)
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, ilbm};
+
+	const IMAGE_INDEXED: &[u8] = include_bytes!("../test/indexed.iff");
+	const IMAGE_RGB: &[u8] = include_bytes!("../test/rgb.iff");
+
+	fn decode(mut image: &[u8]) -> (ilbm::IlbmHeader, Vec<crate::Rgba>) {
+		let mut pixels = ilbm::IlbmPixels::new(&mut image).expect("error?");
+		let header = pixels.header().clone();
+		let out = (&mut pixels).collect::<Result<Vec<_>, _>>().expect("error?");
+		assert_eq!(out.len(), 13 * 5);
+		pixels.finish().expect("error?");
+		(header, out)
+	}
+
+	#[test]
+	fn decode_indexed() {
+		// a mask plane, an odd-sized chunk before the color map, and runs
+		// that go on across rows
+		let (header, pixels) = decode(IMAGE_INDEXED);
+		assert_eq!((header.width, header.height, header.planes), (13, 5, 4));
+		assert_eq!((header.mode, header.masking, header.rle), (ilbm::IlbmMode::Indexed, ilbm::IlbmMasking::Mask, true));
+		assert_eq!(header.palette.len(), 16);
+		for (i, &px) in pixels.iter().enumerate() {
+			let (x, y) = (i as u32 % 13, i as u32 / 13);
+			let (index, a) = match y {
+				1 | 2 => (0, 0),
+				_ => ((x + y * 3) % 16, if (x + y) % 5 != 0 { 255 } else { 0 }),
+			};
+			assert_eq!(px, ((index * 17) as u8, (255 - index * 17) as u8, (index * 8) as u8, a), "{x}, {y}");
+		}
+
+		// gray without a color map
+		let (header, pixels) = decode(include_bytes!("../test/mono.iff"));
+		assert_eq!((header.planes, header.mode, header.rle), (1, ilbm::IlbmMode::Indexed, false));
+		for (i, &px) in pixels.iter().enumerate() {
+			let v = if (i % 13 + i / 13) % 3 == 0 { 255 } else { 0 };
+			assert_eq!(px, (v, v, v, 255));
+		}
+	}
+
+	#[test]
+	fn decode_rgb() {
+		let (header, pixels) = decode(IMAGE_RGB);
+		assert_eq!((header.planes, header.mode), (24, ilbm::IlbmMode::RGB));
+		for (i, &px) in pixels.iter().enumerate() {
+			let (x, y) = (i as u32 % 13, i as u32 / 13);
+			assert_eq!(px, ((x * 19) as u8, (y * 50) as u8, ((x + y) * 10) as u8, 255));
+		}
+	}
+
+	#[test]
+	fn decode_ehb() {
+		let (header, pixels) = decode(include_bytes!("../test/ehb.iff"));
+		assert_eq!((header.planes, header.mode), (6, ilbm::IlbmMode::ExtraHalfbrite));
+		assert_eq!(header.masking, ilbm::IlbmMasking::Transparent(40));
+		assert_eq!(header.palette.len(), 64);
+		for (i, &px) in pixels.iter().enumerate() {
+			let (x, y) = (i as u32 % 13, i as u32 / 13);
+			let index = (x * 7 + y * 5) % 64;
+			let (r, g, b) = ((index % 32 * 8) as u8, (255 - index % 32 * 8) as u8, (index % 32 * 5 + 3) as u8);
+			let expected = match index {
+				40 => (r >> 1, g >> 1, b >> 1, 0),
+				32.. => (r >> 1, g >> 1, b >> 1, 255),
+				_ => (r, g, b, 255),
+			};
+			assert_eq!(px, expected, "{x}, {y}");
+		}
+	}
+
+	#[test]
+	fn decode_ham() {
+		let ham6 = |x: u32, y: u32| ((x + y) % 4) << 4 | (x * 3 + y) & 15;
+		let ham8 = |x: u32, y: u32| ((x + 2 * y) % 4) << 6 | (x * 5 + y * 11) & 63;
+		let pal6 = |i: u32| ((i * 17) as u8, (255 - i * 17) as u8, (i * 8) as u8, 255);
+		let pal8 = |i: u32| ((i * 4 + 1) as u8, (i * 3) as u8, (255 - i) as u8, 255);
+
+		let (header, pixels) = decode(include_bytes!("../test/ham6.iff"));
+		assert_eq!((header.planes, header.mode, header.palette.len()), (6, ilbm::IlbmMode::Ham, 16));
+		// the start of the first row by hand: the first color and then an
+		// index, and then blues, reds and greens changed
+		assert_eq!(pixels[..4], [pal6(0), (0, 255, 3 * 17, 255), (6 * 17, 255, 3 * 17, 255), (6 * 17, 9 * 17, 3 * 17, 255)]);
+		for y in 0..5 {
+			let mut last = pal6(0);
+			for x in 0..13 {
+				let v = ham6(x, y) & 15;
+				last = match ham6(x, y) >> 4 {
+					0 => pal6(v),
+					1 => (last.0, last.1, (v * 17) as u8, 255),
+					2 => ((v * 17) as u8, last.1, last.2, 255),
+					_ => (last.0, (v * 17) as u8, last.2, 255),
+				};
+				assert_eq!(pixels[(y * 13 + x) as usize], last, "{x}, {y}");
+			}
+		}
+
+		let (header, pixels) = decode(include_bytes!("../test/ham8.iff"));
+		assert_eq!((header.planes, header.mode, header.rle), (8, ilbm::IlbmMode::Ham, true));
+		for y in 0..5 {
+			let mut last = pal8(0);
+			for x in 0..13 {
+				let v = ham8(x, y) & 63;
+				let modify = |c: u8| (v << 2) as u8 | c & 3;
+				last = match ham8(x, y) >> 6 {
+					0 => pal8(v),
+					1 => (last.0, last.1, modify(last.2), 255),
+					2 => (modify(last.0), last.1, last.2, 255),
+					_ => (last.0, modify(last.1), last.2, 255),
+				};
+				assert_eq!(pixels[(y * 13 + x) as usize], last, "{x}, {y}");
+			}
+		}
+	}
+
+	#[test]
+	fn finish() {
+		for image in [IMAGE_INDEXED, IMAGE_RGB] {
+			for n in [0, 1, 13, 30] {
+				let mut data = image;
+				let mut pixels = ilbm::IlbmPixels::new(&mut data).expect("error?");
+				if n > 0 {
+					assert!(pixels.nth(n - 1).is_some());
+				}
+				assert_eq!(pixels.finish().expect("error?"), image.len() as u64, "{n}");
+			}
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = ilbm::IlbmPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".iff")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("pbm", Error::InvalidHeader { offset: 8, .. });
+		case!("short_bmhd", Error::InvalidHeader { offset: 16, .. });
+		case!("zero_width", Error::InvalidHeader { offset: 20, .. });
+		case!("zero_height", Error::InvalidHeader { offset: 22, .. });
+		case!("bad_planes", Error::InvalidHeader { offset: 28, .. });
+		case!("bad_masking", Error::InvalidHeader { offset: 29, .. });
+		case!("bad_compression", Error::InvalidHeader { offset: 30, .. });
+		case!("ham_planes", Error::InvalidHeader { offset: 48, .. });
+		case!("no_bmhd", Error::Malformed { offset: 68, .. });
+		case!("no_body", Error::UnexpectedEof { offset: 96 });
+		case!("truncated_chunk", Error::UnexpectedEof { offset: 50 });
+		case!("truncated", Error::UnexpectedEof { offset: 100 });
+		case!("truncated_run", Error::UnexpectedEof { offset: 149 });
+		case!("past_map", Error::Malformed { offset: 88, .. });
+
+		assert!(decode(IMAGE_INDEXED).is_ok());
+		assert!(decode(IMAGE_RGB).is_ok());
+	}
+}
@@ -0,0 +1,230 @@
+/*!
+owned images, for when a whole image needs to be in memory at once.
+*/
+
+use crate::error::oom;
+use crate::{Dimensions, Error, Rgba};
+
+/// a decoded image, stored row-major.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+	width: u32,
+	height: u32,
+	pixels: Vec<Rgba>,
+}
+
+/// `width * height`, or an error if that can't be allocated.
+fn alloc(width: u32, height: u32) -> Result<Vec<Rgba>, Error> {
+	let mut pixels = vec![];
+	let len = (width as u64 * height as u64).try_into().unwrap_or(usize::MAX);
+	pixels.try_reserve_exact(len)
+		.map_err(|_| oom())?;
+	Ok(pixels)
+}
+
+impl Image {
+	/// an image filled with `px`.
+	pub fn new(width: u32, height: u32, px: Rgba) -> Result<Self, Error> {
+		let mut pixels = alloc(width, height)?;
+		// the reservation worked, so this fits
+		pixels.resize(width as usize * height as usize, px);
+		Ok(Self { width, height, pixels })
+	}
+
+	/// wraps `pixels`, which must hold exactly `width * height` pixels.
+	pub fn from_pixels(width: u32, height: u32, pixels: Vec<Rgba>) -> Result<Self, Error> {
+		let expected = width as u64 * height as u64;
+		if pixels.len() as u64 != expected {
+			return Err(Error::PixelCountMismatch { expected, got: pixels.len() as u64 });
+		}
+		Ok(Self { width, height, pixels })
+	}
+
+	/// collects the pixels of `iter`, which must yield exactly
+	/// `width * height` of them.
+	pub fn collect(width: u32, height: u32, iter: impl Iterator<Item = Rgba>) -> Result<Self, Error> {
+		let expected = width as u64 * height as u64;
+		let mut pixels = alloc(width, height)?;
+		for px in iter {
+			if pixels.len() as u64 == expected {
+				// the rest isn't counted, since `iter` might never end
+				return Err(Error::PixelCountMismatch { expected, got: expected + 1 });
+			}
+			pixels.push(px);
+		}
+		Self::from_pixels(width, height, pixels)
+	}
+
+	/// like `collect`, but stopping at the first error, which is returned
+	/// rather than the pixel count it cut short.
+	pub(crate) fn collect_checked(width: u32, height: u32, iter: impl Iterator<Item = Result<Rgba, Error>>) -> Result<Self, Error> {
+		let mut error = None;
+		let image = Self::collect(width, height, iter.map_while(|px| px.map_err(|e| error = Some(e)).ok()));
+		match error {
+			Some(e) => Err(e),
+			None => image,
+		}
+	}
+
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+
+	pub fn height(&self) -> u32 {
+		self.height
+	}
+
+	fn index(&self, x: u32, y: u32) -> Option<usize> {
+		(x < self.width && y < self.height).then(|| y as usize * self.width as usize + x as usize)
+	}
+
+	pub fn get_pixel(&self, x: u32, y: u32) -> Option<Rgba> {
+		self.index(x, y).map(|i| self.pixels[i])
+	}
+
+	pub fn set_pixel(&mut self, x: u32, y: u32, px: Rgba) -> Result<(), Error> {
+		let i = self.index(x, y).ok_or(Error::OutOfBounds { x, y, width: 1, height: 1 })?;
+		self.pixels[i] = px;
+		Ok(())
+	}
+
+	pub fn pixels(&self) -> &[Rgba] {
+		&self.pixels
+	}
+
+	pub fn pixels_mut(&mut self) -> &mut [Rgba] {
+		&mut self.pixels
+	}
+
+	pub fn into_pixels(self) -> Vec<Rgba> {
+		self.pixels
+	}
+
+	/// each row, top to bottom.
+	pub fn rows(&self) -> impl ExactSizeIterator<Item = &[Rgba]> {
+		// an image without columns has no pixels, so the chunk size doesn't matter
+		self.pixels.chunks_exact((self.width as usize).max(1))
+	}
+
+	pub fn rows_mut(&mut self) -> impl ExactSizeIterator<Item = &mut [Rgba]> {
+		self.pixels.chunks_exact_mut((self.width as usize).max(1))
+	}
+
+	/// replaces every pixel with `f` of it.
+	pub fn map_pixels(&mut self, mut f: impl FnMut(Rgba) -> Rgba) {
+		for px in &mut self.pixels {
+			*px = f(*px);
+		}
+	}
+
+	/// copies out the `width` by `height` rectangle whose top left is `(x, y)`.
+	pub fn sub_image(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Image, Error> {
+		if x as u64 + width as u64 > self.width as u64 || y as u64 + height as u64 > self.height as u64 {
+			return Err(Error::OutOfBounds { x, y, width, height });
+		}
+
+		let mut pixels = alloc(width, height)?;
+		for row in self.rows().skip(y as usize).take(height as usize) {
+			pixels.extend_from_slice(&row[x as usize..(x + width) as usize]);
+		}
+		Ok(Image { width, height, pixels })
+	}
+}
+
+impl Dimensions for Image {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Image};
+
+	fn gradient() -> Image {
+		let pixels = (0..6 * 4).map(|i| ((i % 6) as u8 * 40, (i / 6) as u8 * 60, 7, 255)).collect();
+		Image::from_pixels(6, 4, pixels).expect("error?")
+	}
+
+	#[test]
+	fn pixels() {
+		let mut image = gradient();
+
+		assert_eq!(image.get_pixel(0, 0), Some((0, 0, 7, 255)));
+		assert_eq!(image.get_pixel(5, 0), Some((200, 0, 7, 255)));
+		assert_eq!(image.get_pixel(0, 3), Some((0, 180, 7, 255)));
+		assert_eq!(image.get_pixel(5, 3), Some((200, 180, 7, 255)));
+		assert_eq!(image.get_pixel(6, 0), None);
+		assert_eq!(image.get_pixel(0, 4), None);
+		assert_eq!(image.get_pixel(u32::MAX, u32::MAX), None);
+
+		image.set_pixel(5, 3, (1, 2, 3, 4)).expect("error?");
+		assert_eq!(image.pixels()[23], (1, 2, 3, 4));
+		assert_eq!(image.rows().last().unwrap()[5], (1, 2, 3, 4));
+
+		let result = image.set_pixel(6, 3, (0, 0, 0, 0));
+		assert!(matches!(result, Err(Error::OutOfBounds { x: 6, y: 3, width: 1, height: 1 })), "{result:?}");
+
+		for (y, row) in image.rows_mut().enumerate() {
+			row[0] = (y as u8, 0, 0, 0);
+		}
+		assert_eq!(image.get_pixel(0, 2), Some((2, 0, 0, 0)));
+		assert_eq!(image.rows().len(), 4);
+
+		let result = Image::from_pixels(6, 4, vec![(0, 0, 0, 0); 23]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 24, got: 23 })), "{result:?}");
+		assert!(Image::new(u32::MAX, u32::MAX, (0, 0, 0, 0)).is_err());
+
+		let empty = Image::new(0, 3, (0, 0, 0, 0)).expect("error?");
+		assert_eq!(empty.rows().count(), 0);
+	}
+
+	#[test]
+	fn sub_image() {
+		let image = gradient();
+
+		let sub = image.sub_image(2, 1, 3, 2).expect("error?");
+		assert_eq!((sub.width(), sub.height()), (3, 2));
+		for y in 0..2 {
+			for x in 0..3 {
+				assert_eq!(sub.get_pixel(x, y), image.get_pixel(x + 2, y + 1));
+			}
+		}
+
+		assert_eq!(image.sub_image(0, 0, 6, 4).expect("error?"), image);
+		assert_eq!(image.sub_image(6, 4, 0, 0).expect("error?").pixels(), []);
+
+		let result = image.sub_image(4, 0, 3, 1);
+		assert!(matches!(result, Err(Error::OutOfBounds { x: 4, y: 0, width: 3, height: 1 })), "{result:?}");
+		assert!(image.sub_image(u32::MAX, 0, 2, 1).is_err());
+	}
+
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn map_round_trip() {
+		use crate::{Format, qoi};
+
+		let mut image = gradient();
+		image.map_pixels(|(r, g, b, a)| (255 - r, 255 - g, 255 - b, a));
+		assert_eq!(image.get_pixel(5, 3), Some((55, 75, 248, 255)));
+
+		let header = qoi::QoiHeader {
+			width: image.width(),
+			height: image.height(),
+			channels: qoi::QoiHeaderChannels::RGB,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+		let mut out = vec![];
+		qoi::Qoi.encode(image.pixels().iter().cloned(), header, &mut out).expect("error?");
+
+		let mut reader = &out[..];
+		let (header, iter) = qoi::Qoi.decode(&mut reader).expect("error?");
+		let decoded = Image::collect(header.width, header.height, iter).expect("error?");
+		assert_eq!(decoded, image);
+	}
+}
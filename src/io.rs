@@ -0,0 +1,86 @@
+/*!
+core-only stand-ins for `std::io::Read` and `std::io::Write`, which
+`QoiDecoder` and `QoiEncoder` are written against, so they build without
+std.
+
+with std, every `std::io::Read` and `std::io::Write` is one of these, and
+its errors end up in `Error::Io`. without it, byte slices are, and running
+out of room in one is a `BufferSize`.
+*/
+
+use core::task::Poll;
+
+use crate::Error;
+
+/// bytes to decode.
+pub trait Read {
+	/// reads some bytes into `buf`, and returns how many, 0 once the data is
+	/// over, or `Pending` if none are there yet but more may come.
+	fn read(&mut self, buf: &mut [u8]) -> Result<Poll<usize>, Error>;
+}
+
+/// somewhere to encode to.
+pub trait Write {
+	/// writes all of `buf`, or fails.
+	fn write_all(&mut self, buf: &[u8]) -> Result<(), Error>;
+}
+
+/// `WouldBlock` is pending, and `Interrupted` is tried again.
+#[cfg(feature = "std")]
+impl<R: std::io::Read> Read for R {
+	fn read(&mut self, buf: &mut [u8]) -> Result<Poll<usize>, Error> {
+		loop {
+			match std::io::Read::read(self, buf) {
+				Ok(n) => return Ok(Poll::Ready(n)),
+				Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+				Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(Poll::Pending),
+				Err(e) => return Err(e.into()),
+			}
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Write for W {
+	fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+		crate::write_all(self, buf)
+	}
+}
+
+#[cfg(not(feature = "std"))]
+impl Read for &[u8] {
+	fn read(&mut self, buf: &mut [u8]) -> Result<Poll<usize>, Error> {
+		Ok(Poll::Ready(take(self, buf)))
+	}
+}
+
+/// moves as much of `data` as fits into `buf` and returns how much.
+#[cfg(feature = "qoi")]
+pub(crate) fn take(data: &mut &[u8], buf: &mut [u8]) -> usize {
+	let n = buf.len().min(data.len());
+	let (a, b) = data.split_at(n);
+	buf[..n].copy_from_slice(a);
+	*data = b;
+	n
+}
+
+/// a slice that's written into from the start, like `std::io::Cursor`.
+#[cfg(feature = "qoi")]
+#[derive(Debug)]
+pub(crate) struct SliceWriter<'a> {
+	pub(crate) buf: &'a mut [u8],
+	pub(crate) pos: usize,
+}
+
+#[cfg(feature = "qoi")]
+impl Write for SliceWriter<'_> {
+	fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+		let end = self.pos + buf.len();
+		let Some(to) = self.buf.get_mut(self.pos..end) else {
+			return Err(Error::BufferSize { expected: end as u64, got: self.buf.len() as u64 });
+		};
+		to.copy_from_slice(buf);
+		self.pos = end;
+		Ok(())
+	}
+}
@@ -0,0 +1,807 @@
+/*!
+jpeg encoding, baseline only. there's no decoder, so `Format::decode` is
+`Unsupported`.
+
+images are written as jfif, gray or ycbcr, with the example quantization
+tables from the spec scaled by `Jpeg::quality` the way libjpeg scales them,
+and the example huffman tables. subsampled chroma is the average of the
+pixels each sample covers. alpha is dropped.
+
+pixels are encoded a row of blocks at a time, 8 rows or 16 for 4:2:0, so
+only that many rows are kept. the right and bottom edges are padded out to
+whole blocks with copies of the last column and row. pixels that run out
+partway through a row get the same, and the image stops at the end of the
+row of blocks they ran out in, which most decoders show the rest of as
+gray, since the height is written first.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+/// markers.
+const SOI: u8 = 0xd8;
+const EOI: u8 = 0xd9;
+const APP0: u8 = 0xe0;
+const DQT: u8 = 0xdb;
+const SOF0: u8 = 0xc0;
+const DHT: u8 = 0xc4;
+const SOS: u8 = 0xda;
+const COM: u8 = 0xfe;
+
+/// the order coefficients are stored in, as indices into a row-major block.
+const ZIGZAG: [u8; 64] = [
+	0, 1, 8, 16, 9, 2, 3, 10,
+	17, 24, 32, 25, 18, 11, 4, 5,
+	12, 19, 26, 33, 40, 48, 41, 34,
+	27, 20, 13, 6, 7, 14, 21, 28,
+	35, 42, 49, 56, 57, 50, 43, 36,
+	29, 22, 15, 23, 30, 37, 44, 51,
+	58, 59, 52, 45, 38, 31, 39, 46,
+	53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// the spec's example quantization tables, for quality 50, in row-major
+/// order.
+const LUMA_QUANT: [u8; 64] = [
+	16, 11, 10, 16, 24, 40, 51, 61,
+	12, 12, 14, 19, 26, 58, 60, 55,
+	14, 13, 16, 24, 40, 57, 69, 56,
+	14, 17, 22, 29, 51, 87, 80, 62,
+	18, 22, 37, 56, 68, 109, 103, 77,
+	24, 35, 55, 64, 81, 104, 113, 92,
+	49, 64, 78, 87, 103, 121, 120, 101,
+	72, 92, 95, 98, 112, 100, 103, 99,
+];
+const CHROMA_QUANT: [u8; 64] = [
+	17, 18, 24, 47, 99, 99, 99, 99,
+	18, 21, 26, 66, 99, 99, 99, 99,
+	24, 26, 56, 99, 99, 99, 99, 99,
+	47, 66, 99, 99, 99, 99, 99, 99,
+	99, 99, 99, 99, 99, 99, 99, 99,
+	99, 99, 99, 99, 99, 99, 99, 99,
+	99, 99, 99, 99, 99, 99, 99, 99,
+	99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// the spec's example huffman tables, as the number of codes of each length
+/// and the values they code, in order.
+const LUMA_DC: ([u8; 16], &[u8]) = ([0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+const CHROMA_DC: ([u8; 16], &[u8]) = ([0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+const LUMA_AC: ([u8; 16], &[u8]) = ([0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d], &[
+	0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+	0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+	0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+	0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+	0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+	0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+	0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+	0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+	0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+	0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+	0xf9, 0xfa,
+]);
+const CHROMA_AC: ([u8; 16], &[u8]) = ([0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77], &[
+	0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+	0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+	0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+	0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+	0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+	0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+	0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+	0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+	0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+	0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+	0xf9, 0xfa,
+]);
+
+/// the run of zeros that doesn't end in a coefficient, and the end of a
+/// block's coefficients.
+const ZRL: u8 = 0xf0;
+const EOB: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum JpegColorType {
+	Gray,
+	YCbCr,
+}
+
+/// how much chroma is subsampled, in the usual notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum JpegSubsampling {
+	/// none.
+	Chroma444,
+	/// half as wide.
+	Chroma422,
+	/// half as wide and half as tall.
+	Chroma420,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct JpegHeader {
+	pub width: u32,
+	pub height: u32,
+	pub color_type: JpegColorType,
+	/// ignored for gray images.
+	pub subsampling: JpegSubsampling,
+}
+
+impl crate::Dimensions for JpegHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Jpeg {
+	/// 1 to 100, as libjpeg has it. 50 uses the spec's tables as they are,
+	/// and 100 doesn't quantize at all.
+	pub quality: u8,
+}
+
+impl Default for Jpeg {
+	fn default() -> Self {
+		Self { quality: 75 }
+	}
+}
+
+impl Jpeg {
+	/// encodes `data`, stopping after `header.width * header.height` pixels.
+	pub fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: &JpegHeader, out: &mut impl std::io::Write) -> Result<(), Error> {
+		self.encode_with_metadata(data, header, &crate::Metadata::new(), out)
+	}
+
+	/// like `encode`, writing the comment in a comment segment. one that
+	/// doesn't fit in a segment is cut short.
+	pub fn encode_with_metadata(self, data: impl Iterator<Item = crate::Rgba>, header: &JpegHeader, metadata: &crate::Metadata, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = JpegEncoder::new(header, self.quality, metadata, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+/// for `dynamic`, which needs a `Format`. decoding is `Unsupported`.
+impl crate::Format for Jpeg {
+	type Header = JpegHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		_ = data;
+		Err::<(_, core::iter::Empty<_>), _>(Error::Unsupported { what: "decoding jpeg" })
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		Jpeg::encode(self, data, &header, out)
+	}
+
+	fn encode_with_metadata(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, metadata: &crate::Metadata, out: &mut impl std::io::Write) -> Result<(), Error> {
+		Jpeg::encode_with_metadata(self, data, &header, metadata, out)
+	}
+}
+
+/// a quantization table for `quality`, in row-major order.
+fn quantization(base: &[u8; 64], quality: u8) -> [u16; 64] {
+	let quality = quality.clamp(1, 100) as u32;
+	let scale = if quality < 50 { 5000 / quality } else { 200 - quality * 2 };
+	base.map(|x| ((x as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+/// the code and its length for each value of a huffman table.
+fn codes(table: &([u8; 16], &[u8])) -> [(u16, u8); 256] {
+	let mut codes = [(0, 0); 256];
+	let mut code = 0u16;
+	let mut values = table.1.iter();
+	for (len, &count) in table.0.iter().enumerate() {
+		for _ in 0..count {
+			if let Some(&value) = values.next() {
+				codes[value as usize] = (code, len as u8 + 1);
+			}
+			code += 1;
+		}
+		code <<= 1;
+	}
+	codes
+}
+
+/// the forward dct of a level-shifted block, in row-major order.
+fn fdct(block: &[f32; 64]) -> [f32; 64] {
+	// `cos[u][x]` is c(u) / 2 * cos((2x + 1) u pi / 16)
+	let mut cos = [[0.0; 8]; 8];
+	for (u, row) in cos.iter_mut().enumerate() {
+		let c = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+		for (x, c_ux) in row.iter_mut().enumerate() {
+			*c_ux = c / 2.0 * ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos();
+		}
+	}
+	let mut rows = [0.0; 64];
+	for y in 0..8 {
+		for u in 0..8 {
+			rows[y * 8 + u] = (0..8).map(|x| block[y * 8 + x] * cos[u][x]).sum();
+		}
+	}
+	let mut out = [0.0; 64];
+	for v in 0..8 {
+		for u in 0..8 {
+			out[v * 8 + u] = (0..8).map(|y| rows[y * 8 + u] * cos[v][y]).sum();
+		}
+	}
+	out
+}
+
+/// the bits needed for `x`'s magnitude, and the bits that are written for it,
+/// which for negative numbers are one less, so that they start with a 0.
+fn magnitude(x: i32) -> (u8, u16) {
+	let bits = (32 - x.unsigned_abs().leading_zeros()) as u8;
+	let value = if x < 0 { x - 1 } else { x };
+	(bits, (value & ((1 << bits) - 1)) as u16)
+}
+
+/// entropy-coded bytes, with a 0 after every `0xff`.
+#[derive(Debug)]
+struct Bits {
+	buf: Vec<u8>,
+	acc: u32,
+	count: u8,
+}
+
+impl Bits {
+	fn put(&mut self, value: u16, len: u8) -> Result<(), Error> {
+		self.acc = self.acc << len | (value as u32 & ((1 << len) - 1));
+		self.count += len;
+		while self.count >= 8 {
+			let byte = (self.acc >> (self.count - 8)) as u8;
+			self.buf.try_reserve(2).map_err(|_| oom())?;
+			self.buf.push(byte);
+			if byte == 0xff {
+				self.buf.push(0);
+			}
+			self.count -= 8;
+		}
+		Ok(())
+	}
+
+	/// pads the last byte with 1s.
+	fn flush(&mut self) -> Result<(), Error> {
+		match self.count {
+			0 => Ok(()),
+			n => self.put(0x7f, 8 - n),
+		}
+	}
+}
+
+/// a component's sampling factors, and its tables.
+#[derive(Debug, Clone, Copy)]
+struct Component {
+	h: usize,
+	v: usize,
+	table: usize,
+}
+
+/// push-based encoder state, which `Jpeg::encode` drives.
+#[derive(Debug)]
+pub(crate) struct JpegEncoder<W> {
+	out: W,
+	width: usize,
+	height: u32,
+	components: Vec<Component>,
+	/// the block size of a minimum coded unit, in pixels.
+	mcu_width: usize,
+	mcu_height: usize,
+	quant: [[u16; 64]; 2],
+	dc_codes: [[(u16, u8); 256]; 2],
+	ac_codes: [[(u16, u8); 256]; 2],
+	/// each component at full resolution, for the row of blocks being filled,
+	/// padded out to whole blocks.
+	planes: Vec<Vec<f32>>,
+	stride: usize,
+	x: usize,
+	row: usize,
+	rows: u32,
+	/// the last dc coefficient of each component.
+	dc: [i32; 3],
+	bits: Bits,
+}
+
+impl<W: std::io::Write> JpegEncoder<W> {
+	/// writes everything before the entropy-coded data.
+	pub(crate) fn new(header: &JpegHeader, quality: u8, metadata: &crate::Metadata, mut out: W) -> Result<Self, Error> {
+		if header.width == 0 || header.width > u16::MAX as u32 {
+			return Err(Error::InvalidInput { reason: "width doesn't fit in a jpeg" });
+		}
+		if header.height == 0 || header.height > u16::MAX as u32 {
+			return Err(Error::InvalidInput { reason: "height doesn't fit in a jpeg" });
+		}
+		if !(1..=100).contains(&quality) {
+			return Err(Error::InvalidInput { reason: "quality isn't 1 to 100" });
+		}
+
+		let chroma = |table| Component { h: 1, v: 1, table };
+		let components = match (header.color_type, header.subsampling) {
+			(JpegColorType::Gray, _) => vec![Component { h: 1, v: 1, table: 0 }],
+			(_, JpegSubsampling::Chroma444) => vec![Component { h: 1, v: 1, table: 0 }, chroma(1), chroma(1)],
+			(_, JpegSubsampling::Chroma422) => vec![Component { h: 2, v: 1, table: 0 }, chroma(1), chroma(1)],
+			(_, JpegSubsampling::Chroma420) => vec![Component { h: 2, v: 2, table: 0 }, chroma(1), chroma(1)],
+		};
+		let (mcu_width, mcu_height) = (components[0].h * 8, components[0].v * 8);
+		let quant = [quantization(&LUMA_QUANT, quality), quantization(&CHROMA_QUANT, quality)];
+		let tables = components.len().min(2);
+
+		let mut buf = vec![0xff, SOI];
+		let mut segment = |marker: u8, data: &[u8]| {
+			buf.extend_from_slice(&[0xff, marker]);
+			buf.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+			buf.extend_from_slice(data);
+		};
+		// jfif 1.1, with square pixels and no thumbnail
+		segment(APP0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+		if let Some(text) = metadata.get_text(crate::metadata::COMMENT) {
+			let text = text.as_bytes();
+			segment(COM, &text[..text.len().min(u16::MAX as usize - 2)]);
+		}
+		let mut dqt = vec![];
+		for (id, table) in quant.iter().enumerate().take(tables) {
+			dqt.push(id as u8);
+			dqt.extend(ZIGZAG.iter().map(|&i| table[i as usize] as u8));
+		}
+		segment(DQT, &dqt);
+		let mut sof = vec![8];
+		sof.extend_from_slice(&(header.height as u16).to_be_bytes());
+		sof.extend_from_slice(&(header.width as u16).to_be_bytes());
+		sof.push(components.len() as u8);
+		for (id, c) in components.iter().enumerate() {
+			sof.extend_from_slice(&[id as u8 + 1, (c.h << 4 | c.v) as u8, c.table as u8]);
+		}
+		segment(SOF0, &sof);
+		let mut dht = vec![];
+		for (class, id, table) in [(0, 0, LUMA_DC), (1, 0, LUMA_AC), (0, 1, CHROMA_DC), (1, 1, CHROMA_AC)].into_iter().take(tables * 2) {
+			dht.push(class << 4 | id);
+			dht.extend_from_slice(&table.0);
+			dht.extend_from_slice(table.1);
+		}
+		segment(DHT, &dht);
+		let mut sos = vec![components.len() as u8];
+		for (id, c) in components.iter().enumerate() {
+			sos.extend_from_slice(&[id as u8 + 1, (c.table << 4 | c.table) as u8]);
+		}
+		// the whole spectrum, all at once
+		sos.extend_from_slice(&[0, 63, 0]);
+		segment(SOS, &sos);
+		crate::write_all(&mut out, &buf)?;
+
+		let width = header.width as usize;
+		let stride = width.div_ceil(mcu_width) * mcu_width;
+		let mut planes = vec![];
+		for _ in &components {
+			let mut plane = vec![];
+			plane.try_reserve_exact(stride * mcu_height).map_err(|_| oom())?;
+			plane.resize(stride * mcu_height, 0.0);
+			planes.push(plane);
+		}
+		Ok(Self {
+			out,
+			width,
+			height: header.height,
+			components,
+			mcu_width,
+			mcu_height,
+			quant,
+			dc_codes: [codes(&LUMA_DC), codes(&CHROMA_DC)],
+			ac_codes: [codes(&LUMA_AC), codes(&CHROMA_AC)],
+			planes,
+			stride,
+			x: 0,
+			row: 0,
+			rows: 0,
+			dc: [0; 3],
+			bits: Bits { buf: vec![], acc: 0, count: 0 },
+		})
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		let (r, g, b) = (px.0 as f32, px.1 as f32, px.2 as f32);
+		let at = self.row * self.stride + self.x;
+		self.planes[0][at] = 0.299 * r + 0.587 * g + 0.114 * b;
+		if self.planes.len() == 3 {
+			self.planes[1][at] = -0.168736 * r - 0.331264 * g + 0.5 * b + 128.0;
+			self.planes[2][at] = 0.5 * r - 0.418688 * g - 0.081312 * b + 128.0;
+		}
+		self.x += 1;
+		if self.x == self.width {
+			self.end_row()?;
+		}
+		Ok(())
+	}
+
+	/// pads the row out to whole blocks, and encodes the row of blocks if
+	/// it's full.
+	fn end_row(&mut self) -> Result<(), Error> {
+		let start = self.row * self.stride;
+		for plane in &mut self.planes {
+			let last = plane[start + self.width - 1];
+			plane[start + self.width..start + self.stride].fill(last);
+		}
+		self.x = 0;
+		self.row += 1;
+		self.rows += 1;
+		if self.row == self.mcu_height || self.rows == self.height {
+			self.encode_rows()?;
+		}
+		Ok(())
+	}
+
+	/// pads the rows out to whole blocks, and encodes them.
+	fn encode_rows(&mut self) -> Result<(), Error> {
+		let stride = self.stride;
+		for plane in &mut self.planes {
+			let (filled, rest) = plane.split_at_mut(self.row * stride);
+			let last = &filled[filled.len() - stride..];
+			for row in rest.chunks_exact_mut(stride) {
+				row.copy_from_slice(last);
+			}
+		}
+
+		for mcu in 0..stride / self.mcu_width {
+			for (i, c) in self.components.clone().into_iter().enumerate() {
+				// how many pixels each of the component's samples covers
+				let (fx, fy) = (self.components[0].h / c.h, self.components[0].v / c.v);
+				for by in 0..c.v {
+					for bx in 0..c.h {
+						let mut block = [0.0; 64];
+						let (x0, y0) = ((mcu * c.h + bx) * 8 * fx, by * 8 * fy);
+						for (j, sample) in block.iter_mut().enumerate() {
+							let (x, y) = (x0 + j % 8 * fx, y0 + j / 8 * fy);
+							let mut sum = 0.0;
+							for dy in 0..fy {
+								let row = &self.planes[i][(y + dy) * stride..];
+								sum += row[x..x + fx].iter().sum::<f32>();
+							}
+							*sample = sum / (fx * fy) as f32 - 128.0;
+						}
+						self.encode_block(i, c.table, &block)?;
+					}
+				}
+			}
+		}
+		self.row = 0;
+		crate::write_all(&mut self.out, &self.bits.buf)?;
+		self.bits.buf.clear();
+		Ok(())
+	}
+
+	fn encode_block(&mut self, component: usize, table: usize, block: &[f32; 64]) -> Result<(), Error> {
+		let coefficients = fdct(block);
+		let quant = &self.quant[table];
+		let q = |k: usize| {
+			let i = ZIGZAG[k] as usize;
+			(coefficients[i] / quant[i] as f32).round() as i32
+		};
+
+		let dc = q(0).clamp(-2047, 2047);
+		let (bits, value) = magnitude(dc - self.dc[component]);
+		self.dc[component] = dc;
+		let (code, len) = self.dc_codes[table][bits as usize];
+		self.bits.put(code, len)?;
+		self.bits.put(value, bits)?;
+
+		let mut run = 0;
+		for k in 1..64 {
+			let x = q(k).clamp(-1023, 1023);
+			if x == 0 {
+				run += 1;
+				continue;
+			}
+			while run > 15 {
+				let (code, len) = self.ac_codes[table][ZRL as usize];
+				self.bits.put(code, len)?;
+				run -= 16;
+			}
+			let (bits, value) = magnitude(x);
+			let (code, len) = self.ac_codes[table][(run << 4 | bits) as usize];
+			self.bits.put(code, len)?;
+			self.bits.put(value, bits)?;
+			run = 0;
+		}
+		if run > 0 {
+			let (code, len) = self.ac_codes[table][EOB as usize];
+			self.bits.put(code, len)?;
+		}
+		Ok(())
+	}
+
+	/// finishes the row the pixels ran out in, and the row of blocks, and
+	/// writes the end of the image.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		if self.x > 0 {
+			for plane in &mut self.planes {
+				let start = self.row * self.stride;
+				let last = plane[start + self.x - 1];
+				plane[start + self.x..start + self.width].fill(last);
+			}
+			self.x = self.width;
+			self.end_row()?;
+		}
+		if self.row > 0 {
+			self.encode_rows()?;
+		}
+		self.bits.flush()?;
+		crate::write_all(&mut self.out, &self.bits.buf)?;
+		self.bits.buf.clear();
+		crate::write_all(&mut self.out, &[0xff, EOI])
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use std::collections::HashMap;
+
+	use crate::{Error, jpeg};
+	use jpeg::{JpegColorType::*, JpegSubsampling::*};
+
+	struct Bits {
+		bytes: Vec<u8>,
+		at: usize,
+	}
+
+	impl Bits {
+		fn read(&mut self, n: u8) -> u16 {
+			let mut x = 0;
+			for _ in 0..n {
+				x = x << 1 | (self.bytes[self.at / 8] >> (7 - self.at % 8) & 1) as u16;
+				self.at += 1;
+			}
+			x
+		}
+
+		/// `table` is the class and id, as in the table's segment.
+		fn huffman(&mut self, codes: &HashMap<(u8, u8, u16), u8>, table: u8) -> u8 {
+			let (mut code, mut len) = (0, 0);
+			loop {
+				code = code << 1 | self.read(1);
+				len += 1;
+				if let Some(&x) = codes.get(&(table, len, code)) {
+					break x;
+				}
+				assert!(len < 16);
+			}
+		}
+	}
+
+	/// a baseline decoder for what the encoder writes, and not much else.
+	/// blocks past where the data ends are left gray.
+	fn decode(data: &[u8]) -> (u32, u32, Vec<crate::Rgba>) {
+		assert_eq!(data[..2], [0xff, super::SOI]);
+		let mut quant = [[0u16; 64]; 4];
+		let mut huffman = HashMap::new();
+		let (mut width, mut height, mut components) = (0, 0, vec![]);
+		let mut at = 2;
+		let scan = loop {
+			assert_eq!(data[at], 0xff);
+			let marker = data[at + 1];
+			let len = u16::from_be_bytes([data[at + 2], data[at + 3]]) as usize;
+			let segment = &data[at + 4..at + 2 + len];
+			at += 2 + len;
+			match marker {
+				super::DQT => for table in segment.chunks(65) {
+					for (k, &q) in table[1..].iter().enumerate() {
+						quant[table[0] as usize][super::ZIGZAG[k] as usize] = q as u16;
+					}
+				},
+				super::SOF0 => {
+					height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+					width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+					components = segment[6..].chunks(3).map(|c| ((c[1] >> 4) as usize, (c[1] & 15) as usize, c[2] as usize)).collect();
+				}
+				super::DHT => {
+					let mut segment = segment;
+					while !segment.is_empty() {
+						let (counts, rest) = segment[1..].split_at(16);
+						let mut values = rest.iter();
+						let mut code = 0u16;
+						for (len, &count) in counts.iter().enumerate() {
+							for _ in 0..count {
+								huffman.insert((segment[0], len as u8 + 1, code), *values.next().unwrap());
+								code += 1;
+							}
+							code <<= 1;
+						}
+						segment = &rest[counts.iter().map(|&x| x as usize).sum::<usize>()..];
+					}
+				}
+				super::SOS => break &data[at..data.len() - 2],
+				_ => {}
+			}
+		};
+
+		let mut bytes = vec![];
+		let mut iter = scan.iter();
+		while let Some(&x) = iter.next() {
+			bytes.push(x);
+			if x == 0xff {
+				assert_eq!(iter.next(), Some(&0));
+			}
+		}
+		let mut bits = Bits { bytes, at: 0 };
+
+		let (h_max, v_max) = (components[0].0, components[0].1);
+		let (mcus_x, mcus_y) = (width.div_ceil(h_max * 8), height.div_ceil(v_max * 8));
+		let mut planes = components.iter().map(|c| vec![128.0f32; mcus_x * c.0 * 8 * mcus_y * c.1 * 8]).collect::<Vec<_>>();
+		let mut dc = [0i32; 3];
+		'mcus: for my in 0..mcus_y {
+			for mx in 0..mcus_x {
+				if bits.at.div_ceil(8) >= bits.bytes.len() {
+					break 'mcus;
+				}
+				for (i, &(h, v, table)) in components.iter().enumerate() {
+					let stride = mcus_x * h * 8;
+					for by in 0..v {
+						for bx in 0..h {
+							let extend = |bits: u8, x: u16| match bits {
+								0 => 0,
+								_ if x >> (bits - 1) == 1 => x as i32,
+								_ => x as i32 - (1 << bits) + 1,
+							};
+							let mut coefficients = [0.0f32; 64];
+							let n = bits.huffman(&huffman, table as u8);
+							dc[i] += extend(n, bits.read(n));
+							coefficients[0] = (dc[i] * quant[table][0] as i32) as f32;
+							let mut k = 1;
+							while k < 64 {
+								let rs = bits.huffman(&huffman, 1 << 4 | table as u8);
+								if rs == super::EOB {
+									break;
+								}
+								k += (rs >> 4) as usize;
+								let n = rs & 15;
+								let z = super::ZIGZAG[k] as usize;
+								coefficients[z] = (extend(n, bits.read(n)) * quant[table][z] as i32) as f32;
+								k += 1;
+							}
+							for y in 0..8 {
+								for x in 0..8 {
+									let mut sum = 0.0;
+									for (z, &f) in coefficients.iter().enumerate() {
+										let c = |u: usize| if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+										let cos = |u: usize, x: usize| ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos();
+										sum += c(z % 8) * c(z / 8) / 4.0 * f * cos(z % 8, x) * cos(z / 8, y);
+									}
+									let (px, py) = ((mx * h + bx) * 8 + x, (my * v + by) * 8 + y);
+									planes[i][py * stride + px] = sum + 128.0;
+								}
+							}
+						}
+					}
+				}
+			}
+		}
+
+		let mut pixels = vec![];
+		for y in 0..height {
+			for x in 0..width {
+				let sample = |i: usize| {
+					let (h, v, _) = components[i];
+					planes[i][y * v / v_max * mcus_x * h * 8 + x * h / h_max]
+				};
+				let px = if components.len() == 1 {
+					let l = sample(0).round().clamp(0.0, 255.0) as u8;
+					(l, l, l, 255)
+				}
+				else {
+					let (l, cb, cr) = (sample(0), sample(1) - 128.0, sample(2) - 128.0);
+					let c = |x: f32| x.round().clamp(0.0, 255.0) as u8;
+					(c(l + 1.402 * cr), c(l - 0.344136 * cb - 0.714136 * cr), c(l + 1.772 * cb), 255)
+				};
+				pixels.push(px);
+			}
+		}
+		(width as u32, height as u32, pixels)
+	}
+
+	fn gradient(x: u32, y: u32) -> crate::Rgba {
+		((x * 7) as u8, (y * 12) as u8, 200 - (x + y) as u8 * 2, 255)
+	}
+
+	fn encode_gradient(jpeg: &jpeg::Jpeg, header: &jpeg::JpegHeader) -> Vec<u8> {
+		let mut out = vec![];
+		let pixels = (0..header.width * header.height).map(|i| gradient(i % header.width, i / header.width));
+		jpeg.encode(pixels, header, &mut out).expect("error?");
+		out
+	}
+
+	/// the largest difference in any channel.
+	fn error(a: crate::Rgba, b: crate::Rgba) -> u8 {
+		let d = |a: u8, b: u8| a.abs_diff(b);
+		d(a.0, b.0).max(d(a.1, b.1)).max(d(a.2, b.2))
+	}
+
+	#[test]
+	fn uniform() {
+		// no ac coefficients, and a dc that's the same as the prediction,
+		// which is 00 then the end of block, 1010, then padding
+		let header = jpeg::JpegHeader { width: 8, height: 8, color_type: Gray, subsampling: Chroma444 };
+		let mut out = vec![];
+		jpeg::Jpeg { quality: 50 }.encode(std::iter::repeat((128, 128, 128, 255)), &header, &mut out).expect("error?");
+		assert_eq!(out[out.len() - 3..], [0x2b, 0xff, super::EOI]);
+	}
+
+	#[test]
+	fn round_trip() {
+		// sizes that aren't whole blocks, in every layout
+		for (color_type, subsampling, tolerance) in [
+			(Gray, Chroma420, 4),
+			(YCbCr, Chroma444, 6),
+			(YCbCr, Chroma422, 10),
+			(YCbCr, Chroma420, 10),
+		] {
+			let header = jpeg::JpegHeader { width: 21, height: 19, color_type, subsampling };
+			let out = encode_gradient(&jpeg::Jpeg { quality: 95 }, &header);
+			let (width, height, pixels) = decode(&out);
+			assert_eq!((width, height), (21, 19));
+			for (i, &px) in pixels.iter().enumerate() {
+				let (x, y) = (i as u32 % 21, i as u32 / 21);
+				let expected = match color_type {
+					Gray => {
+						let (r, g, b, _) = gradient(x, y);
+						let l = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32).round() as u8;
+						(l, l, l, 255)
+					}
+					YCbCr => gradient(x, y),
+				};
+				assert!(error(px, expected) <= tolerance, "{color_type:?} {subsampling:?} {x}, {y}: {px:?} {expected:?}");
+			}
+		}
+	}
+
+	#[test]
+	fn quality() {
+		let header = jpeg::JpegHeader { width: 40, height: 40, color_type: YCbCr, subsampling: Chroma420 };
+		let sizes = [10, 50, 75, 100].map(|quality| encode_gradient(&jpeg::Jpeg { quality }, &header).len());
+		assert!(sizes.is_sorted(), "{sizes:?}");
+
+		// at 100, the only loss is rounding
+		let header = jpeg::JpegHeader { subsampling: Chroma444, ..header };
+		let (_, _, pixels) = decode(&encode_gradient(&jpeg::Jpeg { quality: 100 }, &header));
+		for (i, &px) in pixels.iter().enumerate() {
+			assert!(error(px, gradient(i as u32 % 40, i as u32 / 40)) <= 2, "{i}");
+		}
+
+		assert_eq!(super::quantization(&super::LUMA_QUANT, 50), super::LUMA_QUANT.map(|x| x as u16));
+		assert_eq!(super::quantization(&super::LUMA_QUANT, 100), [1; 64]);
+	}
+
+	#[test]
+	fn encode() {
+		let header = jpeg::JpegHeader { width: 21, height: 40, color_type: YCbCr, subsampling: Chroma420 };
+
+		// the comment, before anything else but the jfif segment
+		let mut metadata = crate::Metadata::new();
+		metadata.insert(crate::metadata::COMMENT, "a comment");
+		let mut out = vec![];
+		let pixels = (0..21 * 40).map(|i| gradient(i % 21, i / 21));
+		jpeg::Jpeg::default().encode_with_metadata(pixels, &header, &metadata, &mut out).expect("error?");
+		assert_eq!(out[20..26], [0xff, super::COM, 0, 11, b'a', b' ']);
+
+		// pixels that run out
+		let header = jpeg::JpegHeader { subsampling: Chroma444, ..header };
+		let pixels = (0..21 * 3 + 5).map(|i| gradient(i % 21, i / 21));
+		let result = jpeg::Jpeg { quality: 95 }.encode(pixels, &header, &mut vec![]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 840, got: 68 })), "{result:?}");
+
+		for (header, quality) in [
+			(jpeg::JpegHeader { width: 0, ..header.clone() }, 75),
+			(jpeg::JpegHeader { height: 1 << 16, ..header.clone() }, 75),
+			(header.clone(), 0),
+			(header.clone(), 101),
+		] {
+			let result = jpeg::Jpeg { quality }.encode(std::iter::empty(), &header, &mut vec![]);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+		}
+	}
+}
@@ -0,0 +1,715 @@
+/*!
+ktx2 textures, with 8-bit rgba pixels.
+
+the file starts with an identifier and a header, with the size of the
+largest mip level and how many levels, array layers and cube faces there
+are, then an index of where each level is and how long it is, largest
+first. a data format descriptor, describing the pixels' layout, and any
+key/value data come after, then the levels, smallest first. each level
+holds an image for every layer, and every face of each layer, one after
+another. levels can each be a zlib stream, which is the one supercompression
+scheme read and written.
+
+`Ktx2Pixels` decodes one image, of whichever level, layer and face is asked
+for, skipping everything before it and reading rows as they're asked for.
+1d textures, with a height of 0, come out 1 pixel tall, and 3d ones aren't
+read. `encode_images` writes a whole texture, with a descriptor but no
+key/value data.
+*/
+
+use crate::Error;
+use crate::error::oom;
+use crate::zlib::{self, Deflate, Inflate};
+
+const IDENTIFIER: [u8; 12] = [0xab, b'K', b'T', b'X', b' ', b'2', b'0', 0xbb, b'\r', b'\n', 0x1a, b'\n'];
+/// the identifier, header and index, before the level index.
+const HEADER_SIZE: u64 = 80;
+const LEVEL_SIZE: u64 = 24;
+/// `VK_FORMAT_R8G8B8A8_UNORM` and `VK_FORMAT_R8G8B8A8_SRGB`.
+const UNORM: u32 = 37;
+const SRGB: u32 = 43;
+/// supercompression schemes.
+const ZLIB: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Ktx2Supercompression {
+	None,
+	/// each level is a zlib stream.
+	Zlib,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Ktx2Header {
+	/// of the largest mip level.
+	pub width: u32,
+	pub height: u32,
+	/// whether the pixels are srgb, rather than linear.
+	pub srgb: bool,
+	/// at least 1, and no more than it takes to get to 1 by 1.
+	pub levels: u32,
+	/// array layers. 1 for textures that aren't arrays.
+	pub layers: u32,
+	/// 6 for cube maps, and 1 otherwise.
+	pub faces: u32,
+	pub supercompression: Ktx2Supercompression,
+}
+
+impl Ktx2Header {
+	/// the size of mip level `level`.
+	pub fn level_size(&self, level: u32) -> (u32, u32) {
+		let half = |x: u32| x.checked_shr(level).unwrap_or(0).max(1);
+		(half(self.width), half(self.height))
+	}
+
+	/// bytes an image of mip level `level` takes up.
+	fn image_len(&self, level: u32) -> u64 {
+		let (width, height) = self.level_size(level);
+		(width as u64 * height as u64).saturating_mul(4)
+	}
+
+	/// the most levels an image this size can have.
+	fn levels_max(&self) -> u32 {
+		32 - self.width.max(self.height).leading_zeros()
+	}
+}
+
+impl crate::Dimensions for Ktx2Header {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Ktx2;
+
+impl Default for Ktx2 {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// what's left of a level's bytes, as they're stored.
+#[derive(Debug)]
+struct Level<'a, R> {
+	data: &'a mut R,
+	offset: u64,
+	left: u64,
+}
+
+impl<R: std::io::Read> zlib::Input for Level<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		let n = buf.len().min(self.left.try_into().unwrap_or(usize::MAX));
+		if n == 0 {
+			return Ok(0);
+		}
+		crate::read_exact(self.data, &mut buf[..n], self.offset)?;
+		self.offset = self.offset.saturating_add(n as u64);
+		self.left -= n as u64;
+		Ok(n)
+	}
+
+	fn offset(&self) -> u64 {
+		self.offset
+	}
+}
+
+/// a level's bytes, decompressed if they need to be.
+#[derive(Debug)]
+enum Source<'a, R> {
+	Plain(Level<'a, R>),
+	Zlib(Box<Inflate<Level<'a, R>>>),
+}
+
+impl<'a, R: std::io::Read> Source<'a, R> {
+	fn level(&mut self) -> &mut Level<'a, R> {
+		match self {
+			Self::Plain(level) => level,
+			Self::Zlib(inflate) => inflate.input_mut(),
+		}
+	}
+
+	/// fills `buf` with the level's pixels.
+	fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+		match self {
+			Self::Plain(level) => {
+				let offset = level.offset;
+				if zlib::Input::read(level, buf)? < buf.len() {
+					return Err(Error::UnexpectedEof { offset });
+				}
+			}
+			Self::Zlib(inflate) => {
+				let mut n = 0;
+				while n < buf.len() {
+					match inflate.read(&mut buf[n..])? {
+						0 => return Err(Error::Malformed { offset: inflate.input().offset, reason: "level is shorter than it says" }),
+						x => n += x,
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// skips `len` bytes of pixels.
+	fn skip(&mut self, mut len: u64) -> Result<(), Error> {
+		let mut buf = [0; 512];
+		while len > 0 {
+			let n = len.min(buf.len() as u64) as usize;
+			self.read(&mut buf[..n])?;
+			len -= n as u64;
+		}
+		Ok(())
+	}
+}
+
+/// pixels of one image of a ktx2 texture, read from the reader a row at a
+/// time as they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct Ktx2Pixels<'a, R> {
+	header: Ktx2Header,
+	source: Source<'a, R>,
+	/// the size of the level being decoded.
+	width: u32,
+	height: u32,
+	row: Vec<u8>,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> Ktx2Pixels<'a, R> {
+	/// reads the header, up to the first face of the first layer of the
+	/// largest mip level.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		Self::image(data, 0, 0, 0)
+	}
+
+	/// reads the header, and skips to the image of mip level `level`, 0
+	/// being the largest, array layer `layer` and cube face `face`.
+	pub fn image(data: &'a mut R, level: u32, layer: u32, face: u32) -> Result<Self, Error> {
+		let mut buf = [0; HEADER_SIZE as usize];
+		crate::read_exact(data, &mut buf, 0)?;
+		if buf[..12] != IDENTIFIER {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let u32_at = |buf: &[u8], at: usize| u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+		let u64_at = |buf: &[u8], at: usize| u32_at(buf, at) as u64 | (u32_at(buf, at + 4) as u64) << 32;
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+
+		let srgb = match u32_at(&buf, 12) {
+			UNORM => false,
+			SRGB => true,
+			_ => return Err(field(12, "format")),
+		};
+		if u32_at(&buf, 16) != 1 {
+			return Err(field(16, "type size"));
+		}
+		let width = u32_at(&buf, 20);
+		if width == 0 {
+			return Err(field(20, "width"));
+		}
+		let height = u32_at(&buf, 24).max(1);
+		if u32_at(&buf, 28) != 0 {
+			return Err(field(28, "depth"));
+		}
+		let layers = u32_at(&buf, 32).max(1);
+		let faces = u32_at(&buf, 36);
+		if !matches!(faces, 1 | 6) {
+			return Err(field(36, "face count"));
+		}
+		let supercompression = match u32_at(&buf, 44) {
+			0 => Ktx2Supercompression::None,
+			ZLIB => Ktx2Supercompression::Zlib,
+			_ => return Err(field(44, "supercompression")),
+		};
+		let mut header = Ktx2Header { width, height, srgb, levels: 1, layers, faces, supercompression };
+		// 0 asks for the levels to be made when the texture is loaded
+		header.levels = u32_at(&buf, 40).max(1);
+		if header.levels > header.levels_max() {
+			return Err(field(40, "level count"));
+		}
+		if level >= header.levels || layer >= layers || face >= faces {
+			return Err(Error::InvalidInput { reason: "there's no such image" });
+		}
+
+		// up to the level that's asked for, and the rest of the index
+		let mut index = [0; 32 * LEVEL_SIZE as usize];
+		let index = &mut index[..(header.levels as u64 * LEVEL_SIZE) as usize];
+		crate::read_exact(data, index, HEADER_SIZE)?;
+		let at = (level as u64 * LEVEL_SIZE) as usize;
+		let entry = HEADER_SIZE + at as u64;
+		let (start, stored, len) = (u64_at(index, at), u64_at(index, at + 8), u64_at(index, at + 16));
+		let mut offset = HEADER_SIZE + index.len() as u64;
+		if start < offset {
+			return Err(field(entry, "level offset"));
+		}
+		let expected = header.image_len(level).saturating_mul(faces as u64).saturating_mul(layers as u64);
+		if len != expected {
+			return Err(field(entry + 16, "uncompressed level length"));
+		}
+		if supercompression == Ktx2Supercompression::None && stored != expected {
+			return Err(field(entry + 8, "level length"));
+		}
+
+		// the descriptor and anything else before the level
+		let mut buf = [0; 512];
+		while offset < start {
+			let n = (start - offset).min(buf.len() as u64) as usize;
+			crate::read_exact(data, &mut buf[..n], offset)?;
+			offset += n as u64;
+		}
+
+		let input = Level { data, offset, left: stored };
+		let mut source = match supercompression {
+			Ktx2Supercompression::None => Source::Plain(input),
+			Ktx2Supercompression::Zlib => Source::Zlib(Box::new(Inflate::new(input))),
+		};
+		let image = layer as u64 * faces as u64 + face as u64;
+		source.skip(header.image_len(level).saturating_mul(image))?;
+
+		let (width, height) = header.level_size(level);
+		Ok(Self {
+			header,
+			source,
+			width,
+			height,
+			row: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &Ktx2Header {
+		&self.header
+	}
+
+	/// the size of the mip level being decoded.
+	pub fn size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+
+	/// reads the next row, a piece at a time, so memory is only taken up
+	/// once the data shows up.
+	fn read_row(&mut self) -> Result<(), Error> {
+		let mut left = self.width as usize * 4;
+		self.row.clear();
+		while left > 0 {
+			let n = left.min(1 << 16);
+			let start = self.row.len();
+			self.row.try_reserve(n).map_err(|_| oom())?;
+			self.row.resize(start + n, 0);
+			self.source.read(&mut self.row[start..])?;
+			left -= n;
+		}
+		Ok(())
+	}
+
+	/// skips the rest of the level, and returns where it ends. a compressed
+	/// level is decompressed to the end, so its checksum is checked.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		let mut buf = [0; 512];
+		if let Source::Zlib(inflate) = &mut self.source {
+			while inflate.read(&mut buf)? > 0 {}
+		}
+		let level = self.source.level();
+		while zlib::Input::read(level, &mut buf)? > 0 {}
+		Ok(level.offset)
+	}
+}
+
+impl<R: std::io::Read> Iterator for Ktx2Pixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.height {
+			return None;
+		}
+		if self.x == 0
+			&& let Err(e) = self.read_row()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let i = self.x as usize * 4;
+		let px = (self.row[i], self.row[i + 1], self.row[i + 2], self.row[i + 3]);
+		self.x += 1;
+		if self.x == self.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+/// `Ktx2Pixels`, ending at the first error, for `Format::decode`.
+struct Pixels<'a, R>(Ktx2Pixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// the data format descriptor of 8-bit rgba, as its total size and one
+/// basic block.
+fn descriptor(srgb: bool) -> Vec<u8> {
+	let mut dfd = vec![];
+	dfd.extend_from_slice(&92u32.to_le_bytes());
+	// khronos' basic descriptor, version 2, with 4 samples
+	dfd.extend_from_slice(&0u32.to_le_bytes());
+	dfd.extend_from_slice(&2u16.to_le_bytes());
+	dfd.extend_from_slice(&88u16.to_le_bytes());
+	// rgb with alpha, bt.709 primaries, the transfer function, and straight
+	// alpha
+	dfd.extend_from_slice(&[1, 1, if srgb { 2 } else { 1 }, 0]);
+	// a 1 by 1 texel block, of 4 bytes in one plane
+	dfd.extend_from_slice(&[0; 4]);
+	dfd.extend_from_slice(&[4, 0, 0, 0, 0, 0, 0, 0]);
+	// red, green, blue and alpha, 8 bits each from 0 to 255. alpha is
+	// linear even in srgb textures
+	for (i, channel) in [0u8, 1, 2, 15].into_iter().enumerate() {
+		let linear = if channel == 15 && srgb { 0x10 } else { 0 };
+		dfd.extend_from_slice(&(i as u16 * 8).to_le_bytes());
+		dfd.extend_from_slice(&[7, channel | linear]);
+		dfd.extend_from_slice(&[0; 4]);
+		dfd.extend_from_slice(&0u32.to_le_bytes());
+		dfd.extend_from_slice(&255u32.to_le_bytes());
+	}
+	dfd
+}
+
+/// checks `header` can be written.
+fn check(header: &Ktx2Header) -> Result<(), Error> {
+	let field = |offset, field| Error::InvalidHeader { offset, field };
+	if header.width == 0 {
+		return Err(field(20, "width"));
+	}
+	if header.height == 0 {
+		return Err(field(24, "height"));
+	}
+	if header.layers == 0 {
+		return Err(field(32, "layer count"));
+	}
+	// cube faces are square
+	if !matches!(header.faces, 1 | 6) || (header.faces == 6 && header.width != header.height) {
+		return Err(field(36, "face count"));
+	}
+	if header.levels == 0 || header.levels > header.levels_max() {
+		return Err(field(40, "level count"));
+	}
+	Ok(())
+}
+
+/// a level's pixels, as they're stored.
+fn compress(header: &Ktx2Header, data: Vec<u8>) -> Vec<u8> {
+	match header.supercompression {
+		Ktx2Supercompression::None => data,
+		Ktx2Supercompression::Zlib => {
+			let mut deflate = Deflate::new();
+			deflate.write(&data);
+			deflate.finish();
+			core::mem::take(deflate.output())
+		}
+	}
+}
+
+/// writes a texture of `levels`, the largest first, each as it's stored and
+/// how long its pixels are.
+fn write(header: &Ktx2Header, levels: &[(Vec<u8>, u64)], out: &mut impl std::io::Write) -> Result<(), Error> {
+	let dfd = descriptor(header.srgb);
+	let dfd_offset = HEADER_SIZE + levels.len() as u64 * LEVEL_SIZE;
+	let mut buf = vec![];
+	buf.extend_from_slice(&IDENTIFIER);
+	let layers = if header.layers == 1 { 0 } else { header.layers };
+	let supercompression = match header.supercompression {
+		Ktx2Supercompression::None => 0,
+		Ktx2Supercompression::Zlib => ZLIB,
+	};
+	let format = if header.srgb { SRGB } else { UNORM };
+	for x in [format, 1, header.width, header.height, 0, layers, header.faces, header.levels, supercompression] {
+		buf.extend_from_slice(&x.to_le_bytes());
+	}
+	// the descriptor, and no key/value or supercompression global data
+	for x in [dfd_offset as u32, dfd.len() as u32, 0, 0, 0, 0, 0, 0] {
+		buf.extend_from_slice(&x.to_le_bytes());
+	}
+
+	// levels are placed smallest first, but listed largest first
+	let mut offset = dfd_offset + dfd.len() as u64;
+	let mut offsets = vec![0; levels.len()];
+	for (i, (data, _)) in levels.iter().enumerate().rev() {
+		offsets[i] = offset;
+		offset += data.len() as u64;
+	}
+	for ((data, len), offset) in levels.iter().zip(offsets) {
+		let stored = match header.supercompression {
+			Ktx2Supercompression::None => *len,
+			Ktx2Supercompression::Zlib => data.len() as u64,
+		};
+		for x in [offset, stored, *len] {
+			buf.extend_from_slice(&x.to_le_bytes());
+		}
+	}
+	buf.extend_from_slice(&dfd);
+	crate::write_all(out, &buf)?;
+	for (data, _) in levels.iter().rev() {
+		crate::write_all(out, data)?;
+	}
+	Ok(())
+}
+
+/// encodes a texture of `images`, for each level from the largest, each
+/// layer, and each face, which has to be the size of its level. all of them
+/// are kept until the end, since the smallest level comes first.
+pub fn encode_images(images: impl IntoIterator<Item = crate::Image>, header: &Ktx2Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+	check(header)?;
+	let per_level = header.layers as u64 * header.faces as u64;
+	let mut levels = vec![];
+	let mut images = images.into_iter();
+	for level in 0..header.levels {
+		let mut data = vec![];
+		for _ in 0..per_level {
+			let Some(image) = images.next()
+			else {
+				return Err(Error::InvalidInput { reason: "there's an image missing" });
+			};
+			if (image.width(), image.height()) != header.level_size(level) {
+				return Err(Error::InvalidInput { reason: "image isn't the size of its level" });
+			}
+			data.try_reserve(image.pixels().len() * 4).map_err(|_| oom())?;
+			data.extend(image.pixels().iter().flat_map(|px| [px.0, px.1, px.2, px.3]));
+		}
+		let len = data.len() as u64;
+		levels.push((compress(header, data), len));
+	}
+	if images.next().is_some() {
+		return Err(Error::InvalidInput { reason: "there are more images than the texture has" });
+	}
+	write(header, &levels, out)
+}
+
+impl crate::Format for Ktx2 {
+	type Header = Ktx2Header;
+	type Options = ();
+
+	/// the first face of the first layer of the largest level.
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = Ktx2Pixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = Ktx2Pixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	/// writes a texture of one image, whatever the header says about levels,
+	/// layers and faces. a short iterator is a `PixelCountMismatch`, and
+	/// nothing is written.
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let header = Ktx2Header { levels: 1, layers: 1, faces: 1, ..header };
+		check(&header)?;
+		let len = header.image_len(0);
+		let mut bytes = vec![];
+		crate::push_exact(data, len / 4, |px| {
+			bytes.try_reserve(4).map_err(|_| oom())?;
+			bytes.extend_from_slice(&[px.0, px.1, px.2, px.3]);
+			Ok(())
+		})?;
+		write(&header, &[(compress(&header, bytes), len)], out)
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, ktx2};
+
+	const IMAGE_LEVELS: &[u8] = include_bytes!("../test/levels.ktx2");
+	const IMAGE_ZLIB: &[u8] = include_bytes!("../test/zlib.ktx2");
+
+	fn color(x: u32, y: u32, level: u32, image: u32) -> crate::Rgba {
+		((x * 40 + level * 3) as u8, (y * 50 + image * 7) as u8, (image * 30 + level) as u8, (255 - x - y) as u8)
+	}
+
+	#[test]
+	fn decode() {
+		// each image of a texture with two layers and key/value data, of
+		// which each level ends at the same place
+		for (level, end) in [(0, 348), (1, 228), (2, 212)] {
+			for layer in 0..2 {
+				let mut data = IMAGE_LEVELS;
+				let mut pixels = ktx2::Ktx2Pixels::image(&mut data, level, layer, 0).expect("error?");
+				let header = pixels.header();
+				assert_eq!((header.width, header.height, header.srgb), (5, 3, false));
+				assert_eq!((header.levels, header.layers, header.faces), (3, 2, 1));
+				assert_eq!(header.supercompression, ktx2::Ktx2Supercompression::None);
+				let (width, height) = pixels.size();
+				assert_eq!((width, height), header.level_size(level));
+				let mut n = 0;
+				for (i, px) in (&mut pixels).enumerate() {
+					let (x, y) = (i as u32 % width, i as u32 / width);
+					assert_eq!(px.expect("error?"), color(x, y, level, layer), "{level}, {layer}: {x}, {y}");
+					n += 1;
+				}
+				assert_eq!(n, width * height);
+				assert_eq!(pixels.finish().expect("error?"), end);
+			}
+		}
+		for (level, layer, face) in [(3, 0, 0), (0, 2, 0), (0, 0, 1)] {
+			let mut data = IMAGE_LEVELS;
+			let result = ktx2::Ktx2Pixels::image(&mut data, level, layer, face);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+		}
+
+		// each level of a compressed srgb texture, and where it ends from
+		// partway through
+		for (level, end) in [(0, 263), (1, 188)] {
+			let mut data = IMAGE_ZLIB;
+			let mut pixels = ktx2::Ktx2Pixels::image(&mut data, level, 0, 0).expect("error?");
+			assert_eq!((pixels.header().srgb, pixels.header().supercompression), (true, ktx2::Ktx2Supercompression::Zlib));
+			let (width, height) = pixels.size();
+			assert!((&mut pixels).map(|px| px.expect("error?")).eq((0..width * height).map(|i| color(i % width, i / width, level, 0))));
+			assert_eq!(pixels.finish().expect("error?"), end);
+
+			let mut data = IMAGE_ZLIB;
+			let mut pixels = ktx2::Ktx2Pixels::image(&mut data, level, 0, 0).expect("error?");
+			_ = pixels.next();
+			assert_eq!(pixels.finish().expect("error?"), end);
+		}
+
+		// the last face of a cube map
+		let mut data = &include_bytes!("../test/cube.ktx2")[..];
+		let pixels = ktx2::Ktx2Pixels::image(&mut data, 0, 0, 5).expect("error?");
+		assert_eq!((pixels.header().layers, pixels.header().faces), (1, 6));
+		assert!(pixels.map(|px| px.expect("error?")).eq((0..4).map(|i| color(i % 2, i / 2, 0, 5))));
+
+		// a 1d texture is a pixel tall
+		let mut data = &include_bytes!("../test/line.ktx2")[..];
+		let pixels = ktx2::Ktx2Pixels::new(&mut data).expect("error?");
+		assert_eq!(pixels.size(), (7, 1));
+		assert!(pixels.map(|px| px.expect("error?")).eq((0..7).map(|x| color(x, 0, 0, 0))));
+	}
+
+	#[test]
+	fn encode() {
+		// levels, layers and faces come back in the order they went in
+		for supercompression in [ktx2::Ktx2Supercompression::None, ktx2::Ktx2Supercompression::Zlib] {
+			let header = ktx2::Ktx2Header { width: 4, height: 4, srgb: true, levels: 3, layers: 2, faces: 6, supercompression };
+			let mut images = vec![];
+			for level in 0..3 {
+				let (width, height) = header.level_size(level);
+				for image in 0..12 {
+					let mut img = crate::Image::new(width, height, (0, 0, 0, 0)).expect("error?");
+					for (i, px) in img.pixels_mut().iter_mut().enumerate() {
+						*px = color(i as u32 % width, i as u32 / width, level, image);
+					}
+					images.push(img);
+				}
+			}
+			let mut out = vec![];
+			ktx2::encode_images(images.clone(), &header, &mut out).expect("error?");
+			for level in 0..3 {
+				for image in 0..12 {
+					let mut data = &out[..];
+					let pixels = ktx2::Ktx2Pixels::image(&mut data, level, image / 6, image % 6).expect("error?");
+					assert_eq!(pixels.header(), &header);
+					let expected = &images[(level * 12 + image) as usize];
+					assert!(pixels.map(|px| px.expect("error?")).eq(expected.pixels().iter().copied()), "{level}, {image}");
+				}
+			}
+
+			// which have to all be there, and the right size
+			let mut out = vec![];
+			let result = ktx2::encode_images(images[..35].iter().cloned(), &header, &mut out);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+			let result = ktx2::encode_images(images.iter().cloned().chain(images[..1].iter().cloned()), &header, &mut out);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+			let result = ktx2::encode_images(images[12..].iter().cloned(), &header, &mut out);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+		}
+
+		let header = ktx2::Ktx2Header { width: 4, height: 2, srgb: false, levels: 1, layers: 1, faces: 1, supercompression: ktx2::Ktx2Supercompression::None };
+		for (header, offset) in [
+			(ktx2::Ktx2Header { width: 0, ..header.clone() }, 20),
+			(ktx2::Ktx2Header { height: 0, ..header.clone() }, 24),
+			(ktx2::Ktx2Header { layers: 0, ..header.clone() }, 32),
+			(ktx2::Ktx2Header { faces: 6, ..header.clone() }, 36),
+			(ktx2::Ktx2Header { levels: 4, ..header.clone() }, 40),
+		] {
+			let result = ktx2::encode_images([], &header, &mut vec![]);
+			assert!(matches!(result, Err(Error::InvalidHeader { offset: o, .. }) if o == offset), "{result:?}");
+		}
+
+		// through `Format`, one image whatever the header says, which a
+		// short iterator doesn't fill
+		let pixels = (0..8).map(|i| color(i % 4, i / 4, 0, 0));
+		for supercompression in [ktx2::Ktx2Supercompression::None, ktx2::Ktx2Supercompression::Zlib] {
+			let header = ktx2::Ktx2Header { supercompression, ..header.clone() };
+			let mut out = vec![];
+			ktx2::Ktx2.encode(pixels.clone(), ktx2::Ktx2Header { levels: 3, layers: 2, ..header.clone() }, &mut out).expect("error?");
+			let mut data = &out[..];
+			let (decoded, iter) = ktx2::Ktx2.decode(&mut data).expect("error?");
+			assert_eq!(decoded, header);
+			assert!(iter.eq(pixels.clone()), "{supercompression:?}");
+
+			let mut out = vec![];
+			let result = ktx2::Ktx2.encode(pixels.clone().take(6), header, &mut out);
+			assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 8, got: 6 })), "{result:?}");
+			assert!(out.is_empty());
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = ktx2::Ktx2Pixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".ktx2")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("bad_format", Error::InvalidHeader { offset: 12, .. });
+		case!("bad_type_size", Error::InvalidHeader { offset: 16, .. });
+		case!("zero_width", Error::InvalidHeader { offset: 20, .. });
+		case!("depth", Error::InvalidHeader { offset: 28, .. });
+		case!("bad_faces", Error::InvalidHeader { offset: 36, .. });
+		case!("too_many_levels", Error::InvalidHeader { offset: 40, .. });
+		case!("bad_supercompression", Error::InvalidHeader { offset: 44, .. });
+		case!("bad_offset", Error::InvalidHeader { offset: 80, .. });
+		case!("bad_length", Error::InvalidHeader { offset: 88, .. });
+		case!("bad_uncompressed_length", Error::InvalidHeader { offset: 96, .. });
+		case!("truncated", Error::UnexpectedEof { offset: 308 });
+		case!("bad_zlib", Error::ChecksumMismatch { .. });
+		case!("short_zlib", Error::Malformed { .. });
+
+		assert!(decode(IMAGE_LEVELS).is_ok());
+		assert!(decode(IMAGE_ZLIB).is_ok());
+	}
+}
@@ -0,0 +1,145 @@
+/*!
+decoding straight into a caller's buffer.
+
+`Format::decode_into` writes each pixel to its place in the buffer as it's
+decoded, through `fold`, which the formats with a fast path override.
+*/
+
+use crate::{Error, Rgba};
+
+/// how pixels are laid out in a buffer of bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+	/// bytes a pixel, in r, g, b, a order: 3 for rgb, which drops alpha, or
+	/// 4 for rgba.
+	pub channels: u8,
+	/// bytes from the start of one row to the start of the next, which can
+	/// leave padding after each row. 0 packs them.
+	pub stride: u64,
+}
+
+impl Layout {
+	/// rows of `channels` bytes a pixel, without padding.
+	pub fn packed(channels: u8) -> Self {
+		Self { channels, stride: 0 }
+	}
+
+	/// the bytes a row takes up, and its stride.
+	fn row(&self, width: u32) -> Result<(u64, u64), Error> {
+		if !matches!(self.channels, 3 | 4) {
+			return Err(Error::InvalidInput { reason: "a layout has 3 or 4 channels" });
+		}
+		let row = width as u64 * self.channels as u64;
+		match self.stride {
+			0 => Ok((row, row)),
+			stride if stride < row => Err(Error::InvalidInput { reason: "the stride is shorter than a row" }),
+			stride => Ok((row, stride)),
+		}
+	}
+
+	/// the bytes a `width` by `height` image takes up, counting from the
+	/// start of the first row to the end of the last, which isn't padded.
+	pub fn size(&self, width: u32, height: u32) -> Result<u64, Error> {
+		let (row, stride) = self.row(width)?;
+		if row == 0 || height == 0 {
+			return Ok(0);
+		}
+		stride.checked_mul(height as u64 - 1).and_then(|x| x.checked_add(row))
+			.ok_or(Error::InvalidInput { reason: "the layout is too big to address" })
+	}
+}
+
+/// writes the `width` by `height` pixels of `pixels` into `buf`, which
+/// `Layout::size` has to fit in.
+pub(crate) fn fill(pixels: impl Iterator<Item = Result<Rgba, Error>>, width: u32, height: u32, buf: &mut [u8], layout: Layout) -> Result<(), Error> {
+	let size = layout.size(width, height)?;
+	if (buf.len() as u64) < size {
+		return Err(Error::BufferSize { expected: size, got: buf.len() as u64 });
+	}
+	if size == 0 {
+		return Ok(());
+	}
+
+	// the size fits in the buffer, so all of these fit in a usize
+	let (row, stride) = layout.row(width)?;
+	let (channels, padding) = (layout.channels as usize, (stride - row) as usize);
+	let (mut at, mut x, mut y) = (0, 0, 0);
+	let mut result = Ok(());
+	pixels.fold((), |(), px| {
+		if y == height || result.is_err() {
+			return;
+		}
+		match px {
+			Ok(px) => {
+				buf[at..at + channels].copy_from_slice(&[px.0, px.1, px.2, px.3][..channels]);
+				at += channels;
+				x += 1;
+				if x == width {
+					(x, y) = (0, y + 1);
+					at += padding;
+				}
+			}
+			Err(e) => result = Err(e),
+		}
+	});
+	result?;
+	if y < height {
+		let got = y as u64 * width as u64 + x as u64;
+		return Err(Error::PixelCountMismatch { expected: width as u64 * height as u64, got });
+	}
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Layout, layout};
+
+	#[test]
+	fn size() {
+		assert_eq!(Layout::packed(4).size(10, 3).expect("error?"), 120);
+		assert_eq!(Layout { channels: 3, stride: 32 }.size(10, 3).expect("error?"), 94);
+		assert_eq!(Layout::packed(3).size(0, 3).expect("error?"), 0);
+		assert!(matches!(Layout { channels: 4, stride: 39 }.size(10, 3), Err(Error::InvalidInput { .. })));
+		assert!(matches!(Layout::packed(2).size(10, 3), Err(Error::InvalidInput { .. })));
+		assert!(matches!(Layout { channels: 4, stride: u64::MAX }.size(1, 3), Err(Error::InvalidInput { .. })));
+	}
+
+	#[test]
+	fn fill() {
+		let pixels = || (0..6u8).map(|i| Ok((i, i + 10, i + 20, i + 30)));
+		let mut buf = [0xff; 20];
+		layout::fill(pixels(), 2, 3, &mut buf, Layout { channels: 3, stride: 7 }).expect("error?");
+		assert_eq!(buf, [0, 10, 20, 1, 11, 21, 0xff, 2, 12, 22, 3, 13, 23, 0xff, 4, 14, 24, 5, 15, 25]);
+
+		let mut buf = [0; 16];
+		let result = layout::fill(pixels(), 2, 3, &mut buf, Layout::packed(3));
+		assert!(matches!(result, Err(Error::BufferSize { expected: 18, got: 16 })), "{result:?}");
+		let result = layout::fill(pixels().take(3), 2, 2, &mut buf, Layout::packed(4));
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 4, got: 3 })), "{result:?}");
+		let failing = pixels().take(2).chain([Err(Error::Malformed { offset: 9, reason: "test" })]);
+		let result = layout::fill(failing, 2, 2, &mut buf, Layout::packed(4));
+		assert!(matches!(result, Err(Error::Malformed { offset: 9, .. })), "{result:?}");
+	}
+
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn decode_into() {
+		use crate::{Format, qoi};
+
+		let image = include_bytes!("../test/small.qoi");
+		let (_, pixels) = qoi::decode_to_vec(image).expect("error?");
+		let mut buf = vec![0; 4 * 20];
+		let header = qoi::Qoi.decode_into(&mut &image[..], &mut buf, Layout { channels: 4, stride: 20 }).expect("error?");
+		assert_eq!((header.width, header.height), (4, 4));
+		for (y, row) in pixels.chunks(4).enumerate() {
+			let expected = row.iter().flat_map(|px| [px.0, px.1, px.2, px.3]).collect::<Vec<_>>();
+			assert_eq!(buf[y * 20..y * 20 + 16], expected);
+		}
+
+		let result = qoi::Qoi.decode_into(&mut &image[..30], &mut buf, Layout::packed(4));
+		assert!(matches!(result, Err(Error::UnexpectedEof { offset: 30 })), "{result:?}");
+		let result = qoi::Qoi.decode_into(&mut &image[..], &mut buf[..47], Layout::packed(3));
+		assert!(matches!(result, Err(Error::BufferSize { expected: 48, got: 47 })), "{result:?}");
+	}
+}
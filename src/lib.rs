@@ -1,13 +1,856 @@
-/**
+/*!
 iterator based image format encoding.
+
+# features
+
+every format lives behind a feature of the same name, so only the codecs
+that are used get compiled. only `qoi` is on by default. at least one
+format has to be turned on, so `default-features = false` needs one back.
+
+- `qoi`: the `qoi` module.
+- `png`: the `png` module.
+- `bmp`: the `bmp` module.
+- `pnm`: the `pnm` module, pfm included.
+- `tga`: the `tga` module.
+- `gif`: the `gif` module.
+- `tiff`: the `tiff` module.
+- `jpeg`: the `jpeg` module, which only encodes.
+- `hdr`: the `hdr` module.
+- `exr`: the `exr` module, which only decodes.
+- `pcx`: the `pcx` module.
+- `ico`: the `ico` module, which turns on `bmp` and `png`.
+- `dds`: the `dds` module, which only decodes.
+- `ktx2`: the `ktx2` module.
+- `xbm`: the `xbm` module.
+- `xpm`: the `xpm` module.
+- `ras`: the `ras` module, which only decodes.
+- `sgi`: the `sgi` module.
+- `qoy`: the `qoy` module.
+- `qoir`: the `qoir` module, which only decodes.
+- `ilbm`: the `ilbm` module, which only decodes.
+- `psd`: the `psd` module, which only decodes.
+- `fits`: the `fits` module, which only decodes.
+- `raw`: the `raw` module.
+- `serde`: `Serialize` for headers and reports.
+- `futures-io`: `qoi::decode_async` and `qoi::encode_async`, over its
+  `AsyncRead` and `AsyncWrite`.
+- `std`: on by default, and turned on by every format but qoi. without
+  it the crate is `no_std`, and needs no allocator either: what's left is
+  the `io` traits, `QoiDecoder` and its `feed`, `qoi::decode_slice`, and
+  `QoiEncoder` with `qoi::encode_slice`.
+
+# panics
+
+nothing in dwelf panics, whatever it's given. malformed or malicious
+images, headers with absurd dimensions, and readers or writers that fail,
+block, or claim to have moved more bytes than they were handed all end in
+an error or a short iterator instead. allocations sized by untrusted data
+are made with `try_reserve`, so running out of memory is an error too.
+
+`tests/fuzz.rs` checks this with overflow checks on. the only panics that
+can come out are ones from closures and iterators the caller passed in.
 */
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(not(any_format))]
+compile_error!("dwelf needs at least one format's feature turned on, such as `qoi`");
+
+#[cfg(feature = "std")]
+pub mod adapters;
+#[cfg(feature = "std")]
+pub mod animation;
+#[cfg(feature = "std")]
+pub mod checksum;
+#[cfg(feature = "std")]
+pub mod detect;
+#[cfg(feature = "std")]
+pub mod dynamic;
+mod error;
+pub mod io;
+#[cfg(feature = "std")]
+mod facade;
+#[cfg(feature = "std")]
+mod image;
+#[cfg(feature = "std")]
+mod layout;
+#[cfg(feature = "std")]
+mod limits;
+#[cfg(feature = "std")]
+pub mod metadata;
+#[cfg(feature = "std")]
+pub mod pixel;
+#[cfg(feature = "std")]
+mod progress;
+#[cfg(feature = "std")]
+pub mod quantize;
+#[cfg(feature = "std")]
+pub mod registry;
+#[cfg(feature = "std")]
+pub mod rows;
+#[cfg(feature = "std")]
+mod seek;
+
 #[cfg(feature = "qoi")]
 pub mod qoi;
+#[cfg(feature = "png")]
+pub mod png;
+#[cfg(feature = "bmp")]
+pub mod bmp;
+#[cfg(feature = "pnm")]
+pub mod pnm;
+#[cfg(feature = "tga")]
+pub mod tga;
+#[cfg(feature = "gif")]
+pub mod gif;
+#[cfg(feature = "tiff")]
+pub mod tiff;
+#[cfg(feature = "jpeg")]
+pub mod jpeg;
+#[cfg(feature = "hdr")]
+pub mod hdr;
+#[cfg(feature = "exr")]
+pub mod exr;
+#[cfg(feature = "pcx")]
+pub mod pcx;
+#[cfg(feature = "ico")]
+pub mod ico;
+#[cfg(feature = "dds")]
+pub mod dds;
+#[cfg(feature = "ktx2")]
+pub mod ktx2;
+#[cfg(feature = "xbm")]
+pub mod xbm;
+#[cfg(feature = "xpm")]
+pub mod xpm;
+#[cfg(feature = "ras")]
+pub mod ras;
+#[cfg(feature = "sgi")]
+pub mod sgi;
+#[cfg(feature = "qoy")]
+pub mod qoy;
+#[cfg(feature = "qoir")]
+pub mod qoir;
+#[cfg(feature = "ilbm")]
+pub mod ilbm;
+#[cfg(feature = "psd")]
+pub mod psd;
+#[cfg(feature = "fits")]
+pub mod fits;
+#[cfg(feature = "raw")]
+pub mod raw;
+#[cfg(any(feature = "pnm", feature = "hdr", feature = "exr"))]
+mod tone;
+#[cfg(any(feature = "png", feature = "exr", feature = "ktx2"))]
+mod zlib;
+#[cfg(any(feature = "xbm", feature = "xpm"))]
+mod csource;
+
+#[cfg(feature = "std")]
+pub use animation::AnimatedFormat;
+#[cfg(feature = "std")]
+pub use detect::{Detected, detect};
+#[cfg(feature = "std")]
+pub use dynamic::{AnyHeader, DynFormat};
+pub use error::Error;
+#[cfg(feature = "std")]
+pub use facade::{Colorspace, ImageInfo, Reader, Writer};
+#[cfg(feature = "std")]
+pub use image::Image;
+#[cfg(feature = "std")]
+pub use layout::Layout;
+#[cfg(feature = "std")]
+pub use limits::Limits;
+#[cfg(feature = "std")]
+pub use metadata::Metadata;
+#[cfg(feature = "std")]
+pub use pixel::{Depth, Pixel};
+#[cfg(feature = "std")]
+pub use rows::Rows;
+#[cfg(feature = "std")]
+pub use seek::SeekDecode;
+
+/// a straight-alpha 8-bit rgba pixel.
+pub type Rgba = (u8, u8, u8, u8);
+
+/// a straight-alpha rgba pixel with 16 bits per channel, for formats that
+/// carry more precision than [`Rgba`].
+pub type Rgba16 = (u16, u16, u16, u16);
+
+/// [`Rgba`], by the name the other pixel types go by.
+pub type Rgba8 = Rgba;
 
+/// an opaque 8-bit rgb pixel.
+pub type Rgb8 = (u8, u8, u8);
+
+/// an opaque 8-bit gray pixel.
+pub type Gray8 = u8;
+
+/// a straight-alpha 8-bit gray pixel, gray first.
+pub type GrayAlpha8 = (u8, u8);
+
+/// a straight-alpha rgba pixel of floats, with 0 to 1 as the range of an
+/// integer sample. samples past it, for high dynamic range, are allowed.
+pub type RgbaF32 = (f32, f32, f32, f32);
+
+/// image dimensions, which every format's header carries.
+pub trait Dimensions {
+	fn width(&self) -> u32;
+	fn height(&self) -> u32;
+}
+
+#[cfg(feature = "std")]
 pub trait Format: Default {
-	type Header;
-	fn decode(self, data: &mut impl std::io::Read) -> Option<(Self::Header, impl Iterator<Item = (u8, u8, u8, u8)>)>;
-	fn encode(self, data: impl Iterator<Item = (u8, u8, u8, u8)>, header: Self::Header, out: &mut impl std::io::Write);
+	type Header: Dimensions;
+	/// settings that aren't part of the image, such as how hard to compress
+	/// it, for `decode_with_options` and `encode_with_options`. `()` for
+	/// formats that have none.
+	type Options: Default;
+	/// reads the header, and returns it with an iterator over the pixels, or
+	/// the error that stopped it from getting that far.
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Rgba>), Error>;
+	/// like `decode`, but the pixels are results, so an error partway through
+	/// the image can be told apart from its end. the error is the last item.
+	/// formats that can't fail after the header keep this default, which only
+	/// wraps `decode`.
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<Rgba, Error>>), Error> {
+		let (header, iter) = self.decode(data)?;
+		Ok((header, iter.map(Ok)))
+	}
+
+	/// reads just the header, leaving `data` where the pixels would be read
+	/// from next. that's right after the header for most formats, but some
+	/// read further to fill theirs in: png up to its image data, and tiff up
+	/// to its first directory.
+	fn decode_header(self, data: &mut impl std::io::Read) -> Result<Self::Header, Error> {
+		self.decode(data).map(|(header, _)| header)
+	}
+
+	/// like `decode_checked`, with `options`. formats without any that apply
+	/// to decoding keep this default, which ignores them.
+	fn decode_with_options(self, data: &mut impl std::io::Read, options: &Self::Options) -> Result<(Self::Header, impl Iterator<Item = Result<Rgba, Error>>), Error> {
+		_ = options;
+		self.decode_checked(data)
+	}
+
+	/// like `decode_checked`, but an image larger than `limits` allows is
+	/// `TooLarge`, before any pixels are decoded.
+	fn decode_with_limits(self, data: &mut impl std::io::Read, limits: &Limits) -> Result<(Self::Header, impl Iterator<Item = Result<Rgba, Error>>), Error> {
+		let (header, iter) = self.decode_checked(data)?;
+		limits.check(&header)?;
+		Ok((header, iter))
+	}
+
+	/// decodes into `buf`, laid out as `layout` says, rather than through an
+	/// iterator. a buffer too small for the image is `BufferSize`, before any
+	/// pixels are decoded, and any bytes between rows are left alone.
+	fn decode_into(self, data: &mut impl std::io::Read, buf: &mut [u8], layout: Layout) -> Result<Self::Header, Error> {
+		let (header, iter) = self.decode_checked(data)?;
+		layout::fill(iter, header.width(), header.height(), buf, layout)?;
+		Ok(header)
+	}
+
+	/// like `decode_checked`, with the pixels handed out a row at a time.
+	#[allow(clippy::type_complexity)]
+	fn decode_rows(self, data: &mut impl std::io::Read) -> Result<(Self::Header, Rows<impl Iterator<Item = Result<Rgba, Error>>>), Error> {
+		let (header, iter) = self.decode_checked(data)?;
+		let (width, height) = (header.width(), header.height());
+		Ok((header, Rows::new(iter, width, height)))
+	}
+
+	/// like `decode_checked`, but only the pixels of the `width` by `height`
+	/// rectangle whose top left is `(x, y)`. a rectangle that doesn't fit is
+	/// `OutOfBounds`. nothing past its last row is read, and the pixels it
+	/// skips are decoded and dropped, except by formats that override this
+	/// to skip them faster.
+	fn decode_sub_image(self, data: &mut impl std::io::Read, x: u32, y: u32, width: u32, height: u32) -> Result<(Self::Header, impl Iterator<Item = Result<Rgba, Error>>), Error> {
+		let (header, iter) = self.decode_checked(data)?;
+		let pixels = rows::SubImage::new(iter, rows::discard, &header, x, y, width, height)?;
+		Ok((header, pixels))
+	}
+
+	/// writes the image, stopping at the first error, which is returned.
+	/// fewer pixels than the header has room for is a `PixelCountMismatch`,
+	/// and more are left alone.
+	fn encode(self, data: impl Iterator<Item = Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error>;
+
+	/// like `encode`, with `options`. formats without any that apply to
+	/// encoding keep this default, which ignores them.
+	fn encode_with_options(self, data: impl Iterator<Item = Rgba>, header: Self::Header, options: &Self::Options, out: &mut impl std::io::Write) -> Result<(), Error> {
+		_ = options;
+		self.encode(data, header, out)
+	}
+
+	/// like `encode`, returning the number of bytes written. a writer's
+	/// error is returned over whatever the encoder made of it.
+	fn encode_counted(self, data: impl Iterator<Item = Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<u64, Error> {
+		let mut out = Counting::new(out);
+		let result = self.encode(data, header, &mut out);
+		let written = out.finish()?;
+		result?;
+		Ok(written)
+	}
+
+	/// like `encode`, but more pixels than the header has room for is a
+	/// `PixelCountMismatch` too. they're noticed once the image is written.
+	fn encode_exact(self, mut data: impl Iterator<Item = Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width() as u64 * header.height() as u64;
+		self.encode(data.by_ref(), header, out)?;
+		if data.next().is_some() {
+			return Err(Error::PixelCountMismatch { expected: total, got: total.saturating_add(1) });
+		}
+		Ok(())
+	}
+
+	/// like `encode`, from pixels that can fail, such as ones decoded from
+	/// another image. the first error ends the pixels, and is returned over
+	/// whatever the encoder made of them ending early. the encoder's own
+	/// errors are converted, so `E` can be the caller's error type.
+	fn encode_fallible<E: From<Error>>(self, data: impl Iterator<Item = Result<Rgba, E>>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), E> {
+		let mut error = None;
+		let pixels = data.map_while(|px| px.map_err(|e| error = Some(e)).ok()).fuse();
+		let result = self.encode(pixels, header, out);
+		match error {
+			Some(e) => Err(e),
+			None => Ok(result?),
+		}
+	}
+
+	/// like `decode_checked`, calling `f` with the rows done and the rows in
+	/// the image after each row is decoded. breaking ends the pixels with
+	/// `Error::Cancelled`.
+	fn decode_with_progress(self, data: &mut impl std::io::Read, f: impl FnMut(u32, u32) -> std::ops::ControlFlow<()>) -> Result<(Self::Header, impl Iterator<Item = Result<Rgba, Error>>), Error> {
+		let (header, iter) = self.decode_checked(data)?;
+		let mut progress = progress::Progress::new(iter, header.width(), header.height(), f);
+		let mut reported = false;
+		let pixels = std::iter::from_fn(move || match progress.next() {
+			None if progress.cancelled && !reported => {
+				reported = true;
+				Some(Err(Error::Cancelled))
+			}
+			px => px,
+		});
+		Ok((header, pixels))
+	}
+
+	/// like `encode`, calling `f` with the rows done and the rows in the
+	/// image after each row is handed to the encoder. breaking stops it with
+	/// `Error::Cancelled`, leaving whatever was written to `out`.
+	fn encode_with_progress(self, data: impl Iterator<Item = Rgba>, header: Self::Header, out: &mut impl std::io::Write, f: impl FnMut(u32, u32) -> std::ops::ControlFlow<()>) -> Result<(), Error> {
+		let mut progress = progress::Progress::new(data, header.width(), header.height(), f);
+		let result = self.encode(&mut progress, header, out);
+		if progress.cancelled {
+			return Err(Error::Cancelled);
+		}
+		result
+	}
+
+	/// like `decode`, with the pixels converted to `P`. they come by way of
+	/// `decode`, `decode_wide` or `decode_float`, whichever `P::DEPTH` says,
+	/// so formats that keep more than 8 bits, or floats, hand all of it over.
+	fn decode_as<P: Pixel>(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = P>), Error> {
+		Ok(match P::DEPTH {
+			Depth::Eight => {
+				let (header, iter) = self.decode(data)?;
+				(header, pixel::Paths::Eight(iter.map(P::from_rgba)))
+			}
+			Depth::Sixteen => {
+				let (header, iter) = self.decode_wide(data)?;
+				(header, pixel::Paths::Sixteen(iter.map(Pixel::convert)))
+			}
+			Depth::Float => {
+				let (header, iter) = self.decode_float(data)?;
+				(header, pixel::Paths::Float(iter.map(P::from_f32)))
+			}
+		})
+	}
+
+	/// like `encode`, from pixels of any type, by way of `encode`,
+	/// `encode_wide` or `encode_float`, whichever `P::DEPTH` says.
+	fn encode_from<P: Pixel>(self, data: impl Iterator<Item = P>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		match P::DEPTH {
+			Depth::Eight => self.encode(data.map(Pixel::to_rgba), header, out),
+			Depth::Sixteen => self.encode_wide(data.map(Pixel::convert), header, out),
+			Depth::Float => self.encode_float(data.map(Pixel::to_f32), header, out),
+		}
+	}
+
+	/// like `decode`, with 16 bits a sample. formats that store more than 8
+	/// override this to keep them, and the rest are widened.
+	fn decode_wide(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Rgba16>), Error> {
+		let (header, iter) = self.decode(data)?;
+		Ok((header, iter.map(pixel::widen)))
+	}
+
+	/// like `encode`, from 16 bits a sample. formats that can store more than
+	/// 8 override this to keep them, and the rest are narrowed.
+	fn encode_wide(self, data: impl Iterator<Item = Rgba16>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		self.encode(data.map(pixel::narrow), header, out)
+	}
+
+	/// like `decode`, with float samples. formats with float samples
+	/// override this to hand them over as they are, unclamped, and the rest
+	/// come from `decode_wide`, from 0 to 1.
+	fn decode_float(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = RgbaF32>), Error> {
+		let (header, iter) = self.decode_wide(data)?;
+		Ok((header, iter.map(Pixel::to_f32)))
+	}
+
+	/// like `encode`, from float samples. formats with float samples
+	/// override this to keep them, and the rest go to `encode_wide`, clamped
+	/// to 0 to 1.
+	fn encode_float(self, data: impl Iterator<Item = RgbaF32>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		self.encode_wide(data.map(Rgba16::from_f32), header, out)
+	}
+
+	/// like `decode`, but also collects whatever textual metadata the format carries.
+	/// formats without native metadata return an empty map.
+	fn decode_with_metadata(self, data: &mut impl std::io::Read) -> Result<(Self::Header, Metadata, impl Iterator<Item = Rgba>), Error> {
+		let (header, iter) = self.decode(data)?;
+		Ok((header, Metadata::new(), iter))
+	}
+
+	/// like `encode`, writing `metadata` in the format's native representation.
+	/// formats without one ignore it.
+	fn encode_with_metadata(self, data: impl Iterator<Item = Rgba>, header: Self::Header, metadata: &Metadata, out: &mut impl std::io::Write) -> Result<(), Error> {
+		_ = metadata;
+		self.encode(data, header, out)
+	}
+
+	/// the colorspace the pixels of an image with `header` are in, both as
+	/// they're decoded and as they're handed to the encoder. formats that
+	/// don't say are taken to be srgb.
+	fn colorspace(header: &Self::Header) -> Colorspace {
+		_ = header;
+		Colorspace::SRGB
+	}
+}
+
+
+/// counts bytes written through it, and keeps the first error whole, since
+/// encoders only see its kind.
+#[cfg(feature = "std")]
+pub(crate) struct Counting<W> {
+	inner: W,
+	written: u64,
+	error: Option<std::io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W> Counting<W> {
+	pub(crate) fn new(inner: W) -> Self {
+		Self {
+			inner,
+			written: 0,
+			error: None,
+		}
+	}
+
+	/// bytes written so far, or the first error.
+	pub(crate) fn finish(self) -> Result<u64, Error> {
+		match self.error {
+			Some(e) => Err(e.into()),
+			None => Ok(self.written),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> std::io::Write for Counting<W> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		match self.inner.write(buf) {
+			Ok(n) => {
+				let n = n.min(buf.len());
+				self.written += n as u64;
+				Ok(n)
+			}
+			Err(e) => {
+				let kind = e.kind();
+				self.error.get_or_insert(e);
+				Err(kind.into())
+			}
+		}
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.inner.flush()
+	}
 }
 
+/// `Write::write_all`, without its panic when the writer claims to have
+/// written more than it was given.
+#[cfg(feature = "std")]
+pub(crate) fn write_all(out: &mut impl std::io::Write, mut buf: &[u8]) -> Result<(), Error> {
+	while !buf.is_empty() {
+		match out.write(buf) {
+			Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into()),
+			Ok(n) => buf = &buf[n.min(buf.len())..],
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e.into()),
+		}
+	}
+	Ok(())
+}
+
+/// hands the first `total` pixels of `data` to `push`, stopping at its first
+/// error. fewer is a `PixelCountMismatch`, and the rest are left alone.
+#[cfg(any_encoder)]
+pub(crate) fn push_exact<P>(data: impl Iterator<Item = P>, total: u64, mut push: impl FnMut(P) -> Result<(), Error>) -> Result<(), Error> {
+	let mut got = 0;
+	for px in data.take(total.try_into().unwrap_or(usize::MAX)) {
+		push(px)?;
+		got += 1;
+	}
+	if got < total {
+		return Err(Error::PixelCountMismatch { expected: total, got });
+	}
+	Ok(())
+}
+
+/// `Read::read_exact`, reporting where the data ran out. `offset` is where
+/// `buf` starts in the stream.
+#[cfg(all(feature = "std", any_reader))]
+pub(crate) fn read_exact(data: &mut impl std::io::Read, buf: &mut [u8], offset: u64) -> Result<(), Error> {
+	let mut n = 0;
+	while n < buf.len() {
+		match data.read(&mut buf[n..]) {
+			Ok(0) => return Err(Error::UnexpectedEof { offset: offset.saturating_add(n as u64) }),
+			Ok(x) => n += x.min(buf.len() - n),
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e.into()),
+		}
+	}
+	Ok(())
+}
+
+/// encodes an image computed by `f`, which is called once for every `(x, y)`
+/// in row-major order. nothing is buffered.
+///
+/// returns the number of bytes written.
+#[cfg(feature = "std")]
+pub fn encode_with<F: Format>(
+	format: F,
+	header: F::Header,
+	out: &mut impl std::io::Write,
+	mut f: impl FnMut(u32, u32) -> Rgba,
+) -> Result<u64, Error> {
+	let width = header.width();
+	let height = header.height();
+
+	let (mut x, mut y) = (0, 0);
+	let iter = core::iter::from_fn(|| {
+		if width == 0 || y >= height {
+			return None;
+		}
+		let px = f(x, y);
+		x += 1;
+		if x == width {
+			x = 0;
+			y += 1;
+		}
+		Some(px)
+	});
+
+	format.encode_counted(iter, header, out)
+}
+
+/// how `transcode_with` goes between formats.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeOptions {
+	/// when the two headers are in different colorspaces, as
+	/// `Format::colorspace` says, convert the pixels from one to the other,
+	/// rather than failing with `ColorspaceMismatch`.
+	pub colorspace_convert: bool,
+	/// carry metadata across, through `decode_with_metadata` and
+	/// `encode_with_metadata`, so it's kept where both formats have a place
+	/// for it. those pixels can't fail, so an image cut short ends in the
+	/// encoder's `PixelCountMismatch`, rather than the decoder's error.
+	pub metadata: bool,
+}
+
+/// decodes `data` as `from` and encodes it as `to`, a pixel at a time, with
+/// the header `header` makes out of the decoded one. nothing is collected.
+/// an error decoding is returned over whatever the encoder made of the
+/// pixels stopping short. headers in different colorspaces are a
+/// `ColorspaceMismatch`, before anything is written; `transcode_with` can
+/// convert between them instead.
+///
+/// returns the number of bytes written.
+#[cfg(feature = "std")]
+pub fn transcode<F: Format, T: Format>(
+	from: F,
+	to: T,
+	data: &mut impl std::io::Read,
+	out: &mut impl std::io::Write,
+	header: impl FnOnce(&F::Header) -> T::Header,
+) -> Result<u64, Error> {
+	transcode_with(from, to, data, out, header, &TranscodeOptions::default())
+}
+
+/// like `transcode`, as `options` says. formats without a colorspace of
+/// their own are taken to be srgb, so a linear image written as one of them
+/// is converted too.
+#[cfg(feature = "std")]
+pub fn transcode_with<F: Format, T: Format>(
+	from: F,
+	to: T,
+	data: &mut impl std::io::Read,
+	out: &mut impl std::io::Write,
+	header: impl FnOnce(&F::Header) -> T::Header,
+	options: &TranscodeOptions,
+) -> Result<u64, Error> {
+	if options.metadata {
+		let (decoded, metadata, iter) = from.decode_with_metadata(data)?;
+		let header = header(&decoded);
+		convert::<F, T>(&decoded, to, header, iter.map(Ok), Some(&metadata), out, options)
+	} else {
+		let (decoded, iter) = from.decode_checked(data)?;
+		let header = header(&decoded);
+		convert::<F, T>(&decoded, to, header, iter, None, out, options)
+	}
+}
+
+/// the rest of `transcode_with`, once the image is decoded into `iter`.
+#[cfg(feature = "std")]
+fn convert<F: Format, T: Format>(
+	decoded: &F::Header,
+	to: T,
+	header: T::Header,
+	iter: impl Iterator<Item = Result<Rgba, Error>>,
+	metadata: Option<&Metadata>,
+	out: &mut impl std::io::Write,
+	options: &TranscodeOptions,
+) -> Result<u64, Error> {
+	let colorspaces = (F::colorspace(decoded), T::colorspace(&header));
+	if colorspaces.0 != colorspaces.1 && !options.colorspace_convert {
+		return Err(Error::ColorspaceMismatch { from: colorspaces.0, to: colorspaces.1 });
+	}
+
+	let mut error = None;
+	let mut iter = iter.map_while(|px| px.map_err(|e| error = Some(e)).ok());
+	let mut out = Counting::new(out);
+	let encode = |iter: &mut dyn Iterator<Item = Rgba>| match metadata {
+		Some(metadata) => to.encode_with_metadata(iter, header, metadata, &mut out),
+		None => to.encode(iter, header, &mut out),
+	};
+	let result = match colorspaces {
+		(Colorspace::SRGB, Colorspace::Linear) => encode(&mut adapters::srgb_to_linear(iter).map(Rgba::from_f32)),
+		(Colorspace::Linear, Colorspace::SRGB) => encode(&mut adapters::linear_to_srgb(iter.map(Rgba::to_f32))),
+		_ => encode(&mut iter),
+	};
+	let written = out.finish();
+	match error {
+		Some(e) => Err(e),
+		None => {
+			let written = written?;
+			result?;
+			Ok(written)
+		}
+	}
+}
+
+#[cfg(all(test, feature = "std", feature = "qoi"))]
+mod test {
+	use crate::{Format, qoi};
+
+	#[test]
+	fn encode_with() {
+		let gradient = |x: u32, y: u32| ((x * 16) as u8, (y * 32) as u8, 128, 255);
+
+		let header = qoi::QoiHeader {
+			width: 16,
+			height: 8,
+			channels: qoi::QoiHeaderChannels::RGB,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+
+		let mut calls = vec![];
+		let mut out = vec![];
+		let written = crate::encode_with(qoi::Qoi, header, &mut out, |x, y| {
+			calls.push((x, y));
+			gradient(x, y)
+		}).expect("error?");
+
+		assert_eq!(written, out.len() as u64);
+		assert_eq!(calls.len(), 16 * 8);
+		assert_eq!(calls[0], (0, 0));
+		assert_eq!(calls[17], (1, 1));
+
+		let mut data = &out[..];
+		let (_, iter) = qoi::Qoi.decode(&mut data).expect("error?");
+
+		for (i, px) in iter.enumerate() {
+			let (x, y) = (i as u32 % 16, i as u32 / 16);
+			assert_eq!(px, gradient(x, y), "{x}, {y}");
+		}
+	}
+
+	#[test]
+	fn transcode() {
+		let image = include_bytes!("../test/small.qoi");
+		let (header, pixels) = qoi::decode_to_vec(image).expect("error?");
+		let rgb = |header: &qoi::QoiHeader| qoi::QoiHeader { channels: qoi::QoiHeaderChannels::RGB, ..header.clone() };
+		let mut out = vec![];
+		let written = crate::transcode(qoi::Qoi, qoi::Qoi, &mut &image[..], &mut out, rgb).expect("error?");
+		assert_eq!(written, out.len() as u64);
+		assert_eq!(qoi::decode_to_vec(&out).expect("error?"), (rgb(&header), pixels));
+
+		// the decoder's error, rather than the encoder's count
+		let result = crate::transcode(qoi::Qoi, qoi::Qoi, &mut &image[..30], &mut vec![], rgb);
+		assert!(matches!(result, Err(crate::Error::UnexpectedEof { offset: 30 })), "{result:?}");
+	}
+
+	#[test]
+	fn transcode_colorspace() {
+		use crate::{Colorspace, Error, TranscodeOptions};
+
+		let header = |colorspace| qoi::QoiHeader { width: 2, height: 1, channels: qoi::QoiHeaderChannels::RGBA, colorspace };
+		let tagged = |colorspace| move |header: &qoi::QoiHeader| qoi::QoiHeader { colorspace, ..header.clone() };
+		let (srgb, linear) = (qoi::QoiHeaderColorspace::SRGB, qoi::QoiHeaderColorspace::Linear);
+		let image = qoi::encode_to_vec(header(linear), &[(128, 128, 128, 255), (0, 255, 64, 100)]).expect("error?");
+
+		// copying the samples over would make it too dark
+		let mut out = vec![];
+		let result = crate::transcode(qoi::Qoi, qoi::Qoi, &mut &image[..], &mut out, tagged(srgb));
+		assert!(matches!(result, Err(Error::ColorspaceMismatch { from: Colorspace::Linear, to: Colorspace::SRGB })), "{result:?}");
+		assert!(out.is_empty());
+
+		// mid-gray in linear light is about 188 in srgb, and comes back
+		let options = TranscodeOptions { colorspace_convert: true, ..Default::default() };
+		crate::transcode_with(qoi::Qoi, qoi::Qoi, &mut &image[..], &mut out, tagged(srgb), &options).expect("error?");
+		let (decoded, pixels) = qoi::decode_to_vec(&out).expect("error?");
+		assert_eq!((decoded, &pixels[..]), (header(srgb), &[(188, 188, 188, 255), (0, 255, 137, 100)][..]));
+		let mut back = vec![];
+		crate::transcode_with(qoi::Qoi, qoi::Qoi, &mut &out[..], &mut back, tagged(linear), &options).expect("error?");
+		assert_eq!(qoi::decode_to_vec(&back).expect("error?").1, [(128, 128, 128, 255), (0, 255, 64, 100)]);
+	}
+
+	#[cfg(feature = "png")]
+	#[test]
+	fn transcode_png() {
+		use crate::png;
+
+		let image = include_bytes!("../test/small.qoi");
+		let mut out = vec![];
+		let header = |header: &qoi::QoiHeader| png::PngHeader { width: header.width, height: header.height, color_type: png::PngColorType::RGBA, bit_depth: 8, interlaced: false, palette: vec![] };
+		crate::transcode(qoi::Qoi, png::Png, &mut &image[..], &mut out, header).expect("error?");
+		let mut data = &out[..];
+		let (_, iter) = png::Png.decode(&mut data).expect("error?");
+		assert_eq!(iter.collect::<Vec<_>>(), qoi::decode_to_vec(image).expect("error?").1);
+	}
+
+	#[cfg(all(feature = "png", feature = "tiff"))]
+	#[test]
+	fn transcode_metadata() {
+		use crate::{Metadata, TranscodeOptions, metadata, png, tiff};
+
+		let (header, pixels) = qoi::decode_to_vec(include_bytes!("../test/small.qoi")).expect("error?");
+		let mut text = Metadata::new();
+		text.insert(metadata::DESCRIPTION, "a small image");
+		text.insert(metadata::AUTHOR, "someone");
+		let header = png::PngHeader { width: header.width, height: header.height, color_type: png::PngColorType::RGBA, bit_depth: 8, interlaced: false, palette: vec![] };
+		let mut image = vec![];
+		png::Png.encode_with_metadata(pixels.iter().copied(), header, &text, &mut image).expect("error?");
+		assert!(image.windows(4).any(|x| x == b"tEXt"));
+
+		let to_tiff = |header: &png::PngHeader| tiff::TiffHeader { width: header.width, height: header.height, color_type: tiff::TiffColorType::RGBA, compression: tiff::TiffCompression::None, big_endian: false };
+		let options = TranscodeOptions { metadata: true, ..Default::default() };
+		let mut out = vec![];
+		let written = crate::transcode_with(png::Png, tiff::Tiff, &mut &image[..], &mut out, to_tiff, &options).expect("error?");
+		assert_eq!(written, out.len() as u64);
+		let mut data = &out[..];
+		let (_, decoded, iter) = tiff::Tiff.decode_with_metadata(&mut data).expect("error?");
+		assert_eq!(decoded.get_text(metadata::DESCRIPTION), Some("a small image"));
+		assert_eq!(decoded.get_text(metadata::AUTHOR), Some("someone"));
+		assert_eq!(iter.collect::<Vec<_>>(), pixels);
+
+		// left behind without asking for it, and by formats with no place for it
+		let mut out = vec![];
+		crate::transcode(png::Png, tiff::Tiff, &mut &image[..], &mut out, to_tiff).expect("error?");
+		assert!(tiff::Tiff.decode_with_metadata(&mut &out[..]).expect("error?").1.is_empty());
+		let to_qoi = |header: &png::PngHeader| qoi::QoiHeader { width: header.width, height: header.height, channels: qoi::QoiHeaderChannels::RGBA, colorspace: qoi::QoiHeaderColorspace::SRGB };
+		let mut out = vec![];
+		crate::transcode_with(png::Png, qoi::Qoi, &mut &image[..], &mut out, to_qoi, &options).expect("error?");
+		assert_eq!(qoi::decode_to_vec(&out).expect("error?").1, pixels);
+	}
+
+	#[test]
+	fn encode_counted() {
+		let (header, pixels) = qoi::decode_to_vec(include_bytes!("../test/small.qoi")).expect("error?");
+		// only what this encode wrote is counted
+		let mut out = vec![1, 2, 3];
+		let written = qoi::Qoi.encode_counted(pixels.iter().copied(), header.clone(), &mut out).expect("error?");
+		assert_eq!(written, out.len() as u64 - 3);
+
+		let mut full = [0; 20];
+		let result = qoi::Qoi.encode_counted(pixels.into_iter(), header, &mut &mut full[..]);
+		assert!(matches!(result, Err(crate::Error::Io(ref e)) if e.kind() == std::io::ErrorKind::WriteZero), "{result:?}");
+	}
+
+	#[test]
+	fn pixel_count() {
+		use crate::Error;
+
+		let (header, pixels) = qoi::decode_to_vec(include_bytes!("../test/small.qoi")).expect("error?");
+		let result = qoi::Qoi.encode(pixels[1..].iter().copied(), header.clone(), &mut vec![]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 16, got: 15 })), "{result:?}");
+
+		// more is only an error when it's exact
+		let more = || pixels.iter().copied().chain([(0, 0, 0, 255)]);
+		let mut out = vec![];
+		qoi::Qoi.encode(more(), header.clone(), &mut out).expect("error?");
+		assert_eq!(out, include_bytes!("../test/small.qoi"));
+		let result = qoi::Qoi.encode_exact(more(), header.clone(), &mut vec![]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 16, got: 17 })), "{result:?}");
+		qoi::Qoi.encode_exact(pixels.iter().copied(), header, &mut vec![]).expect("error?");
+	}
+
+	#[test]
+	fn encode_fallible() {
+		use crate::Error;
+
+		#[derive(Debug)]
+		enum Source {
+			Broken(usize),
+			Encoder(Error),
+		}
+
+		impl From<Error> for Source {
+			fn from(e: Error) -> Self {
+				Self::Encoder(e)
+			}
+		}
+
+		let image = include_bytes!("../test/small.qoi");
+		let (header, pixels) = qoi::decode_to_vec(image).expect("error?");
+		let mut out = vec![];
+		qoi::Qoi.encode_fallible(pixels.iter().map(|&px| Ok::<_, Source>(px)), header.clone(), &mut out).expect("error?");
+		assert_eq!(out, image);
+
+		// the source's error, rather than the pixels running out, and nothing
+		// after it is pulled
+		let mut pulled = 0;
+		let failing = pixels.iter().enumerate().map(|(i, &px)| {
+			pulled += 1;
+			if i == 5 { Err(Source::Broken(i)) } else { Ok(px) }
+		});
+		let result = qoi::Qoi.encode_fallible(failing, header.clone(), &mut vec![]);
+		assert!(matches!(result, Err(Source::Broken(5))), "{result:?}");
+		assert_eq!(pulled, 6);
+
+		let result = qoi::Qoi.encode_fallible(pixels[1..].iter().map(|&px| Ok::<_, Source>(px)), header.clone(), &mut vec![]);
+		assert!(matches!(result, Err(Source::Encoder(Error::PixelCountMismatch { expected: 16, got: 15 }))), "{result:?}");
+
+		// straight from another decode
+		let mut data = &image[..];
+		let (_, decoded) = qoi::Qoi.decode_checked(&mut data).expect("error?");
+		let mut out = vec![];
+		qoi::Qoi.encode_fallible(decoded, header.clone(), &mut out).expect("error?");
+		assert_eq!(out, image);
+		let mut data = &image[..30];
+		let (_, decoded) = qoi::Qoi.decode_checked(&mut data).expect("error?");
+		let result = qoi::Qoi.encode_fallible(decoded, header, &mut vec![]);
+		assert!(matches!(result, Err(Error::UnexpectedEof { offset: 30 })), "{result:?}");
+	}
+}
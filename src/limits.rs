@@ -0,0 +1,79 @@
+/*!
+limits on the images a decode will go ahead with.
+
+a header can claim billions of pixels in a few bytes, which is fine for an
+iterator, but not for whatever collects it. `Format::decode_with_limits`
+checks the header against `Limits` before any pixels are decoded.
+*/
+
+use crate::{Dimensions, Error};
+
+/// the largest image to decode. `None` is no limit, which is the default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Limits {
+	pub max_width: Option<u32>,
+	pub max_height: Option<u32>,
+	/// width times height.
+	pub max_pixels: Option<u64>,
+	/// the bytes the pixels take up as `Rgba`, at 4 a pixel. past `u64::MAX`
+	/// they're counted as that.
+	pub max_bytes: Option<u64>,
+}
+
+impl Limits {
+	/// `TooLarge` for the first limit `header` goes over.
+	pub fn check(&self, header: &impl Dimensions) -> Result<(), Error> {
+		let (width, height) = (header.width() as u64, header.height() as u64);
+		let checks = [
+			("width", width, self.max_width.map(u64::from)),
+			("height", height, self.max_height.map(u64::from)),
+			("pixels", width * height, self.max_pixels),
+			("bytes", (width * height).saturating_mul(4), self.max_bytes),
+		];
+		for (what, value, limit) in checks {
+			if let Some(limit) = limit.filter(|&limit| value > limit) {
+				return Err(Error::TooLarge { what, value, limit });
+			}
+		}
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Image, Limits};
+
+	#[test]
+	fn check() {
+		let image = Image::new(30, 20, (0, 0, 0, 0)).expect("error?");
+		assert!(Limits::default().check(&image).is_ok());
+		let limits = Limits { max_width: Some(30), max_height: Some(20), max_pixels: Some(600), max_bytes: Some(2400) };
+		assert!(limits.check(&image).is_ok());
+
+		let over = |limits: Limits| limits.check(&image).map_err(|e| match e {
+			Error::TooLarge { what, value, limit } => (what, value, limit),
+			e => panic!("{e:?}"),
+		});
+		assert_eq!(over(Limits { max_width: Some(29), ..limits.clone() }), Err(("width", 30, 29)));
+		assert_eq!(over(Limits { max_height: Some(0), ..limits.clone() }), Err(("height", 20, 0)));
+		assert_eq!(over(Limits { max_pixels: Some(599), ..limits.clone() }), Err(("pixels", 600, 599)));
+		assert_eq!(over(Limits { max_bytes: Some(2000), ..limits.clone() }), Err(("bytes", 2400, 2000)));
+	}
+
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn decode_with_limits() {
+		use crate::{Format, qoi};
+
+		// a header claiming the most pixels it can, and none of them
+		let mut image = b"qoif\xff\xff\xff\xff\xff\xff\xff\xff\x04\x00".as_slice();
+		let limits = Limits { max_pixels: Some(1 << 24), ..Limits::default() };
+		let result = qoi::Qoi.decode_with_limits(&mut image, &limits);
+		assert!(matches!(result, Err(Error::TooLarge { what: "pixels", limit: 0x1000000, .. })));
+
+		let mut image = &include_bytes!("../test/small.qoi")[..];
+		let (_, iter) = qoi::Qoi.decode_with_limits(&mut image, &limits).expect("error?");
+		assert_eq!(iter.count(), 16);
+	}
+}
@@ -0,0 +1,137 @@
+/*!
+textual metadata carried alongside an image.
+
+keys are plain strings. formats that have a native representation
+(png `tEXt`, tiff tags, gif comments) map the well-known keys onto it;
+formats without one ignore the map.
+*/
+
+pub const TITLE: &str = "Title";
+pub const AUTHOR: &str = "Author";
+pub const DESCRIPTION: &str = "Description";
+pub const COMMENT: &str = "Comment";
+pub const SOFTWARE: &str = "Software";
+pub const CREATION_TIME: &str = "Creation Time";
+/// raw exif, as bytes, starting with the tiff header.
+pub const EXIF: &str = "Exif";
+/// an icc color profile, as bytes.
+pub const ICC_PROFILE: &str = "ICC Profile";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataValue {
+	Text(String),
+	Bytes(Vec<u8>),
+}
+
+impl MetadataValue {
+	pub fn as_text(&self) -> Option<&str> {
+		match self {
+			Self::Text(x) => Some(x),
+			Self::Bytes(_) => None,
+		}
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		match self {
+			Self::Text(x) => x.as_bytes(),
+			Self::Bytes(x) => x,
+		}
+	}
+}
+
+impl From<&str> for MetadataValue {
+	fn from(value: &str) -> Self {
+		Self::Text(value.into())
+	}
+}
+
+impl From<String> for MetadataValue {
+	fn from(value: String) -> Self {
+		Self::Text(value)
+	}
+}
+
+impl From<Vec<u8>> for MetadataValue {
+	fn from(value: Vec<u8>) -> Self {
+		Self::Bytes(value)
+	}
+}
+
+/// key/value map that keeps insertion order, since most formats store
+/// their entries sequentially and round trips should not shuffle them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+	entries: Vec<(String, MetadataValue)>,
+}
+
+impl Metadata {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+		self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+	}
+
+	pub fn get_text(&self, key: &str) -> Option<&str> {
+		self.get(key)?.as_text()
+	}
+
+	/// sets `key`, replacing and returning the previous value if there was one.
+	pub fn insert(&mut self, key: impl Into<String>, value: impl Into<MetadataValue>) -> Option<MetadataValue> {
+		let key = key.into();
+		let value = value.into();
+		match self.entries.iter_mut().find(|(k, _)| *k == key) {
+			Some((_, v)) => Some(core::mem::replace(v, value)),
+			None => {
+				self.entries.push((key, value));
+				None
+			}
+		}
+	}
+
+	pub fn remove(&mut self, key: &str) -> Option<MetadataValue> {
+		let index = self.entries.iter().position(|(k, _)| k == key)?;
+		Some(self.entries.remove(index).1)
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &MetadataValue)> {
+		self.entries.iter().map(|(k, v)| (k.as_str(), v))
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::metadata::{self, Metadata, MetadataValue};
+
+	#[test]
+	fn insert() {
+		let mut meta = Metadata::new();
+
+		assert_eq!(meta.insert(metadata::TITLE, "a"), None);
+		assert_eq!(meta.insert(metadata::AUTHOR, "b"), None);
+		assert_eq!(meta.insert("Raw", vec![0, 1, 2]), None);
+		assert_eq!(meta.insert(metadata::TITLE, "c"), Some(MetadataValue::Text("a".into())));
+
+		assert_eq!(meta.len(), 3);
+		assert_eq!(meta.get_text(metadata::TITLE), Some("c"));
+		assert_eq!(meta.get_text("Raw"), None);
+		assert_eq!(meta.get("Raw").map(MetadataValue::as_bytes), Some(&[0, 1, 2][..]));
+
+		let keys = meta.iter().map(|(k, _)| k).collect::<Vec<_>>();
+		assert_eq!(keys, [metadata::TITLE, metadata::AUTHOR, "Raw"]);
+
+		assert!(meta.remove(metadata::AUTHOR).is_some());
+		assert!(meta.get(metadata::AUTHOR).is_none());
+	}
+}
+
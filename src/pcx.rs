@@ -0,0 +1,615 @@
+/*!
+pcx images.
+
+8-bit color-mapped images, and 24-bit ones stored as three planes of red,
+green and blue, are decoded and encoded. the header is 128 bytes, with the
+image's corners, the number of planes, and the bytes each plane of a row
+takes up, which is at least the width and usually rounded up to even.
+
+rows are usually run-length encoded: a byte with the top two bits set
+repeats the byte after it, as many times as its other bits say, and any
+other byte is itself. runs are allowed to go on from one plane or row to
+the next, though the encoder never does.
+
+color-mapped images have their 256-entry color map after the pixels, so
+the pixels are read and kept whole before the first one is handed out, and
+24-bit ones are read a row at a time. the encoder makes the color map from
+the colors it's given, in the order they show up, and writes it last. its
+alpha is dropped.
+*/
+
+use std::collections::HashMap;
+
+use crate::Error;
+use crate::error::oom;
+
+const MANUFACTURER: u8 = 0x0a;
+const HEADER_SIZE: usize = 128;
+/// the byte before the color map.
+const PALETTE: u8 = 0x0c;
+/// the top bits of a run's first byte, and the most it can repeat.
+const RUN: u8 = 0xc0;
+const RUN_MAX: u8 = 0x3f;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PcxColorType {
+	/// 8-bit indices into the color map.
+	Indexed,
+	/// three 8-bit planes.
+	RGB,
+}
+
+impl PcxColorType {
+	fn planes(self) -> usize {
+		match self {
+			Self::Indexed => 1,
+			Self::RGB => 3,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PcxHeader {
+	pub width: u32,
+	pub height: u32,
+	pub color_type: PcxColorType,
+	/// whether rows are run-length encoded, which they almost always are.
+	pub rle: bool,
+}
+
+impl crate::Dimensions for PcxHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Pcx;
+
+impl Default for Pcx {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// pixels of a pcx image, read from the reader as they're asked for, or all
+/// at once before the first for color-mapped images.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct PcxPixels<'a, R> {
+	header: PcxHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// the bytes each plane of a row takes up.
+	stride: usize,
+	/// what's left of a run that went on past the last plane read.
+	run: (u8, u8),
+	/// the planes of the current row, or every row of a color-mapped image.
+	rows: Vec<u8>,
+	palette: Vec<crate::Rgba>,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> PcxPixels<'a, R> {
+	/// reads the header, up to the first row.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; HEADER_SIZE];
+		crate::read_exact(data, &mut buf, 0)?;
+		if buf[0] != MANUFACTURER {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let rle = match buf[2] {
+			0 => false,
+			1 => true,
+			_ => return Err(field(2, "encoding")),
+		};
+		if buf[3] != 8 {
+			return Err(field(3, "bits per pixel"));
+		}
+		let at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]) as u32;
+		let Some(width) = at(8).checked_sub(at(4)).map(|x| x + 1)
+		else {
+			return Err(field(8, "width"));
+		};
+		let Some(height) = at(10).checked_sub(at(6)).map(|x| x + 1)
+		else {
+			return Err(field(10, "height"));
+		};
+		let color_type = match buf[65] {
+			1 => PcxColorType::Indexed,
+			3 => PcxColorType::RGB,
+			_ => return Err(field(65, "planes")),
+		};
+		let stride = at(66) as usize;
+		if stride < width as usize {
+			return Err(field(66, "bytes per line"));
+		}
+
+		Ok(Self {
+			header: PcxHeader { width, height, color_type, rle },
+			data,
+			offset: HEADER_SIZE as u64,
+			stride,
+			run: (0, 0),
+			rows: vec![],
+			palette: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &PcxHeader {
+		&self.header
+	}
+
+	/// the color map of a color-mapped image, once the first pixel has been
+	/// read.
+	pub fn palette(&self) -> &[crate::Rgba] {
+		&self.palette
+	}
+
+	fn byte(&mut self) -> Result<u8, Error> {
+		let mut b = [0];
+		crate::read_exact(self.data, &mut b, self.offset)?;
+		self.offset = self.offset.saturating_add(1);
+		Ok(b[0])
+	}
+
+	/// reads `len` bytes of planes onto the end of `rows`.
+	fn read(&mut self, mut len: usize) -> Result<(), Error> {
+		self.rows.try_reserve(len.min(1 << 16)).map_err(|_| oom())?;
+		if !self.header.rle {
+			while len > 0 {
+				let n = len.min(1 << 16);
+				let at = self.rows.len();
+				self.rows.try_reserve(n).map_err(|_| oom())?;
+				self.rows.resize(at + n, 0);
+				crate::read_exact(self.data, &mut self.rows[at..], self.offset)?;
+				self.offset = self.offset.saturating_add(n as u64);
+				len -= n;
+			}
+			return Ok(());
+		}
+		while len > 0 {
+			if self.run.0 == 0 {
+				let b = self.byte()?;
+				self.run = match b & RUN == RUN {
+					true => (b & RUN_MAX, self.byte()?),
+					false => (1, b),
+				};
+			}
+			let n = (self.run.0 as usize).min(len);
+			self.rows.try_reserve(n).map_err(|_| oom())?;
+			self.rows.resize(self.rows.len() + n, self.run.1);
+			self.run.0 -= n as u8;
+			len -= n;
+		}
+		Ok(())
+	}
+
+	/// reads the next row, or every row and the color map.
+	fn read_rows(&mut self) -> Result<(), Error> {
+		let planes = self.header.color_type.planes();
+		if self.header.color_type == PcxColorType::RGB {
+			self.rows.clear();
+			return self.read(self.stride * planes);
+		}
+		for _ in 0..self.header.height {
+			self.read(self.stride)?;
+		}
+
+		let start = self.offset;
+		if self.byte()? != PALETTE {
+			return Err(Error::Malformed { offset: start, reason: "color map is missing" });
+		}
+		let mut map = [0; 768];
+		crate::read_exact(self.data, &mut map, self.offset)?;
+		self.offset = self.offset.saturating_add(map.len() as u64);
+		self.palette = map.chunks_exact(3).map(|c| (c[0], c[1], c[2], 255)).collect();
+		Ok(())
+	}
+
+	/// skips any pixels that are left, and returns the number of bytes the
+	/// image took up. nothing after the pixels, or the color map, is read,
+	/// though the rest of a run that goes past the last pixel is.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		let indexed = self.header.color_type == PcxColorType::Indexed;
+		if indexed && (self.x, self.y) == (0, 0) {
+			self.read_rows()?;
+		}
+		else if !indexed {
+			// a row at a time, and only what's left of the current one
+			let start = if self.x > 0 { self.y + 1 } else { self.y };
+			for _ in start..self.header.height {
+				self.rows.clear();
+				self.read(self.stride * 3)?;
+			}
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<R: std::io::Read> Iterator for PcxPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		let indexed = self.header.color_type == PcxColorType::Indexed;
+		if self.x == 0 && (!indexed || self.y == 0)
+			&& let Err(e) = self.read_rows()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let x = self.x as usize;
+		let px = match indexed {
+			true => self.palette[self.rows[self.y as usize * self.stride + x] as usize],
+			false => (self.rows[x], self.rows[self.stride + x], self.rows[self.stride * 2 + x], 255),
+		};
+
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+/// `PcxPixels`, ending at the first error, for `Format::decode`.
+struct Pixels<'a, R>(PcxPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// a plane of a row, run-length encoded.
+fn pack(plane: &[u8], out: &mut Vec<u8>) {
+	let mut i = 0;
+	while i < plane.len() {
+		let x = plane[i];
+		let run = plane[i..].iter().take(RUN_MAX as usize).take_while(|&&b| b == x).count();
+		if run > 1 || x & RUN == RUN {
+			out.extend_from_slice(&[RUN | run as u8, x]);
+		}
+		else {
+			out.push(x);
+		}
+		i += run;
+	}
+}
+
+/// push-based encoder state, which `Pcx::encode` drives.
+#[derive(Debug)]
+pub(crate) struct PcxEncoder<W> {
+	out: W,
+	color_type: PcxColorType,
+	rle: bool,
+	width: usize,
+	stride: usize,
+	/// where each color goes in the color map, for color-mapped images.
+	indices: HashMap<crate::Rgba, u8>,
+	/// the current row's planes, one after another.
+	row: Vec<u8>,
+	x: usize,
+	packed: Vec<u8>,
+}
+
+impl<W: std::io::Write> PcxEncoder<W> {
+	/// writes the header.
+	pub(crate) fn new(header: &PcxHeader, mut out: W) -> Result<Self, Error> {
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		// the bytes a plane of a row takes up has to fit too
+		if header.width == 0 || header.width >= u16::MAX as u32 {
+			return Err(field(8, "width"));
+		}
+		if header.height == 0 || header.height > u16::MAX as u32 + 1 {
+			return Err(field(10, "height"));
+		}
+		let width = header.width as usize;
+		// rounded up to even, as the spec asks
+		let stride = width + width % 2;
+		let planes = header.color_type.planes();
+
+		let mut buf = [0; HEADER_SIZE];
+		buf[0] = MANUFACTURER;
+		// 3.0, with a 256-entry color map for color-mapped images
+		buf[1] = 5;
+		buf[2] = header.rle as u8;
+		buf[3] = 8;
+		buf[8..10].copy_from_slice(&(header.width - 1).to_le_bytes()[..2]);
+		buf[10..12].copy_from_slice(&(header.height - 1).to_le_bytes()[..2]);
+		buf[12..14].copy_from_slice(&72u16.to_le_bytes());
+		buf[14..16].copy_from_slice(&72u16.to_le_bytes());
+		buf[65] = planes as u8;
+		buf[66..68].copy_from_slice(&(stride as u16).to_le_bytes());
+		// color, rather than gray
+		buf[68] = 1;
+		crate::write_all(&mut out, &buf)?;
+
+		let mut row = vec![];
+		row.try_reserve_exact(stride * planes).map_err(|_| oom())?;
+		row.resize(stride * planes, 0);
+		Ok(Self {
+			out,
+			color_type: header.color_type,
+			rle: header.rle,
+			width,
+			stride,
+			indices: HashMap::new(),
+			row,
+			x: 0,
+			packed: vec![],
+		})
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		match self.color_type {
+			PcxColorType::Indexed => {
+				let px = (px.0, px.1, px.2, 255);
+				let next = self.indices.len();
+				let i = match self.indices.get(&px) {
+					Some(&i) => i,
+					None if next == 256 => return Err(Error::InvalidInput { reason: "image has more than 256 colors" }),
+					None => {
+						self.indices.insert(px, next as u8);
+						next as u8
+					}
+				};
+				self.row[self.x] = i;
+			}
+			PcxColorType::RGB => {
+				self.row[self.x] = px.0;
+				self.row[self.stride + self.x] = px.1;
+				self.row[self.stride * 2 + self.x] = px.2;
+			}
+		}
+		self.x += 1;
+		if self.x == self.width {
+			self.end_row()?;
+		}
+		Ok(())
+	}
+
+	fn end_row(&mut self) -> Result<(), Error> {
+		let row = match self.rle {
+			true => {
+				// a run can take twice the bytes in it
+				self.packed.clear();
+				self.packed.try_reserve(self.row.len() * 2).map_err(|_| oom())?;
+				for plane in self.row.chunks_exact(self.stride) {
+					pack(plane, &mut self.packed);
+				}
+				&self.packed
+			}
+			false => &self.row,
+		};
+		crate::write_all(&mut self.out, row)?;
+		self.row.fill(0);
+		self.x = 0;
+		Ok(())
+	}
+
+	/// writes a row that didn't get all its pixels, and the color map of
+	/// color-mapped images.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		if self.x > 0 {
+			self.end_row()?;
+		}
+		if self.color_type == PcxColorType::Indexed {
+			let mut map = [0; 769];
+			map[0] = PALETTE;
+			for (px, &i) in &self.indices {
+				let at = 1 + i as usize * 3;
+				map[at..at + 3].copy_from_slice(&[px.0, px.1, px.2]);
+			}
+			crate::write_all(&mut self.out, &map)?;
+		}
+		Ok(())
+	}
+}
+
+impl crate::Format for Pcx {
+	type Header = PcxHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = PcxPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = PcxPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = PcxEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, pcx};
+
+	const IMAGE_RGB: &[u8] = include_bytes!("../test/rgb.pcx");
+	const IMAGE_INDEXED: &[u8] = include_bytes!("../test/indexed.pcx");
+
+	fn color(x: u32, y: u32) -> crate::Rgba {
+		((x * 19) as u8, (y * 36) as u8, ((x + y) * 10) as u8, 255)
+	}
+
+	fn indexed_color(x: u32, y: u32) -> crate::Rgba {
+		let i = (x / 3 + y * 5) % 40;
+		((i * 7) as u8, (i * 3) as u8, (255 - i) as u8, 255)
+	}
+
+	#[test]
+	fn decode() {
+		// rows packed on their own, and planes as they are with a stride of
+		// 16, and a flat image in runs that go on across planes and rows
+		for (image, rle) in [
+			(IMAGE_RGB, true),
+			(&include_bytes!("../test/rgb_raw.pcx")[..], false),
+			(include_bytes!("../test/flat.pcx"), true),
+		] {
+			let flat = image.len() < IMAGE_RGB.len();
+			let mut data = image;
+			let mut pixels = pcx::PcxPixels::new(&mut data).expect("error?");
+			let header = pixels.header();
+			assert_eq!((header.width, header.height, header.color_type, header.rle), (13, 7, pcx::PcxColorType::RGB, rle));
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				let expected = match flat {
+					true => (200, 200, 200, 255),
+					false => color(x, y),
+				};
+				assert_eq!(px.expect("error?"), expected, "{x}, {y}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			// where it ends is found without handing out every pixel
+			let mut data = image;
+			let mut pixels = pcx::PcxPixels::new(&mut data).expect("error?");
+			assert!(pixels.nth(20).is_some());
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+	}
+
+	#[test]
+	fn decode_indexed() {
+		// the corner isn't at 0, 0
+		let mut data = IMAGE_INDEXED;
+		let mut pixels = pcx::PcxPixels::new(&mut data).expect("error?");
+		let header = pixels.header();
+		assert_eq!((header.width, header.height, header.color_type), (13, 7, pcx::PcxColorType::Indexed));
+		assert!(pixels.palette().is_empty());
+		assert_eq!(pixels.next().transpose().expect("error?"), Some(indexed_color(0, 0)));
+		assert_eq!(pixels.palette().len(), 256);
+		for (i, px) in (&mut pixels).enumerate() {
+			let (x, y) = ((i as u32 + 1) % 13, (i as u32 + 1) / 13);
+			assert_eq!(px.expect("error?"), indexed_color(x, y), "{x}, {y}");
+		}
+		assert_eq!(pixels.finish().expect("error?"), IMAGE_INDEXED.len() as u64);
+
+		let mut data = IMAGE_INDEXED;
+		let pixels = pcx::PcxPixels::new(&mut data).expect("error?");
+		assert_eq!(pixels.finish().expect("error?"), IMAGE_INDEXED.len() as u64);
+	}
+
+	#[test]
+	fn encode() {
+		use pcx::PcxColorType::*;
+
+		for color_type in [RGB, Indexed] {
+			for rle in [false, true] {
+				// an odd width, for the padding at the end of each plane
+				let header = pcx::PcxHeader { width: 13, height: 7, color_type, rle };
+				let pixels = (0..13 * 7).map(|i| match color_type {
+					RGB => color(i % 13, i / 13),
+					Indexed => indexed_color(i % 13, i / 13),
+				});
+				let mut out = vec![];
+				pcx::Pcx.encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+
+				let mut data = &out[..];
+				let (decoded_header, decoded) = pcx::Pcx.decode(&mut data).expect("error?");
+				assert_eq!(decoded_header, header);
+				assert!(decoded.eq(pixels), "{color_type:?}, {rle}");
+			}
+		}
+
+		// runs, and bytes with the top bits set in runs of one
+		let mut out = vec![];
+		super::pack(&[1, 1, 1, 2, 0xc5, 0xc5, 0xd0, 3], &mut out);
+		assert_eq!(out, [0xc3, 1, 2, 0xc2, 0xc5, 0xc1, 0xd0, 3]);
+		let mut out = vec![];
+		super::pack(&[7; 100], &mut out);
+		assert_eq!(out, [0xff, 7, 0xe5, 7]);
+
+		// alpha is dropped
+		let header = pcx::PcxHeader { width: 2, height: 1, color_type: RGB, rle: true };
+		let mut out = vec![];
+		pcx::Pcx.encode([(1, 2, 3, 0), (4, 5, 6, 100)].into_iter(), header, &mut out).expect("error?");
+		let mut data = &out[..];
+		let (_, decoded) = pcx::Pcx.decode(&mut data).expect("error?");
+		assert!(decoded.eq([(1, 2, 3, 255), (4, 5, 6, 255)]));
+
+		// the color map only has room for 256
+		let header = pcx::PcxHeader { width: 257, height: 1, color_type: Indexed, rle: true };
+		let mut encoder = pcx::PcxEncoder::new(&header, vec![]).expect("error?");
+		for i in 0..256 {
+			assert!(encoder.push((i as u8, 0, 0, 255)).is_ok());
+		}
+		assert!(encoder.push((0, 0, 0, 255)).is_ok());
+		assert!(matches!(encoder.push((0, 1, 0, 255)), Err(Error::InvalidInput { .. })));
+
+		// too big for the format
+		for (header, offset) in [
+			(pcx::PcxHeader { width: u16::MAX as u32, ..header.clone() }, 8),
+			(pcx::PcxHeader { width: 0, ..header.clone() }, 8),
+			(pcx::PcxHeader { height: (1 << 16) + 1, ..header.clone() }, 10),
+		] {
+			let result = pcx::PcxEncoder::new(&header, vec![]);
+			assert!(matches!(result, Err(Error::InvalidHeader { offset: o, .. }) if o == offset), "{result:?}");
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = pcx::PcxPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".pcx")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("bad_encoding", Error::InvalidHeader { offset: 2, .. });
+		case!("four_bits", Error::InvalidHeader { offset: 3, .. });
+		case!("bad_width", Error::InvalidHeader { offset: 8, .. });
+		case!("two_planes", Error::InvalidHeader { offset: 65, .. });
+		case!("narrow_stride", Error::InvalidHeader { offset: 66, .. });
+		case!("truncated", Error::UnexpectedEof { offset: 200 });
+		case!("no_palette", Error::Malformed { offset: 198, .. });
+		case!("truncated_palette", Error::UnexpectedEof { offset: 867 });
+
+		assert!(decode(IMAGE_RGB).is_ok());
+		assert!(decode(IMAGE_INDEXED).is_ok());
+	}
+}
@@ -0,0 +1,280 @@
+/*!
+pixel types other than `Rgba`, and converting between them.
+
+every type goes to and from `RgbaF32`, which loses nothing from 8 or 16-bit
+samples, so any type converts to any other. converting to fewer channels
+drops alpha, and gray is rec. 601 luma, as the encoders take it. `widen`
+and `narrow` go between 8 and 16 bits directly.
+*/
+
+use crate::{Gray8, GrayAlpha8, Rgb8, Rgba, Rgba16, RgbaF32};
+
+/// what a pixel type's samples hold, which picks the path `decode_as` and
+/// `encode_from` take for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+	/// 8 bits, by way of `decode` and `encode`.
+	Eight,
+	/// 16 bits, by way of `decode_wide` and `encode_wide`.
+	Sixteen,
+	/// floats, by way of `decode_float` and `encode_float`.
+	Float,
+}
+
+/// a pixel that converts to and from the others.
+pub trait Pixel: Copy {
+	const DEPTH: Depth = Depth::Eight;
+
+	/// the pixel as floats, with 0 to 1 covering each sample's range, and
+	/// alpha 1 for types without it.
+	fn to_f32(self) -> RgbaF32;
+
+	/// the pixel nearest `px`. samples out of range, and nan, are clamped,
+	/// except by `RgbaF32`, which keeps them.
+	fn from_f32(px: RgbaF32) -> Self;
+
+	fn to_rgba(self) -> Rgba {
+		Rgba::from_f32(self.to_f32())
+	}
+
+	fn from_rgba(px: Rgba) -> Self {
+		Self::from_f32(px.to_f32())
+	}
+
+	/// the pixel as another type.
+	fn convert<P: Pixel>(self) -> P {
+		P::from_f32(self.to_f32())
+	}
+}
+
+/// an 8-bit pixel with its samples scaled up to 16 bits, so 255 becomes
+/// 65535.
+pub fn widen(px: Rgba) -> Rgba16 {
+	let widen = |x: u8| x as u16 * 257;
+	(widen(px.0), widen(px.1), widen(px.2), widen(px.3))
+}
+
+/// a 16-bit pixel with its samples rounded to 8 bits. `widen` undoes this
+/// for pixels it made.
+pub fn narrow(px: Rgba16) -> Rgba {
+	let narrow = |x: u16| ((x as u32 * 255 + 32767) / 65535) as u8;
+	(narrow(px.0), narrow(px.1), narrow(px.2), narrow(px.3))
+}
+
+/// the pixels of whichever path `decode_as` took.
+pub(crate) enum Paths<A, B, C> {
+	Eight(A),
+	Sixteen(B),
+	Float(C),
+}
+
+impl<P, A: Iterator<Item = P>, B: Iterator<Item = P>, C: Iterator<Item = P>> Iterator for Paths<A, B, C> {
+	type Item = P;
+
+	fn next(&mut self) -> Option<P> {
+		match self {
+			Self::Eight(x) => x.next(),
+			Self::Sixteen(x) => x.next(),
+			Self::Float(x) => x.next(),
+		}
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		match self {
+			Self::Eight(x) => x.size_hint(),
+			Self::Sixteen(x) => x.size_hint(),
+			Self::Float(x) => x.size_hint(),
+		}
+	}
+}
+
+fn unit(x: u8) -> f32 {
+	x as f32 / 255.0
+}
+
+fn unit16(x: u16) -> f32 {
+	x as f32 / 65535.0
+}
+
+fn scale(x: f32) -> u8 {
+	// nan fails every comparison, so it comes out as 0
+	if x > 0.0 { (x.min(1.0) * 255.0 + 0.5) as u8 } else { 0 }
+}
+
+fn scale16(x: f32) -> u16 {
+	if x > 0.0 { (x.min(1.0) * 65535.0 + 0.5) as u16 } else { 0 }
+}
+
+/// an srgb sample for the linear one `x`, both from 0 to 1.
+pub(crate) fn srgb(x: f32) -> f32 {
+	if x <= 0.0031308 { x * 12.92 } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// the linear sample `srgb` maps to `x`.
+pub(crate) fn srgb_inverse(x: f32) -> f32 {
+	if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+}
+
+/// rec. 601 luma of 8 or 16-bit samples, with 0.299, 0.587 and 0.114 as
+/// 77, 150 and 29 256ths, which add up to exactly one, so it never goes
+/// past the samples' range. every encoder that writes gray takes it so,
+/// and so does converting to `Gray8`.
+pub(crate) fn luma<T: Into<u32>>((r, g, b, _): (T, T, T, T)) -> u32 {
+	(77 * r.into() + 150 * g.into() + 29 * b.into() + 128) >> 8
+}
+
+impl Pixel for Rgba {
+	fn to_f32(self) -> RgbaF32 {
+		(unit(self.0), unit(self.1), unit(self.2), unit(self.3))
+	}
+
+	fn from_f32(px: RgbaF32) -> Self {
+		(scale(px.0), scale(px.1), scale(px.2), scale(px.3))
+	}
+
+	fn to_rgba(self) -> Rgba {
+		self
+	}
+
+	fn from_rgba(px: Rgba) -> Self {
+		px
+	}
+}
+
+impl Pixel for Rgb8 {
+	fn to_f32(self) -> RgbaF32 {
+		(unit(self.0), unit(self.1), unit(self.2), 1.0)
+	}
+
+	fn from_f32(px: RgbaF32) -> Self {
+		(scale(px.0), scale(px.1), scale(px.2))
+	}
+
+	fn to_rgba(self) -> Rgba {
+		(self.0, self.1, self.2, 255)
+	}
+
+	fn from_rgba(px: Rgba) -> Self {
+		(px.0, px.1, px.2)
+	}
+}
+
+impl Pixel for Gray8 {
+	fn to_f32(self) -> RgbaF32 {
+		let x = unit(self);
+		(x, x, x, 1.0)
+	}
+
+	/// by way of 16 bits, so the gray is `luma`'s.
+	fn from_f32(px: RgbaF32) -> Self {
+		scale(unit16(luma(Rgba16::from_f32(px)) as u16))
+	}
+
+	fn to_rgba(self) -> Rgba {
+		(self, self, self, 255)
+	}
+
+	fn from_rgba(px: Rgba) -> Self {
+		luma(px) as u8
+	}
+}
+
+impl Pixel for GrayAlpha8 {
+	fn to_f32(self) -> RgbaF32 {
+		let x = unit(self.0);
+		(x, x, x, unit(self.1))
+	}
+
+	fn from_f32(px: RgbaF32) -> Self {
+		(Gray8::from_f32(px), scale(px.3))
+	}
+
+	fn to_rgba(self) -> Rgba {
+		(self.0, self.0, self.0, self.1)
+	}
+
+	fn from_rgba(px: Rgba) -> Self {
+		(luma(px) as u8, px.3)
+	}
+}
+
+impl Pixel for Rgba16 {
+	const DEPTH: Depth = Depth::Sixteen;
+
+	fn to_f32(self) -> RgbaF32 {
+		(unit16(self.0), unit16(self.1), unit16(self.2), unit16(self.3))
+	}
+
+	fn from_f32(px: RgbaF32) -> Self {
+		(scale16(px.0), scale16(px.1), scale16(px.2), scale16(px.3))
+	}
+
+	fn to_rgba(self) -> Rgba {
+		narrow(self)
+	}
+
+	fn from_rgba(px: Rgba) -> Self {
+		widen(px)
+	}
+}
+
+impl Pixel for RgbaF32 {
+	const DEPTH: Depth = Depth::Float;
+
+	fn to_f32(self) -> RgbaF32 {
+		self
+	}
+
+	fn from_f32(px: RgbaF32) -> Self {
+		px
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Gray8, GrayAlpha8, Pixel, Rgb8, Rgba, Rgba16, RgbaF32, pixel};
+
+	#[test]
+	fn round_trip() {
+		for x in 0..=255u8 {
+			let px: Rgba = (x, 255 - x, x / 2, x ^ 0x5a);
+			assert_eq!(Rgba::from_f32(px.to_f32()), px);
+			assert_eq!(px.convert::<Rgba16>().convert::<Rgba>(), px);
+			assert_eq!(px.convert::<RgbaF32>().convert::<Rgba>(), px);
+			assert_eq!(Rgb8::from_rgba(px), px.convert::<Rgb8>());
+			assert_eq!(x.convert::<Rgba>(), (x, x, x, 255));
+			assert_eq!(x.to_rgba(), x.convert::<Rgba>());
+			assert_eq!((x, 255 - x).convert::<Rgba>(), (x, x, x, 255 - x));
+			assert_eq!((x, 255 - x).to_rgba().convert::<GrayAlpha8>(), (x, 255 - x));
+			assert_eq!(x.to_rgba().convert::<Gray8>(), x);
+			assert_eq!(pixel::widen(px), px.convert::<Rgba16>());
+			assert_eq!(pixel::narrow(pixel::widen(px)), px);
+		}
+		for x in 0..=u16::MAX {
+			let px: Rgba16 = (x, !x, x / 3, 65535);
+			assert_eq!(px.convert::<RgbaF32>().convert::<Rgba16>(), px);
+			assert_eq!(px.to_rgba(), px.convert::<Rgba>());
+		}
+	}
+
+	#[test]
+	fn narrowing() {
+		let rgba = |px: Rgba| px;
+
+		// alpha is dropped, not composited
+		assert_eq!(rgba((10, 20, 30, 0)).convert::<Rgb8>(), (10, 20, 30));
+		assert_eq!(rgba((255, 255, 255, 7)).convert::<Gray8>(), 255);
+		assert_eq!(rgba((255, 0, 0, 255)).convert::<Gray8>(), 77);
+		assert_eq!(Gray8::from_rgba((255, 0, 0, 255)), 77);
+		assert_eq!(rgba((0, 255, 0, 255)).convert::<Gray8>(), 149);
+		assert_eq!(rgba((0, 255, 0, 9)).convert::<GrayAlpha8>(), (149, 9));
+		let px: Rgba16 = (0x1234, 0xff00, 0, 0xffff);
+		assert_eq!(px.convert::<Rgba>(), (0x12, 0xfe, 0, 255));
+
+		// out of range and nan are clamped, but kept as floats
+		let px: RgbaF32 = (2.0, -1.0, f32::NAN, 0.5);
+		assert_eq!(px.convert::<Rgba>(), (255, 0, 0, 128));
+		assert_eq!(px.to_f32().0, 2.0);
+	}
+}
@@ -0,0 +1,1476 @@
+/*!
+png images.
+
+truecolor images, with or without alpha, and indexed images of any bit
+depth are decoded so far. indexed pixels come out with their palette
+colors, and alpha from `tRNS`. 16-bit images are rounded to 8 bits, unless
+they're read through [`PngPixels::wide`], which keeps every bit. pixels are
+decompressed a row at a time as they're asked for, so memory use doesn't
+grow with the image's height. text, `eXIf`, `iCCP`, `gAMA` and `pHYs`
+chunks are parsed into [`PngMetadata`], and other ancillary chunks are
+skipped. [`ApngFrames`] reads the frames of animated pngs.
+
+interlaced images still come out in row-major order. the first six adam7
+passes hold the even rows between them, so those are kept as they're
+decompressed, which is half the image at most. the seventh pass is the odd
+rows, which are handed out as they're decompressed, like any other row.
+
+the encoder writes the same kinds of image, without interlacing. truecolor
+rows get the paeth filter, which does well on photos and gradients alike,
+and indexed rows aren't filtered, as the spec suggests. the data is split
+into 32k IDAT chunks.
+*/
+
+use std::collections::{HashMap, HashSet};
+
+use crate::Error;
+use crate::checksum::Crc32;
+use crate::error::oom;
+use crate::zlib::{self, Deflate, Inflate};
+
+mod ancillary;
+mod apng;
+
+pub use ancillary::{PngMetadata, PngPhysical, PngUnit};
+pub use apng::{ApngAnimation, ApngBlend, ApngDispose, ApngFrame, ApngFrameControl, ApngFrames};
+
+pub(crate) const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// compressed bytes the encoder puts in each IDAT chunk.
+const IDAT_SIZE: usize = 1 << 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PngColorType {
+	RGB,
+	RGBA,
+	/// palette indices.
+	Indexed,
+}
+
+impl PngColorType {
+	fn channels(self) -> usize {
+		match self {
+			Self::RGB => 3,
+			Self::RGBA => 4,
+			Self::Indexed => 1,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PngHeader {
+	pub width: u32,
+	pub height: u32,
+	pub color_type: PngColorType,
+	/// bits per channel. 8 or 16 for truecolor images, though only 8 is
+	/// encoded, and 1, 2, 4 or 8 for indexed ones, enough to hold every index
+	/// into `palette`.
+	pub bit_depth: u8,
+	/// whether the image data is adam7 interlaced. the encoder never
+	/// interlaces, so this is ignored when encoding.
+	pub interlaced: bool,
+	/// the colors an indexed image's pixels refer to, with alpha from `tRNS`.
+	/// truecolor images can suggest a palette too, which they don't depend
+	/// on, and which isn't written back out.
+	pub palette: Vec<crate::Rgba>,
+}
+
+impl PngHeader {
+	fn bits(&self) -> u64 {
+		self.bit_depth as u64 * self.color_type.channels() as u64
+	}
+
+	/// bytes in a row `width` pixels wide, filter type not included.
+	fn stride(&self, width: u32) -> u64 {
+		(width as u64 * self.bits()).div_ceil(8)
+	}
+
+	/// distance to the byte on the left that filters use.
+	fn bpp(&self) -> usize {
+		(self.bits() as usize / 8).max(1)
+	}
+
+	/// bytes a pixel takes up once samples are unpacked into bytes.
+	fn pixel_size(&self) -> usize {
+		self.color_type.channels() * (self.bit_depth as usize / 8).max(1)
+	}
+}
+
+impl crate::Dimensions for PngHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Png;
+
+impl Default for Png {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// options for `Format::encode_with_options`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PngOptions {
+	/// how hard the image data is compressed, from 0, for not at all, to 9,
+	/// like zlib's levels. 6 by default.
+	pub level: u8,
+}
+
+impl Default for PngOptions {
+	fn default() -> Self {
+		Self { level: zlib::LEVEL }
+	}
+}
+
+/// the distinct colors in `pixels`, in the order they first appear, or
+/// `None` if there are more than 256. this is a palette an indexed image of
+/// those pixels can be encoded with.
+pub fn palette(pixels: impl IntoIterator<Item = crate::Rgba>) -> Option<Vec<crate::Rgba>> {
+	let mut seen = HashSet::new();
+	let mut palette = vec![];
+	for px in pixels {
+		if seen.insert(px) {
+			if palette.len() == 256 {
+				return None;
+			}
+			palette.push(px);
+		}
+	}
+	Some(palette)
+}
+
+/// a chunk whose header has been read.
+#[derive(Debug)]
+struct Chunk {
+	kind: [u8; 4],
+	len: u32,
+	/// where the chunk starts, length included.
+	offset: u64,
+	crc: Crc32,
+}
+
+impl Chunk {
+	/// critical chunks have an uppercase first letter, and can't be skipped
+	/// without misreading the image.
+	fn is_critical(&self) -> bool {
+		self.kind[0] & 0x20 == 0
+	}
+}
+
+/// the reader, and how far into it decoding is.
+#[derive(Debug)]
+struct Stream<'a, R> {
+	data: &'a mut R,
+	offset: u64,
+}
+
+impl<R: std::io::Read> Stream<'_, R> {
+	fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+		crate::read_exact(self.data, buf, self.offset)?;
+		self.offset = self.offset.saturating_add(buf.len() as u64);
+		Ok(())
+	}
+
+	/// reads at least one byte of `buf`.
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		loop {
+			match self.data.read(buf) {
+				Ok(0) if !buf.is_empty() => return Err(Error::UnexpectedEof { offset: self.offset }),
+				Ok(n) => {
+					let n = n.min(buf.len());
+					self.offset = self.offset.saturating_add(n as u64);
+					return Ok(n);
+				}
+				Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+				Err(e) => return Err(e.into()),
+			}
+		}
+	}
+
+	fn chunk(&mut self) -> Result<Chunk, Error> {
+		let offset = self.offset;
+		let mut buf = [0; 8];
+		self.read_exact(&mut buf)?;
+
+		let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+		if len > i32::MAX as u32 {
+			return Err(Error::Malformed { offset, reason: "chunk length is over 2^31 - 1" });
+		}
+
+		let kind = [buf[4], buf[5], buf[6], buf[7]];
+		let mut crc = Crc32::new();
+		crc.update(&kind);
+		Ok(Chunk { kind, len, offset, crc })
+	}
+
+	/// reads part of a chunk's data.
+	fn data(&mut self, chunk: &mut Chunk, buf: &mut [u8]) -> Result<(), Error> {
+		self.read_exact(buf)?;
+		chunk.crc.update(buf);
+		Ok(())
+	}
+
+	/// reads the crc that ends a chunk, once all of its data has been read.
+	fn end(&mut self, chunk: &Chunk) -> Result<(), Error> {
+		let mut buf = [0; 4];
+		self.read_exact(&mut buf)?;
+		let expected = u32::from_be_bytes(buf);
+		let actual = chunk.crc.finish();
+		if expected != actual {
+			return Err(Error::ChecksumMismatch { expected, actual });
+		}
+		Ok(())
+	}
+
+	/// reads the rest of a chunk, none of which has been read yet.
+	fn skip(&mut self, mut chunk: Chunk) -> Result<(), Error> {
+		let mut buf = [0; 512];
+		let mut left = chunk.len as usize;
+		while left > 0 {
+			let n = left.min(buf.len());
+			self.data(&mut chunk, &mut buf[..n])?;
+			left -= n;
+		}
+		self.end(&chunk)
+	}
+}
+
+/// the data of consecutive `IDAT` chunks, as one stream, or apng's `fdAT`
+/// chunks, minus their sequence numbers.
+#[derive(Debug)]
+struct Idat<'a, R> {
+	stream: Stream<'a, R>,
+	chunk: Chunk,
+	/// bytes of `chunk` that haven't been read.
+	left: u32,
+	/// the chunk after the last data chunk, once it's been reached.
+	next: Option<Chunk>,
+}
+
+impl<'a, R: std::io::Read> Idat<'a, R> {
+	fn new(mut stream: Stream<'a, R>, mut chunk: Chunk) -> Result<Self, Error> {
+		let left = Self::begin(&mut stream, &mut chunk)?;
+		Ok(Self { stream, chunk, left, next: None })
+	}
+
+	/// skips the sequence number of an `fdAT`, and returns how much data
+	/// the chunk has.
+	fn begin(stream: &mut Stream<'a, R>, chunk: &mut Chunk) -> Result<u32, Error> {
+		if chunk.kind != *b"fdAT" {
+			return Ok(chunk.len);
+		}
+		if chunk.len < 4 {
+			return Err(Error::Malformed { offset: chunk.offset, reason: "fdAT has no sequence number" });
+		}
+		stream.data(chunk, &mut [0; 4])?;
+		Ok(chunk.len - 4)
+	}
+
+	/// skips whatever's left of the data, returning the stream and the chunk
+	/// after it.
+	fn skip(mut self) -> Result<(Stream<'a, R>, Chunk), Error> {
+		let mut buf = [0; 512];
+		while zlib::Input::read(&mut self, &mut buf)? > 0 {}
+		match self.next {
+			Some(next) => Ok((self.stream, next)),
+			None => Err(Error::Malformed { offset: self.stream.offset, reason: "image data never ended" }),
+		}
+	}
+}
+
+impl<R: std::io::Read> zlib::Input for Idat<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		loop {
+			if self.next.is_some() || buf.is_empty() {
+				return Ok(0);
+			}
+
+			if self.left > 0 {
+				let n = buf.len().min(self.left as usize);
+				let n = self.stream.read(&mut buf[..n])?;
+				self.chunk.crc.update(&buf[..n]);
+				self.left -= n as u32;
+				return Ok(n);
+			}
+
+			self.stream.end(&self.chunk)?;
+			let mut chunk = self.stream.chunk()?;
+			if chunk.kind == self.chunk.kind {
+				self.left = Self::begin(&mut self.stream, &mut chunk)?;
+				self.chunk = chunk;
+			}
+			else {
+				self.next = Some(chunk);
+			}
+		}
+	}
+
+	fn offset(&self) -> u64 {
+		self.stream.offset
+	}
+}
+
+/// an empty buffer with room for `len` bytes, or an error if that can't be
+/// allocated. it's filled as data arrives, so a header claiming a huge image
+/// doesn't cost anything until the data backs it up.
+fn buffer(len: u64) -> Result<Vec<u8>, Error> {
+	let len = usize::try_from(len).map_err(|_| oom())?;
+	let mut buf = vec![];
+	buf.try_reserve_exact(len).map_err(|_| oom())?;
+	Ok(buf)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+	let p = a as i16 + b as i16 - c as i16;
+	let pa = (p - a as i16).abs();
+	let pb = (p - b as i16).abs();
+	let pc = (p - c as i16).abs();
+	if pa <= pb && pa <= pc {
+		a
+	}
+	else if pb <= pc {
+		b
+	}
+	else {
+		c
+	}
+}
+
+/// undoes `filter` on `row`, given the row above it, which is empty for the
+/// first row. `bpp` is the distance to the byte on the left.
+fn unfilter(filter: u8, row: &mut [u8], prev: &[u8], bpp: usize) -> Result<(), &'static str> {
+	let up = |i: usize| prev.get(i).copied().unwrap_or(0);
+	match filter {
+		0 => {}
+		1 => {
+			for i in bpp..row.len() {
+				row[i] = row[i].wrapping_add(row[i - bpp]);
+			}
+		}
+		2 => {
+			for (x, &up) in row.iter_mut().zip(prev) {
+				*x = x.wrapping_add(up);
+			}
+		}
+		3 => {
+			for i in 0..row.len() {
+				let left = if i >= bpp { row[i - bpp] } else { 0 };
+				row[i] = row[i].wrapping_add(((left as u16 + up(i) as u16) / 2) as u8);
+			}
+		}
+		4 => {
+			for i in 0..row.len() {
+				let (left, up_left) = if i >= bpp { (row[i - bpp], up(i - bpp)) } else { (0, 0) };
+				row[i] = row[i].wrapping_add(paeth(left, up(i), up_left));
+			}
+		}
+		_ => return Err("invalid filter type"),
+	}
+	Ok(())
+}
+
+/// filters `row` into `out`, which starts with the filter type. only none
+/// and paeth are ever used.
+fn filter(kind: u8, row: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+	out.clear();
+	out.push(kind);
+	if kind == 0 {
+		out.extend_from_slice(row);
+		return;
+	}
+	let up = |i: usize| prev.get(i).copied().unwrap_or(0);
+	for (i, &x) in row.iter().enumerate() {
+		let (left, up_left) = if i >= bpp { (row[i - bpp], up(i - bpp)) } else { (0, 0) };
+		out.push(x.wrapping_sub(paeth(left, up(i), up_left)));
+	}
+}
+
+/// writes a whole chunk.
+fn chunk(out: &mut impl std::io::Write, kind: &[u8; 4], data: &[u8]) -> Result<(), Error> {
+	let mut crc = Crc32::new();
+	crc.update(kind);
+	crc.update(data);
+	crate::write_all(out, &(data.len() as u32).to_be_bytes())?;
+	crate::write_all(out, kind)?;
+	crate::write_all(out, data)?;
+	crate::write_all(out, &crc.finish().to_be_bytes())?;
+	Ok(())
+}
+
+/// `(x, y, dx, dy)` of the first pixel of each adam7 pass, and the
+/// distance between its pixels.
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+	(0, 0, 8, 8),
+	(4, 0, 8, 8),
+	(0, 4, 4, 8),
+	(2, 0, 4, 4),
+	(0, 2, 2, 4),
+	(1, 0, 2, 2),
+	(0, 1, 1, 2),
+];
+
+/// which pass each pixel of an 8x8 tile is in, counting from 0. odd rows
+/// are all in the last pass.
+const ADAM7_PASS: [[u8; 8]; 8] = [
+	[0, 5, 3, 5, 1, 5, 3, 5],
+	[6; 8],
+	[4, 5, 4, 5, 4, 5, 4, 5],
+	[6; 8],
+	[2, 5, 3, 5, 2, 5, 3, 5],
+	[6; 8],
+	[4, 5, 4, 5, 4, 5, 4, 5],
+	[6; 8],
+];
+
+/// the width and height of a pass, either of which can be 0.
+fn pass_size(width: u32, height: u32, pass: usize) -> (u32, u32) {
+	let (x, y, dx, dy) = ADAM7[pass];
+	(width.saturating_sub(x).div_ceil(dx), height.saturating_sub(y).div_ceil(dy))
+}
+
+/// fills `buf` with decompressed data, which must not run out.
+fn inflate<R: std::io::Read>(inflate: &mut Inflate<Idat<'_, R>>, buf: &mut [u8]) -> Result<(), Error> {
+	let mut n = 0;
+	while n < buf.len() {
+		match inflate.read(&mut buf[n..])? {
+			0 => {
+				let offset = zlib::Input::offset(inflate.input());
+				return Err(Error::Malformed { offset, reason: "image data is too short" });
+			}
+			x => n += x,
+		}
+	}
+	Ok(())
+}
+
+/// decompresses and unfilters a row of `stride` bytes into `out`, given the
+/// row above it, which is empty for the first row.
+fn read_row<R: std::io::Read>(
+	data: &mut Inflate<Idat<'_, R>>,
+	out: &mut Vec<u8>,
+	prev: &[u8],
+	stride: usize,
+	bpp: usize,
+) -> Result<(), Error> {
+	let mut filter = [0];
+	inflate(data, &mut filter)?;
+
+	out.clear();
+	let mut buf = [0; 1024];
+	while out.len() < stride {
+		let n = buf.len().min(stride - out.len());
+		inflate(data, &mut buf[..n])?;
+		out.extend_from_slice(&buf[..n]);
+	}
+
+	unfilter(filter[0], out, prev, bpp).map_err(|reason| {
+		let offset = zlib::Input::offset(data.input());
+		Error::Malformed { offset, reason }
+	})
+}
+
+/// appends the first `samples` samples of `row` to `out`, a byte each if
+/// they're smaller than that.
+fn unpack(row: &[u8], depth: u8, samples: usize, out: &mut Vec<u8>) {
+	if depth >= 8 {
+		out.extend_from_slice(row);
+		return;
+	}
+	let mask = (1u8 << depth) - 1;
+	let start = out.len();
+	for &x in row {
+		for shift in (0..8 / depth).rev() {
+			out.push(x >> (shift * depth) & mask);
+		}
+	}
+	out.truncate(start + samples);
+}
+
+/// pixels of a png image, decompressed from the reader as they're requested.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct PngPixels<'a, R> {
+	header: PngHeader,
+	inflate: Inflate<Idat<'a, R>>,
+	/// the row being read, before unpacking, and the one before it.
+	raw: Vec<u8>,
+	prev: Vec<u8>,
+	/// the current row, unpacked.
+	row: Vec<u8>,
+	/// next pixel of `row` to hand out.
+	x: u32,
+	/// rows that haven't been handed out.
+	rows: u32,
+	/// the first six passes of an interlaced image, once they've been read.
+	passes: Vec<Vec<u8>>,
+	/// ancillary chunks read so far.
+	metadata: PngMetadata,
+	failed: bool,
+}
+
+/// everything before the image data.
+struct Opened<'a, R> {
+	header: PngHeader,
+	stream: Stream<'a, R>,
+	/// the first `IDAT`.
+	chunk: Chunk,
+	metadata: PngMetadata,
+	/// the data of `acTL`, and of the `fcTL` before the image data, if the
+	/// image is animated.
+	actl: Option<Vec<u8>>,
+	fctl: Option<Vec<u8>>,
+}
+
+fn open<R: std::io::Read>(data: &mut R) -> Result<Opened<'_, R>, Error> {
+	let mut stream = Stream { data, offset: 0 };
+	let mut metadata = PngMetadata::default();
+	let (mut actl, mut fctl) = (None, None);
+
+	let mut signature = [0; 8];
+	stream.read_exact(&mut signature)?;
+	if signature != SIGNATURE {
+		return Err(Error::InvalidMagic { offset: 0 });
+	}
+
+	let mut chunk = stream.chunk()?;
+	if chunk.kind != *b"IHDR" || chunk.len != 13 {
+		return Err(Error::Malformed { offset: chunk.offset, reason: "IHDR must come first" });
+	}
+	let mut buf = [0; 13];
+	stream.data(&mut chunk, &mut buf)?;
+	stream.end(&chunk)?;
+
+	let field = |at: u64, field| Error::InvalidHeader { offset: 16 + at, field };
+
+	let width = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+	let height = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+	if width == 0 || width > i32::MAX as u32 {
+		return Err(field(0, "width"));
+	}
+	if height == 0 || height > i32::MAX as u32 {
+		return Err(field(4, "height"));
+	}
+	let color_type = match buf[9] {
+		2 => PngColorType::RGB,
+		6 => PngColorType::RGBA,
+		3 => PngColorType::Indexed,
+		_ => return Err(field(9, "color type")),
+	};
+	let bit_depth = buf[8];
+	let depths: &[u8] = match color_type {
+		PngColorType::Indexed => &[1, 2, 4, 8],
+		_ => &[8, 16],
+	};
+	if !depths.contains(&bit_depth) {
+		return Err(field(8, "bit depth"));
+	}
+	if buf[10] != 0 {
+		return Err(field(10, "compression method"));
+	}
+	if buf[11] != 0 {
+		return Err(field(11, "filter method"));
+	}
+	let interlaced = match buf[12] {
+		0 => false,
+		1 => true,
+		_ => return Err(field(12, "interlace method")),
+	};
+
+	let mut header = PngHeader {
+		width,
+		height,
+		color_type,
+		bit_depth,
+		interlaced,
+		palette: vec![],
+	};
+
+	let chunk = loop {
+		let mut chunk = stream.chunk()?;
+		let malformed = |reason| Error::Malformed { offset: chunk.offset, reason };
+		match &chunk.kind {
+			b"IDAT" => break chunk,
+			b"IEND" => return Err(malformed("no IDAT chunk")),
+			b"PLTE" => {
+				let len = chunk.len as usize;
+				if len == 0 || len > 256 * 3 || !len.is_multiple_of(3) {
+					return Err(malformed("PLTE isn't 1 to 256 colors"));
+				}
+				if !header.palette.is_empty() {
+					return Err(malformed("more than one PLTE chunk"));
+				}
+				let mut buf = [0; 256 * 3];
+				stream.data(&mut chunk, &mut buf[..len])?;
+				stream.end(&chunk)?;
+				header.palette = buf[..len].chunks_exact(3).map(|x| (x[0], x[1], x[2], 255)).collect();
+			}
+			b"tRNS" if color_type == PngColorType::Indexed => {
+				if header.palette.is_empty() {
+					return Err(malformed("tRNS comes before PLTE"));
+				}
+				let len = chunk.len as usize;
+				if len > header.palette.len() {
+					return Err(malformed("tRNS has more entries than PLTE"));
+				}
+				let mut buf = [0; 256];
+				stream.data(&mut chunk, &mut buf[..len])?;
+				stream.end(&chunk)?;
+				for (px, &alpha) in header.palette.iter_mut().zip(&buf[..len]) {
+					px.3 = alpha;
+				}
+			}
+			b"acTL" => actl = Some(ancillary::contents(&mut stream, chunk)?),
+			b"fcTL" => fctl = Some(ancillary::contents(&mut stream, chunk)?),
+			_ if chunk.is_critical() => return Err(malformed("unknown critical chunk")),
+			_ => ancillary::read(&mut stream, chunk, &mut metadata)?,
+		}
+	};
+
+	if color_type == PngColorType::Indexed && header.palette.is_empty() {
+		return Err(Error::Malformed { offset: chunk.offset, reason: "indexed image without a PLTE chunk" });
+	}
+
+	Ok(Opened { header, stream, chunk, metadata, actl, fctl })
+}
+
+impl<'a, R: std::io::Read> PngPixels<'a, R> {
+	/// reads everything up to the image data, and returns its pixels.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let opened = open(data)?;
+		Self::from_idat(opened.header, Idat::new(opened.stream, opened.chunk)?, opened.metadata)
+	}
+
+	/// the pixels of the image data `idat` that `header` describes.
+	fn from_idat(header: PngHeader, idat: Idat<'a, R>, metadata: PngMetadata) -> Result<Self, Error> {
+		let (width, height) = (header.width, header.height);
+		let stride = header.stride(width);
+		let row = buffer(width as u64 * header.pixel_size() as u64)?;
+
+		Ok(Self {
+			header,
+			inflate: Inflate::new(idat),
+			raw: buffer(stride)?,
+			prev: buffer(stride)?,
+			row,
+			x: width,
+			rows: height,
+			passes: vec![],
+			metadata,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &PngHeader {
+		&self.header
+	}
+
+	/// the ancillary chunks before the image data. the ones after it are only
+	/// read by [`PngPixels::finish_with_metadata`].
+	pub fn metadata(&self) -> &PngMetadata {
+		&self.metadata
+	}
+
+	/// decompresses and unfilters the next row of `width` pixels into `raw`,
+	/// and unpacks it onto `out`.
+	fn read_row(&mut self, width: u32, out: &mut Vec<u8>) -> Result<(), Error> {
+		let header = &self.header;
+		// the header was checked to fit in memory when `raw` was allocated
+		let stride = header.stride(width) as usize;
+		let samples = width as usize * header.color_type.channels();
+
+		core::mem::swap(&mut self.prev, &mut self.raw);
+		read_row(&mut self.inflate, &mut self.raw, &self.prev, stride, header.bpp())?;
+		unpack(&self.raw, header.bit_depth, samples, out);
+		Ok(())
+	}
+
+	/// decompresses the next row, or puts it together from the passes
+	/// before the last.
+	fn next_row(&mut self) -> Result<(), Error> {
+		let y = self.header.height - self.rows;
+		let mut row = core::mem::take(&mut self.row);
+		row.clear();
+
+		let result = if self.header.interlaced && y.is_multiple_of(2) {
+			self.assemble(y, &mut row)
+		}
+		else {
+			// odd rows of interlaced images are their last pass
+			self.read_row(self.header.width, &mut row)
+		};
+		self.row = row;
+		result?;
+
+		self.rows -= 1;
+		self.x = 0;
+		Ok(())
+	}
+
+	/// puts together an even row of an interlaced image.
+	fn assemble(&mut self, y: u32, row: &mut Vec<u8>) -> Result<(), Error> {
+		if self.passes.is_empty() {
+			self.read_passes()?;
+		}
+
+		let size = self.header.pixel_size();
+		for x in 0..self.header.width {
+			let pass = ADAM7_PASS[y as usize % 8][x as usize % 8] as usize;
+			let (x0, y0, dx, dy) = ADAM7[pass];
+			let (width, _) = pass_size(self.header.width, self.header.height, pass);
+			let i = ((y - y0) / dy) as usize * width as usize + ((x - x0) / dx) as usize;
+			row.extend_from_slice(&self.passes[pass][i * size..(i + 1) * size]);
+		}
+		Ok(())
+	}
+
+	/// reads the first six passes of an interlaced image, unpacked.
+	fn read_passes(&mut self) -> Result<(), Error> {
+		// only kept once they're all there, so `assemble` never sees a pass missing
+		let mut passes = Vec::with_capacity(6);
+		for pass in 0..6 {
+			let (width, height) = pass_size(self.header.width, self.header.height, pass);
+			let mut data = buffer(width as u64 * height as u64 * self.header.pixel_size() as u64)?;
+
+			// each pass is filtered on its own
+			self.raw.clear();
+			// empty passes don't even have filter types
+			let height = if width == 0 { 0 } else { height };
+			for _ in 0..height {
+				self.read_row(width, &mut data)?;
+			}
+			passes.push(data);
+		}
+		self.passes = passes;
+		self.raw.clear();
+		Ok(())
+	}
+
+	/// skips any pixels that are left, reads the chunks after the image data,
+	/// and returns the number of bytes the image took up.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		self.end()
+	}
+
+	/// like `finish`, also returning the ancillary chunks from both sides of
+	/// the image data.
+	pub fn finish_with_metadata(mut self) -> Result<(u64, PngMetadata), Error> {
+		let len = self.end()?;
+		Ok((len, self.metadata))
+	}
+
+	/// skips any pixels that are left, and the rest of the zlib stream.
+	fn drain(&mut self) -> Result<(), Error> {
+		while self.rows > 0 {
+			self.next_row()?;
+		}
+
+		// anything decompressed past the last row is ignored, as are bytes
+		// after the end of the zlib stream
+		let mut buf = [0; 512];
+		while !self.inflate.is_done() {
+			self.inflate.read(&mut buf)?;
+		}
+		let idat = self.inflate.input_mut();
+		while zlib::Input::read(idat, &mut buf)? > 0 {}
+		Ok(())
+	}
+
+	fn end(&mut self) -> Result<u64, Error> {
+		self.drain()?;
+		let idat = self.inflate.input_mut();
+		let stream = &mut idat.stream;
+		let mut next = idat.next.take();
+		loop {
+			let chunk = match next.take() {
+				Some(x) => x,
+				None => stream.chunk()?,
+			};
+			match &chunk.kind {
+				b"IEND" => {
+					stream.skip(chunk)?;
+					break;
+				}
+				b"IDAT" => return Err(Error::Malformed { offset: chunk.offset, reason: "IDAT chunks aren't consecutive" }),
+				_ if chunk.is_critical() => {
+					return Err(Error::Malformed { offset: chunk.offset, reason: "unknown critical chunk" });
+				}
+				_ => ancillary::read(stream, chunk, &mut self.metadata)?,
+			}
+		}
+
+		Ok(stream.offset)
+	}
+}
+
+impl<'a, R: std::io::Read> PngPixels<'a, R> {
+	/// hands out pixels with all 16 bits of 16-bit images. 8-bit samples are
+	/// scaled up, so 255 becomes 65535.
+	pub fn wide(self) -> WidePixels<'a, R> {
+		WidePixels(self)
+	}
+
+	fn next_wide(&mut self) -> Option<Result<crate::Rgba16, Error>> {
+		if self.failed {
+			return None;
+		}
+
+		if self.x == self.header.width {
+			if self.rows == 0 {
+				return None;
+			}
+			if let Err(e) = self.next_row() {
+				self.failed = true;
+				return Some(Err(e));
+			}
+		}
+
+		let size = self.header.pixel_size();
+		let i = self.x as usize * size;
+		let px = &self.row[i..i + size];
+		self.x += 1;
+
+		let sample = |i: usize| match self.header.bit_depth {
+			16 => u16::from_be_bytes([px[i * 2], px[i * 2 + 1]]),
+			_ => px[i] as u16 * 257,
+		};
+		Some(Ok(match self.header.color_type {
+			PngColorType::RGB => (sample(0), sample(1), sample(2), 65535),
+			PngColorType::RGBA => (sample(0), sample(1), sample(2), sample(3)),
+			PngColorType::Indexed => match self.header.palette.get(px[0] as usize) {
+				Some(&(r, g, b, a)) => (r as u16 * 257, g as u16 * 257, b as u16 * 257, a as u16 * 257),
+				None => {
+					self.failed = true;
+					let offset = zlib::Input::offset(self.inflate.input());
+					return Some(Err(Error::Malformed { offset, reason: "palette index is past the end of PLTE" }));
+				}
+			},
+		}))
+	}
+}
+
+impl<R: std::io::Read> Iterator for PngPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		// rounded to the nearest 8-bit value
+		let narrow = |x: u16| ((x as u32 * 255 + 32767) / 65535) as u8;
+		Some(self.next_wide()?.map(|(r, g, b, a)| (narrow(r), narrow(g), narrow(b), narrow(a))))
+	}
+}
+
+/// [`PngPixels`], with 16 bits per channel.
+#[derive(Debug)]
+pub struct WidePixels<'a, R>(PngPixels<'a, R>);
+
+impl<R: std::io::Read> WidePixels<'_, R> {
+	pub fn header(&self) -> &PngHeader {
+		self.0.header()
+	}
+
+	/// see [`PngPixels::finish`].
+	pub fn finish(self) -> Result<u64, Error> {
+		self.0.finish()
+	}
+}
+
+impl<R: std::io::Read> Iterator for WidePixels<'_, R> {
+	type Item = Result<crate::Rgba16, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next_wide()
+	}
+}
+
+/// pixels for `Format::decode`, which end at the first error instead of
+/// yielding it.
+struct Pixels<'a, R>(PngPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// push-based encoder state, which `Png::encode` drives.
+#[derive(Debug)]
+pub(crate) struct PngEncoder<W> {
+	out: W,
+	color_type: PngColorType,
+	depth: u8,
+	width: u32,
+	bpp: usize,
+	/// pixels in `row` so far.
+	x: u32,
+	/// where each palette color goes, for indexed images.
+	indices: HashMap<crate::Rgba, u8>,
+	prev: Vec<u8>,
+	row: Vec<u8>,
+	/// a filtered row, filter type included.
+	filtered: Vec<u8>,
+	deflate: Deflate,
+}
+
+impl<W: std::io::Write> PngEncoder<W> {
+	/// writes the signature, IHDR, the icc profile, for indexed images, PLTE
+	/// and tRNS, and then `metadata` as text chunks.
+	pub(crate) fn new(header: &PngHeader, metadata: &crate::Metadata, mut out: W) -> Result<Self, Error> {
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		// the same limits the decoder holds IHDR to
+		if header.width == 0 || header.width > i32::MAX as u32 {
+			return Err(field(16, "width"));
+		}
+		if header.height == 0 || header.height > i32::MAX as u32 {
+			return Err(field(20, "height"));
+		}
+		let indexed = header.color_type == PngColorType::Indexed;
+		let depths: &[u8] = if indexed { &[1, 2, 4, 8] } else { &[8] };
+		if !depths.contains(&header.bit_depth) {
+			return Err(field(24, "bit depth"));
+		}
+		// PLTE comes right after IHDR
+		if indexed && (header.palette.is_empty() || header.palette.len() > 1 << header.bit_depth) {
+			return Err(field(33, "palette"));
+		}
+
+		let stride = header.stride(header.width);
+		let (prev, row, filtered) = (buffer(stride)?, buffer(stride)?, buffer(stride + 1)?);
+
+		let mut ihdr = [0; 13];
+		ihdr[..4].copy_from_slice(&header.width.to_be_bytes());
+		ihdr[4..8].copy_from_slice(&header.height.to_be_bytes());
+		ihdr[8] = header.bit_depth;
+		ihdr[9] = match header.color_type {
+			PngColorType::RGB => 2,
+			PngColorType::RGBA => 6,
+			PngColorType::Indexed => 3,
+		};
+		crate::write_all(&mut out, &SIGNATURE)?;
+		chunk(&mut out, b"IHDR", &ihdr)?;
+		ancillary::write_profile(&mut out, metadata)?;
+
+		let mut indices = HashMap::new();
+		if indexed {
+			let plte = header.palette.iter().flat_map(|x| [x.0, x.1, x.2]).collect::<Vec<_>>();
+			chunk(&mut out, b"PLTE", &plte)?;
+
+			// entries past the last transparent one are opaque
+			let alpha = header.palette.iter().map(|x| x.3).collect::<Vec<_>>();
+			let len = alpha.iter().rposition(|&x| x != 255).map_or(0, |x| x + 1);
+			if len > 0 {
+				chunk(&mut out, b"tRNS", &alpha[..len])?;
+			}
+
+			for (i, &px) in header.palette.iter().enumerate() {
+				indices.entry(px).or_insert(i as u8);
+			}
+		}
+		ancillary::write(&mut out, metadata)?;
+
+		Ok(Self {
+			out,
+			color_type: header.color_type,
+			depth: header.bit_depth,
+			width: header.width,
+			bpp: header.bpp(),
+			x: 0,
+			indices,
+			prev,
+			row,
+			filtered,
+			deflate: Deflate::new(),
+		})
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		match self.color_type {
+			PngColorType::RGB => self.row.extend_from_slice(&[px.0, px.1, px.2]),
+			PngColorType::RGBA => self.row.extend_from_slice(&[px.0, px.1, px.2, px.3]),
+			PngColorType::Indexed => {
+				let Some(&i) = self.indices.get(&px)
+				else {
+					return Err(Error::InvalidInput { reason: "pixel isn't in the palette" });
+				};
+				// indices smaller than a byte are packed from the high bits
+				let bit = (self.x as u64 * self.depth as u64 % 8) as u8;
+				if bit == 0 {
+					self.row.push(0);
+				}
+				if let Some(x) = self.row.last_mut() {
+					*x |= i << (8 - self.depth - bit);
+				}
+			}
+		}
+		self.x += 1;
+		if self.x < self.width {
+			return Ok(());
+		}
+
+		// filters rarely help palette indices
+		let kind = if self.color_type == PngColorType::Indexed { 0 } else { 4 };
+		filter(kind, &self.row, &self.prev, self.bpp, &mut self.filtered);
+		self.deflate.write(&self.filtered);
+		core::mem::swap(&mut self.prev, &mut self.row);
+		self.row.clear();
+		self.x = 0;
+
+		// only full chunks go out until the end
+		let data = self.deflate.output();
+		let full = data.len() / IDAT_SIZE * IDAT_SIZE;
+		for x in data[..full].chunks(IDAT_SIZE) {
+			chunk(&mut self.out, b"IDAT", x)?;
+		}
+		data.drain(..full);
+		Ok(())
+	}
+
+	/// compresses what's left, and writes the last IDAT and IEND.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		self.deflate.finish();
+		let data = self.deflate.output();
+		for x in data.chunks(IDAT_SIZE) {
+			chunk(&mut self.out, b"IDAT", x)?;
+		}
+		data.clear();
+		chunk(&mut self.out, b"IEND", &[])
+	}
+}
+
+impl crate::Format for Png {
+	type Header = PngHeader;
+	type Options = PngOptions;
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = PngPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = PngPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	/// 16-bit images keep all their bits.
+	fn decode_wide(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba16>), Error> {
+		let pixels = PngPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels.wide().map_while(Result::ok)))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		self.encode_with_metadata(data, header, &crate::Metadata::new(), out)
+	}
+
+	/// only the text before the image data is collected, since the pixels
+	/// come before anything after it. use [`PngPixels::finish_with_metadata`]
+	/// for all of it.
+	fn decode_with_metadata(self, data: &mut impl std::io::Read) -> Result<(Self::Header, crate::Metadata, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = PngPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels.metadata().text.clone(), Pixels(pixels)))
+	}
+
+	/// text goes in `tEXt` chunks, or `iTXt` if latin-1 can't hold it,
+	/// before the image data, and an icc profile goes in `iCCP`.
+	fn encode_with_metadata(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, metadata: &crate::Metadata, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = PngEncoder::new(&header, metadata, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+
+	fn encode_with_options(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, options: &PngOptions, out: &mut impl std::io::Write) -> Result<(), Error> {
+		// before anything is written
+		let deflate = Deflate::with_level(options.level).ok_or(Error::InvalidInput { reason: "compression level past 9" })?;
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = PngEncoder::new(&header, &crate::Metadata::new(), out)?;
+		encoder.deflate = deflate;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+/// the frames of an apng, or the one frame of a still png.
+impl crate::AnimatedFormat for Png {
+	fn decode_frames(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl crate::animation::Frames), Error> {
+		let frames = ApngFrames::new(data)?;
+		Ok((frames.header().clone(), frames))
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, png};
+
+	const IMAGE_GRADIENT: &[u8; 477] = include_bytes!("../test/gradient.png");
+
+	fn gradient(x: u32, y: u32) -> crate::Rgba {
+		((x * 7) as u8, (y * 30) as u8, (x * y) as u8, 255)
+	}
+
+	#[test]
+	fn decode() {
+		// dynamic codes, every filter type, ancillary chunks on both sides of
+		// the image data, which is split over 17 byte IDAT chunks
+		let mut image = &IMAGE_GRADIENT[..];
+		let (header, iter) = png::Png.decode(&mut image).expect("error?");
+
+		assert_eq!(header.width, 33);
+		assert_eq!(header.height, 7);
+		assert_eq!(header.color_type, png::PngColorType::RGB);
+
+		let data = iter.collect::<Vec<_>>();
+		assert_eq!(data.len(), 33 * 7);
+		for (i, px) in data.into_iter().enumerate() {
+			let (x, y) = (i as u32 % 33, i as u32 / 33);
+			assert_eq!(px, gradient(x, y), "{x}, {y}");
+		}
+
+		// stored blocks
+		let mut image = &include_bytes!("../test/stored.png")[..];
+		let (_, iter) = png::Png.decode(&mut image).expect("error?");
+		assert!(iter.enumerate().all(|(i, px)| px == gradient(i as u32 % 33, i as u32 / 33)));
+	}
+
+	#[test]
+	fn decode_interlaced() {
+		let mut image = &include_bytes!("../test/interlaced.png")[..];
+		let (header, iter) = png::Png.decode(&mut image).expect("error?");
+		assert!(header.interlaced);
+		let data = iter.collect::<Vec<_>>();
+		assert_eq!(data.len(), 33 * 7);
+		for (i, px) in data.into_iter().enumerate() {
+			let (x, y) = (i as u32 % 33, i as u32 / 33);
+			assert_eq!(px, gradient(x, y), "{x}, {y}");
+		}
+
+		// narrower than a tile, so some passes are empty
+		let mut image = &include_bytes!("../test/interlaced_small.png")[..];
+		let (header, iter) = png::Png.decode(&mut image).expect("error?");
+		assert_eq!((header.width, header.height, header.color_type), (3, 2, png::PngColorType::RGBA));
+		let expected = (0..6).map(|i| {
+			let (x, y) = (i % 3, i / 3);
+			let (r, g, b, _) = gradient(x, y);
+			(r, g, b, ((x + y * 3) * 40) as u8)
+		});
+		assert!(iter.eq(expected));
+
+		// leftover rows, from both kinds of pass, get skipped
+		for n in [0, 40, 33 * 2 + 5] {
+			let mut image = &include_bytes!("../test/interlaced.png")[..];
+			let mut pixels = png::PngPixels::new(&mut image).expect("error?");
+			for _ in 0..n {
+				pixels.next().expect("error?").expect("error?");
+			}
+			pixels.finish().expect("error?");
+		}
+	}
+
+	/// the palette and pixels the indexed fixtures were made with, `n` colors.
+	fn indexed(n: u32) -> (Vec<crate::Rgba>, impl Fn(u32, u32) -> u32) {
+		let palette = (0..n).map(|i| {
+			let alpha = if i % 3 == 0 { (i * 5) as u8 } else { 255 };
+			((i * 37) as u8, (i * 91) as u8, (i * 13) as u8, alpha)
+		});
+		(palette.collect(), move |x, y| (x + y * x) % n)
+	}
+
+	#[test]
+	fn decode_indexed() {
+		let images: [(u8, u32, &[u8]); 4] = [
+			(1, 2, include_bytes!("../test/indexed_1.png")),
+			(2, 3, include_bytes!("../test/indexed_2.png")),
+			(4, 11, include_bytes!("../test/indexed_4.png")),
+			(8, 40, include_bytes!("../test/indexed_8.png")),
+		];
+		for (depth, n, mut image) in images {
+			let (header, iter) = png::Png.decode(&mut image).expect("error?");
+			let (palette, index) = indexed(n);
+			assert_eq!((header.color_type, header.bit_depth), (png::PngColorType::Indexed, depth));
+			assert_eq!(header.palette, palette);
+
+			// 13 pixels don't fill the last byte of a row
+			let data = iter.collect::<Vec<_>>();
+			assert_eq!(data.len(), 13 * 7);
+			for (i, px) in data.into_iter().enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				assert_eq!(px, palette[index(x, y) as usize], "{depth}: {x}, {y}");
+			}
+		}
+
+		// passes smaller than a byte
+		let mut image = &include_bytes!("../test/indexed_interlaced.png")[..];
+		let (header, iter) = png::Png.decode(&mut image).expect("error?");
+		assert!(header.interlaced);
+		let (palette, index) = indexed(11);
+		assert!(iter.eq((0..13 * 9).map(|i| palette[index(i % 13, i / 13) as usize])));
+	}
+
+	#[test]
+	fn decode_wide() {
+		let deep = |x: u32, y: u32| {
+			let channel = |x: u32| (x & 0xffff) as u16;
+			(channel(x * 1999), channel(y * 9001), channel(x * y * 301), channel(65535 - x * 1000 - y))
+		};
+		let narrow = |x: u16| (x as f64 / 257.0).round() as u8;
+
+		// the rgba one is interlaced too
+		let images: [(png::PngColorType, &[u8]); 2] = [
+			(png::PngColorType::RGB, include_bytes!("../test/deep_rgb.png")),
+			(png::PngColorType::RGBA, include_bytes!("../test/deep_rgba.png")),
+		];
+		for (color_type, image) in images {
+			let expected = (0..13 * 7).map(|i| {
+				let (r, g, b, a) = deep(i % 13, i / 13);
+				match color_type {
+					png::PngColorType::RGB => (r, g, b, 65535),
+					_ => (r, g, b, a),
+				}
+			});
+
+			let mut reader = image;
+			let pixels = png::PngPixels::new(&mut reader).expect("error?");
+			assert_eq!((pixels.header().color_type, pixels.header().bit_depth), (color_type, 16));
+			let mut pixels = pixels.wide();
+			assert!((&mut pixels).map(|x| x.expect("error?")).eq(expected.clone()));
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			let mut reader = image;
+			let (_, iter) = png::Png.decode_wide(&mut reader).expect("error?");
+			assert!(iter.eq(expected.clone()));
+
+			// and through `decode_as`, low bytes and all
+			let mut reader = image;
+			let (_, iter) = png::Png.decode_as::<crate::Rgba16>(&mut reader).expect("error?");
+			assert!(iter.eq(expected.clone()));
+			let mut reader = image;
+			let (_, iter) = png::Png.decode_as::<crate::RgbaF32>(&mut reader).expect("error?");
+			assert!(iter.map(crate::Pixel::convert::<crate::Rgba16>).eq(expected.clone()));
+
+			let mut reader = image;
+			let (_, iter) = png::Png.decode(&mut reader).expect("error?");
+			assert!(iter.eq(expected.map(|(r, g, b, a)| (narrow(r), narrow(g), narrow(b), narrow(a)))));
+		}
+
+		// 8-bit images are scaled up
+		let mut image = &IMAGE_GRADIENT[..];
+		let mut pixels = png::PngPixels::new(&mut image).expect("error?").wide();
+		let (r, g, b, a) = gradient(5, 3);
+		let wide = |x: u8| x as u16 * 257;
+		assert_eq!(pixels.nth(3 * 33 + 5).expect("error?").expect("error?"), (wide(r), wide(g), wide(b), wide(a)));
+	}
+
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn decode_rgba() {
+		use crate::qoi;
+
+		let mut image = &include_bytes!("../test/small.png")[..];
+		let (header, iter) = png::Png.decode(&mut image).expect("error?");
+		assert_eq!(header.color_type, png::PngColorType::RGBA);
+
+		let mut expected = &include_bytes!("../test/small.qoi")[..];
+		let (_, expected) = qoi::Qoi.decode(&mut expected).expect("error?");
+		assert!(iter.eq(expected));
+	}
+
+	#[test]
+	fn encode() {
+		for color_type in [png::PngColorType::RGB, png::PngColorType::RGBA] {
+			// noisy enough to take a few IDAT chunks
+			let (width, height) = (300, 200);
+			let header = png::PngHeader { width, height, color_type, bit_depth: 8, interlaced: false, palette: vec![] };
+			let pixels = (0..width * height).map(|i| {
+				let (x, y) = (i % width, i / width);
+				let noise = i.wrapping_mul(0x9e3779b1);
+				((x * 3) as u8, (y ^ x) as u8, ((noise ^ noise >> 15).wrapping_mul(0x85ebca6b) >> 24) as u8, (y + 20) as u8)
+			});
+
+			let mut out = vec![];
+			png::Png.encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+			assert_eq!(out[..8], png::SIGNATURE);
+			assert!(out.windows(4).filter(|x| x == b"IDAT").count() > 1);
+
+			let mut reader = &out[..];
+			let mut decoded = png::PngPixels::new(&mut reader).expect("error?");
+			assert_eq!(*decoded.header(), header);
+			for (i, (a, b)) in (&mut decoded).zip(pixels).enumerate() {
+				let a = a.expect("error?");
+				let b = match color_type {
+					png::PngColorType::RGB => (b.0, b.1, b.2, 255),
+					png::PngColorType::RGBA => b,
+					png::PngColorType::Indexed => unreachable!(),
+				};
+				assert_eq!(a, b, "{i}");
+			}
+			assert_eq!(decoded.finish().expect("error?"), out.len() as u64);
+		}
+
+		// every level decodes to the same image, and storing it is the largest
+		let (width, height) = (64, 48);
+		let header = png::PngHeader { width, height, color_type: png::PngColorType::RGB, bit_depth: 8, interlaced: false, palette: vec![] };
+		let pixels = (0..width * height).map(|i| ((i % width * 4) as u8, (i / width * 5) as u8, 90, 255)).collect::<Vec<_>>();
+		let mut sizes = vec![];
+		for level in 0..=9 {
+			let mut out = vec![];
+			let options = png::PngOptions { level };
+			png::Png.encode_with_options(pixels.iter().copied(), header.clone(), &options, &mut out).expect("error?");
+			let mut reader = &out[..];
+			let (_, decoded) = png::Png.decode(&mut reader).expect("error?");
+			assert!(decoded.eq(pixels.iter().copied()), "{level}");
+			sizes.push(out.len());
+		}
+		assert!(sizes[1..].iter().all(|&n| n < sizes[0] / 2), "{sizes:?}");
+		assert!(sizes[0] > (width * height * 3) as usize);
+
+		// the default level is what `encode` uses
+		let (mut a, mut b) = (vec![], vec![]);
+		png::Png.encode(pixels.iter().copied(), header.clone(), &mut a).expect("error?");
+		png::Png.encode_with_options(pixels.iter().copied(), header.clone(), &png::PngOptions::default(), &mut b).expect("error?");
+		assert_eq!(a, b);
+
+		let mut out = vec![];
+		let options = png::PngOptions { level: 10 };
+		let result = png::Png.encode_with_options(pixels.iter().copied(), header, &options, &mut out);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+		assert!(out.is_empty());
+
+		// a short iterator doesn't end the image
+		let header = png::PngHeader {
+			width: 4,
+			height: 4,
+			color_type: png::PngColorType::RGB,
+			bit_depth: 8,
+			interlaced: false,
+			palette: vec![],
+		};
+		let mut out = vec![];
+		let result = png::Png.encode([(1, 2, 3, 4); 9].into_iter(), header, &mut out);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 16, got: 9 })), "{result:?}");
+		assert!(!out.windows(4).any(|x| x == b"IEND"));
+	}
+
+	#[test]
+	fn encode_indexed() {
+		let (width, height) = (13, 9);
+		for (depth, n) in [(1, 2), (2, 3), (4, 11), (8, 40)] {
+			let (palette, index) = indexed(n);
+			let pixels = (0..width * height).map(|i| palette[index(i % width, i / width) as usize]);
+
+			let header = png::PngHeader {
+				width,
+				height,
+				color_type: png::PngColorType::Indexed,
+				bit_depth: depth,
+				interlaced: false,
+				palette: png::palette(pixels.clone()).expect("error?"),
+			};
+			let mut out = vec![];
+			png::Png.encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+
+			let mut reader = &out[..];
+			let (decoded, iter) = png::Png.decode(&mut reader).expect("error?");
+			assert_eq!(decoded, header);
+			assert!(iter.eq(pixels), "{depth}");
+		}
+
+		let pixels = (0..=256).map(|i: u32| (i as u8, (i >> 8) as u8, 0, 255));
+		assert_eq!(png::palette(pixels.clone().take(256)).map(|x| x.len()), Some(256));
+		assert_eq!(png::palette(pixels), None);
+
+		// palettes have to fit the bit depth, and pixels have to be in them
+		let mut header = png::PngHeader {
+			width: 2,
+			height: 1,
+			color_type: png::PngColorType::Indexed,
+			bit_depth: 1,
+			interlaced: false,
+			palette: vec![(0, 0, 0, 255); 3],
+		};
+		let encode = |header: &png::PngHeader, pixels: &[crate::Rgba]| {
+			let mut encoder = png::PngEncoder::new(header, &crate::Metadata::new(), vec![])?;
+			pixels.iter().try_for_each(|&px| encoder.push(px))
+		};
+		assert!(matches!(encode(&header, &[]), Err(Error::InvalidHeader { field: "palette", .. })));
+		header.bit_depth = 3;
+		assert!(matches!(encode(&header, &[]), Err(Error::InvalidHeader { field: "bit depth", .. })));
+		// 16-bit images decode, but don't encode
+		header.color_type = png::PngColorType::RGB;
+		header.bit_depth = 16;
+		assert!(matches!(encode(&header, &[]), Err(Error::InvalidHeader { field: "bit depth", .. })));
+		header.color_type = png::PngColorType::Indexed;
+		header.bit_depth = 2;
+		assert!(matches!(encode(&header, &[(1, 1, 1, 255)]), Err(Error::InvalidInput { .. })));
+
+		// nothing is written for sizes the decoder would turn away
+		header.color_type = png::PngColorType::RGB;
+		header.bit_depth = 8;
+		for (width, height, field) in [(0, 1, "width"), (1, 0, "height"), (1 << 31, 1, "width"), (1, u32::MAX, "height")] {
+			let header = png::PngHeader { width, height, ..header.clone() };
+			let mut out = vec![];
+			let result = png::Png.encode(core::iter::empty(), header, &mut out);
+			assert!(matches!(result, Err(Error::InvalidHeader { field: f, .. }) if f == field), "{result:?}");
+			assert!(out.is_empty());
+		}
+	}
+
+	#[test]
+	fn finish() {
+		// pixels left over are skipped, and nothing after IEND is read
+		let mut file = IMAGE_GRADIENT.to_vec();
+		file.extend_from_slice(b"after");
+
+		let mut reader = &file[..];
+		let mut pixels = png::PngPixels::new(&mut reader).expect("error?");
+		assert_eq!(pixels.nth(40).expect("error?").expect("error?"), gradient(7, 1));
+		assert_eq!(pixels.finish().expect("error?"), IMAGE_GRADIENT.len() as u64);
+		assert_eq!(reader, b"after");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = png::PngPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".png")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_signature", Error::InvalidMagic { offset: 0 });
+		case!("bad_crc", Error::ChecksumMismatch { .. });
+		case!("zero_width", Error::InvalidHeader { offset: 16, field: "width" });
+		case!("bad_bit_depth", Error::InvalidHeader { offset: 24, field: "bit depth" });
+		case!("bad_color_type", Error::InvalidHeader { offset: 25, field: "color type" });
+		case!("unknown_critical", Error::Malformed { offset: 33, reason: "unknown critical chunk" });
+		case!("no_idat", Error::Malformed { offset: 33, reason: "no IDAT chunk" });
+		case!("truncated", Error::UnexpectedEof { offset: 50 });
+		case!("bad_filter", Error::Malformed { reason: "invalid filter type", .. });
+		case!("short_data", Error::Malformed { reason: "image data is too short", .. });
+		case!("bad_adler", Error::ChecksumMismatch { .. });
+		case!("missing_iend", Error::UnexpectedEof { offset: 97 });
+		case!("no_plte", Error::Malformed { offset: 33, reason: "indexed image without a PLTE chunk" });
+		case!("bad_plte", Error::Malformed { offset: 33, reason: "PLTE isn't 1 to 256 colors" });
+		case!("trns_before_plte", Error::Malformed { offset: 33, reason: "tRNS comes before PLTE" });
+		case!("bad_index", Error::Malformed { reason: "palette index is past the end of PLTE", .. });
+
+		assert!(decode(IMAGE_GRADIENT).is_ok());
+	}
+}
@@ -0,0 +1,389 @@
+/*!
+png ancillary chunks.
+
+text from `tEXt`, `zTXt` and `iTXt` goes into a [`Metadata`] map, keyed
+by keyword. `tEXt` and `zTXt` are latin-1, which every `char` below 256
+covers, and `iTXt` is utf-8. its language tag and translated keyword aren't
+kept. `eXIf` goes in the map too, as bytes under `metadata::EXIF`, and so
+does the profile from `iCCP`, under `metadata::ICC_PROFILE`, without its
+name. `gAMA` and `pHYs` are parsed into fields of their own.
+*/
+
+use super::{Chunk, Stream};
+use crate::Error;
+use crate::error::oom;
+use crate::metadata::{self, Metadata, MetadataValue};
+use crate::zlib::{self, Deflate, Inflate};
+
+/// decompressed text bigger than this is taken to be a zip bomb, not text.
+const TEXT_MAX: usize = 1 << 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PngUnit {
+	/// only the aspect ratio is known.
+	Unknown,
+	Meter,
+}
+
+/// the size of a pixel, from `pHYs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PngPhysical {
+	/// pixels per unit, horizontally.
+	pub x: u32,
+	/// pixels per unit, vertically.
+	pub y: u32,
+	pub unit: PngUnit,
+}
+
+/// the ancillary chunks of a png that dwelf understands.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PngMetadata {
+	pub text: Metadata,
+	/// the gamma the image was encoded with, times 100000, from `gAMA`.
+	pub gamma: Option<u32>,
+	pub physical: Option<PngPhysical>,
+}
+
+/// zlib data inside a chunk that's been read into memory.
+struct Compressed<'a> {
+	data: &'a [u8],
+	offset: u64,
+}
+
+impl zlib::Input for Compressed<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		let n = buf.len().min(self.data.len());
+		buf[..n].copy_from_slice(&self.data[..n]);
+		self.data = &self.data[n..];
+		self.offset = self.offset.saturating_add(n as u64);
+		Ok(n)
+	}
+
+	fn offset(&self) -> u64 {
+		self.offset
+	}
+}
+
+fn latin1(data: &[u8]) -> String {
+	data.iter().map(|&x| x as char).collect()
+}
+
+/// reads all of a chunk's data, growing the buffer as the data actually
+/// shows up, rather than trusting the length.
+pub(super) fn contents<R: std::io::Read>(stream: &mut Stream<'_, R>, mut chunk: Chunk) -> Result<Vec<u8>, Error> {
+	let mut out = vec![];
+	let mut buf = [0; 4096];
+	let mut left = chunk.len as usize;
+	while left > 0 {
+		let n = left.min(buf.len());
+		stream.data(&mut chunk, &mut buf[..n])?;
+		out.try_reserve(n).map_err(|_| oom())?;
+		out.extend_from_slice(&buf[..n]);
+		left -= n;
+	}
+	stream.end(&chunk)?;
+	Ok(out)
+}
+
+fn inflate(data: &[u8], offset: u64) -> Result<Vec<u8>, Error> {
+	let mut inflate = Inflate::new(Compressed { data, offset });
+	let mut out = vec![];
+	let mut buf = [0; 4096];
+	loop {
+		let n = inflate.read(&mut buf)?;
+		if n == 0 {
+			return Ok(out);
+		}
+		if out.len() + n > TEXT_MAX {
+			return Err(Error::Malformed { offset, reason: "compressed text is over 16 MiB" });
+		}
+		out.try_reserve(n).map_err(|_| oom())?;
+		out.extend_from_slice(&buf[..n]);
+	}
+}
+
+/// reads `chunk` into `metadata` if it's one of the chunks above, and skips
+/// it otherwise.
+pub(super) fn read<R: std::io::Read>(
+	stream: &mut Stream<'_, R>,
+	chunk: Chunk,
+	metadata: &mut PngMetadata,
+) -> Result<(), Error> {
+	let offset = chunk.offset;
+	let malformed = |reason| Error::Malformed { offset, reason };
+	// where the chunk's data starts
+	let start = offset.saturating_add(8);
+
+	match &chunk.kind {
+		b"gAMA" => {
+			if chunk.len != 4 {
+				return Err(malformed("gAMA isn't 4 bytes"));
+			}
+			let data = contents(stream, chunk)?;
+			metadata.gamma = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]));
+		}
+		b"pHYs" => {
+			if chunk.len != 9 {
+				return Err(malformed("pHYs isn't 9 bytes"));
+			}
+			let data = contents(stream, chunk)?;
+			let unit = match data[8] {
+				0 => PngUnit::Unknown,
+				1 => PngUnit::Meter,
+				_ => return Err(malformed("unknown pHYs unit")),
+			};
+			metadata.physical = Some(PngPhysical {
+				x: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+				y: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+				unit,
+			});
+		}
+		b"tEXt" | b"zTXt" | b"iTXt" => {
+			let kind = chunk.kind;
+			let data = contents(stream, chunk)?;
+			let Some(nul) = data.iter().position(|&x| x == 0)
+			else {
+				return Err(malformed("text chunk has no keyword"));
+			};
+			let (key, rest) = (&data[..nul], &data[nul + 1..]);
+			if key.is_empty() || key.len() > 79 {
+				return Err(malformed("keyword isn't 1 to 79 bytes"));
+			}
+			// where `rest` starts
+			let start = start.saturating_add(key.len() as u64 + 1);
+
+			let value = match &kind {
+				b"tEXt" => latin1(rest),
+				b"zTXt" => match rest {
+					[0, data @ ..] => latin1(&inflate(data, start.saturating_add(1))?),
+					_ => return Err(malformed("unknown compression method")),
+				},
+				_ => {
+					let [compressed, method, rest @ ..] = rest
+					else {
+						return Err(malformed("iTXt is too short"));
+					};
+					// the language tag, then the translated keyword
+					let mut parts = rest.splitn(3, |&x| x == 0);
+					let (Some(language), Some(translated), Some(text)) = (parts.next(), parts.next(), parts.next())
+					else {
+						return Err(malformed("iTXt is too short"));
+					};
+					let text = match (compressed, method) {
+						(0, _) => text.to_vec(),
+						(1, 0) => {
+							let skipped = 2 + language.len() as u64 + 1 + translated.len() as u64 + 1;
+							inflate(text, start.saturating_add(skipped))?
+						}
+						_ => return Err(malformed("unknown compression method")),
+					};
+					String::from_utf8(text).map_err(|_| malformed("iTXt text isn't utf-8"))?
+				}
+			};
+			metadata.text.insert(latin1(key), value);
+		}
+		b"eXIf" => {
+			let data = contents(stream, chunk)?;
+			metadata.text.insert(metadata::EXIF, data);
+		}
+		b"iCCP" => {
+			let data = contents(stream, chunk)?;
+			let Some(nul) = data.iter().position(|&x| x == 0)
+			else {
+				return Err(malformed("iCCP has no profile name"));
+			};
+			if nul == 0 || nul > 79 {
+				return Err(malformed("profile name isn't 1 to 79 bytes"));
+			}
+			let profile = match &data[nul + 1..] {
+				[0, data @ ..] => inflate(data, start.saturating_add(nul as u64 + 2))?,
+				_ => return Err(malformed("unknown compression method")),
+			};
+			metadata.text.insert(metadata::ICC_PROFILE, profile);
+		}
+		_ => stream.skip(chunk)?,
+	}
+	Ok(())
+}
+
+/// writes the icc profile in `metadata`, if there is one, as `iCCP`, which
+/// has to come before `PLTE`.
+pub(super) fn write_profile(out: &mut impl std::io::Write, metadata: &Metadata) -> Result<(), Error> {
+	let Some(profile) = metadata.get(metadata::ICC_PROFILE)
+	else {
+		return Ok(());
+	};
+	let mut deflate = Deflate::new();
+	deflate.write(profile.as_bytes());
+	deflate.finish();
+	let mut data = b"ICC profile\0\0".to_vec();
+	data.append(deflate.output());
+	super::chunk(out, b"iCCP", &data)
+}
+
+/// writes the entries of `metadata` as `tEXt` chunks, or `iTXt` for text
+/// that latin-1 can't hold, and exif as `eXIf`. entries png can't
+/// represent, with keys that aren't 1 to 79 latin-1 characters or values
+/// containing a nul, are left out, and so is the icc profile, which
+/// `write_profile` writes.
+pub(super) fn write(out: &mut impl std::io::Write, metadata: &Metadata) -> Result<(), Error> {
+	for (key, value) in metadata.iter() {
+		if key == metadata::EXIF {
+			super::chunk(out, b"eXIf", value.as_bytes())?;
+			continue;
+		}
+		if key == metadata::ICC_PROFILE {
+			continue;
+		}
+		let Some(mut data) = key.chars().map(|x| u8::try_from(x).ok()).collect::<Option<Vec<_>>>()
+		else {
+			continue;
+		};
+		if data.is_empty() || data.len() > 79 || data.contains(&0) || value.as_bytes().contains(&0) {
+			continue;
+		}
+		data.push(0);
+
+		let latin1 = match value {
+			MetadataValue::Text(x) => x.chars().map(|x| u8::try_from(x).ok()).collect::<Option<Vec<_>>>(),
+			MetadataValue::Bytes(x) => Some(x.clone()),
+		};
+		match latin1 {
+			Some(text) => {
+				data.extend_from_slice(&text);
+				super::chunk(out, b"tEXt", &data)?;
+			}
+			None => {
+				// uncompressed, with no language or translated keyword
+				data.extend_from_slice(&[0, 0, 0, 0]);
+				data.extend_from_slice(value.as_bytes());
+				super::chunk(out, b"iTXt", &data)?;
+			}
+		}
+	}
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::metadata::{self, Metadata};
+	use crate::png::{self, PngPhysical, PngUnit};
+	use crate::{Error, Format};
+
+	#[test]
+	fn decode() {
+		// text of every kind, before and after the image data
+		let mut data = &include_bytes!("../../test/metadata.png")[..];
+		let pixels = png::PngPixels::new(&mut data).expect("error?");
+
+		let meta = pixels.metadata();
+		assert_eq!(meta.gamma, Some(45455));
+		assert_eq!(meta.physical, Some(PngPhysical { x: 2835, y: 3780, unit: PngUnit::Meter }));
+		assert_eq!(meta.text.get_text(metadata::TITLE), Some("caf\u{e9}"));
+		assert_eq!(meta.text.get_text(metadata::DESCRIPTION), Some("squeezed squeezed squeezed"));
+		assert_eq!(meta.text.get_text(metadata::AUTHOR), None);
+
+		let (len, meta) = pixels.finish_with_metadata().expect("error?");
+		assert_eq!(len, include_bytes!("../../test/metadata.png").len() as u64);
+		assert_eq!(meta.text.get_text(metadata::AUTHOR), Some("\u{3042}\u{3044}"));
+		assert_eq!(meta.text.get_text(metadata::COMMENT), Some("compressed \u{3046}\u{3048}"));
+		let keys = meta.text.iter().map(|(k, _)| k).collect::<Vec<_>>();
+		assert_eq!(keys, [metadata::TITLE, metadata::DESCRIPTION, metadata::AUTHOR, metadata::COMMENT]);
+
+		// only what comes before the image data
+		let mut data = &include_bytes!("../../test/metadata.png")[..];
+		let (_, text, iter) = png::Png.decode_with_metadata(&mut data).expect("error?");
+		assert_eq!(text.len(), 2);
+		assert_eq!(iter.count(), 4 * 4);
+	}
+
+	#[test]
+	fn encode() {
+		let mut meta = Metadata::new();
+		meta.insert(metadata::TITLE, "caf\u{e9}");
+		meta.insert(metadata::AUTHOR, "\u{3042}\u{3044}");
+		meta.insert("Raw", vec![1, 2, 255]);
+		meta.insert(metadata::EXIF, b"MM\0*\0\0\0\x08\0\0".to_vec());
+		let profile = (0..600).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+		meta.insert(metadata::ICC_PROFILE, profile.clone());
+		// left out
+		meta.insert("", "empty key");
+		meta.insert("\u{3042}", "key isn't latin-1");
+		meta.insert(metadata::COMMENT, "has a \0 in it");
+
+		let header = png::PngHeader {
+			width: 2,
+			height: 2,
+			color_type: png::PngColorType::RGB,
+			bit_depth: 8,
+			interlaced: false,
+			palette: vec![],
+		};
+		let pixels = [(1, 2, 3, 255); 4];
+		let mut out = vec![];
+		png::Png.encode_with_metadata(pixels.into_iter(), header, &meta, &mut out).expect("error?");
+		assert_eq!(out.windows(4).filter(|x| x == b"tEXt").count(), 2);
+		assert_eq!(out.windows(4).filter(|x| x == b"iTXt").count(), 1);
+		assert_eq!(out.windows(4).filter(|x| x == b"eXIf").count(), 1);
+		assert_eq!(out.windows(4).filter(|x| x == b"iCCP").count(), 1);
+
+		let mut data = &out[..];
+		let (_, text, iter) = png::Png.decode_with_metadata(&mut data).expect("error?");
+		let entries = text.iter().map(|(k, v)| (k, v.as_bytes())).collect::<Vec<_>>();
+		assert_eq!(entries, [
+			// right after IHDR
+			(metadata::ICC_PROFILE, &profile[..]),
+			(metadata::TITLE, "caf\u{e9}".as_bytes()),
+			(metadata::AUTHOR, "\u{3042}\u{3044}".as_bytes()),
+			// latin-1 comes back as text
+			("Raw", "\u{1}\u{2}\u{ff}".as_bytes()),
+			// nuls and all
+			(metadata::EXIF, b"MM\0*\0\0\0\x08\0\0"),
+		]);
+		assert!(iter.eq(pixels));
+
+		// the profile comes before PLTE
+		let header = png::PngHeader {
+			width: 2,
+			height: 2,
+			color_type: png::PngColorType::Indexed,
+			bit_depth: 1,
+			interlaced: false,
+			palette: vec![(1, 2, 3, 255)],
+		};
+		let mut out = vec![];
+		png::Png.encode_with_metadata(pixels.into_iter(), header, &meta, &mut out).expect("error?");
+		let at = |kind: &[u8]| out.windows(4).position(|x| x == kind);
+		assert!(at(b"iCCP") < at(b"PLTE"));
+		let mut data = &out[..];
+		let (_, text, _) = png::Png.decode_with_metadata(&mut data).expect("error?");
+		assert_eq!(text.get(metadata::ICC_PROFILE).map(|x| x.as_bytes()), Some(&profile[..]));
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = png::PngPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish_with_metadata()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../../test/corrupt/", $name, ".png")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_gama", Error::Malformed { offset: 33, reason: "gAMA isn't 4 bytes" });
+		case!("no_keyword", Error::Malformed { offset: 33, reason: "text chunk has no keyword" });
+		case!("bad_itxt", Error::Malformed { offset: 33, reason: "iTXt text isn't utf-8" });
+		case!("bad_ztxt", Error::Malformed { .. });
+	}
+}
@@ -0,0 +1,469 @@
+/*!
+apng animations.
+
+frames are decoded one at a time, and each streams its rows like a still
+image, so nothing is kept between frames. putting them together on the
+canvas is left to the caller, going by each frame's offset and its dispose
+and blend ops.
+
+a png without `acTL` comes out as one frame covering the whole image.
+*/
+
+use super::{Chunk, Idat, PngHeader, PngMetadata, PngPixels, Stream, ancillary, open};
+use crate::Error;
+
+/// the animation as a whole, from `acTL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ApngAnimation {
+	/// frames in the animation, which the default image may or may not be
+	/// the first of.
+	pub frames: u32,
+	/// times to play the animation, or 0 to loop forever.
+	pub plays: u32,
+}
+
+/// what happens to a frame's area before the next frame is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ApngDispose {
+	/// it's left as it is.
+	None,
+	/// it's cleared to transparent black.
+	Background,
+	/// it goes back to what it was before the frame.
+	Previous,
+}
+
+/// how a frame is drawn over the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ApngBlend {
+	/// replaces what's under it, alpha included.
+	Source,
+	/// alpha composited over what's under it.
+	Over,
+}
+
+/// a frame's place in the animation, from its `fcTL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ApngFrameControl {
+	pub width: u32,
+	pub height: u32,
+	/// where the frame's top left corner goes on the canvas.
+	pub x: u32,
+	pub y: u32,
+	/// how long the frame is shown, as a fraction of a second. a denominator
+	/// of 0 means 100.
+	pub delay_num: u16,
+	pub delay_den: u16,
+	pub dispose: ApngDispose,
+	pub blend: ApngBlend,
+}
+
+impl ApngFrameControl {
+	pub fn delay(&self) -> std::time::Duration {
+		let den = match self.delay_den {
+			0 => 100,
+			x => x as u64,
+		};
+		std::time::Duration::from_nanos(self.delay_num as u64 * 1_000_000_000 / den)
+	}
+
+	/// the control of a still image, as the one frame of its animation.
+	fn still(header: &PngHeader) -> Self {
+		Self {
+			width: header.width,
+			height: header.height,
+			x: 0,
+			y: 0,
+			delay_num: 0,
+			delay_den: 0,
+			dispose: ApngDispose::None,
+			blend: ApngBlend::Source,
+		}
+	}
+
+	fn parse(data: &[u8], offset: u64, canvas: &PngHeader) -> Result<Self, Error> {
+		let malformed = |reason| Error::Malformed { offset, reason };
+		let &[_, _, _, _, w0, w1, w2, w3, h0, h1, h2, h3, x0, x1, x2, x3, y0, y1, y2, y3, n0, n1, d0, d1, dispose, blend] = data
+		else {
+			return Err(malformed("fcTL isn't 26 bytes"));
+		};
+		// the sequence number is skipped, like `fdAT`'s
+
+		let control = Self {
+			width: u32::from_be_bytes([w0, w1, w2, w3]),
+			height: u32::from_be_bytes([h0, h1, h2, h3]),
+			x: u32::from_be_bytes([x0, x1, x2, x3]),
+			y: u32::from_be_bytes([y0, y1, y2, y3]),
+			delay_num: u16::from_be_bytes([n0, n1]),
+			delay_den: u16::from_be_bytes([d0, d1]),
+			dispose: match dispose {
+				0 => ApngDispose::None,
+				1 => ApngDispose::Background,
+				2 => ApngDispose::Previous,
+				_ => return Err(malformed("unknown dispose op")),
+			},
+			blend: match blend {
+				0 => ApngBlend::Source,
+				1 => ApngBlend::Over,
+				_ => return Err(malformed("unknown blend op")),
+			},
+		};
+		if control.width == 0 || control.height == 0 {
+			return Err(malformed("frame is empty"));
+		}
+		if control.x as u64 + control.width as u64 > canvas.width as u64
+			|| control.y as u64 + control.height as u64 > canvas.height as u64
+		{
+			return Err(malformed("frame goes past the edge of the image"));
+		}
+		Ok(control)
+	}
+}
+
+#[derive(Debug)]
+enum State<'a, R> {
+	/// at the first `IDAT`, with the `fcTL` before it if the default image
+	/// is the first frame.
+	Start(Stream<'a, R>, Chunk, Option<ApngFrameControl>),
+	Frame(Box<PngPixels<'a, R>>),
+	/// past `IEND`, and how many bytes the image took up.
+	Done(u64),
+	Failed,
+}
+
+/// the frames of an apng, read from the reader one at a time.
+#[derive(Debug)]
+pub struct ApngFrames<'a, R> {
+	header: PngHeader,
+	animation: Option<ApngAnimation>,
+	metadata: PngMetadata,
+	state: State<'a, R>,
+}
+
+/// one frame of an animation, and its pixels, `control.width` by
+/// `control.height` of them.
+#[derive(Debug)]
+pub struct ApngFrame<'b, 'a, R> {
+	control: ApngFrameControl,
+	pixels: &'b mut PngPixels<'a, R>,
+}
+
+impl<R> ApngFrame<'_, '_, R> {
+	pub fn control(&self) -> &ApngFrameControl {
+		&self.control
+	}
+}
+
+impl<R: std::io::Read> Iterator for ApngFrame<'_, '_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.pixels.next()
+	}
+}
+
+impl<'a, R: std::io::Read> ApngFrames<'a, R> {
+	/// reads everything up to the image data.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let opened = open(data)?;
+		let header = opened.header;
+
+		let (animation, control) = match opened.actl {
+			Some(actl) => {
+				let &[f0, f1, f2, f3, p0, p1, p2, p3] = &actl[..]
+				else {
+					return Err(Error::Malformed { offset: opened.chunk.offset, reason: "acTL isn't 8 bytes" });
+				};
+				let animation = ApngAnimation {
+					frames: u32::from_be_bytes([f0, f1, f2, f3]),
+					plays: u32::from_be_bytes([p0, p1, p2, p3]),
+				};
+				let control = match opened.fctl {
+					Some(x) => Some(ApngFrameControl::parse(&x, opened.chunk.offset, &header)?),
+					None => None,
+				};
+				(Some(animation), control)
+			}
+			None => (None, Some(ApngFrameControl::still(&header))),
+		};
+
+		Ok(Self {
+			header,
+			animation,
+			metadata: opened.metadata,
+			state: State::Start(opened.stream, opened.chunk, control),
+		})
+	}
+
+	/// the whole canvas.
+	pub fn header(&self) -> &PngHeader {
+		&self.header
+	}
+
+	/// `None` for a still image.
+	pub fn animation(&self) -> Option<&ApngAnimation> {
+		self.animation.as_ref()
+	}
+
+	/// the ancillary chunks read so far.
+	pub fn metadata(&self) -> &PngMetadata {
+		&self.metadata
+	}
+
+	/// skips whatever's left of the last frame, and reads the next one up to
+	/// its image data. `None` once `IEND` is reached.
+	pub fn next_frame(&mut self) -> Result<Option<ApngFrame<'_, 'a, R>>, Error> {
+		let state = core::mem::replace(&mut self.state, State::Failed);
+		let Some((control, idat)) = self.advance(state)?
+		else {
+			return Ok(None);
+		};
+
+		let header = PngHeader {
+			width: control.width,
+			height: control.height,
+			..self.header.clone()
+		};
+		self.state = State::Frame(Box::new(PngPixels::from_idat(header, idat, PngMetadata::default())?));
+		match &mut self.state {
+			State::Frame(pixels) => Ok(Some(ApngFrame { control, pixels })),
+			_ => Ok(None),
+		}
+	}
+
+	/// finds the next frame's data, leaving `state` failed if there's an
+	/// error.
+	fn advance(&mut self, state: State<'a, R>) -> Result<Option<(ApngFrameControl, Idat<'a, R>)>, Error> {
+		let (mut stream, mut chunk) = match state {
+			State::Start(stream, chunk, control) => {
+				let idat = Idat::new(stream, chunk)?;
+				match control {
+					Some(control) => return Ok(Some((control, idat))),
+					// the default image isn't part of the animation
+					None => idat.skip()?,
+				}
+			}
+			State::Frame(mut pixels) => {
+				pixels.drain()?;
+				pixels.inflate.into_input().skip()?
+			}
+			State::Done(len) => {
+				self.state = State::Done(len);
+				return Ok(None);
+			}
+			State::Failed => return Err(Error::InvalidInput { reason: "an earlier read failed" }),
+		};
+
+		let mut control = None;
+		loop {
+			let offset = chunk.offset;
+			let malformed = |reason| Error::Malformed { offset, reason };
+			match &chunk.kind {
+				// still images can't have frames
+				b"fcTL" if self.animation.is_some() => {
+					let data = ancillary::contents(&mut stream, chunk)?;
+					control = Some(ApngFrameControl::parse(&data, offset, &self.header)?);
+				}
+				b"fdAT" if self.animation.is_some() => match control {
+					Some(control) => return Ok(Some((control, Idat::new(stream, chunk)?))),
+					None => return Err(malformed("fdAT without an fcTL")),
+				},
+				b"IEND" => {
+					stream.skip(chunk)?;
+					self.state = State::Done(stream.offset);
+					return Ok(None);
+				}
+				b"IDAT" => return Err(malformed("IDAT chunks aren't consecutive")),
+				_ if chunk.is_critical() => return Err(malformed("unknown critical chunk")),
+				_ => ancillary::read(&mut stream, chunk, &mut self.metadata)?,
+			}
+			chunk = stream.chunk()?;
+		}
+	}
+
+	/// skips the frames that are left, and returns the number of bytes the
+	/// image took up.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		while self.next_frame()?.is_some() {}
+		match self.state {
+			State::Done(len) => Ok(len),
+			_ => Err(Error::InvalidInput { reason: "an earlier read failed" }),
+		}
+	}
+}
+
+impl<'a, R: std::io::Read> crate::animation::Frames for ApngFrames<'a, R> {
+	fn next_frame(&mut self) -> Result<Option<(crate::animation::Frame, impl Iterator<Item = Result<crate::Rgba, Error>> + '_)>, Error> {
+		use crate::animation::{Blend, Dispose, Frame};
+
+		let Some(frame) = ApngFrames::next_frame(self)? else {
+			return Ok(None);
+		};
+		let control = *frame.control();
+		let info = Frame {
+			width: control.width,
+			height: control.height,
+			x: control.x,
+			y: control.y,
+			delay: control.delay(),
+			dispose: match control.dispose {
+				ApngDispose::None => Dispose::None,
+				ApngDispose::Background => Dispose::Background,
+				ApngDispose::Previous => Dispose::Previous,
+			},
+			blend: match control.blend {
+				ApngBlend::Source => Blend::Source,
+				ApngBlend::Over => Blend::Over,
+			},
+		};
+		Ok(Some((info, frame)))
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::Error;
+	use crate::png::{self, ApngBlend, ApngDispose};
+
+	const IMAGE_ANIMATED: &[u8] = include_bytes!("../../test/animated.png");
+
+	/// the pixels the fixtures' frames were made with.
+	fn frame(n: u32, x: u32, y: u32) -> crate::Rgba {
+		((x * 20 + n) as u8, (y * 30) as u8, (n * 60) as u8, (255 - n * 40) as u8)
+	}
+
+	#[test]
+	fn frames() {
+		// the default image is the first frame, the second is split over two
+		// fdAT chunks, and the last has a delay denominator of 0
+		let mut data = IMAGE_ANIMATED;
+		let mut frames = png::ApngFrames::new(&mut data).expect("error?");
+		assert_eq!((frames.header().width, frames.header().height), (8, 6));
+		assert_eq!(frames.animation().map(|x| (x.frames, x.plays)), Some((3, 2)));
+
+		let expected = [
+			(8, 6, 0, 0, ApngDispose::None, ApngBlend::Source),
+			(4, 3, 2, 1, ApngDispose::Background, ApngBlend::Over),
+			(5, 2, 3, 4, ApngDispose::Previous, ApngBlend::Source),
+		];
+		for (n, (width, height, x, y, dispose, blend)) in expected.into_iter().enumerate() {
+			let frame_ = frames.next_frame().expect("error?").expect("frame?");
+			let control = *frame_.control();
+			assert_eq!((control.width, control.height, control.x, control.y), (width, height, x, y));
+			assert_eq!((control.dispose, control.blend), (dispose, blend));
+			assert_eq!(control.delay(), std::time::Duration::from_millis(100 * (n as u64 + 1)));
+
+			let pixels = frame_.map(|x| x.expect("error?")).collect::<Vec<_>>();
+			assert_eq!(pixels.len() as u32, width * height);
+			for (i, px) in pixels.into_iter().enumerate() {
+				assert_eq!(px, frame(n as u32, i as u32 % width, i as u32 / width), "{n}: {i}");
+			}
+		}
+		assert!(frames.next_frame().expect("error?").is_none());
+		assert!(frames.next_frame().expect("error?").is_none());
+		assert_eq!(frames.metadata().text.get_text(crate::metadata::COMMENT), Some("between frames"));
+		assert_eq!(frames.finish().expect("error?"), IMAGE_ANIMATED.len() as u64);
+
+		// frames can be skipped, partway through or entirely
+		let mut data = IMAGE_ANIMATED;
+		let mut frames = png::ApngFrames::new(&mut data).expect("error?");
+		frames.next_frame().expect("error?").expect("frame?").nth(3).expect("pixel?").expect("error?");
+		let mut frame_ = frames.next_frame().expect("error?").expect("frame?");
+		assert_eq!(frame_.next().expect("pixel?").expect("error?"), frame(1, 0, 0));
+		assert_eq!(frames.finish().expect("error?"), IMAGE_ANIMATED.len() as u64);
+
+		// still images decode as they always have
+		let mut data = IMAGE_ANIMATED;
+		let pixels = png::PngPixels::new(&mut data).expect("error?");
+		assert!(pixels.map(|x| x.expect("error?")).eq((0..48).map(|i| frame(0, i % 8, i / 8))));
+	}
+
+	#[test]
+	fn animated_format() {
+		use crate::animation::{Blend, Dispose, Frames};
+		use crate::AnimatedFormat;
+
+		let mut data = IMAGE_ANIMATED;
+		let (header, mut frames) = png::Png.decode_frames(&mut data).expect("error?");
+		assert_eq!((header.width, header.height), (8, 6));
+		let expected = [
+			(8, 6, 0, 0, Dispose::None, Blend::Source),
+			(4, 3, 2, 1, Dispose::Background, Blend::Over),
+			(5, 2, 3, 4, Dispose::Previous, Blend::Source),
+		];
+		for (n, (width, height, x, y, dispose, blend)) in expected.into_iter().enumerate() {
+			let (frame_, pixels) = frames.next_frame().expect("error?").expect("frame?");
+			assert_eq!((frame_.width, frame_.height, frame_.x, frame_.y), (width, height, x, y));
+			assert_eq!((frame_.dispose, frame_.blend), (dispose, blend));
+			assert_eq!(frame_.delay, std::time::Duration::from_millis(100 * (n as u64 + 1)));
+			let pixels = pixels.map(|x| x.expect("error?")).collect::<Vec<_>>();
+			assert!(pixels.into_iter().eq((0..width * height).map(|i| frame(n as u32, i % width, i / width))));
+		}
+		assert!(frames.next_frame().expect("error?").is_none());
+
+		// a still png is one frame of all of it
+		let mut data = &include_bytes!("../../test/gradient.png")[..];
+		let (_, mut frames) = png::Png.decode_frames(&mut data).expect("error?");
+		let (frame_, pixels) = frames.next_frame().expect("error?").expect("frame?");
+		assert_eq!((frame_.width, frame_.height, frame_.delay), (33, 7, std::time::Duration::ZERO));
+		assert_eq!(pixels.count(), 33 * 7);
+		assert!(frames.next_frame().expect("error?").is_none());
+	}
+
+	#[test]
+	fn hidden_default() {
+		// a default image that isn't part of the animation is skipped
+		let mut data = &include_bytes!("../../test/animated_hidden.png")[..];
+		let mut frames = png::ApngFrames::new(&mut data).expect("error?");
+		let frame_ = frames.next_frame().expect("error?").expect("frame?");
+		assert_eq!(frame_.control().x, 1);
+		assert!(frame_.map(|x| x.expect("error?")).eq((0..6).map(|i| frame(1, i % 3, i / 3))));
+		assert!(frames.next_frame().expect("error?").is_none());
+
+		// and a still image is a single frame
+		let mut data = &include_bytes!("../../test/gradient.png")[..];
+		let mut frames = png::ApngFrames::new(&mut data).expect("error?");
+		assert!(frames.animation().is_none());
+		let frame_ = frames.next_frame().expect("error?").expect("frame?");
+		assert_eq!((frame_.control().width, frame_.control().height), (33, 7));
+		assert_eq!(frame_.count(), 33 * 7);
+		assert!(frames.next_frame().expect("error?").is_none());
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<u64, Error> {
+			let mut frames = png::ApngFrames::new(&mut data)?;
+			while let Some(frame) = frames.next_frame()? {
+				for px in frame {
+					px?;
+				}
+			}
+			frames.finish()
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../../test/corrupt/", $name, ".png")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("frame_outside", Error::Malformed { reason: "frame goes past the edge of the image", .. });
+		case!("fdat_without_fctl", Error::Malformed { reason: "fdAT without an fcTL", .. });
+		case!("bad_fctl", Error::Malformed { reason: "fcTL isn't 26 bytes", .. });
+
+		// errors stick
+		let mut data = &include_bytes!("../../test/corrupt/fdat_without_fctl.png")[..];
+		let mut frames = png::ApngFrames::new(&mut data).expect("error?");
+		assert!(frames.next_frame().expect("error?").is_some());
+		assert!(frames.next_frame().is_err());
+		assert!(matches!(frames.next_frame(), Err(Error::InvalidInput { .. })));
+		assert!(frames.finish().is_err());
+	}
+}
@@ -0,0 +1,1154 @@
+/*!
+netpbm images.
+
+pbm (`P4`), pgm (`P5`) and ppm (`P6`) images are decoded and encoded, and
+so are their plain versions (`P1` to `P3`). the header is text: the magic,
+then the width, height and max value, separated by whitespace and `#`
+comments, and a single whitespace byte before the samples. bitmaps have no
+max value. samples are a byte each up to a max value of 255, and two
+big-endian bytes past that. they're scaled to 8 bits, rounding, and back
+again when encoding.
+
+pam (`P7`) images have a header of lines instead, each a keyword and its
+value, ending in `ENDHDR`. the `GRAYSCALE`, `RGB` and `BLACKANDWHITE` tuple
+types are decoded, with or without `_ALPHA`, and so are images with no
+tuple type and a depth of 1 to 4, as those. black and white is a gray image
+with a max value of 1, so that's what it comes out as. encoding always
+writes a `TUPLTYPE` line.
+
+plain images have their samples in decimal, separated by whitespace and
+comments like the header, except that bitmaps' single digits don't need
+anything between them. they're read a sample at a time.
+
+gray and bitmap pixels come out as opaque gray, and bitmap ones as black or
+white. pixels are made gray for encoding by their rec. 601 luma, and then
+black or white for bitmaps by whether that's under half. alpha is dropped,
+but for pam's tuple types with it.
+
+nothing is read past the last sample, or the byte after it for plain
+images, so images a file holds one after another can be decoded in turn.
+
+pfm images, with floating point samples, are decoded and encoded by `Pfm`.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+mod pfm;
+pub use crate::tone::ToneCurve;
+pub use pfm::{Pfm, PfmColorType, PfmHeader, PfmPixels, encode_f32};
+
+/// the most digits a header number could need.
+const DIGITS_MAX: usize = 10;
+/// the longest line of a pam header that's read.
+const LINE_MAX: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PnmKind {
+	/// pbm: a bit a pixel, set for black.
+	Bitmap,
+	/// pgm: a gray sample a pixel.
+	Graymap,
+	/// ppm: red, green and blue samples.
+	Pixmap,
+	/// pam: the samples its tuple type says. always binary.
+	Pam(PamTupleType),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PamTupleType {
+	/// `GRAYSCALE`, or `BLACKANDWHITE`.
+	Grayscale,
+	/// `GRAYSCALE_ALPHA`, or `BLACKANDWHITE_ALPHA`.
+	GrayscaleAlpha,
+	/// `RGB`.
+	RGB,
+	/// `RGB_ALPHA`.
+	RGBAlpha,
+}
+
+impl PamTupleType {
+	/// the tuple type of an image with no `TUPLTYPE` line.
+	fn from_depth(depth: u32) -> Option<Self> {
+		match depth {
+			1 => Some(Self::Grayscale),
+			2 => Some(Self::GrayscaleAlpha),
+			3 => Some(Self::RGB),
+			4 => Some(Self::RGBAlpha),
+			_ => None,
+		}
+	}
+
+	fn from_name(name: &[u8]) -> Option<Self> {
+		match name {
+			b"GRAYSCALE" | b"BLACKANDWHITE" => Some(Self::Grayscale),
+			b"GRAYSCALE_ALPHA" | b"BLACKANDWHITE_ALPHA" => Some(Self::GrayscaleAlpha),
+			b"RGB" => Some(Self::RGB),
+			b"RGB_ALPHA" => Some(Self::RGBAlpha),
+			_ => None,
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Self::Grayscale => "GRAYSCALE",
+			Self::GrayscaleAlpha => "GRAYSCALE_ALPHA",
+			Self::RGB => "RGB",
+			Self::RGBAlpha => "RGB_ALPHA",
+		}
+	}
+
+	fn depth(self) -> usize {
+		match self {
+			Self::Grayscale => 1,
+			Self::GrayscaleAlpha => 2,
+			Self::RGB => 3,
+			Self::RGBAlpha => 4,
+		}
+	}
+}
+
+impl PnmKind {
+	fn magic(self, plain: bool) -> &'static [u8; 2] {
+		match (self, plain) {
+			(Self::Bitmap, false) => b"P4",
+			(Self::Graymap, false) => b"P5",
+			(Self::Pixmap, false) => b"P6",
+			(Self::Bitmap, true) => b"P1",
+			(Self::Graymap, true) => b"P2",
+			(Self::Pixmap, true) => b"P3",
+			(Self::Pam(_), _) => b"P7",
+		}
+	}
+
+	/// samples in a pixel.
+	fn channels(self) -> usize {
+		match self {
+			Self::Bitmap | Self::Graymap => 1,
+			Self::Pixmap => 3,
+			Self::Pam(tuple_type) => tuple_type.depth(),
+		}
+	}
+
+	/// bytes in a row of `width` pixels.
+	fn stride(self, width: u32, max_value: u16) -> u64 {
+		let bytes = if max_value > 255 { 2 } else { 1 };
+		match self {
+			Self::Bitmap => (width as u64).div_ceil(8),
+			_ => width as u64 * self.channels() as u64 * bytes,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PnmHeader {
+	pub width: u32,
+	pub height: u32,
+	pub kind: PnmKind,
+	/// whether samples are decimal text, rather than binary. pam images
+	/// are always binary, so it's ignored for them.
+	pub plain: bool,
+	/// the value of a full sample, from 1 to 65535. bitmaps don't have one,
+	/// so it's 1 for them when decoding and ignored when encoding.
+	pub max_value: u16,
+}
+
+impl crate::Dimensions for PnmHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Pnm;
+
+impl Default for Pnm {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// whitespace, as netpbm sees it.
+fn is_space(b: u8) -> bool {
+	matches!(b, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r')
+}
+
+/// reads a byte, moving `offset` past it.
+fn byte(data: &mut impl std::io::Read, offset: &mut u64) -> Result<u8, Error> {
+	let mut buf = [0];
+	crate::read_exact(data, &mut buf, *offset)?;
+	*offset = offset.saturating_add(1);
+	Ok(buf[0])
+}
+
+/// reads a number, after whatever whitespace and comments come before it.
+/// `b` is the byte after the last thing read. the number is returned with
+/// where it starts and the byte after it.
+fn number(data: &mut impl std::io::Read, offset: &mut u64, mut b: u8, field: &'static str) -> Result<(u32, u64, u8), Error> {
+	loop {
+		if b == b'#' {
+			while !matches!(b, b'\n' | b'\r') {
+				b = byte(data, offset)?;
+			}
+		}
+		else if !is_space(b) {
+			break;
+		}
+		b = byte(data, offset)?;
+	}
+
+	let start = offset.saturating_sub(1);
+	let error = Error::InvalidHeader { offset: start, field };
+	let mut n: u64 = 0;
+	let mut digits = 0;
+	while b.is_ascii_digit() {
+		if digits == DIGITS_MAX {
+			return Err(error);
+		}
+		n = n * 10 + (b - b'0') as u64;
+		digits += 1;
+		b = byte(data, offset)?;
+	}
+	match (digits, u32::try_from(n)) {
+		(1.., Ok(n)) => Ok((n, start, b)),
+		_ => Err(error),
+	}
+}
+
+/// reads the rest of a pam header, after the magic and the byte after it,
+/// up to and including its `ENDHDR` line. returns the width, height, max
+/// value and tuple type.
+fn pam_header(data: &mut impl std::io::Read, offset: &mut u64) -> Result<(u32, u32, u16, PamTupleType), Error> {
+	let field = |offset, field| Error::InvalidHeader { offset, field };
+	let [mut width, mut height, mut depth, mut max_value] = [None; 4];
+	let mut tuple_type: Option<(u64, Vec<u8>)> = None;
+	let mut line = vec![];
+	let end = loop {
+		let start = *offset;
+		line.clear();
+		loop {
+			let b = byte(data, offset)?;
+			if b == b'\n' {
+				break;
+			}
+			if line.len() == LINE_MAX {
+				return Err(field(start, "header line"));
+			}
+			line.push(b);
+		}
+
+		let Some(first) = line.iter().position(|&b| !is_space(b))
+		else {
+			continue;
+		};
+		if line[first] == b'#' {
+			continue;
+		}
+		let text = &line[first..];
+		let len = text.iter().position(|&b| is_space(b)).unwrap_or(text.len());
+		let (keyword, value) = text.split_at(len);
+		let skip = value.iter().position(|&b| !is_space(b)).unwrap_or(value.len());
+		let value = value[skip..].trim_ascii_end();
+		let at = start + (first + len + skip) as u64;
+
+		let number = |name| {
+			let n = match value.len() {
+				1..=DIGITS_MAX if value.iter().all(u8::is_ascii_digit) => value.iter().fold(0u64, |n, &b| n * 10 + (b - b'0') as u64),
+				_ => 0,
+			};
+			match u32::try_from(n) {
+				Ok(n @ 1..) => Ok(Some((at, n))),
+				_ => Err(field(at, name)),
+			}
+		};
+		match keyword {
+			b"ENDHDR" => break start,
+			b"WIDTH" => width = number("width")?,
+			b"HEIGHT" => height = number("height")?,
+			b"DEPTH" => depth = number("depth")?,
+			b"MAXVAL" => max_value = number("max value")?,
+			// more than one line of these are joined up
+			b"TUPLTYPE" => match &mut tuple_type {
+				Some((_, name)) => {
+					name.push(b' ');
+					name.extend_from_slice(value);
+				}
+				None => tuple_type = Some((at, value.to_vec())),
+			},
+			_ => return Err(field(start + first as u64, "keyword")),
+		}
+	};
+
+	let (Some((_, width)), Some((_, height))) = (width, height)
+	else {
+		return Err(field(end, if width.is_none() { "width" } else { "height" }));
+	};
+	let Some((depth_at, depth)) = depth
+	else {
+		return Err(field(end, "depth"));
+	};
+	let max_value = match max_value {
+		Some((_, n @ ..=0xffff)) => n as u16,
+		Some((at, _)) => return Err(field(at, "max value")),
+		None => return Err(field(end, "max value")),
+	};
+	let tuple_type = match tuple_type {
+		Some((at, name)) => PamTupleType::from_name(&name).ok_or(field(at, "tuple type"))?,
+		None => PamTupleType::from_depth(depth).ok_or(field(depth_at, "depth"))?,
+	};
+	if tuple_type.depth() as u32 != depth {
+		return Err(field(depth_at, "depth"));
+	}
+	Ok((width, height, max_value, tuple_type))
+}
+
+/// a sample scaled from `0..=max` to 8 bits.
+fn scale(x: u32, max: u32) -> u8 {
+	((x * 255 + max / 2) / max) as u8
+}
+
+/// a sample scaled from `0..=max` to 16 bits.
+fn scale16(x: u32, max: u32) -> u16 {
+	((x as u64 * 65535 + max as u64 / 2) / max as u64) as u16
+}
+
+/// a pixel's samples as rgba, with `one` as the most a sample can be.
+fn arrange<T: Copy + Default>(kind: PnmKind, samples: [u32; 4], one: T, scale: impl Fn(u32) -> T) -> (T, T, T, T) {
+	let [a, b, c, d] = samples.map(scale);
+	let zero = T::default();
+	match kind {
+		PnmKind::Bitmap if samples[0] == 1 => (zero, zero, zero, one),
+		PnmKind::Bitmap => (one, one, one, one),
+		PnmKind::Graymap | PnmKind::Pam(PamTupleType::Grayscale) => (a, a, a, one),
+		PnmKind::Pam(PamTupleType::GrayscaleAlpha) => (a, a, a, b),
+		PnmKind::Pixmap | PnmKind::Pam(PamTupleType::RGB) => (a, b, c, one),
+		PnmKind::Pam(PamTupleType::RGBAlpha) => (a, b, c, d),
+	}
+}
+
+/// pixels of a pnm image, read a row at a time as they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct PnmPixels<'a, R> {
+	header: PnmHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// bytes in a row.
+	stride: usize,
+	row: Vec<u8>,
+	/// next pixel of `row` to hand out.
+	x: u32,
+	/// rows that haven't been read.
+	rows: u32,
+	/// the byte after the last sample of a plain image, if there was one.
+	pending: Option<u8>,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> PnmPixels<'a, R> {
+	/// reads the header, up to the first sample.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut magic = [0; 2];
+		crate::read_exact(data, &mut magic, 0)?;
+		let (kind, plain) = match &magic {
+			b"P1" => (PnmKind::Bitmap, true),
+			b"P2" => (PnmKind::Graymap, true),
+			b"P3" => (PnmKind::Pixmap, true),
+			b"P4" => (PnmKind::Bitmap, false),
+			b"P5" => (PnmKind::Graymap, false),
+			b"P6" => (PnmKind::Pixmap, false),
+			// the tuple type comes later
+			b"P7" => (PnmKind::Pam(PamTupleType::RGB), false),
+			_ => return Err(Error::InvalidMagic { offset: 0 }),
+		};
+		let mut offset = 2;
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+
+		// the magic, and each number but the last, end in whitespace or a
+		// comment. the last is followed by exactly one whitespace byte in
+		// binary images
+		let b = byte(data, &mut offset)?;
+		let comment = b == b'#' && !matches!(kind, PnmKind::Pam(_));
+		if !is_space(b) && !comment {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+
+		if matches!(kind, PnmKind::Pam(_)) {
+			let (width, height, max_value, tuple_type) = pam_header(data, &mut offset)?;
+			let kind = PnmKind::Pam(tuple_type);
+			return Self::from_header(PnmHeader { width, height, kind, plain, max_value }, data, offset, b);
+		}
+
+		let names = match kind {
+			PnmKind::Bitmap => &["width", "height"][..],
+			_ => &["width", "height", "max value"],
+		};
+		let mut numbers = [0, 0, 1];
+		let mut b = b;
+		for (i, &name) in names.iter().enumerate() {
+			let (n, start, next) = number(data, &mut offset, b, name)?;
+			let ends = match i == names.len() - 1 && !plain {
+				true => is_space(next),
+				false => is_space(next) || next == b'#',
+			};
+			if n == 0 || !ends || n > u16::MAX as u32 && i == 2 {
+				return Err(field(start, name));
+			}
+			numbers[i] = n;
+			b = next;
+		}
+		let [width, height, max_value] = numbers;
+		// checked above
+		let max_value = max_value as u16;
+
+		Self::from_header(PnmHeader { width, height, kind, plain, max_value }, data, offset, b)
+	}
+
+	/// `b` is the byte after the header.
+	fn from_header(header: PnmHeader, data: &'a mut R, offset: u64, b: u8) -> Result<Self, Error> {
+		let stride = header.kind.stride(header.width, header.max_value);
+
+		Ok(Self {
+			x: header.width,
+			rows: header.height,
+			pending: header.plain.then_some(b),
+			header,
+			data,
+			offset,
+			stride: stride.try_into().map_err(|_| oom())?,
+			row: vec![],
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &PnmHeader {
+		&self.header
+	}
+
+	/// reads a sample of a plain image, and whatever comes before it.
+	fn plain_sample(&mut self) -> Result<u32, Error> {
+		let mut b = match self.pending.take() {
+			Some(b) => b,
+			None => byte(self.data, &mut self.offset)?,
+		};
+		loop {
+			if b == b'#' {
+				while !matches!(b, b'\n' | b'\r') {
+					b = byte(self.data, &mut self.offset)?;
+				}
+			}
+			else if !is_space(b) {
+				break;
+			}
+			b = byte(self.data, &mut self.offset)?;
+		}
+
+		let offset = self.offset.saturating_sub(1);
+		let error = |reason| Error::Malformed { offset, reason };
+		if self.header.kind == PnmKind::Bitmap {
+			return match b {
+				b'0' => Ok(0),
+				b'1' => Ok(1),
+				_ => Err(error("bitmap sample isn't 0 or 1")),
+			};
+		}
+		if !b.is_ascii_digit() {
+			return Err(error("sample isn't a number"));
+		}
+
+		let mut n: u32 = 0;
+		loop {
+			n = n.saturating_mul(10).saturating_add((b - b'0') as u32);
+			// the last sample of the file can end it
+			let mut buf = [0];
+			match crate::read_exact(self.data, &mut buf, self.offset) {
+				Ok(()) => b = buf[0],
+				Err(Error::UnexpectedEof { .. }) => break,
+				Err(e) => return Err(e),
+			}
+			self.offset = self.offset.saturating_add(1);
+			if !b.is_ascii_digit() {
+				if !is_space(b) && b != b'#' {
+					return Err(error("sample isn't a number"));
+				}
+				self.pending = Some(b);
+				break;
+			}
+		}
+		if n > self.header.max_value as u32 {
+			return Err(error("sample is over the max value"));
+		}
+		Ok(n)
+	}
+
+	/// reads a row, a piece at a time, so memory is only taken up once the
+	/// data shows up.
+	fn next_row(&mut self) -> Result<(), Error> {
+		self.row.clear();
+		while self.row.len() < self.stride {
+			let start = self.row.len();
+			let n = (self.stride - start).min(1 << 16);
+			self.row.try_reserve(n).map_err(|_| oom())?;
+			self.row.resize(start + n, 0);
+			crate::read_exact(self.data, &mut self.row[start..], self.offset)?;
+			self.offset = self.offset.saturating_add(n as u64);
+		}
+		self.rows -= 1;
+		self.x = 0;
+		Ok(())
+	}
+
+	/// skips any pixels that are left, and returns the number of bytes the
+	/// image took up.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		// plain samples are only found by reading them
+		if self.header.plain {
+			let pixels = (self.rows as u64 * self.header.width as u64).saturating_add((self.header.width - self.x) as u64);
+			let samples = pixels.saturating_mul(self.header.kind.channels() as u64);
+			for _ in 0..samples {
+				self.plain_sample()?;
+			}
+			return Ok(self.offset);
+		}
+
+		let mut buf = [0; 512];
+		let mut left = (self.rows as u64).saturating_mul(self.stride as u64);
+		while left > 0 {
+			let n = left.min(buf.len() as u64) as usize;
+			crate::read_exact(self.data, &mut buf[..n], self.offset)?;
+			self.offset = self.offset.saturating_add(n as u64);
+			left -= n as u64;
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<'a, R: std::io::Read> PnmPixels<'a, R> {
+	/// hands out pixels with all the bits of images whose max value is
+	/// over 255. samples are scaled to 16 bits, so the max value becomes
+	/// 65535.
+	pub fn wide(self) -> WidePixels<'a, R> {
+		WidePixels(self)
+	}
+
+	/// the next pixel's samples, as they're stored.
+	fn next_samples(&mut self) -> Option<Result<[u32; 4], Error>> {
+		if self.failed {
+			return None;
+		}
+
+		if self.x == self.header.width {
+			if self.rows == 0 {
+				return None;
+			}
+			if self.header.plain {
+				self.rows -= 1;
+				self.x = 0;
+			}
+			else if let Err(e) = self.next_row() {
+				self.failed = true;
+				return Some(Err(e));
+			}
+		}
+
+		let x = self.x as usize;
+		self.x += 1;
+		let channels = self.header.kind.channels();
+		let max = self.header.max_value as u32;
+		let mut samples = [0; 4];
+		if self.header.plain {
+			for sample in samples.iter_mut().take(channels) {
+				match self.plain_sample() {
+					Ok(x) => *sample = x,
+					Err(e) => {
+						self.failed = true;
+						return Some(Err(e));
+					}
+				}
+			}
+		}
+		else if self.header.kind == PnmKind::Bitmap {
+			samples[0] = (self.row[x / 8] >> (7 - x % 8) & 1) as u32;
+		}
+		else {
+			let wide = max > 255;
+			let bytes = if wide { 2 } else { 1 } * channels;
+			let i = x * bytes;
+			let row = &self.row[i..i + bytes];
+			for (c, sample) in samples.iter_mut().take(channels).enumerate() {
+				*sample = match wide {
+					true => u16::from_be_bytes([row[c * 2], row[c * 2 + 1]]) as u32,
+					false => row[c] as u32,
+				};
+			}
+			if samples.iter().any(|&x| x > max) {
+				self.failed = true;
+				let row_start = self.offset.saturating_sub(self.stride as u64);
+				let offset = row_start.saturating_add(i as u64);
+				return Some(Err(Error::Malformed { offset, reason: "sample is over the max value" }));
+			}
+		}
+
+		Some(Ok(samples))
+	}
+}
+
+impl<R: std::io::Read> Iterator for PnmPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let samples = self.next_samples()?;
+		let max = self.header.max_value as u32;
+		Some(samples.map(|samples| arrange(self.header.kind, samples, 255, |x| scale(x, max))))
+	}
+}
+
+/// [`PnmPixels`], with 16 bits per channel.
+#[derive(Debug)]
+pub struct WidePixels<'a, R>(PnmPixels<'a, R>);
+
+impl<R: std::io::Read> WidePixels<'_, R> {
+	pub fn header(&self) -> &PnmHeader {
+		self.0.header()
+	}
+
+	/// see [`PnmPixels::finish`].
+	pub fn finish(self) -> Result<u64, Error> {
+		self.0.finish()
+	}
+}
+
+impl<R: std::io::Read> Iterator for WidePixels<'_, R> {
+	type Item = Result<crate::Rgba16, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let samples = self.0.next_samples()?;
+		let max = self.0.header.max_value as u32;
+		Some(samples.map(|samples| arrange(self.0.header.kind, samples, 65535, |x| scale16(x, max))))
+	}
+}
+
+/// `PnmPixels`, ending at the first error, for `Format::decode`.
+struct Pixels<'a, R>(PnmPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// push-based encoder state, which `Pnm::encode` drives.
+#[derive(Debug)]
+pub(crate) struct PnmEncoder<W> {
+	out: W,
+	kind: PnmKind,
+	plain: bool,
+	max_value: u16,
+	width: u32,
+	/// pixels of `row` so far.
+	x: u32,
+	row: Vec<u8>,
+	/// bytes of the line of a plain image so far.
+	line: usize,
+}
+
+impl<W: std::io::Write> PnmEncoder<W> {
+	/// writes the header.
+	pub(crate) fn new(header: &PnmHeader, mut out: W) -> Result<Self, Error> {
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let plain = header.plain && !matches!(header.kind, PnmKind::Pam(_));
+		let magic = String::from_utf8_lossy(header.kind.magic(plain));
+		let (width, height, max_value) = (header.width, header.height, header.max_value);
+		// with which whitespace-separated words the numbers are
+		let (text, words) = match header.kind {
+			PnmKind::Bitmap => (format!("{magic}\n{width} {height}\n"), [1, 2, 0]),
+			PnmKind::Pam(tuple_type) => {
+				let (depth, name) = (tuple_type.depth(), tuple_type.name());
+				let text = format!("P7\nWIDTH {width}\nHEIGHT {height}\nDEPTH {depth}\nMAXVAL {max_value}\nTUPLTYPE {name}\nENDHDR\n");
+				(text, [2, 4, 8])
+			}
+			_ => (format!("{magic}\n{width} {height}\n{max_value}\n"), [1, 2, 3]),
+		};
+		let at = |word| text.split_ascii_whitespace().nth(word).map_or(0, |x| x.as_ptr() as u64 - text.as_ptr() as u64);
+		if width == 0 {
+			return Err(field(at(words[0]), "width"));
+		}
+		if height == 0 {
+			return Err(field(at(words[1]), "height"));
+		}
+		if max_value == 0 && header.kind != PnmKind::Bitmap {
+			return Err(field(at(words[2]), "max value"));
+		}
+		crate::write_all(&mut out, text.as_bytes())?;
+
+		Ok(Self {
+			out,
+			kind: header.kind,
+			plain,
+			max_value: header.max_value,
+			width: header.width,
+			x: 0,
+			row: vec![],
+			line: 0,
+		})
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		// gray from the 8-bit samples, so it's the same as it always was
+		self.push_with_gray(crate::pixel::widen(px), crate::pixel::luma(px) as u16 * 257)
+	}
+
+	pub(crate) fn push_wide(&mut self, px: crate::Rgba16) -> Result<(), Error> {
+		self.push_with_gray(px, crate::pixel::luma(px) as u16)
+	}
+
+	fn push_with_gray(&mut self, px: crate::Rgba16, gray: u16) -> Result<(), Error> {
+		// a plain pixmap's is at most 3 numbers and the spaces before them,
+		// and a pam's 4 samples of 2 bytes
+		self.row.try_reserve(18).map_err(|_| oom())?;
+		let max = self.max_value as u64;
+		let samples = match self.kind {
+			PnmKind::Bitmap if self.plain => {
+				self.token(if gray < 0x8000 { "1" } else { "0" });
+				&[][..]
+			}
+			PnmKind::Bitmap => {
+				if self.x.is_multiple_of(8) {
+					self.row.push(0);
+				}
+				if let Some(last) = self.row.last_mut() {
+					*last |= ((gray < 0x8000) as u8) << (7 - self.x % 8);
+				}
+				&[][..]
+			}
+			PnmKind::Graymap | PnmKind::Pam(PamTupleType::Grayscale) => &[gray][..],
+			PnmKind::Pam(PamTupleType::GrayscaleAlpha) => &[gray, px.3],
+			PnmKind::Pixmap | PnmKind::Pam(PamTupleType::RGB) => &[px.0, px.1, px.2],
+			PnmKind::Pam(PamTupleType::RGBAlpha) => &[px.0, px.1, px.2, px.3],
+		};
+		for &x in samples {
+			// which rounds 8-bit samples, widened, the same as it did them
+			let x = (x as u64 * max + 32767) / 65535;
+			match (self.plain, max > 255) {
+				(true, _) => self.token(&x.to_string()),
+				(false, true) => self.row.extend_from_slice(&(x as u16).to_be_bytes()),
+				(false, false) => self.row.push(x as u8),
+			}
+		}
+
+		self.x += 1;
+		if self.x == self.width {
+			if self.plain {
+				self.row.push(b'\n');
+				self.line = 0;
+			}
+			crate::write_all(&mut self.out, &self.row)?;
+			self.row.clear();
+			self.x = 0;
+		}
+		Ok(())
+	}
+
+	/// adds a sample of a plain image to `row`, keeping lines to 70 bytes
+	/// like the spec asks. bitmaps' samples go without spaces.
+	fn token(&mut self, token: &str) {
+		let space = self.line > 0 && self.kind != PnmKind::Bitmap;
+		if self.line + space as usize + token.len() > 70 {
+			self.row.push(b'\n');
+			self.line = 0;
+		}
+		else if space {
+			self.row.push(b' ');
+			self.line += 1;
+		}
+		self.row.extend_from_slice(token.as_bytes());
+		self.line += token.len();
+	}
+
+	/// writes what there is of a row that didn't get all its pixels. the
+	/// image is cut short, like the pixels it was given.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		crate::write_all(&mut self.out, &self.row)?;
+		self.row.clear();
+		self.x = 0;
+		self.line = 0;
+		Ok(())
+	}
+}
+
+impl crate::Format for Pnm {
+	type Header = PnmHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = PnmPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = PnmPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = PnmEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+
+	/// samples keep their bits, up to the max value's.
+	fn decode_wide(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba16>), Error> {
+		let pixels = PnmPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels.wide().map_while(Result::ok)))
+	}
+
+	/// samples are scaled to the max value, which keeps all 16 bits of them
+	/// at 65535.
+	fn encode_wide(self, data: impl Iterator<Item = crate::Rgba16>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = PnmEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push_wide(px))?;
+		encoder.end()
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, pnm};
+
+	const IMAGE_GRADIENT: &[u8] = include_bytes!("../test/gradient.ppm");
+
+	fn gradient(x: u32, y: u32) -> crate::Rgba {
+		((x * 7) as u8, (y * 30) as u8, (x * y) as u8, 255)
+	}
+
+	#[test]
+	fn decode() {
+		// comments between the numbers
+		let mut data = IMAGE_GRADIENT;
+		let mut pixels = pnm::PnmPixels::new(&mut data).expect("error?");
+		assert_eq!(*pixels.header(), pnm::PnmHeader { width: 33, height: 7, kind: pnm::PnmKind::Pixmap, plain: false, max_value: 255 });
+		for (i, px) in (&mut pixels).enumerate() {
+			let (x, y) = (i as u32 % 33, i as u32 / 33);
+			assert_eq!(px.expect("error?"), gradient(x, y), "{x}, {y}");
+		}
+		assert_eq!(pixels.finish().expect("error?"), IMAGE_GRADIENT.len() as u64);
+
+		// two bytes a sample
+		let mut data = &include_bytes!("../test/deep.ppm")[..];
+		let (header, iter) = pnm::Pnm.decode(&mut data).expect("error?");
+		assert_eq!(header.max_value, 1023);
+		let expected = (0..16).map(|i| {
+			let scale = |x: u32| ((x * 255 + 511) / 1023) as u8;
+			(scale(i * 64), scale(1023 - i * 50), scale(i * i), 255)
+		});
+		assert!(iter.eq(expected));
+
+		// one image after another
+		let twice = [IMAGE_GRADIENT, IMAGE_GRADIENT].concat();
+		let mut data = &twice[..];
+		for _ in 0..2 {
+			let (_, iter) = pnm::Pnm.decode(&mut data).expect("error?");
+			assert!(iter.eq((0..33 * 7).map(|i| gradient(i % 33, i / 33))));
+		}
+		assert!(data.is_empty());
+	}
+
+	#[test]
+	fn wide() {
+		let mut data = &include_bytes!("../test/deep.ppm")[..];
+		let (header, iter) = pnm::Pnm.decode_wide(&mut data).expect("error?");
+		let scale = |x: u32| ((x * 65535 + 511) / 1023) as u16;
+		let expected = (0..16).map(|i| (scale(i * 64), scale(1023 - i * 50), scale(i * i), 65535)).collect::<Vec<_>>();
+		assert_eq!(iter.collect::<Vec<_>>(), expected);
+
+		// all 16 bits, written and read back
+		let pixels = (0..16u32).map(|i| ((i * 4099) as u16, !(i * 3) as u16, (i * i * 257) as u16, (i * 1000) as u16 + 1)).collect::<Vec<_>>();
+		for kind in [pnm::PnmKind::Pixmap, pnm::PnmKind::Pam(pnm::PamTupleType::RGBAlpha)] {
+			for plain in [false, true] {
+				let header = pnm::PnmHeader { kind, plain, max_value: 65535, ..header.clone() };
+				let mut out = vec![];
+				pnm::Pnm.encode_wide(pixels.iter().copied(), header, &mut out).expect("error?");
+				let mut data = &out[..];
+				let (_, iter) = pnm::Pnm.decode_wide(&mut data).expect("error?");
+				let alpha = |px: crate::Rgba16| if kind == pnm::PnmKind::Pixmap { (px.0, px.1, px.2, 65535) } else { px };
+				assert!(iter.eq(pixels.iter().copied().map(alpha)), "{kind:?} {plain}");
+			}
+		}
+
+		// 8-bit pixels, widened, come out the same as they did. gray is taken
+		// from all 16 bits, so it can round the other way
+		let pixels = (0..16u32).map(|i| ((i * 17) as u8, (255 - i * 9) as u8, (i * i) as u8, 255)).collect::<Vec<_>>();
+		for kind in [pnm::PnmKind::Pixmap, pnm::PnmKind::Pam(pnm::PamTupleType::RGBAlpha)] {
+			for max_value in [1, 100, 255, 1023, 65535] {
+				let header = pnm::PnmHeader { kind, max_value, ..header.clone() };
+				let (mut narrow, mut wide) = (vec![], vec![]);
+				pnm::Pnm.encode(pixels.iter().copied(), header.clone(), &mut narrow).expect("error?");
+				pnm::Pnm.encode_wide(pixels.iter().copied().map(crate::pixel::widen), header, &mut wide).expect("error?");
+				assert_eq!(narrow, wide, "{kind:?} {max_value}");
+			}
+		}
+	}
+
+	#[test]
+	fn decode_gray() {
+		let mut data = &include_bytes!("../test/gradient.pgm")[..];
+		let (header, iter) = pnm::Pnm.decode(&mut data).expect("error?");
+		assert_eq!((header.kind, header.max_value), (pnm::PnmKind::Graymap, 255));
+		let gray = |i: u32| {
+			let v = ((i % 33) * 7 + (i / 33) * 30) as u8;
+			(v, v, v, 255)
+		};
+		assert!(iter.eq((0..33 * 7).map(gray)));
+
+		let mut data = &include_bytes!("../test/deep.pgm")[..];
+		let (_, iter) = pnm::Pnm.decode(&mut data).expect("error?");
+		let expected = (0..16).map(|i| {
+			let v = ((i * 4000 * 255 + 32767) / 65535) as u8;
+			(v, v, v, 255)
+		});
+		assert!(iter.eq(expected));
+
+		// a bit a pixel, with rows padded to a byte
+		let image = include_bytes!("../test/mask.pbm");
+		let mut data = &image[..];
+		let mut pixels = pnm::PnmPixels::new(&mut data).expect("error?");
+		assert_eq!(*pixels.header(), pnm::PnmHeader { width: 33, height: 7, kind: pnm::PnmKind::Bitmap, plain: false, max_value: 1 });
+		for (i, px) in (&mut pixels).enumerate() {
+			let (x, y) = (i as u32 % 33, i as u32 / 33);
+			let v = if (x + y) % 3 == 0 || x == y { 0 } else { 255 };
+			assert_eq!(px.expect("error?"), (v, v, v, 255), "{x}, {y}");
+		}
+		assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+		// gray a byte a pixel, either way
+		let mut data = &include_bytes!("../test/gradient.pgm")[..];
+		let (header, iter) = pnm::Pnm.decode_as::<crate::Gray8>(&mut data).expect("error?");
+		let gray = iter.collect::<Vec<_>>();
+		assert!(gray.iter().copied().eq((0..33 * 7).map(|i| ((i % 33) * 7 + (i / 33) * 30) as u8)));
+		let mut out = vec![];
+		pnm::Pnm.encode_from(gray.iter().copied(), header, &mut out).expect("error?");
+		// the same samples, without the file's comment
+		let image = include_bytes!("../test/gradient.pgm");
+		assert_eq!(out[out.len() - 33 * 7..], image[image.len() - 33 * 7..]);
+
+		// 16-bit pixels keep their low bytes at a max value of 65535
+		let deep = (0..6u16).map(|i| (i * 10007, i * 3, 65535 - i, 65535)).collect::<Vec<_>>();
+		let header = pnm::PnmHeader { width: 3, height: 2, kind: pnm::PnmKind::Pixmap, plain: false, max_value: 65535 };
+		let mut out = vec![];
+		pnm::Pnm.encode_from(deep.iter().copied(), header, &mut out).expect("error?");
+		let mut data = &out[..];
+		let (_, iter) = pnm::Pnm.decode_as::<crate::Rgba16>(&mut data).expect("error?");
+		assert_eq!(iter.collect::<Vec<_>>(), deep);
+	}
+
+	#[test]
+	fn decode_plain() {
+		// the same pixels as their binary versions. the ppm has a comment
+		// partway through, and it and the pbm have no newline at the end
+		for (plain, binary) in [
+			(&include_bytes!("../test/gradient_plain.ppm")[..], IMAGE_GRADIENT),
+			(include_bytes!("../test/gradient_plain.pgm"), include_bytes!("../test/gradient.pgm")),
+			(include_bytes!("../test/mask_plain.pbm"), include_bytes!("../test/mask.pbm")),
+		] {
+			let mut data = plain;
+			let mut pixels = pnm::PnmPixels::new(&mut data).expect("error?");
+			assert!(pixels.header().plain);
+			let mut data = binary;
+			let (_, expected) = pnm::Pnm.decode(&mut data).expect("error?");
+			for (i, (a, b)) in (&mut pixels).zip(expected).enumerate() {
+				assert_eq!(a.expect("error?"), b, "{i}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), plain.len() as u64);
+
+			let mut data = plain;
+			let pixels = pnm::PnmPixels::new(&mut data).expect("error?");
+			assert_eq!(pixels.finish().expect("error?"), plain.len() as u64);
+		}
+	}
+
+	#[test]
+	fn decode_pam() {
+		let small = |i: u32| ((i * 16) as u8, (i * 3) as u8, (255 - i) as u8, (i * 17) as u8);
+
+		let image = include_bytes!("../test/small.pam");
+		let mut data = &image[..];
+		let mut pixels = pnm::PnmPixels::new(&mut data).expect("error?");
+		let kind = pnm::PnmKind::Pam(pnm::PamTupleType::RGBAlpha);
+		assert_eq!(*pixels.header(), pnm::PnmHeader { width: 4, height: 4, kind, plain: false, max_value: 255 });
+		for (i, px) in (&mut pixels).enumerate() {
+			assert_eq!(px.expect("error?"), small(i as u32), "{i}");
+		}
+		assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+		// header lines in any order, with comments and spacing
+		let mut data = &include_bytes!("../test/gray_alpha.pam")[..];
+		let (header, iter) = pnm::Pnm.decode(&mut data).expect("error?");
+		assert_eq!(header.kind, pnm::PnmKind::Pam(pnm::PamTupleType::GrayscaleAlpha));
+		assert!(iter.eq((0..16).map(|i| {
+			let px = small(i);
+			(px.0, px.0, px.0, px.3)
+		})));
+
+		// the tuple type from the depth
+		let mut data = &include_bytes!("../test/deep.pam")[..];
+		let (header, iter) = pnm::Pnm.decode(&mut data).expect("error?");
+		assert_eq!(header.kind, pnm::PnmKind::Pam(pnm::PamTupleType::RGB));
+		let mut data = &include_bytes!("../test/deep.ppm")[..];
+		let (_, expected) = pnm::Pnm.decode(&mut data).expect("error?");
+		assert!(iter.eq(expected));
+
+		// black and white is gray, with 1 for white unlike pbm
+		let mut data = &include_bytes!("../test/mask.pam")[..];
+		let (header, iter) = pnm::Pnm.decode(&mut data).expect("error?");
+		assert_eq!(header.kind, pnm::PnmKind::Pam(pnm::PamTupleType::Grayscale));
+		let mut data = &include_bytes!("../test/mask.pbm")[..];
+		let (_, expected) = pnm::Pnm.decode(&mut data).expect("error?");
+		assert!(iter.eq(expected));
+	}
+
+	#[test]
+	fn encode() {
+		for max_value in [255, 100, 1023, 65535] {
+			let (width, height) = (13, 5);
+			let header = pnm::PnmHeader { width, height, kind: pnm::PnmKind::Pixmap, plain: false, max_value };
+			let pixels = (0..width * height).map(|i| ((i * 3) as u8, (i / width * 40) as u8, i as u8 ^ 0x55, 255));
+
+			let mut out = vec![];
+			pnm::Pnm.encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+
+			let mut data = &out[..];
+			let mut decoded = pnm::PnmPixels::new(&mut data).expect("error?");
+			assert_eq!(*decoded.header(), header);
+			for (i, (a, b)) in (&mut decoded).zip(pixels).enumerate() {
+				let a = a.expect("error?");
+				// a max value under 255 loses precision
+				match max_value {
+					100 => assert!(a.0.abs_diff(b.0) <= 2 && a.1.abs_diff(b.1) <= 2 && a.2.abs_diff(b.2) <= 2, "{i}: {a:?} {b:?}"),
+					_ => assert_eq!(a, b, "{i}"),
+				}
+			}
+			assert_eq!(decoded.finish().expect("error?"), out.len() as u64);
+		}
+
+		// plain images, with lines of at most 70 bytes
+		for kind in [pnm::PnmKind::Bitmap, pnm::PnmKind::Graymap, pnm::PnmKind::Pixmap] {
+			let (width, height) = (40, 3);
+			let header = pnm::PnmHeader { width, height, kind, plain: false, max_value: 65535 };
+			let pixels = (0..width * height).map(|i| ((i * 3) as u8, (i * 50) as u8, i as u8 ^ 0x55, 255));
+			let mut binary = vec![];
+			pnm::Pnm.encode(pixels.clone(), header.clone(), &mut binary).expect("error?");
+			let mut plain = vec![];
+			pnm::Pnm.encode(pixels, pnm::PnmHeader { plain: true, ..header }, &mut plain).expect("error?");
+			assert!(plain.split(|&b| b == b'\n').all(|line| line.len() <= 70), "{kind:?}");
+
+			let (mut a, mut b) = (&plain[..], &binary[..]);
+			let (_, a) = pnm::Pnm.decode(&mut a).expect("error?");
+			let (_, b) = pnm::Pnm.decode(&mut b).expect("error?");
+			assert!(a.eq(b), "{kind:?}");
+		}
+
+		let header = pnm::PnmHeader { width: 33, height: 7, kind: pnm::PnmKind::Pixmap, plain: false, max_value: 255 };
+		let mut out = vec![];
+		pnm::Pnm.encode((0..33 * 7).map(|i| gradient(i % 33, i / 33)), header, &mut out).expect("error?");
+		assert_eq!(out[..14], *b"P6\n33 7\n255\n\0\0");
+
+		let header = pnm::PnmHeader { width: 33, height: 7, kind: pnm::PnmKind::Pixmap, plain: false, max_value: 0 };
+		let result = pnm::PnmEncoder::new(&header, vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 8, field: "max value" })));
+
+		for tuple_type in [pnm::PamTupleType::Grayscale, pnm::PamTupleType::GrayscaleAlpha, pnm::PamTupleType::RGB, pnm::PamTupleType::RGBAlpha] {
+			for max_value in [255, 1000] {
+				let (width, height) = (7, 3);
+				let header = pnm::PnmHeader { width, height, kind: pnm::PnmKind::Pam(tuple_type), plain: false, max_value };
+				let pixels = (0..width * height).map(|i| ((i * 3) as u8, (i * 50) as u8, i as u8 ^ 0x55, (i * 12) as u8));
+				let mut out = vec![];
+				pnm::Pnm.encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+
+				let mut data = &out[..];
+				let (decoded, iter) = pnm::Pnm.decode(&mut data).expect("error?");
+				assert_eq!(decoded, header);
+				let expected = pixels.map(|px| {
+					let gray = crate::pixel::luma(px) as u8;
+					match tuple_type {
+						pnm::PamTupleType::Grayscale => (gray, gray, gray, 255),
+						pnm::PamTupleType::GrayscaleAlpha => (gray, gray, gray, px.3),
+						pnm::PamTupleType::RGB => (px.0, px.1, px.2, 255),
+						pnm::PamTupleType::RGBAlpha => px,
+					}
+				});
+				assert!(iter.eq(expected), "{tuple_type:?}");
+			}
+		}
+		let header = pnm::PnmHeader { width: 4, height: 0, kind: pnm::PnmKind::Pam(pnm::PamTupleType::RGB), plain: false, max_value: 255 };
+		let result = pnm::PnmEncoder::new(&header, vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 18, field: "height" })), "{result:?}");
+
+		// gray by luma, then black or white by half of that. alpha is dropped
+		for (px, gray) in [((255, 255, 255, 255), 255), ((255, 0, 0, 255), 77), ((0, 255, 0, 0), 149), ((0, 0, 255, 255), 29)] {
+			let header = pnm::PnmHeader { width: 1, height: 1, kind: pnm::PnmKind::Graymap, plain: false, max_value: 255 };
+			let mut out = vec![];
+			pnm::Pnm.encode([px].into_iter(), header, &mut out).expect("error?");
+			assert_eq!(out, [&b"P5\n1 1\n255\n"[..], &[gray]].concat(), "{px:?}");
+
+			// bits pack high first
+			let header = pnm::PnmHeader { width: 3, height: 1, kind: pnm::PnmKind::Bitmap, plain: false, max_value: 0 };
+			let mut out = vec![];
+			pnm::Pnm.encode([px; 3].into_iter(), header, &mut out).expect("error?");
+			let bits = if gray < 128 { 0b1110_0000 } else { 0 };
+			assert_eq!(out, [&b"P4\n3 1\n"[..], &[bits]].concat(), "{px:?}");
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = pnm::PnmPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name)));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic.ppm", Error::InvalidMagic { offset: 0 });
+		case!("zero_width.ppm", Error::InvalidHeader { offset: 3, field: "width" });
+		case!("bad_width.ppm", Error::InvalidHeader { offset: 3, field: "width" });
+		case!("bad_max_value.ppm", Error::InvalidHeader { offset: 8, field: "max value" });
+		case!("over_max_value.ppm", Error::Malformed { offset: 99, .. });
+		case!("truncated.ppm", Error::UnexpectedEof { offset: 500 });
+		case!("over_max_value.pgm", Error::Malformed { offset: 27, .. });
+		case!("truncated.pbm", Error::UnexpectedEof { offset: 28 });
+		case!("over_max_value_plain.pgm", Error::Malformed { offset: 14, reason: "sample is over the max value" });
+		case!("not_a_number.ppm", Error::Malformed { offset: 13, reason: "sample isn't a number" });
+		case!("bad_bit.pbm", Error::Malformed { offset: 9, .. });
+		case!("truncated_plain.ppm", Error::UnexpectedEof { offset: 500 });
+		case!("bad_tuple_type.pam", Error::InvalidHeader { offset: 48, field: "tuple type" });
+		case!("bad_depth.pam", Error::InvalidHeader { offset: 26, field: "depth" });
+		case!("no_height.pam", Error::InvalidHeader { offset: 49, field: "height" });
+		case!("bad_keyword.pam", Error::InvalidHeader { offset: 11, field: "keyword" });
+		case!("bad_max_value.pam", Error::InvalidHeader { offset: 35, field: "max value" });
+		case!("long_line.pam", Error::InvalidHeader { offset: 3, field: "header line" });
+
+		assert!(decode(IMAGE_GRADIENT).is_ok());
+	}
+}
@@ -0,0 +1,487 @@
+/*!
+pfm images, netpbm's floating point relative.
+
+the header is the magic, `PF` for color or `Pf` for gray, then the width,
+height and scale as text, and a single whitespace byte before the samples.
+samples are 32-bit floats, little-endian if the scale is negative and
+big-endian otherwise. like bmp, rows are stored bottom-up, so the samples
+are read and kept whole before the first pixel.
+
+`PfmPixels` hands out samples as they are, gray ones as three of the same.
+`Pfm::decode` maps them to 8 bits with its `ToneCurve`, and `Pfm::encode`
+maps them back. the scale is only read and written, since it only says how
+bright a sample of 1 is, and most tools ignore it.
+*/
+
+use crate::Error;
+
+use super::{byte, is_space, number};
+use crate::error::oom;
+use crate::tone::ToneCurve;
+
+/// the longest scale that's read.
+const SCALE_MAX: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PfmColorType {
+	Gray,
+	RGB,
+}
+
+impl PfmColorType {
+	fn channels(self) -> usize {
+		match self {
+			Self::Gray => 1,
+			Self::RGB => 3,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PfmHeader {
+	pub width: u32,
+	pub height: u32,
+	pub color_type: PfmColorType,
+	/// how bright a sample of 1 is. it's stored negated for little-endian
+	/// samples, and this is how big it is either way.
+	pub scale: f32,
+	pub little_endian: bool,
+}
+
+impl crate::Dimensions for PfmHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Pfm {
+	pub curve: ToneCurve,
+}
+
+impl Default for Pfm {
+	fn default() -> Self {
+		Self { curve: ToneCurve::Srgb }
+	}
+}
+
+/// samples of a pfm image, as red, green and blue. they're all read the
+/// first time one's asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct PfmPixels<'a, R> {
+	header: PfmHeader,
+	data: &'a mut R,
+	offset: u64,
+	stride: usize,
+	/// every row, once they've been read.
+	rows_data: Vec<u8>,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> PfmPixels<'a, R> {
+	/// reads the header, up to the first sample.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut magic = [0; 2];
+		crate::read_exact(data, &mut magic, 0)?;
+		let color_type = match &magic {
+			b"PF" => PfmColorType::RGB,
+			b"Pf" => PfmColorType::Gray,
+			_ => return Err(Error::InvalidMagic { offset: 0 }),
+		};
+		let mut offset = 2;
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+
+		let b = byte(data, &mut offset)?;
+		if !is_space(b) {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let (width, start, b) = number(data, &mut offset, b, "width")?;
+		if width == 0 || !is_space(b) {
+			return Err(field(start, "width"));
+		}
+		let (height, start, mut b) = number(data, &mut offset, b, "height")?;
+		if height == 0 || !is_space(b) {
+			return Err(field(start, "height"));
+		}
+
+		while is_space(b) {
+			b = byte(data, &mut offset)?;
+		}
+		let start = offset.saturating_sub(1);
+		let mut text = vec![];
+		while !is_space(b) {
+			if text.len() == SCALE_MAX {
+				return Err(field(start, "scale"));
+			}
+			text.push(b);
+			b = byte(data, &mut offset)?;
+		}
+		let scale = std::str::from_utf8(&text).ok().and_then(|x| x.parse::<f32>().ok());
+		let Some(scale) = scale.filter(|x| x.is_finite() && *x != 0.0)
+		else {
+			return Err(field(start, "scale"));
+		};
+
+		let header = PfmHeader {
+			width,
+			height,
+			color_type,
+			scale: scale.abs(),
+			little_endian: scale < 0.0,
+		};
+		let stride = width as u64 * color_type.channels() as u64 * 4;
+		Ok(Self {
+			header,
+			data,
+			offset,
+			stride: stride.try_into().map_err(|_| oom())?,
+			rows_data: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &PfmHeader {
+		&self.header
+	}
+
+	/// reads every row into `rows_data`, a piece at a time, so memory is
+	/// only taken up once the data shows up.
+	fn read(&mut self) -> Result<(), Error> {
+		let mut len = (self.stride as u64).saturating_mul(self.header.height as u64);
+		while len > 0 {
+			let n = len.min(1 << 16) as usize;
+			let start = self.rows_data.len();
+			self.rows_data.try_reserve(n).map_err(|_| oom())?;
+			self.rows_data.resize(start + n, 0);
+			crate::read_exact(self.data, &mut self.rows_data[start..], self.offset)?;
+			self.offset = self.offset.saturating_add(n as u64);
+			len -= n as u64;
+		}
+		Ok(())
+	}
+
+	/// skips the samples if they haven't been read, and returns the number
+	/// of bytes the image took up.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.x == 0 && self.y == 0 && !self.failed {
+			let mut buf = [0; 512];
+			let mut left = (self.stride as u64).saturating_mul(self.header.height as u64);
+			while left > 0 {
+				let n = left.min(buf.len() as u64) as usize;
+				crate::read_exact(self.data, &mut buf[..n], self.offset)?;
+				self.offset = self.offset.saturating_add(n as u64);
+				left -= n as u64;
+			}
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<R: std::io::Read> Iterator for PfmPixels<'_, R> {
+	type Item = Result<[f32; 3], Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		if self.x == 0 && self.y == 0
+			&& let Err(e) = self.read()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		// the top row is the last one
+		let channels = self.header.color_type.channels();
+		let row = (self.header.height - 1 - self.y) as usize * self.stride;
+		let i = row + self.x as usize * channels * 4;
+		let samples = &self.rows_data[i..i + channels * 4];
+		let sample = |c: usize| {
+			let bytes = [samples[c * 4], samples[c * 4 + 1], samples[c * 4 + 2], samples[c * 4 + 3]];
+			match self.header.little_endian {
+				true => f32::from_le_bytes(bytes),
+				false => f32::from_be_bytes(bytes),
+			}
+		};
+		let px = match self.header.color_type {
+			PfmColorType::Gray => [sample(0); 3],
+			PfmColorType::RGB => [sample(0), sample(1), sample(2)],
+		};
+
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+/// `PfmPixels` mapped to 8 bits, ending at the first error, for
+/// `Format::decode`.
+struct Pixels<'a, R> {
+	pixels: PfmPixels<'a, R>,
+	curve: ToneCurve,
+}
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		Some(tone(self.pixels.next()?.ok()?, self.curve))
+	}
+}
+
+/// a pixel brought down to 8 bits through `curve`.
+fn tone(px: [f32; 3], curve: ToneCurve) -> crate::Rgba {
+	let [r, g, b] = px.map(|x| curve.apply(x));
+	(r, g, b, 255)
+}
+
+/// encodes samples as red, green and blue. gray images get the rec. 709
+/// luminance of them, since they're linear.
+///
+/// rows are written bottom-up, so they're all kept until the last one.
+/// `data` running out is a `PixelCountMismatch`, and nothing is written.
+pub fn encode_f32(data: impl Iterator<Item = [f32; 3]>, header: &PfmHeader, out: &mut impl std::io::Write) -> Result<(), Error> {
+	let field = |offset, field| Error::InvalidHeader { offset, field };
+	let magic = match header.color_type {
+		PfmColorType::Gray => "Pf",
+		PfmColorType::RGB => "PF",
+	};
+	let scale = if header.little_endian { -header.scale.abs() } else { header.scale.abs() };
+	let text = format!("{magic}\n{} {}\n{scale}\n", header.width, header.height);
+	let at = |word| text.split_ascii_whitespace().nth(word).map_or(0, |x| x.as_ptr() as u64 - text.as_ptr() as u64);
+	if header.width == 0 {
+		return Err(field(at(1), "width"));
+	}
+	if header.height == 0 {
+		return Err(field(at(2), "height"));
+	}
+	if !scale.is_finite() || scale == 0.0 {
+		return Err(field(at(3), "scale"));
+	}
+
+	let channels = header.color_type.channels();
+	let stride = (header.width as u64 * channels as u64 * 4).try_into().map_err(|_| oom())?;
+	let total = header.width as u64 * header.height as u64;
+	let mut rows: Vec<u8> = vec![];
+	crate::push_exact(data, total, |px| {
+		rows.try_reserve(12).map_err(|_| oom())?;
+		let samples = match header.color_type {
+			PfmColorType::Gray => &[0.2126 * px[0] + 0.7152 * px[1] + 0.0722 * px[2]][..],
+			PfmColorType::RGB => &px,
+		};
+		for &x in samples {
+			match header.little_endian {
+				true => rows.extend_from_slice(&x.to_le_bytes()),
+				false => rows.extend_from_slice(&x.to_be_bytes()),
+			}
+		}
+		Ok(())
+	})?;
+	crate::write_all(out, text.as_bytes())?;
+	for row in rows.chunks_exact(stride).rev() {
+		crate::write_all(out, row)?;
+	}
+	Ok(())
+}
+
+impl crate::Format for Pfm {
+	type Header = PfmHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = PfmPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels { pixels, curve: self.curve }))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = PfmPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels.map(move |px| px.map(|px| tone(px, self.curve)))))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let data = data.map(|px| [px.0, px.1, px.2].map(|x| self.curve.invert(x)));
+		encode_f32(data, &header, out)
+	}
+
+	/// samples as they are, without the curve. alpha is 1.
+	fn decode_float(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::RgbaF32>), Error> {
+		let pixels = PfmPixels::new(data)?;
+		let header = pixels.header().clone();
+		Ok((header, pixels.map_while(Result::ok).map(|[r, g, b]| (r, g, b, 1.0))))
+	}
+
+	/// samples as they are, without the curve. alpha is dropped.
+	fn encode_float(self, data: impl Iterator<Item = crate::RgbaF32>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		encode_f32(data.map(|px| [px.0, px.1, px.2]), &header, out)
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::Error;
+	use crate::pnm::{self, pfm};
+
+	fn sample(i: u32, c: u32) -> f32 {
+		(i as f32 - 4.0) * 0.25 + c as f32 * 10.0
+	}
+
+	#[test]
+	fn decode() {
+		// rows are bottom-up. the little-endian one has a negative scale
+		for (image, little_endian, scale) in [
+			(&include_bytes!("../../test/ramp.pfm")[..], false, 1.0),
+			(include_bytes!("../../test/ramp_le.pfm"), true, 2.5),
+		] {
+			let mut data = image;
+			let mut pixels = pfm::PfmPixels::new(&mut data).expect("error?");
+			let header = pixels.header();
+			assert_eq!((header.width, header.height, header.color_type), (5, 3, pfm::PfmColorType::RGB));
+			assert_eq!((header.little_endian, header.scale), (little_endian, scale));
+			for (i, px) in (&mut pixels).enumerate() {
+				let i = i as u32;
+				assert_eq!(px.expect("error?"), [sample(i, 0), sample(i, 1), sample(i, 2)], "{i}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			let mut data = image;
+			let pixels = pfm::PfmPixels::new(&mut data).expect("error?");
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+
+		let mut data = &include_bytes!("../../test/ramp_gray.pfm")[..];
+		let pixels = pfm::PfmPixels::new(&mut data).expect("error?");
+		assert_eq!(pixels.header().color_type, pfm::PfmColorType::Gray);
+		for (i, px) in pixels.enumerate() {
+			assert_eq!(px.expect("error?"), [sample(i as u32, 0); 3], "{i}");
+		}
+	}
+
+	#[test]
+	fn curve() {
+		use crate::Format;
+
+		for curve in [pfm::ToneCurve::Linear, pfm::ToneCurve::Srgb, pfm::ToneCurve::Reinhard] {
+			assert_eq!(curve.apply(-1.0), 0);
+			assert_eq!(curve.apply(f32::NAN), 0);
+			for x in 0..=255 {
+				assert_eq!(curve.apply(curve.invert(x)), x, "{curve:?}");
+			}
+		}
+		assert_eq!(pfm::ToneCurve::Linear.apply(0.5), 128);
+		assert_eq!(pfm::ToneCurve::Linear.apply(2.0), 255);
+		assert_eq!(pfm::ToneCurve::Srgb.apply(0.5), 188);
+		assert_eq!(pfm::ToneCurve::Reinhard.apply(1.0), 188);
+		assert!(pfm::ToneCurve::Reinhard.apply(8.0) < 255);
+
+		let mut data = &include_bytes!("../../test/ramp.pfm")[..];
+		let (_, iter) = pnm::Pfm { curve: pfm::ToneCurve::Linear }.decode(&mut data).expect("error?");
+		let expected = (0..15).map(|i| {
+			let [r, g, b] = [0, 1, 2].map(|c| pfm::ToneCurve::Linear.apply(sample(i, c)));
+			(r, g, b, 255)
+		});
+		assert!(iter.eq(expected));
+	}
+
+	#[test]
+	fn float() {
+		use crate::{Format, RgbaF32};
+
+		// negative samples and ones past 1 come through as they are
+		let mut data = &include_bytes!("../../test/ramp.pfm")[..];
+		let (header, iter) = pnm::Pfm::default().decode_as::<RgbaF32>(&mut data).expect("error?");
+		let pixels = iter.collect::<Vec<_>>();
+		let expected = (0..15).map(|i| (sample(i, 0), sample(i, 1), sample(i, 2), 1.0));
+		assert!(pixels.iter().copied().eq(expected));
+
+		let mut out = vec![];
+		pnm::Pfm::default().encode_from(pixels.iter().copied(), header, &mut out).expect("error?");
+		let mut data = &out[..];
+		let (_, iter) = pnm::Pfm::default().decode_as::<RgbaF32>(&mut data).expect("error?");
+		assert!(iter.eq(pixels));
+	}
+
+	#[test]
+	fn encode() {
+		use crate::Format;
+
+		for little_endian in [false, true] {
+			for color_type in [pfm::PfmColorType::Gray, pfm::PfmColorType::RGB] {
+				let header = pfm::PfmHeader { width: 5, height: 3, color_type, scale: 1.0, little_endian };
+				let pixels = (0..15).map(|i| [sample(i, 0), sample(i, 1), sample(i, 2)]);
+				let mut out = vec![];
+				pfm::encode_f32(pixels.clone(), &header, &mut out).expect("error?");
+
+				let mut data = &out[..];
+				let decoded = pfm::PfmPixels::new(&mut data).expect("error?");
+				assert_eq!(*decoded.header(), header);
+				for (i, (a, b)) in decoded.zip(pixels).enumerate() {
+					let b = match color_type {
+						pfm::PfmColorType::Gray => [0.2126 * b[0] + 0.7152 * b[1] + 0.0722 * b[2]; 3],
+						pfm::PfmColorType::RGB => b,
+					};
+					assert_eq!(a.expect("error?"), b, "{i}");
+				}
+			}
+		}
+
+		let header = pfm::PfmHeader { width: 5, height: 3, color_type: pfm::PfmColorType::RGB, scale: 1.0, little_endian: false };
+		let mut out = vec![];
+		pfm::encode_f32((0..15).map(|i| [sample(i, 0), sample(i, 1), sample(i, 2)]), &header, &mut out).expect("error?");
+		assert_eq!(out, include_bytes!("../../test/ramp.pfm"));
+
+		// through 8 bits
+		let pixels = (0..15).map(|i| ((i * 17) as u8, (i * 3) as u8, 255 - i as u8, 255));
+		let mut out = vec![];
+		pnm::Pfm::default().encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+		let mut data = &out[..];
+		let (_, iter) = pnm::Pfm::default().decode(&mut data).expect("error?");
+		assert!(iter.eq(pixels));
+
+		let header = pfm::PfmHeader { scale: f32::NAN, ..header };
+		let result = pfm::encode_f32([].into_iter(), &header, &mut vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 7, field: "scale" })), "{result:?}");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = pfm::PfmPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../../test/corrupt/", $name)));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic.pfm", Error::InvalidMagic { offset: 0 });
+		case!("zero_scale.pfm", Error::InvalidHeader { offset: 7, field: "scale" });
+		case!("bad_scale.pfm", Error::InvalidHeader { offset: 7, field: "scale" });
+		case!("truncated.pfm", Error::UnexpectedEof { offset: 100 });
+
+		assert!(decode(include_bytes!("../../test/ramp.pfm")).is_ok());
+	}
+}
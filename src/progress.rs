@@ -0,0 +1,121 @@
+/*!
+reporting progress a row at a time, with the chance to stop.
+
+`Progress` passes pixels through, and calls back after each row with how
+many rows are done and how many there are. breaking ends the pixels there,
+which `Format::decode_with_progress` and `encode_with_progress` turn into
+`Error::Cancelled`.
+*/
+
+use std::ops::ControlFlow;
+
+/// `pixels`, calling `f` after each row of them.
+pub(crate) struct Progress<I, F> {
+	pixels: I,
+	f: F,
+	width: u64,
+	height: u32,
+	/// pixels of the row so far, and rows before it.
+	x: u64,
+	y: u32,
+	pub(crate) cancelled: bool,
+}
+
+impl<I: Iterator, F: FnMut(u32, u32) -> ControlFlow<()>> Progress<I, F> {
+	pub(crate) fn new(pixels: I, width: u32, height: u32, f: F) -> Self {
+		Self {
+			pixels,
+			f,
+			width: width as u64,
+			height,
+			x: 0,
+			y: 0,
+			cancelled: false,
+		}
+	}
+}
+
+impl<I: Iterator, F: FnMut(u32, u32) -> ControlFlow<()>> Iterator for Progress<I, F> {
+	type Item = I::Item;
+
+	fn next(&mut self) -> Option<I::Item> {
+		if self.cancelled {
+			return None;
+		}
+		let px = self.pixels.next()?;
+		self.x += 1;
+		// past the last row is past what the header said, so it isn't reported
+		if self.x == self.width && self.y < self.height {
+			self.x = 0;
+			self.y += 1;
+			self.cancelled = (self.f)(self.y, self.height).is_break();
+		}
+		Some(px)
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		if self.cancelled {
+			return (0, Some(0));
+		}
+		let (_, upper) = self.pixels.size_hint();
+		(0, upper)
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use std::ops::ControlFlow;
+
+	use crate::progress::Progress;
+
+	#[test]
+	fn rows() {
+		let mut calls = vec![];
+		let progress = Progress::new(0..12, 4, 3, |y, height| {
+			calls.push((y, height));
+			ControlFlow::Continue(())
+		});
+		assert_eq!(progress.count(), 12);
+		assert_eq!(calls, [(1, 3), (2, 3), (3, 3)]);
+
+		// the row that broke is kept, and nothing after it
+		let mut progress = Progress::new(0..12, 4, 3, |y, _| if y == 2 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) });
+		assert_eq!((&mut progress).collect::<Vec<_>>(), (0..8).collect::<Vec<_>>());
+		assert!(progress.cancelled);
+
+		// extra pixels aren't reported
+		let mut rows = 0;
+		let progress = Progress::new(0..20, 4, 3, |y, _| {
+			rows = y;
+			ControlFlow::Continue(())
+		});
+		assert_eq!(progress.count(), 20);
+		assert_eq!(rows, 3);
+	}
+
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn cancel() {
+		use crate::{Error, Format, qoi};
+
+		let image = include_bytes!("../test/small.qoi");
+		let mut data = &image[..];
+		let (_, iter) = qoi::Qoi.decode_with_progress(&mut data, |y, _| if y == 3 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }).expect("error?");
+		let pixels = iter.collect::<Vec<_>>();
+		assert_eq!(pixels.len(), 13);
+		assert!(matches!(pixels[12], Err(Error::Cancelled)));
+
+		let (header, pixels) = qoi::decode_to_vec(image).expect("error?");
+		let mut out = vec![];
+		let mut rows = 0;
+		let count = |y, _| {
+			rows = y;
+			ControlFlow::Continue(())
+		};
+		qoi::Qoi.encode_with_progress(pixels.iter().copied(), header.clone(), &mut out, count).expect("error?");
+		assert_eq!((&out[..], rows), (&image[..], 4));
+		let result = qoi::Qoi.encode_with_progress(pixels.into_iter(), header, &mut vec![], |_, _| ControlFlow::Break(()));
+		assert!(matches!(result, Err(Error::Cancelled)), "{result:?}");
+	}
+}
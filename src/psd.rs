@@ -0,0 +1,497 @@
+/*!
+photoshop images, of which only the flattened composite is decoded. there's
+no encoder.
+
+8 and 16-bit gray and rgb images are decoded, raw or rle. 16-bit samples
+come out as their high byte. layers are skipped, and so are bitmap, indexed,
+cmyk, lab and other images, and zip compressed ones.
+
+the header is 26 bytes of big-endian numbers: the magic, version, 6 reserved
+bytes, channels, height, width, bits a channel, and color mode. after it are
+sections for the color mode, image resources and layers, each a 4-byte
+length and then that many bytes, and then the composite, as a 2-byte
+compression and the channels one after another, as planes. rle composites
+have a 2-byte length for each row of each plane, and then the rows, each
+packed on its own: a signed byte before each run, 0 to 127 for that many
+bytes and one more as they are, -1 to -127 for the next byte repeated one
+more time than its negation, and -128 for nothing.
+
+the channel after the colors is alpha, and any after it are ignored.
+photoshop blends the colors of the composite with white where it's
+transparent, which is undone.
+
+since pixels are handed out top to bottom, the planes that are used are
+read and kept whole before the first pixel.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+const MAGIC: &[u8; 4] = b"8BPS";
+const HEADER_SIZE: u64 = 26;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PsdColorMode {
+	Gray,
+	RGB,
+}
+
+impl PsdColorMode {
+	fn channels(self) -> u16 {
+		match self {
+			Self::Gray => 1,
+			Self::RGB => 3,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PsdHeader {
+	pub width: u32,
+	pub height: u32,
+	/// channels, including alpha and any after it.
+	pub channels: u16,
+	pub color_mode: PsdColorMode,
+	/// 8 or 16.
+	pub bit_depth: u8,
+	/// whether the composite is rle.
+	pub rle: bool,
+}
+
+impl PsdHeader {
+	/// whether there's a channel after the colors, which is alpha.
+	pub fn alpha(&self) -> bool {
+		self.channels > self.color_mode.channels()
+	}
+}
+
+impl crate::Dimensions for PsdHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// `len` bytes at `offset`, read a piece at a time, so memory is only taken
+/// up once the data shows up. with no `buf`, they're thrown away.
+fn read(data: &mut impl std::io::Read, offset: u64, len: u64, mut buf: Option<&mut Vec<u8>>) -> Result<(), Error> {
+	let mut skip = [0; 512];
+	let mut done = 0;
+	while done < len {
+		let n = (len - done).min(1 << 16) as usize;
+		let piece = match &mut buf {
+			Some(buf) => {
+				let at = buf.len();
+				buf.try_reserve(n).map_err(|_| oom())?;
+				buf.resize(at + n, 0);
+				&mut buf[at..]
+			}
+			None => &mut skip[..n.min(512)],
+		};
+		let n = piece.len();
+		crate::read_exact(data, piece, offset + done)?;
+		done += n as u64;
+	}
+	Ok(())
+}
+
+/// unpacks an rle row into `len` bytes.
+fn unpack(mut data: &[u8], len: usize, out: &mut Vec<u8>) -> Result<(), &'static str> {
+	out.clear();
+	let short = "row ends before its last pixel";
+	while out.len() < len {
+		let [n, rest @ ..] = data
+		else {
+			return Err(short);
+		};
+		data = rest;
+		let (n, repeat) = match *n as i8 {
+			-128 => continue,
+			n @ 0.. => (n as usize + 1, false),
+			n => ((1 - n as isize) as usize, true),
+		};
+		if out.len() + n > len {
+			return Err("run goes past the end of the row");
+		}
+		match repeat {
+			true => {
+				let [b, rest @ ..] = data
+				else {
+					return Err(short);
+				};
+				out.resize(out.len() + n, *b);
+				data = rest;
+			}
+			false => {
+				if data.len() < n {
+					return Err(short);
+				}
+				out.extend_from_slice(&data[..n]);
+				data = &data[n..];
+			}
+		}
+	}
+	Ok(())
+}
+
+/// pixels of a photoshop image's composite, all of which is read before the
+/// first is handed out.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct PsdPixels<'a, R> {
+	header: PsdHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// where each row of each plane starts in `pixel_data`, and one past the
+	/// last used, for rle images.
+	starts: Vec<u64>,
+	/// the bytes the planes that aren't used take up.
+	unused: u64,
+	/// the planes that are used, from right after the compression or the
+	/// row lengths, once they've been read.
+	pixel_data: Vec<u8>,
+	loaded: bool,
+	/// a row of one plane.
+	plane: Vec<u8>,
+	/// the row being handed out, with its channels together.
+	row: Vec<u8>,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> PsdPixels<'a, R> {
+	/// reads the header and skips the sections after it, and for rle images
+	/// reads the row lengths, up to the planes.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; HEADER_SIZE as usize];
+		crate::read_exact(data, &mut buf, 0)?;
+		if buf[..4] != *MAGIC {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let u16_at = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]);
+		let u32_at = |i: usize| u32::from_be_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+		if u16_at(4) != 1 {
+			return Err(field(4, "version"));
+		}
+		let (channels, height, width) = (u16_at(12), u32_at(14), u32_at(18));
+		if height == 0 {
+			return Err(field(14, "height"));
+		}
+		if width == 0 {
+			return Err(field(18, "width"));
+		}
+		let bit_depth = match u16_at(22) {
+			8 => 8,
+			16 => 16,
+			_ => return Err(field(22, "bits per channel")),
+		};
+		let color_mode = match u16_at(24) {
+			1 => PsdColorMode::Gray,
+			3 => PsdColorMode::RGB,
+			_ => return Err(field(24, "color mode")),
+		};
+		if channels < color_mode.channels() || channels > 56 {
+			return Err(field(12, "channels"));
+		}
+
+		// the color mode, image resources and layers
+		let mut offset = HEADER_SIZE;
+		for _ in 0..3 {
+			let mut len = [0; 4];
+			crate::read_exact(data, &mut len, offset)?;
+			let len = u32::from_be_bytes(len) as u64;
+			read(data, offset + 4, len, None)?;
+			offset += 4 + len;
+		}
+
+		let mut compression = [0; 2];
+		crate::read_exact(data, &mut compression, offset)?;
+		let rle = match u16::from_be_bytes(compression) {
+			0 => false,
+			1 => true,
+			_ => return Err(field(offset, "compression")),
+		};
+		offset += 2;
+
+		let header = PsdHeader { width, height, channels, color_mode, bit_depth, rle };
+		let used = (color_mode.channels() + header.alpha() as u16) as u64;
+		let (starts, unused) = match rle {
+			true => {
+				let n = channels as u64 * height as u64;
+				let mut lengths = vec![];
+				read(data, offset, n * 2, Some(&mut lengths))?;
+				offset += n * 2;
+				let rows = (used * height as u64) as usize;
+				let mut starts = vec![];
+				starts.try_reserve_exact(rows + 1).map_err(|_| oom())?;
+				let (mut at, mut unused) = (0, 0);
+				for (i, len) in lengths.chunks_exact(2).enumerate() {
+					let len = u16::from_be_bytes([len[0], len[1]]) as u64;
+					if i < rows {
+						starts.push(at);
+						at += len;
+					} else {
+						unused += len;
+					}
+				}
+				starts.push(at);
+				(starts, unused)
+			}
+			false => {
+				let plane = (width as u64 * height as u64).saturating_mul(bit_depth as u64 / 8);
+				(vec![], plane.saturating_mul(channels as u64 - used))
+			}
+		};
+
+		Ok(Self {
+			header,
+			data,
+			offset,
+			starts,
+			unused,
+			pixel_data: vec![],
+			loaded: false,
+			plane: vec![],
+			row: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &PsdHeader {
+		&self.header
+	}
+
+	/// the channels that are used.
+	fn used(&self) -> usize {
+		(self.header.color_mode.channels() + self.header.alpha() as u16) as usize
+	}
+
+	/// the bytes a row of one plane takes up.
+	fn stride(&self) -> usize {
+		self.header.width as usize * (self.header.bit_depth as usize / 8)
+	}
+
+	/// reads the planes that are used.
+	fn load(&mut self) -> Result<(), Error> {
+		let len = match self.header.rle {
+			true => self.starts.last().copied().unwrap_or(0),
+			false => (self.stride() as u64 * self.header.height as u64).saturating_mul(self.used() as u64),
+		};
+		read(self.data, self.offset, len, Some(&mut self.pixel_data))?;
+		self.offset += len;
+		self.loaded = true;
+		Ok(())
+	}
+
+	fn next_row(&mut self) -> Result<(), Error> {
+		if !self.loaded {
+			self.load()?;
+		}
+		let (width, height, y) = (self.header.width as usize, self.header.height as usize, self.y as usize);
+		let (stride, used, bytes) = (self.stride(), self.used(), self.header.bit_depth as usize / 8);
+		let base = self.offset - self.pixel_data.len() as u64;
+		self.row.clear();
+		self.row.try_reserve(width * used).map_err(|_| oom())?;
+		self.row.resize(width * used, 0);
+		for c in 0..used {
+			let i = c * height + y;
+			let data = match self.header.rle {
+				true => {
+					let (start, end) = (self.starts[i] as usize, self.starts[i + 1] as usize);
+					if let Err(reason) = unpack(&self.pixel_data[start..end], stride, &mut self.plane) {
+						return Err(Error::Malformed { offset: base + start as u64, reason });
+					}
+					&self.plane[..]
+				}
+				false => &self.pixel_data[i * stride..(i + 1) * stride],
+			};
+			for (x, sample) in data.chunks_exact(bytes).enumerate() {
+				self.row[x * used + c] = sample[0];
+			}
+		}
+		Ok(())
+	}
+
+	/// reads whatever's left of the composite, including the planes that
+	/// aren't used, and returns the number of bytes the image took up.
+	/// nothing after it is read.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		if !self.loaded {
+			self.load()?;
+		}
+		read(self.data, self.offset, self.unused, None)?;
+		Ok(self.offset + self.unused)
+	}
+}
+
+/// undoes the blending of `v` with white, where `a` is between 0 and 255.
+fn unmatte(v: u8, a: u8) -> u8 {
+	match a {
+		0 | 255 => v,
+		_ => 255 - ((255 - v as u32) * 255 / a as u32).min(255) as u8,
+	}
+}
+
+impl<R: std::io::Read> Iterator for PsdPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		if self.x == 0
+			&& let Err(e) = self.next_row()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let used = self.used();
+		let i = self.x as usize * used;
+		let px = match self.row[i..i + used] {
+			[v] => (v, v, v, 255),
+			[v, a] => (unmatte(v, a), unmatte(v, a), unmatte(v, a), a),
+			[r, g, b] => (r, g, b, 255),
+			[r, g, b, a, ..] => (unmatte(r, a), unmatte(g, a), unmatte(b, a), a),
+			[] => (0, 0, 0, 255),
+		};
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, psd};
+
+	const IMAGE_RGB: &[u8] = include_bytes!("../test/rgb.psd");
+	const IMAGE_RGBA: &[u8] = include_bytes!("../test/rgba.psd");
+
+	fn color(x: u32, y: u32) -> crate::Rgba {
+		((x * 19) as u8, (y * 50) as u8, ((x + y) * 10) as u8, 255)
+	}
+
+	fn decode(image: &[u8]) -> (psd::PsdHeader, Vec<crate::Rgba>) {
+		let mut data = image;
+		let mut pixels = psd::PsdPixels::new(&mut data).expect("error?");
+		let header = pixels.header().clone();
+		let out = (&mut pixels).collect::<Result<Vec<_>, _>>().expect("error?");
+		assert_eq!(out.len(), 13 * 5);
+		assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		(header, out)
+	}
+
+	#[test]
+	fn decode_rgb() {
+		// image resources and layers to skip
+		let (header, pixels) = decode(IMAGE_RGB);
+		assert_eq!((header.width, header.height, header.channels), (13, 5, 3));
+		assert_eq!((header.color_mode, header.bit_depth, header.rle, header.alpha()), (psd::PsdColorMode::RGB, 8, false, false));
+		for (i, &px) in pixels.iter().enumerate() {
+			let (x, y) = (i as u32 % 13, i as u32 / 13);
+			assert_eq!(px, color(x, y), "{x}, {y}");
+		}
+	}
+
+	#[test]
+	fn decode_rle() {
+		// an extra channel after alpha, and colors blended with white
+		let (header, pixels) = decode(IMAGE_RGBA);
+		assert_eq!((header.channels, header.rle, header.alpha()), (5, true, true));
+		for (i, &px) in pixels.iter().enumerate() {
+			let (x, y) = (i as u32 % 13, i as u32 / 13);
+			let a = [0, 255, 51, 102, 153, 204][((x + y * 2) % 6) as usize];
+			let expected = color(x, y);
+			assert_eq!(px.3, a);
+			match a {
+				// nothing's left of the color
+				0 => assert_eq!(px, (255, 255, 255, 0)),
+				255 => assert_eq!(px, expected),
+				_ => {
+					for (v, expected) in [(px.0, expected.0), (px.1, expected.1), (px.2, expected.2)] {
+						assert!(v.abs_diff(expected) <= 255 / a, "{x}, {y}: {v} {expected}");
+					}
+				}
+			}
+		}
+
+		let (header, pixels) = decode(include_bytes!("../test/gray16.psd"));
+		assert_eq!((header.channels, header.color_mode, header.bit_depth), (1, psd::PsdColorMode::Gray, 16));
+		for (i, &px) in pixels.iter().enumerate() {
+			let (x, y) = (i as u32 % 13, i as u32 / 13);
+			let v = if y == 2 { 0x12 } else { ((x * 5000 + y * 300) >> 8) as u8 };
+			assert_eq!(px, (v, v, v, 255), "{x}, {y}");
+		}
+	}
+
+	#[test]
+	fn finish() {
+		for image in [IMAGE_RGB, IMAGE_RGBA] {
+			for n in [0, 1, 13, 30] {
+				let mut data = image;
+				let mut pixels = psd::PsdPixels::new(&mut data).expect("error?");
+				if n > 0 {
+					assert!(pixels.nth(n - 1).is_some());
+				}
+				assert_eq!(pixels.finish().expect("error?"), image.len() as u64, "{n}");
+			}
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = psd::PsdPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".psd")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("psb", Error::InvalidHeader { offset: 4, .. });
+		case!("bad_channels", Error::InvalidHeader { offset: 12, .. });
+		case!("zero_height", Error::InvalidHeader { offset: 14, .. });
+		case!("zero_width", Error::InvalidHeader { offset: 18, .. });
+		case!("bad_depth", Error::InvalidHeader { offset: 22, .. });
+		case!("cmyk", Error::InvalidHeader { offset: 24, .. });
+		case!("zip", Error::InvalidHeader { offset: 56, .. });
+		case!("long_section", Error::UnexpectedEof { offset: 253 });
+		case!("truncated", Error::UnexpectedEof { offset: 150 });
+		case!("truncated_lengths", Error::UnexpectedEof { offset: 60 });
+		case!("truncated_rle", Error::UnexpectedEof { offset: 120 });
+		case!("long_run", Error::Malformed { offset: 90, .. });
+		case!("short_row", Error::Malformed { offset: 90, .. });
+		case!("truncated_unused", Error::UnexpectedEof { offset: 450 });
+
+		assert!(decode(IMAGE_RGB).is_ok());
+		assert!(decode(IMAGE_RGBA).is_ok());
+	}
+}
@@ -1,17 +1,52 @@
+#[cfg(feature = "std")]
+use core::ops::ControlFlow;
+use core::task::Poll;
+
+use crate::Error;
+
+#[cfg(feature = "futures-io")]
+mod asynchronous;
+#[cfg(feature = "std")]
+mod buffered;
+mod chunks;
+#[cfg(feature = "std")]
+mod lint;
+#[cfg(feature = "std")]
+mod salvage;
+mod slice;
+
+#[cfg(feature = "futures-io")]
+pub use asynchronous::{decode_async, encode_async};
+#[cfg(feature = "std")]
+pub use buffered::{BufPixels, decode_buffered};
+pub use chunks::{BytePixels, ChunkPixels};
+#[cfg(feature = "std")]
+pub use lint::{LintFinding, LintKind, LintReport, LintSeverity, lint};
+#[cfg(feature = "std")]
+pub use salvage::{DamageRegion, SalvageOptions, Salvaged, salvage};
+pub use slice::{SlicePixels, decode_slice, encode_slice};
+
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum QoiHeaderChannels {
 	RGB,
 	RGBA,
 }
 
+/// all qoi says about color. it has nowhere to put an icc profile, so one
+/// is dropped on encoding, and an srgb image needs none.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum QoiHeaderColorspace {
+	/// every channel is linear.
 	Linear,
+	/// srgb color, with linear alpha.
 	SRGB,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct QoiHeader {
 	pub width: u32,
 	pub height: u32,
@@ -19,8 +54,45 @@ pub struct QoiHeader {
 	pub colorspace: QoiHeaderColorspace,
 }
 
+impl QoiHeader {
+	/// a header that's fit to encode with: neither side is 0, and there
+	/// aren't more pixels than the spec recommends decoders take.
+	pub fn new(width: u32, height: u32, channels: QoiHeaderChannels, colorspace: QoiHeaderColorspace) -> Result<Self, Error> {
+		let header = Self { width, height, channels, colorspace };
+		header.check()?;
+		Ok(header)
+	}
+
+	/// the checks `new` makes, for a header that was put together by hand.
+	fn check(&self) -> Result<(), Error> {
+		if self.width == 0 {
+			return Err(Error::InvalidHeader { offset: 4, field: "width" });
+		}
+		if self.height == 0 {
+			return Err(Error::InvalidHeader { offset: 8, field: "height" });
+		}
+		let pixels = self.width as u64 * self.height as u64;
+		if pixels > PIXELS_MAX {
+			return Err(Error::TooLarge { what: "pixels", value: pixels, limit: PIXELS_MAX });
+		}
+		Ok(())
+	}
+}
+
+impl crate::Dimensions for QoiHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
 const MAGIC: u32 = u32::from_be_bytes(*b"qoif");
 
+/// the spec recommends decoders reject images with more pixels than this.
+const PIXELS_MAX: u64 = 400_000_000;
+
 const OP_RGB: u8 = 0b11111110;
 const OP_RGBA: u8 = 0b11111111;
 const OP_INDEX: u8 = 0b00_000000;
@@ -30,11 +102,105 @@ const OP_RUN: u8 = 0b11_000000;
 
 const MASK: u8 = 0b11_000000;
 
+const END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
 #[inline]
 fn hash(px: (u8, u8, u8, u8)) -> usize {
 	(px.0 as usize) * 3 + (px.1 as usize) * 5 + (px.2 as usize) * 7 + (px.3 as usize) * 11
 }
 
+/// length of the op starting with `b0`, in bytes.
+#[inline]
+fn op_len(b0: u8) -> usize {
+	match b0 {
+		OP_RGB => 4,
+		OP_RGBA => 5,
+		c if (c & MASK) == OP_LUMA => 2,
+		_ => 1,
+	}
+}
+
+/// updates `px` with any op except `OP_RUN`. `op` holds all of its bytes.
+#[inline]
+fn apply(op: &[u8], px: &mut crate::Rgba, array: &[crate::Rgba; 64]) {
+	let b0 = op[0];
+	match b0 {
+		OP_RGB => {
+			px.0 = op[1];
+			px.1 = op[2];
+			px.2 = op[3];
+
+		}
+		OP_RGBA => {
+			px.0 = op[1];
+			px.1 = op[2];
+			px.2 = op[3];
+			px.3 = op[4];
+
+		}
+		c if (c & MASK) == OP_INDEX => {
+			let index = c & 0b00_111111;
+			*px = array[index as usize];
+
+		}
+		c if (c & MASK) == OP_DIFF => {
+			let r_diff = ((c >> 4) & 0b11) as i8 - 2;
+			let g_diff = ((c >> 2) & 0b11) as i8 - 2;
+			let b_diff = (c & 0b11) as i8 - 2;
+
+			px.0 = px.0.wrapping_add_signed(r_diff);
+			px.1 = px.1.wrapping_add_signed(g_diff);
+			px.2 = px.2.wrapping_add_signed(b_diff);
+
+		}
+		_ => {
+			let b1 = op[1];
+
+			let g_diff = (b0 & 0b111111) as i8 - 32;
+
+			let dr_dg = (b1 >> 4) & 0b1111;
+			let db_dg = b1 & 0b1111;
+
+			let r_diff = (dr_dg as i8 + g_diff) - 8;
+			let b_diff = (db_dg as i8 + g_diff) - 8;
+
+			px.0 = px.0.wrapping_add_signed(r_diff);
+			px.1 = px.1.wrapping_add_signed(g_diff);
+			px.2 = px.2.wrapping_add_signed(b_diff);
+
+		}
+	}
+}
+
+/// length of the shortest op an encoder could have used for `px`, given the
+/// previous pixel and whether the encoder's index holds `px`.
+#[cfg(feature = "std")]
+fn best_len(px: crate::Rgba, prev: crate::Rgba, indexed: bool) -> usize {
+	if px == prev || indexed {
+		return 1;
+	}
+	if px.3 != prev.3 {
+		return 5;
+	}
+
+	let r_diff = px.0.wrapping_sub(prev.0) as i8;
+	let g_diff = px.1.wrapping_sub(prev.1) as i8;
+	let b_diff = px.2.wrapping_sub(prev.2) as i8;
+
+	if [r_diff, g_diff, b_diff].iter().all(|x| (-2..=1).contains(x)) {
+		1
+	}
+	else if (-32..=31).contains(&g_diff)
+		&& (-8..=7).contains(&r_diff.wrapping_sub(g_diff))
+		&& (-8..=7).contains(&b_diff.wrapping_sub(g_diff))
+		{
+		2
+	}
+	else {
+		4
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct Qoi;
 
@@ -44,237 +210,965 @@ impl Default for Qoi {
 	}
 }
 
-impl crate::Format for Qoi {
-	type Header = QoiHeader;
+/// options for `Format::decode_with_options`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QoiOptions {
+	/// decodes with a strict `QoiDecoder`, so a missing end marker or bytes
+	/// after it are errors, after the last pixel.
+	pub strict: bool,
+}
 
-	fn decode(self, data: &mut impl std::io::Read) -> Option<(Self::Header, impl Iterator<Item = (u8, u8, u8, u8)>)> {
-		
-		#[inline]
-		fn read<const N: usize>(data: &mut impl std::io::Read) -> Option<[u8; N]> {
-			let mut buf = [0; N];
-			data.read_exact(&mut buf).ok()?;
-			Some(buf)
+/// incremental qoi decoder.
+///
+/// all decoding state lives here, including the bytes of a partially read
+/// header or op, so when the reader fails with `WouldBlock` the decoder
+/// returns `Poll::Pending` and can be called again with the same reader once
+/// more data has arrived.
+///
+/// by default it's lenient, as suits damaged files: pixels stop at the
+/// image's end whatever comes after, and the end marker is only checked by
+/// `poll_end`. `set_strict` checks it once the last pixel is out, and
+/// rejects any bytes after it too.
+#[derive(Debug, Clone)]
+pub struct QoiDecoder {
+	header: Option<QoiHeader>,
+	buf: [u8; 14],
+	len: usize,
+	offset: u64,
+	px: crate::Rgba,
+	array: [crate::Rgba; 64],
+	total: u64,
+	run: u8,
+	/// first byte of the last op read.
+	op: u8,
+	end: bool,
+	strict: bool,
+}
+
+impl Default for QoiDecoder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl QoiDecoder {
+	pub fn new() -> Self {
+		Self {
+			header: None,
+			buf: [0; 14],
+			len: 0,
+			offset: 0,
+			px: (0, 0, 0, 255),
+			array: [(0, 0, 0, 0); 64],
+			total: 0,
+			run: 0,
+			op: 0,
+			end: false,
+			strict: false,
+		}
+	}
+
+	/// a decoder for a stream that starts `offset` bytes into the reader,
+	/// so errors and `offset()` are reported relative to the reader instead.
+	pub fn with_offset(offset: u64) -> Self {
+		Self {
+			offset,
+			..Self::new()
 		}
+	}
+
+	/// makes `QoiPixels` and `feed` check that the end marker follows the
+	/// last pixel and ends the stream, yielding `MissingEndMarker` or
+	/// `TrailingData` after it if not. images inside something else, with
+	/// data after them, can't be decoded strictly.
+	pub fn set_strict(&mut self, strict: bool) {
+		self.strict = strict;
+	}
 
-		// read header
+	/// the header, once it has been read.
+	pub fn header(&self) -> Option<&QoiHeader> {
+		self.header.as_ref()
+	}
 
-		let magic = u32::from_be_bytes(read(data)?);
+	/// pixels left to decode.
+	pub fn remaining(&self) -> u64 {
+		self.total
+	}
+
+	/// bytes consumed so far, not counting a partially read op.
+	pub fn offset(&self) -> u64 {
+		self.offset
+	}
+
+	/// buffers bytes until `self.buf[..n]` is full.
+	fn fill(&mut self, data: &mut impl crate::io::Read, n: usize) -> Result<Poll<()>, Error> {
+		while self.len < n {
+			match data.read(&mut self.buf[self.len..n])? {
+				Poll::Ready(0) => return Err(Error::UnexpectedEof { offset: self.offset.saturating_add(self.len as u64) }),
+				// a reader claiming to have read more than it was given is lying
+				Poll::Ready(x) => self.len += x.min(n - self.len),
+				Poll::Pending => return Ok(Poll::Pending),
+			}
+		}
+		Ok(Poll::Ready(()))
+	}
+
+	/// marks the buffered bytes as consumed.
+	fn consume(&mut self) {
+		// offsets saturate rather than overflow, since `with_offset` can
+		// start anywhere
+		self.offset = self.offset.saturating_add(self.len as u64);
+		self.len = 0;
+	}
+
+	/// reads the header, if that hasn't happened yet.
+	pub fn poll_header(&mut self, data: &mut impl crate::io::Read) -> Result<Poll<QoiHeader>, Error> {
+		if let Some(header) = &self.header {
+			return Ok(Poll::Ready(header.clone()));
+		}
+
+		if self.fill(data, 14)?.is_pending() {
+			return Ok(Poll::Pending);
+		}
+
+		let buf = self.buf;
+		let offset = self.offset;
+
+		let magic = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
 		
 		if magic != MAGIC {
-			return None;
+			return Err(Error::InvalidMagic { offset });
 		}
 
-		let width = u32::from_be_bytes(read(data)?);
-		let height = u32::from_be_bytes(read(data)?);
+		let width = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+		let height = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
 
-		if width == 0 || height == 0 {
-			None?;
+		if width == 0 {
+			return Err(Error::InvalidHeader { offset: offset.saturating_add(4), field: "width" });
+		}
+		if height == 0 {
+			return Err(Error::InvalidHeader { offset: offset.saturating_add(8), field: "height" });
 		}
-
-		let [channels, colorspace] = read(data)?;
 
 		let header = QoiHeader {
 			width,
 			height,
-			channels: match channels {
+			channels: match buf[12] {
 				3 => QoiHeaderChannels::RGB,
 				4 => QoiHeaderChannels::RGBA,
-				_ => None?,
+				_ => return Err(Error::InvalidHeader { offset: offset.saturating_add(12), field: "channels" }),
 			},
-			colorspace: match colorspace {
+			colorspace: match buf[13] {
 				0 => QoiHeaderColorspace::SRGB,
 				1 => QoiHeaderColorspace::Linear,
-				_ => None?,
+				_ => return Err(Error::InvalidHeader { offset: offset.saturating_add(13), field: "colorspace" }),
 			},
 		};
 
-		let mut px = (0, 0, 0, 255);
-		let mut array = [(0, 0, 0, 0); 64];
+		self.consume();
 
-		let mut total = width * height;
+		self.total = width as u64 * height as u64;
+		self.header = Some(header.clone());
 
-		let mut run = 0;
+		Ok(Poll::Ready(header))
+	}
+
+	/// decodes the next pixel, reading the header first if needed.
+	/// `Poll::Ready(None)` means the image is complete.
+	pub fn poll_pixel(&mut self, data: &mut impl crate::io::Read) -> Result<Poll<Option<crate::Rgba>>, Error> {
+		if self.header.is_none() && self.poll_header(data)?.is_pending() {
+			return Ok(Poll::Pending);
+		}
 
-		let iter = core::iter::from_fn(move || {
-			if total == 0 {
-				None?;
+		if self.total == 0 {
+			return Ok(Poll::Ready(None));
+		}
+
+		if self.run > 0 {
+			self.run -= 1;
+			self.total -= 1;
+
+			return Ok(Poll::Ready(Some(self.px)));
+		}
+
+		if self.fill(data, 1)?.is_pending() {
+			return Ok(Poll::Pending);
+		}
+
+		let b0 = self.buf[0];
+
+		let n = op_len(b0);
+
+		if self.fill(data, n)?.is_pending() {
+			return Ok(Poll::Pending);
+		}
+
+		let buf = self.buf;
+		let px = self.decode_op(&buf[..n])?;
+		self.consume();
+		Ok(Poll::Ready(Some(px)))
+	}
+
+	/// decodes the op `op` holds all of, which starts at `self.offset`, and
+	/// returns its pixel. the caller moves the offset past it.
+	#[inline]
+	fn decode_op(&mut self, op: &[u8]) -> Result<crate::Rgba, Error> {
+		let b0 = op[0];
+		if (b0 & MASK) == OP_RUN && b0 != OP_RGB && b0 != OP_RGBA {
+			let run = b0 & 0b111111;
+			if run as u64 >= self.total {
+				return Err(Error::RunOverrun { offset: self.offset });
 			}
+			self.run = run;
+		}
+		else {
+			apply(op, &mut self.px, &self.array);
+		}
+
+		self.op = b0;
 
-			if run > 0 {
-				run -= 1;
-				total -= 1;
+		self.array[hash(self.px) & 63] = self.px;
+
+		self.total -= 1;
+		Ok(self.px)
+	}
 
-				return Some(px);
+	/// reads and checks the 8 byte end marker, decoding any pixels that are left first.
+	/// the reader is left right after it, so any following data is untouched.
+	pub fn poll_end(&mut self, data: &mut impl crate::io::Read) -> Result<Poll<()>, Error> {
+		if self.end {
+			return Ok(Poll::Ready(()));
+		}
+
+		// skip whatever pixels are left
+		loop {
+			match self.poll_pixel(data)? {
+				Poll::Ready(Some(_)) => {}
+				Poll::Ready(None) => break,
+				Poll::Pending => return Ok(Poll::Pending),
 			}
+		}
 
-			let [b0] = read(data)?;
+		match self.fill(data, 8) {
+			Ok(Poll::Ready(())) => {}
+			Ok(Poll::Pending) => return Ok(Poll::Pending),
+			Err(Error::UnexpectedEof { .. }) => return Err(Error::MissingEndMarker { offset: self.offset }),
+			Err(e) => return Err(e),
+		}
 
-			match b0 {
-				OP_RGB => {
-					let [r, g, b] = read(data)?;
-					px.0 = r;
-					px.1 = g;
-					px.2 = b;
+		if self.buf[..8] != END {
+			return Err(Error::MissingEndMarker { offset: self.offset });
+		}
 
-				}
-				OP_RGBA => {
-					let [r, g, b, a] = read(data)?;
-					px.0 = r;
-					px.1 = g;
-					px.2 = b;
-					px.3 = a;
+		self.consume();
+		self.end = true;
 
-				}
-				c if (c & MASK) == OP_INDEX => {
-					let index = c & 0b00_111111;
-					px = array[index as usize];
+		Ok(Poll::Ready(()))
+	}
 
-				}
-				c if (c & MASK) == OP_DIFF => {
-					let r_diff = ((c >> 4) & 0b11) as i8 - 2;
-					let g_diff = ((c >> 2) & 0b11) as i8 - 2;
-					let b_diff = (c & 0b11) as i8 - 2;
+	/// decodes whatever pixels `data` completes, and then the end marker.
+	/// bytes of an op that's cut off are kept for the next call, so an image
+	/// can be fed as it arrives, in pieces of any size.
+	///
+	/// bytes are only used up as pixels are pulled, and `DecodedPixels::rest`
+	/// has any that weren't. nothing here blocks or needs a `Read`, so bytes
+	/// from an async reader can be handed over as each read finishes.
+	pub fn feed<'b>(&mut self, data: &'b [u8]) -> DecodedPixels<'_, 'b> {
+		DecodedPixels {
+			decoder: self,
+			data: Fed(data),
+			failed: false,
+		}
+	}
 
-					px.0 = px.0.wrapping_add_signed(r_diff);
-					px.1 = px.1.wrapping_add_signed(g_diff);
-					px.2 = px.2.wrapping_add_signed(b_diff);
+	/// whether the end marker has been read.
+	pub fn is_finished(&self) -> bool {
+		self.end
+	}
 
-				}
-				c if (c & MASK) == OP_LUMA => {
-					let [b1] = read(data)?;
+	/// like `poll_end`, but also requires the reader to be exhausted after the
+	/// end marker, as it should be for a standalone file.
+	pub fn poll_finish(&mut self, data: &mut impl crate::io::Read) -> Result<Poll<()>, Error> {
+		if self.poll_end(data)?.is_pending() {
+			return Ok(Poll::Pending);
+		}
 
-					let g_diff = (b0 & 0b111111) as i8 - 32;
+		match self.fill(data, 1) {
+			Ok(Poll::Ready(())) => Err(Error::TrailingData { offset: self.offset }),
+			Ok(Poll::Pending) => Ok(Poll::Pending),
+			Err(Error::UnexpectedEof { .. }) => Ok(Poll::Ready(())),
+			Err(e) => Err(e),
+		}
+	}
+}
 
-					let dr_dg = (b1 >> 4) & 0b1111;
-					let db_dg = b1 & 0b1111;
+/// turns a pending poll into a `WouldBlock` error, for callers that
+/// expect a blocking reader.
+#[cfg(feature = "std")]
+pub(crate) fn ready<T>(poll: Result<Poll<T>, Error>) -> Result<T, Error> {
+	match poll? {
+		Poll::Ready(x) => Ok(x),
+		Poll::Pending => Err(Error::Io(std::io::ErrorKind::WouldBlock.into())),
+	}
+}
 
-					let r_diff = (dr_dg as i8 + g_diff) - 8;
-					let b_diff = (db_dg as i8 + g_diff) - 8;
+/// pixels of a qoi image, decoded from the reader as they're requested.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+#[cfg(feature = "std")]
+pub struct QoiPixels<'a, R> {
+	decoder: QoiDecoder,
+	header: QoiHeader,
+	data: &'a mut R,
+	start: u64,
+	failed: bool,
+}
 
-					px.0 = px.0.wrapping_add_signed(r_diff);
-					px.1 = px.1.wrapping_add_signed(g_diff);
-					px.2 = px.2.wrapping_add_signed(b_diff);
+#[cfg(feature = "std")]
+impl<'a, R: std::io::Read> QoiPixels<'a, R> {
+	/// reads the header with `decoder`, and returns its pixels.
+	pub fn new(mut decoder: QoiDecoder, data: &'a mut R) -> Result<Self, Error> {
+		let start = decoder.offset();
+		let header = ready(decoder.poll_header(data))?;
+		Ok(Self {
+			decoder,
+			header,
+			data,
+			start,
+			failed: false,
+		})
+	}
 
-				}
-				c if (c & MASK) == OP_RUN => {
-					run = c & 0b111111;
+	pub fn header(&self) -> &QoiHeader {
+		&self.header
+	}
+
+	/// skips any pixels that are left, checks the end marker, and returns the
+	/// number of bytes the image took up, header and end marker included.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		ready(self.decoder.poll_end(self.data))?;
+		Ok(self.decoder.offset() - self.start)
+	}
 
+	/// skips `n` pixels, taking runs in a tight loop, and returns how many
+	/// there were, fewer only if the image ended. an error among them ends it
+	/// too, and is returned.
+	pub fn skip_pixels(&mut self, n: u64) -> Result<u64, Error> {
+		if n == 0 {
+			return Ok(0);
+		}
+		let mut skipped = 0;
+		let (ControlFlow::Continue(result) | ControlFlow::Break(result)) = self.walk(Ok(()), |ok, px| {
+			if let Err(e) = px {
+				return ControlFlow::Break(Err(e));
+			}
+			skipped += 1;
+			if skipped == n { ControlFlow::Break(ok) } else { ControlFlow::Continue(ok) }
+		});
+		result.map(|()| skipped)
+	}
+
+	/// the error a strict decoder finds after the last pixel, if any.
+	fn end(&mut self) -> Option<Error> {
+		if !self.decoder.strict {
+			return None;
+		}
+		let e = ready(self.decoder.poll_finish(self.data)).err()?;
+		self.failed = true;
+		Some(e)
+	}
+
+	/// feeds pixels to `f` until it breaks or the image ends. runs are handed
+	/// out in a tight loop rather than going back through `poll_pixel` for
+	/// each pixel. everything is left consistent after a break, so iteration
+	/// can carry on with `next`.
+	fn walk<B>(&mut self, mut acc: B, mut f: impl FnMut(B, Result<crate::Rgba, Error>) -> ControlFlow<B, B>) -> ControlFlow<B, B> {
+		while !self.failed {
+			// a run never goes past the end of the image, so `total` can't underflow
+			let decoder = &mut self.decoder;
+			while decoder.run > 0 {
+				decoder.run -= 1;
+				decoder.total -= 1;
+				acc = f(acc, Ok(decoder.px))?;
+			}
+
+			acc = match ready(self.decoder.poll_pixel(self.data)) {
+				Ok(Some(px)) => f(acc, Ok(px))?,
+				Ok(None) => match self.end() {
+					Some(e) => f(acc, Err(e))?,
+					None => break,
+				},
+				Err(e) => {
+					self.failed = true;
+					f(acc, Err(e))?
 				}
-				_ => None?,
+			};
+		}
+		ControlFlow::Continue(acc)
+	}
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> Iterator for QoiPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+		match ready(self.decoder.poll_pixel(self.data)) {
+			Ok(Some(px)) => Some(Ok(px)),
+			Ok(None) => self.end().map(Err),
+			Err(e) => {
+				self.failed = true;
+				Some(Err(e))
 			}
+		}
+	}
 
-			array[hash(px) & 63] = px;
+	/// at most the pixels the header has left, and an error after them if
+	/// strict. the lower bound is only what's left of a run, since `collect`
+	/// and friends allocate for it: a hostile header can claim millions of
+	/// pixels in a file of a few bytes, so it isn't trusted for more than the
+	/// upper bound.
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		if self.failed {
+			return (0, Some(0));
+		}
+		let upper = self.decoder.total.checked_add((self.decoder.strict && !self.decoder.end) as u64).and_then(|n| n.try_into().ok());
+		(self.decoder.run as usize, upper)
+	}
 
-			total -= 1;
-			Some(px)
+	fn nth(&mut self, mut n: usize) -> Option<Self::Item> {
+		let (ControlFlow::Continue(x) | ControlFlow::Break(x)) = self.walk(None, |_, px| {
+			if n == 0 {
+				return ControlFlow::Break(Some(px));
+			}
+			n -= 1;
+			ControlFlow::Continue(None)
 		});
+		x
+	}
 
-		Some((header, iter))
+	fn fold<B, F: FnMut(B, Self::Item) -> B>(mut self, init: B, mut f: F) -> B {
+		let (ControlFlow::Continue(x) | ControlFlow::Break(x)) = self.walk(init, |acc, px| ControlFlow::Continue(f(acc, px)));
+		x
 	}
+}
 
-	fn encode(self, data: impl Iterator<Item = (u8, u8, u8, u8)>, header: Self::Header, out: &mut impl std::io::Write) {
+/// bytes handed to `QoiDecoder::feed`, which block once they run out
+/// rather than ending.
+#[derive(Debug)]
+struct Fed<'a>(&'a [u8]);
 
-		#[inline]
-		fn write<const N: usize>(out: &mut impl std::io::Write, input: [u8; N]) {
-			_ = out.write(&input);
+impl crate::io::Read for Fed<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<Poll<usize>, Error> {
+		if self.0.is_empty() && !buf.is_empty() {
+			return Ok(Poll::Pending);
 		}
-		
-		write(out, MAGIC.to_be_bytes());
+		Ok(Poll::Ready(crate::io::take(&mut self.0, buf)))
+	}
+}
+
+/// pixels decoded from the bytes handed to `QoiDecoder::feed`.
+///
+/// the iterator ends when the bytes run out, or once the image and its end
+/// marker are done. the first error is yielded once, after which it ends.
+#[derive(Debug)]
+pub struct DecodedPixels<'a, 'b> {
+	decoder: &'a mut QoiDecoder,
+	data: Fed<'b>,
+	failed: bool,
+}
+
+impl<'b> DecodedPixels<'_, 'b> {
+	/// the bytes that haven't been used up. once the end marker is read,
+	/// these are whatever came after it.
+	pub fn rest(&self) -> &'b [u8] {
+		self.data.0
+	}
+}
+
+impl Iterator for DecodedPixels<'_, '_> {
+	type Item = Result<crate::Rgba, Error>;
 
-		write(out, header.width.to_be_bytes());
-		write(out, header.height.to_be_bytes());
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+		let result = match self.decoder.poll_pixel(&mut self.data) {
+			Ok(Poll::Ready(Some(px))) => return Some(Ok(px)),
+			Ok(Poll::Ready(None)) => match self.decoder.poll_end(&mut self.data) {
+				Ok(Poll::Ready(())) if self.decoder.strict && !self.data.0.is_empty() => Err(Error::TrailingData { offset: self.decoder.offset }),
+				result => result.map(|_| ()),
+			},
+			Ok(Poll::Pending) => Ok(()),
+			Err(e) => Err(e),
+		};
+		match result {
+			Ok(()) => None,
+			Err(e) => {
+				self.failed = true;
+				Some(Err(e))
+			}
+		}
+	}
 
-		write(
+	/// like `QoiPixels`, with room for an error from the end marker. there's
+	/// no telling until the header has been read.
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		if self.failed || self.decoder.end {
+			return (0, Some(0));
+		}
+		if self.decoder.header.is_none() {
+			return (0, None);
+		}
+		let upper = self.decoder.total.checked_add(1).and_then(|n| n.try_into().ok());
+		(self.decoder.run as usize, upper)
+	}
+}
+
+/// pixels for `Format::decode`, which end at the first error instead of
+/// yielding it.
+#[cfg(feature = "std")]
+struct Pixels<'a, R>(QoiPixels<'a, R>);
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.0.size_hint()
+	}
+
+	fn fold<B, F: FnMut(B, Self::Item) -> B>(mut self, init: B, mut f: F) -> B {
+		let (ControlFlow::Continue(x) | ControlFlow::Break(x)) = self.0.walk(init, |acc, px| match px {
+			Ok(px) => ControlFlow::Continue(f(acc, px)),
+			Err(_) => ControlFlow::Break(acc),
+		});
+		x
+	}
+}
+
+/// decodes the image that starts `offset` bytes into `data`.
+///
+/// errors carry offsets into `data` rather than into the image, and
+/// `QoiPixels::finish` gives the image's length, so the next record in an
+/// archive starts at `offset + length`.
+#[cfg(feature = "std")]
+pub fn decode_at<R: std::io::Read + std::io::Seek>(data: &mut R, offset: u64) -> Result<(QoiHeader, QoiPixels<'_, R>), Error> {
+	data.seek(std::io::SeekFrom::Start(offset))?;
+	let pixels = QoiPixels::new(QoiDecoder::with_offset(offset), data)?;
+	Ok((pixels.header().clone(), pixels))
+}
+
+/// decodes with a strict decoder, so the pixels end with an error if the
+/// end marker doesn't follow them, or anything follows it.
+#[cfg(feature = "std")]
+pub fn decode_strict<R: std::io::Read>(data: &mut R) -> Result<(QoiHeader, QoiPixels<'_, R>), Error> {
+	let mut decoder = QoiDecoder::new();
+	decoder.set_strict(true);
+	let pixels = QoiPixels::new(decoder, data)?;
+	Ok((pixels.header().clone(), pixels))
+}
+
+/// encodes an image whose height isn't known until `data` runs out.
+///
+/// a header with a placeholder height is written first, then patched once
+/// every pixel has been counted, so `out` must be seekable; that is checked
+/// before anything is written. the pixel count must be a non-zero multiple
+/// of `width`. returns the final header.
+#[cfg(feature = "std")]
+pub fn encode_unknown_height<W: std::io::Write + std::io::Seek>(
+	width: u32,
+	channels: QoiHeaderChannels,
+	colorspace: QoiHeaderColorspace,
+	data: impl Iterator<Item = crate::Rgba>,
+	out: &mut W,
+) -> Result<QoiHeader, Error> {
+	let start = out.stream_position().map_err(|e| {
+		std::io::Error::new(e.kind(), format!("encode_unknown_height needs a seekable writer: {e}"))
+	})?;
+
+	if width == 0 {
+		return Err(Error::InvalidHeader { offset: 4, field: "width" });
+	}
+
+	let mut header = QoiHeader {
+		width,
+		height: u32::MAX,
+		channels,
+		colorspace,
+	};
+
+	// the placeholder is taller than any image, so every pixel goes in
+	let mut count = 0u64;
+	let mut counting = crate::Counting::new(&mut *out);
+	let result = QoiEncoder::unchecked(&header, &mut counting).and_then(|mut encoder| {
+		for px in data {
+			count += 1;
+			encoder.push(px)?;
+		}
+		encoder.end()
+	});
+	let written = counting.finish()?;
+	result?;
+
+	if count == 0 || !count.is_multiple_of(width as u64) {
+		return Err(Error::PixelCountMismatch {
+			expected: count.div_ceil(width as u64).max(1) * width as u64,
+			got: count,
+		});
+	}
+
+	header.height = (count / width as u64) as u32;
+
+	out.seek(std::io::SeekFrom::Start(start.saturating_add(8)))?;
+	crate::write_all(out, &header.height.to_be_bytes())?;
+	out.seek(std::io::SeekFrom::Start(start.saturating_add(written)))?;
+
+	Ok(header)
+}
+
+/// push-based qoi encoder, for pixels that arrive a few at a time instead
+/// of from an iterator. the run and index state lives here between pushes,
+/// and `Qoi::encode` and `Writer` both drive one too.
+///
+/// once a write fails, every call after it does too, since part of an op
+/// may have been written.
+#[derive(Debug)]
+pub struct QoiEncoder<W> {
+	out: W,
+	prev: crate::Rgba,
+	array: [crate::Rgba; 64],
+	run: u8,
+	width: u32,
+	total: u64,
+	/// pixels that still fit in the image.
+	remaining: u64,
+	failed: bool,
+}
+
+impl<W: crate::io::Write> QoiEncoder<W> {
+	/// writes the header, once it's passed the checks `QoiHeader::new` makes.
+	pub fn new(header: &QoiHeader, out: W) -> Result<Self, Error> {
+		header.check()?;
+		Self::unchecked(header, out)
+	}
+
+	/// writes the header, whatever's in it, as `encode_unknown_height` needs
+	/// for its placeholder.
+	fn unchecked(header: &QoiHeader, mut out: W) -> Result<Self, Error> {
+		let mut buf = [0; 14];
+		buf[..4].copy_from_slice(&MAGIC.to_be_bytes());
+		buf[4..8].copy_from_slice(&header.width.to_be_bytes());
+		buf[8..12].copy_from_slice(&header.height.to_be_bytes());
+		buf[12] = match header.channels {
+			QoiHeaderChannels::RGB => 3,
+			QoiHeaderChannels::RGBA => 4,
+		};
+		buf[13] = match header.colorspace {
+			QoiHeaderColorspace::SRGB => 0,
+			QoiHeaderColorspace::Linear => 1,
+		};
+		out.write_all(&buf)?;
+
+		let total = header.width as u64 * header.height as u64;
+		Ok(Self {
 			out,
-			[
-				match header.channels {
-					QoiHeaderChannels::RGB => 3,
-					QoiHeaderChannels::RGBA => 4,
-				},
-				match header.colorspace {
-					QoiHeaderColorspace::SRGB => 0,
-					QoiHeaderColorspace::Linear => 1,
-				},
-			],
-		);
+			prev: (0, 0, 0, 255),
+			array: [(0, 0, 0, 0); 64],
+			run: 0,
+			width: header.width,
+			total,
+			remaining: total,
+			failed: false,
+		})
+	}
+
+	/// pixels the header has room for that haven't been pushed yet.
+	pub fn remaining(&self) -> u64 {
+		self.remaining
+	}
 
-		let mut px_prev = (0, 0, 0, 255);
-		let mut array = [(0, 0, 0, 0); 64];
+	fn check(&self, n: u64) -> Result<(), Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier write failed" });
+		}
+		if n > self.remaining {
+			let got = (self.total - self.remaining).saturating_add(n);
+			return Err(Error::PixelCountMismatch { expected: self.total, got });
+		}
+		Ok(())
+	}
 
-		let mut run = 0;
+	/// encodes the next pixel. one more than the header has room for is a
+	/// `PixelCountMismatch`.
+	pub fn push_pixel(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		self.check(1)?;
+		let result = self.push(px);
+		self.failed = result.is_err();
+		result
+	}
 
-		for px in data.take(header.width as usize * header.height as usize) {
+	/// encodes a row, which has to be exactly as wide as the image. rows
+	/// needn't start at the start of one, if pixels were pushed before.
+	pub fn push_row(&mut self, row: &[crate::Rgba]) -> Result<(), Error> {
+		if row.len() as u64 != self.width as u64 {
+			return Err(Error::BufferSize { expected: self.width as u64, got: row.len() as u64 });
+		}
+		self.check(row.len() as u64)?;
+		let result = row.iter().try_for_each(|&px| self.push(px));
+		self.failed = result.is_err();
+		result
+	}
 
-			if px == px_prev {
-				run += 1;
-				if run == 62 {
-					write(out, [OP_RUN | (run - 1)]);
-					run = 0;
-				}
+	/// writes the end of the image, and hands back the writer. an image
+	/// that's short of pixels is a `PixelCountMismatch`, and nothing is
+	/// written.
+	pub fn finish(mut self) -> Result<W, Error> {
+		self.check(0)?;
+		if self.remaining > 0 {
+			return Err(Error::PixelCountMismatch { expected: self.total, got: self.total - self.remaining });
+		}
+		self.end()?;
+		Ok(self.out)
+	}
+
+	/// `push_pixel`, without its checks, for callers that keep count.
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		let out = &mut self.out;
+		let px_prev = self.prev;
+
+		self.remaining = self.remaining.saturating_sub(1);
+		self.prev = px;
 
+		if px == px_prev {
+			self.run += 1;
+			if self.run == 62 {
+				out.write_all(&[OP_RUN | (self.run - 1)])?;
+				self.run = 0;
 			}
-			else {
-				if run > 0 {
-					write(out, [OP_RUN | (run - 1)]);
-					run = 0;
-				}
+			return Ok(());
+		}
 
-				let index = hash(px) & 63;
-				if array[index] == px {
-					write(out, [OP_INDEX | index as u8]);
+		if self.run > 0 {
+			out.write_all(&[OP_RUN | (self.run - 1)])?;
+			self.run = 0;
+		}
 
-				}
-				else if px.3 == px_prev.3 {
-					array[index] = px;
-					write(out, [OP_RGBA, px.0, px.1, px.2, px.3]);
+		let index = hash(px) & 63;
+		if self.array[index] == px {
+			out.write_all(&[OP_INDEX | index as u8])?;
+			return Ok(());
+		}
+		self.array[index] = px;
 
-				}
-				else {
-					let r_diff = px.0 as i8 - px_prev.0 as i8;
-					let g_diff = px.1 as i8 - px_prev.1 as i8;
-					let b_diff = px.2 as i8 - px_prev.2 as i8;
-
-					let r_diff_vg = r_diff - g_diff;
-					let b_diff_vg = b_diff - g_diff;
-
-					if (-2..=1).contains(&r_diff)
-						&& (-2..=1).contains(&g_diff)
-						&& (-2..=1).contains(&b_diff)
-						{
-						let r = ((r_diff + 2) as u8) << 4;
-						let g = ((g_diff + 2) as u8) << 2;
-						let b = (b_diff + 2) as u8;
-						write(out, [OP_DIFF | r | g | b]);
-
-					}
-					else if (-8..=7).contains(&r_diff_vg)
-						&& (-32..=31).contains(&g_diff)
-						&& (-8..=7).contains(&b_diff_vg)
-						{
-						let r = ((r_diff_vg + 8) as u8) << 4;
-						let g = (g_diff + 32) as u8;
-						let b = (b_diff_vg + 8) as u8;
-						write(out, [OP_LUMA | g, r | b]);
-
-					}
-					else {
-						write(out, [OP_RGBA, px.0, px.1, px.2]);
-
-					}
-				}
+		if px.3 != px_prev.3 {
+			out.write_all(&[OP_RGBA, px.0, px.1, px.2, px.3])?;
+			return Ok(());
+		}
+
+		// differences wrap around, same as the decoder's additions
+		let r_diff = px.0.wrapping_sub(px_prev.0) as i8;
+		let g_diff = px.1.wrapping_sub(px_prev.1) as i8;
+		let b_diff = px.2.wrapping_sub(px_prev.2) as i8;
+
+		let r_diff_vg = r_diff.wrapping_sub(g_diff);
+		let b_diff_vg = b_diff.wrapping_sub(g_diff);
+
+		if (-2..=1).contains(&r_diff)
+			&& (-2..=1).contains(&g_diff)
+			&& (-2..=1).contains(&b_diff)
+			{
+			let r = ((r_diff + 2) as u8) << 4;
+			let g = ((g_diff + 2) as u8) << 2;
+			let b = (b_diff + 2) as u8;
+			out.write_all(&[OP_DIFF | r | g | b])?;
+
+		}
+		else if (-8..=7).contains(&r_diff_vg)
+			&& (-32..=31).contains(&g_diff)
+			&& (-8..=7).contains(&b_diff_vg)
+			{
+			let r = ((r_diff_vg + 8) as u8) << 4;
+			let g = (g_diff + 32) as u8;
+			let b = (b_diff_vg + 8) as u8;
+			out.write_all(&[OP_LUMA | g, r | b])?;
+
+		}
+		else {
+			out.write_all(&[OP_RGB, px.0, px.1, px.2])?;
+
+		}
+
+		Ok(())
+	}
+
+	/// flushes any pending run and writes the end marker.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		if self.run > 0 {
+			self.out.write_all(&[OP_RUN | (self.run - 1)])?;
+			self.run = 0;
+		}
+		self.out.write_all(&END)?;
+		Ok(())
+	}
+}
+
+#[cfg(feature = "std")]
+impl QoiEncoder<Vec<u8>> {
+	/// takes the bytes encoded so far, leaving none. an encoder can be
+	/// drained this way between pixels, into a writer that isn't a `Write`,
+	/// like an async one.
+	pub fn take_output(&mut self) -> Vec<u8> {
+		std::mem::take(&mut self.out)
+	}
+}
+
+/// the bytes of an image, encoded a chunk at a time as they're asked for,
+/// from the pixels of `encode_iter`. more pixels than the header has room
+/// for are left alone, and fewer is a `PixelCountMismatch`. an error is
+/// yielded once, after which it ends.
+#[derive(Debug)]
+#[cfg(feature = "std")]
+pub struct QoiBytes<I> {
+	encoder: QoiEncoder<Vec<u8>>,
+	pixels: I,
+	done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<I: Iterator<Item = crate::Rgba>> QoiBytes<I> {
+	/// bytes encoded before a chunk is handed out.
+	const CHUNK: usize = 1 << 12;
+
+	fn fill(&mut self) -> Result<(), Error> {
+		let encoder = &mut self.encoder;
+		while encoder.out.len() < Self::CHUNK {
+			if encoder.remaining == 0 {
+				encoder.end()?;
+				self.done = true;
+				break;
 			}
+			match self.pixels.next() {
+				Some(px) => encoder.push(px)?,
+				None => return Err(Error::PixelCountMismatch { expected: encoder.total, got: encoder.total - encoder.remaining }),
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(feature = "std")]
+impl<I: Iterator<Item = crate::Rgba>> Iterator for QoiBytes<I> {
+	type Item = Result<Vec<u8>, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+		match self.fill() {
+			Ok(()) => Some(Ok(self.encoder.take_output())),
+			Err(e) => {
+				self.done = true;
+				Some(Err(e))
+			}
+		}
+	}
+}
+
+/// encodes `data` lazily, into chunks of bytes that are only encoded as
+/// they're pulled, the first of which starts with the header. nothing needs
+/// a `Write`.
+#[cfg(feature = "std")]
+pub fn encode_iter<I: Iterator<Item = crate::Rgba>>(data: I, header: &QoiHeader) -> Result<QoiBytes<I>, Error> {
+	Ok(QoiBytes {
+		encoder: QoiEncoder::new(header, vec![])?,
+		pixels: data,
+		done: false,
+	})
+}
+
+#[cfg(feature = "std")]
+impl crate::Format for Qoi {
+	type Header = QoiHeader;
+	type Options = QoiOptions;
 
-			px_prev = px;
+	/// a reader that returns `WouldBlock` ends the iterator early;
+	/// use `QoiDecoder` directly to resume instead.
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = (u8, u8, u8, u8)>), Error> {
+		let pixels = QoiPixels::new(QoiDecoder::new(), data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	/// a reader that returns `WouldBlock` ends the iterator with that error.
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = QoiPixels::new(QoiDecoder::new(), data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn decode_with_options(self, data: &mut impl std::io::Read, options: &QoiOptions) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let mut decoder = QoiDecoder::new();
+		decoder.set_strict(options.strict);
+		let pixels = QoiPixels::new(decoder, data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	/// runs are skipped in a tight loop, rather than a pixel at a time.
+	fn decode_sub_image(self, data: &mut impl std::io::Read, x: u32, y: u32, width: u32, height: u32) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = QoiPixels::new(QoiDecoder::new(), data)?;
+		let header = pixels.header().clone();
+		let pixels = crate::rows::SubImage::new(pixels, QoiPixels::skip_pixels, &header, x, y, width, height)?;
+		Ok((header, pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = (u8, u8, u8, u8)>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = QoiEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+
+	fn colorspace(header: &Self::Header) -> crate::Colorspace {
+		match header.colorspace {
+			QoiHeaderColorspace::SRGB => crate::Colorspace::SRGB,
+			QoiHeaderColorspace::Linear => crate::Colorspace::Linear,
 		}
+	}
+}
+
+/// decodes a whole image held in memory. one cut short is an error, rather
+/// than fewer pixels.
+#[cfg(feature = "std")]
+pub fn decode_to_vec(mut data: &[u8]) -> Result<(QoiHeader, Vec<crate::Rgba>), Error> {
+	let (header, pixels) = crate::Format::decode_checked(Qoi, &mut data)?;
+	let image = crate::Image::collect_checked(header.width, header.height, pixels)?;
+	Ok((header, image.into_pixels()))
+}
 
-		write(out, [0, 0, 0, 0, 0, 0, 0, 1]);
+/// encodes an image into memory. `pixels` has to hold exactly as many as the
+/// header has room for, or it's a `PixelCountMismatch`.
+#[cfg(feature = "std")]
+pub fn encode_to_vec(header: QoiHeader, pixels: &[crate::Rgba]) -> Result<Vec<u8>, Error> {
+	let mut encoder = QoiEncoder::new(&header, vec![])?;
+	for &px in pixels {
+		encoder.push_pixel(px)?;
 	}
+	encoder.finish()
 }
 
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
-    use crate::{Format, qoi};
+	use core::task::Poll;
+
+	use crate::{Error, Format, qoi};
 
 	const IMAGE_SMALL: &[u8; 44] = include_bytes!("../test/small.qoi");
 
@@ -298,6 +1192,241 @@ mod test {
 		assert_eq!(data[13], (255, 0, 0, 255));
 	}
 
+	#[test]
+	fn header() {
+		use qoi::{QoiHeaderChannels::RGBA, QoiHeaderColorspace::SRGB};
+
+		let (header, _) = qoi::decode_to_vec(IMAGE_SMALL).expect("error?");
+		assert_eq!(qoi::QoiHeader::new(4, 4, header.channels, header.colorspace).expect("error?"), header);
+		assert!(qoi::QoiHeader::new(20000, 20000, RGBA, SRGB).is_ok());
+
+		let result = qoi::QoiHeader::new(0, 4, RGBA, SRGB);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 4, field: "width" })), "{result:?}");
+		let result = qoi::QoiHeader::new(4, 0, RGBA, SRGB);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 8, field: "height" })), "{result:?}");
+		let result = qoi::QoiHeader::new(20000, 20001, RGBA, SRGB);
+		assert!(matches!(result, Err(Error::TooLarge { what: "pixels", value: 400020000, limit: 400000000 })), "{result:?}");
+
+		// encoders hold headers made by hand to the same checks, before
+		// writing anything
+		let empty = qoi::QoiHeader { width: 0, height: 5, ..header.clone() };
+		let mut out = vec![];
+		let result = qoi::Qoi.encode(core::iter::empty(), empty.clone(), &mut out);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 4, field: "width" })), "{result:?}");
+		assert!(out.is_empty());
+		let result = qoi::encode_to_vec(empty, &[]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 4, field: "width" })), "{result:?}");
+		let result = qoi::encode_to_vec(qoi::QoiHeader { width: 3, height: 0, ..header.clone() }, &[]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 8, field: "height" })), "{result:?}");
+		let result = qoi::QoiEncoder::new(&qoi::QoiHeader { width: 20000, height: 20001, ..header }, vec![]);
+		assert!(matches!(result, Err(Error::TooLarge { what: "pixels", .. })), "{result:?}");
+	}
+
+	#[test]
+	fn decode_checked() {
+		let mut image = &IMAGE_SMALL[..];
+		let (_, iter) = qoi::Qoi.decode_checked(&mut image).expect("error?");
+		assert_eq!(iter.collect::<Result<Vec<_>, _>>().expect("error?").len(), 16);
+
+		// the pixels that were there, then what stopped them
+		let mut image = &IMAGE_SMALL[..20];
+		let (_, iter) = qoi::Qoi.decode_checked(&mut image).expect("error?");
+		let pixels = iter.collect::<Vec<_>>();
+		assert!(pixels[..pixels.len() - 1].iter().all(Result::is_ok));
+		assert!(matches!(pixels.last(), Some(Err(Error::UnexpectedEof { offset: 20 }))), "{pixels:?}");
+	}
+
+	#[test]
+	fn pixel_types() {
+		use crate::{Gray8, Pixel, Rgb8};
+
+		let (_, pixels) = qoi::decode_to_vec(IMAGE_SMALL).expect("error?");
+		let mut image = &IMAGE_SMALL[..];
+		let (_, iter) = qoi::Qoi.decode_as::<Gray8>(&mut image).expect("error?");
+		assert_eq!(iter.collect::<Vec<_>>(), pixels.iter().map(|px| px.convert::<Gray8>()).collect::<Vec<_>>());
+
+		let rgb = pixels.iter().map(|px| px.convert::<Rgb8>()).collect::<Vec<_>>();
+		let header = qoi::QoiHeader { width: 4, height: 4, channels: qoi::QoiHeaderChannels::RGB, colorspace: qoi::QoiHeaderColorspace::SRGB };
+		let mut out = vec![];
+		qoi::Qoi.encode_from(rgb.iter().copied(), header, &mut out).expect("error?");
+		let (_, decoded) = qoi::decode_to_vec(&out).expect("error?");
+		assert_eq!(decoded, rgb.iter().map(|px| px.to_rgba()).collect::<Vec<_>>());
+
+		// widened, since qoi only has 8 bits
+		let mut image = &IMAGE_SMALL[..];
+		let (_, iter) = qoi::Qoi.decode_wide(&mut image).expect("error?");
+		assert!(iter.eq(pixels.iter().copied().map(crate::pixel::widen)));
+	}
+
+	#[test]
+	fn to_vec() {
+		let (header, pixels) = qoi::decode_to_vec(IMAGE_SMALL).expect("error?");
+		assert_eq!(pixels.len(), 16);
+		assert_eq!(qoi::encode_to_vec(header.clone(), &pixels).expect("error?"), IMAGE_SMALL);
+
+		let result = qoi::decode_to_vec(&IMAGE_SMALL[..20]);
+		assert!(matches!(result, Err(Error::UnexpectedEof { offset: 20 })), "{result:?}");
+		let result = qoi::encode_to_vec(header.clone(), &pixels[1..]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 16, got: 15 })), "{result:?}");
+		let result = qoi::encode_to_vec(header, &[pixels.clone(), pixels].concat());
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 16, .. })), "{result:?}");
+	}
+
+	#[test]
+	fn size_hint() {
+		let mut image = &IMAGE_SMALL[..];
+		let (_, mut iter) = qoi::Qoi.decode(&mut image).expect("error?");
+		assert_eq!(iter.size_hint(), (0, Some(16)));
+		for left in (0..16).rev() {
+			let (lower, upper) = iter.size_hint();
+			assert!(lower <= left + 1 && upper == Some(left + 1), "{left}: {lower} {upper:?}");
+			iter.next().expect("pixel?");
+		}
+		assert_eq!(iter.size_hint(), (0, Some(0)));
+		assert!(iter.next().is_none());
+
+		let mut image = &IMAGE_SMALL[..20];
+		let (_, mut iter) = qoi::Qoi.decode_checked(&mut image).expect("error?");
+		while let Some(Ok(_)) = iter.next() {}
+		assert_eq!(iter.size_hint(), (0, Some(0)));
+
+		// the end marker can still go wrong after the last pixel
+		let mut decoder = qoi::QoiDecoder::new();
+		let mut pixels = decoder.feed(&IMAGE_SMALL[..14]);
+		assert_eq!(pixels.size_hint(), (0, None));
+		assert!(pixels.next().is_none());
+		assert_eq!(pixels.size_hint(), (0, Some(17)));
+
+		// a huge header in a tiny file promises nothing, and the decoder
+		// takes more pixels than the spec recommends, so neither does the
+		// upper bound stop there
+		let mut huge = IMAGE_SMALL[..14].to_vec();
+		huge[4..12].copy_from_slice(&[0, 0, 0x4e, 0x21, 0, 0, 0x4e, 0x21]);
+		let mut image = &huge[..];
+		let (_, iter) = qoi::Qoi.decode(&mut image).expect("error?");
+		assert_eq!(iter.size_hint(), (0, Some(400_040_001)));
+	}
+
+	#[test]
+	fn encode_iter() {
+		// noisy, so it takes a few chunks
+		let pixels = (0..200 * 50u32).map(|i| {
+			let noise = i.wrapping_mul(0x9e3779b1) >> 8;
+			(noise as u8, (noise >> 8) as u8, i as u8, 255)
+		}).collect::<Vec<_>>();
+		let header = qoi::QoiHeader { width: 200, height: 50, channels: qoi::QoiHeaderChannels::RGB, colorspace: qoi::QoiHeaderColorspace::SRGB };
+		let encoded = qoi::encode_to_vec(header.clone(), &pixels).expect("error?");
+
+		let mut pulled = 0;
+		let mut chunks = qoi::encode_iter(pixels.iter().copied().chain([(0, 0, 0, 0)]).inspect(|_| pulled += 1), &header).expect("error?");
+		let first = chunks.next().expect("chunk?").expect("error?");
+		assert_eq!(first[..4], *b"qoif");
+		let rest = chunks.collect::<Result<Vec<_>, _>>().expect("error?");
+		assert!(rest.len() > 2);
+		assert_eq!([first, rest.concat()].concat(), encoded);
+		// the extra pixel at the end isn't pulled
+		assert_eq!(pulled, pixels.len());
+
+		let mut chunks = qoi::encode_iter(pixels[1..].iter().copied(), &header).expect("error?");
+		assert!(matches!(chunks.by_ref().last(), Some(Err(Error::PixelCountMismatch { expected: 10000, got: 9999 }))));
+		assert!(chunks.next().is_none());
+	}
+
+	#[test]
+	fn strict() {
+		let mut image = &IMAGE_SMALL[..];
+		let (_, mut iter) = qoi::decode_strict(&mut image).expect("error?");
+		assert_eq!(iter.size_hint(), (0, Some(17)));
+		assert_eq!(iter.by_ref().collect::<Result<Vec<_>, _>>().expect("error?").len(), 16);
+		assert_eq!(iter.size_hint(), (0, Some(0)));
+
+		// lenient decoding doesn't look past the pixels, but strict does
+		let marker = IMAGE_SMALL.len() - 8;
+		let mut corrupt = IMAGE_SMALL.to_vec();
+		corrupt[marker + 3] = 9;
+		let trailing = [&IMAGE_SMALL[..], &[0]].concat();
+		for (data, offset) in [(&IMAGE_SMALL[..marker], marker), (&corrupt[..], marker), (&trailing[..], IMAGE_SMALL.len())] {
+			let mut image = data;
+			let (_, iter) = qoi::Qoi.decode_checked(&mut image).expect("error?");
+			assert_eq!(iter.collect::<Result<Vec<_>, _>>().expect("error?").len(), 16);
+
+			let mut image = data;
+			let (_, iter) = qoi::decode_strict(&mut image).expect("error?");
+			let pixels = iter.collect::<Vec<_>>();
+			assert_eq!(pixels.len(), 17);
+			match pixels[16] {
+				Err(Error::MissingEndMarker { offset: x }) | Err(Error::TrailingData { offset: x }) => assert_eq!(x as usize, offset),
+				ref other => panic!("{other:?}"),
+			}
+
+			// through fold, as `count` goes
+			let mut image = data;
+			let (_, iter) = qoi::decode_strict(&mut image).expect("error?");
+			assert_eq!(iter.fold(0, |n, px| n + px.is_err() as u32), 1);
+
+			// and as an option, which is off by default
+			for (strict, errors) in [(false, 0), (true, 1)] {
+				let mut image = data;
+				let options = qoi::QoiOptions { strict };
+				let (_, iter) = qoi::Qoi.decode_with_options(&mut image, &options).expect("error?");
+				assert_eq!(iter.fold(0, |n, px| n + px.is_err() as u32), errors);
+			}
+			assert!(!qoi::QoiOptions::default().strict);
+
+			let mut decoder = qoi::QoiDecoder::new();
+			decoder.set_strict(true);
+			let pixels = decoder.feed(data).collect::<Vec<_>>();
+			let last = pixels.last();
+			if data.len() == marker {
+				// fed data can always be followed by more
+				assert!(matches!(last, Some(Ok(_))), "{last:?}");
+			} else {
+				assert!(matches!(last, Some(Err(Error::MissingEndMarker { .. } | Error::TrailingData { .. }))), "{last:?}");
+			}
+		}
+	}
+
+	#[test]
+	fn sub_image() {
+		// runs, so skipping goes through them
+		let pixels = (0..63u8).map(|i| (i / 5 * 20, i % 9, 0, 255)).collect::<Vec<_>>();
+		let header = qoi::QoiHeader { width: 9, height: 7, channels: qoi::QoiHeaderChannels::RGBA, colorspace: qoi::QoiHeaderColorspace::SRGB };
+		let image = qoi::encode_to_vec(header, &pixels).expect("error?");
+		let whole = crate::Image::from_pixels(9, 7, pixels).expect("error?");
+		for (x, y, width, height) in [(0, 0, 9, 7), (2, 1, 3, 4), (8, 6, 1, 1), (0, 3, 9, 2), (4, 4, 0, 2)] {
+			let expected = whole.sub_image(x, y, width, height).expect("error?");
+			let mut data = &image[..];
+			let (_, iter) = qoi::Qoi.decode_sub_image(&mut data, x, y, width, height).expect("error?");
+			assert_eq!(iter.collect::<Result<Vec<_>, _>>().expect("error?"), expected.rows().flatten().copied().collect::<Vec<_>>());
+		}
+
+		// nothing after the last row is read
+		let mut data = &image[..];
+		let (_, iter) = qoi::Qoi.decode_sub_image(&mut data, 0, 0, 2, 1).expect("error?");
+		assert_eq!(iter.count(), 2);
+		assert!(!data.is_empty());
+
+		let mut data = &image[..];
+		let result = qoi::Qoi.decode_sub_image(&mut data, 5, 0, 5, 1);
+		assert!(matches!(result, Err(Error::OutOfBounds { x: 5, y: 0, width: 5, height: 1 })));
+
+		// cut short, while skipping
+		let mut data = &image[..30];
+		let (_, iter) = qoi::Qoi.decode_sub_image(&mut data, 0, 6, 9, 1).expect("error?");
+		assert!(matches!(iter.collect::<Vec<_>>()[..], [Err(Error::UnexpectedEof { offset: 30 })]));
+	}
+
+	#[test]
+	fn decode_header() {
+		let mut image = &IMAGE_SMALL[..];
+		let header = qoi::Qoi.decode_header(&mut image).expect("error?");
+		assert_eq!((header.width, header.height), (4, 4));
+		assert_eq!(image.len(), IMAGE_SMALL.len() - 14);
+
+		let mut image = &IMAGE_SMALL[..10];
+		assert!(matches!(qoi::Qoi.decode_header(&mut image), Err(Error::UnexpectedEof { offset: 10 })));
+	}
+
 	#[test]
 	fn encode() {
 		let data= &[
@@ -318,7 +1447,7 @@ mod test {
 
 		let mut out = vec![];
 
-		qoi::Qoi.encode(data.iter().cloned(), header.clone(), &mut out);
+		qoi::Qoi.encode(data.iter().cloned(), header.clone(), &mut out).expect("error?");
 
 		let mut data_write = &out[..];
 
@@ -333,5 +1462,462 @@ mod test {
 
 		assert_eq!(&data[..], &data_read);
 	}
+
+	#[test]
+	fn encode_ops() {
+		// alpha changes, small diffs, luma diffs, wrapping diffs and a run that
+		// reaches the end of the image
+		let data = &[
+			(10, 10, 10, 255),
+			(10, 10, 10, 128),
+			(11, 9, 10, 128),
+			(20, 30, 15, 128),
+			(255, 0, 1, 128),
+			(1, 255, 0, 128),
+			(10, 10, 10, 255),
+			(10, 10, 10, 255),
+			(10, 10, 10, 255),
+		];
+
+		let header = qoi::QoiHeader {
+			width: 3,
+			height: 3,
+			channels: qoi::QoiHeaderChannels::RGBA,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+
+		let mut out = vec![];
+		qoi::Qoi.encode(data.iter().cloned(), header, &mut out).expect("error?");
+
+		assert_eq!(&out[out.len() - 8..], &[0, 0, 0, 0, 0, 0, 0, 1]);
+
+		let mut data_write = &out[..];
+		let (_, iter) = qoi::Qoi.decode(&mut data_write).expect("error?");
+
+		assert_eq!(&data[..], &iter.collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn push_encoder() {
+		let header = qoi::QoiHeader {
+			width: 3,
+			height: 3,
+			channels: qoi::QoiHeaderChannels::RGBA,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+		let data = [(10, 10, 10, 255), (10, 10, 10, 255), (11, 9, 10, 255), (20, 30, 15, 128), (255, 0, 1, 128), (1, 255, 0, 128), (10, 10, 10, 255), (10, 10, 10, 255), (0, 0, 0, 0)];
+
+		let mut expected = vec![];
+		qoi::Qoi.encode(data.iter().cloned(), header.clone(), &mut expected).expect("error?");
+
+		// a pixel, then rows that don't line up with the image's
+		let mut encoder = qoi::QoiEncoder::new(&header, vec![]).expect("error?");
+		encoder.push_pixel(data[0]).expect("error?");
+		encoder.push_row(&data[1..4]).expect("error?");
+		encoder.push_row(&data[4..7]).expect("error?");
+		assert!(matches!(encoder.push_row(&data[7..9]), Err(Error::BufferSize { expected: 3, got: 2 })));
+		assert!(matches!(encoder.push_row(&data[6..9]), Err(Error::PixelCountMismatch { expected: 9, got: 10 })));
+		encoder.push_pixel(data[7]).expect("error?");
+		encoder.push_pixel(data[8]).expect("error?");
+		assert_eq!(encoder.remaining(), 0);
+		assert!(matches!(encoder.push_pixel(data[0]), Err(Error::PixelCountMismatch { expected: 9, got: 10 })));
+		assert_eq!(encoder.finish().expect("error?"), expected);
+
+		let mut encoder = qoi::QoiEncoder::new(&header, vec![]).expect("error?");
+		encoder.push_row(&data[..3]).expect("error?");
+		assert!(matches!(encoder.finish(), Err(Error::PixelCountMismatch { expected: 9, got: 3 })));
+
+		// drained a row at a time, and the rest at the end
+		let mut encoder = qoi::QoiEncoder::new(&header, vec![]).expect("error?");
+		let mut out = encoder.take_output();
+		assert_eq!(out.len(), 14);
+		for row in data.chunks(3) {
+			encoder.push_row(row).expect("error?");
+			out.extend(encoder.take_output());
+		}
+		out.extend(encoder.finish().expect("error?"));
+		assert_eq!(out, expected);
+
+		// the header and first row fill it exactly
+		let mut buf = [0; 20];
+		let mut encoder = qoi::QoiEncoder::new(&header, &mut buf[..]).expect("error?");
+		encoder.push_row(&data[..3]).expect("error?");
+		assert!(matches!(encoder.push_row(&data[3..6]), Err(Error::Io(_))));
+		assert!(matches!(encoder.push_pixel(data[6]), Err(Error::InvalidInput { .. })));
+		assert!(matches!(encoder.finish(), Err(Error::InvalidInput { .. })));
+	}
+
+	#[test]
+	fn encode_full_writer() {
+		let header = qoi::QoiHeader {
+			width: 4,
+			height: 4,
+			channels: qoi::QoiHeaderChannels::RGBA,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+		let pixels = (0..16).map(|i| (i * 16, 0, 0, 255));
+
+		// too short for the header, and then for the pixels
+		for len in [10, 20] {
+			let mut buf = vec![0; len];
+			let result = qoi::Qoi.encode(pixels.clone(), header.clone(), &mut &mut buf[..]);
+			assert!(matches!(&result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::WriteZero), "{len}: {result:?}");
+		}
+
+		let written = crate::encode_with(qoi::Qoi, header, &mut &mut [0; 20][..], |x, _| (x as u8, 0, 0, 255));
+		assert!(matches!(written, Err(Error::Io(_))), "{written:?}");
+	}
+
+	/// reader that fails with `WouldBlock` before every byte.
+	struct Blocking<'a> {
+		data: &'a [u8],
+		block: bool,
+	}
+
+	impl std::io::Read for Blocking<'_> {
+		fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+			self.block = !self.block;
+			if self.block {
+				return Err(std::io::ErrorKind::WouldBlock.into());
+			}
+			let n = buf.len().min(self.data.len()).min(1);
+			buf[..n].copy_from_slice(&self.data[..n]);
+			self.data = &self.data[n..];
+			Ok(n)
+		}
+	}
+
+	#[test]
+	fn decode_would_block() {
+		let mut image = &IMAGE_SMALL[..];
+		let (header, iter) = qoi::Qoi.decode(&mut image).expect("error?");
+		let expected = iter.collect::<Vec<_>>();
+
+		let mut reader = Blocking { data: &IMAGE_SMALL[..], block: false };
+		let mut decoder = qoi::QoiDecoder::new();
+
+		let mut pending = 0;
+
+		let header_read = loop {
+			match decoder.poll_header(&mut reader).expect("error?") {
+				Poll::Ready(x) => break x,
+				Poll::Pending => pending += 1,
+			}
+		};
+
+		let mut data = vec![];
+		loop {
+			match decoder.poll_pixel(&mut reader).expect("error?") {
+				Poll::Ready(Some(px)) => data.push(px),
+				Poll::Ready(None) => break,
+				Poll::Pending => pending += 1,
+			}
+		}
+
+		assert!(pending > 14);
+		assert_eq!(header, header_read);
+		assert_eq!(expected, data);
+	}
+
+	#[test]
+	fn feed() {
+		let mut image = &IMAGE_SMALL[..];
+		let (_, iter) = qoi::Qoi.decode(&mut image).expect("error?");
+		let expected = iter.collect::<Vec<_>>();
+
+		// with the start of another file after it
+		let mut file = IMAGE_SMALL.to_vec();
+		file.extend_from_slice(b"qoif");
+
+		for size in 1..=9 {
+			let mut decoder = qoi::QoiDecoder::new();
+			let mut data = vec![];
+			let mut rest = vec![];
+			for chunk in file.chunks(size) {
+				let mut pixels = decoder.feed(chunk);
+				data.extend((&mut pixels).map(|px| px.expect("error?")));
+				rest.extend_from_slice(pixels.rest());
+			}
+			// there's nothing left to decode, whatever's fed
+			assert!(decoder.feed(b"more").next().is_none());
+			assert_eq!(expected, data, "{size}");
+			assert!(decoder.is_finished());
+			assert_eq!(decoder.offset(), IMAGE_SMALL.len() as u64);
+			assert_eq!(rest, b"qoif", "{size}");
+		}
+
+		// pixels that aren't pulled leave their bytes behind
+		let mut decoder = qoi::QoiDecoder::new();
+		let mut pixels = decoder.feed(&IMAGE_SMALL[..]);
+		assert_eq!(pixels.next().map(|px| px.expect("error?")), Some(expected[0]));
+		assert_eq!(pixels.rest().len(), IMAGE_SMALL.len() - 15);
+		assert!(!decoder.is_finished());
+
+		let mut decoder = qoi::QoiDecoder::new();
+		let mut pixels = decoder.feed(include_bytes!("../test/corrupt/bad_magic.qoi"));
+		assert!(matches!(pixels.next(), Some(Err(Error::InvalidMagic { offset: 0 }))));
+		assert!(pixels.next().is_none());
+
+		let mut decoder = qoi::QoiDecoder::new();
+		let pixels = decoder.feed(include_bytes!("../test/corrupt/run_overrun.qoi"));
+		assert!(matches!(pixels.last(), Some(Err(Error::RunOverrun { offset: 14 }))));
+
+		// a file that stops short is only waiting for more, but a wrong end
+		// marker is an error
+		let mut decoder = qoi::QoiDecoder::new();
+		assert!(decoder.feed(&IMAGE_SMALL[..IMAGE_SMALL.len() - 1]).all(|px| px.is_ok()));
+		assert!(!decoder.is_finished());
+		assert!(matches!(decoder.feed(&[2]).next(), Some(Err(Error::MissingEndMarker { .. }))));
+	}
+
+	#[test]
+	fn fold() {
+		// runs of many lengths, so iteration stops partway through some of them
+		let data = (0..200u32)
+			.flat_map(|i| core::iter::repeat_n(((i * 37) as u8, i as u8, 0, 255), (i % 70) as usize + 1))
+			.collect::<Vec<_>>();
+		let header = qoi::QoiHeader {
+			width: data.len() as u32,
+			height: 1,
+			channels: qoi::QoiHeaderChannels::RGB,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+		let mut out = vec![];
+		qoi::Qoi.encode(data.iter().cloned(), header, &mut out).expect("error?");
+
+		let mut reader = &out[..];
+		let pixels = qoi::QoiPixels::new(qoi::QoiDecoder::new(), &mut reader).expect("error?");
+		let folded = pixels.fold(vec![], |mut acc, px| {
+			acc.push(px.expect("error?"));
+			acc
+		});
+		assert_eq!(folded, data);
+
+		let mut reader = &out[..];
+		let (_, iter) = qoi::Qoi.decode(&mut reader).expect("error?");
+		assert_eq!(iter.collect::<Vec<_>>(), data);
+
+		// short-circuits mixed with single steps
+		let mut reader = &out[..];
+		let mut pixels = qoi::QoiPixels::new(qoi::QoiDecoder::new(), &mut reader).expect("error?");
+		let mut mixed = vec![];
+		let mut steps = 0;
+		for i in 0.. {
+			let taken = pixels.try_fold(0, |n, px| {
+				mixed.push(px.expect("error?"));
+				if n == i % 97 { None } else { Some(n + 1) }
+			});
+			if taken.is_some() {
+				break;
+			}
+			steps += 1;
+			if let Some(px) = pixels.nth(i % 5) {
+				mixed.extend_from_slice(&data[mixed.len()..mixed.len() + i % 5]);
+				mixed.push(px.expect("error?"));
+			}
+			if let Some(px) = pixels.next() {
+				mixed.push(px.expect("error?"));
+			}
+		}
+		assert_eq!(mixed, data);
+		assert!(steps > 50);
+		assert!(pixels.next().is_none());
+		assert_eq!(pixels.finish().expect("error?"), out.len() as u64);
+
+		// errors are folded like any other item, and end the iteration
+		let mut reader = &include_bytes!("../test/corrupt/truncated_rgba.qoi")[..];
+		let pixels = qoi::QoiPixels::new(qoi::QoiDecoder::new(), &mut reader).expect("error?");
+		let items = pixels.fold(vec![], |mut acc, px| {
+			acc.push(px);
+			acc
+		});
+		assert!(matches!(items[..], [Err(Error::UnexpectedEof { offset: 17 })]), "{items:?}");
+	}
+
+	#[test]
+	fn hostile_io() {
+		// offsets saturate instead of overflowing
+		let mut decoder = qoi::QoiDecoder::with_offset(u64::MAX - 20);
+		let mut reader = &IMAGE_SMALL[..];
+		while decoder.poll_finish(&mut reader).expect("error?").is_pending() {}
+		assert_eq!(decoder.offset(), u64::MAX);
+
+		// a reader that claims to fill more than it was given
+		struct Liar<'a>(&'a [u8]);
+		impl std::io::Read for Liar<'_> {
+			fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+				let n = self.0.read(buf)?;
+				Ok(if n == 0 { 0 } else { n + 100 })
+			}
+		}
+
+		let mut reader = Liar(&IMAGE_SMALL[..]);
+		let (_, iter) = qoi::Qoi.decode(&mut reader).expect("error?");
+		assert!(iter.count() <= 16);
+		_ = qoi::lint(&mut Liar(&IMAGE_SMALL[..])).expect("io error?");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut decoder = qoi::QoiDecoder::new();
+			// slices never block, so this loop only runs once
+			while decoder.poll_finish(&mut data)?.is_pending() {}
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".qoi")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("zero_width", Error::InvalidHeader { offset: 4, field: "width" });
+		case!("zero_height", Error::InvalidHeader { offset: 8, field: "height" });
+		case!("bad_channels", Error::InvalidHeader { offset: 12, field: "channels" });
+		case!("bad_colorspace", Error::InvalidHeader { offset: 13, field: "colorspace" });
+		case!("truncated_header", Error::UnexpectedEof { offset: 9 });
+		case!("truncated_rgba", Error::UnexpectedEof { offset: 17 });
+		case!("run_overrun", Error::RunOverrun { offset: 14 });
+		case!("missing_end_marker", Error::MissingEndMarker { offset: 18 });
+		case!("trailing_garbage", Error::TrailingData { offset: 26 });
+
+		assert!(decode(IMAGE_SMALL).is_ok());
+
+		// `Format::decode` reports header errors the same way
+		let result = qoi::Qoi.decode(&mut &include_bytes!("../test/corrupt/bad_magic.qoi")[..]).map(|_| ());
+		assert!(matches!(result, Err(Error::InvalidMagic { offset: 0 })), "{result:?}");
+		let result = qoi::Qoi.decode(&mut &include_bytes!("../test/corrupt/truncated_header.qoi")[..]).map(|_| ());
+		assert!(matches!(result, Err(Error::UnexpectedEof { offset: 9 })), "{result:?}");
+	}
+
+	#[test]
+	fn decode_at() {
+		let mut file = vec![0x55; 37];
+		file.extend_from_slice(IMAGE_SMALL);
+
+		let second = &[(1, 2, 3, 255), (1, 2, 3, 255), (9, 8, 7, 6)];
+		let header = qoi::QoiHeader {
+			width: 3,
+			height: 1,
+			channels: qoi::QoiHeaderChannels::RGBA,
+			colorspace: qoi::QoiHeaderColorspace::Linear,
+		};
+		qoi::Qoi.encode(second.iter().cloned(), header.clone(), &mut file).expect("error?");
+		file.extend_from_slice(b"trailing junk");
+
+		let mut reader = std::io::Cursor::new(file);
+
+		let (header_read, mut pixels) = qoi::decode_at(&mut reader, 37).expect("error?");
+		assert_eq!(header_read.width, 4);
+		assert_eq!(pixels.by_ref().filter_map(Result::ok).count(), 16);
+		let len = pixels.finish().expect("error?");
+		assert_eq!(len, IMAGE_SMALL.len() as u64);
+
+		let (header_read, pixels) = qoi::decode_at(&mut reader, 37 + len).expect("error?");
+		assert_eq!(header_read, header);
+		let data = pixels.collect::<Result<Vec<_>, _>>().expect("error?");
+		assert_eq!(&data, second);
+
+		// offsets are relative to the whole file
+		let result = qoi::decode_at(&mut reader, 20);
+		assert!(matches!(result, Err(Error::InvalidMagic { offset: 20 })), "{result:?}");
+
+		let mut truncated = std::io::Cursor::new(reader.into_inner()[..37 + 20].to_vec());
+		let (_, mut pixels) = qoi::decode_at(&mut truncated, 37).expect("error?");
+		let result = pixels.find_map(Result::err);
+		assert!(matches!(result, Some(Error::UnexpectedEof { offset: 57 })), "{result:?}");
+	}
+
+	#[test]
+	fn encode_unknown_height() {
+		let data = (0..7 * 5).map(|x| (x as u8, 0, 255 - x as u8, 255)).collect::<Vec<_>>();
+
+		let mut out = std::io::Cursor::new(vec![]);
+		let header = qoi::encode_unknown_height(
+			7,
+			qoi::QoiHeaderChannels::RGB,
+			qoi::QoiHeaderColorspace::SRGB,
+			data.iter().cloned(),
+			&mut out,
+		).expect("error?");
+
+		assert_eq!((header.width, header.height), (7, 5));
+		assert_eq!(out.position(), out.get_ref().len() as u64);
+
+		let out = out.into_inner();
+		let mut reader = &out[..];
+		let (header_read, iter) = qoi::Qoi.decode(&mut reader).expect("error?");
+		assert_eq!(header, header_read);
+		assert_eq!(data, iter.collect::<Vec<_>>());
+
+		let result = qoi::encode_unknown_height(
+			7,
+			qoi::QoiHeaderChannels::RGB,
+			qoi::QoiHeaderColorspace::SRGB,
+			data[..10].iter().cloned(),
+			&mut std::io::Cursor::new(vec![]),
+		);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 14, got: 10 })), "{result:?}");
+
+		struct Pipe(Vec<u8>);
+		impl std::io::Write for Pipe {
+			fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+				self.0.write(buf)
+			}
+			fn flush(&mut self) -> std::io::Result<()> {
+				Ok(())
+			}
+		}
+		impl std::io::Seek for Pipe {
+			fn seek(&mut self, _: std::io::SeekFrom) -> std::io::Result<u64> {
+				Err(std::io::ErrorKind::Unsupported.into())
+			}
+		}
+
+		let mut pipe = Pipe(vec![]);
+		let result = qoi::encode_unknown_height(
+			7,
+			qoi::QoiHeaderChannels::RGB,
+			qoi::QoiHeaderColorspace::SRGB,
+			data.iter().cloned(),
+			&mut pipe,
+		);
+		assert!(matches!(result, Err(Error::Io(_))), "{result:?}");
+		assert!(pipe.0.is_empty());
+	}
+
+	#[test]
+	fn metadata_dropped() {
+		let data = [(1, 2, 3, 255); 4];
+		
+		let header = qoi::QoiHeader {
+			width: 2,
+			height: 2,
+			channels: qoi::QoiHeaderChannels::RGBA,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+
+		let mut meta = crate::Metadata::new();
+		meta.insert(crate::metadata::TITLE, "title");
+
+		let mut plain = vec![];
+		qoi::Qoi.encode(data.iter().cloned(), header.clone(), &mut plain).expect("error?");
+
+		let mut out = vec![];
+		qoi::Qoi.encode_with_metadata(data.iter().cloned(), header, &meta, &mut out).expect("error?");
+
+		// qoi has nowhere to put metadata, so the output is unchanged
+		assert_eq!(plain, out);
+
+		let mut data_write = &out[..];
+
+		let (_, meta_read, iter) = qoi::Qoi.decode_with_metadata(&mut data_write).expect("error?");
+
+		assert!(meta_read.is_empty());
+		assert_eq!(iter.count(), 4);
+	}
 }
 
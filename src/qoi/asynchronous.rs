@@ -0,0 +1,208 @@
+/*!
+decoding from and encoding to `futures-io` readers and writers.
+
+`decode_async` reads whatever the reader has ready and hands it to
+`QoiDecoder::feed`, which keeps an op cut off between reads for the next
+one, so reads can be of any size. `encode_async` pushes pixels into a
+`QoiEncoder<Vec<u8>>`, and writes out what `take_output` gives every few
+kilobytes. nothing here needs an executor of its own, or blocks one.
+*/
+
+use core::future::poll_fn;
+use core::pin::Pin;
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use crate::Error;
+use crate::error::oom;
+use super::{QoiDecoder, QoiEncoder, QoiHeader};
+
+/// bytes read at once, and encoded before they're written.
+const CHUNK: usize = 4096;
+
+/// decodes a whole image from an async reader. one cut short is an error,
+/// like `decode_to_vec` makes it. anything after the end marker is left
+/// unread, bar what came in the same read.
+pub async fn decode_async(data: &mut (impl AsyncRead + Unpin)) -> Result<(QoiHeader, Vec<crate::Rgba>), Error> {
+	let mut decoder = QoiDecoder::new();
+	let mut pixels = vec![];
+	let mut buf = [0; CHUNK];
+
+	while !decoder.is_finished() {
+		let n = poll_fn(|cx| Pin::new(&mut *data).poll_read(cx, &mut buf)).await;
+		let n = match n {
+			Ok(0) if decoder.header().is_some() && decoder.remaining() == 0 => {
+				return Err(Error::MissingEndMarker { offset: decoder.offset() });
+			}
+			Ok(0) => return Err(Error::UnexpectedEof { offset: decoder.offset() }),
+			// a reader claiming to have read more than it was given is lying
+			Ok(n) => n.min(buf.len()),
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e.into()),
+		};
+
+		// the pixels are grown as they come, rather than by the header
+		for px in decoder.feed(&buf[..n]) {
+			pixels.try_reserve(1).map_err(|_| oom())?;
+			pixels.push(px?);
+		}
+	}
+
+	// the header was read before the end marker could be
+	let header = decoder.header().cloned().ok_or(Error::UnexpectedEof { offset: 0 })?;
+	Ok((header, pixels))
+}
+
+/// writes all of `buf`, like `Write::write_all`.
+async fn write_all(out: &mut (impl AsyncWrite + Unpin), mut buf: &[u8]) -> Result<(), Error> {
+	while !buf.is_empty() {
+		match poll_fn(|cx| Pin::new(&mut *out).poll_write(cx, buf)).await {
+			Ok(0) => return Err(Error::Io(std::io::ErrorKind::WriteZero.into())),
+			Ok(n) => buf = &buf[n.min(buf.len())..],
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e.into()),
+		}
+	}
+	Ok(())
+}
+
+/// encodes an image into an async writer, and flushes it. pixels past what
+/// the header has room for are left alone, and fewer is a
+/// `PixelCountMismatch`, as with `Format::encode`.
+pub async fn encode_async(data: impl Iterator<Item = crate::Rgba>, header: &QoiHeader, out: &mut (impl AsyncWrite + Unpin)) -> Result<(), Error> {
+	let mut encoder = QoiEncoder::new(header, vec![])?;
+	for px in data.take(encoder.remaining().try_into().unwrap_or(usize::MAX)) {
+		encoder.push_pixel(px)?;
+		if encoder.out.len() >= CHUNK {
+			write_all(out, &encoder.take_output()).await?;
+		}
+	}
+	let rest = encoder.finish()?;
+	write_all(out, &rest).await?;
+	poll_fn(|cx| Pin::new(&mut *out).poll_flush(cx)).await?;
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+	use core::pin::Pin;
+	use core::task::{Context, Poll};
+
+	use futures_io::{AsyncRead, AsyncWrite};
+
+	use crate::Error;
+	use crate::qoi::{self, QoiHeader, QoiHeaderChannels, QoiHeaderColorspace};
+
+	/// runs a future that's woken as soon as it's pending.
+	fn block_on<F: Future>(future: F) -> F::Output {
+		let mut future = core::pin::pin!(future);
+		let mut cx = Context::from_waker(core::task::Waker::noop());
+		loop {
+			if let Poll::Ready(x) = future.as_mut().poll(&mut cx) {
+				return x;
+			}
+		}
+	}
+
+	/// hands out a few bytes at a time, and is pending every other call.
+	struct Chunked<'a> {
+		data: &'a [u8],
+		size: usize,
+		ready: bool,
+	}
+
+	impl AsyncRead for Chunked<'_> {
+		fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+			self.ready = !self.ready;
+			if !self.ready {
+				cx.waker().wake_by_ref();
+				return Poll::Pending;
+			}
+			let n = self.size.min(buf.len()).min(self.data.len());
+			buf[..n].copy_from_slice(&self.data[..n]);
+			self.data = &self.data[n..];
+			Poll::Ready(Ok(n))
+		}
+	}
+
+	/// collects what's written, a few bytes at a time, and is pending every
+	/// other call.
+	struct Sink {
+		data: Vec<u8>,
+		size: usize,
+		ready: bool,
+	}
+
+	impl AsyncWrite for Sink {
+		fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+			self.ready = !self.ready;
+			if !self.ready {
+				cx.waker().wake_by_ref();
+				return Poll::Pending;
+			}
+			let n = self.size.min(buf.len());
+			self.data.extend_from_slice(&buf[..n]);
+			Poll::Ready(Ok(n))
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	fn image() -> (QoiHeader, Vec<crate::Rgba>) {
+		let header = QoiHeader {
+			width: 40,
+			height: 30,
+			channels: QoiHeaderChannels::RGBA,
+			colorspace: QoiHeaderColorspace::SRGB,
+		};
+		let pixels = (0..1200u32).map(|i| ((i % 40) as u8 * 6, (i / 40) as u8 * 8, if i % 7 == 0 { 255 } else { 3 }, (i % 5) as u8 * 60)).collect();
+		(header, pixels)
+	}
+
+	#[test]
+	fn decode_chunked() {
+		let (header, pixels) = image();
+		let file = qoi::encode_to_vec(header.clone(), &pixels).expect("error?");
+
+		for size in [1, 3, 7, 4096] {
+			let mut data = Chunked { data: &file, size, ready: false };
+			let (header_read, decoded) = block_on(qoi::decode_async(&mut data)).expect("error?");
+			assert_eq!(header_read, header);
+			assert_eq!(decoded, pixels);
+		}
+
+		// cut short in the pixels, and in the end marker
+		for (cut, missing) in [(file.len() / 2, false), (file.len() - 3, true)] {
+			let mut data = Chunked { data: &file[..cut], size: 5, ready: false };
+			let result = block_on(qoi::decode_async(&mut data));
+			match missing {
+				false => assert!(matches!(result, Err(Error::UnexpectedEof { .. })), "{result:?}"),
+				true => assert!(matches!(result, Err(Error::MissingEndMarker { .. })), "{result:?}"),
+			}
+		}
+		let mut data = Chunked { data: &file[..6], size: 5, ready: false };
+		let result = block_on(qoi::decode_async(&mut data));
+		assert!(matches!(result, Err(Error::UnexpectedEof { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn encode_chunked() {
+		let (header, pixels) = image();
+		let expected = qoi::encode_to_vec(header.clone(), &pixels).expect("error?");
+
+		let mut out = Sink { data: vec![], size: 5, ready: false };
+		block_on(qoi::encode_async(pixels.iter().copied().chain([(1, 2, 3, 4)]), &header, &mut out)).expect("error?");
+		assert_eq!(out.data, expected);
+
+		let mut out = Sink { data: vec![], size: 5, ready: false };
+		let result = block_on(qoi::encode_async(pixels[1..].iter().copied(), &header, &mut out));
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 1200, got: 1199 })), "{result:?}");
+	}
+}
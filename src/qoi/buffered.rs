@@ -0,0 +1,201 @@
+/*!
+decoding from a `BufRead`, straight out of its buffer.
+
+`QoiPixels` goes through `read` once or twice an op, which costs a copy and
+a call through the reader each time, and a syscall each time if the reader
+is an unbuffered `File`. `BufPixels` borrows the reader's buffer with
+`fill_buf` instead, decodes every op that's whole in it, and consumes them
+all at once. an op cut off by the end of the buffer, and the end marker,
+go through `QoiDecoder` as usual, so the pixels and errors are the same as
+`QoiPixels` gives, at the same offsets, and the reader is left in the same
+place.
+
+`cargo bench --bench buffered` measures it. folding over the pixels of a
+1024x1024 image in a file took about 5ms this way from a `BufReader<File>`,
+against 13ms for `QoiPixels` from the same `BufReader`, so about 2.5x as
+fast, and 200ms for `QoiPixels` from the bare `File`.
+*/
+
+use core::ops::ControlFlow;
+
+use super::{QoiDecoder, QoiHeader, QoiPixels, op_len, ready};
+use crate::{Error, Rgba};
+
+/// pixels of a qoi image, decoded out of a `BufRead`'s buffer. like
+/// `QoiPixels`, the first error is yielded once, after which it ends.
+#[derive(Debug)]
+pub struct BufPixels<'a, R>(QoiPixels<'a, R>);
+
+/// reads the header from `data` and returns it with its pixels.
+pub fn decode_buffered<R: std::io::BufRead>(data: &mut R) -> Result<(QoiHeader, BufPixels<'_, R>), Error> {
+	let pixels = BufPixels::new(QoiDecoder::new(), data)?;
+	Ok((pixels.header().clone(), pixels))
+}
+
+impl<'a, R: std::io::BufRead> BufPixels<'a, R> {
+	/// reads the header with `decoder`, and returns its pixels.
+	pub fn new(decoder: QoiDecoder, data: &'a mut R) -> Result<Self, Error> {
+		QoiPixels::new(decoder, data).map(Self)
+	}
+
+	pub fn header(&self) -> &QoiHeader {
+		self.0.header()
+	}
+
+	/// like `QoiPixels::finish`.
+	pub fn finish(self) -> Result<u64, Error> {
+		self.0.finish()
+	}
+
+	/// like `QoiPixels::walk`, but ops that are whole in the reader's buffer
+	/// are decoded a buffer at a time.
+	fn walk<B>(&mut self, mut acc: B, mut f: impl FnMut(B, Result<Rgba, Error>) -> ControlFlow<B, B>) -> ControlFlow<B, B> {
+		let pixels = &mut self.0;
+		while !pixels.failed {
+			let decoder = &mut pixels.decoder;
+			while decoder.run > 0 {
+				decoder.run -= 1;
+				decoder.total -= 1;
+				acc = f(acc, Ok(decoder.px))?;
+			}
+
+			// a partly read op has to be finished through `read`, and an error
+			// from the reader is left for it to yield
+			if decoder.len == 0 {
+				match pixels.data.fill_buf() {
+					Ok(buf) => {
+						let mut used = 0;
+						let flow = ops(decoder, buf, &mut used, acc, &mut f);
+						pixels.data.consume(used);
+						acc = flow?;
+						if used > 0 {
+							continue;
+						}
+					}
+					Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+					Err(_) => {}
+				}
+			}
+
+			// the buffer ended partway through an op, or the image ended
+			acc = match ready(pixels.decoder.poll_pixel(pixels.data)) {
+				Ok(Some(px)) => f(acc, Ok(px))?,
+				Ok(None) => match pixels.end() {
+					Some(e) => f(acc, Err(e))?,
+					None => break,
+				},
+				Err(e) => {
+					pixels.failed = true;
+					f(acc, Err(e))?
+				}
+			};
+		}
+		ControlFlow::Continue(acc)
+	}
+}
+
+/// decodes the ops that are whole in `buf`, from `used` on, and hands their
+/// pixels to `f`. an op that fails is left for `poll_pixel` to yield the error
+/// of. `used` is kept up to date, so it counts every op decoded when `f`
+/// breaks.
+fn ops<B>(decoder: &mut QoiDecoder, buf: &[u8], used: &mut usize, mut acc: B, f: &mut impl FnMut(B, Result<Rgba, Error>) -> ControlFlow<B, B>) -> ControlFlow<B, B> {
+	while decoder.total > 0 {
+		let Some(&b0) = buf.get(*used) else { break };
+		let Some(op) = buf.get(*used..*used + op_len(b0)) else { break };
+		let Ok(px) = decoder.decode_op(op) else { break };
+		*used += op.len();
+		decoder.offset = decoder.offset.saturating_add(op.len() as u64);
+		acc = f(acc, Ok(px))?;
+
+		while decoder.run > 0 {
+			decoder.run -= 1;
+			decoder.total -= 1;
+			acc = f(acc, Ok(decoder.px))?;
+		}
+	}
+	ControlFlow::Continue(acc)
+}
+
+impl<R: std::io::BufRead> Iterator for BufPixels<'_, R> {
+	type Item = Result<Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let (ControlFlow::Continue(x) | ControlFlow::Break(x)) = self.walk(None, |_, px| ControlFlow::Break(Some(px)));
+		x
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.0.size_hint()
+	}
+
+	fn nth(&mut self, mut n: usize) -> Option<Self::Item> {
+		let (ControlFlow::Continue(x) | ControlFlow::Break(x)) = self.walk(None, |_, px| {
+			if n == 0 {
+				return ControlFlow::Break(Some(px));
+			}
+			n -= 1;
+			ControlFlow::Continue(None)
+		});
+		x
+	}
+
+	fn fold<B, F: FnMut(B, Self::Item) -> B>(mut self, init: B, mut f: F) -> B {
+		let (ControlFlow::Continue(x) | ControlFlow::Break(x)) = self.walk(init, |acc, px| ControlFlow::Continue(f(acc, px)));
+		x
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use std::io::Read;
+
+	use crate::{Error, Format, qoi};
+
+	const IMAGE_SMALL: &[u8; 44] = include_bytes!("../../test/small.qoi");
+
+	#[test]
+	fn decode_buffered() {
+		let pixels = qoi::decode_to_vec(IMAGE_SMALL).expect("error?").1;
+
+		// buffers of every size, so ops are cut off everywhere they can be
+		for capacity in 1..=IMAGE_SMALL.len() + 1 {
+			let mut data = std::io::BufReader::with_capacity(capacity, &IMAGE_SMALL[..]);
+			let (_, iter) = qoi::decode_buffered(&mut data).expect("error?");
+			assert_eq!(iter.collect::<Result<Vec<_>, _>>().expect("error?"), pixels, "{capacity}");
+
+			let mut data = std::io::BufReader::with_capacity(capacity, &IMAGE_SMALL[..]);
+			let (_, iter) = qoi::decode_buffered(&mut data).expect("error?");
+			let mut decoded = vec![];
+			// a `for` loop pulls with `next`, one op per fill
+			for px in iter {
+				decoded.push(px.expect("error?"));
+			}
+			assert_eq!(decoded, pixels, "{capacity}");
+		}
+
+		// the same errors, at the same offsets, as through `read`
+		for len in 0..IMAGE_SMALL.len() {
+			let data = &IMAGE_SMALL[..len];
+			let expected = qoi::Qoi.decode_checked(&mut &data[..]).map(|(_, iter)| format!("{:?}", iter.collect::<Vec<_>>()));
+			let got = qoi::decode_buffered(&mut &data[..]).map(|(_, iter)| format!("{:?}", iter.collect::<Vec<_>>()));
+			assert_eq!(format!("{got:?}"), format!("{expected:?}"), "{len}");
+		}
+
+		// and the reader is left right after the image
+		let data = [&IMAGE_SMALL[..], b"next"].concat();
+		let mut reader = std::io::BufReader::with_capacity(8, &data[..]);
+		let (_, mut iter) = qoi::decode_buffered(&mut reader).expect("error?");
+		assert!(iter.nth(3).expect("pixel?").is_ok());
+		assert_eq!(iter.finish().expect("error?"), IMAGE_SMALL.len() as u64);
+		let mut rest = vec![];
+		reader.read_to_end(&mut rest).expect("error?");
+		assert_eq!(rest, b"next");
+
+		let mut decoder = qoi::QoiDecoder::new();
+		decoder.set_strict(true);
+		let mut reader = &data[..];
+		let pixels = qoi::BufPixels::new(decoder, &mut reader).expect("error?");
+		assert!(matches!(pixels.last(), Some(Err(Error::TrailingData { offset: 44 }))));
+	}
+}
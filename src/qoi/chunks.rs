@@ -0,0 +1,227 @@
+/*!
+decoding from iterators of bytes or chunks, for data that doesn't come
+through a reader.
+
+each chunk is handed to the decoder's poll state machine as it comes, the
+way `QoiDecoder::feed` takes bytes, so boundaries can fall anywhere,
+including inside the header or an op. nothing is pulled from the iterator
+past what the image needs, bar the rest of the chunk it ends in.
+*/
+
+use core::task::Poll;
+
+use super::{QoiDecoder, QoiHeader, QoiHeaderChannels, QoiHeaderColorspace};
+use crate::Error;
+
+/// pixels of a qoi image decoded from an iterator of bytes.
+pub type BytePixels<I> = ChunkPixels<core::iter::Map<I, fn(u8) -> [u8; 1]>>;
+
+/// what's left of a chunk. it blocks once it runs out, so the decoder keeps
+/// a cut off op for the next one, unless there's no next one.
+struct Rest<'a> {
+	data: &'a [u8],
+	last: bool,
+}
+
+impl crate::io::Read for Rest<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<Poll<usize>, Error> {
+		if self.data.is_empty() && !buf.is_empty() && !self.last {
+			return Ok(Poll::Pending);
+		}
+		Ok(Poll::Ready(crate::io::take(&mut self.data, buf)))
+	}
+}
+
+/// pixels of a qoi image, decoded from an iterator of chunks as they're
+/// requested. empty chunks are skipped.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug, Clone)]
+pub struct ChunkPixels<I: Iterator> {
+	decoder: QoiDecoder,
+	header: QoiHeader,
+	start: u64,
+	iter: I,
+	chunk: Option<I::Item>,
+	/// how much of `chunk` has been used up.
+	pos: usize,
+	/// whether `iter` has run out.
+	done: bool,
+	failed: bool,
+}
+
+impl QoiDecoder {
+	/// reads the header from an iterator of bytes, and returns its pixels.
+	pub fn from_byte_iter<I: IntoIterator<Item = u8>>(iter: I) -> Result<BytePixels<I::IntoIter>, Error> {
+		Self::from_chunks(iter.into_iter().map((|x| [x]) as fn(u8) -> [u8; 1]))
+	}
+
+	/// reads the header from an iterator of chunks, such as `&[u8]` or
+	/// `Vec<u8>`, and returns its pixels.
+	pub fn from_chunks<I: IntoIterator<Item: AsRef<[u8]>>>(iter: I) -> Result<ChunkPixels<I::IntoIter>, Error> {
+		Self::new().chunks(iter)
+	}
+
+	/// like `from_chunks`, with this decoder, so it can be strict or start at
+	/// an offset.
+	pub fn chunks<I: IntoIterator<Item: AsRef<[u8]>>>(self, iter: I) -> Result<ChunkPixels<I::IntoIter>, Error> {
+		let mut pixels = ChunkPixels {
+			start: self.offset(),
+			decoder: self,
+			// replaced before anyone sees it
+			header: QoiHeader {
+				width: 0,
+				height: 0,
+				channels: QoiHeaderChannels::RGBA,
+				colorspace: QoiHeaderColorspace::SRGB,
+			},
+			iter: iter.into_iter(),
+			chunk: None,
+			pos: 0,
+			done: false,
+			failed: false,
+		};
+		pixels.header = pixels.poll(|decoder, data| decoder.poll_header(data))?;
+		Ok(pixels)
+	}
+}
+
+impl<I: Iterator<Item: AsRef<[u8]>>> ChunkPixels<I> {
+	pub fn header(&self) -> &QoiHeader {
+		&self.header
+	}
+
+	/// skips any pixels that are left, checks the end marker, and returns the
+	/// number of bytes the image took up, header and end marker included.
+	pub fn finish(&mut self) -> Result<u64, Error> {
+		self.poll(|decoder, data| decoder.poll_end(data))?;
+		Ok(self.decoder.offset() - self.start)
+	}
+
+	/// the part of the current chunk that hasn't been used up. once the end
+	/// marker is read, this is whatever came after it.
+	pub fn rest(&self) -> &[u8] {
+		self.chunk.as_ref().map_or(&[], |x| &x.as_ref()[self.pos..])
+	}
+
+	/// the chunks that haven't been pulled yet.
+	pub fn into_inner(self) -> I {
+		self.iter
+	}
+
+	/// polls `f` with what's left of the chunk, moving on to the next one for
+	/// as long as it's pending. once the iterator runs out, the decoder reads
+	/// to the end, and finds out what's missing itself.
+	fn poll<T>(&mut self, mut f: impl FnMut(&mut QoiDecoder, &mut Rest) -> Result<Poll<T>, Error>) -> Result<T, Error> {
+		loop {
+			let data = self.chunk.as_ref().map_or(&[][..], |x| &x.as_ref()[self.pos..]);
+			let mut rest = Rest { data, last: self.done };
+			let result = f(&mut self.decoder, &mut rest);
+			self.pos += data.len() - rest.data.len();
+			if let Poll::Ready(x) = result? {
+				return Ok(x);
+			}
+
+			// a reader at its end never blocks, but this shouldn't spin if it did
+			if self.done {
+				return Err(Error::UnexpectedEof { offset: self.decoder.offset() });
+			}
+			self.chunk = self.iter.next();
+			self.pos = 0;
+			self.done = self.chunk.is_none();
+		}
+	}
+}
+
+impl<I: Iterator<Item: AsRef<[u8]>>> Iterator for ChunkPixels<I> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+		let result = match self.poll(|decoder, data| decoder.poll_pixel(data)) {
+			Ok(Some(px)) => return Some(Ok(px)),
+			Ok(None) if self.decoder.strict => self.poll(|decoder, data| decoder.poll_finish(data)),
+			Ok(None) => return None,
+			Err(e) => Err(e),
+		};
+		// a strict decoder yields nothing once the end checks out
+		self.failed = true;
+		result.err().map(Err)
+	}
+
+	/// like `QoiPixels`.
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		if self.failed {
+			return (0, Some(0));
+		}
+		let upper = self.decoder.total.checked_add((self.decoder.strict && !self.decoder.end) as u64).and_then(|n| n.try_into().ok());
+		(self.decoder.run as usize, upper)
+	}
+}
+
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+	use crate::{Error, Format, qoi};
+	use crate::qoi::QoiDecoder;
+
+	const IMAGE_SMALL: &[u8; 44] = include_bytes!("../../test/small.qoi");
+
+	fn decode(data: &mut impl std::io::Read) -> (qoi::QoiHeader, Vec<crate::Rgba>) {
+		let (header, iter) = qoi::Qoi.decode(data).expect("error?");
+		(header, iter.collect())
+	}
+
+	#[test]
+	fn bytes() {
+		let expected = decode(&mut &IMAGE_SMALL[..]);
+
+		let pixels = QoiDecoder::from_byte_iter(IMAGE_SMALL.iter().copied()).expect("error?");
+		let header = pixels.header().clone();
+		assert_eq!((header, pixels.collect::<Result<Vec<_>, _>>().expect("error?")), expected);
+
+		// nothing past the end marker is pulled from the iterator
+		let mut pixels = QoiDecoder::from_byte_iter(IMAGE_SMALL.iter().copied().chain([1, 2, 3])).expect("error?");
+		assert_eq!(pixels.finish().expect("error?"), 44);
+		assert!(pixels.rest().is_empty());
+		assert_eq!(pixels.into_inner().flatten().collect::<Vec<_>>(), [1, 2, 3]);
+	}
+
+	#[test]
+	fn chunks() {
+		let expected = decode(&mut &IMAGE_SMALL[..]);
+
+		// boundaries inside the magic, the dimensions, and the 5 byte op at 15
+		let cuts = [0, 2, 3, 9, 9, 15, 17, 30, 44];
+		let pixels = QoiDecoder::from_chunks(cuts.windows(2).map(|x| &IMAGE_SMALL[x[0]..x[1]])).expect("error?");
+		let header = pixels.header().clone();
+		assert_eq!((header, pixels.collect::<Result<Vec<_>, _>>().expect("error?")), expected);
+
+		for size in 1..IMAGE_SMALL.len() {
+			let chunks = IMAGE_SMALL.chunks(size).map(<[u8]>::to_vec).chain([vec![9, 9]]);
+			let mut pixels = QoiDecoder::from_chunks(chunks).expect("error?");
+			assert_eq!(pixels.by_ref().collect::<Result<Vec<_>, _>>().expect("error?"), expected.1, "{size}");
+			assert_eq!(pixels.finish().expect("error?"), 44);
+			assert!(pixels.rest().is_empty() && pixels.into_inner().next() == Some(vec![9, 9]), "{size}");
+		}
+	}
+
+	#[test]
+	fn truncated() {
+		// the same errors, at the same offsets, as the reader path
+		for cut in 0..IMAGE_SMALL.len() {
+			let data = &IMAGE_SMALL[..cut];
+			let mut decoder = QoiDecoder::new();
+			decoder.set_strict(true);
+			let expected = qoi::QoiPixels::new(decoder.clone(), &mut &data[..]).map(|x| x.collect::<Result<Vec<_>, _>>());
+
+			let mut decoder = QoiDecoder::new();
+			decoder.set_strict(true);
+			let result = decoder.chunks(data.chunks(3)).map(|x| x.collect::<Result<Vec<_>, _>>());
+			assert_eq!(format!("{result:?}"), format!("{expected:?}"), "{cut}");
+			assert!(matches!(result, Err(Error::UnexpectedEof { .. }) | Ok(Err(Error::UnexpectedEof { .. } | Error::MissingEndMarker { .. }))), "{cut}: {result:?}");
+		}
+	}
+}
@@ -0,0 +1,381 @@
+/*!
+qoi linter.
+
+walks a whole file and reports spec violations and encoding quality,
+rather than just failing on the first problem like `decode` does.
+*/
+
+use super::{END, MASK, OP_RGB, OP_RGBA, OP_RUN, PIXELS_MAX, Qoi, QoiDecoder, QoiHeader, QoiHeaderChannels, best_len, hash, op_len, ready};
+use crate::{Error, Format};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum LintSeverity {
+	Info,
+	Warning,
+	Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum LintKind {
+	InvalidMagic,
+	InvalidHeader,
+	Truncated,
+	RunOverrun,
+	Malformed,
+	MissingEndMarker,
+	TrailingData,
+	/// the header says rgb, but the data uses `OP_RGBA`.
+	ChannelsMismatch,
+	/// more pixels than the spec recommends decoders accept.
+	Oversized,
+	/// ops that have a shorter equivalent.
+	SuboptimalOps,
+	/// re-encoding would make the file smaller.
+	Recompression,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LintFinding {
+	pub severity: LintSeverity,
+	pub kind: LintKind,
+	pub offset: Option<u64>,
+	pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LintReport {
+	/// the header, if it could be read.
+	pub header: Option<QoiHeader>,
+	/// bytes read from the stream.
+	pub size: u64,
+	pub findings: Vec<LintFinding>,
+}
+
+impl LintReport {
+	/// true if nothing worse than informational notes was found.
+	pub fn is_clean(&self) -> bool {
+		self.findings.iter().all(|x| x.severity == LintSeverity::Info)
+	}
+
+	pub fn has_errors(&self) -> bool {
+		self.findings.iter().any(|x| x.severity == LintSeverity::Error)
+	}
+
+	fn push(&mut self, severity: LintSeverity, kind: LintKind, offset: Option<u64>, message: String) {
+		self.findings.push(LintFinding { severity, kind, offset, message });
+	}
+
+	/// records a decode error as a finding. io errors aren't about the file,
+	/// so those are handed back instead.
+	fn fail(&mut self, error: Error) -> Result<(), Error> {
+		let kind = match error {
+			Error::Io(e) => return Err(Error::Io(e)),
+			Error::InvalidMagic { .. } => LintKind::InvalidMagic,
+			Error::InvalidHeader { .. } => LintKind::InvalidHeader,
+			Error::UnexpectedEof { .. } => LintKind::Truncated,
+			Error::RunOverrun { .. } => LintKind::RunOverrun,
+			_ => LintKind::Malformed,
+		};
+		self.push(LintSeverity::Error, kind, error.offset(), error.to_string());
+		Ok(())
+	}
+}
+
+impl core::fmt::Display for LintSeverity {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.write_str(match self {
+			Self::Info => "info",
+			Self::Warning => "warning",
+			Self::Error => "error",
+		})
+	}
+}
+
+impl core::fmt::Display for LintFinding {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self.offset {
+			Some(offset) => write!(f, "{} at byte {}: {}", self.severity, offset, self.message),
+			None => write!(f, "{}: {}", self.severity, self.message),
+		}
+	}
+}
+
+impl core::fmt::Display for LintReport {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match &self.header {
+			Some(x) => writeln!(f, "qoi {}x{} {:?} {:?}, {} bytes", x.width, x.height, x.channels, x.colorspace, self.size)?,
+			None => writeln!(f, "qoi, {} bytes", self.size)?,
+		}
+		if self.findings.is_empty() {
+			writeln!(f, "no findings")?;
+		}
+		for finding in &self.findings {
+			writeln!(f, "{finding}")?;
+		}
+		Ok(())
+	}
+}
+
+/// counts bytes instead of storing them.
+struct Counter(u64);
+
+impl std::io::Write for Counter {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.0 += buf.len() as u64;
+		Ok(buf.len())
+	}
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+/// reads `data` to the end, returning how many bytes there were.
+///
+/// `io::copy` would do, but it panics if the reader claims to have read more
+/// than it was given.
+fn skip(data: &mut impl std::io::Read) -> Result<u64, Error> {
+	let mut buf = [0; 256];
+	let mut total = 0u64;
+	loop {
+		match data.read(&mut buf) {
+			Ok(0) => return Ok(total),
+			Ok(n) => total = total.saturating_add(n.min(buf.len()) as u64),
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e.into()),
+		}
+	}
+}
+
+/// lints a qoi file.
+///
+/// problems with the file itself are findings in the report; the `Err` case
+/// is reserved for the reader failing.
+pub fn lint(data: &mut impl std::io::Read) -> Result<LintReport, Error> {
+	let mut report = LintReport {
+		header: None,
+		size: 0,
+		findings: vec![],
+	};
+
+	let mut decoder = QoiDecoder::new();
+
+	let header = match ready(decoder.poll_header(data)) {
+		Ok(x) => x,
+		Err(e) => {
+			report.fail(e)?;
+			report.size = decoder.offset();
+			return Ok(report);
+		}
+	};
+
+	report.header = Some(header.clone());
+
+	let pixels = header.width as u64 * header.height as u64;
+	if pixels > PIXELS_MAX {
+		report.push(
+			LintSeverity::Warning,
+			LintKind::Oversized,
+			Some(4),
+			format!("{pixels} pixels is more than the recommended limit of {PIXELS_MAX}"),
+		);
+	}
+
+	let mut error = None;
+	let mut rgba_op = None;
+	let mut suboptimal = (0u64, 0u64, None);
+
+	let mut prev = (0, 0, 0, 255);
+	let mut array = [(0, 0, 0, 0); 64];
+
+	// the decoded pixels are fed straight into the encoder, which gives the
+	// size of a re-encode without buffering the image
+	let iter = core::iter::from_fn(|| {
+		let offset = decoder.offset();
+
+		let px = match ready(decoder.poll_pixel(data)) {
+			Ok(x) => x?,
+			Err(e) => {
+				error = Some(e);
+				return None;
+			}
+		};
+
+		// otherwise this pixel continues a run
+		if decoder.offset() != offset {
+			let len = op_len(decoder.op);
+			let best = best_len(px, prev, array[hash(px) & 63] == px);
+			if len > best {
+				suboptimal.0 += 1;
+				suboptimal.1 += (len - best) as u64;
+				suboptimal.2.get_or_insert(offset);
+			}
+
+			if decoder.op == OP_RGBA && header.channels == QoiHeaderChannels::RGB {
+				rgba_op.get_or_insert(offset);
+			}
+
+			// an encoder never adds run pixels to its index, even though the
+			// decoder does, so only track what an encoder could have known
+			if (decoder.op & MASK) != OP_RUN || decoder.op == OP_RGB || decoder.op == OP_RGBA {
+				array[hash(px) & 63] = px;
+			}
+		}
+
+		prev = px;
+
+		Some(px)
+	});
+
+	// the header was just decoded and a counter can't fail, so this can't either
+	let mut counter = Counter(0);
+	_ = Qoi.encode(iter, header.clone(), &mut counter);
+
+	if let Some(offset) = rgba_op {
+		report.push(
+			LintSeverity::Warning,
+			LintKind::ChannelsMismatch,
+			Some(offset),
+			"header declares rgb, but OP_RGBA is used".into(),
+		);
+	}
+
+	if suboptimal.0 > 0 {
+		report.push(
+			LintSeverity::Info,
+			LintKind::SuboptimalOps,
+			suboptimal.2,
+			format!("{} ops have a shorter equivalent, wasting {} bytes", suboptimal.0, suboptimal.1),
+		);
+	}
+
+	if let Some(e) = error {
+		report.fail(e)?;
+		report.size = decoder.offset();
+		return Ok(report);
+	}
+
+	match ready(decoder.poll_end(data)) {
+		Ok(()) => {
+			let size = decoder.offset();
+
+			let trailing = skip(data)?;
+			if trailing > 0 {
+				report.push(
+					LintSeverity::Warning,
+					LintKind::TrailingData,
+					Some(size),
+					format!("{trailing} bytes after the end marker"),
+				);
+			}
+
+			if counter.0 < size {
+				let saved = size - counter.0;
+				report.push(
+					LintSeverity::Info,
+					LintKind::Recompression,
+					None,
+					format!("re-encoding would save {} bytes ({:.1}%)", saved, saved as f64 * 100.0 / size as f64),
+				);
+			}
+
+			report.size = size + trailing;
+		}
+		Err(Error::MissingEndMarker { offset }) => {
+			report.push(
+				LintSeverity::Warning,
+				LintKind::MissingEndMarker,
+				Some(offset),
+				format!("expected end marker {END:?}"),
+			);
+			report.size = offset;
+		}
+		Err(e) => {
+			report.fail(e)?;
+			report.size = decoder.offset();
+		}
+	}
+
+	Ok(report)
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::qoi::{self, LintKind, LintSeverity};
+	use crate::Format;
+
+	fn findings(data: &[u8]) -> Vec<(LintSeverity, LintKind)> {
+		let mut data = data;
+		let report = qoi::lint(&mut data).expect("io error?");
+		report.findings.iter().map(|x| (x.severity, x.kind)).collect()
+	}
+
+	#[test]
+	fn clean() {
+		assert_eq!(findings(include_bytes!("../../test/small.qoi")), []);
+
+		let data = (0..64u8).map(|x| (x, x / 2, 255 - x, 255));
+		let header = qoi::QoiHeader {
+			width: 8,
+			height: 8,
+			channels: qoi::QoiHeaderChannels::RGBA,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+		let mut out = vec![];
+		qoi::Qoi.encode(data, header, &mut out).expect("error?");
+
+		let mut reader = &out[..];
+		let report = qoi::lint(&mut reader).expect("io error?");
+
+		assert!(report.findings.is_empty(), "{report}");
+		assert_eq!(report.size, out.len() as u64);
+	}
+
+	#[test]
+	fn suboptimal() {
+		let mut data = vec![];
+		data.extend_from_slice(b"qoif");
+		data.extend_from_slice(&3u32.to_be_bytes());
+		data.extend_from_slice(&1u32.to_be_bytes());
+		data.extend_from_slice(&[3, 0]);
+		// a literal that could be OP_RGB, then a literal repeat and a tiny diff
+		data.extend_from_slice(&[0xff, 10, 20, 30, 255]);
+		data.extend_from_slice(&[0xfe, 10, 20, 30]);
+		data.extend_from_slice(&[0xfe, 11, 20, 30]);
+		data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+
+		assert_eq!(findings(&data), [
+			(LintSeverity::Warning, LintKind::ChannelsMismatch),
+			(LintSeverity::Info, LintKind::SuboptimalOps),
+			(LintSeverity::Info, LintKind::Recompression),
+		]);
+
+		let mut reader = &data[..];
+		let report = qoi::lint(&mut reader).expect("io error?");
+		assert!(!report.is_clean());
+		assert!(!report.has_errors());
+		assert_eq!(report.findings[1].offset, Some(14));
+		assert!(report.to_string().contains("3 ops have a shorter equivalent"), "{report}");
+	}
+
+	#[test]
+	fn corrupt() {
+		macro_rules! case {
+			($name:literal, $expected:expr) => {
+				let data = include_bytes!(concat!("../../test/corrupt/", $name, ".qoi"));
+				assert_eq!(findings(data), $expected, $name);
+			};
+		}
+
+		case!("bad_magic", [(LintSeverity::Error, LintKind::InvalidMagic)]);
+		case!("bad_channels", [(LintSeverity::Error, LintKind::InvalidHeader)]);
+		case!("truncated_rgba", [(LintSeverity::Error, LintKind::Truncated)]);
+		case!("run_overrun", [(LintSeverity::Error, LintKind::RunOverrun)]);
+		case!("missing_end_marker", [(LintSeverity::Warning, LintKind::MissingEndMarker)]);
+		case!("trailing_garbage", [(LintSeverity::Warning, LintKind::TrailingData)]);
+	}
+}
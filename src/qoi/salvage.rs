@@ -0,0 +1,398 @@
+/*!
+best-effort recovery of damaged qoi files.
+
+qoi has no invalid ops and no checksums, so damage can only be guessed at,
+and everything here is a heuristic.
+
+when the ops don't account for exactly the pixels the header promises,
+the file is assumed to come from an encoder that always picks the shortest
+op (the reference encoder and dwelf's both do), and the first op that breaks
+that rule, or a run past the end of the image, is taken as the start of
+damage. decoding then resumes at the next byte from which the ops parse
+cleanly for `resync_ops` ops and land exactly on the end marker. that fixes
+how many pixels are left, so the rest of the image lines up with the header
+again however many pixels the damage swallowed. the skipped span is set to
+the fill color, as is anything after it that diffs against the lost
+previous pixel. the color index is kept from before the damage, since a
+short damaged span rarely overwrites much of it, so indexed pixels after it
+are a best guess.
+
+files that account for every pixel are decoded as-is, since damage that
+keeps the structure intact only changes colors, which can't be told apart
+from the real image.
+*/
+
+use super::{END, MASK, OP_INDEX, OP_RGB, OP_RGBA, OP_RUN, QoiDecoder, QoiHeader, apply, best_len, hash, op_len, ready};
+use crate::error::oom;
+use crate::{Error, Rgba};
+
+#[derive(Debug, Clone)]
+pub struct SalvageOptions {
+	/// color used for pixels that couldn't be recovered.
+	pub fill: Rgba,
+	/// number of ops that must parse cleanly before decoding resumes.
+	pub resync_ops: usize,
+	/// most pixels a single damaged region may cover. if resyncing would skip
+	/// more, the rest of the image is filled instead.
+	pub max_damage: u64,
+}
+
+impl Default for SalvageOptions {
+	fn default() -> Self {
+		Self {
+			fill: (255, 0, 255, 255),
+			resync_ops: 8,
+			max_damage: u64::MAX,
+		}
+	}
+}
+
+/// a range of pixels, in row-major order, that were filled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DamageRegion {
+	pub start: u64,
+	pub len: u64,
+	/// byte at which the damage was noticed.
+	pub offset: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Salvaged {
+	pub header: QoiHeader,
+	/// always `width * height` pixels.
+	pub pixels: Vec<Rgba>,
+	pub damage: Vec<DamageRegion>,
+}
+
+impl Salvaged {
+	pub fn is_damaged(&self) -> bool {
+		!self.damage.is_empty()
+	}
+
+	fn truncate(&mut self, len: u64) {
+		self.pixels.truncate(len as usize);
+		self.damage.retain_mut(|x| {
+			x.len = x.len.min(len.saturating_sub(x.start));
+			x.len > 0
+		});
+	}
+
+	fn push(&mut self, px: Option<Rgba>, fill: Rgba, offset: u64) {
+		let Some(px) = px else {
+			let index = self.pixels.len() as u64;
+			match self.damage.last_mut() {
+				Some(x) if x.start + x.len == index => x.len += 1,
+				_ => self.damage.push(DamageRegion { start: index, len: 1, offset }),
+			}
+			self.pixels.push(fill);
+			return;
+		};
+		self.pixels.push(px);
+	}
+}
+
+/// marks positions where parsing doesn't land exactly on the end marker.
+const NOWHERE: u64 = u64::MAX;
+
+fn is_run(b0: u8) -> bool {
+	(b0 & MASK) == OP_RUN && b0 != OP_RGB && b0 != OP_RGBA
+}
+
+/// number of pixels the op starting with `b0` produces.
+fn op_pixels(b0: u8) -> u64 {
+	if is_run(b0) {
+		(b0 & 0b111111) as u64 + 1
+	} else {
+		1
+	}
+}
+
+/// whether the ops from `pos` look like a sane op stream, ignoring colors.
+/// the only thing that can be checked without knowing the colors is that no
+/// two runs are ever split needlessly.
+fn plausible(ops: &[u8], mut pos: usize, count: usize) -> bool {
+	let mut short_run = false;
+	for _ in 0..count {
+		let Some(&b0) = ops.get(pos) else {
+			return true;
+		};
+		if is_run(b0) {
+			if short_run {
+				return false;
+			}
+			short_run = op_pixels(b0) < 62;
+		}
+		else {
+			short_run = false;
+		}
+		pos += op_len(b0);
+	}
+	pos <= ops.len()
+}
+
+/// decodes `data`, filling in what can't be recovered instead of failing.
+///
+/// only an unreadable header is an error.
+pub fn salvage(data: &[u8], options: &SalvageOptions) -> Result<Salvaged, Error> {
+	let mut decoder = QoiDecoder::new();
+	let header = ready(decoder.poll_header(&mut &data[..]))?;
+	let start = decoder.offset() as usize;
+
+	let total = header.width as u64 * header.height as u64;
+
+	let mut out = Salvaged {
+		header,
+		pixels: vec![],
+		damage: vec![],
+	};
+	out.pixels.try_reserve_exact(total.try_into().unwrap_or(usize::MAX))
+		.map_err(|_| oom())?;
+
+	let anchored = data.len() >= start + END.len() && data[data.len() - END.len()..] == END;
+	let ops = &data[..if anchored { data.len() - END.len() } else { data.len() }];
+
+	// pixels produced by parsing from each byte to the end marker. the last
+	// byte of an op can be the first of a valid parse too, so this is built
+	// for every position, not just op boundaries.
+	let mut suffix = vec![];
+	if anchored {
+		suffix = vec![NOWHERE; ops.len() + 1];
+		suffix[ops.len()] = 0;
+		for pos in (start..ops.len()).rev() {
+			let next = pos + op_len(ops[pos]);
+			if next <= ops.len() && suffix[next] != NOWHERE {
+				suffix[pos] = suffix[next] + op_pixels(ops[pos]);
+			}
+		}
+	}
+
+	// only look for damage when the structure is known to be broken
+	let checked = !anchored || suffix[start] != total;
+
+	let mut pos = start;
+
+	// colors are `None` once they depend on something lost to damage
+	let mut px = Some((0, 0, 0, 255));
+	let mut alpha = 255;
+	let mut array = [Some((0, 0, 0, 0)); 64];
+	// the encoder's view of the index, which skips run pixels
+	let mut encoder = [Some((0, 0, 0, 0)); 64];
+	let mut short_run = false;
+
+	// where the op before the current one started, in pixels and bytes
+	let mut back = (0, pos);
+
+	while (out.pixels.len() as u64) < total {
+		let index = out.pixels.len() as u64;
+		let remaining = total - index;
+
+		let before = pos;
+		let op = ops.get(pos).and_then(|&b0| ops.get(pos..pos + op_len(b0)));
+
+		let ok = match op {
+			None => false,
+			Some(op) if is_run(op[0]) => {
+				let n = op_pixels(op[0]);
+				if n > remaining || checked && short_run {
+					false
+				}
+				else {
+					for _ in 0..n {
+						out.push(px, options.fill, pos as u64);
+					}
+					short_run = n < 62;
+					pos += 1;
+					true
+				}
+			}
+			Some(op) => {
+				let b0 = op[0];
+				let next = match b0 {
+					OP_RGB => Some((op[1], op[2], op[3], alpha)),
+					OP_RGBA => Some((op[1], op[2], op[3], op[4])),
+					c if (c & MASK) == OP_INDEX => array[(c & 0b111111) as usize],
+					_ => px.map(|mut x| {
+						apply(op, &mut x, &[(0, 0, 0, 0); 64]);
+						x
+					}),
+				};
+
+				// an op an encoder that always picks the shortest op wouldn't write
+				let shorter = match (next, px) {
+					(Some(next), Some(prev)) => {
+						let indexed = encoder[hash(next) & 63] == Some(next);
+						next == prev
+							|| (b0 & MASK) != OP_INDEX && indexed
+							|| op.len() > best_len(next, prev, indexed)
+					}
+					_ => false,
+				};
+
+				if checked && shorter {
+					false
+				}
+				else {
+					if let Some(x) = next {
+						array[hash(x) & 63] = Some(x);
+						encoder[hash(x) & 63] = Some(x);
+						alpha = x.3;
+					}
+					px = next;
+					out.push(px, options.fill, pos as u64);
+					short_run = false;
+					pos += op.len();
+					true
+				}
+			}
+		};
+
+		if ok {
+			back = (index, before);
+			continue;
+		}
+
+		// out of data, so there's nothing to pick up again from
+		if op.is_none() && !anchored {
+			while (out.pixels.len() as u64) < total {
+				out.push(None, options.fill, pos as u64);
+			}
+			break;
+		}
+
+		// damage tends to be noticed an op late, after it has already been
+		// decoded as something plausible, so the op before is thrown out too
+		out.truncate(back.0);
+		pos = back.1;
+		let index = back.0;
+		let remaining = total - index;
+
+		// look for somewhere to pick up again
+		let resume = (pos + 1..=ops.len()).find_map(|q| {
+			let at = if anchored {
+				if suffix[q] == NOWHERE || suffix[q] > remaining {
+					return None;
+				}
+				total - suffix[q]
+			} else {
+				index
+			};
+			(at - index <= options.max_damage && plausible(ops, q, options.resync_ops)).then_some((q, at))
+		});
+
+		let (q, at) = resume.unwrap_or((ops.len(), total));
+
+		while (out.pixels.len() as u64) < at {
+			out.push(None, options.fill, pos as u64);
+		}
+
+		pos = q;
+		back = (at, q);
+		px = None;
+		short_run = false;
+	}
+
+	Ok(out)
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::qoi::{self, DamageRegion, SalvageOptions};
+	use crate::{Format, Rgba};
+
+	/// noisy image that's mostly literal ops, with a few runs.
+	fn image() -> Vec<Rgba> {
+		let mut state = 0x2545f491u32;
+		(0..48 * 48).map(|i| {
+			state ^= state << 13;
+			state ^= state >> 17;
+			state ^= state << 5;
+			if i % 7 < 2 {
+				(10, 20, 30, 255)
+			} else {
+				(state as u8, (state >> 8) as u8, (state >> 16) as u8, 255)
+			}
+		}).collect()
+	}
+
+	fn encode(data: &[Rgba]) -> Vec<u8> {
+		let header = qoi::QoiHeader {
+			width: 48,
+			height: 48,
+			channels: qoi::QoiHeaderChannels::RGB,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+		let mut out = vec![];
+		qoi::Qoi.encode(data.iter().cloned(), header, &mut out).expect("error?");
+		out
+	}
+
+	#[test]
+	fn intact() {
+		let data = image();
+		let file = encode(&data);
+
+		let salvaged = qoi::salvage(&file, &SalvageOptions::default()).expect("error?");
+
+		assert!(!salvaged.is_damaged());
+		assert_eq!(salvaged.pixels, data);
+	}
+
+	#[test]
+	fn damaged() {
+		let data = image();
+		let mut file = encode(&data);
+
+		// pixel that the byte at `at` belongs to
+		let at = file.len() / 2;
+		let mut decoder = qoi::QoiDecoder::new();
+		let mut reader = &file[..];
+		let mut pixel = 0;
+		while decoder.offset() <= at as u64 {
+			_ = decoder.poll_pixel(&mut reader).expect("error?");
+			pixel += 1;
+		}
+
+		// turn a literal into a run, which throws off the pixel count
+		file[at] = 0xc5;
+		file[at + 1] ^= 0x55;
+		file[at + 2] = 0x81;
+
+		let options = SalvageOptions {
+			fill: (1, 2, 3, 4),
+			..SalvageOptions::default()
+		};
+		let salvaged = qoi::salvage(&file, &options).expect("error?");
+
+		assert_eq!(salvaged.pixels.len(), data.len());
+		assert!(salvaged.is_damaged());
+
+		let first = salvaged.damage.first().unwrap();
+		let last = salvaged.damage.last().unwrap();
+		let end = last.start + last.len;
+
+		// the damage starts near the corruption and doesn't go on for long
+		assert!(first.start + 8 >= pixel && first.start <= pixel + 8, "{:?} around {}", salvaged.damage, pixel);
+		assert!(end < pixel + 64, "{:?} around {}", salvaged.damage, pixel);
+		assert!(salvaged.damage.iter().all(|x: &DamageRegion| x.offset as usize >= at - 8));
+
+		// everything before and after is intact
+		assert_eq!(salvaged.pixels[..first.start as usize - 8], data[..first.start as usize - 8]);
+		assert_eq!(salvaged.pixels[end as usize..], data[end as usize..]);
+		for x in &salvaged.damage {
+			assert!(salvaged.pixels[x.start as usize..(x.start + x.len) as usize].iter().all(|&px| px == (1, 2, 3, 4)));
+		}
+	}
+
+	#[test]
+	fn truncated() {
+		let data = image();
+		let file = encode(&data);
+
+		let salvaged = qoi::salvage(&file[..file.len() / 2], &SalvageOptions::default()).expect("error?");
+
+		let last = salvaged.damage.last().unwrap();
+		assert_eq!(last.start + last.len, data.len() as u64);
+		assert_eq!(salvaged.pixels[..last.start as usize], data[..last.start as usize]);
+	}
+}
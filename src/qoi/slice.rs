@@ -0,0 +1,193 @@
+/*!
+decoding from a slice, for images that are already in memory, and
+encoding into one.
+
+there's no reader to go through and nothing is buffered: each op is read
+straight out of the slice, and whatever follows the image is borrowed from
+it rather than copied. neither way needs std or an allocator.
+*/
+
+use core::task::Poll;
+
+use super::{END, MASK, OP_RGB, OP_RGBA, OP_RUN, QoiDecoder, QoiEncoder, QoiHeader, apply, hash, op_len};
+use crate::{Error, Rgba};
+
+/// pixels of a qoi image in a slice. like `QoiPixels`, the first error is
+/// yielded once, after which it ends.
+#[derive(Debug, Clone)]
+pub struct SlicePixels<'a> {
+	data: &'a [u8],
+	/// where the next op starts.
+	at: usize,
+	px: Rgba,
+	array: [Rgba; 64],
+	run: u8,
+	/// pixels left to decode.
+	total: u64,
+	failed: bool,
+}
+
+/// reads the header of the image at the start of `data`, and returns it with
+/// its pixels.
+pub fn decode_slice(data: &[u8]) -> Result<(QoiHeader, SlicePixels<'_>), Error> {
+	let header = match QoiDecoder::new().poll_header(&mut &data[..])? {
+		Poll::Ready(header) => header,
+		// a slice runs out rather than blocking
+		Poll::Pending => return Err(Error::UnexpectedEof { offset: data.len() as u64 }),
+	};
+	let pixels = SlicePixels {
+		data,
+		at: 14,
+		px: (0, 0, 0, 255),
+		array: [(0, 0, 0, 0); 64],
+		run: 0,
+		total: header.width as u64 * header.height as u64,
+		failed: false,
+	};
+	Ok((header, pixels))
+}
+
+/// encodes an image into the start of `out`, and returns how many bytes it
+/// took up. `data` has to have as many pixels as the header has room for,
+/// or it's a `PixelCountMismatch`, and more are left alone. an `out` that's
+/// too small is a `BufferSize`, whose `expected` is how far the encoder got,
+/// so only a lower bound on what it needs.
+pub fn encode_slice(data: impl Iterator<Item = Rgba>, header: &QoiHeader, out: &mut [u8]) -> Result<usize, Error> {
+	let mut encoder = QoiEncoder::new(header, crate::io::SliceWriter { buf: out, pos: 0 })?;
+	crate::push_exact(data, encoder.remaining(), |px| encoder.push(px))?;
+	Ok(encoder.finish()?.pos)
+}
+
+impl<'a> SlicePixels<'a> {
+	fn fail(&mut self, e: Error) -> Option<Result<Rgba, Error>> {
+		self.failed = true;
+		Some(Err(e))
+	}
+
+	/// skips any pixels that are left, checks the end marker, and returns
+	/// what comes after it.
+	pub fn finish(mut self) -> Result<&'a [u8], Error> {
+		for px in &mut self {
+			px?;
+		}
+		match self.data.get(self.at..) {
+			Some(rest) if rest.starts_with(&END) => Ok(&rest[END.len()..]),
+			_ => Err(Error::MissingEndMarker { offset: self.at as u64 }),
+		}
+	}
+}
+
+impl Iterator for SlicePixels<'_> {
+	type Item = Result<Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.total == 0 {
+			return None;
+		}
+		if self.run > 0 {
+			self.run -= 1;
+			self.total -= 1;
+			return Some(Ok(self.px));
+		}
+
+		let b0 = match self.data.get(self.at) {
+			Some(&b0) => b0,
+			None => return self.fail(Error::UnexpectedEof { offset: self.data.len() as u64 }),
+		};
+		let Some(op) = self.data.get(self.at..self.at + op_len(b0)) else {
+			return self.fail(Error::UnexpectedEof { offset: self.data.len() as u64 });
+		};
+
+		if (b0 & MASK) == OP_RUN && b0 != OP_RGB && b0 != OP_RGBA {
+			let run = b0 & 0b111111;
+			if run as u64 >= self.total {
+				return self.fail(Error::RunOverrun { offset: self.at as u64 });
+			}
+			self.run = run;
+		}
+		else {
+			apply(op, &mut self.px, &self.array);
+		}
+
+		self.at += op.len();
+		self.array[hash(self.px) & 63] = self.px;
+		self.total -= 1;
+		Some(Ok(self.px))
+	}
+
+	/// like `QoiPixels`, a run's pixels for sure, and at most the ones the
+	/// header has left.
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		if self.failed {
+			return (0, Some(0));
+		}
+		(self.run as usize, self.total.try_into().ok())
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, qoi};
+
+	const IMAGE_SMALL: &[u8; 44] = include_bytes!("../../test/small.qoi");
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn decode_slice() {
+		use crate::Format;
+
+		let (header, pixels) = qoi::decode_slice(IMAGE_SMALL).expect("error?");
+		assert_eq!(header, qoi::decode_to_vec(IMAGE_SMALL).expect("error?").0);
+		assert_eq!(pixels.size_hint(), (0, Some(16)));
+		let decoded = pixels.clone().collect::<Result<Vec<_>, _>>().expect("error?");
+		assert_eq!(decoded, qoi::decode_to_vec(IMAGE_SMALL).expect("error?").1);
+		assert!(pixels.finish().expect("error?").is_empty());
+
+		// the same errors, at the same offsets, as through a reader
+		for len in 0..IMAGE_SMALL.len() {
+			let data = &IMAGE_SMALL[..len];
+			let expected = qoi::Qoi.decode_checked(&mut &data[..]).map(|(_, iter)| format!("{:?}", iter.collect::<Vec<_>>()));
+			let got = qoi::decode_slice(data).map(|(_, iter)| format!("{:?}", iter.collect::<Vec<_>>()));
+			assert_eq!(format!("{got:?}"), format!("{expected:?}"), "{len}");
+		}
+
+		// what follows is borrowed
+		let data = [&IMAGE_SMALL[..], b"next"].concat();
+		let (_, pixels) = qoi::decode_slice(&data).expect("error?");
+		assert_eq!(pixels.finish().expect("error?"), b"next");
+		let (_, pixels) = qoi::decode_slice(&IMAGE_SMALL[..40]).expect("error?");
+		assert!(matches!(pixels.finish(), Err(Error::MissingEndMarker { offset: 36 })));
+	}
+
+	/// runs without std, so it sticks to arrays.
+	#[test]
+	fn encode_slice() {
+		let (header, pixels) = qoi::decode_slice(IMAGE_SMALL).expect("error?");
+		let mut decoded = [(0, 0, 0, 0); 16];
+		for (to, px) in decoded.iter_mut().zip(pixels) {
+			*to = px.expect("error?");
+		}
+
+		let mut out = [0; 64];
+		let n = qoi::encode_slice(decoded.iter().copied(), &header, &mut out).expect("error?");
+		assert_eq!(out[..n], IMAGE_SMALL[..]);
+
+		// and back again, fed a few bytes at a time
+		let mut decoder = qoi::QoiDecoder::new();
+		let mut again = decoded.iter();
+		for chunk in out[..n].chunks(5) {
+			for px in decoder.feed(chunk) {
+				assert_eq!(Some(&px.expect("error?")), again.next());
+			}
+		}
+		assert!(decoder.is_finished() && again.next().is_none());
+
+		for len in [0, 13, 20, 43] {
+			let result = qoi::encode_slice(decoded.iter().copied(), &header, &mut out[..len]);
+			assert!(matches!(result, Err(Error::BufferSize { expected, got }) if expected > len as u64 && got == len as u64), "{len}: {result:?}");
+		}
+		let result = qoi::encode_slice(decoded[1..].iter().copied(), &header, &mut out);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 16, got: 15 })), "{result:?}");
+	}
+}
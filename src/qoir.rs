@@ -0,0 +1,525 @@
+/*!
+qoir images, lossless ones, which are qoi's ops cut into tiles and
+optionally lz4 compressed. there's no encoder.
+
+a file is a list of chunks, each a 4-byte tag and a little-endian 64-bit
+length, then that many bytes. the first is `QOIR`, whose 8 bytes are two
+little-endian 32-bit numbers. the low 24 bits of the first are the width,
+and its top 8 are the pixel format: 1 for opaque, 2 for straight alpha, 3
+for premultiplied alpha. the low 24 bits of the second are the height, and
+its top 8 are the lossiness, which has to be 0. the pixels are in a `QPIX`
+chunk, and the last chunk is an empty `QEND`. any others, such as `ICCP`
+or `XMPP`, are skipped.
+
+`QPIX` is 64 by 64 tiles, left to right then top to bottom, cut short at
+the right and bottom edges. each starts with 4 bytes: its format, then the
+length of the rest as a little-endian 24-bit number. format 0 is literals,
+4 bytes a pixel, blue, green, red and alpha. format 1 is ops. formats 2 and
+3 are those as an lz4 block.
+
+ops are qoi's, with the tag in the low bits of the first byte, and blue
+before red:
+
+- `0b00` and a 6-bit index into qoi's array of 64 recent pixels.
+- `0b01` and a green difference of -32 to 31, then a byte of the red and
+  blue differences from it, -8 to 7, in its top and low 4 bits.
+- `0b10` and differences of red, green and blue, -2 to 1, from the low
+  bits up.
+- `0b011` and 3 bytes of 7-bit differences of blue, green and red, -64 to
+  63, from the low bits up.
+- `0b111` and a run of 1 to 29 repeats of the last pixel, whenever its top
+  5 bits are 28 or less.
+- `0xef`, then a byte of a run of 30 to 285.
+- `0xf7`, then blue, green and red.
+- `0xff`, then blue, green, red and alpha.
+
+differences wrap around. every tile starts again from opaque black and an
+empty array, and has to end where its pixels do. premultiplied pixels are
+handed out straight.
+
+a row of tiles is read at a time, as its first pixel is asked for.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+mod lz4;
+
+const MAGIC: &[u8; 4] = b"QOIR";
+/// a chunk's tag and length.
+const CHUNK_HEADER: u64 = 12;
+const HEADER_SIZE: u64 = CHUNK_HEADER + 8;
+const TILE: u32 = 64;
+/// the longest a tile's ops can be, at 5 bytes a pixel.
+const OPS_MAX: usize = (TILE * TILE * 5) as usize;
+/// the longest a tile can be, which is its ops once lz4 has failed to
+/// compress them.
+const TILE_MAX: usize = OPS_MAX + OPS_MAX / 255 + 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum QoirAlpha {
+	/// every pixel's alpha is ignored, and read as 255.
+	Opaque,
+	Straight,
+	/// colors are multiplied by alpha, and are divided back out as they're
+	/// read.
+	Premultiplied,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct QoirHeader {
+	pub width: u32,
+	pub height: u32,
+	pub alpha: QoirAlpha,
+}
+
+impl crate::Dimensions for QoirHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// reads past `n` bytes, which start at `offset`.
+fn skip(data: &mut impl std::io::Read, mut n: u64, mut offset: u64) -> Result<(), Error> {
+	let mut buf = [0; 512];
+	while n > 0 {
+		let len = n.min(buf.len() as u64) as usize;
+		crate::read_exact(data, &mut buf[..len], offset)?;
+		offset += len as u64;
+		n -= len as u64;
+	}
+	Ok(())
+}
+
+fn hash((r, g, b, a): crate::Rgba) -> usize {
+	(r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64
+}
+
+/// decodes a tile's literals, which have to be exactly its `n` pixels.
+fn literals(data: &[u8], n: usize, out: &mut Vec<crate::Rgba>, offset: u64) -> Result<(), Error> {
+	if data.len() != n * 4 {
+		return Err(Error::Malformed { offset, reason: "literals aren't 4 bytes a pixel" });
+	}
+	out.extend(data.chunks_exact(4).map(|c| (c[2], c[1], c[0], c[3])));
+	Ok(())
+}
+
+/// decodes a tile's ops, which have to come to exactly its `n` pixels. `at`
+/// turns a position in `data` into an offset to report.
+fn ops(data: &[u8], n: usize, out: &mut Vec<crate::Rgba>, at: impl Fn(usize) -> u64) -> Result<(), Error> {
+	let mut px: crate::Rgba = (0, 0, 0, 255);
+	let mut cache = [(0, 0, 0, 0); 64];
+	let (mut i, mut left) = (0, n);
+	while left > 0 {
+		let short = Error::Malformed { offset: at(i), reason: "tile ends before its pixels do" };
+		let Some(&b0) = data.get(i) else {
+			return Err(short);
+		};
+		let len = match b0 {
+			0xef => 2,
+			0xf7 => 4,
+			0xff => 5,
+			_ if b0 & 3 == 1 => 2,
+			_ if b0 & 7 == 3 => 3,
+			_ => 1,
+		};
+		let Some(op) = data.get(i..i + len) else {
+			return Err(short);
+		};
+
+		let mut run = 0;
+		match b0 & 3 {
+			0 => px = cache[(b0 >> 2) as usize],
+			1 => {
+				let vg = (b0 >> 2).wrapping_sub(32);
+				let vr = vg.wrapping_add(op[1] >> 4).wrapping_sub(8);
+				let vb = vg.wrapping_add(op[1] & 15).wrapping_sub(8);
+				px = (px.0.wrapping_add(vr), px.1.wrapping_add(vg), px.2.wrapping_add(vb), px.3);
+			}
+			2 => {
+				let diff = |shift: u8| (b0 >> shift & 3).wrapping_sub(2);
+				px = (px.0.wrapping_add(diff(2)), px.1.wrapping_add(diff(4)), px.2.wrapping_add(diff(6)), px.3);
+			}
+			_ if b0 & 7 == 3 => {
+				let bits = u32::from_le_bytes([op[0], op[1], op[2], 0]);
+				let diff = |shift: u32| ((bits >> shift & 127) as u8).wrapping_sub(64);
+				px = (px.0.wrapping_add(diff(17)), px.1.wrapping_add(diff(10)), px.2.wrapping_add(diff(3)), px.3);
+			}
+			_ => match b0 {
+				0xef => run = op[1] as usize + 30,
+				0xf7 => px = (op[3], op[2], op[1], px.3),
+				0xff => px = (op[3], op[2], op[1], op[4]),
+				_ => run = (b0 >> 3) as usize + 1,
+			},
+		}
+
+		if run == 0 {
+			cache[hash(px)] = px;
+			run = 1;
+		} else if run > left {
+			return Err(Error::RunOverrun { offset: at(i) });
+		}
+		out.extend(core::iter::repeat_n(px, run));
+		left -= run;
+		i += len;
+	}
+	if i < data.len() {
+		return Err(Error::Malformed { offset: at(i), reason: "tile goes on past its pixels" });
+	}
+	Ok(())
+}
+
+/// pixels of a qoir image, read from the reader a row of tiles at a time as
+/// they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct QoirPixels<'a, R> {
+	header: QoirHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// what's left of the `QPIX` chunk.
+	left: u64,
+	/// the current row of tiles, one tile after another.
+	tiles: Vec<crate::Rgba>,
+	/// a tile as it's read, and once lz4 has decompressed it.
+	buf: Vec<u8>,
+	raw: Vec<u8>,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> QoirPixels<'a, R> {
+	/// reads the header, and skips the chunks up to the pixels.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; HEADER_SIZE as usize];
+		crate::read_exact(data, &mut buf, 0)?;
+		if &buf[..4] != MAGIC {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		if buf[4..12] != 8u64.to_le_bytes() {
+			return Err(field(4, "header length"));
+		}
+		let at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+		let (width, height) = (at(12) & 0xff_ffff, at(16) & 0xff_ffff);
+		if width == 0 {
+			return Err(field(12, "width"));
+		}
+		if height == 0 {
+			return Err(field(16, "height"));
+		}
+		let alpha = match buf[15] {
+			1 => QoirAlpha::Opaque,
+			2 => QoirAlpha::Straight,
+			3 => QoirAlpha::Premultiplied,
+			_ => return Err(field(15, "pixel format")),
+		};
+		if buf[19] != 0 {
+			return Err(Error::Unsupported { what: "lossy qoir" });
+		}
+
+		let mut pixels = Self {
+			header: QoirHeader { width, height, alpha },
+			data,
+			offset: HEADER_SIZE,
+			left: 0,
+			tiles: vec![],
+			buf: vec![],
+			raw: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		};
+		loop {
+			let (tag, len) = pixels.chunk()?;
+			match &tag {
+				b"QPIX" => break,
+				b"QEND" => return Err(Error::Malformed { offset: pixels.offset - CHUNK_HEADER, reason: "there are no pixels" }),
+				_ => pixels.read_past(len)?,
+			}
+		}
+		Ok(pixels)
+	}
+
+	pub fn header(&self) -> &QoirHeader {
+		&self.header
+	}
+
+	/// reads a chunk's tag and length, and leaves the length in `left`.
+	fn chunk(&mut self) -> Result<([u8; 4], u64), Error> {
+		let mut buf = [0; CHUNK_HEADER as usize];
+		crate::read_exact(self.data, &mut buf, self.offset)?;
+		self.offset = self.offset.saturating_add(CHUNK_HEADER);
+		let [tag @ .., _, _, _, _, _, _, _, _] = buf;
+		let [.., a, b, c, d, e, f, g, h] = buf;
+		self.left = u64::from_le_bytes([a, b, c, d, e, f, g, h]);
+		Ok((tag, self.left))
+	}
+
+	fn read_past(&mut self, len: u64) -> Result<(), Error> {
+		skip(self.data, len, self.offset)?;
+		self.offset = self.offset.saturating_add(len);
+		Ok(())
+	}
+
+	/// reads `len` bytes into `buf`, out of what's left of the pixels.
+	fn read(&mut self, len: usize) -> Result<(), Error> {
+		if len as u64 > self.left {
+			return Err(Error::Malformed { offset: self.offset, reason: "tile goes past the pixels" });
+		}
+		self.buf.clear();
+		self.buf.try_reserve(len).map_err(|_| oom())?;
+		self.buf.resize(len, 0);
+		crate::read_exact(self.data, &mut self.buf, self.offset)?;
+		self.offset += len as u64;
+		self.left -= len as u64;
+		Ok(())
+	}
+
+	/// reads and decodes the next tile, of `n` pixels, onto `tiles`.
+	fn read_tile(&mut self, n: usize) -> Result<(), Error> {
+		let start = self.offset;
+		if self.left < 4 {
+			return Err(Error::Malformed { offset: start, reason: "tile goes past the pixels" });
+		}
+		let mut head = [0; 4];
+		crate::read_exact(self.data, &mut head, start)?;
+		self.offset += 4;
+		self.left -= 4;
+		let [format, a, b, c] = head;
+		let len = u32::from_le_bytes([a, b, c, 0]) as usize;
+		if len > TILE_MAX {
+			return Err(Error::Malformed { offset: start, reason: "tile is too long" });
+		}
+		self.read(len)?;
+
+		let data = start + 4;
+		self.tiles.try_reserve(n).map_err(|_| oom())?;
+		let lz4 = |raw: &mut Vec<u8>, buf: &[u8], max: usize| {
+			raw.clear();
+			raw.try_reserve(max).map_err(|_| oom())?;
+			lz4::decompress(buf, raw, max).map_err(|(i, reason)| Error::Malformed { offset: data + i as u64, reason })
+		};
+		match format {
+			0 => literals(&self.buf, n, &mut self.tiles, data),
+			1 => ops(&self.buf, n, &mut self.tiles, |i| data + i as u64),
+			2 => {
+				lz4(&mut self.raw, &self.buf, n * 4)?;
+				literals(&self.raw, n, &mut self.tiles, data)
+			}
+			3 => {
+				lz4(&mut self.raw, &self.buf, n * 5)?;
+				// an offset in the decompressed ops means nothing in the file
+				ops(&self.raw, n, &mut self.tiles, |_| data)
+			}
+			_ => Err(Error::Malformed { offset: start, reason: "unknown tile format" }),
+		}
+	}
+
+	/// reads the row of tiles that starts at `y`.
+	fn read_tiles(&mut self) -> Result<(), Error> {
+		self.tiles.clear();
+		let th = (self.header.height - self.y).min(TILE) as usize;
+		for tx in (0..self.header.width).step_by(TILE as usize) {
+			let tw = (self.header.width - tx).min(TILE) as usize;
+			self.read_tile(tw * th)?;
+		}
+		Ok(())
+	}
+
+	/// skips what's left of the pixels, unread, and the chunks after them,
+	/// and returns the number of bytes the image took up, `QEND` included.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		self.read_past(self.left)?;
+		loop {
+			let (tag, len) = self.chunk()?;
+			match &tag {
+				b"QEND" if len == 0 => return Ok(self.offset),
+				b"QEND" => return Err(Error::Malformed { offset: self.offset - 8, reason: "end chunk isn't empty" }),
+				_ => self.read_past(len)?,
+			}
+		}
+	}
+}
+
+impl<R: std::io::Read> Iterator for QoirPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		if self.x == 0
+			&& self.y.is_multiple_of(TILE)
+			&& let Err(e) = self.read_tiles()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let (width, height) = (self.header.width, self.header.height);
+		let th = (height - self.y / TILE * TILE).min(TILE) as usize;
+		let tx = self.x / TILE * TILE;
+		let tw = (width - tx).min(TILE) as usize;
+		let i = tx as usize * th + (self.y % TILE) as usize * tw + (self.x - tx) as usize;
+		let (r, g, b, a) = self.tiles[i];
+		let px = match self.header.alpha {
+			QoirAlpha::Opaque => (r, g, b, 255),
+			QoirAlpha::Straight => (r, g, b, a),
+			QoirAlpha::Premultiplied if a == 0 => (0, 0, 0, 0),
+			QoirAlpha::Premultiplied => {
+				let straight = |c: u8| ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+				(straight(r), straight(g), straight(b), a)
+			}
+		};
+
+		self.x += 1;
+		if self.x == width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Qoir;
+
+impl crate::Format for Qoir {
+	type Header = QoirHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = QoirPixels::new(data)?;
+		let header = pixels.header().clone();
+		Ok((header, pixels.map_while(Result::ok)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = QoirPixels::new(data)?;
+		let header = pixels.header().clone();
+		Ok((header, pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		_ = (data, header, out);
+		Err(Error::Unsupported { what: "encoding qoir" })
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, qoir};
+	use crate::qoir::QoirAlpha;
+
+	fn gradient(x: u32, y: u32) -> crate::Rgba {
+		match (x, y) {
+			(_, 10..13) => (9, 8, 7, 255),
+			_ if x % 17 == 5 => (200, 100, 50, 255),
+			_ => ((x * 3) as u8, (y * 3) as u8, (x * y) as u8, 255),
+		}
+	}
+
+	fn alpha(x: u32, y: u32) -> crate::Rgba {
+		((x * 19) as u8, (y * 50) as u8, ((x + y) * 10) as u8, (x * 20 + y * 3) as u8)
+	}
+
+	fn stripes(x: u32, y: u32) -> crate::Rgba {
+		[(255, 0, 0, 255), (0, 255, 0, 128), (0, 0, 255, 255)][((x / 3 + y) % 3) as usize]
+	}
+
+	fn premultiplied(x: u32, y: u32) -> crate::Rgba {
+		let a = [0, 51, 128, 255][((x + y) % 4) as usize];
+		let straight = |c: u32| (c * 255 + a / 2).checked_div(a).unwrap_or(0) as u8;
+		(straight(a * x / 5), straight(a * y / 3), straight(a / 2), a as u8)
+	}
+
+	#[test]
+	fn decode() {
+		// ops across tile edges, literals after a skipped chunk, and both
+		// compressed
+		for (image, width, height, alpha, expected) in [
+			(&include_bytes!("../test/gradient.qoir")[..], 80, 70, QoirAlpha::Opaque, gradient as fn(u32, u32) -> crate::Rgba),
+			(include_bytes!("../test/alpha.qoir"), 13, 5, QoirAlpha::Straight, alpha),
+			(include_bytes!("../test/lz4.qoir"), 100, 20, QoirAlpha::Straight, stripes),
+			(include_bytes!("../test/premul.qoir"), 6, 4, QoirAlpha::Premultiplied, premultiplied),
+		] {
+			let mut data = image;
+			let mut pixels = qoir::QoirPixels::new(&mut data).expect("error?");
+			assert_eq!(pixels.header(), &qoir::QoirHeader { width, height, alpha });
+			let mut n = 0;
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % width, i as u32 / width);
+				assert_eq!(px.expect("error?"), expected(x, y), "{width}: {x}, {y}");
+				n += 1;
+			}
+			assert_eq!(n, width * height);
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			// where it ends is found without handing out every pixel
+			let mut data = image;
+			let mut pixels = qoir::QoirPixels::new(&mut data).expect("error?");
+			assert!(pixels.nth(20).is_some());
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			let mut data = image;
+			let (_, iter) = qoir::Qoir.decode(&mut data).expect("error?");
+			assert!(iter.enumerate().all(|(i, px)| px == expected(i as u32 % width, i as u32 / width)));
+		}
+
+		let header = qoir::QoirHeader { width: 1, height: 1, alpha: QoirAlpha::Opaque };
+		let result = qoir::Qoir.encode([(0, 0, 0, 255)].into_iter(), header, &mut vec![]);
+		assert!(matches!(result, Err(Error::Unsupported { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = qoir::QoirPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".qoir")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("bad_header_length", Error::InvalidHeader { offset: 4, .. });
+		case!("zero_width", Error::InvalidHeader { offset: 12, .. });
+		case!("zero_height", Error::InvalidHeader { offset: 16, .. });
+		case!("bad_pixel_format", Error::InvalidHeader { offset: 15, .. });
+		case!("lossy", Error::Unsupported { .. });
+		case!("no_pixels", Error::Malformed { offset: 20, .. });
+		case!("truncated", Error::UnexpectedEof { offset: 300 });
+		case!("bad_tile_format", Error::Malformed { offset: 32, .. });
+		case!("long_tile", Error::Malformed { offset: 36, .. });
+		case!("short_literals", Error::Malformed { offset: 36, .. });
+		case!("short_tile", Error::Malformed { offset: 41, .. });
+		case!("long_ops", Error::Malformed { offset: 41, .. });
+		case!("run_overrun", Error::RunOverrun { offset: 40 });
+		case!("bad_lz4", Error::Malformed { offset: 41, .. });
+		case!("missing_end", Error::UnexpectedEof { offset: 59 });
+
+		assert!(decode(include_bytes!("../test/gradient.qoir")).is_ok());
+	}
+}
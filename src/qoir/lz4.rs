@@ -0,0 +1,102 @@
+/*!
+lz4 blocks, which qoir compresses tiles as.
+
+a block is sequences, each a token byte whose top 4 bits are a number of
+literals and low 4 a match length past 4, then the literals, a 2-byte
+little-endian offset back into what's been decoded, and the match. either
+length's 15 goes on in the bytes after, which add up until one isn't 255:
+the literals' straight after the token, and the match's after the offset.
+the last sequence is only literals, and ends the block.
+*/
+
+/// reads a length that started as `n` in a token, and goes on at `i` if
+/// it's 15.
+fn length(data: &[u8], i: &mut usize, n: u8) -> Result<usize, (usize, &'static str)> {
+	let mut n = n as usize;
+	if n < 15 {
+		return Ok(n);
+	}
+	loop {
+		let Some(&b) = data.get(*i) else {
+			return Err((*i, "lz4 block ends early"));
+		};
+		*i += 1;
+		n = n.saturating_add(b as usize);
+		if b != 255 {
+			return Ok(n);
+		}
+	}
+}
+
+/// decompresses a block onto `out`, which has room for `max` bytes, and
+/// can't go past them. an error is where it is in `data`, and why.
+pub(super) fn decompress(data: &[u8], out: &mut Vec<u8>, max: usize) -> Result<(), (usize, &'static str)> {
+	let mut i = 0;
+	loop {
+		let Some(&token) = data.get(i) else {
+			return Err((i, "lz4 block ends early"));
+		};
+		i += 1;
+
+		let n = length(data, &mut i, token >> 4)?;
+		let Some(literals) = data.get(i..i.saturating_add(n)) else {
+			return Err((i, "lz4 block ends early"));
+		};
+		if n > max - out.len() {
+			return Err((i, "lz4 block is too long"));
+		}
+		out.extend_from_slice(literals);
+		i += n;
+		if i == data.len() {
+			return Ok(());
+		}
+
+		let Some(&[a, b]) = data.get(i..i + 2) else {
+			return Err((i, "lz4 block ends early"));
+		};
+		let offset = u16::from_le_bytes([a, b]) as usize;
+		if offset == 0 || offset > out.len() {
+			return Err((i, "lz4 match goes back past the start"));
+		}
+		let at = i;
+		i += 2;
+		let n = length(data, &mut i, token & 15)?.saturating_add(4);
+		if n > max - out.len() {
+			return Err((at, "lz4 block is too long"));
+		}
+		// a match can overlap what it's copying, repeating it
+		let start = out.len() - offset;
+		for j in start..start + n {
+			out.push(out[j]);
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use super::decompress;
+
+	#[test]
+	fn decompress_block() {
+		// 4 literals, then a match of 9 that overlaps itself, then 2 more
+		let block = [0x45, b'a', b'b', b'c', b'd', 4, 0, 0x20, b'x', b'y'];
+		let mut out = vec![];
+		decompress(&block, &mut out, 64).expect("error?");
+		assert_eq!(out, b"abcdabcdabcdaxy");
+
+		// lengths of 15 that go on, of 15 + 255 + 3 literals
+		let mut block = vec![0xf0, 255, 3];
+		block.extend([7; 273]);
+		let mut out = vec![];
+		decompress(&block, &mut out, 273).expect("error?");
+		assert_eq!(out, [7; 273]);
+		let mut out = vec![];
+		assert_eq!(decompress(&block, &mut out, 272), Err((3, "lz4 block is too long")));
+
+		for (block, at) in [(&[0x40, 1, 2][..], 1), (&[0x10, 1, 2, 0, 0x10], 2), (&[0x10, 1, 0], 2), (&[][..], 0)] {
+			let mut out = vec![];
+			assert!(matches!(decompress(block, &mut out, 64), Err((i, _)) if i == at), "{block:?}");
+		}
+	}
+}
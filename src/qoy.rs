@@ -0,0 +1,592 @@
+/*!
+an experimental qoi-like format, whose ops work on pixels in ycbcr rather
+than rgb. it's lossy, and not any standard format.
+
+photos change much less in chroma than in any one of red, green or blue, so
+ops that only change luma, or change chroma a little, cover more of them
+than qoi's do. pixels are turned into full-range ycbcr the way jfif does it,
+in 16-bit fixed point, which loses a bit of precision both ways. with
+subsampling, each 2 by 2 block of pixels also gets the average of their
+chroma, so half the pixels or more only change in luma.
+
+the header is the magic `qoyf`, the width and height, as big-endian 32-bit
+numbers, the channels, 3 or 4, and whether chroma is subsampled, 0 or 1.
+then come the ops, which are like qoi's:
+
+- `0xfe`, then luma, blue and red chroma.
+- `0xff`, then luma, blue and red chroma, and alpha.
+- `0b000` and a 5-bit index into an array of 32 recent pixels, hashed the
+  same as qoi's, but of their luma, chroma and alpha.
+- `0b001` and a luma difference of -16 to 15, keeping the rest.
+- `0b01` and 2-bit differences of luma, blue and red chroma, -2 to 1.
+- `0b10` and a luma difference of -32 to 31, then a byte of a blue chroma
+  difference and a red one, both -8 to 7.
+- `0b11` and a run, of 1 to 62 copies of the last pixel, as qoi has it.
+
+all but `0xff` keep alpha.
+
+the last pixel starts out black, and the image ends with the same 8-byte
+marker as qoi's. pixels are decoded as they're asked for, and encoded as
+they're pushed, or two rows at a time when subsampling.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+const MAGIC: [u8; 4] = *b"qoyf";
+const HEADER_SIZE: u64 = 14;
+
+const OP_YCC: u8 = 0b11111110;
+const OP_YCCA: u8 = 0b11111111;
+const OP_INDEX: u8 = 0b000_00000;
+const OP_LUMA: u8 = 0b001_00000;
+const OP_SMALL: u8 = 0b01_000000;
+const OP_DIFF: u8 = 0b10_000000;
+const OP_RUN: u8 = 0b11_000000;
+
+const MASK: u8 = 0b11_000000;
+/// the mask of the index and luma ops, which split `0b00` between them.
+const MASK_3: u8 = 0b111_00000;
+
+const END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+/// black, as luma, chroma and alpha.
+const START: crate::Rgba = (0, 128, 128, 255);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum QoyChannels {
+	RGB,
+	RGBA,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct QoyHeader {
+	pub width: u32,
+	pub height: u32,
+	/// whether alpha is kept. rgb images are encoded opaque.
+	pub channels: QoyChannels,
+	/// whether each 2 by 2 block of pixels shares the average of their
+	/// chroma. decoding doesn't depend on it.
+	pub subsampled: bool,
+}
+
+impl crate::Dimensions for QoyHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Qoy;
+
+impl Default for Qoy {
+	fn default() -> Self {
+		Self
+	}
+}
+
+fn hash(px: crate::Rgba) -> usize {
+	(px.0 as usize * 3 + px.1 as usize * 5 + px.2 as usize * 7 + px.3 as usize * 11) & 31
+}
+
+fn clamp(x: i32) -> u8 {
+	(x >> 16).clamp(0, 255) as u8
+}
+
+/// an rgba pixel as luma, blue and red chroma, and alpha.
+fn ycbcr(px: crate::Rgba) -> crate::Rgba {
+	let (r, g, b) = (px.0 as i32, px.1 as i32, px.2 as i32);
+	let half = 1 << 15;
+	let y = 19595 * r + 38470 * g + 7471 * b + half;
+	let cb = -11059 * r - 21709 * g + 32768 * b + (128 << 16) + half;
+	let cr = 32768 * r - 27439 * g - 5329 * b + (128 << 16) + half;
+	(clamp(y), clamp(cb), clamp(cr), px.3)
+}
+
+/// the other way around.
+fn rgb(px: crate::Rgba) -> crate::Rgba {
+	let (y, cb, cr) = ((px.0 as i32) << 16, px.1 as i32 - 128, px.2 as i32 - 128);
+	let half = 1 << 15;
+	let r = y + 91881 * cr + half;
+	let g = y - 22554 * cb - 46802 * cr + half;
+	let b = y + 116130 * cb + half;
+	(clamp(r), clamp(g), clamp(b), px.3)
+}
+
+/// pixels of a qoy image, read from the reader as they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct QoyPixels<'a, R> {
+	header: QoyHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// the last pixel, in ycbcr.
+	px: crate::Rgba,
+	array: [crate::Rgba; 32],
+	/// copies of `px` that are left of a run.
+	run: u8,
+	/// pixels that haven't been handed out.
+	left: u64,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> QoyPixels<'a, R> {
+	/// reads the header, up to the first op.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; HEADER_SIZE as usize];
+		crate::read_exact(data, &mut buf, 0)?;
+		if buf[..4] != MAGIC {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let width = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+		let height = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+		if width == 0 {
+			return Err(field(4, "width"));
+		}
+		if height == 0 {
+			return Err(field(8, "height"));
+		}
+		let channels = match buf[12] {
+			3 => QoyChannels::RGB,
+			4 => QoyChannels::RGBA,
+			_ => return Err(field(12, "channels")),
+		};
+		let subsampled = match buf[13] {
+			0 => false,
+			1 => true,
+			_ => return Err(field(13, "subsampling")),
+		};
+		Ok(Self {
+			header: QoyHeader { width, height, channels, subsampled },
+			data,
+			offset: HEADER_SIZE,
+			px: START,
+			array: [(0, 0, 0, 0); 32],
+			run: 0,
+			left: width as u64 * height as u64,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &QoyHeader {
+		&self.header
+	}
+
+	fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+		crate::read_exact(self.data, buf, self.offset)?;
+		self.offset = self.offset.saturating_add(buf.len() as u64);
+		Ok(())
+	}
+
+	/// the next pixel, in ycbcr.
+	fn op(&mut self) -> Result<crate::Rgba, Error> {
+		if self.run > 0 {
+			self.run -= 1;
+			return Ok(self.px);
+		}
+		let mut buf = [0; 4];
+		self.read(&mut buf[..1])?;
+		let b0 = buf[0];
+		match b0 {
+			OP_YCC => {
+				self.read(&mut buf[..3])?;
+				(self.px.0, self.px.1, self.px.2) = (buf[0], buf[1], buf[2]);
+			}
+			OP_YCCA => {
+				self.read(&mut buf)?;
+				self.px = (buf[0], buf[1], buf[2], buf[3]);
+			}
+			_ if b0 & MASK_3 == OP_INDEX => self.px = self.array[(b0 & 31) as usize],
+			_ if b0 & MASK_3 == OP_LUMA => self.px.0 = self.px.0.wrapping_add_signed((b0 & 31) as i8 - 16),
+			_ => match b0 & MASK {
+				OP_SMALL => {
+					let px = &mut self.px;
+					px.0 = px.0.wrapping_add_signed((b0 >> 4 & 3) as i8 - 2);
+					px.1 = px.1.wrapping_add_signed((b0 >> 2 & 3) as i8 - 2);
+					px.2 = px.2.wrapping_add_signed((b0 & 3) as i8 - 2);
+				}
+				OP_DIFF => {
+					self.read(&mut buf[..1])?;
+					let px = &mut self.px;
+					px.0 = px.0.wrapping_add_signed((b0 & 63) as i8 - 32);
+					px.1 = px.1.wrapping_add_signed((buf[0] >> 4) as i8 - 8);
+					px.2 = px.2.wrapping_add_signed((buf[0] & 15) as i8 - 8);
+				}
+				_ => {
+					let run = b0 & 63;
+					// this pixel is one of the run
+					if run as u64 >= self.left {
+						return Err(Error::RunOverrun { offset: self.offset - 1 });
+					}
+					self.run = run;
+				}
+			},
+		}
+		self.array[hash(self.px)] = self.px;
+		Ok(self.px)
+	}
+
+	/// decodes any pixels that are left, checks the end marker, and returns
+	/// the number of bytes the image took up. nothing after the marker is
+	/// read.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		while self.left > 0 {
+			self.op()?;
+			self.left -= 1;
+		}
+		let (mut buf, offset) = ([0; END.len()], self.offset);
+		match self.read(&mut buf) {
+			Ok(()) if buf == END => Ok(self.offset),
+			Ok(()) | Err(Error::UnexpectedEof { .. }) => Err(Error::MissingEndMarker { offset }),
+			Err(e) => Err(e),
+		}
+	}
+}
+
+impl<R: std::io::Read> Iterator for QoyPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.left == 0 {
+			return None;
+		}
+		match self.op() {
+			Ok(px) => {
+				self.left -= 1;
+				Some(Ok(rgb(px)))
+			}
+			Err(e) => {
+				self.failed = true;
+				Some(Err(e))
+			}
+		}
+	}
+}
+
+/// `Format::decode` hands out pixels until the first error.
+struct Pixels<'a, R>(QoyPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// push-based encoder state, which `Qoy::encode` drives.
+#[derive(Debug)]
+pub(crate) struct QoyEncoder<W> {
+	out: W,
+	width: usize,
+	alpha: bool,
+	subsampled: bool,
+	/// pixels in ycbcr that are waiting for the rest of their 2 by 2
+	/// blocks, when subsampling.
+	rows: Vec<crate::Rgba>,
+	prev: crate::Rgba,
+	array: [crate::Rgba; 32],
+	run: u8,
+}
+
+impl<W: std::io::Write> QoyEncoder<W> {
+	/// writes the header.
+	pub(crate) fn new(header: &QoyHeader, mut out: W) -> Result<Self, Error> {
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		if header.width == 0 {
+			return Err(field(4, "width"));
+		}
+		if header.height == 0 {
+			return Err(field(8, "height"));
+		}
+		let mut buf = [0; HEADER_SIZE as usize];
+		buf[..4].copy_from_slice(&MAGIC);
+		buf[4..8].copy_from_slice(&header.width.to_be_bytes());
+		buf[8..12].copy_from_slice(&header.height.to_be_bytes());
+		buf[12] = match header.channels {
+			QoyChannels::RGB => 3,
+			QoyChannels::RGBA => 4,
+		};
+		buf[13] = header.subsampled as u8;
+		crate::write_all(&mut out, &buf)?;
+
+		Ok(Self {
+			out,
+			width: header.width.try_into().unwrap_or(usize::MAX),
+			alpha: header.channels == QoyChannels::RGBA,
+			subsampled: header.subsampled,
+			rows: vec![],
+			prev: START,
+			array: [(0, 0, 0, 0); 32],
+			run: 0,
+		})
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		let px = ycbcr(if self.alpha { px } else { (px.0, px.1, px.2, 255) });
+		if !self.subsampled {
+			return self.op(px);
+		}
+		self.rows.try_reserve(1).map_err(|_| oom())?;
+		self.rows.push(px);
+		if self.rows.len() / 2 == self.width {
+			self.flush()?;
+		}
+		Ok(())
+	}
+
+	/// gives each 2 by 2 block in `rows` the average of its chroma, up to
+	/// however far they go, and encodes them.
+	fn flush(&mut self) -> Result<(), Error> {
+		let (width, len) = (self.width, self.rows.len());
+		for x in (0..width.min(len)).step_by(2) {
+			let block = || (0..2).flat_map(move |y| [x, x + 1].into_iter().filter(move |&x| x < width).map(move |x| y * width + x)).filter(move |&i| i < len);
+			let (mut n, mut cb, mut cr) = (0, 0, 0);
+			for i in block() {
+				n += 1;
+				cb += self.rows[i].1 as u32;
+				cr += self.rows[i].2 as u32;
+			}
+			let (cb, cr) = (((cb + n / 2) / n) as u8, ((cr + n / 2) / n) as u8);
+			for i in block() {
+				(self.rows[i].1, self.rows[i].2) = (cb, cr);
+			}
+		}
+		for i in 0..len {
+			self.op(self.rows[i])?;
+		}
+		self.rows.clear();
+		Ok(())
+	}
+
+	fn op(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		let out = &mut self.out;
+		let prev = self.prev;
+		self.prev = px;
+
+		if px == prev {
+			self.run += 1;
+			if self.run == 62 {
+				crate::write_all(out, &[OP_RUN | (self.run - 1)])?;
+				self.run = 0;
+			}
+			return Ok(());
+		}
+		if self.run > 0 {
+			crate::write_all(out, &[OP_RUN | (self.run - 1)])?;
+			self.run = 0;
+		}
+
+		let index = hash(px);
+		if self.array[index] == px {
+			return crate::write_all(out, &[OP_INDEX | index as u8]);
+		}
+		self.array[index] = px;
+
+		if px.3 != prev.3 {
+			return crate::write_all(out, &[OP_YCCA, px.0, px.1, px.2, px.3]);
+		}
+		// differences wrap around, same as the decoder's additions
+		let y = px.0.wrapping_sub(prev.0) as i8;
+		let cb = px.1.wrapping_sub(prev.1) as i8;
+		let cr = px.2.wrapping_sub(prev.2) as i8;
+		if cb == 0 && cr == 0 && (-16..=15).contains(&y) {
+			crate::write_all(out, &[OP_LUMA | (y + 16) as u8])
+		}
+		else if [y, cb, cr].iter().all(|d| (-2..=1).contains(d)) {
+			crate::write_all(out, &[OP_SMALL | ((y + 2) as u8) << 4 | ((cb + 2) as u8) << 2 | (cr + 2) as u8])
+		}
+		else if (-32..=31).contains(&y) && (-8..=7).contains(&cb) && (-8..=7).contains(&cr) {
+			crate::write_all(out, &[OP_DIFF | (y + 32) as u8, ((cb + 8) as u8) << 4 | (cr + 8) as u8])
+		}
+		else {
+			crate::write_all(out, &[OP_YCC, px.0, px.1, px.2])
+		}
+	}
+
+	/// encodes whatever's left of subsampled rows, flushes any pending run,
+	/// and writes the end marker.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		if !self.rows.is_empty() {
+			self.flush()?;
+		}
+		if self.run > 0 {
+			crate::write_all(&mut self.out, &[OP_RUN | (self.run - 1)])?;
+			self.run = 0;
+		}
+		crate::write_all(&mut self.out, &END)
+	}
+}
+
+impl crate::Format for Qoy {
+	type Header = QoyHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = QoyPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = QoyPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = QoyEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, qoy};
+
+	const IMAGE_FULL: &[u8] = include_bytes!("../test/full.qoy");
+
+	fn color(x: u32, y: u32) -> crate::Rgba {
+		let a = if x > 5 { (x * y * 3 + 7) as u8 } else { 255 };
+		((x * 19) as u8, (y * 36) as u8, ((x + y) * 10) as u8, a)
+	}
+
+	fn close(a: crate::Rgba, b: crate::Rgba, by: u8) -> bool {
+		a.0.abs_diff(b.0) <= by && a.1.abs_diff(b.1) <= by && a.2.abs_diff(b.2) <= by && a.3 == b.3
+	}
+
+	#[test]
+	fn decode() {
+		// the same image with and without subsampling, which is rgb
+		for (image, channels, subsampled) in [
+			(IMAGE_FULL, qoy::QoyChannels::RGBA, false),
+			(include_bytes!("../test/subsampled.qoy"), qoy::QoyChannels::RGB, true),
+		] {
+			let mut data = image;
+			let mut pixels = qoy::QoyPixels::new(&mut data).expect("error?");
+			assert_eq!(pixels.header(), &qoy::QoyHeader { width: 13, height: 7, channels, subsampled });
+			let mut n = 0;
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				let expected = match channels {
+					qoy::QoyChannels::RGB => (color(x, y).0, color(x, y).1, color(x, y).2, 255),
+					qoy::QoyChannels::RGBA => color(x, y),
+				};
+				let px = px.expect("error?");
+				assert!(close(px, expected, if subsampled { 40 } else { 2 }), "{x}, {y}: {px:?}, {expected:?}");
+				n += 1;
+			}
+			assert_eq!(n, 13 * 7);
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			let mut data = image;
+			let mut pixels = qoy::QoyPixels::new(&mut data).expect("error?");
+			assert!(pixels.nth(20).is_some());
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+	}
+
+	#[test]
+	fn encode() {
+		use qoy::QoyChannels::*;
+
+		// what the decoder reads back matches the fixtures, which were
+		// written by another encoder
+		for (image, channels, subsampled) in [(IMAGE_FULL, RGBA, false), (include_bytes!("../test/subsampled.qoy"), RGB, true)] {
+			let header = qoy::QoyHeader { width: 13, height: 7, channels, subsampled };
+			let mut out = vec![];
+			qoy::Qoy.encode((0..13 * 7).map(|i| color(i % 13, i / 13)), header, &mut out).expect("error?");
+			assert_eq!(out, image);
+		}
+
+		// gray doesn't lose anything, subsampled or not, and neither does an
+		// odd size, whose last block has one pixel
+		for subsampled in [false, true] {
+			for (width, height, n) in [(13, 7, 13 * 7), (1, 5, 5), (5, 3, 15)] {
+				let header = qoy::QoyHeader { width, height, channels: RGBA, subsampled };
+				let pixels = (0..n).map(|i| ((i * 2) as u8, (i * 2) as u8, (i * 2) as u8, 255 - i as u8));
+				let mut out = vec![];
+				qoy::Qoy.encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+				let mut data = &out[..];
+				let (decoded_header, decoded) = qoy::Qoy.decode(&mut data).expect("error?");
+				assert_eq!(decoded_header, header);
+				assert!(decoded.take(n as usize).eq(pixels), "{width}, {height}, {subsampled}");
+			}
+		}
+
+		// a smooth, slightly noisy image, like a photo, is smaller than it is
+		// as qoi, and smaller again when subsampled
+		let (width, height) = (64u32, 64);
+		let photo = (0..width * height).map(|i| {
+			let (x, y) = (i % width, i / width);
+			let noise = (i.wrapping_mul(2654435761) >> 29) as u8;
+			((x * 2 + y) as u8 + noise, (x + y * 2) as u8 + noise / 2, (100 + x) as u8 + noise, 255)
+		});
+		let size = |subsampled| {
+			let mut out = vec![];
+			qoy::Qoy.encode(photo.clone(), qoy::QoyHeader { width, height, channels: RGB, subsampled }, &mut out).expect("error?");
+			out.len()
+		};
+		let (subsampled, full) = (size(true), size(false));
+		assert!(subsampled < full);
+		#[cfg(feature = "qoi")]
+		{
+			use crate::qoi;
+			let header = qoi::QoiHeader { width, height, channels: qoi::QoiHeaderChannels::RGB, colorspace: qoi::QoiHeaderColorspace::SRGB };
+			let mut out = vec![];
+			qoi::Qoi.encode(photo, header, &mut out).expect("error?");
+			assert!(full < out.len(), "{full}, {}", out.len());
+		}
+
+		for (header, offset) in [
+			(qoy::QoyHeader { width: 0, height: 1, channels: RGB, subsampled: false }, 4),
+			(qoy::QoyHeader { width: 1, height: 0, channels: RGB, subsampled: false }, 8),
+		] {
+			let result = qoy::QoyEncoder::new(&header, vec![]);
+			assert!(matches!(result, Err(Error::InvalidHeader { offset: o, .. }) if o == offset), "{result:?}");
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = qoy::QoyPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".qoy")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("zero_width", Error::InvalidHeader { offset: 4, .. });
+		case!("zero_height", Error::InvalidHeader { offset: 8, .. });
+		case!("bad_channels", Error::InvalidHeader { offset: 12, .. });
+		case!("bad_subsampling", Error::InvalidHeader { offset: 13, .. });
+		case!("truncated", Error::UnexpectedEof { offset: 40 });
+		case!("run_overrun", Error::RunOverrun { offset: 14 });
+		case!("no_end", Error::MissingEndMarker { offset: 424 });
+		case!("bad_end", Error::MissingEndMarker { offset: 424 });
+
+		assert!(decode(IMAGE_FULL).is_ok());
+	}
+}
@@ -0,0 +1,230 @@
+/*!
+palettes: making one of a few colors for an image, and turning its pixels
+into indices into one.
+
+`median_cut` reads the pixels once, keeping a count of each color rather
+than the pixels themselves, so it takes a stream. mapping them is a
+second pass, over the image again, or over a decode of it again. for one
+pass, the palette can be made from a sample of the pixels, such as every
+tenth one with `step_by`, while they're kept for mapping.
+
+alpha is a channel like the others, so translucent pixels get colors of
+their own, as png's `tRNS` can hold. gif's transparency is all or nothing,
+so its encoder keeps a color of its own for it instead.
+*/
+
+use std::collections::HashMap;
+
+use crate::error::oom;
+use crate::{Error, Rgba};
+
+/// a box of colors, which is split at the median of its widest channel.
+struct Cut {
+	colors: std::ops::Range<usize>,
+	channel: usize,
+	spread: u8,
+}
+
+impl Cut {
+	fn new(colors: &[([u8; 4], u64)], range: std::ops::Range<usize>) -> Self {
+		let mut spread = (0, 0);
+		for channel in 0..4 {
+			let values = colors[range.clone()].iter().map(|c| c.0[channel]);
+			let (min, max) = values.fold((255, 0), |(min, max), x| (x.min(min), x.max(max)));
+			if max.saturating_sub(min) > spread.1 {
+				spread = (channel, max - min);
+			}
+		}
+		Self { colors: range, channel: spread.0, spread: spread.1 }
+	}
+}
+
+/// a palette of at most `max` colors for `pixels`, made by median cut. an
+/// image with no more colors than that gets them as they are. no pixels
+/// make no colors. `max` has to be from 1 to 256, so that indices fit in a
+/// byte, or it's `InvalidInput`.
+pub fn median_cut(pixels: impl Iterator<Item = Rgba>, max: usize) -> Result<Vec<Rgba>, Error> {
+	if !(1..=256).contains(&max) {
+		return Err(Error::InvalidInput { reason: "a palette has from 1 to 256 colors" });
+	}
+	let mut counts = HashMap::<[u8; 4], u64>::new();
+	for px in pixels {
+		counts.try_reserve(1).map_err(|_| oom())?;
+		*counts.entry([px.0, px.1, px.2, px.3]).or_default() += 1;
+	}
+	let mut colors = vec![];
+	colors.try_reserve_exact(counts.len()).map_err(|_| oom())?;
+	colors.extend(counts);
+
+	let mut cuts = vec![];
+	if !colors.is_empty() {
+		cuts.push(Cut::new(&colors, 0..colors.len()));
+	}
+	while cuts.len() < max {
+		let Some((i, _)) = cuts.iter().enumerate().filter(|(_, x)| x.spread > 0).max_by_key(|(_, x)| x.spread)
+		else {
+			break;
+		};
+		let range = cuts[i].colors.clone();
+		let channel = cuts[i].channel;
+		let part = &mut colors[range.clone()];
+		part.sort_unstable_by_key(|c| c.0[channel]);
+
+		// the median by pixels, not colors, though each side gets one
+		let total = part.iter().map(|c| c.1).sum::<u64>();
+		let mut seen = 0;
+		let half = part.iter().take_while(|c| {
+			seen += c.1;
+			seen * 2 < total
+		});
+		let mid = range.start + (half.count() + 1).clamp(1, part.len() - 1);
+		cuts[i] = Cut::new(&colors, range.start..mid);
+		cuts.push(Cut::new(&colors, mid..range.end));
+	}
+
+	Ok(cuts.iter().map(|cut| {
+		let part = &colors[cut.colors.clone()];
+		let total = part.iter().map(|c| c.1).sum::<u64>();
+		let mean = |channel: usize| {
+			let sum = part.iter().map(|c| c.0[channel] as u64 * c.1).sum::<u64>();
+			((sum + total / 2) / total) as u8
+		};
+		(mean(0), mean(1), mean(2), mean(3))
+	}).collect())
+}
+
+/// the index of the color in `palette` nearest `px`, the first of them if
+/// there's a tie, or `None` if the palette is empty.
+pub fn nearest(palette: &[Rgba], px: Rgba) -> Option<usize> {
+	let distance = |c: &Rgba| {
+		let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+		d(px.0, c.0) + d(px.1, c.1) + d(px.2, c.2) + d(px.3, c.3)
+	};
+	palette.iter().enumerate().min_by_key(|(_, c)| distance(c)).map(|(i, _)| i)
+}
+
+/// pixels as indices into a palette.
+#[derive(Debug, Clone)]
+pub struct Indices<'a, I> {
+	pixels: I,
+	palette: &'a [Rgba],
+	/// the index each color seen so far went to, since most images repeat
+	/// colors far more often than a palette has them.
+	cache: HashMap<Rgba, u8>,
+}
+
+/// `pixels` as the indices of the nearest colors in `palette`. a palette
+/// that's empty, or has more than 256 colors, is `InvalidInput`.
+pub fn map<I: Iterator<Item = Rgba>>(pixels: I, palette: &[Rgba]) -> Result<Indices<'_, I>, Error> {
+	if !(1..=256).contains(&palette.len()) {
+		return Err(Error::InvalidInput { reason: "a palette has from 1 to 256 colors" });
+	}
+	Ok(Indices { pixels, palette, cache: HashMap::new() })
+}
+
+impl<I: Iterator<Item = Rgba>> Iterator for Indices<'_, I> {
+	type Item = u8;
+
+	fn next(&mut self) -> Option<u8> {
+		let px = self.pixels.next()?;
+		if let Some(&i) = self.cache.get(&px) {
+			return Some(i);
+		}
+		// the palette isn't empty, and has no more than 256 colors
+		let i = nearest(self.palette, px).unwrap_or(0) as u8;
+		// without room to remember it, it's worked out again next time
+		if self.cache.try_reserve(1).is_ok() {
+			self.cache.insert(px, i);
+		}
+		Some(i)
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.pixels.size_hint()
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Rgba, quantize};
+
+	#[test]
+	fn median_cut() {
+		// a gradient and a flat stretch, as median cut likes to see
+		let pixels = (0..64u32).flat_map(|y| (0..64u32).map(move |x| {
+			if x < 16 { (200, 40, 40, 255) } else { ((x * 4) as u8, (y * 4) as u8, 128, 255) }
+		})).collect::<Vec<Rgba>>();
+		let palette = quantize::median_cut(pixels.iter().copied(), 16).expect("error?");
+		assert_eq!(palette.len(), 16);
+		assert!(palette.contains(&(200, 40, 40, 255)));
+		let indices = quantize::map(pixels.iter().copied(), &palette).expect("error?").collect::<Vec<_>>();
+		assert_eq!(indices.len(), pixels.len());
+		let mut total = 0;
+		for (&px, &i) in pixels.iter().zip(&indices) {
+			let c = palette[i as usize];
+			let error = [px.0.abs_diff(c.0), px.1.abs_diff(c.1), px.2.abs_diff(c.2), px.3.abs_diff(c.3)];
+			assert!(error.iter().all(|&e| e < 64), "{px:?} {c:?}");
+			total += error.iter().map(|&e| e as u32).sum::<u32>();
+		}
+		// a few steps of the gradient off on average
+		assert!(total / (pixels.len() as u32) < 32, "{total}");
+
+		// as many colors as fit are kept as they are, translucent ones too
+		let mut pixels = vec![(0, 0, 0, 255); 300];
+		pixels.extend((0..=254).map(|i| (i, 0, 0, 255)));
+		pixels.push((9, 9, 9, 100));
+		let mut palette = quantize::median_cut(pixels.iter().copied(), 256).expect("error?");
+		palette.sort();
+		let mut expected = (0..=254).map(|i| (i, 0, 0, 255)).chain([(9, 9, 9, 100)]).collect::<Vec<_>>();
+		expected.sort();
+		assert_eq!(palette, expected);
+		let indices = quantize::map(pixels.iter().copied(), &palette).expect("error?");
+		assert!(indices.zip(&pixels).all(|(i, &px)| palette[i as usize] == px));
+
+		assert!(quantize::median_cut(core::iter::empty(), 4).expect("error?").is_empty());
+		assert_eq!(quantize::median_cut([(1, 2, 3, 4); 9].into_iter(), 4).expect("error?"), [(1, 2, 3, 4)]);
+		for max in [0, 257] {
+			let result = quantize::median_cut(core::iter::empty(), max);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+		}
+	}
+
+	#[test]
+	fn map() {
+		let palette = [(0, 0, 0, 255), (255, 255, 255, 255), (0, 0, 0, 0)];
+		let pixels = [(10, 20, 30, 255), (200, 190, 250, 255), (5, 5, 5, 10), (10, 20, 30, 255)];
+		let indices = quantize::map(pixels.into_iter(), &palette).expect("error?");
+		assert_eq!(indices.collect::<Vec<_>>(), [0, 1, 2, 0]);
+		assert_eq!(quantize::nearest(&palette, (100, 100, 100, 255)), Some(0));
+		assert_eq!(quantize::nearest(&[], (1, 2, 3, 4)), None);
+
+		let result = quantize::map(pixels.into_iter(), &[]);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+		let result = quantize::map(pixels.into_iter(), &[(0, 0, 0, 0); 257]);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+	}
+
+	#[cfg(feature = "png")]
+	#[test]
+	fn png8() {
+		use crate::{Format, png};
+
+		let pixels = (0..64u8).map(|i| (i * 4, 255 - i * 4, 90, if i < 8 { 0 } else { 255 })).collect::<Vec<_>>();
+		let palette = quantize::median_cut(pixels.iter().copied(), 4).expect("error?");
+		let indices = quantize::map(pixels.iter().copied(), &palette).expect("error?");
+		let png = png::PngHeader {
+			width: 8,
+			height: 8,
+			color_type: png::PngColorType::Indexed,
+			bit_depth: 2,
+			interlaced: false,
+			palette: palette.clone(),
+		};
+		let mut out = vec![];
+		png::Png.encode(indices.map(|i| palette[i as usize]), png, &mut out).expect("error?");
+		let mut data = &out[..];
+		let (_, decoded) = png::Png.decode(&mut data).expect("error?");
+		assert!(decoded.into_iter().all(|px| palette.contains(&px)));
+	}
+}
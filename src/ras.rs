@@ -0,0 +1,365 @@
+/*!
+sun raster images, standard, byte-encoded and rgb ones, with or without a
+color map. there's no encoder.
+
+the header is 32 bytes of big-endian numbers: the magic, width, height,
+bits a pixel, length of the pixels, type, color map type, and length of the
+color map, which comes right after it, as all its reds, then its greens,
+then its blues. rows are padded to 2 bytes.
+
+1-bit pixels are set for black, and 8-bit ones are gray, unless there's a
+color map for them. 24-bit pixels are blue, green and red, and 32-bit ones
+have a byte before those, which is ignored. the rgb type has them the other
+way around. the byte-encoded type run-length encodes the pixels: `0x80`,
+then a count and a byte, repeats the byte one more time than the count, a
+count of 0 is `0x80` by itself, and any other byte is itself. runs can go
+on from one row to the next.
+
+rows are read as they're asked for.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+const MAGIC: u32 = 0x59a6_6a95;
+const HEADER_SIZE: u64 = 32;
+/// the byte that starts a run.
+const RUN: u8 = 0x80;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RasHeader {
+	pub width: u32,
+	pub height: u32,
+	/// bits a pixel: 1, 8, 24 or 32.
+	pub depth: u8,
+	/// whether the pixels are run-length encoded, as the byte-encoded type
+	/// has them.
+	pub rle: bool,
+	/// whether 24- and 32-bit pixels are red first, as the rgb type has
+	/// them, rather than blue first.
+	pub rgb: bool,
+	/// the color map, which is empty for images without one. it's only
+	/// used for 1- and 8-bit images.
+	pub palette: Vec<crate::Rgba>,
+}
+
+impl crate::Dimensions for RasHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// pixels of a sun raster image, read from the reader a row at a time as
+/// they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct RasPixels<'a, R> {
+	header: RasHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// the bytes a row takes up, padding included.
+	stride: usize,
+	/// what's left of a run that went on past the last row read.
+	run: (u16, u8),
+	row: Vec<u8>,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> RasPixels<'a, R> {
+	/// reads the header and the color map, up to the first row.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; HEADER_SIZE as usize];
+		crate::read_exact(data, &mut buf, 0)?;
+		let at = |i: usize| u32::from_be_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+		if at(0) != MAGIC {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let (width, height) = (at(4), at(8));
+		if width == 0 {
+			return Err(field(4, "width"));
+		}
+		if height == 0 {
+			return Err(field(8, "height"));
+		}
+		let depth = match at(12) {
+			depth @ (1 | 8 | 24 | 32) => depth as u8,
+			_ => return Err(field(12, "depth")),
+		};
+		// old, standard, byte-encoded and rgb. tiff and iff ones aren't read
+		let (rle, rgb) = match at(20) {
+			0 | 1 => (false, false),
+			2 => (true, false),
+			3 => (false, true),
+			_ => return Err(field(20, "type")),
+		};
+		let map_length = at(28);
+		let palette_size = match at(24) {
+			0 if map_length == 0 => 0,
+			1 if map_length.is_multiple_of(3) && map_length <= 768 => map_length as usize / 3,
+			0 | 1 => return Err(field(28, "map length")),
+			_ => return Err(field(24, "map type")),
+		};
+		let mut map = [0; 768];
+		let map = &mut map[..palette_size * 3];
+		crate::read_exact(data, map, HEADER_SIZE)?;
+		let palette = (0..palette_size).map(|i| (map[i], map[palette_size + i], map[palette_size * 2 + i], 255)).collect();
+
+		let stride = (width as u64 * depth as u64).div_ceil(16) * 2;
+		Ok(Self {
+			header: RasHeader { width, height, depth, rle, rgb, palette },
+			data,
+			offset: HEADER_SIZE + map.len() as u64,
+			stride: stride.try_into().map_err(|_| oom())?,
+			run: (0, 0),
+			row: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &RasHeader {
+		&self.header
+	}
+
+	fn byte(&mut self) -> Result<u8, Error> {
+		let mut b = [0];
+		crate::read_exact(self.data, &mut b, self.offset)?;
+		self.offset = self.offset.saturating_add(1);
+		Ok(b[0])
+	}
+
+	/// reads the next row, a piece at a time, so memory is only taken up
+	/// once the data shows up.
+	fn read_row(&mut self) -> Result<(), Error> {
+		self.row.clear();
+		let mut len = self.stride;
+		if !self.header.rle {
+			while len > 0 {
+				let n = len.min(1 << 16);
+				let at = self.row.len();
+				self.row.try_reserve(n).map_err(|_| oom())?;
+				self.row.resize(at + n, 0);
+				crate::read_exact(self.data, &mut self.row[at..], self.offset)?;
+				self.offset = self.offset.saturating_add(n as u64);
+				len -= n;
+			}
+			return Ok(());
+		}
+		self.row.try_reserve(len.min(1 << 16)).map_err(|_| oom())?;
+		while len > 0 {
+			if self.run.0 == 0 {
+				self.run = match self.byte()? {
+					RUN => match self.byte()? {
+						0 => (1, RUN),
+						count => (count as u16 + 1, self.byte()?),
+					},
+					b => (1, b),
+				};
+			}
+			let n = (self.run.0 as usize).min(len);
+			self.row.try_reserve(n).map_err(|_| oom())?;
+			self.row.resize(self.row.len() + n, self.run.1);
+			self.run.0 -= n as u16;
+			len -= n;
+		}
+		Ok(())
+	}
+
+	/// skips any rows that are left, and returns the number of bytes the
+	/// image took up. nothing after the pixels is read, though the rest of
+	/// a run that goes past the last pixel is.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		let start = if self.x > 0 { self.y + 1 } else { self.y };
+		for _ in start..self.header.height {
+			self.read_row()?;
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<R: std::io::Read> Iterator for RasPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		if self.x == 0
+			&& let Err(e) = self.read_row()
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let x = self.x as usize;
+		let palette = &self.header.palette;
+		let indexed = |i: u8| match palette.get(i as usize) {
+			Some(&px) => Ok(px),
+			None => Err(Error::Malformed { offset: self.offset, reason: "index is past the color map" }),
+		};
+		let px = match self.header.depth {
+			1 => {
+				let bit = self.row[x / 8] >> (7 - x % 8) & 1;
+				match (palette.is_empty(), bit) {
+					(false, _) => indexed(bit),
+					(true, 1) => Ok((0, 0, 0, 255)),
+					(true, _) => Ok((255, 255, 255, 255)),
+				}
+			}
+			8 if palette.is_empty() => Ok((self.row[x], self.row[x], self.row[x], 255)),
+			8 => indexed(self.row[x]),
+			depth => {
+				let bytes = depth as usize / 8;
+				// the byte before a 32-bit pixel's colors is skipped
+				let c = &self.row[x * bytes + bytes - 3..x * bytes + bytes];
+				match self.header.rgb {
+					true => Ok((c[0], c[1], c[2], 255)),
+					false => Ok((c[2], c[1], c[0], 255)),
+				}
+			}
+		};
+		if px.is_err() {
+			self.failed = true;
+		}
+
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(px)
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, ras};
+
+	const IMAGE_GRAY: &[u8] = include_bytes!("../test/gray.ras");
+	const IMAGE_INDEXED: &[u8] = include_bytes!("../test/indexed.ras");
+
+	fn color(x: u32, y: u32) -> crate::Rgba {
+		((x * 19) as u8, (y * 50) as u8, ((x + y) * 10) as u8, 255)
+	}
+
+	fn indexed_color(x: u32, y: u32) -> crate::Rgba {
+		let i = match y {
+			1 | 2 => 0,
+			3 => 0x80,
+			_ => [0, 1, 2, 3, 0x80, 5][((x + y * 3) % 6) as usize],
+		};
+		((i * 7) as u8, (i * 3) as u8, (255 - i) as u8, 255)
+	}
+
+	#[test]
+	fn decode() {
+		// an odd width, for the padding at the end of each row
+		for (image, depth, rgb, expected) in [
+			(IMAGE_GRAY, 8, false, (|x, y| {
+				let v = (x * 20 + y * 7) as u8;
+				(v, v, v, 255)
+			}) as fn(u32, u32) -> crate::Rgba),
+			(include_bytes!("../test/bgr.ras"), 24, false, color),
+			(include_bytes!("../test/xrgb.ras"), 32, true, color),
+			(include_bytes!("../test/mono.ras"), 1, false, |x, y| match (x + y) % 3 {
+				0 => (0, 0, 0, 255),
+				_ => (255, 255, 255, 255),
+			}),
+		] {
+			let mut data = image;
+			let mut pixels = ras::RasPixels::new(&mut data).expect("error?");
+			let header = pixels.header();
+			assert_eq!((header.width, header.height, header.depth), (13, 5, depth));
+			assert_eq!((header.rle, header.rgb, header.palette.len()), (false, rgb, 0));
+			let mut n = 0;
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				assert_eq!(px.expect("error?"), expected(x, y), "{depth}: {x}, {y}");
+				n += 1;
+			}
+			assert_eq!(n, 13 * 5);
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			// where it ends is found without handing out every pixel
+			let mut data = image;
+			let mut pixels = ras::RasPixels::new(&mut data).expect("error?");
+			assert!(pixels.nth(20).is_some());
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+	}
+
+	#[test]
+	fn decode_rle() {
+		// a color map, runs that go on across rows, and 0x80 by itself and
+		// in runs
+		let mut data = IMAGE_INDEXED;
+		let mut pixels = ras::RasPixels::new(&mut data).expect("error?");
+		let header = pixels.header();
+		assert_eq!((header.width, header.height, header.depth, header.rle), (13, 5, 8, true));
+		assert_eq!(header.palette.len(), 129);
+		for (i, px) in (&mut pixels).enumerate() {
+			let (x, y) = (i as u32 % 13, i as u32 / 13);
+			assert_eq!(px.expect("error?"), indexed_color(x, y), "{x}, {y}");
+		}
+		assert_eq!(pixels.finish().expect("error?"), IMAGE_INDEXED.len() as u64);
+
+		for n in [0, 1, 14, 30] {
+			let mut data = IMAGE_INDEXED;
+			let mut pixels = ras::RasPixels::new(&mut data).expect("error?");
+			if n > 0 {
+				assert!(pixels.nth(n - 1).is_some());
+			}
+			assert_eq!(pixels.finish().expect("error?"), IMAGE_INDEXED.len() as u64, "{n}");
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = ras::RasPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".ras")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("zero_width", Error::InvalidHeader { offset: 4, .. });
+		case!("zero_height", Error::InvalidHeader { offset: 8, .. });
+		case!("bad_depth", Error::InvalidHeader { offset: 12, .. });
+		case!("bad_type", Error::InvalidHeader { offset: 20, .. });
+		case!("raw_map", Error::InvalidHeader { offset: 24, .. });
+		case!("bad_map_length", Error::InvalidHeader { offset: 28, .. });
+		case!("stray_map", Error::InvalidHeader { offset: 28, .. });
+		case!("truncated_map", Error::UnexpectedEof { offset: 200 });
+		case!("truncated", Error::UnexpectedEof { offset: 100 });
+		case!("truncated_run", Error::UnexpectedEof { offset: 435 });
+		case!("past_map", Error::Malformed { offset: 436, .. });
+
+		assert!(decode(IMAGE_GRAY).is_ok());
+		assert!(decode(IMAGE_INDEXED).is_ok());
+	}
+}
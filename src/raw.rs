@@ -0,0 +1,553 @@
+/*!
+headerless raw pixels, laid out however the caller says, since nothing in
+the stream does.
+
+each row is `width` pixels of samples, in the order `RawOrder` has them.
+samples are a byte for up to 8 bits and two for more, in the byte order
+`big_endian` says, with the value in the low bits. bits above it are
+ignored, and samples are scaled to and from 8 bits. rows start `stride`
+bytes apart, which is as many as a row takes up by default, and every row,
+the last too, is padded to it.
+
+`Raw::decode` reads with its header, and `Format::encode` writes with the
+one it's given. since every row takes up the stride, a reader that can seek
+can start at any row. rows are read as they're asked for and written as they're
+done, and a short iterator is a `PixelCountMismatch` once the rows it
+filled are written.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+/// the channels of a pixel, in the order they're stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum RawOrder {
+	Gray,
+	GrayAlpha,
+	RGB,
+	RGBA,
+	BGR,
+	BGRA,
+	ARGB,
+	ABGR,
+}
+
+impl RawOrder {
+	fn channels(self) -> usize {
+		match self {
+			Self::Gray => 1,
+			Self::GrayAlpha => 2,
+			Self::RGB | Self::BGR => 3,
+			Self::RGBA | Self::BGRA | Self::ARGB | Self::ABGR => 4,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RawHeader {
+	pub width: u32,
+	pub height: u32,
+	pub order: RawOrder,
+	/// bits a sample, 1 to 16.
+	pub bit_depth: u8,
+	/// whether two-byte samples are big-endian.
+	pub big_endian: bool,
+	/// bytes from the start of one row to the start of the next, or none for
+	/// as many as a row takes up.
+	pub stride: Option<u64>,
+}
+
+impl Default for RawHeader {
+	fn default() -> Self {
+		Self { width: 0, height: 0, order: RawOrder::RGBA, bit_depth: 8, big_endian: false, stride: None }
+	}
+}
+
+impl RawHeader {
+	/// bytes a sample takes up.
+	fn bytes(&self) -> usize {
+		if self.bit_depth > 8 { 2 } else { 1 }
+	}
+
+	/// the bytes a row's pixels take up, and the stride.
+	fn layout(&self) -> Result<(u64, u64), Error> {
+		if !(1..=16).contains(&self.bit_depth) {
+			return Err(Error::InvalidInput { reason: "bit depth isn't 1 to 16" });
+		}
+		let row = self.width as u64 * (self.order.channels() * self.bytes()) as u64;
+		match self.stride {
+			None => Ok((row, row)),
+			Some(stride) if stride >= row => Ok((row, stride)),
+			Some(_) => Err(Error::InvalidInput { reason: "stride is shorter than a row" }),
+		}
+	}
+}
+
+impl crate::Dimensions for RawHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Raw {
+	/// how the pixels `decode` reads are laid out.
+	pub header: RawHeader,
+}
+
+/// pixels laid out as a header says, read from the reader a row at a time
+/// as they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct RawPixels<'a, R> {
+	header: RawHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// the bytes a row's pixels take up, and the padding after them.
+	len: u64,
+	padding: u64,
+	row: Vec<u8>,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> RawPixels<'a, R> {
+	/// checks the header. nothing is read yet.
+	pub fn new(data: &'a mut R, header: &RawHeader) -> Result<Self, Error> {
+		let (len, stride) = header.layout()?;
+		Ok(Self {
+			header: header.clone(),
+			data,
+			offset: 0,
+			len,
+			padding: stride - len,
+			row: vec![],
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &RawHeader {
+		&self.header
+	}
+
+	/// reads the next row, a piece at a time, so memory is only taken up
+	/// once the data shows up, and then its padding.
+	fn read_row(&mut self, keep: bool) -> Result<(), Error> {
+		self.row.clear();
+		let mut skip = [0; 512];
+		let mut done = 0;
+		let stride = self.len + self.padding;
+		while done < stride {
+			let n = (stride - done).min(1 << 16);
+			let buf = match done < self.len && keep {
+				true => {
+					let n = n.min(self.len - done) as usize;
+					let at = self.row.len();
+					self.row.try_reserve(n).map_err(|_| oom())?;
+					self.row.resize(at + n, 0);
+					&mut self.row[at..]
+				}
+				false => &mut skip[..n.min(512) as usize],
+			};
+			let n = buf.len() as u64;
+			crate::read_exact(self.data, buf, self.offset)?;
+			self.offset += n;
+			done += n;
+		}
+		Ok(())
+	}
+
+	/// the sample at `i`, scaled to 8 bits.
+	fn sample(&self, i: usize) -> u8 {
+		let bits = self.header.bit_depth as u32;
+		let v = match self.header.bytes() {
+			1 => self.row[i] as u32,
+			_ if self.header.big_endian => u16::from_be_bytes([self.row[i * 2], self.row[i * 2 + 1]]) as u32,
+			_ => u16::from_le_bytes([self.row[i * 2], self.row[i * 2 + 1]]) as u32,
+		};
+		let max = (1 << bits) - 1;
+		(((v & max) * 255 + max / 2) / max) as u8
+	}
+
+	/// skips any rows that are left, and returns the number of bytes the
+	/// image took up, which is the stride times the height.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		let start = if self.x > 0 { self.y + 1 } else { self.y };
+		for _ in start..self.header.height {
+			self.read_row(false)?;
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<R: std::io::Read + std::io::Seek> RawPixels<'_, R> {
+	/// goes to the start of row `y`, forward or back, without reading what's
+	/// between. `y` can be the height, past which is `OutOfBounds`.
+	pub fn seek_row(&mut self, y: u32) -> Result<(), Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		if y > self.header.height {
+			return Err(Error::OutOfBounds { x: 0, y, width: self.header.width, height: 1 });
+		}
+		let to = (y as u64).saturating_mul(self.len + self.padding);
+		crate::seek::seek(self.data, self.offset, to).inspect_err(|_| self.failed = true)?;
+		self.offset = to;
+		self.x = 0;
+		self.y = y;
+		Ok(())
+	}
+}
+
+impl<R: std::io::Read> Iterator for RawPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height || self.header.width == 0 {
+			return None;
+		}
+		if self.x == 0
+			&& let Err(e) = self.read_row(true)
+		{
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let i = self.x as usize * self.header.order.channels();
+		let s = |c| self.sample(i + c);
+		let px = match self.header.order {
+			RawOrder::Gray => (s(0), s(0), s(0), 255),
+			RawOrder::GrayAlpha => (s(0), s(0), s(0), s(1)),
+			RawOrder::RGB => (s(0), s(1), s(2), 255),
+			RawOrder::RGBA => (s(0), s(1), s(2), s(3)),
+			RawOrder::BGR => (s(2), s(1), s(0), 255),
+			RawOrder::BGRA => (s(2), s(1), s(0), s(3)),
+			RawOrder::ARGB => (s(1), s(2), s(3), s(0)),
+			RawOrder::ABGR => (s(3), s(2), s(1), s(0)),
+		};
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+/// `Format::decode` hands out pixels until the first error.
+struct Pixels<'a, R>(RawPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// encodes pixels as a header lays them out, a row at a time.
+pub(crate) struct RawEncoder<W> {
+	out: W,
+	header: RawHeader,
+	/// the bytes after each row's pixels.
+	padding: u64,
+	/// the current row, as far as it's gone.
+	row: Vec<u8>,
+	x: u32,
+	/// rows that haven't been written.
+	rows: u32,
+}
+
+impl<W: std::io::Write> RawEncoder<W> {
+	/// checks the header. nothing is written yet.
+	pub(crate) fn new(header: &RawHeader, out: W) -> Result<Self, Error> {
+		let (len, stride) = header.layout()?;
+		Ok(Self { out, header: header.clone(), padding: stride - len, row: vec![], x: 0, rows: header.height })
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		if self.rows == 0 || self.header.width == 0 {
+			return Err(Error::InvalidInput { reason: "more pixels than the image has" });
+		}
+		let channels = self.header.order.channels();
+		let samples = match self.header.order {
+			RawOrder::Gray => [crate::pixel::luma(px) as u8, 0, 0, 0],
+			RawOrder::GrayAlpha => [crate::pixel::luma(px) as u8, px.3, 0, 0],
+			RawOrder::RGB => [px.0, px.1, px.2, 0],
+			RawOrder::RGBA => [px.0, px.1, px.2, px.3],
+			RawOrder::BGR => [px.2, px.1, px.0, 0],
+			RawOrder::BGRA => [px.2, px.1, px.0, px.3],
+			RawOrder::ARGB => [px.3, px.0, px.1, px.2],
+			RawOrder::ABGR => [px.3, px.2, px.1, px.0],
+		};
+		self.row.try_reserve(channels * self.header.bytes()).map_err(|_| oom())?;
+		let max = (1u32 << self.header.bit_depth) - 1;
+		for &v in &samples[..channels] {
+			let v = ((v as u32 * max + 127) / 255) as u16;
+			match self.header.bytes() {
+				1 => self.row.push(v as u8),
+				_ if self.header.big_endian => self.row.extend(v.to_be_bytes()),
+				_ => self.row.extend(v.to_le_bytes()),
+			}
+		}
+		self.x += 1;
+		if self.x == self.header.width {
+			crate::write_all(&mut self.out, &self.row)?;
+			let mut padding = self.padding;
+			while padding > 0 {
+				let n = padding.min(512);
+				crate::write_all(&mut self.out, &[0; 512][..n as usize])?;
+				padding -= n;
+			}
+			self.row.clear();
+			self.x = 0;
+			self.rows -= 1;
+		}
+		Ok(())
+	}
+
+	/// writes the row that's been started, which cuts the image off after
+	/// its last pixel, since there's nothing to say where it ends.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		crate::write_all(&mut self.out, &self.row)?;
+		self.row.clear();
+		Ok(())
+	}
+}
+
+impl crate::Format for Raw {
+	type Header = RawHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = RawPixels::new(data, &self.header)?;
+		Ok((self.header, Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = RawPixels::new(data, &self.header)?;
+		Ok((self.header, pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = RawEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+impl crate::SeekDecode for Raw {
+	fn decode_from_row<R: std::io::Read + std::io::Seek>(self, data: &mut R, y: u32) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let mut pixels = RawPixels::new(data, &self.header)?;
+		pixels.seek_row(y)?;
+		Ok((self.header, pixels))
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, raw};
+
+	fn color(x: u32, y: u32) -> crate::Rgba {
+		((x * 19) as u8, (y * 50) as u8, ((x + y) * 10) as u8, (x * 30 + y) as u8)
+	}
+
+	fn header(order: raw::RawOrder, bit_depth: u8, stride: Option<u64>) -> raw::RawHeader {
+		raw::RawHeader { width: 7, height: 3, order, bit_depth, big_endian: false, stride }
+	}
+
+	/// the bytes of `color`, `stride` apart, with the padding set so it's
+	/// clear it's skipped.
+	fn bytes(order: raw::RawOrder, stride: usize) -> Vec<u8> {
+		let mut out = vec![];
+		for y in 0..3 {
+			let start = out.len();
+			for x in 0..7 {
+				let (r, g, b, a) = color(x, y);
+				match order {
+					raw::RawOrder::Gray => out.extend([r]),
+					raw::RawOrder::GrayAlpha => out.extend([r, a]),
+					raw::RawOrder::RGB => out.extend([r, g, b]),
+					raw::RawOrder::RGBA => out.extend([r, g, b, a]),
+					raw::RawOrder::BGR => out.extend([b, g, r]),
+					raw::RawOrder::BGRA => out.extend([b, g, r, a]),
+					raw::RawOrder::ARGB => out.extend([a, r, g, b]),
+					raw::RawOrder::ABGR => out.extend([a, b, g, r]),
+				}
+			}
+			out.resize(start + stride.max(out.len() - start), 0xaa);
+		}
+		out
+	}
+
+	#[test]
+	fn decode() {
+		for (order, expected) in [
+			(raw::RawOrder::Gray, (|(r, _, _, _)| (r, r, r, 255)) as fn(crate::Rgba) -> crate::Rgba),
+			(raw::RawOrder::GrayAlpha, |(r, _, _, a)| (r, r, r, a)),
+			(raw::RawOrder::RGB, |(r, g, b, _)| (r, g, b, 255)),
+			(raw::RawOrder::RGBA, |px| px),
+			(raw::RawOrder::BGR, |(r, g, b, _)| (r, g, b, 255)),
+			(raw::RawOrder::BGRA, |px| px),
+			(raw::RawOrder::ARGB, |px| px),
+			(raw::RawOrder::ABGR, |px| px),
+		] {
+			// padded rows, and without
+			for stride in [None, Some(40)] {
+				let image = bytes(order, stride.unwrap_or(0) as usize);
+				let mut data = &image[..];
+				let mut pixels = raw::RawPixels::new(&mut data, &header(order, 8, stride)).expect("error?");
+				for (i, px) in (&mut pixels).enumerate() {
+					let (x, y) = (i as u32 % 7, i as u32 / 7);
+					assert_eq!(px.expect("error?"), expected(color(x, y)), "{order:?}: {x}, {y}");
+				}
+				assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+				// where it ends is found without handing out every pixel
+				let mut data = &image[..];
+				let mut pixels = raw::RawPixels::new(&mut data, &header(order, 8, stride)).expect("error?");
+				assert!(pixels.nth(8).is_some());
+				assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+			}
+		}
+	}
+
+	#[test]
+	fn decode_depths() {
+		let decode = |header: &raw::RawHeader, image: &[u8]| {
+			let mut data = image;
+			let (_, pixels) = raw::Raw { header: header.clone() }.decode(&mut data).expect("error?");
+			pixels.map(|px| px.0).collect::<Vec<_>>()
+		};
+		let gray = |bit_depth, big_endian| raw::RawHeader { width: 4, height: 1, order: raw::RawOrder::Gray, bit_depth, big_endian, stride: None };
+
+		// 12 bits in 2 bytes, with junk in the bits above
+		assert_eq!(decode(&gray(12, false), &[0, 0, 0xff, 0xf7, 0x00, 0x08, 0xff, 0x0f]), [0, 127, 128, 255]);
+		assert_eq!(decode(&gray(16, true), &[0, 0, 0x80, 0x00, 0x7f, 0xff, 0xff, 0xff]), [0, 128, 127, 255]);
+		assert_eq!(decode(&gray(16, false), &[0, 0, 0x00, 0x80, 0xff, 0x7f, 0xff, 0xff]), [0, 128, 127, 255]);
+		assert_eq!(decode(&gray(4, false), &[0, 0xf5, 8, 15]), [0, 85, 136, 255]);
+		assert_eq!(decode(&gray(1, false), &[0, 1, 2, 3]), [0, 255, 0, 255]);
+	}
+
+	#[test]
+	fn seek() {
+		use crate::SeekDecode;
+
+		let image = bytes(raw::RawOrder::RGBA, 40);
+		let header = header(raw::RawOrder::RGBA, 8, Some(40));
+		let row = |y| (0..7).map(move |x| color(x, y));
+		let mut data = std::io::Cursor::new(&image[..]);
+		let (_, pixels) = raw::Raw { header: header.clone() }.decode_from_row(&mut data, 1).expect("error?");
+		assert!(pixels.map(|px| px.expect("error?")).eq(row(1).chain(row(2))));
+
+		// back partway through a row, and to the end
+		let mut data = std::io::Cursor::new(&image[..]);
+		let mut pixels = raw::RawPixels::new(&mut data, &header).expect("error?");
+		pixels.seek_row(2).expect("error?");
+		assert!((&mut pixels).take(3).map(|px| px.expect("error?")).eq(row(2).take(3)));
+		pixels.seek_row(0).expect("error?");
+		assert!((&mut pixels).take(7).map(|px| px.expect("error?")).eq(row(0)));
+		pixels.seek_row(3).expect("error?");
+		assert!(pixels.next().is_none());
+		let result = pixels.seek_row(4);
+		assert!(matches!(result, Err(Error::OutOfBounds { x: 0, y: 4, width: 7, height: 1 })), "{result:?}");
+		assert_eq!(pixels.finish().expect("error?"), 120);
+	}
+
+	#[test]
+	fn encode() {
+		for order in [raw::RawOrder::RGB, raw::RawOrder::RGBA, raw::RawOrder::BGRA, raw::RawOrder::ARGB, raw::RawOrder::ABGR] {
+			for stride in [None, Some(40)] {
+				let pixels = (0..21).map(|i| color(i % 7, i / 7));
+				let mut out = vec![];
+				raw::Raw::default().encode(pixels, header(order, 8, stride), &mut out).expect("error?");
+				let mut expected = bytes(order, stride.unwrap_or(0) as usize);
+				// padding is written as 0
+				if stride.is_some() {
+					expected.iter_mut().filter(|b| **b == 0xaa).for_each(|b| *b = 0);
+				}
+				assert_eq!(out, expected, "{order:?}, {stride:?}");
+			}
+		}
+
+		// round trips through deeper samples, both byte orders
+		for (bit_depth, big_endian) in [(10, false), (16, true), (12, false), (5, false)] {
+			let header = raw::RawHeader { bit_depth, big_endian, ..header(raw::RawOrder::RGBA, 8, None) };
+			let pixels = (0..21).map(|i| color(i % 7, i / 7));
+			let mut out = vec![];
+			raw::Raw::default().encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+			assert_eq!(out.len(), 7 * 3 * 4 * if bit_depth > 8 { 2 } else { 1 });
+			let mut data = &out[..];
+			let (_, decoded) = raw::Raw { header }.decode(&mut data).expect("error?");
+			let decoded = decoded.collect::<Vec<_>>();
+			// 5 bits can't keep every value
+			for (px, expected) in decoded.iter().zip(pixels) {
+				let close = |a: u8, b: u8| a.abs_diff(b) <= if bit_depth < 8 { 4 } else { 0 };
+				assert!(close(px.0, expected.0) && close(px.1, expected.1) && close(px.2, expected.2) && close(px.3, expected.3), "{bit_depth}: {px:?} {expected:?}");
+			}
+			assert_eq!(decoded.len(), 21);
+		}
+
+		// gray from luma, and a short iterator is cut off after its last whole
+		// row
+		let mut out = vec![];
+		let header = raw::RawHeader { width: 2, height: 2, ..header(raw::RawOrder::Gray, 8, Some(3)) };
+		let pixels = [(255, 255, 255, 255), (255, 0, 0, 255), (0, 0, 255, 0), (0, 255, 0, 255)];
+		raw::Raw::default().encode(pixels.into_iter(), header.clone(), &mut out).expect("error?");
+		assert_eq!(out, [255, 77, 0, 29, 149, 0]);
+		let mut out = vec![];
+		let result = raw::Raw::default().encode(pixels[..3].iter().copied(), header, &mut out);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 4, got: 3 })), "{result:?}");
+		assert_eq!(out, [255, 77, 0]);
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8], header: &raw::RawHeader) -> Result<(), Error> {
+			let mut pixels = raw::RawPixels::new(&mut data, header)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		let image = bytes(raw::RawOrder::RGB, 40);
+		let rgb = header(raw::RawOrder::RGB, 8, Some(40));
+		assert!(decode(&image, &rgb).is_ok());
+		let result = decode(&image[..50], &rgb);
+		assert!(matches!(result, Err(Error::UnexpectedEof { offset: 50 })), "{result:?}");
+		// in the last row's padding
+		let result = decode(&image[..110], &rgb);
+		assert!(matches!(result, Err(Error::UnexpectedEof { offset: 110 })), "{result:?}");
+
+		for header in [header(raw::RawOrder::RGB, 0, None), header(raw::RawOrder::RGB, 17, None), header(raw::RawOrder::RGB, 8, Some(20))] {
+			let result = decode(&image, &header);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{header:?}: {result:?}");
+			let result = raw::RawEncoder::new(&header, vec![]);
+			assert!(matches!(result, Err(Error::InvalidInput { .. })), "{header:?}");
+		}
+
+		let mut encoder = raw::RawEncoder::new(&raw::RawHeader { height: 1, ..rgb }, vec![]).expect("error?");
+		for _ in 0..7 {
+			encoder.push((0, 0, 0, 0)).expect("error?");
+		}
+		assert!(matches!(encoder.push((0, 0, 0, 0)), Err(Error::InvalidInput { .. })));
+	}
+}
@@ -0,0 +1,326 @@
+/*!
+a registry of formats, looked up by the magic bytes their files start with,
+so formats from other crates can be picked at runtime too.
+
+a format is registered as a `DynFormat`, under a name. one whose header
+doesn't convert to and from `AnyHeader` can be wrapped in `Registered`,
+with a function that makes a header for a width and height. the registry
+starts out empty, and is shared by the whole program.
+
+where more than one magic matches, the longest wins, and where they're the
+same length, the one registered last.
+*/
+
+use std::sync::{Arc, RwLock};
+
+use crate::error::oom;
+use crate::{AnyHeader, Dimensions, DynFormat, Error, Format, Image, Rgba};
+
+/// a `Format` whose headers are made out of just a width and height, so
+/// it's a `DynFormat` whatever its header is. decoded headers come out as
+/// `AnyHeader::Other`, and any header can be encoded with, for its width
+/// and height.
+pub struct Registered<F: Format> {
+	format: F,
+	/// the header images are encoded with, for their width and height, or
+	/// `None` if the format only decodes.
+	header: Option<fn(u32, u32) -> F::Header>,
+}
+
+impl<F: Format> Registered<F> {
+	pub fn new(format: F, header: fn(u32, u32) -> F::Header) -> Self {
+		Self { format, header: Some(header) }
+	}
+
+	/// a format that only decodes, so encoding with it is `InvalidInput`.
+	pub fn decode_only(format: F) -> Self {
+		Self { format, header: None }
+	}
+}
+
+// by hand, since deriving them would ask it of the header too
+impl<F: Format + Clone> Clone for Registered<F> {
+	fn clone(&self) -> Self {
+		Self { format: self.format.clone(), header: self.header }
+	}
+}
+
+impl<F: Format + core::fmt::Debug> core::fmt::Debug for Registered<F> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.debug_struct("Registered").field("format", &self.format).field("encodes", &self.header.is_some()).finish()
+	}
+}
+
+impl<F: Format> Default for Registered<F> {
+	fn default() -> Self {
+		Self::decode_only(F::default())
+	}
+}
+
+fn other(header: &impl Dimensions) -> AnyHeader {
+	AnyHeader::Other { width: header.width(), height: header.height() }
+}
+
+impl<F: Format> Format for Registered<F> {
+	type Header = AnyHeader;
+	type Options = F::Options;
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(AnyHeader, impl Iterator<Item = Rgba>), Error> {
+		let (header, pixels) = self.format.decode(data)?;
+		Ok((other(&header), pixels))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(AnyHeader, impl Iterator<Item = Result<Rgba, Error>>), Error> {
+		let (header, pixels) = self.format.decode_checked(data)?;
+		Ok((other(&header), pixels))
+	}
+
+	fn decode_header(self, data: &mut impl std::io::Read) -> Result<AnyHeader, Error> {
+		self.format.decode_header(data).map(|header| other(&header))
+	}
+
+	fn decode_with_options(self, data: &mut impl std::io::Read, options: &F::Options) -> Result<(AnyHeader, impl Iterator<Item = Result<Rgba, Error>>), Error> {
+		let (header, pixels) = self.format.decode_with_options(data, options)?;
+		Ok((other(&header), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = Rgba>, header: AnyHeader, out: &mut impl std::io::Write) -> Result<(), Error> {
+		self.encode_with_options(data, header, &F::Options::default(), out)
+	}
+
+	fn encode_with_options(self, data: impl Iterator<Item = Rgba>, header: AnyHeader, options: &F::Options, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let Some(make) = self.header else {
+			return Err(Error::InvalidInput { reason: "the format was registered without a way to make headers" });
+		};
+		self.format.encode_with_options(data, make(header.width(), header.height()), options, out)
+	}
+}
+
+struct Entry {
+	name: &'static str,
+	magic: Vec<u8>,
+	format: Arc<dyn DynFormat>,
+}
+
+static REGISTRY: RwLock<Vec<Entry>> = RwLock::new(vec![]);
+
+/// every entry, even if a thread panicked while registering, since an entry
+/// is pushed whole or not at all.
+fn entries() -> std::sync::RwLockReadGuard<'static, Vec<Entry>> {
+	REGISTRY.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// adds `format` as `name`, for files starting with `magic`.
+pub fn register(name: &'static str, magic: &[u8], format: Box<dyn DynFormat>) -> Result<(), Error> {
+	if magic.is_empty() {
+		return Err(Error::InvalidInput { reason: "magic is empty, so it would match everything" });
+	}
+	let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+	registry.try_reserve(1).map_err(|_| oom())?;
+	registry.push(Entry { name, magic: magic.to_vec(), format: Arc::from(format) });
+	Ok(())
+}
+
+/// an entry in the registry, as `detect` finds it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Id(usize);
+
+/// the entry for files starting with `start`.
+pub(crate) fn position(start: &[u8]) -> Option<Id> {
+	// the last of the longest, since `max_by_key` keeps the last of equals
+	entries()
+		.iter()
+		.enumerate()
+		.filter(|(_, entry)| start.starts_with(&entry.magic))
+		.max_by_key(|(_, entry)| entry.magic.len())
+		.map(|(i, _)| Id(i))
+}
+
+/// the name and format of an entry. entries are never taken out, so every
+/// `Id` has one.
+pub(crate) fn get(id: Id) -> Option<(&'static str, Arc<dyn DynFormat>)> {
+	entries().get(id.0).map(|entry| (entry.name, entry.format.clone()))
+}
+
+/// the longest magic registered, which is how much has to be read to tell
+/// them apart.
+pub(crate) fn longest() -> usize {
+	entries().iter().map(|entry| entry.magic.len()).max().unwrap_or(0)
+}
+
+/// the name and format for files starting with `start`.
+pub fn find(start: &[u8]) -> Option<(&'static str, Arc<dyn DynFormat>)> {
+	position(start).and_then(get)
+}
+
+/// the format registered last as `name`.
+pub fn by_name(name: &str) -> Option<Arc<dyn DynFormat>> {
+	entries().iter().rev().find(|entry| entry.name == name).map(|entry| entry.format.clone())
+}
+
+/// decodes a whole image with `format`.
+pub(crate) fn collect(format: &dyn DynFormat, mut data: &mut dyn std::io::Read) -> Result<Image, Error> {
+	let (header, pixels) = format.decode(&mut data)?;
+	Image::collect_checked(header.width(), header.height(), pixels)
+}
+
+/// reads enough of `data` to tell which registered format it's in, and
+/// decodes it with that one, returning its name too. data that no format's
+/// magic matches is `InvalidMagic`.
+pub fn decode(data: &mut impl std::io::Read) -> Result<(&'static str, Image), Error> {
+	let start = crate::detect::start(data, longest())?;
+
+	let (name, format) = find(&start).ok_or(Error::InvalidMagic { offset: 0 })?;
+	let mut data = std::io::Read::chain(std::io::Cursor::new(start), data);
+	let image = collect(&*format, &mut data)?;
+	Ok((name, image))
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{AnyHeader, Dimensions, DynFormat, Error, Format, Image, registry};
+
+	/// a format from outside the crate: the magic, then a byte each of the
+	/// width and height, and then the pixels.
+	#[derive(Debug, Clone)]
+	struct Toy(&'static [u8]);
+
+	impl Default for Toy {
+		fn default() -> Self {
+			Self(b"TOY")
+		}
+	}
+
+	struct ToyHeader(u32, u32);
+
+	impl Dimensions for ToyHeader {
+		fn width(&self) -> u32 {
+			self.0
+		}
+
+		fn height(&self) -> u32 {
+			self.1
+		}
+	}
+
+	impl Format for Toy {
+		type Header = ToyHeader;
+		type Options = ();
+
+		fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+			let mut buf = vec![0; self.0.len() + 2];
+			data.read_exact(&mut buf)?;
+			if !buf.starts_with(self.0) {
+				return Err(Error::InvalidMagic { offset: 0 });
+			}
+			let (width, height) = (buf[self.0.len()] as u32, buf[self.0.len() + 1] as u32);
+			let mut pixels = vec![0; width as usize * height as usize * 4];
+			data.read_exact(&mut pixels)?;
+			let pixels = pixels.chunks_exact(4).map(|px| (px[0], px[1], px[2], px[3])).collect::<Vec<_>>();
+			Ok((ToyHeader(width, height), pixels.into_iter()))
+		}
+
+		fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+			out.write_all(self.0)?;
+			out.write_all(&[header.0 as u8, header.1 as u8])?;
+			for px in data {
+				out.write_all(&[px.0, px.1, px.2, px.3])?;
+			}
+			Ok(())
+		}
+	}
+
+	fn toy(magic: &'static [u8]) -> Box<dyn DynFormat> {
+		Box::new(registry::Registered::new(Toy(magic), ToyHeader))
+	}
+
+	fn encode(format: &dyn DynFormat, image: &Image) -> Vec<u8> {
+		let header = AnyHeader::Other { width: image.width(), height: image.height() };
+		let mut out = vec![];
+		format.encode(&mut image.pixels().iter().copied(), header, &mut out).expect("error?");
+		out
+	}
+
+	fn image() -> Image {
+		Image::collect(3, 2, (0..6).map(|i| (i, i * 2, i * 3, 255 - i))).expect("error?")
+	}
+
+	#[test]
+	fn register() {
+		// each test has its own magic, since the registry is shared
+		registry::register("toy a", b"TOYA", toy(b"TOYA")).expect("error?");
+		let format = registry::by_name("toy a").expect("registered?");
+		let out = encode(&*format, &image());
+		assert_eq!(out[..6], *b"TOYA\x03\x02");
+
+		let (name, decoded) = registry::decode(&mut &out[..]).expect("decoded?");
+		assert_eq!((name, decoded), ("toy a", image()));
+		let mut data: &mut dyn std::io::Read = &mut &out[..];
+		let (header, _) = format.decode(&mut data).expect("error?");
+		assert_eq!(header, AnyHeader::Other { width: 3, height: 2 });
+
+		// nothing registered starts like this, and cut short
+		assert!(matches!(registry::decode(&mut &b"TOYZ\x01\x01abcd"[..]), Err(Error::InvalidMagic { offset: 0 })));
+		assert!(matches!(registry::decode(&mut &out[..10]), Err(Error::Io(_))));
+		assert!(matches!(registry::decode(&mut &b"TO"[..]), Err(Error::InvalidMagic { offset: 0 })));
+		assert!(matches!(registry::register("empty", b"", toy(b"")), Err(Error::InvalidInput { .. })));
+
+		// one that only decodes
+		registry::register("toy a?", b"TOYA?", Box::new(registry::Registered::<Toy>::default())).expect("error?");
+		let format = registry::by_name("toy a?").expect("registered?");
+		let result = format.encode(&mut image().pixels().iter().copied(), AnyHeader::Other { width: 3, height: 2 }, &mut vec![]);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn precedence() {
+		let name = |start: &[u8]| registry::find(start).map(|(name, _)| name);
+		registry::register("toy b", b"TOYB", toy(b"TOYB")).expect("error?");
+		registry::register("toy b+", b"TOYB+", toy(b"TOYB+")).expect("error?");
+		assert_eq!(name(b"TOYB+..."), Some("toy b+"));
+		assert_eq!(name(b"TOYB-..."), Some("toy b"));
+
+		// the same magic again, which takes over
+		registry::register("toy b again", b"TOYB", toy(b"TOYB")).expect("error?");
+		assert_eq!(name(b"TOYB-..."), Some("toy b again"));
+	}
+
+	#[test]
+	fn detect_and_by_name() {
+		use crate::{detect, dynamic};
+
+		// longer than any of the crate's magics, so `detect` reads further
+		let magic = b"TOYC, which is long";
+		registry::register("toy c", magic, toy(magic)).expect("error?");
+		let out = encode(&*dynamic::by_name("toy c").expect("registered?"), &image());
+
+		let (format, mut data) = detect::detect(&out[..]).expect("error?");
+		let format = format.expect("detected?");
+		assert!(matches!(format, detect::Detected::Registered(_)), "{format:?}");
+		assert_eq!(format.name(), "toy c");
+		assert_eq!(format.decode(&mut data).expect("error?"), image());
+		assert_eq!(detect::detect(&out[..10]).expect("error?").0, None);
+	}
+
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn crate_format() {
+		use crate::qoi;
+
+		// the crate's formats are `DynFormat`s as they are
+		registry::register("qoi", b"qoif", Box::new(qoi::Qoi)).expect("error?");
+		let format = registry::by_name("qoi").expect("registered?");
+		let header = qoi::QoiHeader { width: 3, height: 2, channels: qoi::QoiHeaderChannels::RGBA, colorspace: qoi::QoiHeaderColorspace::SRGB };
+		let mut out = vec![];
+		format.encode(&mut image().pixels().iter().copied(), header.into(), &mut out).expect("error?");
+		let (name, decoded) = registry::decode(&mut &out[..]).expect("decoded?");
+		assert_eq!((name, decoded), ("qoi", image()));
+
+		// the crate's own still come first elsewhere
+		assert_eq!(crate::detect::detect(&out[..]).expect("error?").0, Some(crate::Detected::Qoi));
+
+		// cut off in the pixels, which is an error of its own
+		let result = registry::decode(&mut &out[..16]).map(|_| ());
+		assert!(matches!(result, Err(Error::UnexpectedEof { offset: 16 })), "{result:?}");
+	}
+}
@@ -0,0 +1,256 @@
+/*!
+handing out parts of an image as it's decoded: a row at a time, or just a
+rectangle of it.
+
+`Rows` collects each row into a buffer it keeps, and lends it out until the
+next one is asked for, so nothing is allocated past the first row. the
+buffer grows as pixels arrive, rather than to the width the header claims.
+
+`SubImage` stops once its rectangle is done, so nothing after it is read.
+what's before it, and around it in each row, goes through a skipping
+function, which formats that can skip faster than they decode pass in.
+*/
+
+use crate::error::oom;
+use crate::{Dimensions, Error, Rgba};
+
+/// pixels a row at a time, from an iterator of them.
+#[derive(Debug)]
+pub struct Rows<I> {
+	pixels: I,
+	width: u32,
+	height: u32,
+	/// rows handed out so far.
+	y: u32,
+	row: Vec<Rgba>,
+	failed: bool,
+}
+
+impl<I: Iterator<Item = Result<Rgba, Error>>> Rows<I> {
+	/// rows of an image `width` pixels wide and `height` tall.
+	pub fn new(pixels: I, width: u32, height: u32) -> Self {
+		Self {
+			pixels,
+			width,
+			height,
+			y: 0,
+			row: vec![],
+			failed: false,
+		}
+	}
+
+	/// rows handed out so far.
+	pub fn y(&self) -> u32 {
+		self.y
+	}
+
+	/// the next row, or `None` after the last. the pixels running out early
+	/// is a `PixelCountMismatch`, and after an error there are no more rows.
+	pub fn next_row(&mut self) -> Result<Option<&[Rgba]>, Error> {
+		if self.failed || self.y == self.height {
+			return Ok(None);
+		}
+		let width = self.width as usize;
+		self.row.clear();
+		for px in (&mut self.pixels).take(width) {
+			let px = px.inspect_err(|_| self.failed = true)?;
+			if self.row.len() == self.row.capacity() {
+				// grown as pixels arrive, so a header can't reserve more than the data fills
+				self.row.try_reserve(1).map_err(|_| {
+					self.failed = true;
+					oom()
+				})?;
+			}
+			self.row.push(px);
+		}
+		if self.row.len() < width {
+			self.failed = true;
+			let got = self.y as u64 * width as u64 + self.row.len() as u64;
+			return Err(Error::PixelCountMismatch { expected: self.width as u64 * self.height as u64, got });
+		}
+		self.y += 1;
+		Ok(Some(&self.row))
+	}
+
+	/// the pixels, with whatever rows haven't been handed out.
+	pub fn into_inner(self) -> I {
+		self.pixels
+	}
+}
+
+/// skips `n` pixels of `pixels` by decoding and dropping them, and returns
+/// how many there were, up to the error that stopped them.
+pub(crate) fn discard<I: Iterator<Item = Result<Rgba, Error>>>(pixels: &mut I, n: u64) -> Result<u64, Error> {
+	let mut skipped = 0;
+	while skipped < n {
+		match pixels.next() {
+			Some(px) => _ = px?,
+			None => break,
+		}
+		skipped += 1;
+	}
+	Ok(skipped)
+}
+
+/// the pixels of a rectangle of an image, and an error if any came up
+/// before its end.
+pub(crate) struct SubImage<I> {
+	pixels: I,
+	/// skips pixels, returning how many there were.
+	skip: fn(&mut I, u64) -> Result<u64, Error>,
+	/// pixels to skip before the next one.
+	gap: u64,
+	/// pixels of each row that are skipped, after the first.
+	stride: u64,
+	width: u64,
+	/// pixels of the rectangle still to come.
+	left: u64,
+	/// pixels the rectangle and what came before it take up, and how many
+	/// of them were read, for when the pixels end early.
+	expected: u64,
+	got: u64,
+}
+
+impl<I: Iterator<Item = Result<Rgba, Error>>> SubImage<I> {
+	/// the `width` by `height` rectangle of `pixels`, whose image `header` is
+	/// for, with its top left at `(x, y)`.
+	pub(crate) fn new(pixels: I, skip: fn(&mut I, u64) -> Result<u64, Error>, header: &impl Dimensions, x: u32, y: u32, width: u32, height: u32) -> Result<Self, Error> {
+		let (image_width, image_height) = (header.width() as u64, header.height() as u64);
+		if x as u64 + width as u64 > image_width || y as u64 + height as u64 > image_height {
+			return Err(Error::OutOfBounds { x, y, width, height });
+		}
+		let left = width as u64 * height as u64;
+		let gap = y as u64 * image_width + x as u64;
+		Ok(Self {
+			pixels,
+			skip,
+			gap,
+			stride: image_width - width as u64,
+			width: width as u64,
+			left,
+			expected: gap + left + (height as u64).saturating_sub(1) * (image_width - width as u64),
+			got: 0,
+		})
+	}
+
+	fn fail(&mut self, e: Error) -> Option<Result<Rgba, Error>> {
+		self.left = 0;
+		Some(Err(e))
+	}
+}
+
+impl<I: Iterator<Item = Result<Rgba, Error>>> Iterator for SubImage<I> {
+	type Item = Result<Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.left == 0 {
+			return None;
+		}
+		if self.gap > 0 {
+			match (self.skip)(&mut self.pixels, self.gap) {
+				Ok(skipped) => self.got += skipped,
+				Err(e) => return self.fail(e),
+			}
+			self.gap = 0;
+		}
+
+		match self.pixels.next() {
+			Some(Ok(px)) => {
+				self.got += 1;
+				self.left -= 1;
+				if self.left.is_multiple_of(self.width) {
+					self.gap = self.stride;
+				}
+				Some(Ok(px))
+			}
+			Some(Err(e)) => self.fail(e),
+			None => {
+				let (expected, got) = (self.expected, self.got);
+				self.fail(Error::PixelCountMismatch { expected, got })
+			}
+		}
+	}
+
+	/// an error ends it early, so this is as exact as it gets.
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(0, self.left.try_into().ok())
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Rgba, rows, rows::Rows};
+
+	fn pixels(n: u8) -> impl Iterator<Item = Result<Rgba, Error>> {
+		(0..n).map(|i| Ok((i, i, i, 255)))
+	}
+
+	#[test]
+	fn rows() {
+		let mut rows = Rows::new(pixels(12), 4, 3);
+		for y in 0..3 {
+			let row = rows.next_row().expect("error?").expect("row?");
+			assert_eq!(row.iter().map(|px| px.0).collect::<Vec<_>>(), (y * 4..y * 4 + 4).collect::<Vec<_>>());
+			assert_eq!(rows.y(), y as u32 + 1);
+		}
+		assert!(rows.next_row().expect("error?").is_none());
+
+		// short, and a row partway
+		let mut rows = Rows::new(pixels(10), 4, 3);
+		rows.next_row().expect("error?");
+		rows.next_row().expect("error?");
+		assert!(matches!(rows.next_row(), Err(Error::PixelCountMismatch { expected: 12, got: 10 })));
+		assert!(rows.next_row().expect("error?").is_none());
+
+		// an error ends it
+		let failing = pixels(6).chain([Err(Error::Malformed { offset: 3, reason: "test" })]);
+		let mut rows = Rows::new(failing, 4, 3);
+		rows.next_row().expect("error?");
+		assert!(matches!(rows.next_row(), Err(Error::Malformed { offset: 3, .. })));
+		assert!(rows.next_row().expect("error?").is_none());
+
+		// nothing wide has rows of nothing
+		let mut rows = Rows::new(pixels(0), 0, 2);
+		assert_eq!(rows.next_row().expect("error?"), Some(&[][..]));
+		assert_eq!(rows.next_row().expect("error?"), Some(&[][..]));
+		assert!(rows.next_row().expect("error?").is_none());
+	}
+
+	#[test]
+	fn sub_image() {
+		let header = crate::Image::new(4, 3, (0, 0, 0, 0)).expect("error?");
+		let take = |x, y, width, height, n| -> Vec<Result<Rgba, Error>> {
+			rows::SubImage::new(pixels(n), rows::discard, &header, x, y, width, height).expect("in bounds?").collect()
+		};
+		let values = |pixels: Vec<Result<Rgba, Error>>| pixels.into_iter().map(|px| px.expect("error?").0).collect::<Vec<_>>();
+		assert_eq!(values(take(1, 1, 2, 2, 12)), [5, 6, 9, 10]);
+		assert_eq!(values(take(0, 2, 4, 1, 12)), [8, 9, 10, 11]);
+		assert_eq!(values(take(3, 0, 1, 3, 12)), [3, 7, 11]);
+		assert!(take(2, 1, 0, 2, 12).is_empty());
+
+		// the pixels ending early
+		assert!(matches!(take(1, 1, 2, 2, 7)[..], [Ok(_), Ok(_), Err(Error::PixelCountMismatch { expected: 11, got: 7 })]));
+		assert!(matches!(take(0, 2, 1, 1, 3)[..], [Err(Error::PixelCountMismatch { expected: 9, got: 3 })]));
+
+		let result = rows::SubImage::new(pixels(12), rows::discard, &header, 0, 2, 1, 2);
+		assert!(matches!(result, Err(Error::OutOfBounds { x: 0, y: 2, width: 1, height: 2 })));
+	}
+
+	#[cfg(feature = "qoi")]
+	#[test]
+	fn decode_rows() {
+		use crate::{Format, qoi};
+
+		let image = include_bytes!("../test/small.qoi");
+		let (_, pixels) = qoi::decode_to_vec(image).expect("error?");
+		let mut data = &image[..];
+		let (_, mut rows) = qoi::Qoi.decode_rows(&mut data).expect("error?");
+		let mut n = 0;
+		while let Some(row) = rows.next_row().expect("error?") {
+			assert_eq!(row, &pixels[n * 4..n * 4 + 4]);
+			n += 1;
+		}
+		assert_eq!(n, 4);
+	}
+}
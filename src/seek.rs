@@ -0,0 +1,29 @@
+/*!
+decoding from any row, for readers that can seek.
+
+formats that store every row in the same number of bytes, uncompressed, can
+work out where a row starts without reading what's before it.
+`SeekDecode` starts their pixels at a row, and their pixel types also have
+a `seek_row`, which jumps to another row partway through, back as well as
+forward. dds, which only decodes, has `seek_row` too.
+*/
+
+use crate::{Error, Format, Rgba};
+
+/// a `Format` whose pixels can start at any row.
+pub trait SeekDecode: Format {
+	/// like `decode_checked`, with the pixels starting at the start of row
+	/// `y`, and the rows before it sought past. `y` can be the height, which
+	/// leaves no pixels, and past that is `OutOfBounds`.
+	fn decode_from_row<R: std::io::Read + std::io::Seek>(self, data: &mut R, y: u32) -> Result<(Self::Header, impl Iterator<Item = Result<Rgba, Error>>), Error>;
+}
+
+/// seeks from `from` to `to`, which are offsets from the same place.
+#[cfg(any_seeker)]
+pub(crate) fn seek<R: std::io::Seek>(data: &mut R, from: u64, to: u64) -> Result<(), Error> {
+	if from != to {
+		let by = i64::try_from(to as i128 - from as i128).map_err(|_| Error::InvalidInput { reason: "too far to seek" })?;
+		data.seek(std::io::SeekFrom::Current(by))?;
+	}
+	Ok(())
+}
@@ -0,0 +1,700 @@
+/*!
+sgi images, also known as `.rgb` or iris images.
+
+8 and 16-bit images with 1 to 4 channels are decoded, verbatim or rle, as
+gray, gray and alpha, rgb, or rgba. 16-bit samples come out as their high
+byte. only normal images are read, not the old dithered, screen or color map
+ones, whose pixels aren't colors.
+
+the header is 512 bytes of big-endian numbers, and the name. the channels
+come one after another, as planes, and each is stored bottom-up, the last
+row first. rle images have a table of where each row of each plane starts,
+and one of how long it is, and then the rows, in whatever order and place
+they like, even sharing bytes. a row is a control item, its high bit set for
+that many items as they are, or clear for the next item repeated that many
+times, until a control item of 0. items are a channel's width, and the count
+is in the low byte of 16-bit ones.
+
+since pixels are handed out top to bottom, the pixel data is read and kept
+whole before the first pixel. the encoder keeps every pixel until the end,
+and writes verbatim planes, or rle rows in order, after the tables. a short
+iterator leaves the rest of the image 0.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+const MAGIC: u16 = 474;
+const HEADER_SIZE: u64 = 512;
+/// the name, which is nul-terminated in 80 bytes.
+const NAME_MAX: usize = 79;
+/// the longest run an rle control item holds.
+const RUN_MAX: usize = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum SgiColorType {
+	Gray,
+	GrayAlpha,
+	RGB,
+	RGBA,
+}
+
+impl SgiColorType {
+	fn channels(self) -> usize {
+		match self {
+			Self::Gray => 1,
+			Self::GrayAlpha => 2,
+			Self::RGB => 3,
+			Self::RGBA => 4,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SgiHeader {
+	pub width: u32,
+	pub height: u32,
+	pub color_type: SgiColorType,
+	/// bits per channel, 8 or 16. 16-bit images are encoded with each
+	/// sample's byte repeated.
+	pub bit_depth: u8,
+	/// whether rows are run-length encoded.
+	pub rle: bool,
+	/// the image's name, up to 79 bytes without nuls.
+	pub name: String,
+}
+
+impl crate::Dimensions for SgiHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sgi;
+
+impl Default for Sgi {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// `len` bytes at `offset`, read a piece at a time, so memory is only taken
+/// up once the data shows up.
+fn read(data: &mut impl std::io::Read, offset: u64, len: u64, buf: &mut Vec<u8>) -> Result<(), Error> {
+	let mut done = 0;
+	while done < len {
+		let n = (len - done).min(1 << 16) as usize;
+		let at = buf.len();
+		buf.try_reserve(n).map_err(|_| oom())?;
+		buf.resize(at + n, 0);
+		crate::read_exact(data, &mut buf[at..], offset + done)?;
+		done += n as u64;
+	}
+	Ok(())
+}
+
+/// unpacks an rle row of `bytes`-wide items into a sample for each pixel.
+fn unpack(data: &[u8], bytes: usize, width: usize, out: &mut Vec<u8>) -> Result<(), &'static str> {
+	out.clear();
+	let mut items = data.chunks_exact(bytes);
+	while out.len() < width {
+		let short = "row ends before its last pixel";
+		let control = items.next().ok_or(short)?[bytes - 1];
+		let n = (control & 0x7f) as usize;
+		if n == 0 {
+			return Err(short);
+		}
+		if out.len() + n > width {
+			return Err("run goes past the end of the row");
+		}
+		match control & 0x80 {
+			0 => {
+				let item = items.next().ok_or(short)?;
+				out.resize(out.len() + n, item[0]);
+			}
+			_ => {
+				for _ in 0..n {
+					out.push(items.next().ok_or(short)?[0]);
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+/// pixels of an sgi image, read from the reader and kept whole before the
+/// first is handed out.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct SgiPixels<'a, R> {
+	header: SgiHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// where each row of each plane starts, and how long it is, for rle
+	/// images. the first plane's bottom row is first.
+	table: Vec<(u64, u64)>,
+	/// the pixel data, from right after the header or the tables, once it's
+	/// been read.
+	pixel_data: Vec<u8>,
+	loaded: bool,
+	/// a row of one plane, a sample for each pixel.
+	plane: Vec<u8>,
+	/// the row being handed out, with its channels together.
+	row: Vec<u8>,
+	/// next pixel of `row` to hand out.
+	x: u32,
+	/// rows that haven't been handed out.
+	rows: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> SgiPixels<'a, R> {
+	/// reads the header, and for rle images, the tables, up to the pixel
+	/// data.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; HEADER_SIZE as usize];
+		crate::read_exact(data, &mut buf, 0)?;
+		let u16_at = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]);
+		if u16_at(0) != MAGIC {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let rle = match buf[2] {
+			0 => false,
+			1 => true,
+			_ => return Err(field(2, "storage")),
+		};
+		let bit_depth = match buf[3] {
+			1 => 8,
+			2 => 16,
+			_ => return Err(field(3, "bytes per channel")),
+		};
+		// one row, one plane, or as many planes as it says
+		let (height, channels) = match u16_at(4) {
+			1 => (1, 1),
+			2 => (u16_at(8), 1),
+			3 => (u16_at(8), u16_at(10)),
+			_ => return Err(field(4, "dimension")),
+		};
+		let width = u16_at(6) as u32;
+		if width == 0 {
+			return Err(field(6, "width"));
+		}
+		if height == 0 {
+			return Err(field(8, "height"));
+		}
+		let color_type = match channels {
+			1 => SgiColorType::Gray,
+			2 => SgiColorType::GrayAlpha,
+			3 => SgiColorType::RGB,
+			4 => SgiColorType::RGBA,
+			_ => return Err(field(10, "channels")),
+		};
+		if buf[104..108] != [0; 4] {
+			return Err(field(104, "color map"));
+		}
+		let name = &buf[24..24 + NAME_MAX + 1];
+		let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+
+		let mut offset = HEADER_SIZE;
+		let mut table = vec![];
+		if rle {
+			let n = height as usize * channels as usize;
+			let mut tables = vec![];
+			read(data, offset, n as u64 * 8, &mut tables)?;
+			let (starts, lengths) = tables.split_at(n * 4);
+			let at = |b: &[u8], i: usize| u32::from_be_bytes([b[i * 4], b[i * 4 + 1], b[i * 4 + 2], b[i * 4 + 3]]) as u64;
+			offset += n as u64 * 8;
+			table.try_reserve_exact(n).map_err(|_| oom())?;
+			for i in 0..n {
+				let start = at(starts, i);
+				if start < offset {
+					let reason = "row starts before the end of the tables";
+					return Err(Error::Malformed { offset: HEADER_SIZE + i as u64 * 4, reason });
+				}
+				table.push((start, at(lengths, i)));
+			}
+		}
+
+		Ok(Self {
+			header: SgiHeader {
+				width,
+				height: height as u32,
+				color_type,
+				bit_depth,
+				rle,
+				name: String::from_utf8_lossy(name).into_owned(),
+			},
+			data,
+			offset,
+			table,
+			pixel_data: vec![],
+			loaded: false,
+			plane: vec![],
+			row: vec![],
+			x: width,
+			rows: height as u32,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &SgiHeader {
+		&self.header
+	}
+
+	/// bytes a sample takes up.
+	fn bytes(&self) -> usize {
+		self.header.bit_depth as usize / 8
+	}
+
+	/// reads all of the pixel data, up to the furthest any row goes for rle
+	/// images.
+	fn load(&mut self) -> Result<(), Error> {
+		let len = match self.header.rle {
+			true => self.table.iter().map(|&(start, len)| start.saturating_add(len) - self.offset).max().unwrap_or(0),
+			false => {
+				let channels = self.header.color_type.channels() as u64;
+				(self.header.width as u64 * self.header.height as u64).saturating_mul(channels * self.bytes() as u64)
+			}
+		};
+		read(self.data, self.offset, len, &mut self.pixel_data)?;
+		self.offset += len;
+		self.loaded = true;
+		Ok(())
+	}
+
+	fn next_row(&mut self) -> Result<(), Error> {
+		if !self.loaded {
+			self.load()?;
+		}
+		// the top row left is the last one stored
+		self.rows -= 1;
+		let (width, height) = (self.header.width as usize, self.header.height as usize);
+		let (bytes, channels) = (self.bytes(), self.header.color_type.channels());
+		let base = self.offset - self.pixel_data.len() as u64;
+		self.row.clear();
+		self.row.try_reserve(width * channels).map_err(|_| oom())?;
+		self.row.resize(width * channels, 0);
+		for c in 0..channels {
+			let i = c * height + self.rows as usize;
+			match self.header.rle {
+				true => {
+					let (start, len) = self.table[i];
+					let at = (start - base) as usize;
+					let data = &self.pixel_data[at..at + len as usize];
+					if let Err(reason) = unpack(data, bytes, width, &mut self.plane) {
+						return Err(Error::Malformed { offset: start, reason });
+					}
+				}
+				false => {
+					let stride = width * bytes;
+					let data = &self.pixel_data[i * stride..(i + 1) * stride];
+					self.plane.clear();
+					self.plane.extend(data.chunks_exact(bytes).map(|item| item[0]));
+				}
+			}
+			for (x, &sample) in self.plane.iter().enumerate() {
+				self.row[x * channels + c] = sample;
+			}
+		}
+		self.x = 0;
+		Ok(())
+	}
+
+	/// reads whatever's left of the pixel data, and returns the number of
+	/// bytes the image took up, which for rle images is up to the end of the
+	/// row that goes furthest. nothing after it is read.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		if !self.loaded {
+			self.load()?;
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<R: std::io::Read> Iterator for SgiPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+		if self.x == self.header.width {
+			if self.rows == 0 {
+				return None;
+			}
+			if let Err(e) = self.next_row() {
+				self.failed = true;
+				return Some(Err(e));
+			}
+		}
+
+		let channels = self.header.color_type.channels();
+		let i = self.x as usize * channels;
+		let px = &self.row[i..i + channels];
+		self.x += 1;
+		Some(Ok(match *px {
+			[v] => (v, v, v, 255),
+			[v, a] => (v, v, v, a),
+			[r, g, b] => (r, g, b, 255),
+			[r, g, b, a, ..] => (r, g, b, a),
+			[] => (0, 0, 0, 255),
+		}))
+	}
+}
+
+/// `Format::decode` hands out pixels until the first error.
+struct Pixels<'a, R>(SgiPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// packs one plane's row of samples into rle items, with the control item
+/// of 0 at the end.
+fn pack(row: &[u8], bytes: usize, out: &mut Vec<u8>) {
+	let item = |out: &mut Vec<u8>, b: u8| match bytes {
+		2 => out.extend_from_slice(&[b, b]),
+		_ => out.push(b),
+	};
+	let control = |out: &mut Vec<u8>, b: u8| match bytes {
+		2 => out.extend_from_slice(&[0, b]),
+		_ => out.push(b),
+	};
+	// a run of at least 3 is worth its own control item
+	let run = |i: usize| row[i..].iter().take(RUN_MAX).take_while(|&&b| b == row[i]).count();
+	let mut i = 0;
+	while i < row.len() {
+		let n = run(i);
+		if n >= 3 {
+			control(out, n as u8);
+			item(out, row[i]);
+			i += n;
+			continue;
+		}
+		let start = i;
+		while i < row.len() && i - start < RUN_MAX && run(i) < 3 {
+			i += 1;
+		}
+		control(out, 0x80 | (i - start) as u8);
+		for &b in &row[start..i] {
+			item(out, b);
+		}
+	}
+	control(out, 0);
+}
+
+/// push-based encoder state, which `Sgi::encode` drives.
+#[derive(Debug)]
+pub(crate) struct SgiEncoder<W> {
+	out: W,
+	header: SgiHeader,
+	/// every pixel's samples, with their channels together, since planes
+	/// come one after another and rows go bottom-up.
+	samples: Vec<u8>,
+}
+
+impl<W: std::io::Write> SgiEncoder<W> {
+	pub(crate) fn new(header: &SgiHeader, out: W) -> Result<Self, Error> {
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		if !matches!(header.bit_depth, 8 | 16) {
+			return Err(field(3, "bytes per channel"));
+		}
+		if header.width == 0 || header.width > u16::MAX as u32 {
+			return Err(field(6, "width"));
+		}
+		if header.height == 0 || header.height > u16::MAX as u32 {
+			return Err(field(8, "height"));
+		}
+		if header.name.len() > NAME_MAX || header.name.contains('\0') {
+			return Err(field(24, "name"));
+		}
+		Ok(Self { out, header: header.clone(), samples: vec![] })
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		let samples: &[u8] = match self.header.color_type {
+			SgiColorType::Gray => &[crate::pixel::luma(px) as u8],
+			SgiColorType::GrayAlpha => &[crate::pixel::luma(px) as u8, px.3],
+			SgiColorType::RGB => &[px.0, px.1, px.2],
+			SgiColorType::RGBA => &[px.0, px.1, px.2, px.3],
+		};
+		self.samples.try_reserve(samples.len()).map_err(|_| oom())?;
+		self.samples.extend_from_slice(samples);
+		Ok(())
+	}
+
+	/// one plane's row of samples, 0 past the pixels that were pushed.
+	fn plane_row(&self, c: usize, y: usize, row: &mut Vec<u8>) {
+		let channels = self.header.color_type.channels();
+		let width = self.header.width as usize;
+		row.clear();
+		row.extend((0..width).map(|x| self.samples.get((y * width + x) * channels + c).copied().unwrap_or(0)));
+	}
+
+	/// writes the whole image.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		let SgiHeader { width, height, color_type, bit_depth, rle, ref name } = self.header;
+		let channels = color_type.channels();
+		let bytes = bit_depth as usize / 8;
+
+		let mut buf = [0; HEADER_SIZE as usize];
+		buf[0..2].copy_from_slice(&MAGIC.to_be_bytes());
+		buf[2] = rle as u8;
+		buf[3] = bytes as u8;
+		buf[4..6].copy_from_slice(&3u16.to_be_bytes());
+		buf[6..8].copy_from_slice(&(width as u16).to_be_bytes());
+		buf[8..10].copy_from_slice(&(height as u16).to_be_bytes());
+		buf[10..12].copy_from_slice(&(channels as u16).to_be_bytes());
+		let max: u32 = if bytes == 2 { 65535 } else { 255 };
+		buf[16..20].copy_from_slice(&max.to_be_bytes());
+		buf[24..24 + name.len()].copy_from_slice(name.as_bytes());
+		crate::write_all(&mut self.out, &buf)?;
+
+		// every row of the first plane, bottom-up, then the next plane's
+		let rows = (0..channels).flat_map(|c| (0..height as usize).rev().map(move |y| (c, y)));
+		let mut row = vec![];
+		let mut packed = vec![];
+		if !rle {
+			for (c, y) in rows {
+				self.plane_row(c, y, &mut row);
+				packed.clear();
+				for &b in &row {
+					packed.extend_from_slice(&[b, b][..bytes]);
+				}
+				crate::write_all(&mut self.out, &packed)?;
+			}
+			return Ok(());
+		}
+
+		// the tables come first, so the rows are packed once for their
+		// lengths, and again to be written
+		let mut lengths = vec![];
+		lengths.try_reserve_exact(channels * height as usize).map_err(|_| oom())?;
+		for (c, y) in rows.clone() {
+			self.plane_row(c, y, &mut row);
+			packed.clear();
+			pack(&row, bytes, &mut packed);
+			lengths.push(packed.len() as u32);
+		}
+		let mut start = HEADER_SIZE as u32 + lengths.len() as u32 * 8;
+		let mut tables = vec![];
+		tables.try_reserve_exact(lengths.len() * 8).map_err(|_| oom())?;
+		for &len in &lengths {
+			tables.extend_from_slice(&start.to_be_bytes());
+			start = start.checked_add(len).ok_or(Error::InvalidInput { reason: "rows go past where the tables can point" })?;
+		}
+		for &len in &lengths {
+			tables.extend_from_slice(&len.to_be_bytes());
+		}
+		crate::write_all(&mut self.out, &tables)?;
+		for (c, y) in rows {
+			self.plane_row(c, y, &mut row);
+			packed.clear();
+			pack(&row, bytes, &mut packed);
+			crate::write_all(&mut self.out, &packed)?;
+		}
+		Ok(())
+	}
+}
+
+impl crate::Format for Sgi {
+	type Header = SgiHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = SgiPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = SgiPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = SgiEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, sgi};
+
+	const IMAGE_RGB: &[u8] = include_bytes!("../test/rgb.sgi");
+	const IMAGE_RGBA: &[u8] = include_bytes!("../test/rgba.sgi");
+
+	fn color(x: u32, y: u32) -> crate::Rgba {
+		((x * 19) as u8, (y * 36) as u8, ((x + y) * 10) as u8, (x * y * 3 + 7) as u8)
+	}
+
+	#[test]
+	fn decode() {
+		use sgi::SgiColorType::*;
+
+		// verbatim and rle, 8 and 16-bit, and the rle ones in runs of 3
+		for (image, color_type, bit_depth, rle) in [
+			(IMAGE_RGB, RGB, 8, false),
+			(IMAGE_RGBA, RGBA, 8, true),
+			(include_bytes!("../test/gray16.sgi"), Gray, 16, false),
+			(include_bytes!("../test/gray_alpha16.sgi"), GrayAlpha, 16, true),
+		] {
+			let mut data = image;
+			let mut pixels = sgi::SgiPixels::new(&mut data).expect("error?");
+			let header = pixels.header();
+			assert_eq!((header.width, header.height, header.color_type), (13, 7, color_type));
+			assert_eq!((header.bit_depth, header.rle), (bit_depth, rle));
+			let mut n = 0;
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				let (r, g, b, a) = color(if rle { x / 3 * 3 } else { x }, y);
+				let expected = match color_type {
+					RGB => (r, g, b, 255),
+					RGBA => (r, g, b, a),
+					Gray => (r, r, r, 255),
+					GrayAlpha => (r, r, r, a),
+				};
+				assert_eq!(px.expect("error?"), expected, "{color_type:?}: {x}, {y}");
+				n += 1;
+			}
+			assert_eq!(n, 13 * 7);
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			let mut data = image;
+			let pixels = sgi::SgiPixels::new(&mut data).expect("error?");
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+
+		let mut data = IMAGE_RGB;
+		let pixels = sgi::SgiPixels::new(&mut data).expect("error?");
+		assert_eq!(pixels.header().name, "gradient");
+
+		// rle rows out of order, with a gap before them, and the rows 3 and 4
+		// from the top sharing their bytes
+		let image = include_bytes!("../test/shared.sgi");
+		let mut data = &image[..];
+		let pixels = sgi::SgiPixels::new(&mut data).expect("error?");
+		assert_eq!(pixels.header().color_type, Gray);
+		assert!(pixels.map(|px| px.expect("error?")).eq((0..13 * 7).map(|i| {
+			let y = if i / 13 == 4 { 3 } else { i / 13 };
+			let v = (y * 40) as u8;
+			(v, v, v, 255)
+		})));
+	}
+
+	#[test]
+	fn encode() {
+		use sgi::SgiColorType::*;
+
+		for color_type in [Gray, GrayAlpha, RGB, RGBA] {
+			for bit_depth in [8, 16] {
+				for rle in [false, true] {
+					let header = sgi::SgiHeader { width: 13, height: 7, color_type, bit_depth, rle, name: "fuzz".into() };
+					// runs, for rle to pack
+					let pixels = (0..13 * 7).map(|i| match color_type {
+						Gray => (i as u8 / 4, i as u8 / 4, i as u8 / 4, 255),
+						GrayAlpha => (i as u8 / 4, i as u8 / 4, i as u8 / 4, i as u8),
+						RGB => (i as u8 / 4, i as u8, 7, 255),
+						RGBA => (i as u8 / 4, i as u8, 7, (i % 13) as u8),
+					});
+					let mut out = vec![];
+					sgi::Sgi.encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+
+					let mut data = &out[..];
+					let (decoded_header, decoded) = sgi::Sgi.decode(&mut data).expect("error?");
+					assert_eq!(decoded_header, header);
+					assert!(decoded.eq(pixels), "{color_type:?}, {bit_depth}, {rle}");
+				}
+			}
+		}
+
+		// runs of 3 or more get a control item of their own, and longer ones
+		// are split
+		let mut out = vec![];
+		super::pack(&[1, 1, 2, 3, 3, 3, 3, 4], 1, &mut out);
+		assert_eq!(out, [0x83, 1, 1, 2, 4, 3, 0x81, 4, 0]);
+		let mut out = vec![];
+		super::pack(&[7; 130], 2, &mut out);
+		assert_eq!(out, [0, 127, 7, 7, 0, 3, 7, 7, 0, 0]);
+
+		// gray is luma, and a short iterator is an error
+		let header = sgi::SgiHeader { width: 2, height: 2, color_type: Gray, bit_depth: 8, rle: true, name: String::new() };
+		let pixels = [(255, 0, 0, 255), (0, 255, 0, 0), (0, 0, 255, 255), (0, 0, 0, 9)];
+		let mut out = vec![];
+		sgi::Sgi.encode(pixels.into_iter(), header.clone(), &mut out).expect("error?");
+		let mut data = &out[..];
+		let (_, decoded) = sgi::Sgi.decode(&mut data).expect("error?");
+		assert!(decoded.eq([(77, 77, 77, 255), (149, 149, 149, 255), (29, 29, 29, 255), (0, 0, 0, 255)]));
+		let result = sgi::Sgi.encode(pixels[..3].iter().copied(), header.clone(), &mut vec![]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 4, got: 3 })), "{result:?}");
+
+		// too big for the format, and names that don't fit
+		for (header, offset) in [
+			(sgi::SgiHeader { bit_depth: 12, ..header.clone() }, 3),
+			(sgi::SgiHeader { width: 1 << 16, ..header.clone() }, 6),
+			(sgi::SgiHeader { width: 0, ..header.clone() }, 6),
+			(sgi::SgiHeader { height: 1 << 16, ..header.clone() }, 8),
+			(sgi::SgiHeader { name: "a".repeat(80), ..header.clone() }, 24),
+			(sgi::SgiHeader { name: "a\0".into(), ..header.clone() }, 24),
+		] {
+			let result = sgi::SgiEncoder::new(&header, vec![]);
+			assert!(matches!(result, Err(Error::InvalidHeader { offset: o, .. }) if o == offset), "{result:?}");
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = sgi::SgiPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".sgi")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("bad_storage", Error::InvalidHeader { offset: 2, .. });
+		case!("bad_bpc", Error::InvalidHeader { offset: 3, .. });
+		case!("bad_dimension", Error::InvalidHeader { offset: 4, .. });
+		case!("zero_width", Error::InvalidHeader { offset: 6, .. });
+		case!("zero_height", Error::InvalidHeader { offset: 8, .. });
+		case!("five_channels", Error::InvalidHeader { offset: 10, .. });
+		case!("color_map", Error::InvalidHeader { offset: 104, .. });
+		case!("truncated", Error::UnexpectedEof { offset: 700 });
+		case!("truncated_table", Error::UnexpectedEof { offset: 600 });
+		case!("early_row", Error::Malformed { offset: 520, .. });
+		case!("truncated_rows", Error::UnexpectedEof { offset: 970 });
+		case!("short_row", Error::Malformed { reason: "row ends before its last pixel", .. });
+		case!("long_run", Error::Malformed { reason: "run goes past the end of the row", .. });
+
+		assert!(decode(IMAGE_RGB).is_ok());
+		assert!(decode(IMAGE_RGBA).is_ok());
+	}
+}
@@ -0,0 +1,857 @@
+/*!
+tga images.
+
+true color, gray and color-mapped images are decoded, uncompressed or
+run-length encoded. true color ones are 24 or 32-bit, and 32-bit images
+whose descriptor gives them 8 bits of alpha come out with it, and ones with
+none come out opaque. gray and color-mapped ones are 8-bit, and color maps
+have 15, 16, 24 or 32-bit entries, the first two opaque. the image id, and
+the color map of a true color image, are skipped.
+
+rle packets are a byte with the high bit set and then one pixel for a run of
+up to 128 of it, or a byte without and up to 128 pixels as they are. they're
+allowed to run on from one row to the next, and past the last pixel, though
+the encoder never does either.
+
+rows are usually stored bottom-up, the last row first, and sometimes right
+to left, as the origin bits of the descriptor say. since pixels are handed
+out top to bottom, a bottom-up image is read and kept whole before the first
+pixel, and top-down ones are read a row at a time. the encoder is the same,
+so it only streams top-down images.
+
+gray pixels are encoded as their rec. 601 luma, with alpha dropped. color
+maps are written with 24-bit entries, or 32-bit if any of them aren't
+opaque.
+
+nothing is read past the pixels, so the tga 2.0 footer and extension area
+are left alone, and the encoder doesn't write them.
+*/
+
+use std::collections::HashMap;
+
+use crate::Error;
+use crate::error::oom;
+
+/// the fixed part of the header, before the image id.
+const HEADER_SIZE: u64 = 18;
+
+/// the descriptor bits for rows stored right to left, and top to bottom.
+const RIGHT_TO_LEFT: u8 = 1 << 4;
+const TOP_DOWN: u8 = 1 << 5;
+
+/// the most pixels in an rle packet.
+const PACKET_MAX: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum TgaColorType {
+	/// 24-bit, or 32-bit without alpha bits.
+	RGB,
+	/// 32-bit, with 8 alpha bits.
+	RGBA,
+	/// 8-bit gray.
+	Gray,
+	/// 8-bit indices into the color map.
+	Indexed,
+}
+
+impl TgaColorType {
+	/// bytes per pixel, when encoding.
+	fn bytes(self) -> usize {
+		match self {
+			Self::RGB => 3,
+			Self::RGBA => 4,
+			Self::Gray | Self::Indexed => 1,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TgaHeader {
+	pub width: u32,
+	pub height: u32,
+	pub color_type: TgaColorType,
+	/// whether rows are stored top to bottom, rather than tga's usual bottom
+	/// to top.
+	pub top_down: bool,
+	/// whether pixels are stored right to left in their rows.
+	pub right_to_left: bool,
+	/// whether pixels are stored in rle packets.
+	pub rle: bool,
+	/// the color map of indexed images, starting at its first entry, and empty
+	/// otherwise.
+	pub palette: Vec<crate::Rgba>,
+}
+
+impl crate::Dimensions for TgaHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Tga;
+
+impl Default for Tga {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// a color map entry. 15 and 16-bit ones are 5 bits a channel, and opaque.
+fn entry(c: &[u8]) -> crate::Rgba {
+	match *c {
+		[b, g, r, a] => (r, g, b, a),
+		[b, g, r] => (r, g, b, 255),
+		[a, b] => {
+			let x = u16::from_le_bytes([a, b]);
+			let scale = |x: u16| ((x & 31) << 3 | (x & 31) >> 2) as u8;
+			(scale(x >> 10), scale(x >> 5), scale(x), 255)
+		}
+		_ => (0, 0, 0, 255),
+	}
+}
+
+/// pixels of a tga image, read from the reader as they're asked for, or all
+/// at once before the first for bottom-up images.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct TgaPixels<'a, R> {
+	header: TgaHeader,
+	data: &'a mut R,
+	offset: u64,
+	/// bytes per pixel.
+	bytes: usize,
+	stride: usize,
+	/// the index of `palette`'s first entry.
+	map_first: u16,
+	/// pixels left in the rle packet being read, whether it's a run, and the
+	/// pixel it's a run of.
+	packet: u8,
+	repeat: bool,
+	px: [u8; 4],
+	/// the rows that haven't been handed out, for bottom-up images, once
+	/// they've been read.
+	rows_data: Vec<u8>,
+	row: Vec<u8>,
+	/// next pixel of `row` to hand out.
+	x: u32,
+	/// rows that haven't been handed out.
+	rows: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> TgaPixels<'a, R> {
+	/// reads the header, the image id and the color map, up to the pixel
+	/// data.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut buf = [0; HEADER_SIZE as usize];
+		crate::read_exact(data, &mut buf, 0)?;
+		let u16_at = |at: usize| u16::from_le_bytes([buf[at], buf[at + 1]]);
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+
+		let id_length = buf[0];
+		// 1 is color-mapped, 2 true color and 3 gray, and 8 more is rle
+		let (kind, rle) = match buf[2] {
+			1..=3 => (buf[2], false),
+			9..=11 => (buf[2] - 8, true),
+			_ => return Err(field(2, "image type")),
+		};
+		// a color map can come with any image, and is only used by
+		// color-mapped ones
+		let (map_first, map_length, map_bits) = match buf[1] {
+			0 if kind != 1 => (0, 0, 0),
+			1 => (u16_at(3), u16_at(5), buf[7]),
+			_ => return Err(field(1, "color map type")),
+		};
+		if kind == 1 && !matches!(map_bits, 15 | 16 | 24 | 32) {
+			return Err(field(7, "color map entry size"));
+		}
+		let (width, height) = (u16_at(12), u16_at(14));
+		if width == 0 {
+			return Err(field(12, "width"));
+		}
+		if height == 0 {
+			return Err(field(14, "height"));
+		}
+		let descriptor = buf[17];
+		let (bytes, color_type) = match (kind, buf[16], descriptor & 0xf) {
+			// alpha bits of a color-mapped image are about its entries
+			(1, 8, _) => (1, TgaColorType::Indexed),
+			(2, 24, 0) => (3, TgaColorType::RGB),
+			(2, 32, 0) => (4, TgaColorType::RGB),
+			(2, 32, 8) => (4, TgaColorType::RGBA),
+			(3, 8, 0) => (1, TgaColorType::Gray),
+			(2, 24 | 32, _) | (3, 8, _) => return Err(field(17, "alpha bits")),
+			_ => return Err(field(16, "pixel depth")),
+		};
+
+		// the color map of an image that doesn't use it is skipped with the id
+		let entry_size = (map_bits as usize).div_ceil(8);
+		let map_size = map_length as u64 * entry_size as u64;
+		let mut offset = HEADER_SIZE;
+		let mut skip = [0; 512];
+		let mut gap = id_length as u64 + if kind == 1 { 0 } else { map_size };
+		while gap > 0 {
+			let n = gap.min(skip.len() as u64) as usize;
+			crate::read_exact(data, &mut skip[..n], offset)?;
+			offset += n as u64;
+			gap -= n as u64;
+		}
+
+		let mut palette = vec![];
+		if kind == 1 {
+			// at most 65535 entries of 4 bytes
+			let mut map = vec![0; map_size as usize];
+			crate::read_exact(data, &mut map, offset)?;
+			offset += map_size;
+			palette = map.chunks_exact(entry_size).map(entry).collect();
+		}
+
+		let header = TgaHeader {
+			width: width as u32,
+			height: height as u32,
+			color_type,
+			top_down: descriptor & TOP_DOWN != 0,
+			right_to_left: descriptor & RIGHT_TO_LEFT != 0,
+			rle,
+			palette,
+		};
+		// 16-bit dimensions, so this fits
+		let stride = width as usize * bytes;
+		Ok(Self {
+			x: header.width,
+			rows: header.height,
+			header,
+			data,
+			offset,
+			bytes,
+			stride,
+			map_first,
+			packet: 0,
+			repeat: false,
+			px: [0; 4],
+			rows_data: vec![],
+			row: Vec::with_capacity(stride),
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &TgaHeader {
+		&self.header
+	}
+
+	/// reads `len` bytes as they are, appending them to `rows_data` if `keep`.
+	fn raw(&mut self, mut len: u64, keep: bool) -> Result<(), Error> {
+		let mut buf = [0; 512];
+		while len > 0 {
+			let n = len.min(buf.len() as u64) as usize;
+			crate::read_exact(self.data, &mut buf[..n], self.offset)?;
+			self.offset = self.offset.saturating_add(n as u64);
+			if keep {
+				self.rows_data.try_reserve(n).map_err(|_| oom())?;
+				self.rows_data.extend_from_slice(&buf[..n]);
+			}
+			len -= n as u64;
+		}
+		Ok(())
+	}
+
+	/// decodes `pixels` pixels of rle packets, appending them to `rows_data`
+	/// if `keep`.
+	fn packets(&mut self, mut pixels: u64, keep: bool) -> Result<(), Error> {
+		let bytes = self.bytes;
+		let mut buf = [0; PACKET_MAX * 4];
+		while pixels > 0 {
+			if self.packet == 0 {
+				crate::read_exact(self.data, &mut buf[..1], self.offset)?;
+				self.offset = self.offset.saturating_add(1);
+				self.packet = (buf[0] & 0x7f) + 1;
+				self.repeat = buf[0] & 0x80 != 0;
+				if self.repeat {
+					crate::read_exact(self.data, &mut self.px[..bytes], self.offset)?;
+					self.offset = self.offset.saturating_add(bytes as u64);
+				}
+			}
+
+			let n = pixels.min(self.packet as u64) as usize;
+			if self.repeat {
+				for px in buf[..n * bytes].chunks_exact_mut(bytes) {
+					px.copy_from_slice(&self.px[..bytes]);
+				}
+			}
+			else {
+				crate::read_exact(self.data, &mut buf[..n * bytes], self.offset)?;
+				self.offset = self.offset.saturating_add((n * bytes) as u64);
+			}
+			if keep {
+				self.rows_data.try_reserve(n * bytes).map_err(|_| oom())?;
+				self.rows_data.extend_from_slice(&buf[..n * bytes]);
+			}
+			self.packet -= n as u8;
+			pixels -= n as u64;
+		}
+		Ok(())
+	}
+
+	/// appends a row to `rows_data`.
+	fn read_row(&mut self) -> Result<(), Error> {
+		match self.header.rle {
+			true => self.packets(self.header.width as u64, true),
+			false => self.raw(self.stride as u64, true),
+		}
+	}
+
+	fn next_row(&mut self) -> Result<(), Error> {
+		if self.header.top_down {
+			self.rows_data.clear();
+			self.read_row()?;
+		}
+		else if self.rows == self.header.height {
+			for _ in 0..self.header.height {
+				self.read_row()?;
+			}
+		}
+		// the last row left is the top one
+		let start = self.rows_data.len().saturating_sub(self.stride);
+		self.row.clear();
+		self.row.extend_from_slice(&self.rows_data[start..]);
+		self.rows_data.truncate(start);
+		self.rows -= 1;
+		self.x = 0;
+		Ok(())
+	}
+
+	/// skips any pixels that are left, and returns the number of bytes the
+	/// image took up. nothing after the pixel data is read, though the rest of
+	/// an rle packet that runs past the last pixel is.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		// bottom-up images have been read whole once they've been started
+		let mut left = 0;
+		if self.header.top_down || self.rows == self.header.height {
+			left = self.rows as u64 * self.header.width as u64;
+		}
+		if self.header.rle {
+			self.packets(left, false)?;
+			if !self.repeat {
+				self.raw(self.packet as u64 * self.bytes as u64, false)?;
+			}
+		}
+		else {
+			self.raw(left * self.bytes as u64, false)?;
+		}
+		Ok(self.offset)
+	}
+}
+
+impl<R: std::io::Read> Iterator for TgaPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+		if self.x == self.header.width {
+			if self.rows == 0 {
+				return None;
+			}
+			if let Err(e) = self.next_row() {
+				self.failed = true;
+				return Some(Err(e));
+			}
+		}
+
+		let x = match self.header.right_to_left {
+			true => self.header.width - 1 - self.x,
+			false => self.x,
+		};
+		let i = x as usize * self.bytes;
+		self.x += 1;
+		Some(Ok(match self.row[i..i + self.bytes] {
+			[i] if self.header.color_type == TgaColorType::Indexed => {
+				let entry = (i as u16).checked_sub(self.map_first);
+				match entry.and_then(|i| self.header.palette.get(i as usize)) {
+					Some(&px) => px,
+					None => {
+						self.failed = true;
+						let reason = "color index isn't in the color map";
+						return Some(Err(Error::Malformed { offset: self.offset, reason }));
+					}
+				}
+			}
+			[g] => (g, g, g, 255),
+			[b, g, r] => (r, g, b, 255),
+			[b, g, r, _] if self.header.color_type == TgaColorType::RGB => (r, g, b, 255),
+			[b, g, r, a] => (r, g, b, a),
+			_ => return None,
+		}))
+	}
+}
+
+/// `TgaPixels`, ending at the first error, for `Format::decode`.
+struct Pixels<'a, R>(TgaPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// `row` of pixels of `bytes` bytes as rle packets, appended to `out`. runs
+/// of two or more are packed, and the pixels between them left as they are.
+fn pack(row: &[u8], bytes: usize, out: &mut Vec<u8>) {
+	let px = |i: usize| &row[i * bytes..(i + 1) * bytes];
+	let n = row.len() / bytes;
+	let mut i = 0;
+	while i < n {
+		let run = (i..n.min(i + PACKET_MAX)).take_while(|&j| px(j) == px(i)).count();
+		if run > 1 {
+			out.push(0x80 | (run - 1) as u8);
+			out.extend_from_slice(px(i));
+			i += run;
+			continue;
+		}
+		// up to where the next run starts
+		let mut end = i + 1;
+		while end < n && end - i < PACKET_MAX && (end + 1 == n || px(end) != px(end + 1)) {
+			end += 1;
+		}
+		out.push((end - i - 1) as u8);
+		out.extend_from_slice(&row[i * bytes..end * bytes]);
+		i = end;
+	}
+}
+
+/// push-based encoder state, which `Tga::encode` drives.
+#[derive(Debug)]
+pub(crate) struct TgaEncoder<W> {
+	out: W,
+	color_type: TgaColorType,
+	top_down: bool,
+	right_to_left: bool,
+	rle: bool,
+	stride: usize,
+	/// where each color map entry goes, for indexed images.
+	indices: HashMap<crate::Rgba, u8>,
+	row: Vec<u8>,
+	/// `row` as rle packets.
+	packed: Vec<u8>,
+	/// the rows so far, for bottom-up images.
+	rows: Vec<Vec<u8>>,
+}
+
+impl<W: std::io::Write> TgaEncoder<W> {
+	/// writes the header and any color map.
+	pub(crate) fn new(header: &TgaHeader, mut out: W) -> Result<Self, Error> {
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		if header.width == 0 || header.width > u16::MAX as u32 {
+			return Err(field(12, "width"));
+		}
+		if header.height == 0 || header.height > u16::MAX as u32 {
+			return Err(field(14, "height"));
+		}
+		let indexed = header.color_type == TgaColorType::Indexed;
+		if indexed && !(1..=256).contains(&header.palette.len()) {
+			return Err(field(5, "color map length"));
+		}
+		let (width, height) = (header.width as u16, header.height as u16);
+		let bytes = header.color_type.bytes();
+		let (kind, alpha_bits) = match header.color_type {
+			TgaColorType::Indexed => (1, 0),
+			TgaColorType::RGB => (2, 0),
+			TgaColorType::RGBA => (2, 8),
+			TgaColorType::Gray => (3, 0),
+		};
+		let mut descriptor = alpha_bits;
+		if header.top_down {
+			descriptor |= TOP_DOWN;
+		}
+		if header.right_to_left {
+			descriptor |= RIGHT_TO_LEFT;
+		}
+
+		let mut map = vec![];
+		let mut indices = HashMap::new();
+		if indexed {
+			let alpha = header.palette.iter().any(|px| px.3 != 255);
+			for (i, &px) in header.palette.iter().enumerate() {
+				match alpha {
+					true => map.extend_from_slice(&[px.2, px.1, px.0, px.3]),
+					false => map.extend_from_slice(&[px.2, px.1, px.0]),
+				}
+				indices.entry(px).or_insert(i as u8);
+			}
+		}
+
+		let mut buf = [0; HEADER_SIZE as usize];
+		// no image id
+		buf[1] = indexed as u8;
+		buf[2] = kind + if header.rle { 8 } else { 0 };
+		if indexed {
+			buf[5..7].copy_from_slice(&(header.palette.len() as u16).to_le_bytes());
+			buf[7] = (map.len() / header.palette.len() * 8) as u8;
+		}
+		buf[12..14].copy_from_slice(&width.to_le_bytes());
+		buf[14..16].copy_from_slice(&height.to_le_bytes());
+		buf[16] = bytes as u8 * 8;
+		buf[17] = descriptor;
+		crate::write_all(&mut out, &buf)?;
+		crate::write_all(&mut out, &map)?;
+
+		let stride = width as usize * bytes;
+		Ok(Self {
+			out,
+			color_type: header.color_type,
+			top_down: header.top_down,
+			right_to_left: header.right_to_left,
+			rle: header.rle,
+			stride,
+			indices,
+			row: Vec::with_capacity(stride),
+			packed: vec![],
+			rows: vec![],
+		})
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		match self.color_type {
+			TgaColorType::RGB => self.row.extend_from_slice(&[px.2, px.1, px.0]),
+			TgaColorType::RGBA => self.row.extend_from_slice(&[px.2, px.1, px.0, px.3]),
+			TgaColorType::Gray => self.row.push(crate::pixel::luma(px) as u8),
+			TgaColorType::Indexed => {
+				let Some(&i) = self.indices.get(&px)
+				else {
+					return Err(Error::InvalidInput { reason: "pixel isn't in the color map" });
+				};
+				self.row.push(i);
+			}
+		}
+		if self.row.len() == self.stride {
+			self.end_row()?;
+		}
+		Ok(())
+	}
+
+	fn end_row(&mut self) -> Result<(), Error> {
+		let bytes = self.color_type.bytes();
+		self.row.resize(self.stride, 0);
+		if self.right_to_left {
+			// reversing the bytes and then each pixel's puts the pixels in
+			// reverse, as they were
+			self.row.reverse();
+			for px in self.row.chunks_exact_mut(bytes) {
+				px.reverse();
+			}
+		}
+		let row = match self.rle {
+			true => {
+				// a packet can take a byte more than the pixels in it
+				self.packed.clear();
+				self.packed.try_reserve(self.stride + self.stride.div_ceil(PACKET_MAX)).map_err(|_| oom())?;
+				pack(&self.row, bytes, &mut self.packed);
+				&self.packed
+			}
+			false => &self.row,
+		};
+		if self.top_down {
+			crate::write_all(&mut self.out, row)?;
+		}
+		else {
+			let mut copy = vec![];
+			copy.try_reserve_exact(row.len()).map_err(|_| oom())?;
+			copy.extend_from_slice(row);
+			self.rows.try_reserve(1).map_err(|_| oom())?;
+			self.rows.push(copy);
+		}
+		self.row.clear();
+		Ok(())
+	}
+
+	/// writes a row that didn't get all its pixels, and for bottom-up
+	/// images, every row, last first.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		if !self.row.is_empty() {
+			self.end_row()?;
+		}
+		for row in self.rows.iter().rev() {
+			crate::write_all(&mut self.out, row)?;
+		}
+		self.rows.clear();
+		Ok(())
+	}
+}
+
+impl crate::Format for Tga {
+	type Header = TgaHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = TgaPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = TgaPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = TgaEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, tga};
+
+	const IMAGE_GRADIENT: &[u8] = include_bytes!("../test/gradient.tga");
+
+	fn gradient(x: u32, y: u32) -> crate::Rgba {
+		((x * 7) as u8, (y * 30) as u8, (x * y) as u8, 255)
+	}
+
+	#[test]
+	fn decode() {
+		// every origin. the top-down one has an image id and a color map
+		// before the pixels, to be skipped
+		for (image, top_down, right_to_left) in [
+			(IMAGE_GRADIENT, false, false),
+			(include_bytes!("../test/gradient_top_down.tga"), true, false),
+			(include_bytes!("../test/gradient_right_to_left.tga"), false, true),
+			(include_bytes!("../test/gradient_top_right.tga"), true, true),
+		] {
+			let mut data = image;
+			let mut pixels = tga::TgaPixels::new(&mut data).expect("error?");
+			let header = pixels.header();
+			assert_eq!((header.width, header.height, header.color_type), (33, 7, tga::TgaColorType::RGB));
+			assert_eq!((header.top_down, header.right_to_left), (top_down, right_to_left));
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 33, i as u32 / 33);
+				assert_eq!(px.expect("error?"), gradient(x, y), "{x}, {y}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			let mut data = image;
+			let pixels = tga::TgaPixels::new(&mut data).expect("error?");
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+	}
+
+	#[test]
+	fn decode_alpha() {
+		let mut data = &include_bytes!("../test/small.tga")[..];
+		let (header, iter) = tga::Tga.decode(&mut data).expect("error?");
+		assert_eq!(header.color_type, tga::TgaColorType::RGBA);
+		let expected = (0..16).map(|i| ((i * 16) as u8, (i * 3) as u8, (255 - i) as u8, (i * 17) as u8));
+		assert!(iter.eq(expected));
+
+		// 32 bits, but no alpha bits, with junk where alpha would be
+		let mut data = &include_bytes!("../test/rgbx.tga")[..];
+		let (header, iter) = tga::Tga.decode(&mut data).expect("error?");
+		assert_eq!(header.color_type, tga::TgaColorType::RGB);
+		assert!(iter.eq((0..33 * 7).map(|i| gradient(i % 33, i / 33))));
+	}
+
+	#[test]
+	fn decode_indexed() {
+		fn color(i: u32) -> crate::Rgba {
+			(i as u8, (255 - i) as u8, (i * 3) as u8, 255)
+		}
+
+		// 24-bit entries, and 16-bit ones of 5 bits a channel, starting at
+		// index 2
+		for (image, bits) in [(&include_bytes!("../test/indexed.tga")[..], 24), (include_bytes!("../test/indexed_16.tga"), 16)] {
+			let mut data = image;
+			let mut pixels = tga::TgaPixels::new(&mut data).expect("error?");
+			let header = pixels.header();
+			assert_eq!((header.width, header.height, header.color_type), (13, 5, tga::TgaColorType::Indexed));
+			let color = |i| match bits {
+				24 => color(i),
+				_ => {
+					let scale = |x: u32| ((x & 31) << 3 | (x & 31) >> 2) as u8;
+					(scale(i), scale(31 - i), scale(i * 3), 255)
+				}
+			};
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				assert_eq!(px.expect("error?"), color((x * 7 + y * 3) % 16), "{bits}: {x}, {y}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+
+		let mut data = &include_bytes!("../test/gray.tga")[..];
+		let (header, iter) = tga::Tga.decode(&mut data).expect("error?");
+		assert_eq!(header.color_type, tga::TgaColorType::Gray);
+		assert!(iter.eq((0..13 * 5).map(|i| ((i * 3) as u8, (i * 3) as u8, (i * 3) as u8, 255))));
+	}
+
+	#[test]
+	fn decode_rle() {
+		// runs of 4, which run on from one row to the next in the top-down one.
+		// both end in a packet that runs past the last pixel, a run in the
+		// top-down one and pixels as they are in the other
+		fn runs(x: u32, y: u32) -> crate::Rgba {
+			gradient((x + y * 33) / 4 % 32, 0)
+		}
+		for (image, color_type) in [
+			(&include_bytes!("../test/rle.tga")[..], tga::TgaColorType::RGB),
+			(include_bytes!("../test/rle_top_down.tga"), tga::TgaColorType::RGBA),
+			(include_bytes!("../test/rle_gray.tga"), tga::TgaColorType::Gray),
+			(include_bytes!("../test/rle_indexed.tga"), tga::TgaColorType::Indexed),
+		] {
+			let mut data = image;
+			let mut pixels = tga::TgaPixels::new(&mut data).expect("error?");
+			let header = pixels.header();
+			assert_eq!((header.width, header.height, header.color_type, header.rle), (33, 7, color_type, true));
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 33, i as u32 / 33);
+				let expected = match color_type {
+					tga::TgaColorType::Gray => {
+						let g = (x / 4 * 20 + y) as u8;
+						(g, g, g, 255)
+					}
+					tga::TgaColorType::Indexed => indexed_color(x / 4 + y),
+					_ => runs(x, y),
+				};
+				assert_eq!(px.expect("error?"), expected, "{color_type:?}: {x}, {y}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+
+			// where it ends is found without handing out any pixels
+			let mut data = image;
+			let pixels = tga::TgaPixels::new(&mut data).expect("error?");
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64);
+		}
+
+		fn indexed_color(i: u32) -> crate::Rgba {
+			((i * 20) as u8, (255 - i * 9) as u8, 0, (i * 16) as u8)
+		}
+	}
+
+	#[test]
+	fn encode() {
+		use tga::TgaColorType::*;
+
+		for color_type in [RGB, RGBA, Gray, Indexed] {
+			for rle in [false, true] {
+				for (top_down, right_to_left) in [(false, false), (true, false), (false, true), (true, true)] {
+					let (width, height) = (13, 5);
+					// runs of 3, for rle to pack
+					let px = |i: u32| ((i * 3) as u8, (i * 3 / width * 40) as u8, i as u8 ^ 0x55, (i * 7) as u8);
+					let pixels = (0..width * height).map(|i| px(i / 3));
+					let palette = match color_type {
+						Indexed => (0..(width * height).div_ceil(3)).map(px).collect(),
+						_ => vec![],
+					};
+					let header = tga::TgaHeader {
+						width,
+						height,
+						color_type,
+						top_down,
+						right_to_left,
+						rle,
+						palette,
+					};
+					let mut out = vec![];
+					tga::Tga.encode(pixels.clone(), header.clone(), &mut out).expect("error?");
+
+					let mut data = &out[..];
+					let mut decoded = tga::TgaPixels::new(&mut data).expect("error?");
+					assert_eq!(*decoded.header(), header);
+					for (i, (a, b)) in (&mut decoded).zip(pixels).enumerate() {
+						let b = match color_type {
+							RGB => (b.0, b.1, b.2, 255),
+							Gray => {
+								let g = crate::pixel::luma(b) as u8;
+								(g, g, g, 255)
+							}
+							_ => b,
+						};
+						assert_eq!(a.expect("error?"), b, "{color_type:?}, {rle}: {i}");
+					}
+					assert_eq!(decoded.finish().expect("error?"), out.len() as u64);
+				}
+			}
+		}
+
+		// runs, and the pixels between them as they are
+		let mut out = vec![];
+		super::pack(&[1, 1, 1, 2, 3, 4, 4, 5], 1, &mut out);
+		assert_eq!(out, [0x82, 1, 0x01, 2, 3, 0x81, 4, 0x00, 5]);
+		let mut out = vec![];
+		super::pack(&[7; 300], 1, &mut out);
+		assert_eq!(out, [0xff, 7, 0xff, 7, 0xab, 7]);
+
+		let header = tga::TgaHeader {
+			width: 33,
+			height: 7,
+			color_type: tga::TgaColorType::RGB,
+			top_down: false,
+			right_to_left: false,
+			rle: false,
+			palette: vec![],
+		};
+		let mut out = vec![];
+		tga::Tga.encode((0..33 * 7).map(|i| gradient(i % 33, i / 33)), header.clone(), &mut out).expect("error?");
+		assert_eq!(out, IMAGE_GRADIENT);
+
+		// pixels have to be in the color map
+		let indexed = tga::TgaHeader { color_type: Indexed, palette: vec![gradient(0, 0)], ..header.clone() };
+		let mut encoder = tga::TgaEncoder::new(&indexed, vec![]).expect("error?");
+		assert!(encoder.push(gradient(0, 0)).is_ok());
+		assert!(matches!(encoder.push(gradient(1, 0)), Err(Error::InvalidInput { .. })));
+		let indexed = tga::TgaHeader { palette: vec![], ..indexed };
+		let result = tga::TgaEncoder::new(&indexed, vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 5, .. })), "{result:?}");
+
+		// too big for the format
+		let header = tga::TgaHeader { height: 1 << 16, ..header };
+		let result = tga::TgaEncoder::new(&header, vec![]);
+		assert!(matches!(result, Err(Error::InvalidHeader { offset: 14, .. })), "{result:?}");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = tga::TgaPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".tga")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_map_type", Error::InvalidHeader { offset: 1, .. });
+		case!("bad_image_type", Error::InvalidHeader { offset: 2, .. });
+		case!("zero_width", Error::InvalidHeader { offset: 12, .. });
+		case!("bad_depth", Error::InvalidHeader { offset: 16, .. });
+		case!("bad_alpha_bits", Error::InvalidHeader { offset: 17, .. });
+		case!("truncated_id", Error::UnexpectedEof { offset: 20 });
+		case!("truncated", Error::UnexpectedEof { offset: 100 });
+		case!("no_color_map", Error::InvalidHeader { offset: 1, .. });
+		case!("bad_entry_size", Error::InvalidHeader { offset: 7, .. });
+		case!("bad_color_index", Error::Malformed { .. });
+		case!("rle_truncated", Error::UnexpectedEof { offset: 60 });
+
+		assert!(decode(IMAGE_GRADIENT).is_ok());
+	}
+}
@@ -0,0 +1,1063 @@
+/*!
+tiff images.
+
+baseline 8-bit gray and rgb images are decoded, with or without alpha, from
+strips that are uncompressed or packbits, in either byte order. only the
+first image file directory is read. samples past the color and alpha ones
+are skipped, and premultiplied alpha comes out straight. tiled, planar and
+predicted images aren't supported.
+
+tiff puts things wherever it likes, and a reader only goes forward, so the
+file is read forward as far as each thing needs and the bytes before the
+directory are kept until it's been read. after that, only the strips that
+are left are kept, so an image whose directory comes first is read a row at
+a time. the description, artist, software and date tags are read into the
+metadata, and so is the icc profile, as bytes.
+
+the encoder writes the directory first, then the strips, which for
+uncompressed images means nothing is buffered. packbits strips are kept
+until the end, since their offsets come before them.
+*/
+
+use crate::Error;
+use crate::error::oom;
+
+/// tags, as the spec names them.
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC: u16 = 262;
+const IMAGE_DESCRIPTION: u16 = 270;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+const SOFTWARE: u16 = 305;
+const DATE_TIME: u16 = 306;
+const ARTIST: u16 = 315;
+const PREDICTOR: u16 = 317;
+const EXTRA_SAMPLES: u16 = 338;
+const ICC_PROFILE: u16 = 34675;
+
+/// field types.
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const UNDEFINED: u16 = 7;
+
+/// the text tags, and the metadata keys they're read into.
+const TEXT: [(u16, &str); 4] = [
+	(IMAGE_DESCRIPTION, crate::metadata::DESCRIPTION),
+	(SOFTWARE, crate::metadata::SOFTWARE),
+	(DATE_TIME, crate::metadata::CREATION_TIME),
+	(ARTIST, crate::metadata::AUTHOR),
+];
+
+/// how much is read into the window at once, and about how big the encoder
+/// makes strips.
+const CHUNK: u64 = 1 << 16;
+const STRIP_SIZE: u64 = 1 << 13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum TiffColorType {
+	Gray,
+	GrayAlpha,
+	RGB,
+	RGBA,
+}
+
+impl TiffColorType {
+	fn samples(self) -> usize {
+		match self {
+			Self::Gray => 1,
+			Self::GrayAlpha => 2,
+			Self::RGB => 3,
+			Self::RGBA => 4,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum TiffCompression {
+	None,
+	PackBits,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TiffHeader {
+	pub width: u32,
+	pub height: u32,
+	pub color_type: TiffColorType,
+	pub compression: TiffCompression,
+	/// whether the file is big-endian, `MM`, rather than little-endian, `II`.
+	pub big_endian: bool,
+}
+
+impl crate::Dimensions for TiffHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Tiff;
+
+impl Default for Tiff {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// the bytes of the file from `base` on, read as far as they've been needed.
+#[derive(Debug)]
+struct Window {
+	base: u64,
+	/// how far the file has been read, which is behind `base` when what's
+	/// between is to be skipped.
+	pos: u64,
+	buf: Vec<u8>,
+}
+
+impl Window {
+	/// `len` bytes at `offset`, reading up to them.
+	fn get(&mut self, data: &mut impl std::io::Read, offset: u64, len: u64) -> Result<&[u8], Error> {
+		if offset < self.base {
+			return Err(Error::Malformed { offset, reason: "data goes back to before what's been read" });
+		}
+		let end = offset.saturating_add(len);
+		let mut skip = [0; 4096];
+		while self.pos < end {
+			if self.pos < self.base {
+				let n = (self.base - self.pos).min(skip.len() as u64) as usize;
+				crate::read_exact(data, &mut skip[..n], self.pos)?;
+				self.pos += n as u64;
+				continue;
+			}
+			let n = (end - self.pos).min(CHUNK) as usize;
+			let start = self.buf.len();
+			self.buf.try_reserve(n).map_err(|_| oom())?;
+			self.buf.resize(start + n, 0);
+			crate::read_exact(data, &mut self.buf[start..], self.pos)?;
+			self.pos += n as u64;
+		}
+		let start = (offset - self.base) as usize;
+		Ok(&self.buf[start..start + len as usize])
+	}
+
+	/// lets go of the bytes before `offset`.
+	fn release(&mut self, offset: u64) {
+		if offset <= self.base {
+			return;
+		}
+		let n = (offset - self.base).min(self.buf.len() as u64) as usize;
+		self.buf.drain(..n);
+		self.base = offset;
+	}
+}
+
+/// a directory entry.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+	offset: u64,
+	tag: u16,
+	kind: u16,
+	count: u32,
+	value: [u8; 4],
+}
+
+/// reads numbers in the file's byte order.
+#[derive(Debug, Clone, Copy)]
+struct Order(bool);
+
+impl Order {
+	fn u16(self, b: &[u8]) -> u16 {
+		match self.0 {
+			true => u16::from_be_bytes([b[0], b[1]]),
+			false => u16::from_le_bytes([b[0], b[1]]),
+		}
+	}
+
+	fn u32(self, b: &[u8]) -> u32 {
+		match self.0 {
+			true => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+			false => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+		}
+	}
+}
+
+/// an entry's bytes, which are in the entry if they fit.
+fn entry_bytes<'w>(window: &'w mut Window, data: &mut impl std::io::Read, order: Order, entry: &'w Entry, size: u64) -> Result<&'w [u8], Error> {
+	let len = size * entry.count as u64;
+	match len {
+		0..=4 => Ok(&entry.value[..len as usize]),
+		_ => window.get(data, order.u32(&entry.value) as u64, len),
+	}
+}
+
+/// an entry's numbers, which have to be bytes, shorts or longs.
+fn numbers(window: &mut Window, data: &mut impl std::io::Read, order: Order, entry: &Entry, field: &'static str) -> Result<Vec<u32>, Error> {
+	let size = match entry.kind {
+		BYTE => 1,
+		SHORT => 2,
+		LONG => 4,
+		_ => return Err(Error::InvalidHeader { offset: entry.offset, field }),
+	};
+	let bytes = entry_bytes(window, data, order, entry, size)?;
+	let mut out = vec![];
+	out.try_reserve_exact(entry.count as usize).map_err(|_| oom())?;
+	out.extend(bytes.chunks_exact(size as usize).map(|b| match size {
+		1 => b[0] as u32,
+		2 => order.u16(b) as u32,
+		_ => order.u32(b),
+	}));
+	Ok(out)
+}
+
+/// pixels of a tiff image, read from the reader as they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct TiffPixels<'a, R> {
+	header: TiffHeader,
+	metadata: crate::Metadata,
+	data: &'a mut R,
+	window: Window,
+	/// samples per pixel, which of them is alpha, and whether it's
+	/// premultiplied.
+	samples: usize,
+	alpha: Option<usize>,
+	premultiplied: bool,
+	white_is_zero: bool,
+	stride: usize,
+	rows_per_strip: u32,
+	/// each strip's offset and length, and the lowest offset of it and the
+	/// ones after it, which is what has to be kept.
+	strips: Vec<(u64, u64, u64)>,
+	/// where the next byte of the current strip is.
+	at: u64,
+	/// bytes left in the packbits packet being read, whether it's a run, and
+	/// the byte it's a run of.
+	packet: u8,
+	repeat: bool,
+	byte: u8,
+	row: Vec<u8>,
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> TiffPixels<'a, R> {
+	/// reads the header and the first directory, and whatever it points to
+	/// apart from the strips.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut window = Window { base: 0, pos: 0, buf: vec![] };
+		let head = window.get(data, 0, 8)?;
+		let order = match head[..4] {
+			[b'I', b'I', 42, 0] => Order(false),
+			[b'M', b'M', 0, 42] => Order(true),
+			_ => return Err(Error::InvalidMagic { offset: 0 }),
+		};
+		let ifd = order.u32(&head[4..]) as u64;
+		let count = order.u16(window.get(data, ifd, 2)?);
+		let mut entries = vec![];
+		let raw = window.get(data, ifd + 2, count as u64 * 12)?;
+		entries.extend(raw.chunks_exact(12).enumerate().map(|(i, b)| Entry {
+			offset: ifd + 2 + i as u64 * 12,
+			tag: order.u16(&b[0..]),
+			kind: order.u16(&b[2..]),
+			count: order.u32(&b[4..]),
+			value: [b[8], b[9], b[10], b[11]],
+		}));
+
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let find = |tag| entries.iter().find(|x| x.tag == tag);
+		let mut get = |tag, field| -> Result<Option<Vec<u32>>, Error> {
+			match find(tag) {
+				Some(entry) => numbers(&mut window, data, order, entry, field).map(Some),
+				None => Ok(None),
+			}
+		};
+		let mut one = |tag, field, default: Option<u32>| -> Result<u32, Error> {
+			let at = find(tag).map_or(ifd, |x| x.offset);
+			match get(tag, field)? {
+				Some(x) => x.first().copied().ok_or(Error::InvalidHeader { offset: at, field }),
+				None => default.ok_or(Error::InvalidHeader { offset: at, field }),
+			}
+		};
+
+		let width = one(IMAGE_WIDTH, "width", None)?;
+		let height = one(IMAGE_LENGTH, "height", None)?;
+		let compression = one(COMPRESSION, "compression", Some(1))?;
+		let photometric = one(PHOTOMETRIC, "photometric interpretation", None)?;
+		let samples = one(SAMPLES_PER_PIXEL, "samples per pixel", Some(1))?;
+		let rows_per_strip = one(ROWS_PER_STRIP, "rows per strip", Some(u32::MAX))?;
+		let planar = one(PLANAR_CONFIGURATION, "planar configuration", Some(1))?;
+		let predictor = one(PREDICTOR, "predictor", Some(1))?;
+		let bits = get(BITS_PER_SAMPLE, "bits per sample")?.unwrap_or(vec![1]);
+		let extra = get(EXTRA_SAMPLES, "extra samples")?.unwrap_or_default();
+		let offsets = get(STRIP_OFFSETS, "strip offsets")?;
+		let counts = get(STRIP_BYTE_COUNTS, "strip byte counts")?;
+
+		let at = |tag| find(tag).map_or(ifd, |x| x.offset);
+		if width == 0 {
+			return Err(field(at(IMAGE_WIDTH), "width"));
+		}
+		if height == 0 {
+			return Err(field(at(IMAGE_LENGTH), "height"));
+		}
+		let compression = match compression {
+			1 => TiffCompression::None,
+			32773 => TiffCompression::PackBits,
+			_ => return Err(field(at(COMPRESSION), "compression")),
+		};
+		let (white_is_zero, colors) = match photometric {
+			0 => (true, 1),
+			1 => (false, 1),
+			2 => (false, 3),
+			_ => return Err(field(at(PHOTOMETRIC), "photometric interpretation")),
+		};
+		if samples < colors || samples > u16::MAX as u32 {
+			return Err(field(at(SAMPLES_PER_PIXEL), "samples per pixel"));
+		}
+		// some writers give one size for every sample
+		if !(bits == [8] || bits.len() == samples as usize && bits.iter().all(|&x| x == 8)) {
+			return Err(field(at(BITS_PER_SAMPLE), "bits per sample"));
+		}
+		if planar != 1 {
+			return Err(field(at(PLANAR_CONFIGURATION), "planar configuration"));
+		}
+		if predictor != 1 {
+			return Err(field(at(PREDICTOR), "predictor"));
+		}
+		if rows_per_strip == 0 {
+			return Err(field(at(ROWS_PER_STRIP), "rows per strip"));
+		}
+		// an extra sample that's unspecified isn't alpha
+		let alpha = match extra.first() {
+			Some(1 | 2) if samples > colors => Some(colors as usize),
+			_ => None,
+		};
+		let color_type = match (colors, alpha) {
+			(1, None) => TiffColorType::Gray,
+			(1, Some(_)) => TiffColorType::GrayAlpha,
+			(_, None) => TiffColorType::RGB,
+			(_, Some(_)) => TiffColorType::RGBA,
+		};
+
+		let (Some(offsets), Some(counts)) = (offsets, counts)
+		else {
+			return Err(field(at(STRIP_OFFSETS).min(at(STRIP_BYTE_COUNTS)), "strip offsets"));
+		};
+		let strips_needed = height.div_ceil(rows_per_strip) as usize;
+		if offsets.len() < strips_needed {
+			return Err(field(at(STRIP_OFFSETS), "strip offsets"));
+		}
+		if counts.len() < strips_needed {
+			return Err(field(at(STRIP_BYTE_COUNTS), "strip byte counts"));
+		}
+		let mut strips = vec![];
+		strips.try_reserve_exact(strips_needed).map_err(|_| oom())?;
+		strips.extend(offsets.iter().zip(&counts).take(strips_needed).map(|(&at, &len)| (at as u64, len as u64, 0)));
+		let mut lowest = u64::MAX;
+		for strip in strips.iter_mut().rev() {
+			lowest = lowest.min(strip.0);
+			strip.2 = lowest;
+		}
+
+		let mut metadata = crate::Metadata::new();
+		for (tag, key) in TEXT {
+			if let Some(entry) = find(tag).filter(|x| x.kind == ASCII) {
+				let text = entry_bytes(&mut window, data, order, entry, 1)?;
+				let text = text.split(|&b| b == 0).next().unwrap_or_default();
+				metadata.insert(key, String::from_utf8_lossy(text).into_owned());
+			}
+		}
+		if let Some(entry) = find(ICC_PROFILE).filter(|x| matches!(x.kind, BYTE | UNDEFINED)) {
+			let bytes = entry_bytes(&mut window, data, order, entry, 1)?;
+			let mut profile = vec![];
+			profile.try_reserve_exact(bytes.len()).map_err(|_| oom())?;
+			profile.extend_from_slice(bytes);
+			metadata.insert(crate::metadata::ICC_PROFILE, profile);
+		}
+
+		let stride = (width as u64 * samples as u64).try_into().map_err(|_| oom())?;
+		let at = strips[0].0;
+		window.release(strips[0].2);
+		Ok(Self {
+			header: TiffHeader {
+				width,
+				height,
+				color_type,
+				compression,
+				big_endian: order.0,
+			},
+			metadata,
+			data,
+			window,
+			samples: samples as usize,
+			alpha,
+			premultiplied: extra.first() == Some(&1),
+			white_is_zero,
+			stride,
+			rows_per_strip,
+			strips,
+			at,
+			packet: 0,
+			repeat: false,
+			byte: 0,
+			row: vec![],
+			x: width,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &TiffHeader {
+		&self.header
+	}
+
+	/// the text tags that were read.
+	pub fn metadata(&self) -> &crate::Metadata {
+		&self.metadata
+	}
+
+	/// `len` bytes of the current strip, which ends at `end`, from `at` on.
+	fn strip_bytes(&mut self, end: u64, len: u64) -> Result<(), Error> {
+		if self.at.saturating_add(len) > end {
+			return Err(Error::Malformed { offset: end, reason: "strip ends early" });
+		}
+		let bytes = self.window.get(self.data, self.at, len)?;
+		self.row.try_reserve(bytes.len()).map_err(|_| oom())?;
+		self.row.extend_from_slice(bytes);
+		self.at += len;
+		Ok(())
+	}
+
+	fn next_row(&mut self) -> Result<(), Error> {
+		let strip = (self.y / self.rows_per_strip) as usize;
+		let (start, len, _) = self.strips[strip];
+		let end = start.saturating_add(len);
+		if self.y.is_multiple_of(self.rows_per_strip) {
+			self.at = start;
+			self.packet = 0;
+		}
+		self.row.clear();
+		match self.header.compression {
+			TiffCompression::None => {
+				let mut left = self.stride as u64;
+				while left > 0 {
+					let n = left.min(CHUNK);
+					self.strip_bytes(end, n)?;
+					left -= n;
+				}
+			}
+			TiffCompression::PackBits => {
+				let stride = self.stride;
+				while self.row.len() < stride {
+					if self.packet == 0 {
+						// the packet's header, and the byte of a run, are read
+						// onto the row and taken back off
+						self.strip_bytes(end, 1)?;
+						let n = self.row.pop().unwrap_or_default() as i8;
+						match n {
+							// a no-op
+							-128 => continue,
+							0.. => {
+								self.packet = n as u8 + 1;
+								self.repeat = false;
+							}
+							_ => {
+								self.packet = (1 - n as i16) as u8;
+								self.repeat = true;
+								self.strip_bytes(end, 1)?;
+								self.byte = self.row.pop().unwrap_or_default();
+							}
+						}
+					}
+					let n = (self.packet as usize).min(stride - self.row.len());
+					if self.repeat {
+						self.row.try_reserve(n).map_err(|_| oom())?;
+						self.row.extend(std::iter::repeat_n(self.byte, n));
+					}
+					else {
+						self.strip_bytes(end, n as u64)?;
+					}
+					self.packet -= n as u8;
+				}
+			}
+		}
+
+		// what's kept is the rest of this strip, and the ones after it
+		let keep = match self.strips.get(strip + 1) {
+			Some(next) => next.2.min(self.at),
+			None => self.at,
+		};
+		self.window.release(keep);
+		self.y += 1;
+		self.x = 0;
+		Ok(())
+	}
+
+	/// skips any pixels that are left, and returns how far the file was
+	/// read, which is to the end of whatever came last of the directory,
+	/// what it points to and the strips.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		while self.y < self.header.height {
+			self.next_row()?;
+		}
+		Ok(self.window.pos)
+	}
+}
+
+impl<R: std::io::Read> Iterator for TiffPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+		if self.x == self.header.width {
+			if self.y == self.header.height {
+				return None;
+			}
+			if let Err(e) = self.next_row() {
+				self.failed = true;
+				return Some(Err(e));
+			}
+		}
+
+		let i = self.x as usize * self.samples;
+		self.x += 1;
+		let s = &self.row[i..i + self.samples];
+		let a = self.alpha.map_or(255, |i| s[i]);
+		let (r, g, b) = match self.header.color_type {
+			TiffColorType::Gray | TiffColorType::GrayAlpha if self.white_is_zero => (!s[0], !s[0], !s[0]),
+			TiffColorType::Gray | TiffColorType::GrayAlpha => (s[0], s[0], s[0]),
+			TiffColorType::RGB | TiffColorType::RGBA => (s[0], s[1], s[2]),
+		};
+		if !self.premultiplied {
+			return Some(Ok((r, g, b, a)));
+		}
+		let straight = |c: u8| match a {
+			0 => 0,
+			_ => ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8,
+		};
+		Some(Ok((straight(r), straight(g), straight(b), a)))
+	}
+}
+
+/// `TiffPixels`, ending at the first error, for `Format::decode`.
+struct Pixels<'a, R>(TiffPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// `row` as packbits, appended to `out`. runs of three or more are packed,
+/// and the bytes between them left as they are.
+fn pack(row: &[u8], out: &mut Vec<u8>) {
+	let mut i = 0;
+	while i < row.len() {
+		let run = row[i..].iter().take(128).take_while(|&&x| x == row[i]).count();
+		if run >= 3 {
+			out.push((1 - run as i16) as u8);
+			out.push(row[i]);
+			i += run;
+			continue;
+		}
+		// up to where the next run starts
+		let mut end = i + 1;
+		while end < row.len() && end - i < 128 && !(end + 2 < row.len() && row[end] == row[end + 1] && row[end] == row[end + 2]) {
+			end += 1;
+		}
+		out.push((end - i - 1) as u8);
+		out.extend_from_slice(&row[i..end]);
+		i = end;
+	}
+}
+
+/// lays out and writes the header and the directory, with the strips right
+/// after it, of the sizes in `counts`.
+fn write_directory(out: &mut impl std::io::Write, header: &TiffHeader, height: u32, rows_per_strip: u32, counts: &[u32], metadata: &crate::Metadata) -> Result<(), Error> {
+	let order = |x: u32, size: usize| -> Vec<u8> {
+		match header.big_endian {
+			true => x.to_be_bytes()[4 - size..].to_vec(),
+			false => x.to_le_bytes()[..size].to_vec(),
+		}
+	};
+	let shorts = |x: &[u32]| (SHORT, x.len() as u32, x.iter().flat_map(|&x| order(x, 2)).collect::<Vec<_>>());
+	let longs = |x: &[u32]| (LONG, x.len() as u32, x.iter().flat_map(|&x| order(x, 4)).collect::<Vec<_>>());
+	let oom_vec = |len: usize| -> Result<Vec<u32>, Error> {
+		let mut x = vec![];
+		x.try_reserve_exact(len).map_err(|_| oom())?;
+		Ok(x)
+	};
+
+	let samples = header.color_type.samples();
+	let photometric = match header.color_type {
+		TiffColorType::Gray | TiffColorType::GrayAlpha => 1,
+		TiffColorType::RGB | TiffColorType::RGBA => 2,
+	};
+	let compression = match header.compression {
+		TiffCompression::None => 1,
+		TiffCompression::PackBits => 32773,
+	};
+
+	// the strip offsets are filled in once the rest is laid out
+	let mut entries = vec![
+		(IMAGE_WIDTH, longs(&[header.width])),
+		(IMAGE_LENGTH, longs(&[height])),
+		(BITS_PER_SAMPLE, shorts(&vec![8; samples])),
+		(COMPRESSION, shorts(&[compression])),
+		(PHOTOMETRIC, shorts(&[photometric])),
+		(STRIP_OFFSETS, (LONG, counts.len() as u32, vec![])),
+		(SAMPLES_PER_PIXEL, shorts(&[samples as u32])),
+		(ROWS_PER_STRIP, longs(&[rows_per_strip])),
+		(STRIP_BYTE_COUNTS, longs(counts)),
+		(PLANAR_CONFIGURATION, shorts(&[1])),
+	];
+	if matches!(header.color_type, TiffColorType::GrayAlpha | TiffColorType::RGBA) {
+		// unassociated alpha
+		entries.push((EXTRA_SAMPLES, shorts(&[2])));
+	}
+	for (tag, key) in TEXT {
+		if let Some(text) = metadata.get_text(key) {
+			let mut text = text.as_bytes().to_vec();
+			text.push(0);
+			entries.push((tag, (ASCII, text.len() as u32, text)));
+		}
+	}
+	if let Some(profile) = metadata.get(crate::metadata::ICC_PROFILE) {
+		let profile = profile.as_bytes().to_vec();
+		entries.push((ICC_PROFILE, (UNDEFINED, profile.len() as u32, profile)));
+	}
+	entries.sort_by_key(|x| x.0);
+
+	// values that don't fit in their entry go after the directory, on word
+	// boundaries
+	let size = |entry: &(u16, (u16, u32, Vec<u8>))| match entry.0 {
+		STRIP_OFFSETS => counts.len() as u64 * 4,
+		_ => entry.1.2.len() as u64,
+	};
+	let directory = 8 + 2 + entries.len() as u64 * 12 + 4;
+	let values = entries.iter().map(size).filter(|&x| x > 4).map(|x| x.next_multiple_of(2)).sum::<u64>();
+	let mut at = directory + values;
+	let mut offsets = oom_vec(counts.len())?;
+	for &count in counts {
+		offsets.push(at as u32);
+		at += count as u64;
+	}
+	if at > u32::MAX as u64 {
+		return Err(Error::InvalidInput { reason: "image is too big for a tiff" });
+	}
+	if let Some(entry) = entries.iter_mut().find(|x| x.0 == STRIP_OFFSETS) {
+		entry.1 = longs(&offsets);
+	}
+
+	let mut buf = match header.big_endian {
+		true => b"MM\0\x2a".to_vec(),
+		false => b"II\x2a\0".to_vec(),
+	};
+	buf.extend(order(8, 4));
+	buf.extend(order(entries.len() as u32, 2));
+	let mut value_at = directory;
+	for (tag, (kind, count, value)) in &entries {
+		buf.extend(order(*tag as u32, 2));
+		buf.extend(order(*kind as u32, 2));
+		buf.extend(order(*count, 4));
+		match value.len() {
+			0..=4 => {
+				buf.extend_from_slice(value);
+				buf.resize(buf.len() + 4 - value.len(), 0);
+			}
+			len => {
+				buf.extend(order(value_at as u32, 4));
+				value_at += (len as u64).next_multiple_of(2);
+			}
+		}
+	}
+	// no next directory
+	buf.extend([0; 4]);
+	crate::write_all(out, &buf)?;
+	for (_, (_, _, value)) in &entries {
+		if value.len() > 4 {
+			crate::write_all(out, value)?;
+			if value.len() % 2 == 1 {
+				crate::write_all(out, &[0])?;
+			}
+		}
+	}
+	Ok(())
+}
+
+/// push-based encoder state, which `Tiff::encode` drives.
+#[derive(Debug)]
+pub(crate) struct TiffEncoder<W> {
+	out: W,
+	header: TiffHeader,
+	metadata: crate::Metadata,
+	stride: usize,
+	rows_per_strip: u32,
+	row: Vec<u8>,
+	/// the packbits strips so far, and their sizes.
+	packed: Vec<u8>,
+	counts: Vec<u32>,
+	rows: u32,
+}
+
+impl<W: std::io::Write> TiffEncoder<W> {
+	/// writes the header and the directory, for uncompressed images. a
+	/// packbits image's are written at the end.
+	pub(crate) fn new(header: &TiffHeader, metadata: &crate::Metadata, mut out: W) -> Result<Self, Error> {
+		if header.width == 0 {
+			return Err(Error::InvalidInput { reason: "width is zero" });
+		}
+		if header.height == 0 {
+			return Err(Error::InvalidInput { reason: "height is zero" });
+		}
+		let stride = header.width as u64 * header.color_type.samples() as u64;
+		if stride.saturating_mul(header.height as u64) > u32::MAX as u64 {
+			return Err(Error::InvalidInput { reason: "image is too big for a tiff" });
+		}
+		let rows_per_strip = (STRIP_SIZE / stride).clamp(1, header.height as u64) as u32;
+
+		if header.compression == TiffCompression::None {
+			let strips = header.height.div_ceil(rows_per_strip);
+			let mut counts = vec![];
+			counts.try_reserve_exact(strips as usize).map_err(|_| oom())?;
+			let full = header.height / rows_per_strip;
+			counts.extend(std::iter::repeat_n((rows_per_strip as u64 * stride) as u32, full as usize));
+			if full < strips {
+				counts.push(((header.height % rows_per_strip) as u64 * stride) as u32);
+			}
+			write_directory(&mut out, header, header.height, rows_per_strip, &counts, metadata)?;
+		}
+		Ok(Self {
+			out,
+			header: header.clone(),
+			metadata: metadata.clone(),
+			stride: stride as usize,
+			rows_per_strip,
+			row: vec![],
+			packed: vec![],
+			counts: vec![],
+			rows: 0,
+		})
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		match self.header.color_type {
+			TiffColorType::Gray => self.row.push(crate::pixel::luma(px) as u8),
+			TiffColorType::GrayAlpha => self.row.extend_from_slice(&[crate::pixel::luma(px) as u8, px.3]),
+			TiffColorType::RGB => self.row.extend_from_slice(&[px.0, px.1, px.2]),
+			TiffColorType::RGBA => self.row.extend_from_slice(&[px.0, px.1, px.2, px.3]),
+		}
+		if self.row.len() >= self.stride.min(CHUNK as usize) {
+			self.flush()?;
+		}
+		Ok(())
+	}
+
+	/// writes or packs what's been pushed, a row at a time when packing.
+	fn flush(&mut self) -> Result<(), Error> {
+		match self.header.compression {
+			TiffCompression::None => {
+				crate::write_all(&mut self.out, &self.row)?;
+				self.row.clear();
+			}
+			TiffCompression::PackBits if self.row.len() == self.stride => {
+				if self.rows.is_multiple_of(self.rows_per_strip) {
+					self.counts.try_reserve(1).map_err(|_| oom())?;
+					self.counts.push(0);
+				}
+				let start = self.packed.len();
+				self.packed.try_reserve(self.stride + self.stride.div_ceil(128)).map_err(|_| oom())?;
+				pack(&self.row, &mut self.packed);
+				if let Some(count) = self.counts.last_mut() {
+					*count = count.saturating_add((self.packed.len() - start) as u32);
+				}
+				self.rows += 1;
+				self.row.clear();
+			}
+			TiffCompression::PackBits => {}
+		}
+		Ok(())
+	}
+
+	/// writes a packbits image, with the rows that got any pixels. the last
+	/// of them is finished with zeros. an uncompressed image has been
+	/// written already, and stops where the pixels did.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		if self.header.compression == TiffCompression::None {
+			return self.flush();
+		}
+		if !self.row.is_empty() {
+			self.row.try_reserve(self.stride - self.row.len()).map_err(|_| oom())?;
+			self.row.resize(self.stride, 0);
+			self.flush()?;
+		}
+		if self.rows == 0 {
+			return Ok(());
+		}
+		let rows_per_strip = self.rows_per_strip.min(self.rows);
+		write_directory(&mut self.out, &self.header, self.rows, rows_per_strip, &self.counts, &self.metadata)?;
+		crate::write_all(&mut self.out, &self.packed)?;
+		self.packed.clear();
+		Ok(())
+	}
+}
+
+impl crate::Format for Tiff {
+	type Header = TiffHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = TiffPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = TiffPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		self.encode_with_metadata(data, header, &crate::Metadata::new(), out)
+	}
+
+	fn decode_with_metadata(self, data: &mut impl std::io::Read) -> Result<(Self::Header, crate::Metadata, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = TiffPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels.metadata().clone(), Pixels(pixels)))
+	}
+
+	/// the description, author, software and creation time go in their tags.
+	fn encode_with_metadata(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, metadata: &crate::Metadata, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = TiffEncoder::new(&header, metadata, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, tiff};
+	use crate::tiff::{TiffColorType, TiffCompression};
+
+	const IMAGE_GRADIENT: &[u8] = include_bytes!("../test/gradient.tiff");
+
+	/// the pixels the fixtures were made with, as many samples of them as
+	/// the image has.
+	fn px(x: u32, y: u32) -> crate::Rgba {
+		((x * 19) as u8, (y * 50) as u8, (x * y * 7) as u8, (255 - x * 9 - y * 3) as u8)
+	}
+
+	fn opaque(x: u32, y: u32) -> crate::Rgba {
+		(px(x, y).0, px(x, y).1, px(x, y).2, 255)
+	}
+
+	fn decode(mut data: &[u8]) -> (tiff::TiffHeader, Vec<crate::Rgba>, u64) {
+		let mut pixels = tiff::TiffPixels::new(&mut data).expect("error?");
+		let header = pixels.header().clone();
+		let decoded = (&mut pixels).map(|x| x.expect("error?")).collect::<Vec<_>>();
+		assert_eq!(decoded.len() as u32, header.width * header.height);
+		(header, decoded, pixels.finish().expect("error?"))
+	}
+
+	#[test]
+	fn decode_rgb() {
+		// strips after the directory, strips before it, and strips before it
+		// in reverse
+		for (image, big_endian) in [
+			(IMAGE_GRADIENT, false),
+			(&include_bytes!("../test/gradient_be.tiff")[..], true),
+			(&include_bytes!("../test/reversed.tiff")[..], false),
+		] {
+			let (header, pixels, len) = decode(image);
+			assert_eq!(header, tiff::TiffHeader {
+				width: 13,
+				height: 5,
+				color_type: TiffColorType::RGB,
+				compression: TiffCompression::None,
+				big_endian,
+			});
+			for (i, px) in pixels.into_iter().enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				assert_eq!(px, opaque(x, y), "{x}, {y}");
+			}
+			assert_eq!(len, image.len() as u64);
+		}
+
+		let mut data = IMAGE_GRADIENT;
+		let (_, metadata, _) = tiff::Tiff.decode_with_metadata(&mut data).expect("error?");
+		assert_eq!(metadata.get_text(crate::metadata::DESCRIPTION), Some("a gradient"));
+		assert_eq!(metadata.get_text(crate::metadata::SOFTWARE), Some("dwelf"));
+		assert_eq!(metadata.get_text(crate::metadata::CREATION_TIME), Some("2026:10:14 12:00:00"));
+		assert_eq!(metadata.get_text(crate::metadata::AUTHOR), Some("someone"));
+	}
+
+	#[test]
+	fn decode_alpha() {
+		let (header, pixels, _) = decode(include_bytes!("../test/rgba.tiff"));
+		assert_eq!((header.color_type, header.compression), (TiffColorType::RGBA, TiffCompression::PackBits));
+		assert!(pixels.into_iter().eq((0..13 * 5).map(|i| px(i % 13, i / 13))));
+
+		// premultiplied alpha comes out straight, give or take the rounding
+		let (header, pixels, _) = decode(include_bytes!("../test/premultiplied.tiff"));
+		assert_eq!(header.color_type, TiffColorType::RGBA);
+		for (i, a) in pixels.into_iter().enumerate() {
+			let b = px(i as u32 % 13, i as u32 / 13);
+			let d = |a: u8, b: u8| a.abs_diff(b) <= 1;
+			assert!(d(a.0, b.0) && d(a.1, b.1) && d(a.2, b.2) && a.3 == b.3, "{i}: {a:?}, {b:?}");
+		}
+
+		let (header, pixels, _) = decode(include_bytes!("../test/gray_alpha.tiff"));
+		assert_eq!(header.color_type, TiffColorType::GrayAlpha);
+		for (i, px) in pixels.into_iter().enumerate() {
+			let (x, y) = (i as u32 % 13, i as u32 / 13);
+			let (g, a) = ((x * 19) as u8, (y * 50) as u8);
+			assert_eq!(px, (g, g, g, a), "{x}, {y}");
+		}
+	}
+
+	#[test]
+	fn decode_gray() {
+		// a run goes on from one row to the next, after a no-op packet
+		let (header, pixels, _) = decode(include_bytes!("../test/gray.tiff"));
+		assert_eq!((header.color_type, header.compression), (TiffColorType::Gray, TiffCompression::PackBits));
+		for (i, px) in pixels.into_iter().enumerate() {
+			let (x, y) = (i as u32 % 13, i as u32 / 13);
+			let g = match y {
+				2 | 3 => 77,
+				_ => (x / 4 * 40 + y * 3) as u8,
+			};
+			assert_eq!(px, (g, g, g, 255), "{x}, {y}");
+		}
+
+		// an extra sample that isn't alpha is skipped
+		let (header, pixels, _) = decode(include_bytes!("../test/white_is_zero.tiff"));
+		assert_eq!(header.color_type, TiffColorType::Gray);
+		for (i, px) in pixels.into_iter().enumerate() {
+			let g = !((i as u32 % 13 * 19) as u8);
+			assert_eq!(px, (g, g, g, 255), "{i}");
+		}
+	}
+
+	#[test]
+	fn encode() {
+		use TiffColorType::*;
+
+		let mut text = crate::Metadata::new();
+		text.insert(crate::metadata::SOFTWARE, "dwelf");
+		text.insert(crate::metadata::DESCRIPTION, "odd");
+		// odd, so it's padded
+		let profile = (0..301).map(|i| i as u8).collect::<Vec<_>>();
+		text.insert(crate::metadata::ICC_PROFILE, profile.clone());
+		for color_type in [Gray, GrayAlpha, RGB, RGBA] {
+			for compression in [TiffCompression::None, TiffCompression::PackBits] {
+				for big_endian in [false, true] {
+					// wide enough for a few rows a strip, and runs for packbits
+					let (width, height) = (1200, 9);
+					let pixels = (0..width * height).map(|i| px(i % width / 5 % 13, i / width));
+					let header = tiff::TiffHeader { width, height, color_type, compression, big_endian };
+					let mut out = vec![];
+					tiff::Tiff.encode_with_metadata(pixels.clone(), header.clone(), &text, &mut out).expect("error?");
+
+					let mut data = &out[..];
+					let (decoded, metadata, iter) = tiff::Tiff.decode_with_metadata(&mut data).expect("error?");
+					assert_eq!(decoded, header);
+					assert_eq!(metadata.get_text(crate::metadata::SOFTWARE), Some("dwelf"));
+					assert_eq!(metadata.get_text(crate::metadata::DESCRIPTION), Some("odd"));
+					assert_eq!(metadata.get(crate::metadata::ICC_PROFILE).map(|x| x.as_bytes()), Some(&profile[..]));
+					let mut n = 0;
+					for (i, (a, b)) in iter.zip(pixels).enumerate() {
+						let g = crate::pixel::luma(b) as u8;
+						let b = match color_type {
+							Gray => (g, g, g, 255),
+							GrayAlpha => (g, g, g, b.3),
+							RGB => (b.0, b.1, b.2, 255),
+							RGBA => b,
+						};
+						assert_eq!(a, b, "{color_type:?}, {compression:?}, {big_endian}: {i}");
+						n += 1;
+					}
+					assert_eq!(n, width * height);
+				}
+			}
+		}
+
+		// runs, and the bytes between them as they are
+		let mut out = vec![];
+		super::pack(&[1, 1, 1, 2, 3, 4, 4, 5, 5, 5], &mut out);
+		assert_eq!(out, [0xfe, 1, 0x03, 2, 3, 4, 4, 0xfe, 5]);
+		let mut out = vec![];
+		super::pack(&[7; 300], &mut out);
+		assert_eq!(out, [0x81, 7, 0x81, 7, 0xd5, 7]);
+
+		// a short iterator, packbits or not
+		for compression in [TiffCompression::PackBits, TiffCompression::None] {
+			let header = tiff::TiffHeader { width: 13, height: 5, color_type: RGB, compression, big_endian: false };
+			let result = tiff::Tiff.encode((0..13 * 2 + 4).map(|i| px(i % 13, i / 13)), header, &mut vec![]);
+			assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 65, got: 30 })), "{result:?}");
+		}
+
+		// too big for 32-bit offsets
+		let header = tiff::TiffHeader { width: 1 << 16, height: 1 << 15, color_type: RGBA, compression: TiffCompression::None, big_endian: false };
+		let result = tiff::TiffEncoder::new(&header, &crate::Metadata::new(), vec![]);
+		assert!(matches!(result, Err(Error::InvalidInput { .. })), "{result:?}");
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = tiff::TiffPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".tiff")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("zero_width", Error::InvalidHeader { offset: 10, field: "width" });
+		case!("bad_width_type", Error::InvalidHeader { offset: 10, field: "width" });
+		case!("bad_compression", Error::InvalidHeader { field: "compression", .. });
+		case!("bad_bits", Error::InvalidHeader { field: "bits per sample", .. });
+		case!("bad_photometric", Error::InvalidHeader { field: "photometric interpretation", .. });
+		case!("planar", Error::InvalidHeader { field: "planar configuration", .. });
+		case!("predictor", Error::InvalidHeader { field: "predictor", .. });
+		case!("no_strips", Error::InvalidHeader { field: "strip offsets", .. });
+		case!("few_strips", Error::InvalidHeader { field: "strip byte counts", .. });
+		case!("short_strip", Error::Malformed { reason: "strip ends early", .. });
+		case!("short_packbits", Error::Malformed { reason: "strip ends early", .. });
+		case!("truncated", Error::UnexpectedEof { offset: 200 });
+		case!("truncated_ifd", Error::UnexpectedEof { offset: 50 });
+
+		assert!(decode(IMAGE_GRADIENT).is_ok());
+	}
+}
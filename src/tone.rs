@@ -0,0 +1,47 @@
+/*!
+tone curves, for formats with floating point samples.
+*/
+
+use crate::pixel::{srgb, srgb_inverse};
+
+/// how floating point samples are mapped to 8 bits and back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ToneCurve {
+	/// samples from 0 to 1, as they are.
+	Linear,
+	/// samples from 0 to 1, with the srgb transfer function.
+	Srgb,
+	/// `x / (1 + x)` first, so everything past 1 is kept apart too, then
+	/// the srgb transfer function.
+	Reinhard,
+}
+
+impl ToneCurve {
+	/// a sample mapped to 8 bits. anything out of range, and nan, is
+	/// clamped.
+	pub fn apply(self, x: f32) -> u8 {
+		let x = match self {
+			Self::Linear => x,
+			Self::Srgb => srgb(x),
+			// written this way so infinity comes out as 1
+			Self::Reinhard => srgb(1.0 - 1.0 / (1.0 + x.max(0.0))),
+		};
+		// nan fails every comparison, so it comes out as 0
+		if x > 0.0 { (x.min(1.0) * 255.0 + 0.5) as u8 } else { 0 }
+	}
+
+	/// the sample `apply` maps to `x`. 255 maps back to infinity for
+	/// `Reinhard`.
+	pub fn invert(self, x: u8) -> f32 {
+		let x = x as f32 / 255.0;
+		match self {
+			Self::Linear => x,
+			Self::Srgb => srgb_inverse(x),
+			Self::Reinhard => {
+				let x = srgb_inverse(x);
+				x / (1.0 - x)
+			}
+		}
+	}
+}
@@ -0,0 +1,507 @@
+/*!
+x bitmaps, which are c source.
+
+the file is `#define`s of the image's name with `_width`, `_height` and,
+for cursors, `_x_hot` and `_y_hot`, then an array of the pixels:
+
+```c
+#define cross_width 8
+#define cross_height 2
+static unsigned char cross_bits[] = {
+   0x18, 0xff};
+```
+
+each row starts a new value, and the lowest bit of each value is its
+leftmost pixel, set for black. x11 bitmaps have byte values, in a `char`
+array, and x10 ones 16-bit values, in a `short` array. values are hex or
+decimal, and comments and whitespace can go anywhere between tokens.
+
+pixels come out as opaque black or white, and are made black for encoding
+by whether their rec. 601 luma is under half. alpha is dropped. values are
+read as they're asked for, so nothing is held in memory, and nothing is
+read past the `;` that ends the array.
+*/
+
+use crate::Error;
+use crate::csource::{Source, WORD_MAX};
+use crate::error::oom;
+
+/// the most words before the array's name, like `static const unsigned`.
+const WORDS_MAX: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct XbmHeader {
+	pub width: u32,
+	pub height: u32,
+	/// what the defines and the array are named after, which has to be an
+	/// identifier for encoding.
+	pub name: String,
+	/// the cursor's hotspot, from the top left.
+	pub hotspot: Option<(u32, u32)>,
+	/// whether values are 16 bits, in a `short` array, rather than 8.
+	pub x10: bool,
+}
+
+impl crate::Dimensions for XbmHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Xbm;
+
+impl Default for Xbm {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// pixels of an xbm image, read a value at a time as they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct XbmPixels<'a, R> {
+	header: XbmHeader,
+	source: Source<'a, R>,
+	/// the value being handed out.
+	value: u16,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	/// whether a value has been read, so the next comes after a comma.
+	started: bool,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> XbmPixels<'a, R> {
+	/// reads the defines and the array's declaration, up to its first value.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut source = Source::new(data);
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		if source.peek()? != b'#' {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+
+		let mut name: Option<String> = None;
+		let [mut width, mut height, mut x_hot, mut y_hot] = [None; 4];
+		while source.peek()? == b'#' {
+			source.byte()?;
+			let (at, directive) = source.word("directive")?;
+			if directive != "define" {
+				return Err(field(at, "directive"));
+			}
+			let (at, define) = source.word("define")?;
+			let (value_at, value) = source.number()?;
+			let value = value.ok_or(field(value_at, "value"))?;
+			let suffixes = ["_width", "_height", "_x_hot", "_y_hot"];
+			let Some((i, prefix)) = suffixes.iter().enumerate().find_map(|(i, suffix)| Some((i, define.strip_suffix(suffix)?)))
+			else {
+				return Err(field(at, "define"));
+			};
+			match &name {
+				Some(name) if name != prefix => return Err(field(at, "name")),
+				_ => name = Some(prefix.into()),
+			}
+			let value = u32::try_from(value).map_err(|_| field(value_at, ["width", "height", "hotspot", "hotspot"][i]))?;
+			*[&mut width, &mut height, &mut x_hot, &mut y_hot][i] = Some((value_at, value));
+		}
+
+		// `static unsigned char name_bits[] = {`, whichever words come
+		// before the name
+		let declaration = source.offset;
+		let mut words = vec![];
+		while source.peek()? != b'[' {
+			if words.len() == WORDS_MAX {
+				return Err(field(declaration, "declaration"));
+			}
+			words.push(source.word("declaration")?);
+		}
+		let Some((at, bits)) = words.pop()
+		else {
+			return Err(field(declaration, "declaration"));
+		};
+		let x10 = match (words.iter().any(|(_, w)| w == "short"), words.iter().any(|(_, w)| w == "char")) {
+			(true, false) => true,
+			(false, true) => false,
+			_ => return Err(field(declaration, "type")),
+		};
+		let name = match (bits.strip_suffix("_bits"), name) {
+			(Some(prefix), Some(name)) if prefix == name => name,
+			(Some(prefix), None) => prefix.into(),
+			_ => return Err(field(at, "name")),
+		};
+		source.expect(b'[', "declaration")?;
+		if let (at, None) = source.number()?
+			&& source.peek()? != b']'
+		{
+			return Err(field(at, "array size"));
+		}
+		source.expect(b']', "declaration")?;
+		source.expect(b'=', "declaration")?;
+		source.expect(b'{', "declaration")?;
+
+		let (width, height) = match (width, height) {
+			(Some((at, 0)), _) => return Err(field(at, "width")),
+			(_, Some((at, 0))) => return Err(field(at, "height")),
+			(Some((_, width)), Some((_, height))) => (width, height),
+			(None, _) => return Err(field(declaration, "width")),
+			(_, None) => return Err(field(declaration, "height")),
+		};
+		let hotspot = match (x_hot, y_hot) {
+			(Some((_, x)), Some((_, y))) => Some((x, y)),
+			(None, None) => None,
+			(Some((at, _)), None) | (None, Some((at, _))) => return Err(field(at, "hotspot")),
+		};
+
+		Ok(Self {
+			header: XbmHeader { width, height, name, hotspot, x10 },
+			source,
+			value: 0,
+			x: 0,
+			y: 0,
+			started: false,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &XbmHeader {
+		&self.header
+	}
+
+	/// bits in a value.
+	fn bits(&self) -> u32 {
+		if self.header.x10 { 16 } else { 8 }
+	}
+
+	/// reads the next value, and the comma before it.
+	fn read_value(&mut self) -> Result<u16, Error> {
+		let malformed = |offset, reason| Error::Malformed { offset, reason };
+		let mut b = self.source.peek()?;
+		if self.started && b == b',' {
+			self.source.byte()?;
+			b = self.source.peek()?;
+		}
+		else if self.started && b != b'}' {
+			return Err(malformed(self.source.offset, "values aren't separated by commas"));
+		}
+		self.started = true;
+		if b == b'}' {
+			return Err(malformed(self.source.offset, "there are too few values"));
+		}
+		match self.source.number()? {
+			(at, None) => Err(malformed(at, "value isn't a number")),
+			(_, Some(n)) if n < 1 << self.bits() => Ok(n as u16),
+			(at, Some(_)) => Err(malformed(at, "value is too big")),
+		}
+	}
+
+	/// reads any values that are left and the end of the array, and
+	/// returns the number of bytes the image took up.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		for px in &mut self {
+			px?;
+		}
+		// a comma can come after the last value
+		let source = &mut self.source;
+		if source.peek()? == b',' {
+			source.byte()?;
+		}
+		if source.peek()? != b'}' {
+			return Err(Error::Malformed { offset: source.offset, reason: "there are too many values" });
+		}
+		source.byte()?;
+		if source.peek()? != b';' {
+			return Err(Error::Malformed { offset: source.offset, reason: "array doesn't end in ;" });
+		}
+		source.byte()?;
+		Ok(source.offset)
+	}
+}
+
+impl<R: std::io::Read> Iterator for XbmPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		if self.x.is_multiple_of(self.bits()) {
+			match self.read_value() {
+				Ok(value) => self.value = value,
+				Err(e) => {
+					self.failed = true;
+					return Some(Err(e));
+				}
+			}
+		}
+
+		let black = self.value >> (self.x % self.bits()) & 1 == 1;
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(if black { (0, 0, 0, 255) } else { (255, 255, 255, 255) }))
+	}
+}
+
+/// `XbmPixels`, ending at the first error, for `Format::decode`.
+struct Pixels<'a, R>(XbmPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// push-based encoder state, which `Xbm::encode` drives.
+#[derive(Debug)]
+pub(crate) struct XbmEncoder<W> {
+	out: W,
+	width: u32,
+	x10: bool,
+	/// pixels of the row so far.
+	x: u32,
+	value: u16,
+	/// values written so far.
+	values: u64,
+	/// text of the row so far.
+	row: String,
+}
+
+impl<W: std::io::Write> XbmEncoder<W> {
+	/// writes the defines and the array's declaration.
+	pub(crate) fn new(header: &XbmHeader, mut out: W) -> Result<Self, Error> {
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		let name = &header.name;
+		let mut text = format!("#define {name}_width {}\n#define {name}_height {}\n", header.width, header.height);
+		if !crate::csource::is_identifier(name) || name.len() > WORD_MAX - 7 {
+			return Err(field(8, "name"));
+		}
+		// after `#define ` and the name, and `_width ` or `_height `
+		if header.width == 0 {
+			return Err(field((8 + name.len() + 7) as u64, "width"));
+		}
+		if header.height == 0 {
+			let line = text.find('\n').map_or(0, |x| x + 1);
+			return Err(field((line + 8 + name.len() + 8) as u64, "height"));
+		}
+		if let Some((x, y)) = header.hotspot {
+			text += &format!("#define {name}_x_hot {x}\n#define {name}_y_hot {y}\n");
+		}
+		let kind = if header.x10 { "short" } else { "unsigned char" };
+		text += &format!("static {kind} {name}_bits[] = {{");
+		crate::write_all(&mut out, text.as_bytes())?;
+
+		Ok(Self {
+			out,
+			width: header.width,
+			x10: header.x10,
+			x: 0,
+			value: 0,
+			values: 0,
+			row: String::new(),
+		})
+	}
+
+	/// adds the value so far to the row, 12 bytes or 8 shorts a line.
+	fn value(&mut self) {
+		let per_line = if self.x10 { 8 } else { 12 };
+		if self.values > 0 {
+			self.row.push(',');
+		}
+		self.row += if self.values.is_multiple_of(per_line) { "\n   " } else { " " };
+		self.row += &match self.x10 {
+			true => format!("0x{:04x}", self.value),
+			false => format!("0x{:02x}", self.value),
+		};
+		self.values += 1;
+		self.value = 0;
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		// a separator, a line break and a value
+		self.row.try_reserve(12).map_err(|_| oom())?;
+		let bits = if self.x10 { 16 } else { 8 };
+		self.value |= ((crate::pixel::luma(px) < 128) as u16) << (self.x % bits);
+		self.x += 1;
+		if self.x.is_multiple_of(bits) || self.x == self.width {
+			self.value();
+		}
+		if self.x == self.width {
+			crate::write_all(&mut self.out, self.row.as_bytes())?;
+			self.row.clear();
+			self.x = 0;
+		}
+		Ok(())
+	}
+
+	/// writes what there is of a row that didn't get all its pixels, and
+	/// ends the array. the image is cut short, like the pixels it was given.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		if !self.x.is_multiple_of(if self.x10 { 16 } else { 8 }) {
+			self.value();
+		}
+		self.row += "};\n";
+		crate::write_all(&mut self.out, self.row.as_bytes())?;
+		self.row.clear();
+		self.x = 0;
+		Ok(())
+	}
+}
+
+impl crate::Format for Xbm {
+	type Header = XbmHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = XbmPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = XbmPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = XbmEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, xbm};
+
+	const IMAGE_CROSS: &[u8] = include_bytes!("../test/cross.xbm");
+
+	fn color(x: u32, y: u32) -> crate::Rgba {
+		match (x * 3 + y) % 5 < 2 {
+			true => (0, 0, 0, 255),
+			false => (255, 255, 255, 255),
+		}
+	}
+
+	#[test]
+	fn decode() {
+		// the same cursor, with hex values, and with decimal ones, comments,
+		// an array size and a comma after the last value
+		for image in [IMAGE_CROSS, include_bytes!("../test/commented.xbm")] {
+			let mut data = image;
+			let mut pixels = xbm::XbmPixels::new(&mut data).expect("error?");
+			assert_eq!(*pixels.header(), xbm::XbmHeader { width: 13, height: 5, name: "cross".into(), hotspot: Some((6, 2)), x10: false });
+			for (i, px) in (&mut pixels).enumerate() {
+				let (x, y) = (i as u32 % 13, i as u32 / 13);
+				assert_eq!(px.expect("error?"), color(x, y), "{x}, {y}");
+			}
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64 - 1);
+
+			let mut data = image;
+			let mut pixels = xbm::XbmPixels::new(&mut data).expect("error?");
+			_ = pixels.nth(20);
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64 - 1);
+		}
+
+		// 16-bit values
+		let mut data = &include_bytes!("../test/x10.xbm")[..];
+		let pixels = xbm::XbmPixels::new(&mut data).expect("error?");
+		assert_eq!(*pixels.header(), xbm::XbmHeader { width: 21, height: 3, name: "wide".into(), hotspot: None, x10: true });
+		assert!(pixels.map(|px| px.expect("error?")).eq((0..21 * 3).map(|i| color(i % 21, i / 21))));
+	}
+
+	#[test]
+	fn encode() {
+		// byte for byte what the fixture is, and 16-bit values round trip
+		let header = xbm::XbmHeader { width: 13, height: 5, name: "cross".into(), hotspot: Some((6, 2)), x10: false };
+		let mut out = vec![];
+		xbm::Xbm.encode((0..13 * 5).map(|i| color(i % 13, i / 13)), header, &mut out).expect("error?");
+		assert_eq!(out, IMAGE_CROSS);
+
+		for (width, height) in [(21, 3), (16, 2), (1, 1)] {
+			let header = xbm::XbmHeader { width, height, name: "wide".into(), hotspot: None, x10: true };
+			let mut out = vec![];
+			xbm::Xbm.encode((0..width * height).map(|i| color(i % width, i / width)), header.clone(), &mut out).expect("error?");
+			let mut data = &out[..];
+			let mut pixels = xbm::XbmPixels::new(&mut data).expect("error?");
+			assert_eq!(*pixels.header(), header);
+			assert!((&mut pixels).map(|px| px.expect("error?")).eq((0..width * height).map(|i| color(i % width, i / width))));
+			assert_eq!(pixels.finish().expect("error?"), out.len() as u64 - 1);
+		}
+
+		// gray by its luma, without alpha
+		let header = xbm::XbmHeader { width: 4, height: 2, name: "gray".into(), hotspot: None, x10: false };
+		let mut pixels = vec![(0, 0, 0, 0), (127, 127, 127, 255), (128, 128, 128, 255), (255, 0, 0, 255), (0, 255, 0, 0)];
+		pixels.extend([(255, 255, 255, 255); 3]);
+		let mut out = vec![];
+		xbm::Xbm.encode(pixels.iter().copied(), header.clone(), &mut out).expect("error?");
+		assert!(out.ends_with(b"{\n   0x0b, 0x00};\n"), "{}", String::from_utf8_lossy(&out));
+		let result = xbm::Xbm.encode(pixels[..5].iter().copied(), header, &mut vec![]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 8, got: 5 })), "{result:?}");
+
+		for (header, offset) in [
+			(xbm::XbmHeader { width: 0, height: 2, name: "a".into(), hotspot: None, x10: false }, 16),
+			(xbm::XbmHeader { width: 1, height: 0, name: "a".into(), hotspot: None, x10: false }, 35),
+			(xbm::XbmHeader { width: 1, height: 1, name: "a b".into(), hotspot: None, x10: false }, 8),
+			(xbm::XbmHeader { width: 1, height: 1, name: "1a".into(), hotspot: None, x10: false }, 8),
+		] {
+			let result = xbm::XbmEncoder::new(&header, vec![]);
+			assert!(matches!(result, Err(Error::InvalidHeader { offset: o, .. }) if o == offset), "{result:?}");
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = xbm::XbmPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".xbm")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("not_c", Error::InvalidMagic { offset: 0 });
+		case!("bad_directive", Error::InvalidHeader { offset: 24, field: "directive" });
+		case!("bad_define", Error::InvalidHeader { offset: 31, field: "define" });
+		case!("names_differ", Error::InvalidHeader { offset: 31, field: "name" });
+		case!("zero_width", Error::InvalidHeader { offset: 20, field: "width" });
+		case!("no_value", Error::InvalidHeader { offset: 20, field: "value" });
+		case!("no_height", Error::InvalidHeader { offset: 67, field: "height" });
+		case!("one_hotspot", Error::InvalidHeader { offset: 66, field: "hotspot" });
+		case!("no_type", Error::InvalidHeader { offset: 90, field: "type" });
+		case!("bad_array_name", Error::InvalidHeader { offset: 111, field: "name" });
+		case!("no_brace", Error::InvalidHeader { offset: 126, .. });
+		case!("bad_value", Error::Malformed { offset: 131, reason: "value isn't a number" });
+		case!("too_big", Error::Malformed { offset: 131, reason: "value is too big" });
+		case!("no_comma", Error::Malformed { offset: 136, reason: "values aren't separated by commas" });
+		case!("too_few", Error::Malformed { reason: "there are too few values", .. });
+		case!("too_many", Error::Malformed { reason: "there are too many values", .. });
+		case!("no_semicolon", Error::Malformed { reason: "array doesn't end in ;", .. });
+		case!("stray_slash", Error::Malformed { offset: 90, .. });
+		case!("truncated", Error::UnexpectedEof { offset: 150 });
+
+		assert!(decode(IMAGE_CROSS).is_ok());
+	}
+}
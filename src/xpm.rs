@@ -0,0 +1,653 @@
+/*!
+xpm3 pixmaps, which are c source.
+
+the file is `/* XPM */`, then an array of strings: the width, height, number
+of colors, characters per pixel and, for cursors, the hotspot, then a line
+per color, then a string per row:
+
+```c
+/* XPM */
+static char *dot[] = {
+"3 2 2 1",
+"  c None",
+". c #FF0000",
+" . ",
+"..."
+};
+```
+
+each color line is the characters of its pixels, then a key and a color for
+each of color displays (`c`), gray ones (`g`, `g4`) and monochrome ones
+(`m`), and any of them can be left out. the color one is used if it's
+there. colors are hex, with 1 to 4 digits a channel, `None`, for
+transparent, or one of a few x11 names: the basic colors, and `grayN` from
+0 to 100. `XPMEXT` extensions after the rows are skipped.
+
+`Xpm::encode` writes every color as `#RRGGBB`, or `None` when alpha is under
+half, with as few characters per pixel as there are colors for. pixels are
+kept until the end, since the palette comes before them.
+*/
+
+use std::collections::HashMap;
+
+use crate::Error;
+use crate::csource::Source;
+use crate::error::oom;
+
+/// the longest string of the header that's read.
+const LINE_MAX: usize = 4096;
+/// the most characters a pixel that's read can take.
+const CHARS_MAX: usize = 8;
+/// the most strings an extension that's skipped can take.
+const EXTENSION_MAX: usize = 1 << 16;
+/// characters pixels are written with: printable ones but `"` and `\`.
+const CHARS: &[u8] = b" .XoO+@#$%&*=-;:>,<1234567890qwertyuipasdfghjklzxcvbnmMNBVCZASDFGHJKLPIUYTREWQ!~^/()_`'][{}|?";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct XpmHeader {
+	pub width: u32,
+	pub height: u32,
+	/// what the array is named, which has to be an identifier for encoding.
+	pub name: String,
+	/// the cursor's hotspot, from the top left.
+	pub hotspot: Option<(u32, u32)>,
+}
+
+impl crate::Dimensions for XpmHeader {
+	fn width(&self) -> u32 {
+		self.width
+	}
+
+	fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Xpm;
+
+impl Default for Xpm {
+	fn default() -> Self {
+		Self
+	}
+}
+
+/// a color by its x11 name, which is matched without case or spaces.
+fn named(name: &str) -> Option<crate::Rgba> {
+	let name = name.to_ascii_lowercase().replace(' ', "").replace("grey", "gray");
+	if let Some(n) = name.strip_prefix("gray")
+		&& let Ok(n @ 0..=100) = n.parse::<u32>()
+	{
+		let x = ((n * 255 + 50) / 100) as u8;
+		return Some((x, x, x, 255));
+	}
+	let (r, g, b) = match name.as_str() {
+		"black" => (0, 0, 0),
+		"white" => (255, 255, 255),
+		"red" => (255, 0, 0),
+		"green" => (0, 255, 0),
+		"blue" => (0, 0, 255),
+		"yellow" => (255, 255, 0),
+		"cyan" => (0, 255, 255),
+		"magenta" => (255, 0, 255),
+		"gray" => (190, 190, 190),
+		"darkgray" => (169, 169, 169),
+		"lightgray" => (211, 211, 211),
+		"orange" => (255, 165, 0),
+		"purple" => (160, 32, 240),
+		"brown" => (165, 42, 42),
+		"pink" => (255, 192, 203),
+		"navy" => (0, 0, 128),
+		_ => return None,
+	};
+	Some((r, g, b, 255))
+}
+
+/// a color, as a color line has it.
+fn color(value: &str) -> Option<crate::Rgba> {
+	if value.eq_ignore_ascii_case("none") {
+		return Some((0, 0, 0, 0));
+	}
+	let Some(hex) = value.strip_prefix('#')
+	else {
+		return named(value);
+	};
+	let digits = hex.len() / 3;
+	if !(1..=4).contains(&digits) || !hex.len().is_multiple_of(3) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return None;
+	}
+	// the top 8 bits of each, or a digit twice
+	let channel = |i: usize| {
+		let x = u16::from_str_radix(&hex[i * digits..(i + 1) * digits], 16).unwrap_or(0);
+		match digits {
+			1 => x as u8 * 17,
+			_ => (x >> ((digits - 2) * 4)) as u8,
+		}
+	};
+	Some((channel(0), channel(1), channel(2), 255))
+}
+
+/// the color out of a color line's keys and colors, after its characters.
+/// names can have spaces, so a color is the words up to the next key.
+fn line_color(line: &str) -> Option<crate::Rgba> {
+	let mut colors: [Option<String>; 4] = Default::default();
+	let mut key = None;
+	for word in line.split_ascii_whitespace() {
+		let next = match word {
+			"c" => Some(0),
+			"g" => Some(1),
+			"g4" => Some(2),
+			"m" => Some(3),
+			// symbolic names don't have a color
+			"s" => Some(4),
+			_ => None,
+		};
+		match (next, key) {
+			(Some(next), _) => key = Some(next),
+			(None, Some(i @ 0..4)) => {
+				let value = colors[i].get_or_insert_default();
+				if !value.is_empty() {
+					value.push(' ');
+				}
+				value.push_str(word);
+			}
+			(None, Some(_)) => {}
+			(None, None) => return None,
+		}
+	}
+	colors.into_iter().flatten().next().and_then(|value| color(&value))
+}
+
+/// a pixel's characters, packed into a key.
+fn code(chars: &[u8]) -> u64 {
+	chars.iter().fold(0, |n, &b| n << 8 | b as u64)
+}
+
+/// pixels of an xpm image, read a row at a time as they're asked for.
+///
+/// the first error is yielded once, after which the iterator ends.
+#[derive(Debug)]
+pub struct XpmPixels<'a, R> {
+	header: XpmHeader,
+	source: Source<'a, R>,
+	/// characters a pixel.
+	chars: usize,
+	palette: HashMap<u64, crate::Rgba>,
+	/// whether there are extensions after the rows.
+	extensions: bool,
+	/// the next pixel to hand out, from the top left.
+	x: u32,
+	y: u32,
+	failed: bool,
+}
+
+impl<'a, R: std::io::Read> XpmPixels<'a, R> {
+	/// reads the header and the colors, up to the first row.
+	pub fn new(data: &'a mut R) -> Result<Self, Error> {
+		let mut magic = [0; 9];
+		crate::read_exact(data, &mut magic, 0)?;
+		if &magic != b"/* XPM */" {
+			return Err(Error::InvalidMagic { offset: 0 });
+		}
+		let mut source = Source::new(data);
+		source.offset = 9;
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+
+		// `static char *name[] = {`, with whichever words come before the
+		// name
+		let declaration = source.offset;
+		let mut words = vec![];
+		loop {
+			match source.peek()? {
+				b'[' => break,
+				b'*' => _ = source.byte()?,
+				_ if words.len() == 8 => return Err(field(declaration, "declaration")),
+				_ => words.push(source.word("declaration")?),
+			}
+		}
+		let Some((_, name)) = words.pop()
+		else {
+			return Err(field(declaration, "declaration"));
+		};
+		if !words.iter().any(|(_, word)| word == "char") {
+			return Err(field(declaration, "type"));
+		}
+		source.expect(b'[', "declaration")?;
+		if let (at, None) = source.number()?
+			&& source.peek()? != b']'
+		{
+			return Err(field(at, "array size"));
+		}
+		source.expect(b']', "declaration")?;
+		source.expect(b'=', "declaration")?;
+		source.expect(b'{', "declaration")?;
+
+		// `width height colors chars [x y] [XPMEXT]`
+		const NAMES: [&str; 6] = ["width", "height", "color count", "chars per pixel", "hotspot", "hotspot"];
+		let (start, values) = source.string(LINE_MAX, "values")?;
+		let mut numbers = vec![];
+		let mut extensions = false;
+		let mut at = start + 1;
+		for word in values.split(|b| b.is_ascii_whitespace()) {
+			let this = at;
+			at += word.len() as u64 + 1;
+			if word.is_empty() {
+				continue;
+			}
+			if word == b"XPMEXT" && !extensions && matches!(numbers.len(), 4 | 6) {
+				extensions = true;
+				continue;
+			}
+			if extensions || numbers.len() == 6 {
+				return Err(field(this, "values"));
+			}
+			let n = str::from_utf8(word).ok().and_then(|word| word.parse::<u32>().ok());
+			numbers.push((this, n.ok_or(field(this, NAMES[numbers.len()]))?));
+		}
+		let (width, height, colors, chars) = match numbers[..] {
+			[width, height, colors, chars] | [width, height, colors, chars, _, _] => (width, height, colors, chars),
+			_ => return Err(field(at - 1, "values")),
+		};
+		for ((at, n), name) in [(width, "width"), (height, "height"), (colors, "color count")] {
+			if n == 0 {
+				return Err(field(at, name));
+			}
+		}
+		if !(1..=CHARS_MAX as u32).contains(&chars.1) {
+			return Err(field(chars.0, "chars per pixel"));
+		}
+		let hotspot = numbers.get(4..6).map(|xy| (xy[0].1, xy[1].1));
+		let chars = chars.1 as usize;
+
+		// each color grows the palette, so it takes up no more than the file
+		let mut palette = HashMap::new();
+		for _ in 0..colors.1 {
+			source.expect(b',', "color")?;
+			let (start, line) = source.string(LINE_MAX, "color")?;
+			let px = match line.split_at_checked(chars) {
+				Some((code, rest)) => str::from_utf8(rest).ok().and_then(line_color).map(|px| (code, px)),
+				None => None,
+			};
+			let Some((code, px)) = px
+			else {
+				return Err(field(start, "color"));
+			};
+			palette.insert(self::code(code), px);
+		}
+
+		Ok(Self {
+			header: XpmHeader { width: width.1, height: height.1, name, hotspot },
+			source,
+			chars,
+			palette,
+			extensions,
+			x: 0,
+			y: 0,
+			failed: false,
+		})
+	}
+
+	pub fn header(&self) -> &XpmHeader {
+		&self.header
+	}
+
+	/// reads the next pixel, and the start of its row if it's the first.
+	fn read_pixel(&mut self) -> Result<crate::Rgba, Error> {
+		let malformed = |offset, reason| Error::Malformed { offset, reason };
+		let source = &mut self.source;
+		if self.x == 0 {
+			let mut b = source.peek()?;
+			if b == b',' {
+				source.byte()?;
+				b = source.peek()?;
+			}
+			else if b != b'}' {
+				return Err(malformed(source.offset, "strings aren't separated by commas"));
+			}
+			match b {
+				b'"' => source.byte()?,
+				b'}' => return Err(malformed(source.offset, "there are too few rows")),
+				_ => return Err(malformed(source.offset, "row isn't a string")),
+			};
+		}
+		let start = source.offset;
+		let mut chars = [0; CHARS_MAX];
+		for c in &mut chars[..self.chars] {
+			*c = source.byte()?;
+			if *c == b'"' {
+				return Err(malformed(source.offset - 1, "row is too short"));
+			}
+		}
+		let Some(&px) = self.palette.get(&code(&chars[..self.chars]))
+		else {
+			return Err(malformed(start, "pixel isn't one of the colors"));
+		};
+		if self.x + 1 == self.header.width && source.byte()? != b'"' {
+			return Err(malformed(source.offset - 1, "row is too long"));
+		}
+		Ok(px)
+	}
+
+	/// reads any pixels that are left, extensions and the end of the
+	/// array, and returns the number of bytes the image took up.
+	pub fn finish(mut self) -> Result<u64, Error> {
+		if self.failed {
+			return Err(Error::InvalidInput { reason: "an earlier read failed" });
+		}
+		for px in &mut self {
+			px?;
+		}
+		let source = &mut self.source;
+		if self.extensions {
+			let mut strings = 0;
+			loop {
+				source.expect(b',', "extension")?;
+				let (start, string) = source.string(LINE_MAX, "extension")?;
+				if string.starts_with(b"XPMENDEXT") {
+					break;
+				}
+				strings += 1;
+				if strings == EXTENSION_MAX {
+					return Err(Error::InvalidHeader { offset: start, field: "extension" });
+				}
+			}
+		}
+		// a comma can come after the last string
+		if source.peek()? == b',' {
+			source.byte()?;
+		}
+		if source.peek()? != b'}' {
+			return Err(Error::Malformed { offset: source.offset, reason: "there are too many rows" });
+		}
+		source.byte()?;
+		if source.peek()? != b';' {
+			return Err(Error::Malformed { offset: source.offset, reason: "array doesn't end in ;" });
+		}
+		source.byte()?;
+		Ok(source.offset)
+	}
+}
+
+impl<R: std::io::Read> Iterator for XpmPixels<'_, R> {
+	type Item = Result<crate::Rgba, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.y == self.header.height {
+			return None;
+		}
+		let px = match self.read_pixel() {
+			Ok(px) => px,
+			Err(e) => {
+				self.failed = true;
+				return Some(Err(e));
+			}
+		};
+		self.x += 1;
+		if self.x == self.header.width {
+			self.x = 0;
+			self.y += 1;
+		}
+		Some(Ok(px))
+	}
+}
+
+/// `XpmPixels`, ending at the first error, for `Format::decode`.
+struct Pixels<'a, R>(XpmPixels<'a, R>);
+
+impl<R: std::io::Read> Iterator for Pixels<'_, R> {
+	type Item = crate::Rgba;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()?.ok()
+	}
+}
+
+/// push-based encoder state, which `Xpm::encode` drives.
+#[derive(Debug)]
+pub(crate) struct XpmEncoder<W> {
+	out: W,
+	header: XpmHeader,
+	/// every pixel, since the colors come first.
+	pixels: Vec<crate::Rgba>,
+}
+
+impl<W: std::io::Write> XpmEncoder<W> {
+	pub(crate) fn new(header: &XpmHeader, out: W) -> Result<Self, Error> {
+		let field = |offset, field| Error::InvalidHeader { offset, field };
+		// after `/* XPM */\nstatic char *`, and the name and `[] = {\n"`
+		if !crate::csource::is_identifier(&header.name) || header.name.is_empty() || header.name.len() > crate::csource::WORD_MAX {
+			return Err(field(23, "name"));
+		}
+		let values = 23 + header.name.len() as u64 + 7;
+		if header.width == 0 {
+			return Err(field(values, "width"));
+		}
+		if header.height == 0 {
+			return Err(field(values + header.width.to_string().len() as u64 + 1, "height"));
+		}
+		Ok(Self { out, header: header.clone(), pixels: vec![] })
+	}
+
+	pub(crate) fn push(&mut self, px: crate::Rgba) -> Result<(), Error> {
+		self.pixels.try_reserve(1).map_err(|_| oom())?;
+		self.pixels.push(match px.3 < 128 {
+			true => (0, 0, 0, 0),
+			false => (px.0, px.1, px.2, 255),
+		});
+		Ok(())
+	}
+
+	/// writes the whole image. a short iterator leaves the last row short,
+	/// and the ones after it out.
+	pub(crate) fn end(&mut self) -> Result<(), Error> {
+		let mut indices = HashMap::new();
+		let mut colors = vec![];
+		for &px in &self.pixels {
+			indices.entry(px).or_insert_with(|| {
+				colors.push(px);
+				colors.len() - 1
+			});
+		}
+		// an empty image still has a color
+		if colors.is_empty() {
+			colors.push((0, 0, 0, 0));
+		}
+		let mut chars = 1;
+		while (CHARS.len() as u64).pow(chars) < colors.len() as u64 {
+			chars += 1;
+		}
+		let codes: Vec<_> = (0..colors.len())
+			.map(|mut i| {
+				let mut code = vec![0; chars as usize];
+				for c in code.iter_mut().rev() {
+					*c = CHARS[i % CHARS.len()];
+					i /= CHARS.len();
+				}
+				code
+			})
+			.collect();
+
+		let XpmHeader { width, height, name, hotspot } = &self.header;
+		let hotspot = hotspot.map_or(String::new(), |(x, y)| format!(" {x} {y}"));
+		let mut text = format!("/* XPM */\nstatic char *{name}[] = {{\n\"{width} {height} {} {chars}{hotspot}\",\n", colors.len());
+		for (px, code) in colors.iter().zip(&codes) {
+			let code = String::from_utf8_lossy(code);
+			match px {
+				(_, _, _, 0) => text += &format!("\"{code} c None\",\n"),
+				(r, g, b, _) => text += &format!("\"{code} c #{r:02X}{g:02X}{b:02X}\",\n"),
+			}
+		}
+		crate::write_all(&mut self.out, text.as_bytes())?;
+
+		let mut rows = self.pixels.chunks(*width as usize).peekable();
+		let mut row = vec![];
+		while let Some(pixels) = rows.next() {
+			row.clear();
+			row.push(b'"');
+			for px in pixels {
+				row.extend_from_slice(&codes[indices.get(px).copied().unwrap_or(0)]);
+			}
+			row.extend_from_slice(if rows.peek().is_some() { b"\",\n" } else { b"\"\n" });
+			crate::write_all(&mut self.out, &row)?;
+		}
+		crate::write_all(&mut self.out, b"};\n")?;
+		Ok(())
+	}
+}
+
+impl crate::Format for Xpm {
+	type Header = XpmHeader;
+	type Options = ();
+
+	fn decode(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = crate::Rgba>), Error> {
+		let pixels = XpmPixels::new(data)?;
+		Ok((pixels.header().clone(), Pixels(pixels)))
+	}
+
+	fn decode_checked(self, data: &mut impl std::io::Read) -> Result<(Self::Header, impl Iterator<Item = Result<crate::Rgba, Error>>), Error> {
+		let pixels = XpmPixels::new(data)?;
+		Ok((pixels.header().clone(), pixels))
+	}
+
+	fn encode(self, data: impl Iterator<Item = crate::Rgba>, header: Self::Header, out: &mut impl std::io::Write) -> Result<(), Error> {
+		let total = header.width as u64 * header.height as u64;
+		let mut encoder = XpmEncoder::new(&header, out)?;
+		crate::push_exact(data, total, |px| encoder.push(px))?;
+		encoder.end()
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::{Error, Format, xpm};
+
+	const IMAGE_ICON: &[u8] = include_bytes!("../test/icon.xpm");
+	const IMAGE_CURSOR: &[u8] = include_bytes!("../test/cursor.xpm");
+
+	/// the pixel at `(x, y)`, out of `colors`.
+	fn color(x: u32, y: u32, colors: &[crate::Rgba]) -> crate::Rgba {
+		colors[((x + y * 2) % colors.len() as u32) as usize]
+	}
+
+	#[test]
+	fn decode() {
+		// hex of every length, a name, and keys in any order
+		let colors = [(0, 0, 0, 0), (255, 0, 0, 255), (0, 255, 0, 255), (0, 0, 255, 255), (211, 211, 211, 255)];
+		let mut data = IMAGE_ICON;
+		let mut pixels = xpm::XpmPixels::new(&mut data).expect("error?");
+		assert_eq!(*pixels.header(), xpm::XpmHeader { width: 11, height: 6, name: "icon".into(), hotspot: None });
+		for (i, px) in (&mut pixels).enumerate() {
+			let (x, y) = (i as u32 % 11, i as u32 / 11);
+			assert_eq!(px.expect("error?"), color(x, y, &colors), "{x}, {y}");
+		}
+		assert_eq!(pixels.finish().expect("error?"), IMAGE_ICON.len() as u64 - 1);
+
+		// two characters a pixel, a hotspot, colors only for gray displays,
+		// and extensions, with comments between everything
+		let colors = [(0, 0, 0, 0), (0x10, 0x20, 0x30, 255), (0, 0, 128, 255), (128, 128, 128, 255)];
+		let mut data = IMAGE_CURSOR;
+		let mut pixels = xpm::XpmPixels::new(&mut data).expect("error?");
+		assert_eq!(*pixels.header(), xpm::XpmHeader { width: 7, height: 4, name: "cursor_xpm".into(), hotspot: Some((3, 1)) });
+		assert!((&mut pixels).map(|px| px.expect("error?")).eq((0..7 * 4).map(|i| color(i % 7, i / 7, &colors))));
+		assert_eq!(pixels.finish().expect("error?"), IMAGE_CURSOR.len() as u64 - 1);
+
+		for image in [IMAGE_ICON, IMAGE_CURSOR] {
+			let mut data = image;
+			let mut pixels = xpm::XpmPixels::new(&mut data).expect("error?");
+			_ = pixels.nth(8);
+			assert_eq!(pixels.finish().expect("error?"), image.len() as u64 - 1);
+		}
+
+		// colors by themselves
+		assert_eq!(super::color("#abc"), Some((0xaa, 0xbb, 0xcc, 255)));
+		assert_eq!(super::color("#123456789abc"), Some((0x12, 0x56, 0x9a, 255)));
+		assert_eq!(super::color("NONE"), Some((0, 0, 0, 0)));
+		assert_eq!(super::color("Grey100"), Some((255, 255, 255, 255)));
+		assert_eq!(super::color("gray101"), None);
+		assert_eq!(super::color("#12345"), None);
+	}
+
+	#[test]
+	fn encode() {
+		// byte for byte what the fixture would be with the colors it writes
+		let colors = [(0, 0, 0, 0), (255, 0, 0, 255), (0, 255, 0, 255), (0, 0, 255, 255), (211, 211, 211, 255)];
+		let header = xpm::XpmHeader { width: 11, height: 6, name: "icon".into(), hotspot: None };
+		let mut out = vec![];
+		xpm::Xpm.encode((0..11 * 6).map(|i| color(i % 11, i / 11, &colors)), header, &mut out).expect("error?");
+		let expected = String::from_utf8_lossy(IMAGE_ICON)
+			.replace("c #0F0", "c #00FF00")
+			.replace("m white c #00000000FFFF", "c #0000FF")
+			.replace("s shade g4 black c light grey", "c #D3D3D3");
+		assert_eq!(String::from_utf8_lossy(&out), expected);
+
+		// as many characters a pixel as it takes, a hotspot, and alpha
+		// under half as transparent
+		let header = xpm::XpmHeader { width: 40, height: 5, name: "many".into(), hotspot: Some((1, 2)) };
+		let pixels: Vec<_> = (0..200u32).map(|i| (i as u8, 0, 0, if i == 7 { 127 } else { 255 })).collect();
+		let mut out = vec![];
+		xpm::Xpm.encode(pixels.iter().copied(), header.clone(), &mut out).expect("error?");
+		assert!(out.starts_with(b"/* XPM */\nstatic char *many[] = {\n\"40 5 200 2 1 2\",\n"));
+		let mut data = &out[..];
+		let mut decoded = xpm::XpmPixels::new(&mut data).expect("error?");
+		assert_eq!(*decoded.header(), header);
+		assert!((&mut decoded).map(|px| px.expect("error?")).eq(pixels.iter().map(|&px| if px.3 == 127 { (0, 0, 0, 0) } else { px })));
+		assert_eq!(decoded.finish().expect("error?"), out.len() as u64 - 1);
+
+		// a short iterator
+		let header = xpm::XpmHeader { width: 2, height: 2, name: "a".into(), hotspot: None };
+		let result = xpm::Xpm.encode([(1, 2, 3, 255); 3].into_iter(), header, &mut vec![]);
+		assert!(matches!(result, Err(Error::PixelCountMismatch { expected: 4, got: 3 })), "{result:?}");
+
+		for (header, offset) in [
+			(xpm::XpmHeader { width: 1, height: 1, name: "a b".into(), hotspot: None }, 23),
+			(xpm::XpmHeader { width: 1, height: 1, name: "".into(), hotspot: None }, 23),
+			(xpm::XpmHeader { width: 0, height: 1, name: "a".into(), hotspot: None }, 31),
+			(xpm::XpmHeader { width: 10, height: 0, name: "a".into(), hotspot: None }, 34),
+		] {
+			let result = xpm::XpmEncoder::new(&header, vec![]);
+			assert!(matches!(result, Err(Error::InvalidHeader { offset: o, .. }) if o == offset), "{result:?}");
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		fn decode(mut data: &[u8]) -> Result<(), Error> {
+			let mut pixels = xpm::XpmPixels::new(&mut data)?;
+			for px in &mut pixels {
+				px?;
+			}
+			pixels.finish()?;
+			Ok(())
+		}
+
+		macro_rules! case {
+			($name:literal, $pat:pat) => {
+				let result = decode(include_bytes!(concat!("../test/corrupt/", $name, ".xpm")));
+				assert!(matches!(result, Err($pat)), "{}: {:?}", $name, result);
+			};
+		}
+
+		case!("bad_magic", Error::InvalidMagic { offset: 0 });
+		case!("no_type", Error::InvalidHeader { offset: 9, field: "type" });
+		case!("no_brace", Error::InvalidHeader { offset: 32, .. });
+		case!("zero_width", Error::InvalidHeader { offset: 35, field: "width" });
+		case!("few_values", Error::InvalidHeader { offset: 41, field: "values" });
+		case!("many_chars", Error::InvalidHeader { offset: 42, field: "chars per pixel" });
+		case!("bad_color", Error::InvalidHeader { offset: 58, field: "color" });
+		case!("bad_hex", Error::InvalidHeader { offset: 58, field: "color" });
+		case!("not_in_palette", Error::Malformed { offset: 165, reason: "pixel isn't one of the colors" });
+		case!("short_row", Error::Malformed { offset: 175, reason: "row is too short" });
+		case!("long_row", Error::Malformed { offset: 191, reason: "row is too long" });
+		case!("no_comma", Error::Malformed { offset: 193, reason: "strings aren't separated by commas" });
+		case!("too_few_rows", Error::Malformed { reason: "there are too few rows", .. });
+		case!("too_many_rows", Error::Malformed { reason: "there are too many rows", .. });
+		case!("no_semicolon", Error::UnexpectedEof { .. });
+		case!("unended_extension", Error::InvalidHeader { field: "extension", .. });
+		case!("truncated", Error::UnexpectedEof { offset: 150 });
+
+		assert!(decode(IMAGE_ICON).is_ok());
+		assert!(decode(IMAGE_CURSOR).is_ok());
+	}
+}
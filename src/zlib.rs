@@ -0,0 +1,838 @@
+/*!
+zlib streams (rfc 1950) holding deflate data (rfc 1951), which png and
+other formats wrap their image data in.
+
+`Inflate` decompresses into whatever buffer it's handed, so nothing past
+the 32k window is held in memory. `Deflate` compresses with fixed codes,
+which cost a little in size but don't need a pass over the data first.
+*/
+
+// exr only inflates, and ktx2 only needs some of it
+#![cfg_attr(not(feature = "png"), allow(dead_code))]
+
+use crate::Error;
+
+/// compressed bytes, which can be split up anywhere.
+pub(crate) trait Input {
+	/// reads at least one byte into `buf`, or returns 0 once the data is over.
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+
+	/// position of the next byte `read` would return, for errors.
+	fn offset(&self) -> u64;
+}
+
+const WINDOW: usize = 1 << 15;
+
+const LEN_BASE: [u16; 29] = [
+	3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+	35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+	0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+	3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+	1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+	257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+	0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+	7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// order the code length code lengths are stored in.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// bits looked up at once. longer codes are rare, and fall back to walking
+/// the code one bit at a time.
+const FAST: u32 = 9;
+
+/// a canonical huffman code.
+#[derive(Debug, Clone)]
+struct Huffman {
+	/// number of codes of each length.
+	counts: [u16; 16],
+	/// symbols ordered by code.
+	symbols: [u16; 288],
+	/// `symbol << 4 | length` for every `FAST` bit pattern whose code is at
+	/// most that long, and 0 for the rest.
+	table: [u16; 1 << FAST],
+}
+
+impl Huffman {
+	fn new(lengths: &[u8]) -> Result<Self, &'static str> {
+		let mut this = Self {
+			counts: [0; 16],
+			symbols: [0; 288],
+			table: [0; 1 << FAST],
+		};
+
+		for &len in lengths {
+			this.counts[len as usize] += 1;
+		}
+		this.counts[0] = 0;
+
+		// codes left over at each length, which can't go negative
+		let mut left = 1i32;
+		for len in 1..16 {
+			left = (left << 1) - this.counts[len] as i32;
+			if left < 0 {
+				return Err("over-subscribed huffman code");
+			}
+		}
+
+		let mut offsets = [0u16; 16];
+		for len in 1..15 {
+			offsets[len + 1] = offsets[len] + this.counts[len];
+		}
+
+		// first code of each length
+		let mut next = [0u32; 16];
+		for len in 1..16 {
+			next[len] = (next[len - 1] + this.counts[len - 1] as u32) << 1;
+		}
+
+		for (symbol, &len) in lengths.iter().enumerate() {
+			if len == 0 {
+				continue;
+			}
+			let len = len as usize;
+			this.symbols[offsets[len] as usize] = symbol as u16;
+			offsets[len] += 1;
+
+			let code = next[len];
+			next[len] += 1;
+
+			if len as u32 <= FAST {
+				// codes are read starting from their top bit
+				let reversed = code.reverse_bits() >> (32 - len);
+				let entry = (symbol as u16) << 4 | len as u16;
+				let mut i = reversed as usize;
+				while i < 1 << FAST {
+					this.table[i] = entry;
+					i += 1 << len;
+				}
+			}
+		}
+
+		Ok(this)
+	}
+}
+
+fn fixed() -> Result<(Huffman, Huffman), &'static str> {
+	let mut lengths = [0u8; 288];
+	lengths[..144].fill(8);
+	lengths[144..256].fill(9);
+	lengths[256..280].fill(7);
+	lengths[280..].fill(8);
+	Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+#[derive(Debug, Clone)]
+enum State {
+	Header,
+	Block,
+	Stored(u16),
+	Codes(Box<(Huffman, Huffman)>),
+	Trailer,
+	Done,
+}
+
+/// decompresses a zlib stream from an `Input`.
+#[derive(Debug)]
+pub(crate) struct Inflate<I> {
+	input: I,
+	buf: [u8; 512],
+	pos: usize,
+	len: usize,
+	/// bits not yet used, starting from the lowest.
+	bits: u64,
+	nbits: u32,
+	state: State,
+	last: bool,
+	window: Vec<u8>,
+	/// bytes decompressed so far.
+	total: u64,
+	/// a match that didn't fit in the last output buffer.
+	copy: (u16, u16),
+	adler: (u32, u32),
+}
+
+impl<I: Input> Inflate<I> {
+	pub(crate) fn new(input: I) -> Self {
+		Self {
+			input,
+			buf: [0; 512],
+			pos: 0,
+			len: 0,
+			bits: 0,
+			nbits: 0,
+			state: State::Header,
+			last: false,
+			window: vec![0; WINDOW],
+			total: 0,
+			copy: (0, 0),
+			adler: (1, 0),
+		}
+	}
+
+	pub(crate) fn input(&self) -> &I {
+		&self.input
+	}
+
+	pub(crate) fn input_mut(&mut self) -> &mut I {
+		&mut self.input
+	}
+
+	pub(crate) fn into_input(self) -> I {
+		self.input
+	}
+
+	/// whether the whole stream, checksum included, has been read.
+	pub(crate) fn is_done(&self) -> bool {
+		matches!(self.state, State::Done)
+	}
+
+	/// roughly where in the input decompression is, for errors.
+	fn offset(&self) -> u64 {
+		let buffered = (self.len - self.pos) as u64 + (self.nbits / 8) as u64;
+		self.input.offset().saturating_sub(buffered)
+	}
+
+	fn malformed(&self, reason: &'static str) -> Error {
+		Error::Malformed { offset: self.offset(), reason }
+	}
+
+	/// tops up the bit buffer as far as the input allows.
+	fn refill(&mut self) -> Result<(), Error> {
+		while self.nbits <= 56 {
+			if self.pos == self.len {
+				self.len = self.input.read(&mut self.buf)?.min(self.buf.len());
+				self.pos = 0;
+				if self.len == 0 {
+					break;
+				}
+			}
+			self.bits |= (self.buf[self.pos] as u64) << self.nbits;
+			self.pos += 1;
+			self.nbits += 8;
+		}
+		Ok(())
+	}
+
+	fn peek(&mut self, n: u32) -> Result<u32, Error> {
+		if self.nbits < n {
+			self.refill()?;
+			if self.nbits < n {
+				return Err(Error::UnexpectedEof { offset: self.offset() });
+			}
+		}
+		Ok((self.bits & ((1 << n) - 1)) as u32)
+	}
+
+	fn consume(&mut self, n: u32) {
+		self.bits >>= n;
+		self.nbits -= n;
+	}
+
+	fn bits(&mut self, n: u32) -> Result<u32, Error> {
+		let x = self.peek(n)?;
+		self.consume(n);
+		Ok(x)
+	}
+
+	/// drops bits up to the next byte boundary.
+	fn align(&mut self) {
+		self.consume(self.nbits % 8);
+	}
+
+	fn symbol(&mut self, code: &Huffman) -> Result<u16, Error> {
+		if self.nbits < 15 {
+			self.refill()?;
+		}
+
+		let entry = code.table[(self.bits & ((1 << FAST) - 1)) as usize];
+		if entry != 0 {
+			let len = (entry & 15) as u32;
+			if len > self.nbits {
+				return Err(Error::UnexpectedEof { offset: self.offset() });
+			}
+			self.consume(len);
+			return Ok(entry >> 4);
+		}
+
+		// walk the code a bit at a time, comparing it against the first
+		// code of each length
+		let (mut code_, mut first, mut index) = (0i32, 0i32, 0i32);
+		for len in 1..16 {
+			if len > self.nbits {
+				return Err(Error::UnexpectedEof { offset: self.offset() });
+			}
+			code_ |= ((self.bits >> (len - 1)) & 1) as i32;
+			let count = code.counts[len as usize] as i32;
+			if code_ - first < count {
+				self.consume(len);
+				return Ok(code.symbols[(index + code_ - first) as usize]);
+			}
+			index += count;
+			first = (first + count) << 1;
+			code_ <<= 1;
+		}
+		Err(self.malformed("invalid huffman code"))
+	}
+
+	fn dynamic(&mut self) -> Result<(Huffman, Huffman), Error> {
+		let nlit = self.bits(5)? as usize + 257;
+		let ndist = self.bits(5)? as usize + 1;
+		let nclen = self.bits(4)? as usize + 4;
+
+		if nlit > 286 || ndist > 30 {
+			return Err(self.malformed("too many length or distance codes"));
+		}
+
+		let mut clens = [0; 19];
+		for &i in &CLEN_ORDER[..nclen] {
+			clens[i] = self.bits(3)? as u8;
+		}
+		let clen = Huffman::new(&clens).map_err(|e| self.malformed(e))?;
+
+		let mut lengths = [0; 286 + 30];
+		let mut i = 0;
+		while i < nlit + ndist {
+			let (x, n) = match self.symbol(&clen)? {
+				x @ 0..16 => (x as u8, 1),
+				16 => {
+					if i == 0 {
+						return Err(self.malformed("repeated length with no previous length"));
+					}
+					(lengths[i - 1], 3 + self.bits(2)? as usize)
+				}
+				17 => (0, 3 + self.bits(3)? as usize),
+				_ => (0, 11 + self.bits(7)? as usize),
+			};
+			if i + n > nlit + ndist {
+				return Err(self.malformed("code lengths overrun"));
+			}
+			lengths[i..i + n].fill(x);
+			i += n;
+		}
+
+		if lengths[256] == 0 {
+			return Err(self.malformed("missing end of block code"));
+		}
+
+		let lit = Huffman::new(&lengths[..nlit]).map_err(|e| self.malformed(e))?;
+		let dist = Huffman::new(&lengths[nlit..nlit + ndist]).map_err(|e| self.malformed(e))?;
+		Ok((lit, dist))
+	}
+
+	/// decodes symbols into `out` until it's full, a match doesn't fit, or
+	/// the block ends, which returns true.
+	fn codes(&mut self, (lit, dist): &(Huffman, Huffman), out: &mut [u8], n: &mut usize) -> Result<bool, Error> {
+		while *n < out.len() && self.copy.0 == 0 {
+			let symbol = self.symbol(lit)? as usize;
+			if symbol < 256 {
+				self.push(out, n, symbol as u8);
+				continue;
+			}
+			if symbol == 256 {
+				return Ok(true);
+			}
+
+			let i = symbol - 257;
+			if i >= LEN_BASE.len() {
+				return Err(self.malformed("invalid length code"));
+			}
+			let len = LEN_BASE[i] + self.bits(LEN_EXTRA[i] as u32)? as u16;
+
+			let i = self.symbol(dist)? as usize;
+			if i >= DIST_BASE.len() {
+				return Err(self.malformed("invalid distance code"));
+			}
+			let distance = DIST_BASE[i] + self.bits(DIST_EXTRA[i] as u32)? as u16;
+			if distance as u64 > self.total {
+				return Err(self.malformed("distance goes back past the start"));
+			}
+
+			self.copy = (len, distance);
+		}
+		Ok(false)
+	}
+
+	fn push(&mut self, out: &mut [u8], n: &mut usize, x: u8) {
+		self.window[self.total as usize % WINDOW] = x;
+		self.total += 1;
+		out[*n] = x;
+		*n += 1;
+	}
+
+	/// decompresses into `out`, returning how much of it was filled. 0 means
+	/// the stream is over and its checksum matched.
+	pub(crate) fn read(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+		let mut n = 0;
+		// output up to here has gone into the checksum
+		let mut summed = 0;
+
+		while n < out.len() {
+			if self.copy.0 > 0 {
+				let dist = self.copy.1 as u64;
+				while self.copy.0 > 0 && n < out.len() {
+					let x = self.window[(self.total - dist) as usize % WINDOW];
+					self.push(out, &mut n, x);
+					self.copy.0 -= 1;
+				}
+				continue;
+			}
+
+			match &self.state {
+				State::Header => {
+					let cmf = self.bits(8)?;
+					let flg = self.bits(8)?;
+					if cmf & 15 != 8 || cmf >> 4 > 7 {
+						return Err(self.malformed("not a deflate stream"));
+					}
+					if (cmf << 8 | flg) % 31 != 0 {
+						return Err(self.malformed("bad zlib header check"));
+					}
+					if flg & 0x20 != 0 {
+						return Err(self.malformed("preset dictionaries aren't supported"));
+					}
+					self.state = State::Block;
+				}
+				State::Block => {
+					if self.last {
+						self.state = State::Trailer;
+						continue;
+					}
+					self.last = self.bits(1)? == 1;
+					self.state = match self.bits(2)? {
+						0 => {
+							self.align();
+							let len = self.bits(16)?;
+							let nlen = self.bits(16)?;
+							if len != !nlen & 0xffff {
+								return Err(self.malformed("stored block length doesn't match its complement"));
+							}
+							State::Stored(len as u16)
+						}
+						1 => State::Codes(Box::new(fixed().map_err(|e| self.malformed(e))?)),
+						2 => State::Codes(Box::new(self.dynamic()?)),
+						_ => return Err(self.malformed("invalid block type")),
+					};
+				}
+				&State::Stored(left) => {
+					if left == 0 {
+						self.state = State::Block;
+						continue;
+					}
+					let x = self.bits(8)? as u8;
+					self.push(out, &mut n, x);
+					self.state = State::Stored(left - 1);
+				}
+				State::Codes(_) => {
+					// the tables are boxed, so this only moves a pointer
+					if let State::Codes(codes) = core::mem::replace(&mut self.state, State::Block)
+						&& !self.codes(&codes, out, &mut n)?
+						{
+						self.state = State::Codes(codes);
+					}
+				}
+				State::Trailer => {
+					self.checksum(&out[summed..n]);
+					summed = n;
+					self.align();
+					let mut expected = 0;
+					for _ in 0..4 {
+						expected = expected << 8 | self.bits(8)?;
+					}
+					let actual = self.adler.1 << 16 | self.adler.0;
+					if expected != actual {
+						return Err(Error::ChecksumMismatch { expected, actual });
+					}
+					self.state = State::Done;
+				}
+				State::Done => break,
+			}
+		}
+
+		self.checksum(&out[summed..n]);
+		Ok(n)
+	}
+
+	fn checksum(&mut self, data: &[u8]) {
+		self.adler = adler32(self.adler, data);
+	}
+}
+
+fn adler32((mut a, mut b): (u32, u32), data: &[u8]) -> (u32, u32) {
+	// 5552 bytes is as many as can be summed before `b` could overflow
+	for chunk in data.chunks(5552) {
+		for &x in chunk {
+			a += x as u32;
+			b += a;
+		}
+		a %= 65521;
+		b %= 65521;
+	}
+	(a, b)
+}
+
+/// longest match a length code can express.
+const MAX_MATCH: usize = 258;
+
+/// earlier positions with the same hash that are tried for a match, at
+/// each level. level 0 stores the input as is.
+const CHAINS: [usize; 10] = [0, 1, 2, 4, 8, 16, 32, 64, 128, 256];
+
+/// the level `Deflate::new` compresses at.
+pub(crate) const LEVEL: u8 = 6;
+
+/// input compressed into each block.
+const BLOCK: usize = 1 << 16;
+
+const HASH: usize = 1 << 15;
+
+fn hash(x: &[u8]) -> usize {
+	let x = (x[0] as u32) << 16 | (x[1] as u32) << 8 | x[2] as u32;
+	(x.wrapping_mul(0x9e3779b1) >> 17) as usize
+}
+
+/// `code`, `len` bits long, in the order huffman codes are written in.
+fn reversed(code: u32, len: u32) -> u32 {
+	code.reverse_bits() >> (32 - len)
+}
+
+/// compresses into a zlib stream, which builds up in `output`.
+#[derive(Debug)]
+pub(crate) struct Deflate {
+	/// the window, followed by input that hasn't been compressed yet.
+	data: Vec<u8>,
+	/// where in `data` the input starts.
+	start: usize,
+	/// bytes that have been dropped from the front of `data`.
+	base: u64,
+	/// one more than the last position with each hash, or 0.
+	head: Vec<u64>,
+	/// one more than the position before each one with the same hash, or 0.
+	prev: Vec<u64>,
+	bits: u64,
+	nbits: u32,
+	out: Vec<u8>,
+	adler: (u32, u32),
+	/// positions tried for each match, or 0 to store blocks uncompressed.
+	chain: usize,
+}
+
+impl Deflate {
+	pub(crate) fn new() -> Self {
+		Self {
+			data: vec![],
+			start: 0,
+			base: 0,
+			head: vec![0; HASH],
+			prev: vec![0; WINDOW],
+			bits: 0,
+			nbits: 0,
+			// deflate with a 32k window, compressed fast
+			out: vec![0x78, 0x01],
+			adler: (1, 0),
+			chain: CHAINS[LEVEL as usize],
+		}
+	}
+
+	/// like `new`, compressing at `level`, from 0 for none to 9 for the
+	/// smallest output, or `None` past 9.
+	pub(crate) fn with_level(level: u8) -> Option<Self> {
+		let chain = *CHAINS.get(level as usize)?;
+		Some(Self { chain, ..Self::new() })
+	}
+
+	/// compressed bytes that haven't been taken yet.
+	pub(crate) fn output(&mut self) -> &mut Vec<u8> {
+		&mut self.out
+	}
+
+	pub(crate) fn write(&mut self, data: &[u8]) {
+		self.adler = adler32(self.adler, data);
+		for chunk in data.chunks(BLOCK) {
+			self.data.extend_from_slice(chunk);
+			if self.data.len() - self.start >= BLOCK {
+				self.block(false);
+			}
+		}
+	}
+
+	/// compresses what's left, and ends the stream.
+	pub(crate) fn finish(&mut self) {
+		self.block(true);
+		if self.nbits > 0 {
+			self.out.push(self.bits as u8);
+			self.bits = 0;
+			self.nbits = 0;
+		}
+		let adler = self.adler.1 << 16 | self.adler.0;
+		self.out.extend_from_slice(&adler.to_be_bytes());
+	}
+
+	fn put(&mut self, value: u32, n: u32) {
+		self.bits |= (value as u64) << self.nbits;
+		self.nbits += n;
+		while self.nbits >= 8 {
+			self.out.push(self.bits as u8);
+			self.bits >>= 8;
+			self.nbits -= 8;
+		}
+	}
+
+	fn symbol(&mut self, x: u16) {
+		let (code, len) = match x {
+			0..144 => (0x30 + x, 8),
+			144..256 => (0x190 + x - 144, 9),
+			256..280 => (x - 256, 7),
+			_ => (0xc0 + x - 280, 8),
+		};
+		self.put(reversed(code as u32, len), len);
+	}
+
+	fn insert(&mut self, i: usize) {
+		if i + 3 > self.data.len() {
+			return;
+		}
+		let h = hash(&self.data[i..]);
+		let pos = self.base + i as u64;
+		self.prev[pos as usize % WINDOW] = self.head[h];
+		self.head[h] = pos + 1;
+	}
+
+	/// the longest earlier match for the input at `i`, as `(length, distance)`.
+	fn find(&self, i: usize) -> (usize, u64) {
+		let max = MAX_MATCH.min(self.data.len() - i);
+		if max < 3 {
+			return (0, 0);
+		}
+
+		let pos = self.base + i as u64;
+		let mut best = (0, 0);
+		let mut next = self.head[hash(&self.data[i..])];
+		for _ in 0..self.chain {
+			// positions only go back, unless the chain was overwritten by
+			// one a window later
+			let Some(candidate) = next.checked_sub(1).filter(|&x| x < pos && x >= self.base) else {
+				break;
+			};
+			let distance = pos - candidate;
+			if distance > WINDOW as u64 {
+				break;
+			}
+
+			let j = (candidate - self.base) as usize;
+			let len = self.data[j..].iter().zip(&self.data[i..i + max]).take_while(|(a, b)| a == b).count();
+			if len > best.0 {
+				best = (len, distance);
+				if len == max {
+					break;
+				}
+			}
+
+			let prev = self.prev[candidate as usize % WINDOW];
+			if prev >= next {
+				break;
+			}
+			next = prev;
+		}
+		best
+	}
+
+	/// compresses all of the input, and drops what's past the window.
+	fn block(&mut self, last: bool) {
+		match self.chain {
+			0 => self.stored(last),
+			_ => self.compressed(last),
+		}
+
+		// keep a window's worth for the next block to refer back to
+		let drop = self.data.len().saturating_sub(WINDOW);
+		self.data.drain(..drop);
+		self.base += drop as u64;
+		self.start = self.data.len();
+	}
+
+	/// writes the input as it is, in as many stored blocks as it takes.
+	fn stored(&mut self, last: bool) {
+		let mut i = self.start;
+		loop {
+			let n = (self.data.len() - i).min(u16::MAX as usize);
+			let end = i + n == self.data.len();
+			self.put((last && end) as u32, 1);
+			self.put(0, 2);
+			// stored blocks start on a byte
+			if self.nbits > 0 {
+				self.put(0, 8 - self.nbits);
+			}
+			self.out.extend_from_slice(&(n as u16).to_le_bytes());
+			self.out.extend_from_slice(&(!(n as u16)).to_le_bytes());
+			self.out.extend_from_slice(&self.data[i..i + n]);
+			i += n;
+			if end {
+				break;
+			}
+		}
+	}
+
+	/// compresses the input into one block with fixed codes.
+	fn compressed(&mut self, last: bool) {
+		self.put(last as u32, 1);
+		self.put(1, 2);
+
+		let mut i = self.start;
+		while i < self.data.len() {
+			let (len, distance) = self.find(i);
+			if len < 3 {
+				self.symbol(self.data[i] as u16);
+				self.insert(i);
+				i += 1;
+				continue;
+			}
+
+			let code = LEN_BASE.partition_point(|&x| x as usize <= len) - 1;
+			self.symbol(257 + code as u16);
+			self.put((len - LEN_BASE[code] as usize) as u32, LEN_EXTRA[code] as u32);
+
+			let code = DIST_BASE.partition_point(|&x| x as u64 <= distance) - 1;
+			self.put(reversed(code as u32, 5), 5);
+			self.put((distance - DIST_BASE[code] as u64) as u32, DIST_EXTRA[code] as u32);
+
+			for i in i..i + len {
+				self.insert(i);
+			}
+			i += len;
+		}
+		self.symbol(256);
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::Error;
+
+	use super::{Deflate, Inflate, Input};
+
+	/// hands out at most `step` bytes at a time.
+	struct Slice<'a> {
+		data: &'a [u8],
+		pos: usize,
+		step: usize,
+	}
+
+	impl Input for Slice<'_> {
+		fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+			let n = buf.len().min(self.step).min(self.data.len() - self.pos);
+			buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+			self.pos += n;
+			Ok(n)
+		}
+
+		fn offset(&self) -> u64 {
+			self.pos as u64
+		}
+	}
+
+	fn inflate(data: &[u8], step: usize, out: usize) -> Result<Vec<u8>, Error> {
+		let mut inflate = Inflate::new(Slice { data, pos: 0, step });
+		let mut result = vec![];
+		let mut buf = vec![0; out];
+		loop {
+			match inflate.read(&mut buf)? {
+				0 => break,
+				n => result.extend_from_slice(&buf[..n]),
+			}
+		}
+		assert!(inflate.is_done());
+		Ok(result)
+	}
+
+	#[test]
+	fn blocks() {
+		const STORED: &[u8] = &[
+			0x78, 0x01, 0x01, 0x0c, 0x00, 0xf3, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65,
+			0x64, 0x20, 0x62, 0x79, 0x74, 0x65, 0x73, 0x1f, 0xcf, 0x04, 0xd9,
+		];
+		// "abcabcabcabc" and 300 'a's, with fixed codes
+		const FIXED: &[u8] = &[
+			0x78, 0x01, 0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0xa3, 0x51, 0x40, 0x34, 0x00,
+			0x00, 0x59, 0x02, 0x76, 0x45,
+		];
+
+		let mut expected = b"abcabcabcabc".to_vec();
+		expected.resize(312, b'a');
+
+		// matches and input both get split up
+		for (step, out) in [(1, 1), (1, 7), (3, 64), (512, 5), (512, 512)] {
+			assert_eq!(inflate(STORED, step, out).expect("error?"), b"stored bytes");
+			assert_eq!(inflate(FIXED, step, out).expect("error?"), expected);
+		}
+	}
+
+	#[test]
+	fn corrupt() {
+		macro_rules! case {
+			($data:expr, $pat:pat) => {
+				let result = inflate($data, 512, 512);
+				assert!(matches!(result, Err($pat)), "{:?}", result);
+			};
+		}
+
+		case!(&[0x78, 0x02], Error::Malformed { reason: "bad zlib header check", .. });
+		case!(&[0x79, 0xda], Error::Malformed { reason: "not a deflate stream", .. });
+		case!(&[0x78, 0x01, 0x07], Error::Malformed { reason: "invalid block type", .. });
+		case!(&[0x78, 0x01, 0x01, 0x01, 0x00, 0xff, 0xff], Error::Malformed { reason: "stored block length doesn't match its complement", .. });
+		// a match before any literals
+		case!(&[0x78, 0x01, 0x03, 0x02, 0x00], Error::Malformed { reason: "distance goes back past the start", .. });
+		case!(&[0x78, 0x01, 0x01, 0x01, 0x00, 0xfe, 0xff, 0x61, 0x00, 0x62], Error::UnexpectedEof { offset: 10 });
+		case!(&[0x78, 0x01, 0x01, 0x01, 0x00, 0xfe, 0xff, 0x61, 0x00, 0x62, 0x00, 0x61], Error::ChecksumMismatch { expected: 0x00620061, actual: 0x00620062 });
+	}
+
+	#[test]
+	fn deflate() {
+		let mut random = 1u32;
+		let mut random = move || {
+			random ^= random << 13;
+			random ^= random >> 17;
+			random ^= random << 5;
+			random as u8
+		};
+
+		let compress_with = |input: &[u8], level| {
+			let mut deflate = Deflate::with_level(level).expect("level?");
+			for chunk in input.chunks(1000) {
+				deflate.write(chunk);
+			}
+			deflate.finish();
+			let compressed = core::mem::take(deflate.output());
+			assert_eq!(inflate(&compressed, 512, 512).expect("error?"), input);
+			compressed.len()
+		};
+		let compress = |input: &[u8]| compress_with(input, super::LEVEL);
+
+		compress(b"");
+		compress(b"a");
+		assert!(compress(&b"abcabcabcabc".repeat(100)) < 50);
+		compress(&(0..200_000).map(|_| random()).collect::<Vec<_>>());
+
+		// a repeat from almost a window back, across a block boundary
+		let mut far = (0..50_000).map(|_| random()).collect::<Vec<_>>();
+		far.extend_from_within(20_000..);
+		assert!(compress(&far) < compress(&far[..50_000]) + 1000);
+
+		// matching is greedy, so more effort is only smaller on the whole,
+		// and none at all is the input, a few bytes a block, the header and
+		// the checksum
+		let text = b"the quick brown fox jumps over the lazy dog, and then some. ".repeat(2000);
+		let sizes = (0..=9).map(|level| compress_with(&text, level)).collect::<Vec<_>>();
+		assert!(sizes[9] <= sizes[1] && sizes[1..].iter().all(|&n| n < text.len() / 10), "{sizes:?}");
+		assert!(sizes[0] > text.len() && sizes[0] < text.len() + 30, "{sizes:?}");
+		assert_eq!(compress_with(b"", 0), 11);
+		assert!(Deflate::with_level(10).is_none());
+	}
+}
@@ -0,0 +1,369 @@
+/*!
+differential tests against independent implementations.
+
+random images are encoded by dwelf and by a reference crate, and each
+output is decoded by the other side. only pixels are compared, since
+encoders are free to pick different ops for the same image. qoi is checked
+against the `qoi` crate, png against `png`, and bmp and tga against
+`image`.
+
+run with `cargo test --features differential --test differential`.
+set `DWELF_DIFFERENTIAL_SEED` to replay a reported failure.
+*/
+
+use std::io::Write;
+
+use dwelf::{Format, Rgba, bmp, png, qoi, tga};
+use proptest::prelude::*;
+use proptest::test_runner::{Config, RngAlgorithm, TestError, TestRng, TestRunner};
+
+#[derive(Debug, Clone)]
+struct TestImage {
+	width: u32,
+	height: u32,
+	alpha: bool,
+	pixels: Vec<Rgba>,
+}
+
+impl TestImage {
+	fn bytes(&self) -> Vec<u8> {
+		let mut out = vec![];
+		for px in &self.pixels {
+			out.extend_from_slice(&[px.0, px.1, px.2]);
+			if self.alpha {
+				out.push(px.3);
+			}
+		}
+		out
+	}
+
+	fn from_bytes(width: u32, height: u32, alpha: bool, bytes: &[u8]) -> Self {
+		let pixels = if alpha {
+			bytes.chunks_exact(4).map(|x| (x[0], x[1], x[2], x[3])).collect()
+		} else {
+			bytes.chunks_exact(3).map(|x| (x[0], x[1], x[2], 255)).collect()
+		};
+		Self { width, height, alpha, pixels }
+	}
+}
+
+/// how the next pixel relates to the previous one. plain random pixels
+/// would nearly always encode as literals, so steps are biased toward
+/// the cases the smaller ops cover.
+#[derive(Debug, Clone)]
+enum Step {
+	Same,
+	Diff(i8, i8, i8),
+	Luma(i8, i8, i8),
+	Alpha(u8),
+	Palette(usize),
+	Random(Rgba),
+}
+
+fn step() -> impl Strategy<Value = Step> {
+	prop_oneof![
+		3 => Just(Step::Same),
+		2 => (-2i8..=1, -2i8..=1, -2i8..=1).prop_map(|(r, g, b)| Step::Diff(r, g, b)),
+		2 => (-8i8..=7, -32i8..=31, -8i8..=7).prop_map(|(r, g, b)| Step::Luma(r, g, b)),
+		1 => any::<u8>().prop_map(Step::Alpha),
+		2 => (0usize..8).prop_map(Step::Palette),
+		2 => any::<(u8, u8, u8, u8)>().prop_map(Step::Random),
+	]
+}
+
+fn image() -> impl Strategy<Value = TestImage> {
+	(1u32..=24, 1u32..=24, any::<bool>())
+		.prop_flat_map(|(width, height, alpha)| {
+			let len = (width * height) as usize;
+			(
+				Just((width, height, alpha)),
+				prop::collection::vec(any::<(u8, u8, u8, u8)>(), 8),
+				prop::collection::vec(step(), len),
+			)
+		})
+		.prop_map(|((width, height, alpha), palette, steps)| {
+			let mut px = (0u8, 0u8, 0u8, 255u8);
+			let pixels = steps.into_iter().map(|step| {
+				px = match step {
+					Step::Same => px,
+					Step::Diff(r, g, b) => (
+						px.0.wrapping_add_signed(r),
+						px.1.wrapping_add_signed(g),
+						px.2.wrapping_add_signed(b),
+						px.3,
+					),
+					Step::Luma(r, g, b) => (
+						px.0.wrapping_add_signed(r.wrapping_add(g)),
+						px.1.wrapping_add_signed(g),
+						px.2.wrapping_add_signed(b.wrapping_add(g)),
+						px.3,
+					),
+					Step::Alpha(a) => (px.0, px.1, px.2, a),
+					Step::Palette(i) => palette[i],
+					Step::Random(x) => x,
+				};
+				if !alpha {
+					px.3 = 255;
+				}
+				px
+			}).collect();
+			TestImage { width, height, alpha, pixels }
+		})
+}
+
+/// a format, with both sides' encoder and decoder for it.
+struct Pair {
+	name: &'static str,
+	dwelf_encode: fn(&TestImage) -> Vec<u8>,
+	dwelf_decode: fn(&[u8]) -> Result<TestImage, String>,
+	reference_encode: fn(&TestImage) -> Vec<u8>,
+	reference_decode: fn(&[u8]) -> Result<TestImage, String>,
+}
+
+const QOI: Pair = Pair {
+	name: "qoi",
+	dwelf_encode: |image| {
+		let header = qoi::QoiHeader {
+			width: image.width,
+			height: image.height,
+			channels: if image.alpha { qoi::QoiHeaderChannels::RGBA } else { qoi::QoiHeaderChannels::RGB },
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+		let mut out = vec![];
+		qoi::Qoi.encode(image.pixels.iter().cloned(), header, &mut out).expect("dwelf encode failed");
+		out
+	},
+	dwelf_decode: |mut data| {
+		let (header, iter) = qoi::Qoi.decode(&mut data).map_err(|e| format!("dwelf failed to decode: {e}"))?;
+		Ok(TestImage {
+			width: header.width,
+			height: header.height,
+			alpha: header.channels == qoi::QoiHeaderChannels::RGBA,
+			pixels: iter.collect(),
+		})
+	},
+	reference_encode: |image| {
+		::qoi::encode_to_vec(image.bytes(), image.width, image.height).expect("reference encode failed")
+	},
+	reference_decode: |data| {
+		let (header, bytes) = ::qoi::decode_to_vec(data).map_err(|e| format!("reference failed to decode: {e}"))?;
+		Ok(TestImage::from_bytes(header.width, header.height, header.channels.is_rgba(), &bytes))
+	},
+};
+
+const PNG: Pair = Pair {
+	name: "png",
+	dwelf_encode: |image| {
+		let header = png::PngHeader {
+			width: image.width,
+			height: image.height,
+			color_type: if image.alpha { png::PngColorType::RGBA } else { png::PngColorType::RGB },
+			bit_depth: 8,
+			interlaced: false,
+			palette: vec![],
+		};
+		let mut out = vec![];
+		png::Png.encode(image.pixels.iter().cloned(), header, &mut out).expect("dwelf encode failed");
+		out
+	},
+	dwelf_decode: |mut data| {
+		let (header, iter) = png::Png.decode(&mut data).map_err(|e| format!("dwelf failed to decode: {e}"))?;
+		Ok(TestImage {
+			width: header.width,
+			height: header.height,
+			alpha: header.color_type == png::PngColorType::RGBA,
+			pixels: iter.collect(),
+		})
+	},
+	reference_encode: |image| {
+		let mut out = vec![];
+		let mut encoder = ::png::Encoder::new(&mut out, image.width, image.height);
+		encoder.set_color(if image.alpha { ::png::ColorType::Rgba } else { ::png::ColorType::Rgb });
+		encoder.set_depth(::png::BitDepth::Eight);
+		let mut writer = encoder.write_header().expect("reference encode failed");
+		writer.write_image_data(&image.bytes()).expect("reference encode failed");
+		writer.finish().expect("reference encode failed");
+		out
+	},
+	reference_decode: |data| {
+		let mut reader = ::png::Decoder::new(data).read_info().map_err(|e| format!("reference failed to decode: {e}"))?;
+		let mut bytes = vec![0; reader.output_buffer_size()];
+		let info = reader.next_frame(&mut bytes).map_err(|e| format!("reference failed to decode: {e}"))?;
+		if info.bit_depth != ::png::BitDepth::Eight {
+			return Err(format!("reference decoded {:?} samples", info.bit_depth));
+		}
+		let alpha = match info.color_type {
+			::png::ColorType::Rgb => false,
+			::png::ColorType::Rgba => true,
+			x => return Err(format!("reference decoded {x:?} pixels")),
+		};
+		Ok(TestImage::from_bytes(info.width, info.height, alpha, &bytes[..info.buffer_size()]))
+	},
+};
+
+const BMP: Pair = Pair {
+	name: "bmp",
+	dwelf_encode: |image| {
+		let header = bmp::BmpHeader {
+			width: image.width,
+			height: image.height,
+			color_type: if image.alpha { bmp::BmpColorType::RGBA } else { bmp::BmpColorType::RGB },
+			top_down: false,
+			palette: vec![],
+		};
+		let mut out = vec![];
+		bmp::Bmp.encode(image.pixels.iter().cloned(), header, &mut out).expect("dwelf encode failed");
+		out
+	},
+	dwelf_decode: |mut data| {
+		let (header, iter) = bmp::Bmp.decode(&mut data).map_err(|e| format!("dwelf failed to decode: {e}"))?;
+		Ok(TestImage {
+			width: header.width,
+			height: header.height,
+			alpha: header.color_type == bmp::BmpColorType::RGBA,
+			pixels: iter.collect(),
+		})
+	},
+	reference_encode: |image| reference_encode(image, ::image::ImageFormat::Bmp),
+	reference_decode: |data| reference_decode(data, ::image::ImageFormat::Bmp),
+};
+
+const TGA: Pair = Pair {
+	name: "tga",
+	dwelf_encode: |image| {
+		let header = tga::TgaHeader {
+			width: image.width,
+			height: image.height,
+			color_type: if image.alpha { tga::TgaColorType::RGBA } else { tga::TgaColorType::RGB },
+			top_down: false,
+			right_to_left: false,
+			rle: true,
+			palette: vec![],
+		};
+		let mut out = vec![];
+		tga::Tga.encode(image.pixels.iter().cloned(), header, &mut out).expect("dwelf encode failed");
+		out
+	},
+	dwelf_decode: |mut data| {
+		let (header, iter) = tga::Tga.decode(&mut data).map_err(|e| format!("dwelf failed to decode: {e}"))?;
+		Ok(TestImage {
+			width: header.width,
+			height: header.height,
+			alpha: header.color_type == tga::TgaColorType::RGBA,
+			pixels: iter.collect(),
+		})
+	},
+	reference_encode: |image| reference_encode(image, ::image::ImageFormat::Tga),
+	reference_decode: |data| reference_decode(data, ::image::ImageFormat::Tga),
+};
+
+/// the `image` crate's encoder for `format`.
+fn reference_encode(image: &TestImage, format: ::image::ImageFormat) -> Vec<u8> {
+	let color = if image.alpha { ::image::ExtendedColorType::Rgba8 } else { ::image::ExtendedColorType::Rgb8 };
+	let mut out = std::io::Cursor::new(vec![]);
+	::image::write_buffer_with_format(&mut out, &image.bytes(), image.width, image.height, color, format).expect("reference encode failed");
+	out.into_inner()
+}
+
+/// the `image` crate's decoder for `format`.
+fn reference_decode(data: &[u8], format: ::image::ImageFormat) -> Result<TestImage, String> {
+	let decoded = ::image::load_from_memory_with_format(data, format).map_err(|e| format!("reference failed to decode: {e}"))?;
+	let alpha = decoded.color().has_alpha();
+	let bytes = if alpha { decoded.to_rgba8().into_raw() } else { decoded.to_rgb8().into_raw() };
+	Ok(TestImage::from_bytes(decoded.width(), decoded.height(), alpha, &bytes))
+}
+
+fn compare(direction: &str, image: &TestImage, decoded: Result<TestImage, String>) -> Result<(), TestCaseError> {
+	let decoded = decoded.map_err(|e| TestCaseError::fail(format!("{direction}: {e}")))?;
+	prop_assert_eq!((image.width, image.height), (decoded.width, decoded.height), "{}: dimensions", direction);
+	if let Some(index) = image.pixels.iter().zip(&decoded.pixels).position(|(a, b)| a != b) {
+		return Err(TestCaseError::fail(format!(
+			"{direction}: pixel {index} is {:?}, expected {:?}",
+			decoded.pixels[index], image.pixels[index],
+		)));
+	}
+	prop_assert_eq!(image.pixels.len(), decoded.pixels.len(), "{}: pixel count", direction);
+	Ok(())
+}
+
+fn check(pair: &Pair, image: &TestImage) -> Result<(), TestCaseError> {
+	let ours = (pair.dwelf_encode)(image);
+	let theirs = (pair.reference_encode)(image);
+
+	compare("dwelf -> dwelf", image, (pair.dwelf_decode)(&ours))?;
+	compare("dwelf -> reference", image, (pair.reference_decode)(&ours))?;
+	compare("reference -> dwelf", image, (pair.dwelf_decode)(&theirs))?;
+	compare("reference -> reference", image, (pair.reference_decode)(&theirs))?;
+	Ok(())
+}
+
+/// writes the failing image as a pam file, next to the seed that produced it.
+fn dump(name: &str, seed: u64, image: &TestImage) -> std::path::PathBuf {
+	let dir = std::env::temp_dir().join("dwelf-differential");
+	std::fs::create_dir_all(&dir).expect("failed to create dump directory");
+
+	let path = dir.join(format!("{name}-{seed:016x}.pam"));
+	let mut file = std::fs::File::create(&path).expect("failed to create dump file");
+	write!(
+		file,
+		"P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+		image.width, image.height,
+	).unwrap();
+	for px in &image.pixels {
+		file.write_all(&[px.0, px.1, px.2, px.3]).unwrap();
+	}
+
+	std::fs::write(dir.join(format!("{name}-{seed:016x}.seed")), seed.to_string()).unwrap();
+	path
+}
+
+fn run(pair: &Pair) {
+	let seed = match std::env::var("DWELF_DIFFERENTIAL_SEED") {
+		Ok(x) => x.parse().expect("DWELF_DIFFERENTIAL_SEED must be a u64"),
+		Err(_) => std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap()
+			.as_nanos() as u64,
+	};
+
+	let mut bytes = [0; 32];
+	for (i, chunk) in bytes.chunks_exact_mut(8).enumerate() {
+		chunk.copy_from_slice(&seed.wrapping_add(i as u64).to_le_bytes());
+	}
+
+	let config = Config {
+		cases: 512,
+		failure_persistence: None,
+		..Config::default()
+	};
+	let mut runner = TestRunner::new_with_rng(config, TestRng::from_seed(RngAlgorithm::ChaCha, &bytes));
+
+	match runner.run(&image(), |image| check(pair, &image)) {
+		Ok(()) => {}
+		Err(TestError::Fail(reason, image)) => {
+			let path = dump(pair.name, seed, &image);
+			panic!("{}: {reason}\nseed {seed}, image dumped to {}", pair.name, path.display());
+		}
+		Err(e) => panic!("{}: {e} (seed {seed})", pair.name),
+	}
+}
+
+#[test]
+fn qoi() {
+	run(&QOI);
+}
+
+#[test]
+fn png() {
+	run(&PNG);
+}
+
+#[test]
+fn bmp() {
+	run(&BMP);
+}
+
+#[test]
+fn tga() {
+	run(&TGA);
+}
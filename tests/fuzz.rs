@@ -0,0 +1,1541 @@
+/*!
+fuzz tests. arbitrary and mutated input, and readers and writers that
+misbehave, must never panic, whatever they make the decoders return.
+
+these run in the test profile, so overflow checks and debug assertions are
+on. set `PROPTEST_CASES` to run more cases than the default.
+*/
+
+use std::io::{Read, Write};
+
+#[cfg(feature = "bmp")]
+use dwelf::bmp;
+#[cfg(feature = "fits")]
+use dwelf::fits;
+#[cfg(feature = "gif")]
+use dwelf::gif;
+#[cfg(feature = "dds")]
+use dwelf::dds;
+#[cfg(feature = "exr")]
+use dwelf::exr;
+#[cfg(feature = "hdr")]
+use dwelf::hdr;
+#[cfg(feature = "ico")]
+use dwelf::ico;
+#[cfg(feature = "ilbm")]
+use dwelf::ilbm;
+#[cfg(feature = "jpeg")]
+use dwelf::jpeg;
+#[cfg(feature = "ktx2")]
+use dwelf::ktx2;
+#[cfg(feature = "pcx")]
+use dwelf::pcx;
+#[cfg(feature = "png")]
+use dwelf::png;
+#[cfg(feature = "pnm")]
+use dwelf::pnm;
+#[cfg(feature = "psd")]
+use dwelf::psd;
+#[cfg(feature = "qoy")]
+use dwelf::qoy;
+#[cfg(feature = "qoir")]
+use dwelf::qoir;
+#[cfg(feature = "ras")]
+use dwelf::ras;
+#[cfg(feature = "raw")]
+use dwelf::raw;
+#[cfg(feature = "sgi")]
+use dwelf::sgi;
+#[cfg(feature = "tga")]
+use dwelf::tga;
+#[cfg(feature = "tiff")]
+use dwelf::tiff;
+#[cfg(feature = "xbm")]
+use dwelf::xbm;
+#[cfg(feature = "xpm")]
+use dwelf::xpm;
+use dwelf::{Format, Rgba, checksum, qoi};
+use proptest::prelude::*;
+
+/// everything that takes untrusted bytes, run over `data`.
+fn decode_all(data: &[u8]) {
+	let mut reader = data;
+	if let Ok((header, iter)) = qoi::Qoi.decode(&mut reader) {
+		let (_, upper) = iter.size_hint();
+		let count = iter.count();
+		assert!(count as u64 <= header.width as u64 * header.height as u64);
+		assert!(upper.is_none_or(|n| count <= n));
+	}
+
+	let mut reader = data;
+	let limits = dwelf::Limits { max_pixels: Some(1 << 16), ..Default::default() };
+	if let Ok((_, iter)) = qoi::Qoi.decode_with_limits(&mut reader, &limits) {
+		assert!(iter.count() <= 1 << 16);
+	}
+
+	// from a slice, with the same pixels and errors as from a reader
+	let mut reader = data;
+	let from_reader = qoi::Qoi.decode_checked(&mut reader).map(|(_, iter)| format!("{:?}", iter.take(1 << 12).collect::<Vec<_>>()));
+	let from_slice = qoi::decode_slice(data).map(|(_, iter)| format!("{:?}", iter.take(1 << 12).collect::<Vec<_>>()));
+	assert_eq!(format!("{from_slice:?}"), format!("{from_reader:?}"));
+
+	// and out of a buffer small enough that ops are cut off by its end
+	let mut reader = std::io::BufReader::with_capacity(7, data);
+	let from_buffer = qoi::decode_buffered(&mut reader).map(|(_, iter)| format!("{:?}", iter.take(1 << 12).collect::<Vec<_>>()));
+	assert_eq!(format!("{from_buffer:?}"), format!("{from_reader:?}"));
+
+	let mut buf = vec![0; 1 << 16];
+	_ = qoi::Qoi.decode_into(&mut &data[..], &mut buf, dwelf::Layout { channels: 3, stride: 1 << 8 });
+
+	let mut reader = data;
+	if let Ok((header, iter)) = qoi::decode_strict(&mut reader) {
+		assert!(iter.count() as u64 <= header.width as u64 * header.height as u64 + 1);
+	}
+
+	let mut reader = data;
+	let mut decoder = qoi::QoiDecoder::new();
+	_ = decoder.poll_finish(&mut reader);
+
+	// fed in pieces, as if off a socket
+	let mut decoder = qoi::QoiDecoder::new();
+	for chunk in data.chunks(5) {
+		if decoder.feed(chunk).any(|px| px.is_err()) {
+			break;
+		}
+	}
+
+	let mut reader = data;
+	if let Ok((_, mut rows)) = qoi::Qoi.decode_rows(&mut reader) {
+		while let Ok(Some(_)) = rows.next_row() {}
+	}
+
+	let mut reader = data;
+	if let Ok((_, iter)) = qoi::Qoi.decode_sub_image(&mut reader, 1, 1, 3, 2) {
+		assert!(iter.count() <= 6);
+	}
+
+	if let Ok((Some(format), mut reader)) = dwelf::detect(data) {
+		_ = format.decode(&mut reader);
+	}
+
+	let mut reader = data;
+	if let Ok(pixels) = qoi::QoiPixels::new(qoi::QoiDecoder::new(), &mut reader) {
+		_ = pixels.fold(0, |n, px| n + px.is_ok() as u64);
+	}
+
+	let mut reader = data;
+	let report = qoi::lint(&mut reader).expect("slices don't fail");
+	_ = report.to_string();
+
+	_ = checksum::verify_checksum(&mut &data[..], 0);
+
+	let mut reader = data;
+	if let Ok(mut reader) = dwelf::Reader::new(&mut reader) {
+		let mut row = vec![0; reader.info().row_size().min(1 << 16) as usize];
+		while let Ok(true) = reader.next_row(&mut row) {}
+	}
+
+	#[cfg(feature = "png")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = png::Png.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(pixels) = png::PngPixels::new(&mut reader) {
+			_ = pixels.finish();
+		}
+
+		// finishing after an error, partway through
+		let mut reader = data;
+		if let Ok(pixels) = png::PngPixels::new(&mut reader) {
+			let mut pixels = pixels.wide();
+			while let Some(Ok(_)) = pixels.next() {}
+			_ = pixels.finish();
+		}
+
+		let mut reader = data;
+		if let Ok(pixels) = png::PngPixels::new(&mut reader) {
+			_ = pixels.finish_with_metadata();
+		}
+
+		// every other frame is skipped without reading its pixels
+		let mut reader = data;
+		if let Ok(mut frames) = png::ApngFrames::new(&mut reader) {
+			let mut n = 0;
+			while let Ok(Some(frame)) = frames.next_frame() {
+				if n % 2 == 0 {
+					let (width, height) = (frame.control().width, frame.control().height);
+					assert!(frame.count() as u64 <= width as u64 * height as u64);
+				}
+				n += 1;
+			}
+			_ = frames.finish();
+		}
+	}
+
+	#[cfg(feature = "bmp")]
+	{
+		let mut reader = data;
+		// pixels an rle image skips over are index 0, so a few bytes can
+		// make any size of image. only so much of one is looked at
+		if let Ok((header, iter)) = bmp::Bmp.decode(&mut reader) {
+			assert!(iter.take(1 << 20).count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		// finishing partway through
+		let mut reader = data;
+		if let Ok(mut pixels) = bmp::BmpPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "pnm")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = pnm::Pnm.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = pnm::PnmPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+
+		let mut reader = data;
+		if let Ok(pixels) = pnm::PnmPixels::new(&mut reader) {
+			let mut pixels = pixels.wide();
+			while let Some(Ok(_)) = pixels.next() {}
+			_ = pixels.finish();
+		}
+
+		let mut reader = data;
+		if let Ok((header, iter)) = pnm::Pfm::default().decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = pnm::PfmPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "tga")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = tga::Tga.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = tga::TgaPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "gif")]
+	{
+		// pixels outside the first frame come out without being read, so
+		// there can be billions of them
+		let mut reader = data;
+		if let Ok((_, iter)) = gif::Gif.decode(&mut reader) {
+			_ = iter.take(1 << 16).count();
+		}
+
+		let mut reader = data;
+		if let Ok(frames) = gif::GifFrames::new(&mut reader) {
+			_ = frames.finish();
+		}
+
+		// the canvas is as big as the header says, so that's only done for
+		// small ones
+		let mut reader = data;
+		if let Ok(mut frames) = gif::GifFrames::composited(&mut reader) {
+			let pixels = frames.header().width * frames.header().height;
+			if pixels <= 1 << 20 {
+				while let Some(Ok(frame)) = frames.next() {
+					assert_eq!(frame.image.pixels().len() as u32, pixels);
+				}
+			}
+		}
+	}
+
+	#[cfg(feature = "tiff")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = tiff::Tiff.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = tiff::TiffPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "hdr")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = hdr::Hdr::default().decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = hdr::HdrPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "exr")]
+	{
+		let mut reader = data;
+		if let Ok(pixels) = exr::ExrPixels::new(&mut reader) {
+			let header = pixels.header().clone();
+			assert!(pixels.map_while(Result::ok).count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = exr::ExrPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "pcx")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = pcx::Pcx.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = pcx::PcxPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "ico")]
+	{
+		let mut reader = data;
+		if let Ok(images) = ico::IcoImages::new(&mut reader) {
+			for i in 0..images.header().entries.len().min(8) {
+				if let Ok(image) = images.decode(i) {
+					assert_eq!(image.image.pixels().len() as u64, image.image.width() as u64 * image.image.height() as u64);
+				}
+			}
+		}
+	}
+
+	#[cfg(feature = "dds")]
+	{
+		let mut reader = data;
+		if let Ok(pixels) = dds::DdsPixels::new(&mut reader) {
+			let (width, height) = pixels.size();
+			assert!(pixels.map_while(Result::ok).count() as u64 <= width as u64 * height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = dds::DdsPixels::level(&mut reader, 1) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "ktx2")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = ktx2::Ktx2.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = ktx2::Ktx2Pixels::image(&mut reader, 1, 1, 0) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "xbm")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = xbm::Xbm.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = xbm::XbmPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "xpm")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = xpm::Xpm.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = xpm::XpmPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "ras")]
+	{
+		let mut reader = data;
+		if let Ok(pixels) = ras::RasPixels::new(&mut reader) {
+			let (width, height) = (pixels.header().width, pixels.header().height);
+			assert!(pixels.map_while(Result::ok).count() as u64 <= width as u64 * height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = ras::RasPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "sgi")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = sgi::Sgi.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = sgi::SgiPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "qoy")]
+	{
+		let mut reader = data;
+		if let Ok((header, iter)) = qoy::Qoy.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = qoy::QoyPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "qoir")]
+	{
+		let mut reader = data;
+		if let Ok(pixels) = qoir::QoirPixels::new(&mut reader) {
+			let (width, height) = (pixels.header().width, pixels.header().height);
+			assert!(pixels.map_while(Result::ok).count() as u64 <= width as u64 * height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = qoir::QoirPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "ilbm")]
+	{
+		let mut reader = data;
+		if let Ok(pixels) = ilbm::IlbmPixels::new(&mut reader) {
+			let (width, height) = (pixels.header().width, pixels.header().height);
+			assert!(pixels.map_while(Result::ok).count() as u64 <= width as u64 * height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = ilbm::IlbmPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "psd")]
+	{
+		let mut reader = data;
+		if let Ok(pixels) = psd::PsdPixels::new(&mut reader) {
+			let (width, height) = (pixels.header().width, pixels.header().height);
+			assert!(pixels.map_while(Result::ok).count() as u64 <= width as u64 * height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = psd::PsdPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "fits")]
+	{
+		let mut reader = data;
+		let fits = fits::Fits { range: fits::FitsRange::Clipped(0.01), scale: fits::FitsScale::Asinh };
+		if let Ok((header, iter)) = fits.decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = fits::FitsPixels::new(&mut reader) {
+			_ = pixels.nth(40);
+			_ = pixels.finish();
+		}
+	}
+
+	#[cfg(feature = "raw")]
+	{
+		let header = raw::RawHeader { width: 7, height: 5, order: raw::RawOrder::BGRA, bit_depth: 12, big_endian: true, stride: Some(60) };
+		let mut reader = data;
+		if let Ok((header, iter)) = (raw::Raw { header: header.clone() }).decode(&mut reader) {
+			assert!(iter.count() as u64 <= header.width as u64 * header.height as u64);
+		}
+
+		let mut reader = data;
+		if let Ok(mut pixels) = raw::RawPixels::new(&mut reader, &header) {
+			_ = pixels.nth(10);
+			_ = pixels.finish();
+		}
+	}
+
+	checked(qoi::Qoi, data);
+	#[cfg(feature = "png")]
+	checked(png::Png, data);
+	#[cfg(feature = "bmp")]
+	checked(bmp::Bmp, data);
+	#[cfg(feature = "pnm")]
+	checked(pnm::Pnm, data);
+	#[cfg(feature = "pnm")]
+	checked(pnm::Pfm::default(), data);
+	#[cfg(feature = "tga")]
+	checked(tga::Tga, data);
+	#[cfg(feature = "tiff")]
+	checked(tiff::Tiff, data);
+	#[cfg(feature = "hdr")]
+	checked(hdr::Hdr::default(), data);
+	#[cfg(feature = "pcx")]
+	checked(pcx::Pcx, data);
+	#[cfg(feature = "ktx2")]
+	checked(ktx2::Ktx2, data);
+	#[cfg(feature = "xbm")]
+	checked(xbm::Xbm, data);
+	#[cfg(feature = "xpm")]
+	checked(xpm::Xpm, data);
+	#[cfg(feature = "sgi")]
+	checked(sgi::Sgi, data);
+	#[cfg(feature = "qoy")]
+	checked(qoy::Qoy, data);
+}
+
+/// `Format::decode_checked`, whose only error is its last item. the rest
+/// of `decode_all` goes through whole images, so this stops early.
+fn checked(format: impl Format, data: &[u8]) {
+	let mut reader = data;
+	if let Ok((_, iter)) = format.decode_checked(&mut reader) {
+		let mut iter = iter.take(1 << 12);
+		while let Some(Ok(_)) = iter.next() {}
+		assert!(iter.next().is_none());
+	}
+}
+
+#[cfg(feature = "bmp")]
+const BMPS: [&[u8]; 12] = [
+	include_bytes!("../test/gradient.bmp"),
+	include_bytes!("../test/gradient_top_down.bmp"),
+	include_bytes!("../test/core.bmp"),
+	include_bytes!("../test/rgbx.bmp"),
+	include_bytes!("../test/small.bmp"),
+	include_bytes!("../test/bitfields.bmp"),
+	include_bytes!("../test/indexed_1.bmp"),
+	include_bytes!("../test/indexed_4.bmp"),
+	include_bytes!("../test/indexed_8.bmp"),
+	include_bytes!("../test/indexed_core.bmp"),
+	include_bytes!("../test/rle8.bmp"),
+	include_bytes!("../test/rle4.bmp"),
+];
+
+#[cfg(feature = "pnm")]
+const PNMS: [&[u8]; 15] = [
+	include_bytes!("../test/gradient.ppm"),
+	include_bytes!("../test/deep.ppm"),
+	include_bytes!("../test/gradient.pgm"),
+	include_bytes!("../test/deep.pgm"),
+	include_bytes!("../test/mask.pbm"),
+	include_bytes!("../test/gradient_plain.ppm"),
+	include_bytes!("../test/gradient_plain.pgm"),
+	include_bytes!("../test/mask_plain.pbm"),
+	include_bytes!("../test/small.pam"),
+	include_bytes!("../test/gray_alpha.pam"),
+	include_bytes!("../test/deep.pam"),
+	include_bytes!("../test/mask.pam"),
+	include_bytes!("../test/ramp.pfm"),
+	include_bytes!("../test/ramp_le.pfm"),
+	include_bytes!("../test/ramp_gray.pfm"),
+];
+
+#[cfg(feature = "tga")]
+const TGAS: [&[u8]; 13] = [
+	include_bytes!("../test/gradient.tga"),
+	include_bytes!("../test/gradient_top_down.tga"),
+	include_bytes!("../test/gradient_right_to_left.tga"),
+	include_bytes!("../test/gradient_top_right.tga"),
+	include_bytes!("../test/small.tga"),
+	include_bytes!("../test/rgbx.tga"),
+	include_bytes!("../test/indexed.tga"),
+	include_bytes!("../test/indexed_16.tga"),
+	include_bytes!("../test/gray.tga"),
+	include_bytes!("../test/rle.tga"),
+	include_bytes!("../test/rle_top_down.tga"),
+	include_bytes!("../test/rle_gray.tga"),
+	include_bytes!("../test/rle_indexed.tga"),
+];
+
+#[cfg(feature = "gif")]
+const GIFS: [&[u8]; 6] = [
+	include_bytes!("../test/still.gif"),
+	include_bytes!("../test/still87.gif"),
+	include_bytes!("../test/interlaced.gif"),
+	include_bytes!("../test/noise.gif"),
+	include_bytes!("../test/noise_deferred.gif"),
+	include_bytes!("../test/animated.gif"),
+];
+
+#[cfg(feature = "tiff")]
+const TIFFS: [&[u8]; 8] = [
+	include_bytes!("../test/gradient.tiff"),
+	include_bytes!("../test/gradient_be.tiff"),
+	include_bytes!("../test/reversed.tiff"),
+	include_bytes!("../test/rgba.tiff"),
+	include_bytes!("../test/premultiplied.tiff"),
+	include_bytes!("../test/gray.tiff"),
+	include_bytes!("../test/gray_alpha.tiff"),
+	include_bytes!("../test/white_is_zero.tiff"),
+];
+
+#[cfg(feature = "hdr")]
+const HDRS: [&[u8]; 3] = [
+	include_bytes!("../test/gradient.hdr"),
+	include_bytes!("../test/flat.hdr"),
+	include_bytes!("../test/flat_wide.hdr"),
+];
+
+#[cfg(feature = "exr")]
+const EXRS: [&[u8]; 5] = [
+	include_bytes!("../test/gradient.exr"),
+	include_bytes!("../test/gradient_zip.exr"),
+	include_bytes!("../test/gradient_zips.exr"),
+	include_bytes!("../test/gray.exr"),
+	include_bytes!("../test/noise.exr"),
+];
+
+#[cfg(feature = "pcx")]
+const PCXS: [&[u8]; 4] = [
+	include_bytes!("../test/rgb.pcx"),
+	include_bytes!("../test/rgb_raw.pcx"),
+	include_bytes!("../test/flat.pcx"),
+	include_bytes!("../test/indexed.pcx"),
+];
+
+#[cfg(feature = "dds")]
+const DDSS: [&[u8]; 6] = [
+	include_bytes!("../test/rgba.dds"),
+	include_bytes!("../test/bgrx.dds"),
+	include_bytes!("../test/dx10.dds"),
+	include_bytes!("../test/cube.dds"),
+	include_bytes!("../test/bc1.dds"),
+	include_bytes!("../test/bc3.dds"),
+];
+
+#[cfg(feature = "ktx2")]
+const KTX2S: [&[u8]; 4] = [
+	include_bytes!("../test/levels.ktx2"),
+	include_bytes!("../test/zlib.ktx2"),
+	include_bytes!("../test/cube.ktx2"),
+	include_bytes!("../test/line.ktx2"),
+];
+
+#[cfg(feature = "xbm")]
+const XBMS: [&[u8]; 3] = [
+	include_bytes!("../test/cross.xbm"),
+	include_bytes!("../test/commented.xbm"),
+	include_bytes!("../test/x10.xbm"),
+];
+
+#[cfg(feature = "xpm")]
+const XPMS: [&[u8]; 2] = [include_bytes!("../test/icon.xpm"), include_bytes!("../test/cursor.xpm")];
+
+#[cfg(feature = "ras")]
+const RASES: [&[u8]; 5] = [
+	include_bytes!("../test/gray.ras"),
+	include_bytes!("../test/bgr.ras"),
+	include_bytes!("../test/xrgb.ras"),
+	include_bytes!("../test/mono.ras"),
+	include_bytes!("../test/indexed.ras"),
+];
+
+#[cfg(feature = "sgi")]
+const SGIS: [&[u8]; 5] = [
+	include_bytes!("../test/rgb.sgi"),
+	include_bytes!("../test/rgba.sgi"),
+	include_bytes!("../test/gray16.sgi"),
+	include_bytes!("../test/gray_alpha16.sgi"),
+	include_bytes!("../test/shared.sgi"),
+];
+
+#[cfg(feature = "qoy")]
+const QOYS: [&[u8]; 2] = [include_bytes!("../test/full.qoy"), include_bytes!("../test/subsampled.qoy")];
+
+#[cfg(feature = "qoir")]
+const QOIRS: [&[u8]; 4] = [
+	include_bytes!("../test/gradient.qoir"),
+	include_bytes!("../test/alpha.qoir"),
+	include_bytes!("../test/lz4.qoir"),
+	include_bytes!("../test/premul.qoir"),
+];
+
+#[cfg(feature = "ilbm")]
+const ILBMS: [&[u8]; 6] = [
+	include_bytes!("../test/indexed.iff"),
+	include_bytes!("../test/mono.iff"),
+	include_bytes!("../test/rgb.iff"),
+	include_bytes!("../test/ham6.iff"),
+	include_bytes!("../test/ham8.iff"),
+	include_bytes!("../test/ehb.iff"),
+];
+
+#[cfg(feature = "psd")]
+const PSDS: [&[u8]; 3] = [include_bytes!("../test/rgb.psd"), include_bytes!("../test/rgba.psd"), include_bytes!("../test/gray16.psd")];
+
+#[cfg(feature = "fits")]
+const FITSES: [&[u8]; 3] = [include_bytes!("../test/u8.fits"), include_bytes!("../test/i16.fits"), include_bytes!("../test/f32.fits")];
+
+#[cfg(feature = "ico")]
+const ICOS: [&[u8]; 2] = [include_bytes!("../test/icon.ico"), include_bytes!("../test/cursor.cur")];
+
+#[cfg(feature = "png")]
+const PNGS: [&[u8]; 15] = [
+	include_bytes!("../test/small.png"),
+	include_bytes!("../test/gradient.png"),
+	include_bytes!("../test/stored.png"),
+	include_bytes!("../test/interlaced.png"),
+	include_bytes!("../test/interlaced_small.png"),
+	include_bytes!("../test/indexed_1.png"),
+	include_bytes!("../test/indexed_2.png"),
+	include_bytes!("../test/indexed_4.png"),
+	include_bytes!("../test/indexed_8.png"),
+	include_bytes!("../test/indexed_interlaced.png"),
+	include_bytes!("../test/deep_rgb.png"),
+	include_bytes!("../test/deep_rgba.png"),
+	include_bytes!("../test/metadata.png"),
+	include_bytes!("../test/animated.png"),
+	include_bytes!("../test/animated_hidden.png"),
+];
+
+/// the concatenated `IDAT` data of a well formed png.
+#[cfg(feature = "png")]
+fn idat(mut data: &[u8]) -> Vec<u8> {
+	let mut out = vec![];
+	data = &data[8..];
+	while data.len() >= 12 {
+		let len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+		if &data[4..8] == b"IDAT" {
+			out.extend_from_slice(&data[8..8 + len]);
+		}
+		data = &data[12 + len..];
+	}
+	out
+}
+
+/// a png with valid chunks around `ihdr` and `zlib`, so that mutations get
+/// past the crcs.
+#[cfg(feature = "png")]
+fn png_file(ihdr: [u8; 13], zlib: &[u8]) -> Vec<u8> {
+	fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+		out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+		let start = out.len();
+		out.extend_from_slice(kind);
+		out.extend_from_slice(data);
+		let crc = checksum::crc32(&out[start..]);
+		out.extend_from_slice(&crc.to_be_bytes());
+	}
+
+	let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+	chunk(&mut out, b"IHDR", &ihdr);
+	// a short palette, so indexed images get to their data, and some indices
+	// are out of range
+	let plte = (0..200).flat_map(|i: u8| [i, !i, i.wrapping_mul(7)]).collect::<Vec<_>>();
+	chunk(&mut out, b"PLTE", &plte);
+	chunk(&mut out, b"IDAT", zlib);
+	chunk(&mut out, b"IEND", &[]);
+	out
+}
+
+/// salvage always produces every pixel, so it's only run on small images.
+fn salvage(data: &[u8]) {
+	if let [b'q', b'o', b'i', b'f', w0, w1, w2, w3, h0, h1, h2, h3, ..] = *data {
+		let pixels = u32::from_be_bytes([w0, w1, w2, w3]) as u64 * u32::from_be_bytes([h0, h1, h2, h3]) as u64;
+		if pixels > 1 << 16 {
+			return;
+		}
+	}
+	if let Ok(salvaged) = qoi::salvage(data, &qoi::SalvageOptions::default()) {
+		let header = salvaged.header;
+		assert_eq!(salvaged.pixels.len() as u64, header.width as u64 * header.height as u64);
+		for x in &salvaged.damage {
+			assert!(x.start + x.len <= salvaged.pixels.len() as u64);
+		}
+	}
+}
+
+fn header(width: u32, height: u32, channels: u8, colorspace: u8) -> Vec<u8> {
+	let mut out = b"qoif".to_vec();
+	out.extend_from_slice(&width.to_be_bytes());
+	out.extend_from_slice(&height.to_be_bytes());
+	out.extend_from_slice(&[channels, colorspace]);
+	out
+}
+
+fn image() -> impl Strategy<Value = (u32, u32, Vec<Rgba>)> {
+	(1u32..=16, 1u32..=16).prop_flat_map(|(width, height)| {
+		// a small palette, so runs and index hits come up
+		let px = prop_oneof![
+			(0u8..4).prop_map(|x| (x * 60, x * 20, 255 - x, 255)),
+			any::<(u8, u8, u8, u8)>(),
+		];
+		(Just(width), Just(height), prop::collection::vec(px, (width * height) as usize))
+	})
+}
+
+fn encode(width: u32, height: u32, pixels: &[Rgba]) -> Vec<u8> {
+	let header = qoi::QoiHeader {
+		width,
+		height,
+		channels: qoi::QoiHeaderChannels::RGBA,
+		colorspace: qoi::QoiHeaderColorspace::SRGB,
+	};
+	let mut out = vec![];
+	qoi::Qoi.encode(pixels.iter().cloned(), header, &mut out).expect("vecs don't fail");
+	out
+}
+
+#[derive(Debug, Clone)]
+enum Mutation {
+	Set(usize, u8),
+	Flip(usize, u8),
+	Insert(usize, u8),
+	Remove(usize),
+	Truncate(usize),
+}
+
+fn mutation() -> impl Strategy<Value = Mutation> {
+	prop_oneof![
+		(any::<usize>(), any::<u8>()).prop_map(|(i, x)| Mutation::Set(i, x)),
+		(any::<usize>(), 0u8..8).prop_map(|(i, x)| Mutation::Flip(i, x)),
+		(any::<usize>(), any::<u8>()).prop_map(|(i, x)| Mutation::Insert(i, x)),
+		any::<usize>().prop_map(Mutation::Remove),
+		any::<usize>().prop_map(Mutation::Truncate),
+	]
+}
+
+fn mutate(data: &mut Vec<u8>, mutations: &[Mutation]) {
+	for mutation in mutations {
+		if data.is_empty() {
+			return;
+		}
+		let len = data.len();
+		match *mutation {
+			Mutation::Set(i, x) => data[i % len] = x,
+			Mutation::Flip(i, bit) => data[i % len] ^= 1 << bit,
+			Mutation::Insert(i, x) => data.insert(i % (len + 1), x),
+			Mutation::Remove(i) => _ = data.remove(i % len),
+			Mutation::Truncate(i) => data.truncate(i % len),
+		}
+	}
+}
+
+/// what a misbehaving reader or writer does on a call.
+#[derive(Debug, Clone, Copy)]
+enum Behavior {
+	Normal,
+	/// claims to have moved more bytes than it was given.
+	Overstate,
+	Short,
+	Interrupted,
+	WouldBlock,
+	Fail,
+}
+
+fn behavior() -> impl Strategy<Value = Behavior> {
+	prop_oneof![
+		4 => Just(Behavior::Normal),
+		1 => Just(Behavior::Overstate),
+		1 => Just(Behavior::Short),
+		1 => Just(Behavior::Interrupted),
+		1 => Just(Behavior::WouldBlock),
+		1 => Just(Behavior::Fail),
+	]
+}
+
+struct Flaky<'a, T> {
+	inner: T,
+	behaviors: &'a [Behavior],
+	calls: usize,
+}
+
+impl<T> Flaky<'_, T> {
+	fn next(&mut self) -> Behavior {
+		// every other call behaves, so retrying on `Interrupted` gets somewhere
+		let x = match self.calls % 2 {
+			0 => Behavior::Normal,
+			_ => self.behaviors[self.calls / 2 % self.behaviors.len()],
+		};
+		self.calls += 1;
+		x
+	}
+}
+
+impl<T: Read> Read for Flaky<'_, T> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		match self.next() {
+			Behavior::Normal => self.inner.read(buf),
+			// still reports the end of the data, or nothing would ever stop
+			Behavior::Overstate => self.inner.read(buf).map(|n| if n > 0 { n + 8 } else { 0 }),
+			Behavior::Short => {
+				let len = buf.len().min(1);
+				self.inner.read(&mut buf[..len])
+			}
+			Behavior::Interrupted => Err(std::io::ErrorKind::Interrupted.into()),
+			Behavior::WouldBlock => Err(std::io::ErrorKind::WouldBlock.into()),
+			Behavior::Fail => Err(std::io::ErrorKind::Other.into()),
+		}
+	}
+}
+
+impl<T: Write> Write for Flaky<'_, T> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		match self.next() {
+			Behavior::Normal => self.inner.write(buf),
+			Behavior::Overstate => Ok(self.inner.write(buf)? + 8),
+			Behavior::Short => self.inner.write(&buf[..buf.len().min(1)]),
+			Behavior::Interrupted => Err(std::io::ErrorKind::Interrupted.into()),
+			Behavior::WouldBlock => Err(std::io::ErrorKind::WouldBlock.into()),
+			Behavior::Fail => Err(std::io::ErrorKind::Other.into()),
+		}
+	}
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+proptest! {
+	#![proptest_config(ProptestConfig {
+		failure_persistence: None,
+		..ProptestConfig::default()
+	})]
+
+	#[test]
+	fn arbitrary(data in prop::collection::vec(any::<u8>(), 0..256)) {
+		decode_all(&data);
+	}
+
+	#[test]
+	fn arbitrary_ops(
+		width in 1u32..=32,
+		height in 1u32..=32,
+		ops in prop::collection::vec(any::<u8>(), 0..512),
+		end in any::<bool>(),
+	) {
+		let mut data = header(width, height, 4, 0);
+		data.extend_from_slice(&ops);
+		if end {
+			data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+		}
+		decode_all(&data);
+		salvage(&data);
+	}
+
+	#[test]
+	fn arbitrary_header(width in any::<u32>(), height in any::<u32>(), channels in any::<u8>(), colorspace in any::<u8>()) {
+		let mut data = header(width, height, channels, colorspace);
+		data.extend_from_slice(&[0xfd; 64]);
+		decode_all(&data);
+	}
+
+	#[test]
+	fn mutated((width, height, pixels) in image(), mutations in prop::collection::vec(mutation(), 1..8)) {
+		let mut data = encode(width, height, &pixels);
+		mutate(&mut data, &mutations);
+		decode_all(&data);
+		salvage(&data);
+	}
+
+	#[test]
+	fn decode_at(prefix in prop::collection::vec(any::<u8>(), 0..32), (width, height, pixels) in image(), offset in any::<u64>()) {
+		let mut data = prefix.clone();
+		data.extend_from_slice(&encode(width, height, &pixels));
+
+		for offset in [prefix.len() as u64, offset % (data.len() as u64 + 16), offset] {
+			let mut reader = std::io::Cursor::new(&data);
+			if let Ok((_, pixels)) = qoi::decode_at(&mut reader, offset) {
+				_ = pixels.count();
+			}
+		}
+
+		let mut decoder = qoi::QoiDecoder::with_offset(u64::MAX - offset % 64);
+		let mut reader = &data[prefix.len()..];
+		while let Ok(core::task::Poll::Ready(Some(_))) = decoder.poll_pixel(&mut reader) {}
+		_ = decoder.poll_finish(&mut reader);
+	}
+
+	#[test]
+	fn flaky_reader((width, height, pixels) in image(), behaviors in prop::collection::vec(behavior(), 1..16)) {
+		let data = encode(width, height, &pixels);
+
+		let mut reader = Flaky { inner: &data[..], behaviors: &behaviors, calls: 0 };
+		if let Ok((_, iter)) = qoi::Qoi.decode(&mut reader) {
+			_ = iter.count();
+		}
+
+		let mut reader = Flaky { inner: &data[..], behaviors: &behaviors, calls: 0 };
+		let mut decoder = qoi::QoiDecoder::new();
+		for _ in 0..data.len() * 4 {
+			match decoder.poll_finish(&mut reader) {
+				Ok(core::task::Poll::Pending) => {}
+				_ => break,
+			}
+		}
+
+		let mut reader = Flaky { inner: &data[..], behaviors: &behaviors, calls: 0 };
+		_ = qoi::lint(&mut reader);
+
+		let mut reader = Flaky { inner: &data[..], behaviors: &behaviors, calls: 0 };
+		_ = checksum::verify_checksum(&mut reader, 0);
+	}
+
+	#[test]
+	fn feed((width, height, pixels) in image(), size in 1usize..32) {
+		let data = encode(width, height, &pixels);
+		let mut decoder = qoi::QoiDecoder::new();
+		let mut decoded = vec![];
+		for chunk in data.chunks(size) {
+			decoded.extend(decoder.feed(chunk).map(|px| px.expect("encoded images decode")));
+		}
+		prop_assert_eq!(decoded, pixels);
+		prop_assert!(decoder.is_finished());
+	}
+
+	#[test]
+	fn flaky_writer((width, height, pixels) in image(), behaviors in prop::collection::vec(behavior(), 1..16)) {
+		let header = qoi::QoiHeader {
+			width,
+			height,
+			channels: qoi::QoiHeaderChannels::RGBA,
+			colorspace: qoi::QoiHeaderColorspace::SRGB,
+		};
+
+		let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+		_ = qoi::Qoi.encode(pixels.iter().cloned(), header.clone(), &mut out);
+
+		let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+		_ = checksum::encode_with_checksum(qoi::Qoi, pixels.iter().cloned(), header.clone(), &mut out);
+
+		// pushing on after an error, which should keep failing
+		if let Ok(mut encoder) = qoi::QoiEncoder::new(&header, Flaky { inner: vec![], behaviors: &behaviors, calls: 0 }) {
+			let (first, rest) = pixels.split_at(pixels.len().min(1));
+			first.iter().for_each(|&px| _ = encoder.push_pixel(px));
+			rest.chunks(width as usize).for_each(|row| _ = encoder.push_row(row));
+			_ = encoder.finish();
+		}
+
+		let mut writer = dwelf::Writer::new(Flaky { inner: vec![], behaviors: &behaviors, calls: 0 });
+		let info = dwelf::ImageInfo {
+			width,
+			height,
+			channels: 4,
+			colorspace: dwelf::Colorspace::SRGB,
+			format: "qoi",
+		};
+		if writer.write_header(&info).is_ok() {
+			let data = pixels.iter().flat_map(|x| [x.0, x.1, x.2, x.3]).collect::<Vec<_>>();
+			_ = writer.write_image(&data);
+			_ = writer.finish();
+		}
+
+		let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+		_ = dwelf::encode_with(qoi::Qoi, header, &mut out, |x, y| pixels[(y * width + x) as usize]);
+
+		#[cfg(feature = "png")]
+		{
+			let header = png::PngHeader {
+				width,
+				height,
+				color_type: png::PngColorType::RGBA,
+				bit_depth: 8,
+				interlaced: false,
+				palette: vec![],
+			};
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = png::Png.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "bmp")]
+		for top_down in [false, true] {
+			let header = bmp::BmpHeader { width, height, color_type: bmp::BmpColorType::RGBA, top_down, palette: vec![] };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = bmp::Bmp.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "pnm")]
+		{
+			let header = pnm::PnmHeader { width, height, kind: pnm::PnmKind::Pixmap, plain: false, max_value: 255 };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = pnm::Pnm.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "tga")]
+		for top_down in [false, true] {
+			let header = tga::TgaHeader {
+				width,
+				height,
+				color_type: tga::TgaColorType::RGBA,
+				top_down,
+				right_to_left: !top_down,
+				rle: top_down,
+				palette: vec![],
+			};
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = tga::Tga.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "gif")]
+		{
+			let header = gif::GifHeader { width, height, palette: vec![], background: 0 };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = gif::Gif.encode(pixels.iter().cloned(), header.clone(), &mut out);
+
+			let image = dwelf::Image::from_pixels(width, height, pixels.clone()).expect("sized to fit");
+			let control = gif::GifFrameControl {
+				width,
+				height,
+				x: 0,
+				y: 0,
+				delay: 10,
+				dispose: gif::GifDispose::Previous,
+				transparent: None,
+				interlaced: true,
+			};
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = gif::encode_frames([gif::GifFrame { control, image }], &header, Some(0), &mut out);
+		}
+
+		#[cfg(feature = "tiff")]
+		for compression in [tiff::TiffCompression::None, tiff::TiffCompression::PackBits] {
+			let header = tiff::TiffHeader { width, height, color_type: tiff::TiffColorType::RGBA, compression, big_endian: false };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = tiff::Tiff.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "hdr")]
+		{
+			let header = hdr::HdrHeader { width, height, exposure: 1.0 };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = hdr::Hdr::default().encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "jpeg")]
+		{
+			let header = jpeg::JpegHeader { width, height, color_type: jpeg::JpegColorType::YCbCr, subsampling: jpeg::JpegSubsampling::Chroma420 };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = jpeg::Jpeg::default().encode(pixels.iter().cloned(), &header, &mut out);
+		}
+
+		#[cfg(feature = "pcx")]
+		for color_type in [pcx::PcxColorType::RGB, pcx::PcxColorType::Indexed] {
+			let header = pcx::PcxHeader { width, height, color_type, rle: true };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = pcx::Pcx.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "ico")]
+		if let Ok(image) = dwelf::Image::from_pixels(width, height, pixels.clone()) {
+			let images = [ico::IcoFormat::Bmp, ico::IcoFormat::Png].map(|format| ico::IcoImage { hotspot: (0, 0), format, image: image.clone() });
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = ico::encode(images, ico::IcoKind::Cursor, &mut out);
+		}
+
+		#[cfg(feature = "ktx2")]
+		for supercompression in [ktx2::Ktx2Supercompression::None, ktx2::Ktx2Supercompression::Zlib] {
+			let header = ktx2::Ktx2Header { width, height, srgb: false, levels: 1, layers: 1, faces: 1, supercompression };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = ktx2::Ktx2.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "xbm")]
+		for x10 in [false, true] {
+			let header = xbm::XbmHeader { width, height, name: "fuzz".into(), hotspot: Some((0, 0)), x10 };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = xbm::Xbm.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "xpm")]
+		{
+			let header = xpm::XpmHeader { width, height, name: "fuzz".into(), hotspot: Some((0, 0)) };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = xpm::Xpm.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "sgi")]
+		for rle in [false, true] {
+			let header = sgi::SgiHeader { width, height, color_type: sgi::SgiColorType::RGBA, bit_depth: 8, rle, name: "fuzz".into() };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = sgi::Sgi.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "qoy")]
+		for subsampled in [false, true] {
+			let header = qoy::QoyHeader { width, height, channels: qoy::QoyChannels::RGBA, subsampled };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = qoy::Qoy.encode(pixels.iter().cloned(), header, &mut out);
+		}
+
+		#[cfg(feature = "raw")]
+		{
+			let header = raw::RawHeader { width, height, order: raw::RawOrder::RGB, bit_depth: 16, big_endian: false, stride: Some(width as u64 * 6 + 3) };
+			let mut out = Flaky { inner: vec![], behaviors: &behaviors, calls: 0 };
+			_ = raw::Raw::default().encode(pixels.iter().cloned(), header, &mut out);
+		}
+	}
+
+	#[test]
+	fn adapters(width in prop_oneof![0u32..8, any::<u32>()], height in prop_oneof![0u32..8, any::<u32>()], n in 0u8..128, rect in any::<[u8; 4]>()) {
+		use dwelf::adapters;
+
+		let total = width as u64 * height as u64;
+		let counts = [
+			adapters::flip_horizontal(0..n, width, height).count(),
+			adapters::flip_vertical(0..n, width, height).count(),
+			adapters::rotate_90(0..n, width, height).count(),
+			adapters::rotate_180(0..n, width, height).count(),
+			adapters::rotate_270(0..n, width, height).count(),
+		];
+		for count in counts {
+			prop_assert!(count as u64 <= total.min(n as u64));
+		}
+
+		let pixels = (0..n).map(|x| (x, x, x, x));
+		for matrix in [adapters::Bayer::X2, adapters::Bayer::X4, adapters::Bayer::X8] {
+			let dithered = adapters::dither_ordered(pixels.clone(), width, height, matrix, n.max(2)).expect("levels?");
+			prop_assert!(dithered.count() as u64 <= total.min(n as u64));
+		}
+		let dithered = adapters::dither_blue_noise(pixels, width, height, n.max(2)).expect("levels?");
+		prop_assert!(dithered.count() as u64 <= total.min(n as u64));
+
+		let [x, y, crop_width, crop_height] = rect.map(|n| n as u32 % 8);
+		if let Ok(cropped) = adapters::crop(0..n, width, height, x, y, crop_width, crop_height) {
+			prop_assert!(cropped.count() as u64 <= crop_width as u64 * crop_height as u64);
+		}
+		let resized = adapters::resize_nearest(0..n, width, height, crop_width, crop_height);
+		prop_assert!(resized.count() as u64 <= crop_width as u64 * crop_height as u64);
+		let resized = adapters::resize_nearest(0..n, crop_width, crop_height, width, height);
+		prop_assert!(resized.take(1 << 12).count() as u64 <= total);
+	}
+
+	#[test]
+	fn quantize(pixels in prop::collection::vec(any::<(u8, u8, u8, u8)>(), 0..512), max in 1usize..=256) {
+		use dwelf::quantize;
+
+		let palette = quantize::median_cut(pixels.iter().copied(), max).expect("error?");
+		prop_assert!(palette.len() <= max);
+		prop_assert_eq!(palette.is_empty(), pixels.is_empty());
+		if let Ok(indices) = quantize::map(pixels.iter().copied(), &palette) {
+			let indices = indices.collect::<Vec<_>>();
+			prop_assert_eq!(indices.len(), pixels.len());
+			prop_assert!(indices.iter().all(|&i| (i as usize) < palette.len()));
+		}
+	}
+
+	#[test]
+	fn encode_headers(width in any::<u32>(), height in any::<u32>(), pixels in prop::collection::vec(any::<(u8, u8, u8, u8)>(), 0..64)) {
+		let header = qoi::QoiHeader {
+			width,
+			height,
+			channels: qoi::QoiHeaderChannels::RGB,
+			colorspace: qoi::QoiHeaderColorspace::Linear,
+		};
+		let mut out = vec![];
+		_ = qoi::Qoi.encode(pixels.iter().cloned(), header, &mut out);
+		decode_all(&out);
+
+		#[cfg(feature = "png")]
+		{
+			let header = png::PngHeader {
+				width,
+				height,
+				color_type: png::PngColorType::RGB,
+				bit_depth: 8,
+				interlaced: false,
+				palette: vec![],
+			};
+			let mut out = vec![];
+			_ = png::Png.encode(pixels.iter().cloned(), header.clone(), &mut out);
+			decode_all(&out);
+
+			let header = png::PngHeader {
+				color_type: png::PngColorType::Indexed,
+				bit_depth: 4,
+				palette: pixels.iter().cloned().take(16).collect(),
+				..header
+			};
+			let mut out = vec![];
+			_ = png::Png.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "bmp")]
+		for top_down in [false, true] {
+			let header = bmp::BmpHeader { width, height, color_type: bmp::BmpColorType::RGB, top_down, palette: vec![] };
+			let mut out = vec![];
+			_ = bmp::Bmp.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "pnm")]
+		for kind in [
+			pnm::PnmKind::Bitmap,
+			pnm::PnmKind::Graymap,
+			pnm::PnmKind::Pixmap,
+			pnm::PnmKind::Pam(pnm::PamTupleType::GrayscaleAlpha),
+			pnm::PnmKind::Pam(pnm::PamTupleType::RGBAlpha),
+		] {
+			for (plain, max_value) in [(false, 255), (false, 1000), (true, 255)] {
+				let header = pnm::PnmHeader { width, height, kind, plain, max_value };
+				let mut out = vec![];
+				_ = pnm::Pnm.encode(pixels.iter().cloned(), header, &mut out);
+				decode_all(&out);
+			}
+		}
+
+		#[cfg(feature = "pnm")]
+		for (color_type, little_endian) in [(pnm::PfmColorType::Gray, false), (pnm::PfmColorType::RGB, true)] {
+			let header = pnm::PfmHeader { width, height, color_type, scale: 1.0, little_endian };
+			let mut out = vec![];
+			_ = pnm::Pfm::default().encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "tga")]
+		for (color_type, top_down, rle) in [
+			(tga::TgaColorType::RGB, false, false),
+			(tga::TgaColorType::RGBA, true, true),
+			(tga::TgaColorType::Gray, false, true),
+			(tga::TgaColorType::Indexed, true, true),
+		] {
+			let palette = match color_type {
+				tga::TgaColorType::Indexed => pixels.iter().cloned().take(16).collect(),
+				_ => vec![],
+			};
+			let header = tga::TgaHeader { width, height, color_type, top_down, right_to_left: top_down, rle, palette };
+			let mut out = vec![];
+			_ = tga::Tga.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "gif")]
+		for palette in [vec![], pixels.iter().cloned().take(16).collect()] {
+			let header = gif::GifHeader { width, height, palette, background: 0 };
+			let mut out = vec![];
+			_ = gif::Gif.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "tiff")]
+		for (color_type, compression, big_endian) in [
+			(tiff::TiffColorType::Gray, tiff::TiffCompression::None, false),
+			(tiff::TiffColorType::RGBA, tiff::TiffCompression::PackBits, true),
+		] {
+			let header = tiff::TiffHeader { width, height, color_type, compression, big_endian };
+			let mut out = vec![];
+			_ = tiff::Tiff.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "hdr")]
+		for exposure in [1.0, 0.5] {
+			let header = hdr::HdrHeader { width, height, exposure };
+			let mut out = vec![];
+			_ = hdr::encode_rgbe(pixels.iter().map(|px| [px.0, px.1, px.2, px.3]), &header, &mut out);
+			decode_all(&out);
+		}
+
+		// there's no jpeg decoder, so this only checks that encoding doesn't
+		// panic
+		#[cfg(feature = "jpeg")]
+		for (color_type, subsampling, quality) in [
+			(jpeg::JpegColorType::Gray, jpeg::JpegSubsampling::Chroma444, 1),
+			(jpeg::JpegColorType::YCbCr, jpeg::JpegSubsampling::Chroma422, 100),
+			(jpeg::JpegColorType::YCbCr, jpeg::JpegSubsampling::Chroma420, pixels.len() as u8 * 2),
+		] {
+			let header = jpeg::JpegHeader { width, height, color_type, subsampling };
+			_ = jpeg::Jpeg { quality }.encode(pixels.iter().cloned(), &header, &mut vec![]);
+		}
+
+		#[cfg(feature = "pcx")]
+		for (color_type, rle) in [(pcx::PcxColorType::RGB, false), (pcx::PcxColorType::Indexed, true)] {
+			let header = pcx::PcxHeader { width, height, color_type, rle };
+			let mut out = vec![];
+			_ = pcx::Pcx.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "ktx2")]
+		for (srgb, supercompression) in [(false, ktx2::Ktx2Supercompression::None), (true, ktx2::Ktx2Supercompression::Zlib)] {
+			let header = ktx2::Ktx2Header { width, height, srgb, levels: 1, layers: 1, faces: 1, supercompression };
+			let mut out = vec![];
+			_ = ktx2::Ktx2.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "xbm")]
+		for (hotspot, x10) in [(None, false), (Some((width, height)), true)] {
+			let header = xbm::XbmHeader { width, height, name: "fuzz".into(), hotspot, x10 };
+			let mut out = vec![];
+			_ = xbm::Xbm.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "xpm")]
+		{
+			let header = xpm::XpmHeader { width, height, name: "fuzz".into(), hotspot: None };
+			let mut out = vec![];
+			_ = xpm::Xpm.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "sgi")]
+		for (color_type, bit_depth, rle) in [(sgi::SgiColorType::Gray, 16, false), (sgi::SgiColorType::RGBA, 8, true)] {
+			let header = sgi::SgiHeader { width, height, color_type, bit_depth, rle, name: "fuzz".into() };
+			let mut out = vec![];
+			_ = sgi::Sgi.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "qoy")]
+		for (channels, subsampled) in [(qoy::QoyChannels::RGB, false), (qoy::QoyChannels::RGBA, true)] {
+			let header = qoy::QoyHeader { width, height, channels, subsampled };
+			let mut out = vec![];
+			_ = qoy::Qoy.encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		#[cfg(feature = "raw")]
+		for (order, bit_depth, stride) in [(raw::RawOrder::Gray, 1, None), (raw::RawOrder::ARGB, 10, Some(width as u64 * 8))] {
+			let header = raw::RawHeader { width, height, order, bit_depth, big_endian: true, stride };
+			let mut out = vec![];
+			_ = raw::Raw::default().encode(pixels.iter().cloned(), header, &mut out);
+			decode_all(&out);
+		}
+
+		let mut out = std::io::Cursor::new(vec![]);
+		_ = qoi::encode_unknown_height(
+			width,
+			qoi::QoiHeaderChannels::RGBA,
+			qoi::QoiHeaderColorspace::SRGB,
+			pixels.iter().cloned(),
+			&mut out,
+		);
+	}
+
+	#[cfg(feature = "png")]
+	#[test]
+	fn png_mutated_data(
+		file in 0..PNGS.len(),
+		ihdr in any::<[u8; 13]>(),
+		keep_ihdr in any::<bool>(),
+		mutations in prop::collection::vec(mutation(), 0..8),
+	) {
+		let original = PNGS[file];
+		let mut zlib = idat(original);
+		mutate(&mut zlib, &mutations);
+		let ihdr = match keep_ihdr {
+			true => original[16..29].try_into().unwrap(),
+			false => ihdr,
+		};
+		decode_all(&png_file(ihdr, &zlib));
+	}
+}
+
+/// the `<format>_mutated` and `<format>_flaky_reader` tests of a format:
+/// mutated copies of its test files go through `decode_all`, and the files
+/// as they are through each of its decoders from a reader that misbehaves.
+/// decoders yield `Result`s of pixels, which are counted, unless the
+/// pixels are called something and handled otherwise.
+macro_rules! format_tests {
+	($feature:literal, $files:ident, $mutated:ident, $flaky_reader:ident, $($new:path $(=> |$pixels:ident| $consume:expr)?),+ $(,)?) => {
+		proptest! {
+			#![proptest_config(ProptestConfig {
+				failure_persistence: None,
+				..ProptestConfig::default()
+			})]
+
+			#[cfg(feature = $feature)]
+			#[test]
+			fn $mutated(file in 0..$files.len(), mutations in prop::collection::vec(mutation(), 1..8)) {
+				let mut data = $files[file].to_vec();
+				mutate(&mut data, &mutations);
+				decode_all(&data);
+			}
+
+			#[cfg(feature = $feature)]
+			#[test]
+			fn $flaky_reader(file in 0..$files.len(), behaviors in prop::collection::vec(behavior(), 1..16)) {
+				$(
+					let mut reader = Flaky { inner: $files[file], behaviors: &behaviors, calls: 0 };
+					format_tests!(@consume $new(&mut reader) $(=> |$pixels| $consume)?);
+				)+
+			}
+		}
+	};
+	(@consume $decoded:expr) => {
+		if let Ok(pixels) = $decoded {
+			_ = pixels.fold(0, |n, px| n + px.is_ok() as u64);
+		}
+	};
+	(@consume $decoded:expr => |$pixels:ident| $consume:expr) => {
+		if let Ok($pixels) = $decoded {
+			_ = $consume;
+		}
+	};
+}
+
+format_tests!("png", PNGS, png_mutated, png_flaky_reader, png::PngPixels::new => |pixels| pixels.finish());
+format_tests!("bmp", BMPS, bmp_mutated, bmp_flaky_reader, bmp::BmpPixels::new);
+format_tests!("pnm", PNMS, pnm_mutated, pnm_flaky_reader, pnm::PnmPixels::new, pnm::PfmPixels::new);
+format_tests!("tga", TGAS, tga_mutated, tga_flaky_reader, tga::TgaPixels::new);
+format_tests!("gif", GIFS, gif_mutated, gif_flaky_reader, gif::GifFrames::composited);
+format_tests!("tiff", TIFFS, tiff_mutated, tiff_flaky_reader, tiff::TiffPixels::new);
+format_tests!("hdr", HDRS, hdr_mutated, hdr_flaky_reader, hdr::HdrPixels::new);
+format_tests!("exr", EXRS, exr_mutated, exr_flaky_reader, exr::ExrPixels::new);
+format_tests!("pcx", PCXS, pcx_mutated, pcx_flaky_reader, pcx::PcxPixels::new);
+format_tests!("ico", ICOS, ico_mutated, ico_flaky_reader, ico::IcoImages::new => |images| images.decode(0));
+format_tests!("dds", DDSS, dds_mutated, dds_flaky_reader, dds::DdsPixels::new);
+format_tests!("ktx2", KTX2S, ktx2_mutated, ktx2_flaky_reader, ktx2::Ktx2Pixels::new);
+format_tests!("xbm", XBMS, xbm_mutated, xbm_flaky_reader, xbm::XbmPixels::new);
+format_tests!("xpm", XPMS, xpm_mutated, xpm_flaky_reader, xpm::XpmPixels::new);
+format_tests!("ras", RASES, ras_mutated, ras_flaky_reader, ras::RasPixels::new);
+format_tests!("sgi", SGIS, sgi_mutated, sgi_flaky_reader, sgi::SgiPixels::new);
+format_tests!("qoy", QOYS, qoy_mutated, qoy_flaky_reader, qoy::QoyPixels::new);
+format_tests!("qoir", QOIRS, qoir_mutated, qoir_flaky_reader, qoir::QoirPixels::new);
+format_tests!("ilbm", ILBMS, ilbm_mutated, ilbm_flaky_reader, ilbm::IlbmPixels::new);
+format_tests!("psd", PSDS, psd_mutated, psd_flaky_reader, psd::PsdPixels::new);
+format_tests!("fits", FITSES, fits_mutated, fits_flaky_reader, fits::FitsPixels::new);